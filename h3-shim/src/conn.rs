@@ -5,7 +5,7 @@ use std::{
 };
 
 use futures::Stream;
-use qbase::sid::StreamId;
+use qbase::{sid::StreamId, varint::VarInt};
 use qconnection::conn::{StreamReader, StreamWriter};
 
 use crate::{
@@ -80,9 +80,9 @@ impl<B: bytes::Buf> h3::quic::OpenStreams<B> for QuicConnection {
 
     #[inline]
     fn close(&mut self, code: h3::error::Code, reason: &[u8]) {
-        let _ignored = code;
+        let code = app_error_code(code);
         let reason = unsafe { String::from_utf8_unchecked(reason.to_vec()) };
-        self.connection.close(reason);
+        self.connection.close_with_code(code, reason);
     }
 }
 
@@ -173,12 +173,19 @@ impl<B: bytes::Buf> h3::quic::OpenStreams<B> for OpenStreams {
 
     #[inline]
     fn close(&mut self, code: h3::error::Code, reason: &[u8]) {
-        let _ignored = code;
+        let code = app_error_code(code);
         let reason = unsafe { String::from_utf8_unchecked(reason.to_vec()) };
-        self.connection.close(reason);
+        self.connection.close_with_code(code, reason);
     }
 }
 
+/// The HTTP/3 error `code` as the application error code of QUIC, the codes beyond the range of
+/// varints can not be sent, H3_INTERNAL_ERROR is sent instead.
+fn app_error_code(code: h3::error::Code) -> VarInt {
+    VarInt::try_from(code.value())
+        .unwrap_or_else(|_| VarInt::from_u32(h3::error::Code::H3_INTERNAL_ERROR.value() as u32))
+}
+
 type BoxStream<T> = Pin<Box<dyn Stream<Item = T> + Send + Sync>>;
 
 fn sid_exceed_limit_error() -> io::Error {
@@ -1,9 +1,15 @@
 mod connection_id;
 pub use connection_id::*;
 
+mod generator;
+pub use generator::*;
+
 mod local_cid;
 pub use local_cid::*;
 
+mod routable;
+pub use routable::*;
+
 mod remote_cid;
 pub use remote_cid::*;
 
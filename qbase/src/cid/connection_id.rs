@@ -1,6 +1,8 @@
 use nom::{bytes::streaming::take, number::streaming::be_u8, IResult};
 use rand::Rng;
 
+use crate::{entropy::EntropySource, token::ResetToken};
+
 /// The connection id length must not exceed 20 bytes. See [`ConnectionId`].
 pub const MAX_CID_SIZE: usize = 20;
 
@@ -97,6 +99,19 @@ impl ConnectionId {
             bytes,
         }
     }
+
+    /// Generates a connection ID like [`Self::random_gen_with_mark`], but the bytes are filled by
+    /// the given `entropy` source.
+    pub fn gen_with_mark(entropy: &dyn EntropySource, len: usize, mark: u8, mask: u8) -> Self {
+        debug_assert!(len > 0 && len <= MAX_CID_SIZE);
+        let mut bytes = [0; MAX_CID_SIZE];
+        entropy.fill_bytes(&mut bytes[..len]);
+        bytes[0] = (bytes[0] & mask) | mark;
+        Self {
+            len: len as u8,
+            bytes,
+        }
+    }
 }
 
 impl std::ops::Deref for ConnectionId {
@@ -113,6 +128,14 @@ pub trait GenUniqueCid {
     /// Generate a unique connection ID.
     #[must_use]
     fn gen_unique_cid(&self) -> ConnectionId;
+
+    /// Generate the stateless reset token for the connection ID.
+    ///
+    /// It is random by default, an endpoint that is able to send stateless resets should derive it
+    /// from the connection ID, so that the token can be regenerated without any connection state.
+    fn gen_reset_token(&self, _cid: &ConnectionId) -> ResetToken {
+        ResetToken::random_gen()
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,56 @@
+use std::{fmt::Debug, sync::Arc};
+
+use super::ConnectionId;
+use crate::entropy::{ArcEntropy, OsEntropy};
+
+/// The generator of the local connection IDs.
+///
+/// The initial SCID of a server connection, and the connection IDs issued in the
+/// NEW_CONNECTION_ID frames are produced by the generator. The generated connection IDs may
+/// collide with the existing ones, the caller is responsible for checking the uniqueness.
+///
+/// The random connection IDs are generated by default. An endpoint behind a load balancer can
+/// use the [`RoutableCidGenerator`] instead, to embed its server ID into the connection IDs, so the
+/// load balancer can route the packets of the connections to it.
+///
+/// [`RoutableCidGenerator`]: super::RoutableCidGenerator
+pub trait CidGenerator: Send + Sync + Debug {
+    /// The length of the connection IDs generated by the generator.
+    fn cid_len(&self) -> usize;
+
+    /// Generate a new connection ID.
+    fn generate_cid(&self) -> ConnectionId;
+}
+
+/// The shared version of [`CidGenerator`].
+pub type ArcCidGenerator = Arc<dyn CidGenerator>;
+
+/// The default [`CidGenerator`], the connection IDs are 8 bytes filled by the `entropy` source.
+#[derive(Debug, Clone)]
+pub struct RandomCidGenerator {
+    len: usize,
+    entropy: ArcEntropy,
+}
+
+impl RandomCidGenerator {
+    /// Create a generator whose connection IDs are filled by the `entropy` source.
+    pub fn new(entropy: ArcEntropy) -> Self {
+        Self { len: 8, entropy }
+    }
+}
+
+impl Default for RandomCidGenerator {
+    fn default() -> Self {
+        Self::new(Arc::new(OsEntropy))
+    }
+}
+
+impl CidGenerator for RandomCidGenerator {
+    fn cid_len(&self) -> usize {
+        self.len
+    }
+
+    fn generate_cid(&self) -> ConnectionId {
+        ConnectionId::gen_with_mark(&*self.entropy, self.len, 0x80, 0x7F)
+    }
+}
@@ -37,18 +37,29 @@ where
 {
     /// Create a new local connection ID manager.
     fn new(scid: ConnectionId, issued_cids: ISSUED) -> Self {
-        let mut cid_deque = IndexDeque::default();
-        cid_deque
-            .push_back(Some((scid, ResetToken::default())))
-            .unwrap();
-
         let new_cid = issued_cids.gen_unique_cid();
-        let new_cid_frame =
-            NewConnectionIdFrame::new(new_cid, VarInt::from_u32(1), VarInt::from_u32(0));
+        let new_cid_frame = NewConnectionIdFrame {
+            sequence: VarInt::from_u32(1),
+            retire_prior_to: VarInt::from_u32(0),
+            id: new_cid,
+            reset_token: issued_cids.gen_reset_token(&new_cid),
+        };
         issued_cids.send_frame([new_cid_frame]);
+        Self::with_second_cid(scid, (new_cid, new_cid_frame.reset_token), issued_cids)
+    }
+
+    /// Create a new local connection ID manager, whose connection ID of sequence number 1 has
+    /// been issued.
+    fn with_second_cid(
+        scid: ConnectionId,
+        second_cid: (ConnectionId, ResetToken),
+        issued_cids: ISSUED,
+    ) -> Self {
+        let mut cid_deque = IndexDeque::default();
         cid_deque
-            .push_back(Some((new_cid_frame.id, new_cid_frame.reset_token)))
+            .push_back(Some((scid, ResetToken::default())))
             .unwrap();
+        cid_deque.push_back(Some(second_cid)).unwrap();
         Self {
             cid_deque,
             issued_cids,
@@ -70,19 +81,30 @@ where
                 format!("{} < 2", active_cid_limit),
             ));
         }
-        for _ in self.cid_deque.largest()..active_cid_limit {
+        // 启动时补齐到对端的限制，已退休的连接ID不计入活跃的数量
+        for _ in self.active_count()..active_cid_limit {
             self.issue_new_cid();
         }
         self.active_cid_limit = Some(active_cid_limit);
         Ok(())
     }
 
+    /// The number of the issued connection IDs that have not been retired yet.
+    fn active_count(&self) -> u64 {
+        self.cid_deque.iter().filter(|v| v.is_some()).count() as u64
+    }
+
     /// Issue a new connection ID, for internal used only.
     fn issue_new_cid(&mut self) {
         let seq = VarInt::from_u64(self.cid_deque.largest()).unwrap();
         let retire_prior_to = VarInt::from_u64(self.cid_deque.offset()).unwrap();
         let new_cid = self.issued_cids.gen_unique_cid();
-        let new_cid_frame = NewConnectionIdFrame::new(new_cid, seq, retire_prior_to);
+        let new_cid_frame = NewConnectionIdFrame {
+            sequence: seq,
+            retire_prior_to,
+            id: new_cid,
+            reset_token: self.issued_cids.gen_reset_token(&new_cid),
+        };
         self.issued_cids.send_frame([new_cid_frame]);
         self.cid_deque.push_back(Some((new_cid_frame.id, new_cid_frame.reset_token)))
             .expect("it's very very hard to issue a new connection ID whose sequence excceeds VARINT_MAX");
@@ -97,7 +119,7 @@ where
         let seq = frame.sequence.into_inner();
         if seq >= self.cid_deque.largest() {
             return Err(Error::new(
-                ErrorKind::ConnectionIdLimit,
+                ErrorKind::ProtocolViolation,
                 frame.frame_type(),
                 format!(
                     "Sequence({seq}) in RetireConnectionIdFrame exceeds the largest one({}) issued by us",
@@ -111,8 +133,11 @@ where
                 let n = self.cid_deque.iter().take_while(|v| v.is_none()).count();
                 self.cid_deque.advance(n);
 
-                // generates a new connection ID while retiring an old one.
-                self.issue_new_cid();
+                // generates a new connection ID while retiring an old one, but never
+                // exceeds the active_connection_id_limit of the peer.
+                if self.active_count() < self.active_cid_limit.unwrap_or(2) {
+                    self.issue_new_cid();
+                }
                 return Ok(Some(cid));
             }
         }
@@ -159,6 +184,21 @@ where
         Self(Arc::new(Mutex::new(raw_local_cids)))
     }
 
+    /// Create a new share local connection ID manager for the server advertising a preferred
+    /// address.
+    ///
+    /// The `preferred_cid` and its stateless reset token are carried by the `preferred_address`
+    /// transport parameter as the connection ID of sequence number 1, instead of a
+    /// [`NewConnectionIdFrame`]. The caller is responsible for routing the packets with it.
+    pub fn with_preferred_cid(
+        scid: ConnectionId,
+        preferred_cid: (ConnectionId, ResetToken),
+        issued_cids: ISSUED,
+    ) -> Self {
+        let raw_local_cids = LocalCids::with_second_cid(scid, preferred_cid, issued_cids);
+        Self(Arc::new(Mutex::new(raw_local_cids)))
+    }
+
     /// Get all active connection IDs.
     ///
     /// This method will be useful when finally releasing connection resources,
@@ -173,6 +213,34 @@ where
             .collect()
     }
 
+    /// Get the connection ID of the sequence number `seq`, if it has been issued and
+    /// not been retired yet.
+    ///
+    /// It is used to check whether the [`RetireConnectionIdFrame`] refers to the
+    /// Destination Connection ID of the packet in which the frame is contained.
+    pub fn get(&self, seq: u64) -> Option<ConnectionId> {
+        self.0
+            .lock()
+            .unwrap()
+            .cid_deque
+            .get(seq)
+            .and_then(|v| v.map(|(cid, _)| cid))
+    }
+
+    /// Get the sequence number of the connection ID `cid`, if it has been issued and
+    /// not been retired yet.
+    ///
+    /// With the multipath extension, the packets received with a connection ID belong to the
+    /// packet number space identified by its sequence number.
+    pub fn sequence_of(&self, cid: &ConnectionId) -> Option<u64> {
+        self.0
+            .lock()
+            .unwrap()
+            .cid_deque
+            .iter_with_idx()
+            .find_map(|(seq, v)| v.filter(|(c, _)| c == cid).map(|_| seq))
+    }
+
     /// Set the maximum number of active connection IDs.
     ///
     /// After fully obtaining the peer's connection parameters, extract the peer's
@@ -274,5 +342,104 @@ mod tests {
         };
         let cid3 = local_cids.recv_retire_cid_frame(&retire_frame);
         assert!(cid3.is_ok());
+
+        // retire a connection ID that has not been issued yet
+        let retire_frame = RetireConnectionIdFrame {
+            sequence: VarInt::from_u32(10),
+        };
+        assert_eq!(
+            local_cids
+                .recv_retire_cid_frame(&retire_frame)
+                .map_err(|e| e.kind()),
+            Err(ErrorKind::ProtocolViolation)
+        );
+    }
+
+    #[test]
+    fn test_issue_within_limit() {
+        let initial_scid = ConnectionId::random_gen(8);
+        let mut local_cids = LocalCids::new(initial_scid, IssuedCids::default());
+        local_cids.set_limit(4).unwrap();
+        assert_eq!(local_cids.active_count(), 4);
+        assert_eq!(local_cids.issued_cids.lock_guard().len(), 3);
+
+        // 每退休一个才补发一个，活跃的连接ID数量始终不超过限制
+        for seq in [1, 0, 3] {
+            let retire_frame = RetireConnectionIdFrame {
+                sequence: VarInt::from_u32(seq),
+            };
+            assert!(local_cids
+                .recv_retire_cid_frame(&retire_frame)
+                .unwrap()
+                .is_some());
+            assert_eq!(local_cids.active_count(), 4);
+        }
+        assert_eq!(local_cids.issued_cids.lock_guard().len(), 6);
+
+        // 重复的退休帧不会导致多发
+        let retire_frame = RetireConnectionIdFrame {
+            sequence: VarInt::from_u32(3),
+        };
+        assert_eq!(local_cids.recv_retire_cid_frame(&retire_frame), Ok(None));
+        assert_eq!(local_cids.active_count(), 4);
+        assert_eq!(local_cids.issued_cids.lock_guard().len(), 6);
+    }
+
+    #[test]
+    fn test_set_limit_after_retirement() {
+        let initial_scid = ConnectionId::random_gen(8);
+        let mut local_cids = LocalCids::new(initial_scid, IssuedCids::default());
+        // 在得知对端的限制之前，按默认的2个补发
+        let retire_frame = RetireConnectionIdFrame {
+            sequence: VarInt::from_u32(1),
+        };
+        assert!(local_cids
+            .recv_retire_cid_frame(&retire_frame)
+            .unwrap()
+            .is_some());
+        assert_eq!(local_cids.active_count(), 2);
+        assert_eq!(local_cids.issued_cids.lock_guard().len(), 2);
+
+        local_cids.set_limit(3).unwrap();
+        assert_eq!(local_cids.active_count(), 3);
+        assert_eq!(local_cids.issued_cids.lock_guard().len(), 3);
+    }
+
+    #[test]
+    fn test_preferred_cid() {
+        let initial_scid = ConnectionId::random_gen(8);
+        let preferred_cid = ConnectionId::random_gen(8);
+        let reset_token = ResetToken::new(&[0x01; 16]);
+        let local_cids = ArcLocalCids::with_preferred_cid(
+            initial_scid,
+            (preferred_cid, reset_token),
+            IssuedCids::default(),
+        );
+        assert_eq!(local_cids.get(1), Some(preferred_cid));
+
+        let mut guard = local_cids.0.lock().unwrap();
+        // 首选地址的连接ID在传输参数中发布，不通过NEW_CONNECTION_ID帧
+        assert!(guard.issued_cids.lock_guard().is_empty());
+        guard.set_limit(3).unwrap();
+        let issued = guard.issued_cids.lock_guard().clone();
+        assert_eq!(issued.len(), 1);
+        assert_eq!(issued[0].sequence, VarInt::from_u32(2));
+    }
+
+    #[test]
+    fn test_get_local_cid() {
+        let initial_scid = ConnectionId::random_gen(8);
+        let local_cids = ArcLocalCids::new(initial_scid, IssuedCids::default());
+        assert_eq!(local_cids.get(0), Some(initial_scid));
+        assert_eq!(local_cids.sequence_of(&initial_scid), Some(0));
+
+        let retire_frame = RetireConnectionIdFrame {
+            sequence: VarInt::from_u32(0),
+        };
+        assert_eq!(local_cids.recv_frame(&retire_frame), Ok(Some(initial_scid)));
+        assert_eq!(local_cids.get(0), None);
+        assert_eq!(local_cids.sequence_of(&initial_scid), None);
+        let cid = local_cids.get(1).unwrap();
+        assert_eq!(local_cids.sequence_of(&cid), Some(1));
     }
 }
@@ -70,14 +70,36 @@ where
     /// response packet from the server, and the initial dcid should be updated
     /// based on the scid in the response packet.
     fn revise_initial_dcid(&mut self, initial_dcid: ConnectionId) {
-        let first_dcid = self.cid_deque.get_mut(0).unwrap();
-        *first_dcid = Some((0, initial_dcid, ResetToken::default()));
+        if let Some(Some((_, first_dcid, _))) = self.cid_deque.get_mut(0) {
+            *first_dcid = initial_dcid;
+        }
 
         if let Some(apply) = self.ready_cells.get_mut(0) {
             apply.revise(initial_dcid);
         }
     }
 
+    /// Set the stateless reset token of the initial dcid, which is carried by the
+    /// `stateless_reset_token` transport parameter of the server.
+    fn set_initial_reset_token(&mut self, reset_token: ResetToken) {
+        if let Some(Some((_, _, token))) = self.cid_deque.get_mut(0) {
+            *token = reset_token;
+        }
+    }
+
+    /// Check whether the token is the stateless reset token of any active connection ID.
+    ///
+    /// The default token, which is used as a placeholder for the initial dcid whose reset
+    /// token is unknown, never matches.
+    fn is_stateless_reset(&self, reset_token: &ResetToken) -> bool {
+        let placeholder = ResetToken::default();
+        self.cid_deque
+            .iter()
+            .flatten()
+            .filter(|(_, _, token)| *token != placeholder)
+            .any(|(_, _, token)| token.ct_eq(reset_token))
+    }
+
     /// Receive a [`NewConnectionIdFrame`] from peer.
     ///
     /// Add the new connection id to the deque, and retire the old cids before
@@ -91,7 +113,46 @@ where
     ) -> Result<Option<ResetToken>, Error> {
         let seq = frame.sequence.into_inner();
         let retire_prior_to = frame.retire_prior_to.into_inner();
-        let active_len = seq.saturating_sub(retire_prior_to);
+
+        // Discard the frame if the sequence number is less than the current offset.
+        if frame.sequence < self.cid_deque.offset() {
+            return Ok(None);
+        }
+
+        let id = frame.id;
+        let token = frame.reset_token;
+        // A retransmitted frame is harmless, but a sequence number used for different
+        // connection IDs or reset tokens is a PROTOCOL_VIOLATION.
+        if let Some(Some((_, known_id, known_token))) = self.cid_deque.get(seq) {
+            if *known_id != id || *known_token != token {
+                return Err(Error::new(
+                    crate::error::ErrorKind::ProtocolViolation,
+                    frame.frame_type(),
+                    format!("sequence {seq} is used for different connection IDs"),
+                ));
+            }
+            return Ok(None);
+        }
+
+        // 对端逐个递增地签发连接ID，空缺只来自乱序和丢包，不会远远超出活跃数量的限制；
+        // 否则插入时会把队列扩展到seq
+        let window = self.cid_deque.largest() + 2 * self.active_cid_limit;
+        if seq > window {
+            return Err(Error::new(
+                crate::error::ErrorKind::ConnectionIdLimit,
+                frame.frame_type(),
+                format!("sequence {seq} is too far beyond {window}"),
+            ));
+        }
+
+        // 收到此帧后仍然活跃的连接ID，即未被退休的已知连接ID，以及这个新的连接ID，
+        // 乱序到达的帧之间的空缺不计在内
+        let active_len = self
+            .cid_deque
+            .iter_with_idx()
+            .filter(|(idx, cid)| *idx >= retire_prior_to && cid.is_some())
+            .count() as u64
+            + (seq >= retire_prior_to) as u64;
         if active_len > self.active_cid_limit {
             return Err(Error::new(
                 crate::error::ErrorKind::ConnectionIdLimit,
@@ -102,14 +163,6 @@ where
                 ),
             ));
         }
-
-        // Discard the frame if the sequence number is less than the current offset.
-        if frame.sequence < self.cid_deque.offset() {
-            return Ok(None);
-        }
-
-        let id = frame.id;
-        let token = frame.reset_token;
         self.cid_deque.insert(seq, Some((seq, id, token))).unwrap();
         self.retire_prior_to(retire_prior_to);
         self.arrange_idle_cid();
@@ -249,6 +302,22 @@ where
         self.0.lock().unwrap().revise_initial_dcid(initial_dcid);
     }
 
+    /// Set the stateless reset token of the initial dcid, which is learned from the
+    /// `stateless_reset_token` transport parameter of the server.
+    pub fn set_initial_reset_token(&self, reset_token: ResetToken) {
+        self.0.lock().unwrap().set_initial_reset_token(reset_token);
+    }
+
+    /// Check whether a datagram which can not be decrypted is a stateless reset, that is, the
+    /// tail of the datagram, read by [`ResetToken::from_datagram_tail`], is the stateless reset
+    /// token of any active connection ID issued by the peer.
+    ///
+    /// See [detecting a stateless reset](https://www.rfc-editor.org/rfc/rfc9000.html#name-detecting-a-stateless-reset)
+    /// of [QUIC](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
+    pub fn is_stateless_reset(&self, reset_token: &ResetToken) -> bool {
+        self.0.lock().unwrap().is_stateless_reset(reset_token)
+    }
+
     /// Apply for a new connection ID, which is used when the Path is created.
     ///
     /// Return an [`ArcCidCell`], which may be not ready state.
@@ -271,6 +340,20 @@ where
             .next()
             .map(|(_, cid, _)| *cid)
     }
+
+    /// Return the connection ID with sequence number 0, which is the source connection ID of the
+    /// Initial packets received from the peer, if it is not retired yet.
+    ///
+    /// It must match the `initial_source_connection_id` transport parameter of the peer.
+    pub fn initial_dcid(&self) -> Option<ConnectionId> {
+        let guard = self.0.lock().unwrap();
+        guard
+            .cid_deque
+            .get(0)
+            .copied()
+            .flatten()
+            .map(|(_, cid, _)| cid)
+    }
 }
 
 impl<RETIRED> ReceiveFrame<NewConnectionIdFrame> for ArcRemoteCids<RETIRED>
@@ -324,7 +407,7 @@ where
         self.allocated_cids[0].1 = dcid;
     }
 
-    fn poll_borrow_cid(&mut self, cx: &mut Context<'_>) -> Poll<Option<ConnectionId>> {
+    fn poll_borrow_cid(&mut self, cx: &mut Context<'_>) -> Poll<Option<(u64, ConnectionId)>> {
         if self.is_retired {
             return Poll::Ready(None);
         }
@@ -333,9 +416,8 @@ where
             self.waker = Some(cx.waker().clone());
             Poll::Pending
         } else {
-            let cid = self.allocated_cids[0].1;
             self.is_using = true;
-            Poll::Ready(Some(cid))
+            Poll::Ready(Some(self.allocated_cids[0]))
         }
     }
 
@@ -408,6 +490,22 @@ where
     /// then this cid apply is retired.
     /// In this case, None will be returned.
     pub fn poll_borrow_cid(&self, cx: &mut Context<'_>) -> Poll<Option<ConnectionId>> {
+        self.0
+            .lock()
+            .unwrap()
+            .poll_borrow_cid(cx)
+            .map(|borrowed| borrowed.map(|(_, cid)| cid))
+    }
+
+    /// The same as [`ArcCidCell::poll_borrow_cid`], but also returns the sequence number of the
+    /// borrowed connection ID.
+    ///
+    /// With the multipath extension, the packets sent with a connection ID belong to the packet
+    /// number space identified by its sequence number.
+    pub fn poll_borrow_cid_with_seq(
+        &self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<(u64, ConnectionId)>> {
         self.0.lock().unwrap().poll_borrow_cid(cx)
     }
 
@@ -470,6 +568,10 @@ mod tests {
         assert_eq!(remote_cids.cid_deque.len(), 2);
 
         assert_eq!(cid_apply1.poll_borrow_cid(&mut cx), Poll::Ready(Some(cid)));
+        assert_eq!(
+            cid_apply1.poll_borrow_cid_with_seq(&mut cx),
+            Poll::Ready(Some((1, cid)))
+        );
 
         // Additionally, a new request will be made because if the peer-issued CID is
         // insufficient, it will still return Pending.
@@ -477,6 +579,144 @@ mod tests {
         assert_eq!(cid_apply2.poll_borrow_cid(&mut cx), Poll::Pending);
     }
 
+    #[test]
+    fn test_repeated_new_cid_frame() {
+        let initial_dcid = ConnectionId::random_gen(8);
+        let retired_cids = RetiredCids::default();
+        let mut remote_cids = RemoteCids::new(initial_dcid, 8, retired_cids);
+
+        let frame = NewConnectionIdFrame {
+            sequence: VarInt::from_u32(1),
+            retire_prior_to: VarInt::from_u32(0),
+            id: ConnectionId::random_gen(8),
+            reset_token: ResetToken::random_gen(),
+        };
+        assert_eq!(
+            remote_cids.recv_new_cid_frame(&frame),
+            Ok(Some(frame.reset_token))
+        );
+        // the retransmitted frame is ignored
+        assert_eq!(remote_cids.recv_new_cid_frame(&frame), Ok(None));
+        assert_eq!(remote_cids.cid_deque.len(), 2);
+
+        let conflict = NewConnectionIdFrame {
+            id: ConnectionId::random_gen(8),
+            ..frame
+        };
+        assert_eq!(
+            remote_cids
+                .recv_new_cid_frame(&conflict)
+                .map_err(|e| e.kind()),
+            Err(crate::error::ErrorKind::ProtocolViolation)
+        );
+    }
+
+    #[test]
+    fn test_exceed_active_cid_limit() {
+        let initial_dcid = ConnectionId::random_gen(8);
+        let retired_cids = RetiredCids::default();
+        let mut remote_cids = RemoteCids::new(initial_dcid, 2, retired_cids);
+        let new_cid_frame = |seq, retire_prior_to| NewConnectionIdFrame {
+            sequence: VarInt::from_u32(seq),
+            retire_prior_to: VarInt::from_u32(retire_prior_to),
+            id: ConnectionId::random_gen(8),
+            reset_token: ResetToken::random_gen(),
+        };
+
+        assert!(remote_cids.recv_new_cid_frame(&new_cid_frame(1, 0)).is_ok());
+        // 序号0、1、2同时活跃，超过了我们通告的限制
+        assert_eq!(
+            remote_cids
+                .recv_new_cid_frame(&new_cid_frame(2, 0))
+                .map_err(|e| e.kind()),
+            Err(crate::error::ErrorKind::ConnectionIdLimit)
+        );
+        // 同时退休序号0，活跃的数量仍在限制内
+        assert!(remote_cids.recv_new_cid_frame(&new_cid_frame(2, 1)).is_ok());
+        assert_eq!(remote_cids.cid_deque.offset(), 1);
+
+        // 乱序到达的序号5，尚未收到的序号3、4不计入活跃的数量
+        assert!(remote_cids.recv_new_cid_frame(&new_cid_frame(5, 2)).is_ok());
+        assert_eq!(
+            remote_cids
+                .recv_new_cid_frame(&new_cid_frame(4, 2))
+                .map_err(|e| e.kind()),
+            Err(crate::error::ErrorKind::ConnectionIdLimit)
+        );
+    }
+
+    #[test]
+    fn test_sequence_too_far_ahead() {
+        let initial_dcid = ConnectionId::random_gen(8);
+        let retired_cids = RetiredCids::default();
+        let mut remote_cids = RemoteCids::new(initial_dcid, 2, retired_cids);
+        let new_cid_frame = |seq, retire_prior_to| NewConnectionIdFrame {
+            sequence: VarInt::from_u64(seq).unwrap(),
+            retire_prior_to: VarInt::from_u64(retire_prior_to).unwrap(),
+            id: ConnectionId::random_gen(8),
+            reset_token: ResetToken::random_gen(),
+        };
+
+        // 序号远超已知的最大序号，即使退休了之前所有的连接ID，也不能把队列扩展过去
+        assert_eq!(
+            remote_cids
+                .recv_new_cid_frame(&new_cid_frame(1 << 40, 1))
+                .map_err(|e| e.kind()),
+            Err(crate::error::ErrorKind::ConnectionIdLimit)
+        );
+        assert_eq!(remote_cids.cid_deque.len(), 1);
+        // 窗口之内乱序到达的序号仍然被接受
+        assert!(remote_cids.recv_new_cid_frame(&new_cid_frame(5, 4)).is_ok());
+    }
+
+    #[test]
+    fn test_initial_dcid() {
+        let initial_dcid = ConnectionId::random_gen(8);
+        let remote_cids = ArcRemoteCids::new(initial_dcid, 8, RetiredCids::default());
+        assert_eq!(remote_cids.initial_dcid(), Some(initial_dcid));
+
+        let server_scid = ConnectionId::random_gen(8);
+        remote_cids.revise_initial_dcid(server_scid);
+        assert_eq!(remote_cids.initial_dcid(), Some(server_scid));
+    }
+
+    #[test]
+    fn test_stateless_reset() {
+        let initial_dcid = ConnectionId::random_gen(8);
+        let remote_cids = ArcRemoteCids::new(initial_dcid, 8, RetiredCids::default());
+
+        let is_stateless_reset = |datagram: &[u8]| {
+            ResetToken::from_datagram_tail(datagram)
+                .is_some_and(|token| remote_cids.is_stateless_reset(&token))
+        };
+        // the reset token of the initial dcid is unknown yet, zero tail never matches
+        assert!(!is_stateless_reset(&[0; 32]));
+
+        let frame = NewConnectionIdFrame {
+            sequence: VarInt::from_u32(1),
+            retire_prior_to: VarInt::from_u32(0),
+            id: ConnectionId::random_gen(8),
+            reset_token: ResetToken::random_gen(),
+        };
+        remote_cids.recv_frame(&frame).unwrap();
+        let initial_reset_token = ResetToken::random_gen();
+        remote_cids.set_initial_reset_token(initial_reset_token);
+
+        for token in [frame.reset_token, initial_reset_token] {
+            let mut datagram = vec![0x40];
+            datagram.extend(std::iter::repeat_with(rand::random::<u8>).take(20));
+            datagram.extend_from_slice(&token);
+            assert!(is_stateless_reset(&datagram));
+            // too short to be a stateless reset
+            assert!(!is_stateless_reset(&datagram[datagram.len() - 20..]));
+        }
+
+        let random_datagram = std::iter::repeat_with(rand::random::<u8>)
+            .take(64)
+            .collect::<Vec<_>>();
+        assert!(!is_stateless_reset(&random_datagram));
+    }
+
     #[test]
     fn test_retire_in_remote_cids() {
         let waker = futures::task::noop_waker();
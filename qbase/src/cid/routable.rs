@@ -0,0 +1,370 @@
+use aes::{
+    cipher::{generic_array::GenericArray, BlockDecrypt, BlockEncrypt, KeyInit},
+    Aes128,
+};
+
+use super::{CidGenerator, ConnectionId, MAX_CID_SIZE};
+use crate::entropy::ArcEntropy;
+
+/// The size of an AES-128 block, and of the key.
+const BLOCK_SIZE: usize = 16;
+
+/// The configuration shared by the load balancer and the servers behind it, to encode and decode
+/// the routable connection IDs of
+/// [QUIC-LB](https://datatracker.ietf.org/doc/draft-ietf-quic-load-balancers/).
+///
+/// - The first octet holds the config rotation bits(`config_id`) in its 3 most significant bits,
+///   and the length of the connection ID minus one in the remaining 5 bits, it is never encrypted;
+/// - It is followed by the server ID and a nonce, which are encrypted together when a key is
+///   configured, or left in plaintext otherwise.
+///
+/// When the server ID and the nonce are 16 bytes in total, they are encrypted as a single
+/// AES-128-ECB block(the single-pass algorithm). Otherwise they are encrypted by a four-pass
+/// Feistel network whose round function is AES-128-ECB(the four-pass algorithm), for the odd
+/// lengths, the two halves share the middle octet, 4 bits each.
+#[derive(Clone)]
+pub struct RoutableCidConfig {
+    config_id: u8,
+    server_id_len: usize,
+    nonce_len: usize,
+    cipher: Option<Aes128>,
+}
+
+impl std::fmt::Debug for RoutableCidConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RoutableCidConfig")
+            .field("config_id", &self.config_id)
+            .field("server_id_len", &self.server_id_len)
+            .field("nonce_len", &self.nonce_len)
+            .field("encrypted", &self.cipher.is_some())
+            .finish()
+    }
+}
+
+impl RoutableCidConfig {
+    /// Create a new configuration, the server IDs and the nonces are encrypted with the AES-128
+    /// `key` if it is provided.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `config_id` is not in `0..=6`, the `server_id_len` is not in `1..=15`,
+    /// the `nonce_len` is not in `4..=18`, or the connection ID would be longer than 20 bytes.
+    pub fn new(
+        config_id: u8,
+        server_id_len: usize,
+        nonce_len: usize,
+        key: Option<&[u8; BLOCK_SIZE]>,
+    ) -> Self {
+        // 0b111 保留给不可路由的连接ID
+        assert!(config_id < 7, "config_id must be in 0..=6");
+        assert!(
+            (1..=15).contains(&server_id_len),
+            "server_id_len must be in 1..=15"
+        );
+        assert!((4..=18).contains(&nonce_len), "nonce_len must be in 4..=18");
+        assert!(
+            1 + server_id_len + nonce_len <= MAX_CID_SIZE,
+            "the connection ID must not exceed 20 bytes"
+        );
+        Self {
+            config_id,
+            server_id_len,
+            nonce_len,
+            cipher: key.map(|key| Aes128::new(GenericArray::from_slice(&key[..]))),
+        }
+    }
+
+    /// The length of the connection IDs of this configuration.
+    pub fn cid_len(&self) -> usize {
+        1 + self.server_id_len + self.nonce_len
+    }
+
+    /// Encode the `server_id` and the `nonce` into a connection ID.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the length of the `server_id` or the `nonce` does not match the configuration.
+    pub fn encode(&self, server_id: &[u8], nonce: &[u8]) -> ConnectionId {
+        assert_eq!(server_id.len(), self.server_id_len);
+        assert_eq!(nonce.len(), self.nonce_len);
+        let mut bytes = [0; MAX_CID_SIZE];
+        bytes[0] = self.first_octet();
+        bytes[1..1 + self.server_id_len].copy_from_slice(server_id);
+        bytes[1 + self.server_id_len..self.cid_len()].copy_from_slice(nonce);
+        if let Some(cipher) = &self.cipher {
+            self.encrypt(cipher, &mut bytes[1..self.cid_len()]);
+        }
+        ConnectionId::from_slice(&bytes[..self.cid_len()])
+    }
+
+    /// Decode the server ID from the `cid`.
+    ///
+    /// Return `None` if the `cid` is not encoded with this configuration.
+    pub fn decode(&self, cid: &[u8]) -> Option<Vec<u8>> {
+        if cid.len() != self.cid_len() || cid[0] >> 5 != self.config_id {
+            return None;
+        }
+        let mut plaintext = cid[1..].to_vec();
+        if let Some(cipher) = &self.cipher {
+            self.decrypt(cipher, &mut plaintext);
+        }
+        plaintext.truncate(self.server_id_len);
+        Some(plaintext)
+    }
+
+    fn first_octet(&self) -> u8 {
+        (self.config_id << 5) | ((self.cid_len() - 1) as u8 & 0x1F)
+    }
+
+    fn encrypt(&self, cipher: &Aes128, plaintext: &mut [u8]) {
+        if plaintext.len() == BLOCK_SIZE {
+            cipher.encrypt_block(GenericArray::from_mut_slice(plaintext));
+            return;
+        }
+        let mut halves = Halves::split(plaintext);
+        halves.right_pass(cipher, 1);
+        halves.left_pass(cipher, 2);
+        halves.right_pass(cipher, 3);
+        halves.left_pass(cipher, 4);
+        halves.join(plaintext);
+    }
+
+    fn decrypt(&self, cipher: &Aes128, ciphertext: &mut [u8]) {
+        if ciphertext.len() == BLOCK_SIZE {
+            cipher.decrypt_block(GenericArray::from_mut_slice(ciphertext));
+            return;
+        }
+        let mut halves = Halves::split(ciphertext);
+        halves.left_pass(cipher, 4);
+        halves.right_pass(cipher, 3);
+        halves.left_pass(cipher, 2);
+        halves.right_pass(cipher, 1);
+        halves.join(ciphertext);
+    }
+}
+
+/// The two halves of the four-pass algorithm, each of them is `ceil(len / 2)` bytes.
+///
+/// For the odd lengths, the left half takes the upper 4 bits of the middle octet, with the lower
+/// 4 bits of its last octet zeroed, and the right half takes the lower 4 bits, with the upper 4
+/// bits of its first octet zeroed.
+struct Halves {
+    len: usize,
+    left: [u8; BLOCK_SIZE],
+    right: [u8; BLOCK_SIZE],
+}
+
+impl Halves {
+    fn half_len(&self) -> usize {
+        self.len.div_ceil(2)
+    }
+
+    fn split(bytes: &[u8]) -> Self {
+        let mut halves = Self {
+            len: bytes.len(),
+            left: [0; BLOCK_SIZE],
+            right: [0; BLOCK_SIZE],
+        };
+        let half_len = halves.half_len();
+        halves.left[..half_len].copy_from_slice(&bytes[..half_len]);
+        halves.right[..half_len].copy_from_slice(&bytes[bytes.len() - half_len..]);
+        Self::mask_left(halves.len, &mut halves.left);
+        Self::mask_right(halves.len, &mut halves.right);
+        halves
+    }
+
+    fn mask_left(len: usize, half: &mut [u8; BLOCK_SIZE]) {
+        if len % 2 == 1 {
+            half[len.div_ceil(2) - 1] &= 0xF0;
+        }
+    }
+
+    fn mask_right(len: usize, half: &mut [u8; BLOCK_SIZE]) {
+        if len % 2 == 1 {
+            half[0] &= 0x0F;
+        }
+    }
+
+    fn join(&self, bytes: &mut [u8]) {
+        let half_len = self.half_len();
+        bytes[..half_len].copy_from_slice(&self.left[..half_len]);
+        if self.len % 2 == 1 {
+            // 中间的字节由左半部分的高4位与右半部分的低4位拼成
+            bytes[half_len - 1] |= self.right[0];
+            bytes[half_len..].copy_from_slice(&self.right[1..half_len]);
+        } else {
+            bytes[half_len..].copy_from_slice(&self.right[..half_len]);
+        }
+    }
+
+    /// The 16 bytes input of AES-128-ECB in each pass: the `half`, zero padded, followed by the
+    /// length of the plaintext and the index of the pass.
+    fn expand(len: usize, pass: u8, half: &[u8]) -> [u8; BLOCK_SIZE] {
+        let mut block = [0; BLOCK_SIZE];
+        block[..half.len()].copy_from_slice(half);
+        block[BLOCK_SIZE - 2] = len as u8;
+        block[BLOCK_SIZE - 1] = pass;
+        block
+    }
+
+    fn aes_ecb(&self, cipher: &Aes128, pass: u8, half: &[u8; BLOCK_SIZE]) -> [u8; BLOCK_SIZE] {
+        let mut block = Self::expand(self.len, pass, &half[..self.half_len()]);
+        cipher.encrypt_block(GenericArray::from_mut_slice(&mut block[..]));
+        block
+    }
+
+    /// Xor the right half with the rightmost bits of the AES-128-ECB output of the left half.
+    fn right_pass(&mut self, cipher: &Aes128, pass: u8) {
+        let half_len = self.half_len();
+        let output = self.aes_ecb(cipher, pass, &self.left);
+        let mut mask = [0; BLOCK_SIZE];
+        mask[..half_len].copy_from_slice(&output[BLOCK_SIZE - half_len..]);
+        Self::mask_right(self.len, &mut mask);
+        self.right
+            .iter_mut()
+            .zip(mask)
+            .for_each(|(byte, mask)| *byte ^= mask);
+    }
+
+    /// Xor the left half with the leftmost bits of the AES-128-ECB output of the right half.
+    fn left_pass(&mut self, cipher: &Aes128, pass: u8) {
+        let half_len = self.half_len();
+        let output = self.aes_ecb(cipher, pass, &self.right);
+        let mut mask = [0; BLOCK_SIZE];
+        mask[..half_len].copy_from_slice(&output[..half_len]);
+        Self::mask_left(self.len, &mut mask);
+        self.left
+            .iter_mut()
+            .zip(mask)
+            .for_each(|(byte, mask)| *byte ^= mask);
+    }
+}
+
+/// A [`CidGenerator`] that embeds the server ID into the connection IDs, so that the load
+/// balancer can route the packets to this server, read [`RoutableCidConfig`].
+#[derive(Debug, Clone)]
+pub struct RoutableCidGenerator {
+    config: RoutableCidConfig,
+    server_id: Vec<u8>,
+    entropy: ArcEntropy,
+}
+
+impl RoutableCidGenerator {
+    /// Create a generator for the server identified by the `server_id`, the nonces are filled
+    /// by the `entropy` source.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the length of the `server_id` does not match the `config`.
+    pub fn new(config: RoutableCidConfig, server_id: &[u8], entropy: ArcEntropy) -> Self {
+        assert_eq!(server_id.len(), config.server_id_len);
+        Self {
+            config,
+            server_id: server_id.to_vec(),
+            entropy,
+        }
+    }
+}
+
+impl CidGenerator for RoutableCidGenerator {
+    fn cid_len(&self) -> usize {
+        self.config.cid_len()
+    }
+
+    fn generate_cid(&self) -> ConnectionId {
+        let mut nonce = [0; MAX_CID_SIZE];
+        let nonce = &mut nonce[..self.config.nonce_len];
+        self.entropy.fill_bytes(nonce);
+        self.config.encode(&self.server_id, nonce)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::entropy::SeededEntropy;
+
+    #[test]
+    fn test_decode_server_id() {
+        let server_id = [0x31, 0x44, 0x1a];
+        for key in [None, Some(b"fdcc1ef2d9b6c8a0")] {
+            let config = RoutableCidConfig::new(2, server_id.len(), 4, key);
+            let generator = RoutableCidGenerator::new(
+                config.clone(),
+                &server_id,
+                Arc::new(SeededEntropy::new(7)),
+            );
+            let cid = generator.generate_cid();
+            assert_eq!(cid.len(), 8);
+            assert_eq!(cid[0], (2 << 5) | 7);
+            assert_eq!(config.decode(&cid).as_deref(), Some(&server_id[..]));
+            // 每次生成的连接ID都不同，但都能解出相同的server ID
+            let another = generator.generate_cid();
+            assert_ne!(another, cid);
+            assert_eq!(config.decode(&another).as_deref(), Some(&server_id[..]));
+        }
+
+        // 加密后server ID不再以明文出现，错误的密钥或配置解不出server ID
+        let config = RoutableCidConfig::new(2, 3, 4, Some(b"fdcc1ef2d9b6c8a0"));
+        let cid = config.encode(&server_id, &[0; 4]);
+        assert_ne!(&cid[1..4], &server_id[..]);
+        let wrong_key = RoutableCidConfig::new(2, 3, 4, Some(b"0000000000000000"));
+        assert_ne!(wrong_key.decode(&cid).as_deref(), Some(&server_id[..]));
+        assert_eq!(RoutableCidConfig::new(3, 3, 4, None).decode(&cid), None);
+    }
+
+    #[test]
+    fn test_single_pass() {
+        // FIPS-197附录C.1的AES-128向量，server ID与nonce共16字节时即单次AES-128-ECB
+        let key = core::array::from_fn(|i| i as u8);
+        let plaintext = [
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd,
+            0xee, 0xff,
+        ];
+        let ciphertext = [
+            0x69, 0xc4, 0xe0, 0xd8, 0x6a, 0x7b, 0x04, 0x30, 0xd8, 0xcd, 0xb7, 0x80, 0x70, 0xb4,
+            0xc5, 0x5a,
+        ];
+        let config = RoutableCidConfig::new(1, 8, 8, Some(&key));
+        let cid = config.encode(&plaintext[..8], &plaintext[8..]);
+        assert_eq!(cid[0], (1 << 5) | 16);
+        assert_eq!(&cid[1..], &ciphertext);
+        assert_eq!(config.decode(&cid).as_deref(), Some(&plaintext[..8]));
+    }
+
+    #[test]
+    fn test_expand() {
+        // QUIC-LB草案中expand的示例
+        assert_eq!(
+            Halves::expand(0x06, 0x02, &[0xaa, 0xba, 0x3c]),
+            [
+                0xaa, 0xba, 0x3c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                0x06, 0x02
+            ]
+        );
+    }
+
+    #[test]
+    fn test_four_pass() {
+        let key = b"fdcc1ef2d9b6c8a0";
+        for server_id_len in 1..=15 {
+            for nonce_len in (4..=18).filter(|len| server_id_len + len <= 19) {
+                if server_id_len + nonce_len == BLOCK_SIZE {
+                    continue;
+                }
+                let config = RoutableCidConfig::new(0, server_id_len, nonce_len, Some(key));
+                let server_id = (1..=server_id_len as u8).collect::<Vec<_>>();
+                let nonce = vec![0x5a; nonce_len];
+                let cid = config.encode(&server_id, &nonce);
+                assert_eq!(cid.len(), config.cid_len());
+                let plain = RoutableCidConfig::new(0, server_id_len, nonce_len, None);
+                assert_eq!(cid[0], plain.encode(&server_id, &nonce)[0]);
+                assert_ne!(cid[1..], plain.encode(&server_id, &nonce)[1..]);
+                // 奇数长度时，中间字节的高低4位分属左右两半，解密后仍能拼回
+                assert_eq!(config.decode(&cid), Some(server_id));
+            }
+        }
+    }
+}
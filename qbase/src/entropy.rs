@@ -0,0 +1,82 @@
+use std::{
+    fmt::Debug,
+    sync::{Arc, Mutex},
+};
+
+use rand::{
+    rngs::{OsRng, StdRng},
+    RngCore, SeedableRng,
+};
+
+/// The source of the randomness of a connection.
+///
+/// The connection IDs issued by the connection and the data of the [`PathChallengeFrame`]s are
+/// filled by the source. The operating system's random number generator is used by default, a
+/// [`SeededEntropy`] can be injected instead to get reproducible results in tests.
+///
+/// An endpoint behind a load balancer can also implement it to derive the connection IDs from a
+/// keyed routing scheme, as long as they are still unpredictable to the others.
+///
+/// [`PathChallengeFrame`]: crate::frame::PathChallengeFrame
+pub trait EntropySource: Send + Sync + Debug {
+    /// Fill the `dest` with random bytes.
+    fn fill_bytes(&self, dest: &mut [u8]);
+}
+
+/// The shared version of [`EntropySource`].
+pub type ArcEntropy = Arc<dyn EntropySource>;
+
+/// The random number generator of the operating system, which is the default
+/// [`EntropySource`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OsEntropy;
+
+impl EntropySource for OsEntropy {
+    fn fill_bytes(&self, dest: &mut [u8]) {
+        OsRng.fill_bytes(dest);
+    }
+}
+
+/// A deterministic [`EntropySource`], the same seed always produces the same bytes.
+///
+/// It is predictable, so only use it in tests.
+#[derive(Debug)]
+pub struct SeededEntropy(Mutex<StdRng>);
+
+impl SeededEntropy {
+    /// Create a new source seeded with the `seed`.
+    pub fn new(seed: u64) -> Self {
+        Self(Mutex::new(StdRng::seed_from_u64(seed)))
+    }
+}
+
+impl EntropySource for SeededEntropy {
+    fn fill_bytes(&self, dest: &mut [u8]) {
+        self.0.lock().unwrap().fill_bytes(dest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cid::ConnectionId, frame::PathChallengeFrame};
+
+    #[test]
+    fn test_seeded_entropy() {
+        let generate = |entropy: &SeededEntropy| {
+            let cids = (0..4)
+                .map(|_| ConnectionId::gen_with_mark(entropy, 8, 0x80, 0x7F))
+                .collect::<Vec<_>>();
+            (cids, PathChallengeFrame::from_entropy(entropy))
+        };
+        let (cids, challenge) = generate(&SeededEntropy::new(42));
+        // 相同的种子，生成相同的连接ID和挑战数据
+        assert_eq!(generate(&SeededEntropy::new(42)), (cids.clone(), challenge));
+        assert!(cids
+            .iter()
+            .all(|cid| cid.len() == 8 && cid[0] & 0x80 == 0x80));
+        assert!(cids.windows(2).all(|pair| pair[0] != pair[1]));
+
+        assert_ne!(generate(&SeededEntropy::new(43)).0, cids);
+    }
+}
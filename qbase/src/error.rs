@@ -208,6 +208,11 @@ impl Error {
     pub fn frame_type(&self) -> FrameType {
         self.frame_type
     }
+
+    /// Return the reason phrase of this error.
+    pub fn reason(&self) -> &Cow<'static, str> {
+        &self.reason
+    }
 }
 
 impl From<Error> for std::io::Error {
@@ -218,20 +223,24 @@ impl From<Error> for std::io::Error {
 
 impl From<Error> for ConnectionCloseFrame {
     fn from(e: Error) -> Self {
-        Self {
-            error_kind: e.kind,
-            frame_type: Some(e.frame_type),
-            reason: e.reason,
-        }
+        Self::new_quic(e.kind, e.frame_type, e.reason)
     }
 }
 
 impl From<ConnectionCloseFrame> for Error {
     fn from(value: ConnectionCloseFrame) -> Self {
-        Self {
-            kind: value.error_kind,
-            frame_type: value.frame_type.unwrap_or(FrameType::Padding),
-            reason: value.reason,
+        match value {
+            ConnectionCloseFrame::Quic(frame) => Self {
+                kind: frame.error_kind,
+                frame_type: frame.frame_type,
+                reason: frame.reason,
+            },
+            // 应用层的错误码由应用定义，只能以Application错误的形式体现
+            ConnectionCloseFrame::App(frame) => Self {
+                kind: ErrorKind::Application,
+                frame_type: FrameType::Padding,
+                reason: frame.reason,
+            },
         }
     }
 }
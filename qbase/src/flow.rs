@@ -126,6 +126,15 @@ impl ArcSendControler {
         }
     }
 
+    /// Returns the connection-level limit on the data we can send, which is the largest
+    /// `max_data` advertised by the peer, or 0 if the connection has encountered an error.
+    pub fn max_data(&self) -> u64 {
+        match self.0.lock().unwrap().deref() {
+            Ok(inner) => inner.max_data,
+            Err(_) => 0,
+        }
+    }
+
     /// For external monitoring, whether it is blocked.
     /// If blocked, a [`DataBlockedFrame`] needs to be sent to the other party.
     pub fn would_block(&self) -> WouldBlock {
@@ -253,8 +262,15 @@ pub struct Overflow(usize);
 #[derive(Debug, Default)]
 struct RecvController {
     total_rcvd: AtomicU64,
+    total_consumed: AtomicU64,
     max_data: AtomicU64,
-    step: u64,
+    window: u64,
+    // The largest limit reported by the DATA_BLOCKED frames from the peer.
+    blocked_limit: AtomicU64,
+    // Whether a DATA_BLOCKED frame has been received since the last poll.
+    is_blocked: AtomicBool,
+    // The number of DATA_BLOCKED frames received, for diagnosing flow control tuning.
+    data_blocked_frames: AtomicU64,
     is_closed: AtomicBool,
     waker: AtomicWaker,
 }
@@ -264,8 +280,12 @@ impl RecvController {
     fn with_initial(initial_max_data: u64) -> Self {
         Self {
             total_rcvd: AtomicU64::new(0),
+            total_consumed: AtomicU64::new(0),
             max_data: AtomicU64::new(initial_max_data),
-            step: initial_max_data / 2,
+            window: initial_max_data,
+            blocked_limit: AtomicU64::new(0),
+            is_blocked: AtomicBool::new(false),
+            data_blocked_frames: AtomicU64::new(0),
             is_closed: AtomicBool::new(false),
             waker: AtomicWaker::new(),
         }
@@ -273,9 +293,9 @@ impl RecvController {
 
     /// Handles the event when new data is received.
     ///
-    /// The data must be new, old retransmitted data does not count. Whether the data is
-    /// new or not will be determined by each stream after delivering the data packet to them.
-    /// The amount of new data will be passed as the `amount` parameter.
+    /// The data must be new, old retransmitted data does not count. Each stream counts the
+    /// increase of the largest offset received on it, up to the final size once it is reset,
+    /// which will be passed as the `amount` parameter.
     fn on_new_rcvd(&self, amount: usize) -> Result<usize, Overflow> {
         debug_assert!(!self.is_closed.load(Ordering::Relaxed));
 
@@ -283,33 +303,72 @@ impl RecvController {
         let total_rcvd = self.total_rcvd.load(Ordering::Acquire);
         let max_data = self.max_data.load(Ordering::Acquire);
         if total_rcvd <= max_data {
-            if total_rcvd + self.step >= max_data {
-                self.waker.wake();
-            }
             Ok(amount)
         } else {
             Err(Overflow((total_rcvd - max_data) as usize))
         }
     }
 
+    /// Handles the event when the application reads data from the streams.
+    ///
+    /// The read data frees up the receive buffer, once more than half of the window is
+    /// consumed, the receive window should be extended.
+    fn on_consumed(&self, amount: usize) {
+        let total_consumed = self
+            .total_consumed
+            .fetch_add(amount as u64, Ordering::AcqRel)
+            + amount as u64;
+        if self.is_window_half_consumed(total_consumed) {
+            self.waker.wake();
+        }
+    }
+
+    fn is_window_half_consumed(&self, total_consumed: u64) -> bool {
+        total_consumed + self.window / 2 > self.max_data.load(Ordering::Acquire)
+    }
+
+    /// Handles the DATA_BLOCKED frame from the peer, which reports it is blocked at `limit`.
+    ///
+    /// If the application has consumed any data, the receive window should be extended
+    /// immediately rather than waiting for half of the window to be consumed.
+    /// If the current limit is already above the blocked limit,
+    /// the last [`MaxDataFrame`] may have been lost, and it should be sent again.
+    fn on_data_blocked(&self, limit: u64) {
+        self.data_blocked_frames.fetch_add(1, Ordering::Relaxed);
+        self.blocked_limit.fetch_max(limit, Ordering::AcqRel);
+        self.is_blocked.store(true, Ordering::Release);
+        self.waker.wake();
+    }
+
     /// Polls for an increase in the receive window limit.
     fn poll_incr_limit(&self, cx: &mut Context<'_>) -> Poll<Option<MaxDataFrame>> {
         if self.is_closed.load(Ordering::Acquire) {
-            Poll::Ready(None)
+            return Poll::Ready(None);
+        }
+        // Register first, so that the wake-up in `on_consumed` will not be missed.
+        self.waker.register(cx.waker());
+        let is_blocked = self.is_blocked.swap(false, Ordering::AcqRel);
+        let total_consumed = self.total_consumed.load(Ordering::Acquire);
+        let max_data = self.max_data.load(Ordering::Acquire);
+        if self.is_window_half_consumed(total_consumed)
+            || (is_blocked && total_consumed + self.window > max_data)
+        {
+            // The new window starts from the data consumed by the application.
+            let max_data = total_consumed + self.window;
+            self.max_data.store(max_data, Ordering::Release);
+            Poll::Ready(Some(Self::max_data_frame(max_data)))
+        } else if is_blocked && max_data > self.blocked_limit.load(Ordering::Acquire) {
+            // The peer has not received the last MaxDataFrame yet
+            Poll::Ready(Some(Self::max_data_frame(max_data)))
         } else {
-            let max_data = self.max_data.load(Ordering::Acquire);
-            let total_rcvd = self.total_rcvd.load(Ordering::Acquire);
-
-            if total_rcvd + self.step >= max_data {
-                self.max_data.fetch_add(self.step, Ordering::Release);
-                Poll::Ready(Some(MaxDataFrame {
-                    max_data: VarInt::from_u64(self.max_data.load(Ordering::Acquire))
-                        .expect("max_data of flow controller is very very hard to exceed 2^62 - 1"),
-                }))
-            } else {
-                self.waker.register(cx.waker());
-                Poll::Pending
-            }
+            Poll::Pending
+        }
+    }
+
+    fn max_data_frame(max_data: u64) -> MaxDataFrame {
+        MaxDataFrame {
+            max_data: VarInt::from_u64(max_data)
+                .expect("max_data of flow controller is very very hard to exceed 2^62 - 1"),
         }
     }
 
@@ -356,6 +415,26 @@ impl ArcRecvController {
         self.0.on_new_rcvd(amount)
     }
 
+    /// Updates the total size of data consumed by the application.
+    ///
+    /// Once more than half of the receive window is consumed, the receive window is extended by
+    /// the [`IncrLimit`] future, and a [`MaxDataFrame`] needs to be sent to the sender.
+    pub fn on_consumed(&self, amount: usize) {
+        self.0.on_consumed(amount)
+    }
+
+    /// Returns the connection-level limit on the data the peer can send, which is the latest
+    /// `max_data` advertised to the peer.
+    pub fn max_data(&self) -> u64 {
+        self.0.max_data.load(Ordering::Acquire)
+    }
+
+    /// Returns the number of [`DataBlockedFrame`]s received from the peer,
+    /// which helps to diagnose whether the receive window is too small.
+    pub fn data_blocked_frames(&self) -> u64 {
+        self.0.data_blocked_frames.load(Ordering::Relaxed)
+    }
+
     /// Return a future that resolves when the receive window limit is increased.
     /// At that time, a [`MaxDataFrame`] needs to be sent to the sender.
     /// And this is a continuous monitoring process until the connection ends.
@@ -371,14 +450,15 @@ impl ArcRecvController {
 
 /// [`ArcRecvController`] need to receive [`DataBlockedFrame`] from peer.
 ///
-/// However, the receiver may also not be able to immediately expand the receive window
-/// and must wait for the application layer to read the data to free up more space
-/// in the receive buffer.
+/// The receiver expands the receive window immediately if the application layer has read
+/// any data, otherwise it must wait for the application layer to read the data to free up
+/// more space in the receive buffer. If the current limit is already above the blocked limit,
+/// the [`MaxDataFrame`] will be sent again, in case the peer lost it.
 impl ReceiveFrame<DataBlockedFrame> for ArcRecvController {
     type Output = ();
 
-    fn recv_frame(&self, _frame: &DataBlockedFrame) -> Result<Self::Output, QuicError> {
-        // Do nothing, just print a log
+    fn recv_frame(&self, frame: &DataBlockedFrame) -> Result<Self::Output, QuicError> {
+        self.0.on_data_blocked(frame.limit.into_inner());
         Ok(())
     }
 }
@@ -447,3 +527,90 @@ impl FlowController {
         self.recver.terminate();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use futures::task::noop_waker_ref;
+
+    use super::*;
+
+    #[test]
+    fn test_max_data_unblocks_sender() {
+        let sender = ArcSendControler::with_initial(100);
+        let recver = ArcRecvController::with_initial(100);
+        let mut cx = Context::from_waker(noop_waker_ref());
+
+        let credit = sender.credit().unwrap();
+        assert_eq!(credit.available(), 100);
+        credit.post_sent(100);
+        recver.on_new_rcvd(100).unwrap();
+        // The sender is blocked by the flow control
+        assert!(matches!(
+            Pin::new(&mut sender.would_block()).poll(&mut cx),
+            Poll::Ready(Ok(DataBlockedFrame { limit })) if limit.into_inner() == 100
+        ));
+        // Received data does not extend the window until it is consumed
+        assert!(Pin::new(&mut recver.incr_limit())
+            .poll(&mut cx)
+            .is_pending());
+        recver.on_consumed(50);
+        assert!(Pin::new(&mut recver.incr_limit())
+            .poll(&mut cx)
+            .is_pending());
+        recver.on_consumed(10);
+        let Poll::Ready(Some(max_data)) = Pin::new(&mut recver.incr_limit()).poll(&mut cx) else {
+            panic!("MAX_DATA should be sent after more than half of the window is consumed");
+        };
+        assert_eq!(max_data.max_data.into_inner(), 160);
+        assert_eq!(recver.max_data(), 160);
+        assert!(Pin::new(&mut recver.incr_limit())
+            .poll(&mut cx)
+            .is_pending());
+
+        sender.recv_frame(&max_data).unwrap();
+        assert_eq!(sender.max_data(), 160);
+        assert_eq!(sender.credit().unwrap().available(), 60);
+        recver.on_new_rcvd(60).unwrap();
+    }
+
+    #[test]
+    fn test_data_blocked_sends_max_data_sooner() {
+        let recver = ArcRecvController::with_initial(100);
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let blocked = |limit| DataBlockedFrame {
+            limit: VarInt::from_u32(limit),
+        };
+
+        recver.on_new_rcvd(100).unwrap();
+        // Nothing consumed, the window can not be extended
+        recver.recv_frame(&blocked(100)).unwrap();
+        assert!(Pin::new(&mut recver.incr_limit())
+            .poll(&mut cx)
+            .is_pending());
+
+        // Less than half of the window is consumed, but the peer is blocked
+        recver.on_consumed(10);
+        assert!(Pin::new(&mut recver.incr_limit())
+            .poll(&mut cx)
+            .is_pending());
+        recver.recv_frame(&blocked(100)).unwrap();
+        let Poll::Ready(Some(max_data)) = Pin::new(&mut recver.incr_limit()).poll(&mut cx) else {
+            panic!("MAX_DATA should be sent after DATA_BLOCKED received");
+        };
+        assert_eq!(max_data.max_data.into_inner(), 110);
+        assert!(Pin::new(&mut recver.incr_limit())
+            .poll(&mut cx)
+            .is_pending());
+
+        // The MAX_DATA frame was lost, the peer is still blocked at the old limit
+        recver.recv_frame(&blocked(100)).unwrap();
+        let Poll::Ready(Some(max_data)) = Pin::new(&mut recver.incr_limit()).poll(&mut cx) else {
+            panic!("MAX_DATA should be retransmitted");
+        };
+        assert_eq!(max_data.max_data.into_inner(), 110);
+        assert!(Pin::new(&mut recver.incr_limit())
+            .poll(&mut cx)
+            .is_pending());
+        assert_eq!(recver.data_blocked_frames(), 3);
+    }
+}
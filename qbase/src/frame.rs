@@ -3,20 +3,23 @@ use enum_dispatch::enum_dispatch;
 use io::WriteFrame;
 
 use super::varint::VarInt;
-use crate::packet::r#type::Type;
+use crate::{packet::r#type::Type, sid::Role};
 
 mod ack;
+mod ack_frequency;
 mod connection_close;
 mod crypto;
 mod data_blocked;
 mod datagram;
 mod handshake_done;
+mod immediate_ack;
 mod max_data;
 mod max_stream_data;
 mod max_streams;
 mod new_connection_id;
 mod new_token;
 mod padding;
+mod path_ack;
 mod path_challenge;
 mod path_response;
 mod ping;
@@ -33,19 +36,22 @@ pub mod error;
 pub mod io;
 
 pub use ack::{AckFrame, EcnCounts};
-pub use connection_close::ConnectionCloseFrame;
+pub use ack_frequency::AckFrequencyFrame;
+pub use connection_close::{AppCloseFrame, ConnectionCloseFrame, QuicCloseFrame};
 pub use crypto::CryptoFrame;
 pub use data_blocked::DataBlockedFrame;
 pub use datagram::DatagramFrame;
 #[doc(hidden)]
 pub use error::Error;
 pub use handshake_done::HandshakeDoneFrame;
+pub use immediate_ack::ImmediateAckFrame;
 pub use max_data::MaxDataFrame;
 pub use max_stream_data::MaxStreamDataFrame;
 pub use max_streams::MaxStreamsFrame;
 pub use new_connection_id::NewConnectionIdFrame;
 pub use new_token::NewTokenFrame;
 pub use padding::PaddingFrame;
+pub use path_ack::PathAckFrame;
 pub use path_challenge::PathChallengeFrame;
 pub use path_response::PathResponseFrame;
 pub use ping::PingFrame;
@@ -129,19 +135,26 @@ pub enum FrameType {
     HandshakeDone,
     /// DATAGRAM frame, see [`DatagramFrame`].
     Datagram(u8),
+    /// ACK_FREQUENCY frame, see [`AckFrequencyFrame`].
+    AckFrequency,
+    /// IMMEDIATE_ACK frame, see [`ImmediateAckFrame`].
+    ImmediateAck,
+    /// PATH_ACK frame, see [`PathAckFrame`].
+    PathAck(u8),
 }
 
 impl FrameType {
     /// Determine if a frame type belongs to the given packet_type
     pub fn belongs_to(&self, packet_type: Type) -> bool {
-        use crate::packet::r#type::{
-            long::{Type::V1, Ver1},
-            short::OneRtt,
+        use crate::packet::r#type::{long::v1::Type as LongType, short::OneRtt};
+        // IH01，各版本的长包类型相同，只是编码不同
+        let long_type = match packet_type {
+            Type::Long(long_type) => long_type.packet_type(),
+            Type::Short(_) => None,
         };
-        // IH01
-        let i = matches!(packet_type, Type::Long(V1(Ver1::INITIAL)));
-        let h = matches!(packet_type, Type::Long(V1(Ver1::HANDSHAKE)));
-        let o = matches!(packet_type, Type::Long(V1(Ver1::ZERO_RTT)));
+        let i = long_type == Some(LongType::Initial);
+        let h = long_type == Some(LongType::Handshake);
+        let o = long_type == Some(LongType::ZeroRtt);
         let l = matches!(packet_type, Type::Short(OneRtt(_)));
 
         match self {
@@ -170,15 +183,31 @@ impl FrameType {
             // When an application wishes to abandon a connection during the handshake,
             // an endpoint can send a CONNECTION_CLOSE frame (type 0x1c) with an error code
             // of APPLICATION_ERROR in an Initial or Handshake packet.
-            FrameType::ConnectionClose(bit) => {
-                if *bit == 0 && i || h {
-                    true
+            FrameType::ConnectionClose(layer) => {
+                if *layer == 0 {
+                    i | h | o | l
                 } else {
                     o | l
                 }
             }
             FrameType::HandshakeDone => l,
             FrameType::Datagram(_) => o | l,
+            FrameType::AckFrequency => o | l,
+            FrameType::ImmediateAck => o | l,
+            FrameType::PathAck(_) => l,
+        }
+    }
+
+    /// Determine if a frame type can be sent by the given `sender`.
+    ///
+    /// The NEW_TOKEN and HANDSHAKE_DONE frames are only sent by the server, a server MUST treat
+    /// the receipt of them as a connection error of type PROTOCOL_VIOLATION, see
+    /// [Section 19.7](https://www.rfc-editor.org/rfc/rfc9000.html#section-19.7) and
+    /// [Section 19.20](https://www.rfc-editor.org/rfc/rfc9000.html#section-19.20).
+    pub fn is_sent_by(&self, sender: Role) -> bool {
+        match self {
+            FrameType::NewToken | FrameType::HandshakeDone => sender == Role::Server,
+            _ => true,
         }
     }
 
@@ -186,7 +215,7 @@ impl FrameType {
     pub fn is_ack_eliciting(&self) -> bool {
         !matches!(
             self,
-            Self::Padding | Self::Ack(..) | Self::ConnectionClose(..)
+            Self::Padding | Self::Ack(..) | Self::ConnectionClose(..) | Self::PathAck(..)
         )
     }
 }
@@ -224,6 +253,10 @@ impl TryFrom<u8> for FrameType {
             // The last bit is the length flag bit, 0 the length field is absent and the Datagram Data
             // field extends to the end of the packet, 1 the length field is present.
             ty @ (0x30 | 0x31) => FrameType::Datagram(ty & 1),
+            0xaf => FrameType::AckFrequency,
+            0x1f => FrameType::ImmediateAck,
+            // The last bit is the ECN flag, the same as the ACK frame.
+            ty @ (0x3e | 0x3f) => FrameType::PathAck(ty & 0b1),
             _ => return Err(Self::Error::InvalidType(VarInt::from(frame_type))),
         })
     }
@@ -253,15 +286,27 @@ impl From<FrameType> for u8 {
             FrameType::ConnectionClose(layer) => 0x1c | layer,
             FrameType::HandshakeDone => 0x1e,
             FrameType::Datagram(with_len) => 0x30 | with_len,
+            FrameType::AckFrequency => 0xaf,
+            FrameType::ImmediateAck => 0x1f,
+            FrameType::PathAck(ecn) => 0x3e | ecn,
         }
     }
 }
 
 /// Parse the frame type from the input buffer,
 /// [nom](https://docs.rs/nom/latest/nom/) parser style.
+///
+/// The frame type is encoded as a varint, the types of the extension frames like
+/// [`AckFrequencyFrame`] take more than 1 byte.
 pub fn be_frame_type(input: &[u8]) -> nom::IResult<&[u8], FrameType, Error> {
-    let (remain, frame_type) = nom::number::complete::be_u8(input)?;
-    let frame_type = FrameType::try_from(frame_type).map_err(nom::Err::Error)?;
+    let (remain, frame_type) = crate::varint::be_varint(input).map_err(|e| match e {
+        ne @ nom::Err::Incomplete(_) => nom::Err::Error(Error::IncompleteType(ne.to_string())),
+        _ => unreachable!("parsing varint never generates error or failure"),
+    })?;
+    let frame_type = u8::try_from(frame_type.into_inner())
+        .map_err(|_| Error::InvalidType(frame_type))
+        .and_then(FrameType::try_from)
+        .map_err(nom::Err::Error)?;
     Ok((remain, frame_type))
 }
 
@@ -287,6 +332,8 @@ pub enum StreamCtlFrame {
 #[derive(Debug, Clone, Eq, PartialEq)]
 #[enum_dispatch(BeFrame)]
 pub enum ReliableFrame {
+    /// PING frame, see [`PingFrame`].
+    Ping(PingFrame),
     /// NEW_TOKEN frame, see [`NewTokenFrame`].
     NewToken(NewTokenFrame),
     /// MAX_DATA frame, see [`MaxDataFrame`].
@@ -299,6 +346,8 @@ pub enum ReliableFrame {
     RetireConnectionId(RetireConnectionIdFrame),
     /// HANDSHAKE_DONE frame, see [`HandshakeDoneFrame`].
     HandshakeDone(HandshakeDoneFrame),
+    /// ACK_FREQUENCY frame, see [`AckFrequencyFrame`].
+    AckFrequency(AckFrequencyFrame),
     /// STREAM control frame, see [`StreamCtlFrame`].
     Stream(StreamCtlFrame),
 }
@@ -340,6 +389,12 @@ pub enum Frame {
     Crypto(CryptoFrame, Bytes),
     /// DATAGRAM frame and its data, see [`DatagramFrame`].
     Datagram(DatagramFrame, Bytes),
+    /// ACK_FREQUENCY frame, see [`AckFrequencyFrame`].
+    AckFrequency(AckFrequencyFrame),
+    /// IMMEDIATE_ACK frame, see [`ImmediateAckFrame`].
+    ImmediateAck(ImmediateAckFrame),
+    /// PATH_ACK frame, see [`PathAckFrame`].
+    PathAck(PathAckFrame),
 }
 
 /// Some modules that need send specific frames can implement `SendFrame` trait directly.
@@ -365,9 +420,13 @@ pub trait ReceiveFrame<T> {
 }
 
 /// Reads frames from a buffer until the packet buffer is empty.
+///
+/// The frames not permitted in the packet type are rejected, see
+/// [Section 12.4](https://www.rfc-editor.org/rfc/rfc9000.html#section-12.4) of RFC 9000.
 pub struct FrameReader {
     payload: Bytes,
     packet_type: Type,
+    sender: Option<Role>,
 }
 
 impl FrameReader {
@@ -376,8 +435,16 @@ impl FrameReader {
         Self {
             payload,
             packet_type,
+            sender: None,
         }
     }
+
+    /// Also reject the frames that are never sent by the `sender` of the packet, such as the
+    /// NEW_TOKEN frames from a client, read [`FrameType::is_sent_by`].
+    pub fn sent_by(mut self, sender: Role) -> Self {
+        self.sender = Some(sender);
+        self
+    }
 }
 
 impl Iterator for FrameReader {
@@ -388,7 +455,7 @@ impl Iterator for FrameReader {
             return None;
         }
 
-        match io::be_frame(&self.payload, self.packet_type) {
+        match io::be_frame_sent_by(&self.payload, self.packet_type, self.sender) {
             Ok((consumed, frame, is_ack_eliciting)) => {
                 self.payload.advance(consumed);
                 Some(Ok((frame, is_ack_eliciting)))
@@ -417,12 +484,14 @@ impl<T: BufMut> WriteFrame<StreamCtlFrame> for T {
 impl<T: BufMut> WriteFrame<ReliableFrame> for T {
     fn put_frame(&mut self, frame: &ReliableFrame) {
         match frame {
+            ReliableFrame::Ping(frame) => self.put_frame(frame),
             ReliableFrame::NewToken(frame) => self.put_frame(frame),
             ReliableFrame::MaxData(frame) => self.put_frame(frame),
             ReliableFrame::DataBlocked(frame) => self.put_frame(frame),
             ReliableFrame::NewConnectionId(frame) => self.put_frame(frame),
             ReliableFrame::RetireConnectionId(frame) => self.put_frame(frame),
             ReliableFrame::HandshakeDone(frame) => self.put_frame(frame),
+            ReliableFrame::AckFrequency(frame) => self.put_frame(frame),
             ReliableFrame::Stream(frame) => self.put_frame(frame),
         }
     }
@@ -167,21 +167,27 @@ impl<T: bytes::BufMut> super::io::WriteFrame<AckFrame> for T {
             frame_type |= ECN_OPT;
         }
         self.put_u8(frame_type);
-        self.put_varint(&frame.largest);
-        self.put_varint(&frame.delay);
+        put_ack_fields(self, frame);
+    }
+}
 
-        let ack_range_count = VarInt::try_from(frame.ranges.len()).unwrap();
-        self.put_varint(&ack_range_count);
-        self.put_varint(&frame.first_range);
-        for (gap, ack) in &frame.ranges {
-            self.put_varint(gap);
-            self.put_varint(ack);
-        }
-        if let Some(ecn) = &frame.ecn {
-            self.put_varint(&ecn.ect0);
-            self.put_varint(&ecn.ect1);
-            self.put_varint(&ecn.ce);
-        }
+/// Write the fields following the frame type of an ACK frame into the buffer, shared with the
+/// PATH_ACK frame.
+pub(super) fn put_ack_fields<T: bytes::BufMut>(buf: &mut T, frame: &AckFrame) {
+    buf.put_varint(&frame.largest);
+    buf.put_varint(&frame.delay);
+
+    let ack_range_count = VarInt::try_from(frame.ranges.len()).unwrap();
+    buf.put_varint(&ack_range_count);
+    buf.put_varint(&frame.first_range);
+    for (gap, ack) in &frame.ranges {
+        buf.put_varint(gap);
+        buf.put_varint(ack);
+    }
+    if let Some(ecn) = &frame.ecn {
+        buf.put_varint(&ecn.ect0);
+        buf.put_varint(&ecn.ect1);
+        buf.put_varint(&ecn.ce);
     }
 }
 
@@ -0,0 +1,120 @@
+use crate::varint::{be_varint, VarInt, WriteVarInt};
+
+/// ACK_FREQUENCY frame.
+///
+/// ```text
+/// ACK_FREQUENCY Frame {
+///   Type (i) = 0xaf,
+///   Sequence Number (i),
+///   Ack-Eliciting Threshold (i),
+///   Request Max Ack Delay (i),
+///   Reordering Threshold (i),
+/// }
+/// ```
+///
+/// See [ACK_FREQUENCY Frame](https://datatracker.ietf.org/doc/html/draft-ietf-quic-ack-frequency#name-ack_frequency-frame)
+/// of [QUIC Acknowledgment Frequency](https://datatracker.ietf.org/doc/html/draft-ietf-quic-ack-frequency)
+/// for more details.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AckFrequencyFrame {
+    /// The sequence number, frames with a smaller sequence number than the largest received
+    /// one are ignored.
+    pub sequence_number: VarInt,
+    /// The maximum number of ack-eliciting packets that can be received without sending an
+    /// acknowledgment immediately.
+    pub ack_eliciting_threshold: VarInt,
+    /// The max ack delay requested to the peer, in microseconds.
+    pub request_max_ack_delay: VarInt,
+    /// The number of out-of-order packets that triggers an immediate acknowledgment,
+    /// 0 means never acknowledging immediately due to reordering.
+    pub reordering_threshold: VarInt,
+}
+
+const ACK_FREQUENCY_FRAME_TYPE: u8 = 0xaf;
+
+impl super::BeFrame for AckFrequencyFrame {
+    fn frame_type(&self) -> super::FrameType {
+        super::FrameType::AckFrequency
+    }
+
+    fn max_encoding_size(&self) -> usize {
+        2 + 8 + 8 + 8 + 8
+    }
+
+    fn encoding_size(&self) -> usize {
+        2 + self.sequence_number.encoding_size()
+            + self.ack_eliciting_threshold.encoding_size()
+            + self.request_max_ack_delay.encoding_size()
+            + self.reordering_threshold.encoding_size()
+    }
+}
+
+/// Parse an ACK_FREQUENCY frame from the input buffer,
+/// [nom](https://docs.rs/nom/latest/nom/) parser style.
+pub fn be_ack_frequency_frame(input: &[u8]) -> nom::IResult<&[u8], AckFrequencyFrame> {
+    use nom::{combinator::map, sequence::tuple};
+    map(
+        tuple((be_varint, be_varint, be_varint, be_varint)),
+        |(
+            sequence_number,
+            ack_eliciting_threshold,
+            request_max_ack_delay,
+            reordering_threshold,
+        )| AckFrequencyFrame {
+            sequence_number,
+            ack_eliciting_threshold,
+            request_max_ack_delay,
+            reordering_threshold,
+        },
+    )(input)
+}
+
+impl<T: bytes::BufMut> super::io::WriteFrame<AckFrequencyFrame> for T {
+    fn put_frame(&mut self, frame: &AckFrequencyFrame) {
+        // 帧类型0xaf需要用2字节的varint编码
+        self.put_varint(&VarInt::from(ACK_FREQUENCY_FRAME_TYPE));
+        self.put_varint(&frame.sequence_number);
+        self.put_varint(&frame.ack_eliciting_threshold);
+        self.put_varint(&frame.request_max_ack_delay);
+        self.put_varint(&frame.reordering_threshold);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{be_ack_frequency_frame, AckFrequencyFrame};
+    use crate::{
+        frame::{io::WriteFrame, BeFrame},
+        varint::VarInt,
+    };
+
+    #[test]
+    fn test_read_ack_frequency_frame() {
+        let buf = vec![0x01, 0x09, 0x44, 0xe2, 0x00];
+        let (remain, frame) = be_ack_frequency_frame(&buf).unwrap();
+        assert!(remain.is_empty());
+        assert_eq!(
+            frame,
+            AckFrequencyFrame {
+                sequence_number: VarInt::from_u32(1),
+                ack_eliciting_threshold: VarInt::from_u32(9),
+                request_max_ack_delay: VarInt::from_u32(1250),
+                reordering_threshold: VarInt::from_u32(0),
+            }
+        );
+    }
+
+    #[test]
+    fn test_write_ack_frequency_frame() {
+        let frame = AckFrequencyFrame {
+            sequence_number: VarInt::from_u32(1),
+            ack_eliciting_threshold: VarInt::from_u32(9),
+            request_max_ack_delay: VarInt::from_u32(1250),
+            reordering_threshold: VarInt::from_u32(0),
+        };
+        let mut buf = Vec::new();
+        buf.put_frame(&frame);
+        assert_eq!(buf, vec![0x40, 0xaf, 0x01, 0x09, 0x44, 0xe2, 0x00]);
+        assert_eq!(buf.len(), frame.encoding_size());
+    }
+}
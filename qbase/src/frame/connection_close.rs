@@ -15,54 +15,102 @@ use crate::{error::ErrorKind, frame::be_frame_type, varint::VarInt};
 /// }
 /// ```
 ///
+/// The frame of type 0x1c signals an error at the QUIC layer, with a transport error code and the
+/// type of the frame that triggered the error. The frame of type 0x1d signals an error with the
+/// application, with an application-defined error code, and can only be sent in 0-RTT or 1-RTT
+/// packets.
+///
 /// See [connection close frames](https://www.rfc-editor.org/rfc/rfc9000.html#name-connection-close-frames)
 /// of [QUIC](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct ConnectionCloseFrame {
+pub enum ConnectionCloseFrame {
+    /// The CONNECTION_CLOSE frame of type 0x1c, see [`QuicCloseFrame`].
+    Quic(QuicCloseFrame),
+    /// The CONNECTION_CLOSE frame of type 0x1d, see [`AppCloseFrame`].
+    App(AppCloseFrame),
+}
+
+/// The CONNECTION_CLOSE frame of type 0x1c, which signals an error at the QUIC layer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuicCloseFrame {
     pub error_kind: ErrorKind,
-    pub frame_type: Option<FrameType>,
+    pub frame_type: FrameType,
+    pub reason: Cow<'static, str>,
+}
+
+/// The CONNECTION_CLOSE frame of type 0x1d, which signals an error with the application.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppCloseFrame {
+    pub error_code: VarInt,
     pub reason: Cow<'static, str>,
 }
 
 const CONNECTION_CLOSE_FRAME_TYPE: u8 = 0x1c;
 
-const QUIC_LAYER: u8 = 1;
-const APP_LAYER: u8 = 0;
+const QUIC_LAYER: u8 = 0;
+const APP_LAYER: u8 = 1;
 
 impl super::BeFrame for ConnectionCloseFrame {
     fn frame_type(&self) -> FrameType {
-        FrameType::ConnectionClose(if self.frame_type.is_some() {
-            QUIC_LAYER
-        } else {
-            APP_LAYER
+        FrameType::ConnectionClose(match self {
+            Self::Quic(_) => QUIC_LAYER,
+            Self::App(_) => APP_LAYER,
         })
     }
 
     fn max_encoding_size(&self) -> usize {
         // reason's length could not exceed 16KB.
-        1 + 8 + if self.frame_type.is_some() { 8 } else { 0 } + 2 + self.reason.len()
+        1 + 8 + if self.is_quic_layer() { 8 } else { 0 } + 2 + self.reason().len()
     }
 
     fn encoding_size(&self) -> usize {
-        1 + VarInt::from(self.error_kind).encoding_size()
-            + self.frame_type.is_some()  as usize
+        let (error_code, frame_type) = self.codes();
+        1 + error_code.encoding_size()
+            + frame_type.map_or(0, |fty| VarInt::from(u8::from(fty)).encoding_size())
             // reason's length could not exceed 16KB.
-            + VarInt::try_from(self.reason.len()).unwrap().encoding_size()
-            + self.reason.len()
+            + VarInt::try_from(self.reason().len()).unwrap().encoding_size()
+            + self.reason().len()
     }
 }
 
 impl ConnectionCloseFrame {
-    /// Create a new `ConnectionCloseFrame`.
-    pub fn new(
+    /// Create a CONNECTION_CLOSE frame of type 0x1c, which signals an error at the QUIC layer.
+    pub fn new_quic(
         error_kind: ErrorKind,
-        frame_type: Option<FrameType>,
-        reason: Cow<'static, str>,
+        frame_type: FrameType,
+        reason: impl Into<Cow<'static, str>>,
     ) -> Self {
-        Self {
+        Self::Quic(QuicCloseFrame {
             error_kind,
             frame_type,
-            reason,
+            reason: reason.into(),
+        })
+    }
+
+    /// Create a CONNECTION_CLOSE frame of type 0x1d, which signals an error with the application.
+    pub fn new_app(error_code: VarInt, reason: impl Into<Cow<'static, str>>) -> Self {
+        Self::App(AppCloseFrame {
+            error_code,
+            reason: reason.into(),
+        })
+    }
+
+    /// Return the reason phrase of the frame.
+    pub fn reason(&self) -> &Cow<'static, str> {
+        match self {
+            Self::Quic(frame) => &frame.reason,
+            Self::App(frame) => &frame.reason,
+        }
+    }
+
+    fn is_quic_layer(&self) -> bool {
+        matches!(self, Self::Quic(_))
+    }
+
+    fn codes(&self) -> (VarInt, Option<FrameType>) {
+        match self {
+            Self::Quic(frame) => (frame.error_kind.into(), Some(frame.frame_type)),
+            Self::App(frame) => (frame.error_code, None),
         }
     }
 }
@@ -77,48 +125,51 @@ pub fn connection_close_frame_at_layer(
     use crate::varint::be_varint;
     move |input: &[u8]| {
         let (remain, error_code) = be_varint(input)?;
-        let kind = ErrorKind::try_from(error_code).map_err(|_e| {
-            nom::Err::Error(nom::error::make_error(input, nom::error::ErrorKind::Alt))
-        })?;
-        // The application-specific variant of CONNECTION_CLOSE (type 0x1d) does not include frame_type field.
-        let (remain, frame_type) = if layer == QUIC_LAYER {
+        // The application-specific variant of CONNECTION_CLOSE (type 0x1d) does not include
+        // frame_type field, and its error code is defined by the application.
+        let (remain, error) = if layer == QUIC_LAYER {
+            let kind = ErrorKind::try_from(error_code).map_err(|_e| {
+                nom::Err::Error(nom::error::make_error(input, nom::error::ErrorKind::Alt))
+            })?;
             let (remain, frame_type) = be_frame_type(remain).map_err(|_e| {
                 nom::Err::Error(nom::error::make_error(input, nom::error::ErrorKind::Alt))
             })?;
-            (remain, Some(frame_type))
+            (remain, Ok((kind, frame_type)))
         } else {
-            (remain, None)
+            (remain, Err(error_code))
         };
         let (remain, rease_length) = be_varint(remain)?;
         let (remain, reason) = take(rease_length.into_inner() as usize)(remain)?;
-        let cow = String::from_utf8_lossy(reason).into_owned();
-        Ok((
-            remain,
-            ConnectionCloseFrame {
-                error_kind: kind,
+        let reason = Cow::Owned(String::from_utf8_lossy(reason).into_owned());
+        let frame = match error {
+            Ok((error_kind, frame_type)) => ConnectionCloseFrame::Quic(QuicCloseFrame {
+                error_kind,
                 frame_type,
-                reason: Cow::Owned(cow),
-            },
-        ))
+                reason,
+            }),
+            Err(error_code) => ConnectionCloseFrame::App(AppCloseFrame { error_code, reason }),
+        };
+        Ok((remain, frame))
     }
 }
 
 impl<T: bytes::BufMut> super::io::WriteFrame<ConnectionCloseFrame> for T {
     fn put_frame(&mut self, frame: &ConnectionCloseFrame) {
         use crate::varint::WriteVarInt;
-        let layer = if frame.frame_type.is_some() {
+        let layer = if frame.is_quic_layer() {
             QUIC_LAYER
         } else {
             APP_LAYER
         };
+        let (error_code, frame_type) = frame.codes();
         self.put_u8(CONNECTION_CLOSE_FRAME_TYPE | layer);
-        self.put_varint(&frame.error_kind.into());
-        if let Some(frame_type) = frame.frame_type {
-            self.put_u8(frame_type.into());
+        self.put_varint(&error_code);
+        if let Some(frame_type) = frame_type {
+            self.put_varint(&VarInt::from(u8::from(frame_type)));
         }
-        self.put_varint(&VarInt::from_u32(frame.reason.len() as u32));
+        let reason = frame.reason().as_bytes();
+        self.put_varint(&VarInt::from_u32(reason.len() as u32));
         let remaining = self.remaining_mut();
-        let reason = frame.reason.as_bytes();
         self.put_slice(&reason[..reason.len().min(remaining)]);
     }
 }
@@ -136,6 +187,7 @@ mod tests {
         let buf = vec![
             super::CONNECTION_CLOSE_FRAME_TYPE,
             0x0c,
+            0,
             5,
             b'w',
             b'r',
@@ -144,33 +196,49 @@ mod tests {
             b'g',
         ];
         let (input, frame) = flat_map(be_varint, |frame_type| {
-            if frame_type.into_inner() == super::CONNECTION_CLOSE_FRAME_TYPE as u64 {
-                connection_close_frame_at_layer(0)
-            } else {
-                panic!("wrong frame type: {}", frame_type)
+            match frame_type.into_inner() as u8 {
+                super::CONNECTION_CLOSE_FRAME_TYPE => connection_close_frame_at_layer(0),
+                _ => panic!("wrong frame type: {}", frame_type),
             }
         })(buf.as_ref())
         .unwrap();
         assert!(input.is_empty());
         assert_eq!(
             frame,
-            super::ConnectionCloseFrame {
-                error_kind: ErrorKind::Application,
-                frame_type: None,
-                reason: "wrong".into(),
-            }
+            super::ConnectionCloseFrame::new_quic(
+                ErrorKind::Application,
+                super::FrameType::Padding,
+                "wrong"
+            )
         );
     }
 
+    #[test]
+    fn test_app_close_frame() {
+        use super::{connection_close_frame_at_layer, ConnectionCloseFrame, APP_LAYER};
+        use crate::{frame::BeFrame, varint::VarInt};
+
+        // 应用层的错误码由应用定义，例如HTTP/3的H3_NO_ERROR
+        let frame = ConnectionCloseFrame::new_app(VarInt::from_u32(0x100), "bye");
+        let mut buf = Vec::<u8>::new();
+        buf.put_frame(&frame);
+        assert_eq!(buf, vec![0x1d, 0x41, 0x00, 3, b'b', b'y', b'e']);
+        assert_eq!(buf.len(), frame.encoding_size());
+
+        let (input, read) = connection_close_frame_at_layer(APP_LAYER)(&buf[1..]).unwrap();
+        assert!(input.is_empty());
+        assert_eq!(read, frame);
+    }
+
     #[test]
     fn test_write_connection_close_frame() {
         use super::FrameType;
         let mut buf = Vec::<u8>::new();
-        let frame = super::ConnectionCloseFrame {
-            error_kind: ErrorKind::FlowControl,
-            frame_type: Some(FrameType::Stream(0b110)),
-            reason: "wrong".into(),
-        };
+        let frame = super::ConnectionCloseFrame::new_quic(
+            ErrorKind::FlowControl,
+            FrameType::Stream(0b110),
+            "wrong",
+        );
         buf.put_frame(&frame);
         assert_eq!(
             buf,
@@ -5,6 +5,7 @@ use super::FrameType;
 use crate::{
     error::{Error as TransportError, ErrorKind as TransportErrorKind},
     packet::r#type::Type,
+    sid::Role,
     varint::VarInt,
 };
 
@@ -19,6 +20,8 @@ pub enum Error {
     InvalidType(VarInt),
     #[error("Wrong frame type {0:?}")]
     WrongType(FrameType, Type),
+    #[error("Frame {0:?} is never sent by the {1}")]
+    WrongSender(FrameType, Role),
     #[error("Incomplete frame {0:?}: {1}")]
     IncompleteFrame(FrameType, String),
     #[error("Error occurred when parsing frame {0:?}: {1}")]
@@ -44,8 +47,13 @@ impl From<Error> for TransportError {
                 FrameType::Padding,
                 e.to_string(),
             ),
+            // An endpoint MUST treat receipt of a frame in a packet type that is not permitted as
+            // a connection error of type PROTOCOL_VIOLATION.
             Error::WrongType(fty, _) => {
-                Self::new(TransportErrorKind::FrameEncoding, fty, e.to_string())
+                Self::new(TransportErrorKind::ProtocolViolation, fty, e.to_string())
+            }
+            Error::WrongSender(fty, _) => {
+                Self::new(TransportErrorKind::ProtocolViolation, fty, e.to_string())
             }
             Error::IncompleteFrame(fty, _) => {
                 Self::new(TransportErrorKind::FrameEncoding, fty, e.to_string())
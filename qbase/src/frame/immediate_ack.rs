@@ -0,0 +1,48 @@
+/// IMMEDIATE_ACK frame.
+///
+/// ```text
+/// IMMEDIATE_ACK Frame {
+///   Type (i) = 0x1f,
+/// }
+/// ```
+///
+/// See [IMMEDIATE_ACK Frame](https://datatracker.ietf.org/doc/html/draft-ietf-quic-ack-frequency#name-immediate_ack-frame)
+/// of [QUIC Acknowledgment Frequency](https://datatracker.ietf.org/doc/html/draft-ietf-quic-ack-frequency)
+/// for more details.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImmediateAckFrame;
+
+const IMMEDIATE_ACK_FRAME_TYPE: u8 = 0x1f;
+
+impl super::BeFrame for ImmediateAckFrame {
+    fn frame_type(&self) -> super::FrameType {
+        super::FrameType::ImmediateAck
+    }
+}
+
+impl<T: bytes::BufMut> super::io::WriteFrame<ImmediateAckFrame> for T {
+    fn put_frame(&mut self, _: &ImmediateAckFrame) {
+        self.put_u8(IMMEDIATE_ACK_FRAME_TYPE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ImmediateAckFrame, IMMEDIATE_ACK_FRAME_TYPE};
+    use crate::frame::{be_frame_type, io::WriteFrame, FrameType};
+
+    #[test]
+    fn test_read_immediate_ack_frame() {
+        let buf = vec![IMMEDIATE_ACK_FRAME_TYPE];
+        let (remain, frame_type) = be_frame_type(&buf).unwrap();
+        assert!(remain.is_empty());
+        assert_eq!(frame_type, FrameType::ImmediateAck);
+    }
+
+    #[test]
+    fn test_write_immediate_ack_frame() {
+        let mut buf = Vec::new();
+        buf.put_frame(&ImmediateAckFrame);
+        assert_eq!(buf, vec![IMMEDIATE_ACK_FRAME_TYPE]);
+    }
+}
@@ -1,11 +1,12 @@
 use bytes::Bytes;
 
 use super::{
-    ack::ack_frame_with_flag, connection_close::connection_close_frame_at_layer,
-    crypto::be_crypto_frame, data_blocked::be_data_blocked_frame,
-    datagram::datagram_frame_with_flag, max_data::be_max_data_frame,
-    max_stream_data::be_max_stream_data_frame, max_streams::max_streams_frame_with_dir,
-    new_connection_id::be_new_connection_id_frame, new_token::be_new_token_frame,
+    ack::ack_frame_with_flag, ack_frequency::be_ack_frequency_frame,
+    connection_close::connection_close_frame_at_layer, crypto::be_crypto_frame,
+    data_blocked::be_data_blocked_frame, datagram::datagram_frame_with_flag,
+    max_data::be_max_data_frame, max_stream_data::be_max_stream_data_frame,
+    max_streams::max_streams_frame_with_dir, new_connection_id::be_new_connection_id_frame,
+    new_token::be_new_token_frame, path_ack::path_ack_frame_with_flag,
     path_challenge::be_path_challenge_frame, path_response::be_path_response_frame,
     reset_stream::be_reset_stream_frame, retire_connection_id::be_retire_connection_id_frame,
     stop_sending::be_stop_sending_frame, stream::stream_frame_with_flag,
@@ -41,8 +42,11 @@ fn complete_frame(
         FrameType::PathChallenge => map(be_path_challenge_frame, Frame::Challenge)(input),
         FrameType::PathResponse => map(be_path_response_frame, Frame::Response)(input),
         FrameType::HandshakeDone => Ok((input, Frame::HandshakeDone(HandshakeDoneFrame))),
+        FrameType::AckFrequency => map(be_ack_frequency_frame, Frame::AckFrequency)(input),
+        FrameType::ImmediateAck => Ok((input, Frame::ImmediateAck(ImmediateAckFrame))),
         FrameType::NewToken => map(be_new_token_frame, Frame::NewToken)(input),
         FrameType::Ack(ecn) => map(ack_frame_with_flag(ecn), Frame::Ack)(input),
+        FrameType::PathAck(ecn) => map(path_ack_frame_with_flag(ecn), Frame::PathAck)(input),
         FrameType::ResetStream => map(be_reset_stream_frame, |f| Frame::StreamCtl(f.into()))(input),
         FrameType::StopSending => map(be_stop_sending_frame, |f| Frame::StreamCtl(f.into()))(input),
         FrameType::MaxStreamData => {
@@ -101,11 +105,24 @@ fn complete_frame(
 
 /// Parse a frame type from the raw bytes, [nom](https://docs.rs/nom/latest/nom/) parser style.
 pub fn be_frame(raw: &Bytes, packet_type: Type) -> Result<(usize, Frame, bool), Error> {
+    be_frame_sent_by(raw, packet_type, None)
+}
+
+/// Parse a frame from the raw bytes like [`be_frame`], but also reject the frame if it's never
+/// sent by the `sender` of the packet.
+pub fn be_frame_sent_by(
+    raw: &Bytes,
+    packet_type: Type,
+    sender: Option<Role>,
+) -> Result<(usize, Frame, bool), Error> {
     let input = raw.as_ref();
     let (remain, frame_type) = be_frame_type(input)?;
     if !frame_type.belongs_to(packet_type) {
         return Err(Error::WrongType(frame_type, packet_type));
     }
+    if let Some(sender) = sender.filter(|sender| !frame_type.is_sent_by(*sender)) {
+        return Err(Error::WrongSender(frame_type, sender));
+    }
 
     let (remain, frame) = complete_frame(frame_type, raw.clone())(remain).map_err(|e| match e {
         ne @ nom::Err::Incomplete(_) => {
@@ -140,3 +157,192 @@ pub trait WriteDataFrame<F, D: DescribeData>: bytes::BufMut {
     /// Write a frame and its data to the buffer.
     fn put_data_frame(&mut self, frame: &F, data: &D);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        error::{Error as TransportError, ErrorKind},
+        packet::{
+            r#type::{
+                long::{
+                    Type::{V1, V2},
+                    Ver1, Ver2,
+                },
+                short::OneRtt,
+            },
+            SpinBit,
+        },
+        sid::Role,
+        varint::{VarInt, WriteVarInt},
+    };
+
+    fn close_frame_bytes(frame: &ConnectionCloseFrame) -> Bytes {
+        let mut buf = Vec::new();
+        buf.put_frame(frame);
+        Bytes::from(buf)
+    }
+
+    #[test]
+    fn test_app_close_only_in_data_packets() {
+        let app_close = close_frame_bytes(&ConnectionCloseFrame::new_app(
+            VarInt::from_u32(0x100),
+            "bye",
+        ));
+        for packet_type in [
+            Type::Long(V1(Ver1::INITIAL)),
+            Type::Long(V1(Ver1::HANDSHAKE)),
+        ] {
+            let error = be_frame(&app_close, packet_type).unwrap_err();
+            assert_eq!(
+                error,
+                Error::WrongType(FrameType::ConnectionClose(1), packet_type)
+            );
+            assert_eq!(
+                TransportError::from(error).kind(),
+                ErrorKind::ProtocolViolation
+            );
+        }
+        for packet_type in [
+            Type::Long(V1(Ver1::ZERO_RTT)),
+            Type::Short(OneRtt(SpinBit::One)),
+        ] {
+            let (_, frame, _) = be_frame(&app_close, packet_type).unwrap();
+            assert!(matches!(frame, Frame::Close(ConnectionCloseFrame::App(_))));
+        }
+
+        // 传输层的CONNECTION_CLOSE帧可以出现在任何包中，并携带触发错误的帧类型
+        let quic_close = close_frame_bytes(&ConnectionCloseFrame::new_quic(
+            ErrorKind::FlowControl,
+            FrameType::MaxData,
+            "",
+        ));
+        for packet_type in [
+            Type::Long(V1(Ver1::INITIAL)),
+            Type::Long(V1(Ver1::HANDSHAKE)),
+            Type::Short(OneRtt(SpinBit::One)),
+        ] {
+            let (_, frame, ack_eliciting) = be_frame(&quic_close, packet_type).unwrap();
+            assert!(!ack_eliciting);
+            let Frame::Close(ConnectionCloseFrame::Quic(frame)) = frame else {
+                panic!("unexpected frame: {frame:?}");
+            };
+            assert_eq!(frame.frame_type, FrameType::MaxData);
+        }
+    }
+
+    #[test]
+    fn test_permitted_frames() {
+        let packet_types = [
+            Type::Long(V1(Ver1::INITIAL)),
+            Type::Long(V1(Ver1::HANDSHAKE)),
+            Type::Long(V1(Ver1::ZERO_RTT)),
+            Type::Short(OneRtt(SpinBit::One)),
+        ];
+        // RFC 9000 §12.4 表3中的IH01列，以及扩展帧允许出现的包类型
+        let matrix: &[(u8, &str)] = &[
+            (0x00, "IH01"),
+            (0x01, "IH01"),
+            (0x02, "IH_1"),
+            (0x03, "IH_1"),
+            (0x04, "__01"),
+            (0x05, "__01"),
+            (0x06, "IH_1"),
+            (0x07, "___1"),
+            (0x08, "__01"),
+            (0x0f, "__01"),
+            (0x10, "__01"),
+            (0x11, "__01"),
+            (0x12, "__01"),
+            (0x13, "__01"),
+            (0x14, "__01"),
+            (0x15, "__01"),
+            (0x16, "__01"),
+            (0x17, "__01"),
+            (0x18, "__01"),
+            (0x19, "__01"),
+            (0x1a, "__01"),
+            (0x1b, "___1"),
+            (0x1c, "IH01"),
+            (0x1d, "__01"),
+            (0x1e, "___1"),
+            (0x1f, "__01"),
+            (0x30, "__01"),
+            (0x31, "__01"),
+            (0x3e, "___1"),
+            (0x3f, "___1"),
+            (0xaf, "__01"),
+        ];
+        for &(ty, permitted) in matrix {
+            let mut buf = Vec::new();
+            buf.put_varint(&VarInt::from(ty));
+            let raw = Bytes::from(buf);
+            let (_, frame_type) = be_frame_type(&raw).unwrap();
+            for (packet_type, permitted) in packet_types.into_iter().zip(permitted.chars()) {
+                match be_frame(&raw, packet_type) {
+                    Err(error @ Error::WrongType(..)) => {
+                        assert_eq!(permitted, '_', "{frame_type:?} in {packet_type:?}");
+                        assert_eq!(error, Error::WrongType(frame_type, packet_type));
+                        let error = TransportError::from(error);
+                        assert_eq!(error.kind(), ErrorKind::ProtocolViolation);
+                        assert_eq!(error.frame_type(), frame_type);
+                    }
+                    // 允许的帧只有类型没有内容，可能因不完整而解析失败，但不会因包类型被拒绝
+                    _ => assert_ne!(permitted, '_', "{frame_type:?} in {packet_type:?}"),
+                }
+            }
+            // QUIC v2的长包中允许的帧与v1相同
+            for (v1, v2) in [
+                (V1(Ver1::INITIAL), V2(Ver2::INITIAL)),
+                (V1(Ver1::HANDSHAKE), V2(Ver2::HANDSHAKE)),
+                (V1(Ver1::ZERO_RTT), V2(Ver2::ZERO_RTT)),
+            ] {
+                assert_eq!(
+                    frame_type.belongs_to(Type::Long(v1)),
+                    frame_type.belongs_to(Type::Long(v2))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_frames_from_client() {
+        let one_rtt = Type::Short(OneRtt(SpinBit::Zero));
+        let mut buf = Vec::new();
+        buf.put_frame(&HandshakeDoneFrame);
+        let handshake_done = Bytes::from(buf);
+
+        let mut reader = FrameReader::new(handshake_done.clone(), one_rtt).sent_by(Role::Client);
+        let error = reader.next().unwrap().unwrap_err();
+        assert_eq!(
+            error,
+            Error::WrongSender(FrameType::HandshakeDone, Role::Client)
+        );
+        assert_eq!(
+            TransportError::from(error).kind(),
+            ErrorKind::ProtocolViolation
+        );
+        assert!(reader.next().is_none());
+        // NEW_TOKEN帧同样只能由服务端发送
+        assert_eq!(
+            be_frame_sent_by(&Bytes::from_static(&[0x07]), one_rtt, Some(Role::Client)),
+            Err(Error::WrongSender(FrameType::NewToken, Role::Client))
+        );
+
+        let mut reader = FrameReader::new(handshake_done, one_rtt).sent_by(Role::Server);
+        assert!(matches!(
+            reader.next(),
+            Some(Ok((Frame::HandshakeDone(_), true)))
+        ));
+    }
+
+    #[test]
+    fn test_unknown_frame_type() {
+        let one_rtt = Type::Short(OneRtt(SpinBit::One));
+        for raw in [&[0x21][..], &[0x40, 0x40], &[0x80, 0x00, 0x01, 0x00]] {
+            let error = be_frame(&Bytes::copy_from_slice(raw), one_rtt).unwrap_err();
+            assert!(matches!(error, Error::InvalidType(_)), "{error:?}");
+            assert_eq!(TransportError::from(error).kind(), ErrorKind::FrameEncoding);
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use deref_derive::Deref;
 
+use crate::entropy::EntropySource;
+
 /// PATH_CHALLENGE frame.
 ///
 /// ```text
@@ -31,6 +33,13 @@ impl PathChallengeFrame {
         rng.fill(&mut data);
         Self { data }
     }
+
+    /// Create a frame whose data is filled by the given `entropy` source.
+    pub fn from_entropy(entropy: &dyn EntropySource) -> Self {
+        let mut data = [0; 8];
+        entropy.fill_bytes(&mut data);
+        Self { data }
+    }
 }
 
 const PATH_CHALLENGE_FRAME_TYPE: u8 = 0x1a;
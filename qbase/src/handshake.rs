@@ -1,6 +1,6 @@
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc,
+use std::{
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
 };
 
 use crate::{
@@ -9,20 +9,71 @@ use crate::{
     sid::Role,
 };
 
+#[derive(Debug)]
+enum SignalState {
+    Waiting(Vec<Waker>),
+    Set,
+}
+
+impl Default for SignalState {
+    fn default() -> Self {
+        SignalState::Waiting(Vec::new())
+    }
+}
+
+/// A signal which can only be set once, and can be waited by multiple tasks at the same time.
+#[derive(Debug, Default)]
+struct Signal(Mutex<SignalState>);
+
+impl Signal {
+    fn is_set(&self) -> bool {
+        matches!(*self.0.lock().unwrap(), SignalState::Set)
+    }
+
+    /// Set the signal and wake all the waiting tasks, return false if it has been set before.
+    fn set(&self) -> bool {
+        let mut state = self.0.lock().unwrap();
+        match std::mem::replace(&mut *state, SignalState::Set) {
+            SignalState::Waiting(wakers) => {
+                wakers.into_iter().for_each(Waker::wake);
+                true
+            }
+            SignalState::Set => false,
+        }
+    }
+
+    fn poll_wait(&self, cx: &mut Context<'_>) -> Poll<()> {
+        match &mut *self.0.lock().unwrap() {
+            SignalState::Waiting(wakers) => {
+                // 同一个任务重复poll时不重复登记
+                if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                    wakers.push(cx.waker().clone());
+                }
+                Poll::Pending
+            }
+            SignalState::Set => Poll::Ready(()),
+        }
+    }
+
+    async fn wait(&self) {
+        core::future::poll_fn(|cx| self.poll_wait(cx)).await
+    }
+}
+
 /// The completion flag for the client handshake.
 ///
 /// The client considers the handshake complete only after
 /// receiving the [`HandshakeDoneFrame`] from the server.
-/// In the QUIC protocol, there are no tasks that specifically
-/// require waiting for the client handshake to complete.
-/// Instead, it simply queries the handshake status.
+/// Most of the components simply query the handshake status, the
+/// keys of the Handshake space are discarded once the handshake is
+/// complete, see [`Handshake::confirmed`].
 #[derive(Debug, Default, Clone)]
-pub struct ClientHandshake(Arc<AtomicBool>);
+pub struct ClientHandshake(Arc<Signal>);
 
 impl ClientHandshake {
     /// Check if the client handshake is complete.
     pub fn is_handshake_done(&self) -> bool {
-        self.0.load(Ordering::Acquire)
+        self.0.is_set()
     }
 
     /// Receive the HANDSHAKE_DONE frame.
@@ -30,8 +81,7 @@ impl ClientHandshake {
     /// Once the client receives the HANDSHAKE_DONE frame,
     /// it marks the completion of the client handshake.
     pub fn recv_handshake_done_frame(&self, _frame: &HandshakeDoneFrame) {
-        let _has_done = self.0.swap(true, Ordering::AcqRel);
-        if !_has_done {
+        if self.0.set() {
             log::trace!("Client handshake is done");
         }
     }
@@ -56,7 +106,7 @@ pub struct ServerHandshake<T>
 where
     T: SendFrame<HandshakeDoneFrame> + Clone,
 {
-    is_done: Arc<AtomicBool>,
+    is_done: Arc<Signal>,
     output: T,
 }
 
@@ -70,14 +120,14 @@ where
     /// see [`ServerHandshake`].
     pub fn new(output: T) -> Self {
         ServerHandshake {
-            is_done: Arc::new(AtomicBool::new(false)),
+            is_done: Arc::default(),
             output,
         }
     }
 
     /// Check if the server handshake is complete.
     pub fn is_handshake_done(&self) -> bool {
-        self.is_done.load(Ordering::Acquire)
+        self.is_done.is_set()
     }
 
     /// Actively set the server's handshake status to complete.
@@ -92,11 +142,7 @@ where
     /// servers should send the [`HandshakeDoneFrame`] immediately.
     /// See [`ServerHandshake`].
     pub fn done(&self) {
-        if self
-            .is_done
-            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
-            .is_ok()
-        {
+        if self.is_done.set() {
             log::trace!("Server handshake is done");
             self.output.send_frame([HandshakeDoneFrame]);
         }
@@ -150,6 +196,20 @@ where
         }
     }
 
+    /// Wait until the handshake is complete, that is, the handshake is confirmed.
+    ///
+    /// Once the handshake is confirmed, the keys of the Handshake space should be discarded, see
+    /// [section 4.9.2](https://www.rfc-editor.org/rfc/rfc9001.html#section-4.9.2)
+    /// of [QUIC-TLS](https://www.rfc-editor.org/rfc/rfc9001.html).
+    ///
+    /// Multiple tasks can wait for the handshake at the same time.
+    pub async fn confirmed(&self) {
+        match self {
+            Handshake::Client(h) => h.0.wait().await,
+            Handshake::Server(h) => h.is_done.wait().await,
+        }
+    }
+
     /// Return the role of this handshake signal.
     pub fn role(&self) -> Role {
         match self {
@@ -257,6 +317,25 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_wait_handshake_confirmed() {
+        let handshake = Handshake::<HandshakeDoneFrameTx>::new_client();
+        // 多个任务可以同时等待握手被确认
+        let tasks = [(); 2].map(|_| {
+            let handshake = handshake.clone();
+            tokio::spawn(async move { handshake.confirmed().await })
+        });
+        tokio::task::yield_now().await;
+        assert!(tasks.iter().all(|task| !task.is_finished()));
+
+        handshake.recv_frame(&HandshakeDoneFrame).unwrap();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        // 已经确认的握手，不会再等待
+        handshake.confirmed().await;
+    }
+
     #[test]
     fn test_server_send_handshake_done_frame() {
         let handshake = ServerHandshake::new(HandshakeDoneFrameTx::default());
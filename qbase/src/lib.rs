@@ -13,6 +13,8 @@
 
 /// Operations about QUIC connection IDs.
 pub mod cid;
+/// The source of randomness, which can be replaced in tests.
+pub mod entropy;
 /// [QUIC errors](https://www.rfc-editor.org/rfc/rfc9000.html#name-error-codes).
 pub mod error;
 /// QUIC connection-level flow control.
@@ -25,8 +27,12 @@ pub mod handshake;
 pub mod packet;
 /// [QUIC transport parameters and their codec](https://www.rfc-editor.org/rfc/rfc9000.html#name-transport-parameter-encodin).
 pub mod param;
+/// [qlog](https://datatracker.ietf.org/doc/html/draft-ietf-quic-qlog-main-schema) events and sinks.
+pub mod qlog;
 /// Stream id types and controllers for different roles and different directions.
 pub mod sid;
+/// The clock of the timers, which can be replaced in tests.
+pub mod time;
 /// Issuing, storing and verifing tokens operations.
 pub mod token;
 /// Utilities for common data structures.
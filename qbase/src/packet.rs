@@ -1,4 +1,4 @@
-use bytes::BytesMut;
+use bytes::{Bytes, BytesMut};
 use deref_derive::{Deref, DerefMut};
 use enum_dispatch::enum_dispatch;
 
@@ -44,6 +44,14 @@ pub mod encrypt;
 /// Encapsulate the crypto keys's logic for long headers and 1-RTT headers.
 pub mod keys;
 
+/// Compute and verify the integrity tag of the Retry packets.
+pub mod retry;
+
+/// The ECN codepoint of the datagrams carrying QUIC packets.
+pub mod ecn;
+#[doc(hidden)]
+pub use ecn::Ecn;
+
 /// The sum type of all QUIC packet headers.
 #[derive(Debug, Clone)]
 #[enum_dispatch(GetDcid, GetType)]
@@ -81,11 +89,30 @@ impl GetType for DataPacket {
     }
 }
 
+/// A Retry packet, with the raw bytes of the whole packet to verify its integrity tag.
+///
+/// See [retry packet integrity](https://www.rfc-editor.org/rfc/rfc9001.html#name-retry-packet-integrity)
+/// of [RFC 9001](https://www.rfc-editor.org/rfc/rfc9001.html) for more details.
+#[derive(Debug, Clone, Deref)]
+pub struct RetryPacket {
+    #[deref]
+    pub header: RetryHeader,
+    pub bytes: Bytes,
+}
+
+impl RetryPacket {
+    /// Verify the integrity tag of the Retry packet, with the original destination connection ID,
+    /// which is the DCID of the first Initial packet sent by the client.
+    pub fn verify_integrity(&self, odcid: &ConnectionId) -> bool {
+        retry::verify_retry_integrity(self.header.version, odcid, &self.bytes)
+    }
+}
+
 /// The sum type of all QUIC packets.
 #[derive(Debug, Clone)]
 pub enum Packet {
     VN(VersionNegotiationHeader),
-    Retry(RetryHeader),
+    Retry(RetryPacket),
     // Data(header, bytes, payload_offset)
     Data(DataPacket),
 }
@@ -98,16 +125,26 @@ pub enum Packet {
 ///
 /// The received packet is a BytesMut, in order to be decrypted in future, and make as few
 /// copies cheaply until it is read by the application layer.
+///
+/// Each long packet is strictly bounded by its length field, and a 1-RTT packet runs to the end of
+/// the datagram. Once a packet fails to parse, the rest of the datagram is dropped: the error is
+/// returned only if it is the first packet, otherwise the remaining bytes are just coalesced junk
+/// and ignored, see [coalescing packets](https://www.rfc-editor.org/rfc/rfc9000.html#name-coalescing-packets).
 #[derive(Debug)]
 pub struct PacketReader {
     raw: BytesMut,
     dcid_len: usize,
+    has_read: bool,
     // TODO: 添加level，各种包类型顺序不能错乱，否则失败
 }
 
 impl PacketReader {
     pub fn new(raw: BytesMut, dcid_len: usize) -> Self {
-        Self { raw, dcid_len }
+        Self {
+            raw,
+            dcid_len,
+            has_read: false,
+        }
     }
 }
 
@@ -120,10 +157,15 @@ impl Iterator for PacketReader {
         }
 
         match io::be_packet(&mut self.raw, self.dcid_len) {
-            Ok(packet) => Some(Ok(packet)),
+            Ok(packet) => {
+                self.has_read = true;
+                Some(Ok(packet))
+            }
             Err(e) => {
                 self.raw.clear(); // no longer parsing
-                Some(Err(e))
+
+                // 已解析出包之后的残余字节，不作为错误
+                (!self.has_read).then_some(Err(e))
             }
         }
     }
@@ -134,7 +176,6 @@ impl Iterator for PacketReader {
 /// The writing of the QUIC packet is not provided here, they are written in place.
 pub mod io {
     use bytes::BytesMut;
-    use nom::multi::length_data;
 
     use super::{
         error::Error,
@@ -155,18 +196,29 @@ pub mod io {
     ) -> Result<(BytesMut, usize), Error> {
         let offset = datagram.len() - remain_len;
         let input = &datagram[offset..];
-        let (remain, payload) = length_data(be_varint)(input).map_err(|e| match e {
+        let (remain, length) = be_varint(input).map_err(|e| match e {
             ne @ nom::Err::Incomplete(_) => Error::IncompleteHeader(pkty, ne.to_string()),
-            _ => unreachable!("parsing packet header never generates error or failure"),
+            _ => unreachable!("parsing varint never generates error or failure"),
         })?;
-        let payload_len = payload.len();
+        // 包的长度字段不能超出数据报的剩余部分，否则剩余部分全部丢弃
+        let payload_len = length.into_inner();
+        if payload_len > remain.len() as u64 {
+            return Err(Error::IncompletePacket(
+                pkty,
+                format!(
+                    "declared length {payload_len} exceeds the remaining {} bytes",
+                    remain.len()
+                ),
+            ));
+        }
+        let payload_len = payload_len as usize;
         if payload_len < 20 {
             // The payload needs at least 20 bytes to have enough samples to remove the packet header protection.
-            return Err(Error::UnderSampling(payload.len()));
+            return Err(Error::UnderSampling(payload_len));
         }
-        let packet_length = datagram.len() - remain.len();
-        let bytes = datagram.split_to(packet_length);
-        Ok((bytes, packet_length - payload_len))
+        let header_len = datagram.len() - remain.len();
+        let bytes = datagram.split_to(header_len + payload_len);
+        Ok((bytes, header_len))
     }
 
     /// Parse the QUIC packet from the datagram, given the length of the DCID.
@@ -180,11 +232,19 @@ pub mod io {
         })?;
         let (remain, header) = be_header(pkty, dcid_len, remain).map_err(|e| match e {
             ne @ nom::Err::Incomplete(_) => Error::IncompleteHeader(pkty, ne.to_string()),
-            _ => unreachable!("parsing packet header never generates error or failure"),
+            // 例如连接ID的长度超过20字节
+            e => Error::InvalidHeader(pkty, e.to_string()),
         })?;
         match header {
-            Header::VN(header) => Ok(Packet::VN(header)),
-            Header::Retry(header) => Ok(Packet::Retry(header)),
+            // VN and Retry packets can not be coalesced, they always occupy the whole datagram
+            Header::VN(header) => {
+                datagram.clear();
+                Ok(Packet::VN(header))
+            }
+            Header::Retry(header) => {
+                let bytes = datagram.split().freeze();
+                Ok(Packet::Retry(RetryPacket { header, bytes }))
+            }
             Header::Initial(header) => {
                 let (bytes, offset) = be_payload(pkty, datagram, remain.len())?;
                 Ok(Packet::Data(DataPacket {
@@ -228,4 +288,146 @@ pub mod io {
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use bytes::BufMut;
+
+    use super::{error::Error, *};
+
+    const DCID: [u8; 8] = [0xd0; 8];
+
+    // 只关心包的边界，包号和负载都不加密
+    fn put_long_packet(buf: &mut Vec<u8>, first_byte: u8, payload_len: usize) {
+        buf.put_u8(first_byte);
+        buf.put_u32(1);
+        buf.put_u8(DCID.len() as u8);
+        buf.put_slice(&DCID);
+        buf.put_u8(0);
+        if first_byte & 0x30 == 0 {
+            // Initial包的token长度
+            buf.put_u8(0);
+        }
+        buf.put_u16(0x4000 | payload_len as u16);
+        buf.put_bytes(first_byte, payload_len);
+    }
+
+    fn coalesced_datagram() -> (Vec<u8>, [usize; 3]) {
+        let mut buf = vec![];
+        put_long_packet(&mut buf, 0xc0, 40);
+        let initial_len = buf.len();
+        put_long_packet(&mut buf, 0xe0, 30);
+        let handshake_len = buf.len() - initial_len;
+        buf.put_u8(0x40);
+        buf.put_slice(&DCID);
+        buf.put_bytes(0x40, 25);
+        let one_rtt_len = buf.len() - initial_len - handshake_len;
+        (buf, [initial_len, handshake_len, one_rtt_len])
+    }
+
+    fn read_lens(datagram: &[u8]) -> Vec<Result<usize, Error>> {
+        PacketReader::new(BytesMut::from(datagram), DCID.len())
+            .map(|packet| match packet? {
+                Packet::Data(packet) => Ok(packet.bytes.len()),
+                packet => panic!("unexpected packet {packet:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_coalesced_packets() {
+        let (datagram, lens) = coalesced_datagram();
+        assert_eq!(read_lens(&datagram), lens.map(Ok));
+
+        let mut reader = PacketReader::new(BytesMut::from(&datagram[..]), DCID.len());
+        let Some(Ok(Packet::Data(initial))) = reader.next() else {
+            panic!("expect an initial packet")
+        };
+        assert!(matches!(
+            initial.header,
+            DataHeader::Long(long::DataHeader::Initial(_))
+        ));
+        // 负载紧跟在长度字段之后
+        assert_eq!(initial.offset, lens[0] - 40);
+        assert!(initial.bytes[initial.offset..].iter().all(|&b| b == 0xc0));
+    }
+
+    #[test]
+    fn test_one_rtt_packet_runs_to_the_end() {
+        let (mut datagram, lens) = coalesced_datagram();
+        // 1-RTT包之后的任何内容都属于它自己
+        let initial = datagram[..lens[0]].to_vec();
+        datagram.put_slice(&initial);
+        assert_eq!(
+            read_lens(&datagram),
+            [Ok(lens[0]), Ok(lens[1]), Ok(lens[2] + lens[0])]
+        );
+    }
+
+    #[test]
+    fn test_truncated_datagram() {
+        let (datagram, lens) = coalesced_datagram();
+        for len in 1..datagram.len() {
+            let read = read_lens(&datagram[..len]);
+            if len < lens[0] {
+                // 第一个包就不完整，整个数据报无效
+                assert_eq!(read.len(), 1, "len {len}");
+                assert!(read[0].is_err(), "len {len}");
+            } else if len >= lens[0] + lens[1] {
+                // 1-RTT包延伸到数据报末尾，采样不足时被丢弃
+                let one_rtt_len = len - lens[0] - lens[1];
+                let expected = [Ok(lens[0]), Ok(lens[1]), Ok(one_rtt_len)];
+                let expected_count = if one_rtt_len >= 1 + DCID.len() + 20 {
+                    3
+                } else {
+                    2
+                };
+                assert_eq!(read, expected[..expected_count], "len {len}");
+            } else {
+                // 残缺的握手包被静默丢弃
+                assert_eq!(read, [Ok(lens[0])], "len {len}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_over_declared_length() {
+        let (datagram, lens) = coalesced_datagram();
+        // 握手包长度字段的位置：类型、版本、dcid、scid之后
+        let len_pos = lens[0] + 1 + 4 + 1 + DCID.len() + 1;
+        let remaining = datagram.len() - len_pos - 2;
+        for declared in [remaining + 1, remaining + 100, 0x3fff] {
+            let mut datagram = datagram.clone();
+            datagram[len_pos..len_pos + 2]
+                .copy_from_slice(&(0x4000 | declared as u16).to_be_bytes());
+            // 握手包及其后的1-RTT包都被丢弃
+            assert_eq!(read_lens(&datagram), [Ok(lens[0])], "declared {declared}");
+        }
+
+        // 第一个包的长度就越界时，返回错误
+        let mut datagram = datagram.clone();
+        let len_pos = 1 + 4 + 1 + DCID.len() + 1 + 1;
+        datagram[len_pos..len_pos + 2].copy_from_slice(&0x7fffu16.to_be_bytes());
+        let read = read_lens(&datagram);
+        assert_eq!(read.len(), 1);
+        assert!(matches!(read[0], Err(Error::IncompletePacket(..))));
+    }
+
+    #[test]
+    fn test_mangled_datagram() {
+        let (datagram, _) = coalesced_datagram();
+        // 逐字节篡改，解析不应panic，且解析出的包不会越过数据报的边界
+        for pos in 0..datagram.len() {
+            for value in [0x00, 0x15, 0x40, 0x7f, 0x80, 0xc0, 0xff] {
+                let mut datagram = datagram.clone();
+                datagram[pos] = value;
+                let total: usize = PacketReader::new(BytesMut::from(&datagram[..]), DCID.len())
+                    .map(|packet| match packet {
+                        Ok(Packet::Data(packet)) => packet.bytes.len(),
+                        Ok(Packet::Retry(packet)) => packet.bytes.len(),
+                        _ => 0,
+                    })
+                    .sum();
+                assert!(total <= datagram.len());
+            }
+        }
+    }
+}
@@ -1,8 +1,8 @@
 use rustls::quic::{HeaderProtectionKey, PacketKey};
 
 use super::{
-    error::Error, take_pn_len, GetPacketNumberLength, KeyPhaseBit, LongSpecificBits, PacketNumber,
-    ShortSpecificBits,
+    error::Error, r#type::HEADER_FORM_MASK, take_pn_len, GetPacketNumberLength, KeyPhaseBit,
+    LongSpecificBits, PacketNumber, ShortSpecificBits,
 };
 
 /// Removes the header protection of the long packet.
@@ -15,12 +15,8 @@ use super::{
 /// If not so, it will put the QUIC connection in a situation that is highly susceptible
 /// to denial-of-service attacks.
 ///
-/// Note that after removing the long header protection, the 2-bit reserved bits of the
-/// long header, i.e., the 5th and 6th bits of the first byte of the first byte, must
-/// be 0, otherwise it will return a connection error of type PROTOCOL_VIOLATION.
-///
-/// See [Section 17.2](https://www.rfc-editor.org/rfc/rfc9000.html#section-17.2-8.2) of
-/// QUIC RFC 9000.
+/// Note that the reserved bits of the long header are not checked here, they can only be
+/// trusted after the packet is decrypted, see [`decrypt_packet`].
 ///
 /// After obtaining the undecoded packet number, it is necessary to rely on the largest
 /// received packet number to further decode the actual packet number.
@@ -28,7 +24,7 @@ pub fn remove_protection_of_long_packet(
     key: &dyn HeaderProtectionKey,
     pkt_buf: &mut [u8],
     payload_offset: usize,
-) -> Result<Option<PacketNumber>, Error> {
+) -> Option<PacketNumber> {
     let (pre_data, payload) = pkt_buf.split_at_mut(payload_offset);
     let first_byte = &mut pre_data[0];
     let (max_pn_buf, sample) = payload.split_at_mut(4);
@@ -37,14 +33,14 @@ pub fn remove_protection_of_long_packet(
         .decrypt_in_place(&sample[..key.sample_len()], first_byte, max_pn_buf)
         .is_err()
     {
-        return Ok(None);
+        return None;
     }
 
     let specific_bits = LongSpecificBits::from(*first_byte);
-    let pn_len = specific_bits.pn_len()?;
+    let pn_len = specific_bits.pn_len();
     let (_, undecoded_pn) = take_pn_len(pn_len)(max_pn_buf).unwrap();
 
-    Ok(Some(undecoded_pn))
+    Some(undecoded_pn)
 }
 
 /// Removes the header protection of the short packet.
@@ -57,12 +53,8 @@ pub fn remove_protection_of_long_packet(
 /// If not so, it will put the QUIC connection in a situation that is highly susceptible
 /// to denial-of-service attacks.
 ///
-/// Note that after removing the long header protection, the 2-bit reserved bits of the
-/// long header, i.e., the 4th and 5th bits of the first byte of the first byte, must
-/// be 0, otherwise it will return a connection error of type PROTOCOL_VIOLATION.
-///
-/// See [Section 17.3.1](https://www.rfc-editor.org/rfc/rfc9000.html#section-17.3.1-4.8) of
-/// QUIC RFC 9000.
+/// Note that the reserved bits of the short header are not checked here, they can only be
+/// trusted after the packet is decrypted, see [`decrypt_packet`].
 ///
 /// After obtaining the undecoded packet number, it is necessary to rely on the maximum
 /// receiving packet number to further decode the actual packet number.
@@ -70,7 +62,7 @@ pub fn remove_protection_of_short_packet(
     key: &dyn HeaderProtectionKey,
     pkt_buf: &mut [u8],
     payload_offset: usize,
-) -> Result<Option<(PacketNumber, KeyPhaseBit)>, Error> {
+) -> Option<(PacketNumber, KeyPhaseBit)> {
     let (pre_data, payload) = pkt_buf.split_at_mut(payload_offset);
     let first_byte = &mut pre_data[0];
     let (max_pn_buf, sample) = payload.split_at_mut(4);
@@ -79,14 +71,14 @@ pub fn remove_protection_of_short_packet(
         .decrypt_in_place(&sample[..key.sample_len()], first_byte, max_pn_buf)
         .is_err()
     {
-        return Ok(None);
+        return None;
     }
 
     let clear_bits = ShortSpecificBits::from(*first_byte);
-    let pn_len = clear_bits.pn_len()?;
+    let pn_len = clear_bits.pn_len();
     let (_, undecoded_pn) = take_pn_len(pn_len)(max_pn_buf).unwrap();
 
-    Ok(Some((undecoded_pn, clear_bits.key_phase())))
+    Some((undecoded_pn, clear_bits.key_phase()))
 }
 
 /// Decrypt the body of a packet, applicable to both long and short packets.
@@ -101,19 +93,227 @@ pub fn remove_protection_of_short_packet(
 /// uses the corresponding level of packet decryption key to decrypt the packet body.
 /// The packet body refers to the content located after the packet number.
 /// Decrypting a packet will verify the integrity of the packet.
-/// If decryption fails, it indicates that the packet is incorrect (strangely, removing the
-/// header protection succeeded, right?), indicating an error in the peer's packaging
-/// and encrypting logic, and then the QUIC connection should be terminated.
+/// If decryption fails, [`Error::DecryptPacketFailure`] is returned, the packet may be forged
+/// or corrupted and should be discarded.
+///
+/// Once the packet is decrypted, the first byte whose header protection has been removed is
+/// authenticated, the reserved bits in it must be 0, otherwise [`Error::InvalidReservedBits`] is
+/// returned, which is a connection error of type PROTOCOL_VIOLATION.
+/// See [Section 17.2](https://www.rfc-editor.org/rfc/rfc9000.html#section-17.2-8.2) and
+/// [Section 17.3.1](https://www.rfc-editor.org/rfc/rfc9000.html#section-17.3.1-4.8) of
+/// QUIC RFC 9000.
 pub fn decrypt_packet(
     key: &dyn PacketKey,
     pn: u64,
     pkt_buf: &mut [u8],
     body_offset: usize,
 ) -> Result<usize, Error> {
+    let first_byte = pkt_buf[0];
     let (aad, body) = pkt_buf.split_at_mut(body_offset);
     let plain = key
         .decrypt_in_place(pn, aad, body)
         .map_err(|_| Error::DecryptPacketFailure)?;
+    // 只有解密成功后，保留位才是可信的
+    if first_byte & HEADER_FORM_MASK != 0 {
+        LongSpecificBits::from(first_byte).check_reserved_bits()?;
+    } else {
+        ShortSpecificBits::from(first_byte).check_reserved_bits()?;
+    }
     // should return plain.len()
     Ok(plain.len())
 }
+
+/// Decrypt the body of a 1-RTT packet received in the packet number space of the path `path_id`.
+///
+/// It is the same as [`decrypt_packet`], except that the path identifier is mixed into the
+/// nonce, see [`encrypt_packet_for_path`](super::encrypt::encrypt_packet_for_path).
+pub fn decrypt_packet_for_path(
+    key: &dyn PacketKey,
+    path_id: u32,
+    pn: u64,
+    pkt_buf: &mut [u8],
+    body_offset: usize,
+) -> Result<usize, Error> {
+    if path_id == 0 {
+        return decrypt_packet(key, pn, pkt_buf, body_offset);
+    }
+    let first_byte = pkt_buf[0];
+    let (aad, body) = pkt_buf.split_at_mut(body_offset);
+    let plain = key
+        .decrypt_in_place_for_path(path_id, pn, aad, body)
+        .map_err(|_| Error::DecryptPacketFailure)?;
+    ShortSpecificBits::from(first_byte).check_reserved_bits()?;
+    Ok(plain.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use rustls::{
+        crypto::ring::cipher_suite::TLS13_AES_128_GCM_SHA256,
+        quic::{Keys, Version},
+        Side,
+    };
+
+    use super::*;
+    use crate::packet::encrypt::{
+        encode_long_first_byte, encode_short_first_byte, encrypt_packet, encrypt_packet_for_path,
+        protect_header,
+    };
+
+    // 1字节的包号之后是20字节的负载和16字节的tag
+    const BODY_LEN: usize = 20;
+    const TAG_LEN: usize = 16;
+
+    fn keys() -> Keys {
+        let suite = TLS13_AES_128_GCM_SHA256.tls13().unwrap();
+        suite
+            .quic_suite()
+            .unwrap()
+            .keys(&[0x0d; 8], Side::Client, Version::V1)
+    }
+
+    // 加密时发送方篡改了保留位，包头保护和包保护都是完整的
+    fn seal_packet(keys: &Keys, mut header: Vec<u8>, reserved_bits: u8) -> (Vec<u8>, usize) {
+        let payload_offset = header.len();
+        if header[0] & HEADER_FORM_MASK != 0 {
+            encode_long_first_byte(&mut header[0], 1);
+        } else {
+            encode_short_first_byte(&mut header[0], 1, KeyPhaseBit::default());
+        }
+        header[0] |= reserved_bits;
+        let mut buf = header;
+        buf.push(0x00);
+        buf.extend_from_slice(&[0x01; BODY_LEN]);
+        buf.extend_from_slice(&[0; TAG_LEN]);
+        encrypt_packet(keys.local.packet.as_ref(), 0, &mut buf, payload_offset + 1);
+        protect_header(keys.local.header.as_ref(), &mut buf, payload_offset, 1);
+        (buf, payload_offset)
+    }
+
+    fn short_header() -> Vec<u8> {
+        let mut header = vec![0x40];
+        header.extend_from_slice(&[0xd0; 8]);
+        header
+    }
+
+    fn long_header() -> Vec<u8> {
+        // Handshake包：版本、dcid、scid和长度字段
+        let mut header = vec![0xe0, 0, 0, 0, 1, 8];
+        header.extend_from_slice(&[0xd0; 8]);
+        header.push(0);
+        let length = (1 + BODY_LEN + TAG_LEN) as u16 | 0x4000;
+        header.extend_from_slice(&length.to_be_bytes());
+        header
+    }
+
+    #[test]
+    fn test_decrypt_short_packet() {
+        let keys = keys();
+        let (mut buf, payload_offset) = seal_packet(&keys, short_header(), 0);
+        let (pn, key_phase) =
+            remove_protection_of_short_packet(keys.local.header.as_ref(), &mut buf, payload_offset)
+                .unwrap();
+        assert_eq!(pn, PacketNumber::U8(0));
+        assert_eq!(key_phase, KeyPhaseBit::default());
+        let body_len = decrypt_packet(keys.local.packet.as_ref(), 0, &mut buf, payload_offset + 1);
+        assert_eq!(body_len, Ok(BODY_LEN));
+    }
+
+    #[test]
+    fn test_invalid_reserved_bits() {
+        let keys = keys();
+        for (header, reserved_bits) in [
+            (short_header(), 0x08),
+            (short_header(), 0x18),
+            (long_header(), 0x04),
+            (long_header(), 0x0c),
+        ] {
+            let (mut buf, payload_offset) = seal_packet(&keys, header, reserved_bits);
+            let pn = if buf[0] & HEADER_FORM_MASK != 0 {
+                remove_protection_of_long_packet(
+                    keys.local.header.as_ref(),
+                    &mut buf,
+                    payload_offset,
+                )
+            } else {
+                remove_protection_of_short_packet(
+                    keys.local.header.as_ref(),
+                    &mut buf,
+                    payload_offset,
+                )
+                .map(|(pn, _)| pn)
+            };
+            // 保留位不影响包号的解析，要等到解密成功后才报错
+            assert_eq!(pn, Some(PacketNumber::U8(0)));
+            let mask = if buf[0] & HEADER_FORM_MASK != 0 {
+                0x0c
+            } else {
+                0x18
+            };
+            assert_eq!(
+                decrypt_packet(keys.local.packet.as_ref(), 0, &mut buf, payload_offset + 1),
+                Err(Error::InvalidReservedBits(reserved_bits, mask))
+            );
+        }
+    }
+
+    #[test]
+    fn test_tampered_reserved_bits() {
+        let keys = keys();
+        for (header, reserved_bit) in [(short_header(), 0x10), (long_header(), 0x08)] {
+            let (mut buf, payload_offset) = seal_packet(&keys, header, 0);
+            // 在途中篡改受保护的保留位，包头保护去除后保留位不为0，但包无法通过完整性校验，
+            // 只是被丢弃，不应导致连接错误
+            buf[0] ^= reserved_bit;
+            if buf[0] & HEADER_FORM_MASK != 0 {
+                remove_protection_of_long_packet(
+                    keys.local.header.as_ref(),
+                    &mut buf,
+                    payload_offset,
+                )
+                .unwrap();
+            } else {
+                remove_protection_of_short_packet(
+                    keys.local.header.as_ref(),
+                    &mut buf,
+                    payload_offset,
+                )
+                .unwrap();
+            }
+            assert_eq!(buf[0] & reserved_bit, reserved_bit);
+            assert_eq!(
+                decrypt_packet(keys.local.packet.as_ref(), 0, &mut buf, payload_offset + 1),
+                Err(Error::DecryptPacketFailure)
+            );
+        }
+    }
+
+    #[test]
+    fn test_decrypt_packet_for_path() {
+        let keys = keys();
+        let mut header = short_header();
+        let payload_offset = header.len();
+        encode_short_first_byte(&mut header[0], 1, KeyPhaseBit::default());
+        let mut sealed = header;
+        sealed.push(0x00);
+        sealed.extend_from_slice(&[0x01; BODY_LEN]);
+        sealed.extend_from_slice(&[0; TAG_LEN]);
+        let key = keys.local.packet.as_ref();
+        encrypt_packet_for_path(key, 2, 0, &mut sealed, payload_offset + 1);
+
+        // 路径标识参与nonce的计算，同一包号在其他路径的包号空间中无法解密
+        for path_id in [0, 1] {
+            let mut buf = sealed.clone();
+            assert_eq!(
+                decrypt_packet_for_path(key, path_id, 0, &mut buf, payload_offset + 1),
+                Err(Error::DecryptPacketFailure)
+            );
+        }
+        let mut buf = sealed;
+        assert_eq!(
+            decrypt_packet_for_path(key, 2, 0, &mut buf, payload_offset + 1),
+            Ok(BODY_LEN)
+        );
+        assert_eq!(&buf[payload_offset + 1..][..BODY_LEN], &[0x01; BODY_LEN]);
+    }
+}
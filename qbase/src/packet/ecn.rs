@@ -0,0 +1,35 @@
+/// The Explicit Congestion Notification(ECN) codepoint in the IP header of a datagram.
+///
+/// See [RFC 3168](https://www.rfc-editor.org/rfc/rfc3168.html#section-5) and
+/// [ECN](https://www.rfc-editor.org/rfc/rfc9000.html#name-explicit-congestion-notific)
+/// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Ecn {
+    /// Not ECN-Capable Transport
+    #[default]
+    NotEct = 0b00,
+    /// ECN Capable Transport(1)
+    Ect1 = 0b01,
+    /// ECN Capable Transport(0)
+    Ect0 = 0b10,
+    /// Congestion Experienced
+    Ce = 0b11,
+}
+
+impl From<u8> for Ecn {
+    /// Only the lowest 2 bits of the TOS/Traffic Class byte are ECN codepoint.
+    fn from(tos: u8) -> Self {
+        match tos & 0b11 {
+            0b00 => Ecn::NotEct,
+            0b01 => Ecn::Ect1,
+            0b10 => Ecn::Ect0,
+            _ => Ecn::Ce,
+        }
+    }
+}
+
+impl From<Ecn> for u8 {
+    fn from(ecn: Ecn) -> Self {
+        ecn as u8
+    }
+}
@@ -24,6 +24,29 @@ pub fn encrypt_packet(key: &dyn PacketKey, pn: u64, pkt_buf: &mut [u8], body_off
     tag_buf.copy_from_slice(tag.as_ref());
 }
 
+/// Encrypt the body of a 1-RTT packet sent in the packet number space of the path `path_id`.
+///
+/// With the multipath extension, the packet number spaces of the paths share the same packet
+/// protection keys, the path identifier is mixed into the nonce to avoid the nonce reuse.
+/// The path identifier 0 uses the same nonce as [`encrypt_packet`].
+pub fn encrypt_packet_for_path(
+    key: &dyn PacketKey,
+    path_id: u32,
+    pn: u64,
+    pkt_buf: &mut [u8],
+    body_offset: usize,
+) {
+    if path_id == 0 {
+        return encrypt_packet(key, pn, pkt_buf, body_offset);
+    }
+    let (aad, body_tag) = pkt_buf.split_at_mut(body_offset);
+    let (body, tag_buf) = body_tag.split_at_mut(body_tag.len() - key.tag_len());
+    let tag = key
+        .encrypt_in_place_for_path(path_id, pn, aad, body)
+        .unwrap();
+    tag_buf.copy_from_slice(tag.as_ref());
+}
+
 /// Add header protection, applicable to both long and short packets.
 /// Mainly protects the Reserved Bits and Packet Number Length in the packet header,
 /// as well as the Packet Number.
@@ -17,7 +17,7 @@ pub use long::{
 pub use short::{io::WriteShortHeader, OneRttHeader};
 
 use super::r#type::{
-    long::{v1, Type as LongType, Version},
+    long::{v1, Type as LongType},
     short::OneRtt,
     Type,
 };
@@ -100,7 +100,7 @@ pub mod io {
             Type::Long(long_ty) => {
                 let (remain, dcid) = be_connection_id(input)?;
                 let (remain, scid) = be_connection_id(remain)?;
-                let builder = LongHeaderBuilder { dcid, scid };
+                let builder = LongHeaderBuilder::with_cid(dcid, scid);
                 builder.parse(long_ty, remain)
             }
             Type::Short(OneRtt(spin)) => {
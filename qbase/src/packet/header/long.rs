@@ -25,6 +25,7 @@ use crate::{cid::ConnectionId, varint::VarInt};
 /// of [QUIC](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
 #[derive(Debug, Default, Clone, Deref, DerefMut)]
 pub struct LongHeader<T> {
+    pub version: u32,
     pub dcid: ConnectionId,
     pub scid: ConnectionId,
     #[deref]
@@ -154,12 +155,19 @@ impl<S: EncodeHeader> EncodeHeader for LongHeader<S> {
     }
 }
 
+impl GetType for VersionNegotiationHeader {
+    fn get_type(&self) -> Type {
+        Type::Long(LongType::VersionNegotiation)
+    }
+}
+
+// 长包头的包类型的编码随版本而不同，由包头中的版本号决定
 macro_rules! bind_type {
     ($($type:ty => $value:expr),*) => {
         $(
             impl GetType for $type {
                 fn get_type(&self) -> Type {
-                    $value
+                    Type::Long(LongType::new(self.version, $value))
                 }
             }
         )*
@@ -167,11 +175,10 @@ macro_rules! bind_type {
 }
 
 bind_type!(
-    VersionNegotiationHeader => Type::Long(LongType::VersionNegotiation),
-    RetryHeader => Type::Long(LongType::V1(Version::<1, _>(v1::Type::Retry))),
-    InitialHeader => Type::Long(LongType::V1(Version::<1, _>(v1::Type::Initial))),
-    ZeroRttHeader => Type::Long(LongType::V1(Version::<1, _>(v1::Type::ZeroRtt))),
-    HandshakeHeader => Type::Long(LongType::V1(Version::<1, _>(v1::Type::Handshake)))
+    RetryHeader => v1::Type::Retry,
+    InitialHeader => v1::Type::Initial,
+    ZeroRttHeader => v1::Type::ZeroRtt,
+    HandshakeHeader => v1::Type::Handshake
 );
 
 /// The sum type of long packets that carry data,
@@ -186,8 +193,6 @@ pub enum DataHeader {
 
 /// The io module provides functions for parsing and writing long headers.
 pub mod io {
-    use std::ops::Deref;
-
     use bytes::BufMut;
     use nom::{
         bytes::streaming::take,
@@ -202,7 +207,7 @@ pub mod io {
         cid::WriteConnectionId,
         packet::r#type::{
             io::WritePacketType,
-            long::{v1::Type as LongV1Type, Type as LongType},
+            long::{v1::Type as LongV1Type, Type as LongType, QUIC_VERSION_1},
         },
         varint::{be_varint, WriteVarInt},
     };
@@ -257,15 +262,27 @@ pub mod io {
     /// let handshake_header = LongHeaderBuilder::with_cid(dcid, scid).handshake();
     /// ```
     pub struct LongHeaderBuilder {
+        pub(crate) version: u32,
         pub(crate) dcid: ConnectionId,
         pub(crate) scid: ConnectionId,
     }
 
     impl LongHeaderBuilder {
         /// Create a new long header builder with the given destination
-        /// and source connection IDs.
+        /// and source connection IDs, the header is of QUIC version 1 by default.
         pub fn with_cid(dcid: ConnectionId, scid: ConnectionId) -> Self {
-            Self { dcid, scid }
+            Self {
+                version: QUIC_VERSION_1,
+                dcid,
+                scid,
+            }
+        }
+
+        /// Set the QUIC version of the long header, which determines the encoding of the
+        /// packet type, read [`LongType`] for more details.
+        pub fn with_version(mut self, version: u32) -> Self {
+            self.version = version;
+            self
         }
 
         /// Build into a version negotiation header.
@@ -297,6 +314,7 @@ pub mod io {
         /// Return the specific long header.
         pub fn wrap<T>(self, specific: T) -> LongHeader<T> {
             LongHeader {
+                version: self.version,
                 dcid: self.dcid,
                 scid: self.scid,
                 specific,
@@ -308,29 +326,28 @@ pub mod io {
         ///
         /// The input buffer would be the remaining data of the buffer.
         pub fn parse(self, ty: LongType, input: &[u8]) -> nom::IResult<&[u8], Header> {
-            match ty {
-                LongType::VersionNegotiation => {
+            let builder = self.with_version(ty.version());
+            match ty.packet_type() {
+                None => {
                     let (remain, versions) = be_version_negotiation(input)?;
-                    Ok((remain, Header::VN(self.wrap(versions))))
+                    Ok((remain, Header::VN(builder.wrap(versions))))
+                }
+                Some(LongV1Type::Retry) => {
+                    let (remain, retry) = be_retry(input)?;
+                    Ok((remain, Header::Retry(builder.wrap(retry))))
+                }
+                Some(LongV1Type::Initial) => {
+                    let (remain, initial) = be_initial(input)?;
+                    Ok((remain, Header::Initial(builder.wrap(initial))))
+                }
+                Some(LongV1Type::ZeroRtt) => {
+                    let (remain, zero_rtt) = be_zero_rtt(input)?;
+                    Ok((remain, Header::ZeroRtt(builder.wrap(zero_rtt))))
+                }
+                Some(LongV1Type::Handshake) => {
+                    let (remain, handshake) = be_handshake(input)?;
+                    Ok((remain, Header::Handshake(builder.wrap(handshake))))
                 }
-                LongType::V1(ty) => match ty.deref() {
-                    LongV1Type::Retry => {
-                        let (remain, retry) = be_retry(input)?;
-                        Ok((remain, Header::Retry(self.wrap(retry))))
-                    }
-                    LongV1Type::Initial => {
-                        let (remain, initial) = be_initial(input)?;
-                        Ok((remain, Header::Initial(self.wrap(initial))))
-                    }
-                    LongV1Type::ZeroRtt => {
-                        let (remain, zero_rtt) = be_zero_rtt(input)?;
-                        Ok((remain, Header::ZeroRtt(self.wrap(zero_rtt))))
-                    }
-                    LongV1Type::Handshake => {
-                        let (remain, handshake) = be_handshake(input)?;
-                        Ok((remain, Header::Handshake(self.wrap(handshake))))
-                    }
-                },
             }
         }
     }
@@ -507,4 +524,47 @@ mod tests {
         buf.put_specific(&initial_long_header.specific);
         assert_eq!(buf, vec![0x03, 0x00, 0x00, 0x00,]);
     }
+
+    #[test]
+    fn test_v2_long_header() {
+        use super::{
+            io::{LongHeaderBuilder, WriteLongHeader},
+            GetType,
+        };
+        use crate::{
+            cid::ConnectionId,
+            packet::{
+                header::{io::be_header, Header},
+                r#type::{
+                    io::be_packet_type,
+                    long::{Type as LongType, Ver2, QUIC_VERSION_2},
+                    Type,
+                },
+            },
+        };
+
+        let dcid = ConnectionId::from_slice(b"dcid");
+        let scid = ConnectionId::from_slice(b"scid");
+        let handshake = LongHeaderBuilder::with_cid(dcid, scid)
+            .with_version(QUIC_VERSION_2)
+            .handshake();
+        assert_eq!(
+            handshake.get_type(),
+            Type::Long(LongType::V2(Ver2::HANDSHAKE))
+        );
+
+        let mut buf = Vec::<u8>::new();
+        buf.put_long_header(&handshake);
+        assert_eq!(buf[..5], [0xf0, 0x6b, 0x33, 0x43, 0xcf]);
+
+        let (remain, ty) = be_packet_type(&buf).unwrap();
+        assert_eq!(ty, Type::Long(LongType::V2(Ver2::HANDSHAKE)));
+        let (_, header) = be_header(ty, 0, remain).unwrap();
+        let Header::Handshake(parsed) = header else {
+            panic!("unexpected header: {header:?}");
+        };
+        assert_eq!(parsed.version, QUIC_VERSION_2);
+        assert_eq!(parsed.dcid, dcid);
+        assert_eq!(parsed.scid, scid);
+    }
 }
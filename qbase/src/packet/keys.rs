@@ -7,8 +7,10 @@ use std::{
 };
 
 use rustls::quic::{HeaderProtectionKey, Keys, PacketKey, Secrets};
+use thiserror::Error;
 
 use super::KeyPhaseBit;
+use crate::error::{Error as QuicError, ErrorKind};
 
 #[derive(Clone)]
 enum KeysState {
@@ -134,12 +136,34 @@ impl ArcKeys {
         }
     }
 
+    /// Replace the keys of the [`ArcKeys`] with the new ones.
+    ///
+    /// Unlike [`ArcKeys::set_keys`], this can be called when the keys are ready already. It is
+    /// used to re-derive the Initial keys, when the client has to restart the handshake. The
+    /// invalid keys will not be revived.
+    pub fn replace_keys(&self, keys: Keys) {
+        let mut state = self.lock_guard();
+        match &mut *state {
+            KeysState::Pending(waker) => {
+                if let Some(waker) = waker.take() {
+                    waker.wake();
+                }
+                *state = KeysState::Ready(Arc::new(keys));
+            }
+            KeysState::Ready(old_keys) => *old_keys = Arc::new(keys),
+            KeysState::Invalid => {}
+        }
+    }
+
     /// Retire the keys, which means that the keys are no longer available.
     ///
     /// This is used when the connection enters the closing state or draining state.
     /// Especially in the closing state, the return keys are used to generate the final packet
     /// containing the ConnectionClose frame, and decrypt the data packets received from the
     /// peer for a while.
+    ///
+    /// It is also used to discard the Initial and Handshake keys once the handshake is
+    /// confirmed, in which case [`None`] will be returned if the keys are retired again.
     pub fn invalid(&self) -> Option<Arc<Keys>> {
         let mut state = self.lock_guard();
         match std::mem::replace(state.deref_mut(), KeysState::Invalid) {
@@ -150,7 +174,7 @@ impl ArcKeys {
                 None
             }
             KeysState::Ready(keys) => Some(keys),
-            KeysState::Invalid => unreachable!(),
+            KeysState::Invalid => None,
         }
     }
 }
@@ -181,6 +205,18 @@ impl Future for GetRemoteKeys<'_> {
     }
 }
 
+/// Errors that prevent a 1-RTT key update from being initiated locally.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum KeyUpdateError {
+    /// The 1-RTT keys are not ready yet, or have been discarded.
+    #[error("1-RTT keys are not available")]
+    Unavailable,
+    /// No packet protected with the current keys has been acknowledged yet,
+    /// see [Section 6.1](https://www.rfc-editor.org/rfc/rfc9001#section-6.1-9).
+    #[error("the current key phase has not been confirmed")]
+    Unconfirmed,
+}
+
 /// The packet encryption and decryption keys for 1-RTT packets,
 /// which will still change after negotiation between the two endpoints.
 ///
@@ -191,6 +227,19 @@ pub struct OneRttPacketKeys {
     secrets: Secrets,
     remote: [Option<Arc<dyn PacketKey>>; 2],
     local: Arc<dyn PacketKey>,
+    // The next generation of keys, derived in advance when a packet with the
+    // flipped key phase arrives, and installed only after it is decrypted.
+    next: Option<(Arc<dyn PacketKey>, Arc<dyn PacketKey>)>,
+    // The smallest packet number received in the current key phase, packets
+    // with the old key phase and a smaller packet number are reordered ones.
+    first_rcvd_pn: Option<u64>,
+    // The first packet number sent in the current key phase, an acknowledgment
+    // for it or any later packet confirms the current key phase.
+    first_sent_pn: Option<u64>,
+    confirmed: bool,
+    // Whether the current key phase is entered by a key update, rather than
+    // the handshake.
+    updated: bool,
 }
 
 impl OneRttPacketKeys {
@@ -203,19 +252,56 @@ impl OneRttPacketKeys {
             secrets,
             remote: [Some(Arc::from(remote)), None],
             local: Arc::from(local),
+            next: None,
+            first_rcvd_pn: None,
+            first_sent_pn: None,
+            confirmed: false,
+            updated: false,
         }
     }
 
-    /// Proactively update the 1-RTT packet key locally.
-    /// Or be informed by the peer to update the key.
+    /// Derive the next generation of the remote and local packet keys, via the
+    /// HKDF-Expand-Label with the label "quic ku" performed by [`Secrets`].
     ///
-    /// The key phase bit will be toggled and sent to the peer,
-    /// informing the peer to update the key to next 1-RTT packet key too.
-    pub fn update(&mut self) {
+    /// The derived keys are cached until they are installed, so calling this
+    /// method repeatedly does not advance the secrets more than once.
+    fn derive_next_keys(&mut self) -> (Arc<dyn PacketKey>, Arc<dyn PacketKey>) {
+        let secrets = &mut self.secrets;
+        self.next
+            .get_or_insert_with(|| {
+                let key_set = secrets.next_packet_keys();
+                (Arc::from(key_set.remote), Arc::from(key_set.local))
+            })
+            .clone()
+    }
+
+    fn install_next_keys(&mut self) {
+        let (remote, local) = match self.next.take() {
+            Some(keys) => keys,
+            None => self.derive_next_keys(),
+        };
+        self.next = None;
         self.cur_phase.toggle();
-        let key_set = self.secrets.next_packet_keys();
-        self.remote[self.cur_phase.as_index()] = Some(Arc::from(key_set.remote));
-        self.local = Arc::from(key_set.local);
+        self.remote[self.cur_phase.as_index()] = Some(remote);
+        self.local = local;
+        self.first_rcvd_pn = None;
+        self.first_sent_pn = None;
+        self.confirmed = false;
+        self.updated = true;
+    }
+
+    /// Proactively update the 1-RTT packet key locally.
+    ///
+    /// The key phase bit will be toggled and sent to the peer, informing the
+    /// peer to update the key to next 1-RTT packet key too.
+    /// A subsequent update is not allowed until a packet protected with the
+    /// current keys has been acknowledged, see [`Self::on_pkt_acked`].
+    pub fn update(&mut self) -> Result<KeyPhaseBit, KeyUpdateError> {
+        if !self.confirmed {
+            return Err(KeyUpdateError::Unconfirmed);
+        }
+        self.install_next_keys();
+        Ok(self.cur_phase)
     }
 
     /// Old key must be phased out within a certain period of time.
@@ -227,15 +313,81 @@ impl OneRttPacketKeys {
         self.remote[(!self.cur_phase).as_index()].take();
     }
 
+    fn is_old_phase(&self, key_phase: KeyPhaseBit, pn: u64) -> bool {
+        key_phase != self.cur_phase
+            && self.remote[key_phase.as_index()].is_some()
+            && self.first_rcvd_pn.map_or(true, |first| pn < first)
+    }
+
     /// Get the remote key to decrypt the incoming 1-RTT packet.
-    /// If the key phase is not the current key phase, update the key, see [`Self::update`].
+    ///
+    /// If the key phase is not the current key phase, the packet is either a
+    /// reordered one protected with the old keys, or the peer has initiated a
+    /// key update, in which case the next generation keys are derived but not
+    /// installed until [`Self::on_pkt_rcvd`] is called after a successful decryption.
     ///
     /// Return `Arc<PacketKey>` to decrypt the incoming 1-RTT packet.
-    pub fn get_remote(&mut self, key_phase: KeyPhaseBit, _pn: u64) -> Arc<dyn PacketKey> {
-        if key_phase != self.cur_phase && self.remote[key_phase.as_index()].is_none() {
-            self.update();
+    pub fn get_remote(&mut self, key_phase: KeyPhaseBit, pn: u64) -> Arc<dyn PacketKey> {
+        if key_phase == self.cur_phase || self.is_old_phase(key_phase, pn) {
+            return self.remote[key_phase.as_index()].clone().unwrap();
+        }
+        self.derive_next_keys().0
+    }
+
+    /// Get the installed remote key of the given key phase, without deriving the next generation
+    /// keys.
+    ///
+    /// It is used for the 1-RTT packets received in the packet number spaces of the additional
+    /// paths of the multipath extension, whose packet numbers can not be compared with the ones
+    /// tracked by the key phase. Such packets never initiate a key update, a packet with the next
+    /// key phase is dropped until the key update is initiated by the packets of the first path.
+    pub fn get_remote_of_phase(&self, key_phase: KeyPhaseBit) -> Option<Arc<dyn PacketKey>> {
+        self.remote[key_phase.as_index()].clone()
+    }
+
+    /// Should be called after the 1-RTT packet has been decrypted successfully
+    /// with the key returned by [`Self::get_remote`].
+    ///
+    /// If the packet was protected with the next generation keys, the peer has
+    /// initiated a key update, and the keys will be updated accordingly.
+    ///
+    /// The peer must not update the keys again before it receives a packet
+    /// protected with the current keys, which acknowledges the packet that
+    /// initiated the previous update. A KEY_UPDATE_ERROR is returned if no
+    /// packet has been sent in the current key phase yet, see
+    /// [Section 6.2](https://www.rfc-editor.org/rfc/rfc9001#section-6.2-6)
+    /// of RFC 9001.
+    pub fn on_pkt_rcvd(&mut self, key_phase: KeyPhaseBit, pn: u64) -> Result<(), QuicError> {
+        if key_phase != self.cur_phase {
+            if self.is_old_phase(key_phase, pn) {
+                return Ok(());
+            }
+            if self.updated && self.first_sent_pn.is_none() {
+                return Err(QuicError::with_default_fty(
+                    ErrorKind::KeyUpdate,
+                    "the keys are updated twice without awaiting confirmation",
+                ));
+            }
+            self.install_next_keys();
+        }
+        self.first_rcvd_pn = Some(self.first_rcvd_pn.map_or(pn, |first| first.min(pn)));
+        Ok(())
+    }
+
+    /// Should be called after a 1-RTT packet has been encrypted with the key
+    /// returned by [`Self::get_local`].
+    pub fn on_pkt_sent(&mut self, pn: u64) {
+        self.first_sent_pn.get_or_insert(pn);
+    }
+
+    /// Should be called when a 1-RTT packet is acknowledged by the peer.
+    ///
+    /// Once a packet sent in the current key phase is acknowledged, the current
+    /// key phase is confirmed, and the next key update is allowed.
+    pub fn on_pkt_acked(&mut self, pn: u64) {
+        if self.first_sent_pn.is_some_and(|first| pn >= first) {
+            self.confirmed = true;
         }
-        self.remote[key_phase.as_index()].clone().unwrap()
     }
 
     /// Get the local current key to encrypt the outgoing packet.
@@ -382,6 +534,21 @@ impl ArcOneRttKeys {
     pub fn get_remote_keys(&self) -> GetRemoteOneRttKeys {
         GetRemoteOneRttKeys(self)
     }
+
+    /// Initiate a key update locally, switch to the next generation of 1-RTT
+    /// packet keys derived from the current secrets.
+    ///
+    /// Return the new key phase, which will be carried by the following 1-RTT
+    /// packets to inform the peer to update its keys too.
+    /// The update is refused if the keys are not ready, or the previous update
+    /// has not been confirmed by an acknowledged packet, see [`KeyUpdateError`].
+    pub fn next_key_phase(&self) -> Result<KeyPhaseBit, KeyUpdateError> {
+        let keys = self.lock_guard();
+        match &*keys {
+            OneRttKeysState::Ready { pk, .. } => pk.lock_guard().update(),
+            _ => Err(KeyUpdateError::Unavailable),
+        }
+    }
 }
 
 /// To obtain the remote key from [`ArcOneRttKeys`]` for removing 1-RTT header
@@ -411,3 +578,188 @@ impl Future for GetRemoteOneRttKeys<'_> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rustls::{
+        pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer},
+        quic::{self, KeyChange, Keys, PacketKey, Secrets, Version},
+        ClientConfig, RootCertStore, ServerConfig,
+    };
+
+    use super::*;
+
+    fn step(send: &mut quic::Connection, recv: &mut quic::Connection) -> Option<KeyChange> {
+        let mut buf = Vec::new();
+        let change = loop {
+            let prev = buf.len();
+            if let Some(change) = send.write_hs(&mut buf) {
+                break Some(change);
+            }
+            if prev == buf.len() {
+                break None;
+            }
+        };
+        recv.read_hs(&buf).unwrap();
+        change
+    }
+
+    /// Complete a TLS handshake in memory, return the 1-RTT keys of the client and the server.
+    fn one_rtt_keys() -> ((Keys, Secrets), (Keys, Secrets)) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let mut roots = RootCertStore::empty();
+        roots.add(cert.cert.der().clone()).unwrap();
+        let mut client_config = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        client_config.alpn_protocols = vec![b"test".to_vec()];
+        let key = PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der());
+        let mut server_config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert.cert.der().clone()], PrivateKeyDer::Pkcs8(key))
+            .unwrap();
+        server_config.alpn_protocols = vec![b"test".to_vec()];
+
+        let mut client: quic::Connection = quic::ClientConnection::new(
+            Arc::new(client_config),
+            Version::V1,
+            "localhost".try_into().unwrap(),
+            b"client params".to_vec(),
+        )
+        .unwrap()
+        .into();
+        let mut server: quic::Connection = quic::ServerConnection::new(
+            Arc::new(server_config),
+            Version::V1,
+            b"server params".to_vec(),
+        )
+        .unwrap()
+        .into();
+
+        let (mut client_keys, mut server_keys) = (None, None);
+        for _ in 0..8 {
+            if let Some(KeyChange::OneRtt { keys, next }) = step(&mut client, &mut server) {
+                client_keys = Some((keys, next));
+            }
+            if let Some(KeyChange::OneRtt { keys, next }) = step(&mut server, &mut client) {
+                server_keys = Some((keys, next));
+            }
+        }
+        (client_keys.unwrap(), server_keys.unwrap())
+    }
+
+    fn packet_keys((keys, secrets): (Keys, Secrets)) -> OneRttPacketKeys {
+        OneRttPacketKeys::new(keys.remote.packet, keys.local.packet, secrets)
+    }
+
+    fn seal(pk: &dyn PacketKey, pn: u64, payload: &[u8]) -> Vec<u8> {
+        let mut buf = payload.to_vec();
+        let tag = pk.encrypt_in_place(pn, &[], &mut buf).unwrap();
+        buf.extend_from_slice(tag.as_ref());
+        buf
+    }
+
+    fn open(pk: &dyn PacketKey, pn: u64, mut packet: Vec<u8>) -> Option<Vec<u8>> {
+        let len = pk.decrypt_in_place(pn, &[], &mut packet).ok()?.len();
+        packet.truncate(len);
+        Some(packet)
+    }
+
+    /// Send a packet from `tx` to `rx`, return whether `rx` decrypts it successfully.
+    fn transfer(tx: &mut OneRttPacketKeys, rx: &mut OneRttPacketKeys, pn: u64) -> bool {
+        let (key_phase, pk) = tx.get_local();
+        let packet = seal(pk.as_ref(), pn, b"hello");
+        tx.on_pkt_sent(pn);
+        deliver(rx, key_phase, pn, packet)
+    }
+
+    fn deliver(rx: &mut OneRttPacketKeys, key_phase: KeyPhaseBit, pn: u64, pkt: Vec<u8>) -> bool {
+        let pk = rx.get_remote(key_phase, pn);
+        match open(pk.as_ref(), pn, pkt) {
+            Some(payload) => {
+                assert_eq!(payload, b"hello");
+                rx.on_pkt_rcvd(key_phase, pn).unwrap();
+                true
+            }
+            None => false,
+        }
+    }
+
+    #[test]
+    fn test_key_update_initiated_by_peer() {
+        let (client, server) = one_rtt_keys();
+        let (mut client, mut server) = (packet_keys(client), packet_keys(server));
+
+        assert!(transfer(&mut client, &mut server, 0));
+        server.on_pkt_acked(0);
+        // the peer sent a packet with the old key phase, which arrives after the flip
+        let (old_phase, pk) = client.get_local();
+        let reordered = seal(pk.as_ref(), 1, b"hello");
+        client.on_pkt_sent(1);
+
+        client.on_pkt_acked(0);
+        assert_eq!(client.update(), Ok(KeyPhaseBit::One));
+        assert!(transfer(&mut client, &mut server, 2));
+        assert_eq!(server.get_local().0, KeyPhaseBit::One);
+        assert!(deliver(&mut server, old_phase, 1, reordered));
+        assert_eq!(server.get_local().0, KeyPhaseBit::One);
+
+        // the server responds with the updated keys
+        assert!(transfer(&mut server, &mut client, 0));
+        assert!(transfer(&mut client, &mut server, 3));
+    }
+
+    #[test]
+    fn test_consecutive_key_updates() {
+        let (client, server) = one_rtt_keys();
+        let (mut client, mut server) = (packet_keys(client), packet_keys(server));
+
+        assert!(transfer(&mut client, &mut server, 0));
+        client.on_pkt_acked(0);
+        assert_eq!(client.update(), Ok(KeyPhaseBit::One));
+        assert!(transfer(&mut client, &mut server, 1));
+        assert_eq!(server.get_local().0, KeyPhaseBit::One);
+
+        // a misbehaving peer updates the keys again, before the server sends
+        // any packet with the updated keys
+        client.on_pkt_acked(1);
+        assert_eq!(client.update(), Ok(KeyPhaseBit::Zero));
+        let (key_phase, pk) = client.get_local();
+        let packet = seal(pk.as_ref(), 2, b"hello");
+        let pk = server.get_remote(key_phase, 2);
+        assert!(open(pk.as_ref(), 2, packet).is_some());
+        let error = server.on_pkt_rcvd(key_phase, 2).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::KeyUpdate);
+        assert_eq!(server.get_local().0, KeyPhaseBit::One);
+    }
+
+    #[test]
+    fn test_key_update_initiated_locally() {
+        let (client, server) = one_rtt_keys();
+        let (mut client, mut server) = (packet_keys(client), packet_keys(server));
+
+        // the handshake is not confirmed by any acknowledged packet yet
+        assert_eq!(client.update(), Err(KeyUpdateError::Unconfirmed));
+        assert!(transfer(&mut client, &mut server, 0));
+        client.on_pkt_acked(0);
+        assert_eq!(client.update(), Ok(KeyPhaseBit::One));
+        // the previous update has not been confirmed
+        assert_eq!(client.update(), Err(KeyUpdateError::Unconfirmed));
+
+        // the server has not noticed the update, a packet with the old key phase
+        assert!(transfer(&mut server, &mut client, 0));
+        assert_eq!(client.get_local().0, KeyPhaseBit::One);
+
+        assert!(transfer(&mut client, &mut server, 1));
+        assert!(transfer(&mut server, &mut client, 1));
+        // an acknowledgment for the packet sent with the old keys doesn't confirm
+        client.on_pkt_acked(0);
+        assert_eq!(client.update(), Err(KeyUpdateError::Unconfirmed));
+        client.on_pkt_acked(1);
+        assert_eq!(client.update(), Ok(KeyPhaseBit::Zero));
+        assert!(transfer(&mut client, &mut server, 2));
+        assert_eq!(server.get_local().0, KeyPhaseBit::Zero);
+    }
+}
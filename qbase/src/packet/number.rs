@@ -65,26 +65,34 @@ pub fn take_pn_len(pn_len: u8) -> impl FnMut(&[u8]) -> nom::IResult<&[u8], Packe
 }
 
 impl PacketNumber {
-    /// Encode the packet number, based on the maximum confirmed packet number.
+    /// Encode the packet number, based on the largest acknowledged packet number.
     ///
     /// The size of the packet number encoding is at least one bit more than the
-    /// base-2 logarithm of the number of contiguous unacknowledged packet numbers
+    /// base-2 logarithm of the number of contiguous unacknowledged packet numbers,
+    /// the minimal number of bytes satisfying this is chosen.
+    /// `largest_acked` is [`None`] if no packet in this space has been acknowledged yet.
     ///
     /// See [Section 17.1-5](https://www.rfc-editor.org/rfc/rfc9000.html#section-17.1-5) and
     /// [Appendix A.2](https://www.rfc-editor.org/rfc/rfc9000.html#section-a.2)
     /// for more details.
-    pub fn encode(pn: u64, largest_acked: u64) -> Self {
-        let range = (pn - largest_acked) * 2;
-        if range < 1 << 8 {
-            Self::U8(pn as u8)
-        } else if range < 1 << 16 {
-            Self::U16(pn as u16)
-        } else if range < 1 << 24 {
-            Self::U24(pn as u32)
-        } else if range < 1 << 32 {
-            Self::U32(pn as u32)
-        } else {
-            panic!("packet number too large to encode")
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of unacknowledged packet numbers is too large to be encoded in 4 bytes.
+    pub fn encode(pn: u64, largest_acked: Option<u64>) -> Self {
+        // 尚未有包被确认时，所有比pn小的包号都算作未确认
+        let num_unacked = match largest_acked {
+            Some(largest_acked) => pn - largest_acked,
+            None => pn + 1,
+        };
+        // ceil(log2(num_unacked)) + 1，即满足num_unacked <= 2^(min_bits-1)的最小位数
+        let min_bits = u64::BITS - num_unacked.saturating_sub(1).leading_zeros() + 1;
+        match min_bits.div_ceil(8) {
+            1 => Self::U8(pn as u8),
+            2 => Self::U16(pn as u16),
+            3 => Self::U24(pn as u32 & 0xFF_FFFF),
+            4 => Self::U32(pn as u32),
+            _ => panic!("packet number too large to encode"),
         }
     }
 
@@ -119,18 +127,12 @@ impl PacketNumber {
         let win = 1 << nbits;
         let hwin = win / 2;
         let mask = win - 1;
-        // The incoming packet number should be greater than expected - hwin and less than or equal
-        // to expected + hwin
-        //
-        // This means we can't just strip the trailing bits from expected and add the truncated
-        // because that might yield a value outside the window.
-        //
-        // The following code calculates a candidate value and makes sure it's within the packet
-        // number window.
+        // 收到的包号应落在(expected - hwin, expected + hwin]的窗口内，
+        // 直接用truncated替换expected的低位得到的候选值可能落在窗口之外，需要再调整一个win
         let candidate = (expected & !mask) | truncated;
-        if expected.checked_sub(hwin).map_or(false, |x| candidate <= x) {
+        if candidate + hwin <= expected && candidate < (1 << 62) - win {
             candidate + win
-        } else if candidate > expected + hwin && candidate > win {
+        } else if candidate > expected + hwin && candidate >= win {
             candidate - win
         } else {
             candidate
@@ -181,34 +183,100 @@ mod tests {
         use crate::packet::PacketNumber;
 
         let mut buf = vec![];
-        buf.put_packet_number(PacketNumber::encode(0, 0));
+        buf.put_packet_number(PacketNumber::encode(0, None));
         assert_eq!(buf, [0x00]);
 
         buf.clear();
-        buf.put_packet_number(PacketNumber::encode(1 << 8, 0));
+        buf.put_packet_number(PacketNumber::encode(1 << 8, None));
         assert_eq!(buf, [0x01, 0x00]);
 
         buf.clear();
-        buf.put_packet_number(PacketNumber::encode(1 << 16, 0));
+        buf.put_packet_number(PacketNumber::encode(1 << 16, None));
         assert_eq!(buf, [0x01, 0x00, 0x00]);
 
         buf.clear();
-        buf.put_packet_number(PacketNumber::encode(1 << 24, 0));
+        buf.put_packet_number(PacketNumber::encode(1 << 24, None));
         assert_eq!(buf, [0x01, 0x00, 0x00, 0x00]);
     }
 
     #[test]
     fn test_encode_packet_number() {
-        let pn = super::PacketNumber::encode((1 << 31) - 1, 0);
+        let pn = super::PacketNumber::encode((1 << 31) - 1, None);
         assert_eq!(pn.decode(0), (1 << 31) - 1);
 
-        let pn = super::PacketNumber::encode(0, 0);
+        let pn = super::PacketNumber::encode(0, None);
         assert_eq!(pn.decode(0), 0);
     }
 
     #[test]
     #[should_panic]
     fn test_encode_packet_number_overflow() {
-        super::PacketNumber::encode(1 << 31, 0);
+        super::PacketNumber::encode((1 << 31) + 1, Some(0));
+    }
+
+    #[test]
+    fn test_encode_minimal_size() {
+        use super::PacketNumber;
+
+        // RFC 9000 A.2中的例子
+        assert_eq!(
+            PacketNumber::encode(0xac5c02, Some(0xabe8b3)),
+            PacketNumber::U16(0x5c02)
+        );
+        assert_eq!(
+            PacketNumber::encode(0xace8fe, Some(0xabe8b3)),
+            PacketNumber::U24(0xace8fe)
+        );
+
+        // 未确认的包号数量跨过2的幂次时，编码长度增长
+        assert_eq!(PacketNumber::encode(127, None).size(), 1);
+        assert_eq!(PacketNumber::encode(128, None).size(), 2);
+        assert_eq!(PacketNumber::encode(0x1_0080, Some(0x1_0000)).size(), 1);
+        assert_eq!(PacketNumber::encode(0x1_0081, Some(0x1_0000)).size(), 2);
+        assert_eq!(PacketNumber::encode(0x1_8000, Some(0x1_0000)).size(), 2);
+        assert_eq!(PacketNumber::encode(0x1_8001, Some(0x1_0000)).size(), 3);
+        assert_eq!(PacketNumber::encode((1 << 31) - 1, Some(0)).size(), 4);
+        assert_eq!(PacketNumber::encode(1 << 31, Some(0)).size(), 4);
+        assert_eq!(PacketNumber::encode(1, Some(1)).size(), 1);
+    }
+
+    #[test]
+    fn test_decode_packet_number() {
+        use super::PacketNumber;
+
+        // RFC 9000 A.3中的例子
+        assert_eq!(PacketNumber::U16(0x9b32).decode(0xa82f30eb), 0xa82f9b32);
+
+        // 窗口的上下边界
+        let expected = 0x1_0000;
+        assert_eq!(PacketNumber::U8(0x80).decode(expected), 0x1_0080);
+        assert_eq!(PacketNumber::U8(0x81).decode(expected), 0xff81);
+        assert_eq!(PacketNumber::U8(0x00).decode(expected), 0x1_0000);
+        assert_eq!(PacketNumber::U8(0xff).decode(expected), 0xffff);
+        assert_eq!(PacketNumber::U16(0x7fff).decode(expected), 0x1_7fff);
+        assert_eq!(PacketNumber::U16(0x8001).decode(expected), 0x8001);
+
+        // 包号接近0时不会向下溢出
+        assert_eq!(PacketNumber::U8(0xff).decode(0), 0xff);
+        assert_eq!(PacketNumber::U8(0xff).decode(1), 0xff);
+        // 包号接近2^62时不会越过上限
+        let max = (1u64 << 62) - 1;
+        assert_eq!(PacketNumber::U8(0x00).decode(max), max & !0xff);
+    }
+
+    #[test]
+    fn test_encode_decode_round_trip() {
+        use super::PacketNumber;
+
+        // 最大确认包号位于1字节、2字节编码的边界附近
+        for largest_acked in [0xfe, 0xff, 0x100, 0xfffe, 0xffff, 0x1_0000, 0xff_ffff] {
+            for pn in largest_acked + 1..largest_acked + 0x1_0000 {
+                let encoded = PacketNumber::encode(pn, Some(largest_acked));
+                // 接收端已收到的最大包号至少是发送端最大确认的包号
+                for largest_rcvd in [largest_acked, pn - 1] {
+                    assert_eq!(encoded.decode(largest_rcvd + 1), pn);
+                }
+            }
+        }
     }
 }
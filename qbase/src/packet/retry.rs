@@ -0,0 +1,142 @@
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_128_GCM};
+
+use crate::{cid::ConnectionId, packet::r#type::long::QUIC_VERSION_2};
+
+/// The size of the Retry Integrity Tag at the end of the Retry packet.
+pub const RETRY_INTEGRITY_TAG_SIZE: usize = 16;
+
+/// The secret key to compute the Retry Integrity Tag of QUIC version 1.
+const RETRY_INTEGRITY_KEY: [u8; 16] = [
+    0xbe, 0x0c, 0x69, 0x0b, 0x9f, 0x66, 0x57, 0x5a, 0x1d, 0x76, 0x6b, 0x54, 0xe3, 0x68, 0xc8, 0x4e,
+];
+
+/// The nonce to compute the Retry Integrity Tag of QUIC version 1.
+const RETRY_INTEGRITY_NONCE: [u8; 12] = [
+    0x46, 0x15, 0x99, 0xd3, 0x5d, 0x63, 0x2b, 0xf2, 0x23, 0x98, 0x25, 0xbb,
+];
+
+/// The secret key to compute the Retry Integrity Tag of QUIC version 2.
+const RETRY_INTEGRITY_KEY_V2: [u8; 16] = [
+    0x8f, 0xb4, 0xb0, 0x1b, 0x56, 0xac, 0x48, 0xe2, 0x60, 0xfb, 0xcb, 0xce, 0xad, 0x7c, 0xcc, 0x92,
+];
+
+/// The nonce to compute the Retry Integrity Tag of QUIC version 2.
+const RETRY_INTEGRITY_NONCE_V2: [u8; 12] = [
+    0xd8, 0x69, 0x69, 0xbc, 0x2d, 0x7c, 0x6d, 0x99, 0x90, 0xef, 0xb0, 0x4a,
+];
+
+/// Compute the Retry Integrity Tag, which is the output of AES-128-GCM with an empty plaintext
+/// and the Retry Pseudo-Packet as the associated data.
+///
+/// The Retry Pseudo-Packet is the Retry packet without the tag, prefixed with the Original
+/// Destination Connection ID, which is the DCID of the first Initial packet sent by the client.
+///
+/// The key and nonce of the AES-128-GCM depend on the QUIC `version` of the Retry packet, see
+/// [retry integrity](https://www.rfc-editor.org/rfc/rfc9369.html#name-retry-integrity) of
+/// [RFC 9369](https://www.rfc-editor.org/rfc/rfc9369.html) for the ones of QUIC version 2.
+///
+/// See [retry packet integrity](https://www.rfc-editor.org/rfc/rfc9001.html#name-retry-packet-integrity)
+/// of [RFC 9001](https://www.rfc-editor.org/rfc/rfc9001.html) for more details.
+pub fn retry_integrity_tag(
+    version: u32,
+    odcid: &ConnectionId,
+    retry_without_tag: &[u8],
+) -> [u8; RETRY_INTEGRITY_TAG_SIZE] {
+    let mut pseudo_packet = Vec::with_capacity(1 + odcid.len() + retry_without_tag.len());
+    pseudo_packet.push(odcid.len() as u8);
+    pseudo_packet.extend_from_slice(odcid);
+    pseudo_packet.extend_from_slice(retry_without_tag);
+
+    let (key, nonce) = match version {
+        QUIC_VERSION_2 => (RETRY_INTEGRITY_KEY_V2, RETRY_INTEGRITY_NONCE_V2),
+        _ => (RETRY_INTEGRITY_KEY, RETRY_INTEGRITY_NONCE),
+    };
+    let key = UnboundKey::new(&AES_128_GCM, &key).unwrap();
+    let tag = LessSafeKey::new(key)
+        .seal_in_place_separate_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(pseudo_packet),
+            &mut [],
+        )
+        .unwrap();
+
+    let mut integrity = [0; RETRY_INTEGRITY_TAG_SIZE];
+    integrity.copy_from_slice(tag.as_ref());
+    integrity
+}
+
+/// Verify the Retry Integrity Tag at the end of the whole Retry `packet` of the QUIC `version`.
+///
+/// Return `false` if the packet is too short or the tag is invalid, in which case the Retry packet
+/// must be discarded.
+pub fn verify_retry_integrity(version: u32, odcid: &ConnectionId, packet: &[u8]) -> bool {
+    let Some(tag_offset) = packet.len().checked_sub(RETRY_INTEGRITY_TAG_SIZE) else {
+        return false;
+    };
+    let (retry_without_tag, integrity) = packet.split_at(tag_offset);
+    let expected = retry_integrity_tag(version, odcid, retry_without_tag);
+    // constant time comparison
+    expected
+        .iter()
+        .zip(integrity)
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::r#type::long::QUIC_VERSION_1;
+
+    // See [Retry](https://www.rfc-editor.org/rfc/rfc9001.html#name-retry) in the appendix of
+    // RFC 9001 for the test vector.
+    const ODCID: [u8; 8] = [0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08];
+    const RETRY: [u8; 36] = [
+        0xff, 0x00, 0x00, 0x00, 0x01, 0x00, 0x08, 0xf0, 0x67, 0xa5, 0x50, 0x2a, 0x42, 0x62, 0xb5,
+        0x74, 0x6f, 0x6b, 0x65, 0x6e, 0x04, 0xa2, 0x65, 0xba, 0x2e, 0xff, 0x4d, 0x82, 0x90, 0x58,
+        0xfb, 0x3f, 0x0f, 0x24, 0x96, 0xba,
+    ];
+
+    // See [Retry](https://www.rfc-editor.org/rfc/rfc9369.html#name-retry) in the appendix of
+    // RFC 9369 for the test vector of QUIC version 2.
+    const RETRY_V2: [u8; 36] = [
+        0xcf, 0x6b, 0x33, 0x43, 0xcf, 0x00, 0x08, 0xf0, 0x67, 0xa5, 0x50, 0x2a, 0x42, 0x62, 0xb5,
+        0x74, 0x6f, 0x6b, 0x65, 0x6e, 0xc8, 0x64, 0x6c, 0xe8, 0xbf, 0xe3, 0x39, 0x52, 0xd9, 0x55,
+        0x54, 0x36, 0x65, 0xdc, 0xc7, 0xb6,
+    ];
+
+    #[test]
+    fn test_retry_integrity_tag() {
+        let odcid = ConnectionId::from_slice(&ODCID);
+        let (retry_without_tag, integrity) = RETRY.split_at(RETRY.len() - 16);
+        assert_eq!(
+            retry_integrity_tag(QUIC_VERSION_1, &odcid, retry_without_tag),
+            integrity
+        );
+        assert!(verify_retry_integrity(QUIC_VERSION_1, &odcid, &RETRY));
+
+        let mut tampered = RETRY;
+        tampered[20] ^= 0x01;
+        assert!(!verify_retry_integrity(QUIC_VERSION_1, &odcid, &tampered));
+        let other_odcid = ConnectionId::from_slice(&ODCID[1..]);
+        assert!(!verify_retry_integrity(
+            QUIC_VERSION_1,
+            &other_odcid,
+            &RETRY
+        ));
+        assert!(!verify_retry_integrity(
+            QUIC_VERSION_1,
+            &odcid,
+            &RETRY[..15]
+        ));
+    }
+
+    #[test]
+    fn test_retry_integrity_tag_v2() {
+        let odcid = ConnectionId::from_slice(&ODCID);
+        assert!(verify_retry_integrity(QUIC_VERSION_2, &odcid, &RETRY_V2));
+        // 不同版本的完整性标签互不通用
+        assert!(!verify_retry_integrity(QUIC_VERSION_1, &odcid, &RETRY_V2));
+        assert!(!verify_retry_integrity(QUIC_VERSION_2, &odcid, &RETRY));
+    }
+}
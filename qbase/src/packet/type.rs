@@ -8,7 +8,7 @@ pub mod long;
 pub mod short;
 
 /// Header form bit
-const HEADER_FORM_MASK: u8 = 0x80;
+pub(super) const HEADER_FORM_MASK: u8 = 0x80;
 /// The next bit (0x40) of byte 0 is set to 1, unless the packet is a Version Negotiation packet.
 const FIXED_BIT: u8 = 0x40;
 
@@ -62,34 +62,44 @@ impl ShortSpecificBits {
     }
 }
 
+impl<const R: u8> SpecificBits<R> {
+    /// Check the reserved bits, which must be 0 after removing both the header protection and
+    /// the packet protection; otherwise, a connection error of type PROTOCOL_VIOLATION is returned.
+    ///
+    /// See [Section 17.2](https://www.rfc-editor.org/rfc/rfc9000.html#section-17.2-8.2) and
+    /// [Section 17.3.1](https://www.rfc-editor.org/rfc/rfc9000.html#section-17.3.1-4.8) of QUIC.
+    pub fn check_reserved_bits(&self) -> Result<(), Error> {
+        let reserved_bits = self.0 & R;
+        if reserved_bits == 0 {
+            Ok(())
+        } else {
+            Err(Error::InvalidReservedBits(reserved_bits, R))
+        }
+    }
+}
+
 impl<const R: u8> From<u8> for SpecificBits<R> {
     fn from(byte: u8) -> Self {
         Self(byte)
     }
 }
 
-/// Get the packet number length from the protected first byte of the long or short header.
-/// The reserved bits must be 0; otherwise, a connection error of type PROTOCOL_VIOLATION
-/// is returned.
+/// Get the packet number length from the first byte of the long or short header, whose header
+/// protection has been removed.
 ///
-/// See [Section 17.2](https://www.rfc-editor.org/rfc/rfc9000.html#section-17.2-8.2) and
-/// [Section 17.3.1](https://www.rfc-editor.org/rfc/rfc9000.html#section-17.3.1-4.8) of QUIC.
+/// The reserved bits are not checked here, because they can only be trusted after the packet
+/// is decrypted successfully, see [`SpecificBits::check_reserved_bits`].
 pub trait GetPacketNumberLength {
     /// The last two bits of first byte contain the length of the Packet Number
     const PN_LEN_MASK: u8 = 0x03;
 
     /// Get the encoding length of the Packet Number
-    fn pn_len(&self) -> Result<u8, Error>;
+    fn pn_len(&self) -> u8;
 }
 
 impl<const R: u8> GetPacketNumberLength for SpecificBits<R> {
-    fn pn_len(&self) -> Result<u8, Error> {
-        let reserved_bit = self.0 & R;
-        if reserved_bit == 0 {
-            Ok((self.0 & Self::PN_LEN_MASK) + 1)
-        } else {
-            Err(Error::InvalidReservedBits(reserved_bit, R))
-        }
+    fn pn_len(&self) -> u8 {
+        (self.0 & Self::PN_LEN_MASK) + 1
     }
 }
 
@@ -161,56 +171,62 @@ mod tests {
     fn test_long_clear_bits() {
         let specific_bits = SpecificBits::<0x0C>(0x0C);
         assert_eq!(
-            specific_bits.pn_len(),
+            specific_bits.check_reserved_bits(),
             Err(Error::InvalidReservedBits(0x0C, 0x0C))
         );
         let specific_bits = SpecificBits::<0x0C>(0x04);
         assert_eq!(
-            specific_bits.pn_len(),
+            specific_bits.check_reserved_bits(),
             Err(Error::InvalidReservedBits(0x04, 0x0C))
         );
-        let specific_bits = SpecificBits::<0x0C>(0x08);
+        let specific_bits = SpecificBits::<0x0C>(0x0B);
         assert_eq!(
-            specific_bits.pn_len(),
+            specific_bits.check_reserved_bits(),
             Err(Error::InvalidReservedBits(0x08, 0x0C))
         );
+        // 保留位不影响包号长度的解析
+        assert_eq!(specific_bits.pn_len(), 4);
+        assert_eq!(SpecificBits::<0x0C>(0x03).check_reserved_bits(), Ok(()));
 
         let specific_bits = LongSpecificBits::with_pn_len(4);
-        assert_eq!(specific_bits.pn_len().unwrap(), 4);
+        assert_eq!(specific_bits.pn_len(), 4);
         let specific_bits = LongSpecificBits::with_pn_len(3);
-        assert_eq!(specific_bits.pn_len().unwrap(), 3);
+        assert_eq!(specific_bits.pn_len(), 3);
         let specific_bits = LongSpecificBits::with_pn_len(2);
-        assert_eq!(specific_bits.pn_len().unwrap(), 2);
+        assert_eq!(specific_bits.pn_len(), 2);
         let specific_bits = LongSpecificBits::with_pn_len(1);
-        assert_eq!(specific_bits.pn_len().unwrap(), 1);
+        assert_eq!(specific_bits.pn_len(), 1);
     }
 
     #[test]
     fn test_short_specific_bits() {
         let specific_bits = SpecificBits::<0x18>(0x18);
         assert_eq!(
-            specific_bits.pn_len(),
+            specific_bits.check_reserved_bits(),
             Err(Error::InvalidReservedBits(0x18, 0x18))
         );
         let specific_bits = SpecificBits::<0x18>(0x11);
         assert_eq!(
-            specific_bits.pn_len(),
+            specific_bits.check_reserved_bits(),
             Err(Error::InvalidReservedBits(0x10, 0x18))
         );
+        assert_eq!(specific_bits.pn_len(), 2);
         let specific_bits = SpecificBits::<0x18>(0x0A);
         assert_eq!(
-            specific_bits.pn_len(),
+            specific_bits.check_reserved_bits(),
             Err(Error::InvalidReservedBits(0x08, 0x18))
         );
+        // key phase位不属于保留位
+        assert_eq!(SpecificBits::<0x18>(0x07).check_reserved_bits(), Ok(()));
 
         let specific_bits = ShortSpecificBits::with_pn_len(4);
-        assert_eq!(specific_bits.pn_len().unwrap(), 4);
+        assert_eq!(specific_bits.pn_len(), 4);
         let specific_bits = ShortSpecificBits::with_pn_len(3);
-        assert_eq!(specific_bits.pn_len().unwrap(), 3);
+        assert_eq!(specific_bits.pn_len(), 3);
         let specific_bits = ShortSpecificBits::with_pn_len(2);
-        assert_eq!(specific_bits.pn_len().unwrap(), 2);
+        assert_eq!(specific_bits.pn_len(), 2);
         let specific_bits = ShortSpecificBits::with_pn_len(1);
-        assert_eq!(specific_bits.pn_len().unwrap(), 1);
+        assert_eq!(specific_bits.pn_len(), 1);
     }
 
     #[test]
@@ -2,6 +2,13 @@ use deref_derive::Deref;
 
 /// Supports IQuic version 1, if other versions are supported in the future, add them here.
 pub mod v1;
+/// The long packet type codepoints of QUIC version 2, whose packet types are the same as version 1.
+pub mod v2;
+
+/// The version number of QUIC version 1, see [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html).
+pub const QUIC_VERSION_1: u32 = 0x0000_0001;
+/// The version number of QUIC version 2, see [RFC 9369](https://www.rfc-editor.org/rfc/rfc9369.html).
+pub const QUIC_VERSION_2: u32 = 0x6b33_43cf;
 
 /// The long packet header contains version information, so the 32-bit
 /// version number info is also one part of the versioned packet type.
@@ -39,13 +46,61 @@ impl Version<1, v1::Type> {
 /// Represent the packet types in the IQuic version 1, including Retry/Initial/0-RTT/Handshake.
 pub type Ver1 = Version<1, v1::Type>;
 
+/// Mainly define the long packet types of the QUIC version 2.
+impl Version<QUIC_VERSION_2, v1::Type> {
+    /// Retry packet type of the QUIC version 2.
+    pub const RETRY: Self = Self(v1::Type::Retry);
+    /// Initial packet type of the QUIC version 2.
+    pub const INITIAL: Self = Self(v1::Type::Initial);
+    /// 0-RTT packet type of the QUIC version 2.
+    pub const ZERO_RTT: Self = Self(v1::Type::ZeroRtt);
+    /// Handshake packet type of the QUIC version 2.
+    pub const HANDSHAKE: Self = Self(v1::Type::Handshake);
+}
+
+/// Represent the packet types in the QUIC version 2, which are the same as the version 1, but
+/// encoded with different codepoints, see [`v2`].
+pub type Ver2 = Version<QUIC_VERSION_2, v1::Type>;
+
 /// The sum types of the long packets.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Type {
     VersionNegotiation,
     V1(Version<1, v1::Type>),
+    V2(Version<QUIC_VERSION_2, v1::Type>),
     // in the future, add other versions here
-    // V2(v2::HeaderType),
+}
+
+impl Type {
+    /// Create the long packet type `ty` of the QUIC `version`.
+    ///
+    /// The packet types of version 1 are used for the unknown versions, because only the known
+    /// versions can be parsed from the packets or negotiated with the peer.
+    pub fn new(version: u32, ty: v1::Type) -> Self {
+        match version {
+            QUIC_VERSION_2 => Type::V2(Version(ty)),
+            _ => Type::V1(Version(ty)),
+        }
+    }
+
+    /// Get the version number of the long packet type, 0 for the Version Negotiation packet.
+    pub fn version(&self) -> u32 {
+        match self {
+            Type::VersionNegotiation => 0,
+            Type::V1(ty) => ty.get_version(),
+            Type::V2(ty) => ty.get_version(),
+        }
+    }
+
+    /// Get the packet type regardless of the version, [`None`] for the Version Negotiation
+    /// packet.
+    pub fn packet_type(&self) -> Option<v1::Type> {
+        match self {
+            Type::VersionNegotiation => None,
+            Type::V1(ty) => Some(**ty),
+            Type::V2(ty) => Some(**ty),
+        }
+    }
 }
 
 /// The io module provides the functions to parse and write the long packet type.
@@ -71,6 +126,12 @@ pub mod io {
                         ty.try_into().map_err(nom::Err::Error)?,
                     )),
                 )),
+                QUIC_VERSION_2 => Ok((
+                    remain,
+                    Type::V2(Version::<QUIC_VERSION_2, v1::Type>(
+                        v2::decode(ty).map_err(nom::Err::Error)?,
+                    )),
+                )),
                 v => Err(nom::Err::Error(Error::UnsupportedVersion(v))),
             }
         }
@@ -94,6 +155,10 @@ pub mod io {
                     self.put_u8(LONG_HEADER_BIT | FIXED_BIT | ty);
                     self.put_u32(1);
                 }
+                Type::V2(Version::<QUIC_VERSION_2, _>(ty)) => {
+                    self.put_u8(LONG_HEADER_BIT | FIXED_BIT | v2::encode(*ty));
+                    self.put_u32(QUIC_VERSION_2);
+                }
             }
         }
     }
@@ -101,7 +166,7 @@ pub mod io {
 
 #[cfg(test)]
 mod tests {
-    use crate::packet::r#type::long::Ver1;
+    use crate::packet::r#type::long::{Ver1, Ver2};
 
     #[test]
     fn test_read_long_type() {
@@ -116,6 +181,14 @@ mod tests {
         let (remain, ty) = parse_long_type(0x80)(&buf).unwrap();
         assert_eq!(remain.len(), 0);
         assert_eq!(ty, Type::VersionNegotiation);
+
+        // QUIC v2的Initial包类型为0b01，v1的0-RTT包也是0b01
+        let buf = vec![0x6b, 0x33, 0x43, 0xcf];
+        let (remain, ty) = parse_long_type(0xd0)(&buf).unwrap();
+        assert_eq!(remain.len(), 0);
+        assert_eq!(ty, Type::V2(Ver2::INITIAL));
+        assert_eq!(ty.version(), super::QUIC_VERSION_2);
+        assert_eq!(ty.packet_type(), Some(super::v1::Type::Initial));
     }
 
     #[test]
@@ -140,6 +213,24 @@ mod tests {
         assert_eq!(buf, vec![0xc0, 0x00, 0x00, 0x00, 0x01]);
     }
 
+    #[test]
+    fn test_write_v2_long_type() {
+        use super::{v1, Type, QUIC_VERSION_2};
+        use crate::packet::r#type::long::io::WriteLongType;
+
+        for (ty, first_byte) in [
+            (v1::Type::Retry, 0xc0),
+            (v1::Type::Initial, 0xd0),
+            (v1::Type::ZeroRtt, 0xe0),
+            (v1::Type::Handshake, 0xf0),
+        ] {
+            let ty = Type::new(QUIC_VERSION_2, ty);
+            let mut buf = vec![];
+            buf.put_long_type(&ty);
+            assert_eq!(buf, vec![first_byte, 0x6b, 0x33, 0x43, 0xcf]);
+        }
+    }
+
     #[test]
     fn test_write_version_negotiation_long_type() {
         use super::Type;
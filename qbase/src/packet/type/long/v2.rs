@@ -0,0 +1,51 @@
+use super::v1::Type;
+use crate::packet::{error::Error, r#type::FIXED_BIT};
+
+// QUIC v2重新分配了长包头的包类型，以防中间设备僵化于v1的编码
+const LONG_PACKET_TYPE_MASK: u8 = 0x30;
+const RETRY_PACKET_TYPE: u8 = 0x00;
+const INITIAL_PACKET_TYPE: u8 = 0x10;
+const ZERO_RTT_PACKET_TYPE: u8 = 0x20;
+const HANDSHAKE_PACKET_TYPE: u8 = 0x30;
+
+/// Encode the long packet type into the type bits of QUIC version 2, which are Initial 0b01,
+/// 0-RTT 0b10, Handshake 0b11 and Retry 0b00.
+///
+/// See [long header packet types](https://www.rfc-editor.org/rfc/rfc9369.html#name-long-header-packet-types)
+/// of [RFC 9369](https://www.rfc-editor.org/rfc/rfc9369.html) for more details.
+pub fn encode(ty: Type) -> u8 {
+    match ty {
+        Type::Retry => RETRY_PACKET_TYPE,
+        Type::Initial => INITIAL_PACKET_TYPE,
+        Type::ZeroRtt => ZERO_RTT_PACKET_TYPE,
+        Type::Handshake => HANDSHAKE_PACKET_TYPE,
+    }
+}
+
+/// Decode the long packet type from the first byte of a QUIC version 2 long header.
+pub fn decode(value: u8) -> Result<Type, Error> {
+    if value & FIXED_BIT == 0 {
+        return Err(Error::InvalidFixedBit);
+    }
+    match value & LONG_PACKET_TYPE_MASK {
+        RETRY_PACKET_TYPE => Ok(Type::Retry),
+        INITIAL_PACKET_TYPE => Ok(Type::Initial),
+        ZERO_RTT_PACKET_TYPE => Ok(Type::ZeroRtt),
+        HANDSHAKE_PACKET_TYPE => Ok(Type::Handshake),
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode(0xc0), Ok(Type::Retry));
+        assert_eq!(decode(0xd0), Ok(Type::Initial));
+        assert_eq!(decode(0xe0), Ok(Type::ZeroRtt));
+        assert_eq!(decode(0xf0), Ok(Type::Handshake));
+        assert_eq!(decode(0x90), Err(Error::InvalidFixedBit));
+    }
+}
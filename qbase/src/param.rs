@@ -61,9 +61,18 @@ pub struct Parameters {
     retry_source_connection_id: Option<ConnectionId>,
     #[getset(get_copy = "pub", set = "pub")]
     max_datagram_frame_size: VarInt,
+    // ack frequency扩展，单位为微秒，未通告则不支持该扩展
+    #[getset(get_copy = "pub", set = "pub")]
+    min_ack_delay: Option<VarInt>,
+    // multipath扩展，双方都通告才能同时使用多条路径
+    #[getset(get_copy = "pub", set = "pub")]
+    enable_multipath: bool,
     // TOOD: 对此传输参数的支持
     #[getset(get_copy = "pub", set = "pub")]
     grease_quic_bit: bool,
+    // 附带一个保留的传输参数，防止对端的实现僵化，只对本地参数有意义
+    #[getset(get_copy = "pub", set = "pub")]
+    grease: bool,
 }
 
 impl Default for Parameters {
@@ -87,7 +96,10 @@ impl Default for Parameters {
             initial_source_connection_id: None,
             retry_source_connection_id: None,
             max_datagram_frame_size: VarInt::from_u32(65535),
+            min_ack_delay: None,
+            enable_multipath: false,
             grease_quic_bit: false,
+            grease: true,
         }
     }
 }
@@ -96,19 +108,37 @@ impl Default for Parameters {
 macro_rules! generate_validate {
     ($t:ty) => {
         impl $t {
+            /// Check the values of the transport parameters are in the valid range.
+            ///
+            /// See [transport parameter definitions](https://www.rfc-editor.org/rfc/rfc9000.html#name-transport-parameter-definit)
+            /// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
             pub fn validate(&self) -> Result<(), &'static str> {
-                if !(1200..65527).contains(&self.max_udp_payload_size.into_inner()) {
-                    return Err("max_udp_payload_size must be at least 1200 bytes");
+                if !(1200..=65527).contains(&self.max_udp_payload_size.into_inner()) {
+                    return Err("max_udp_payload_size must be between 1200 and 65527 bytes");
                 }
                 if self.ack_delay_exponent > 20 {
                     return Err("ack_delay_exponent must be at most 20");
                 }
-                if self.max_ack_delay > 1 << 14 {
-                    return Err("max_ack_delay must be at most 2^14");
+                if self.max_ack_delay >= 1 << 14 {
+                    return Err("max_ack_delay must be less than 2^14");
+                }
+                if self.initial_max_streams_bidi > 1 << 60 {
+                    return Err("initial_max_streams_bidi must not exceed 2^60");
+                }
+                if self.initial_max_streams_uni > 1 << 60 {
+                    return Err("initial_max_streams_uni must not exceed 2^60");
                 }
                 if self.active_connection_id_limit < 2 {
                     return Err("active_connection_id_limit must be at least 2");
                 }
+                if let Some(min_ack_delay) = self.min_ack_delay {
+                    if min_ack_delay >= 1 << 24 {
+                        return Err("min_ack_delay must be less than 2^24");
+                    }
+                    if min_ack_delay > self.max_ack_delay.into_inner() * 1000 {
+                        return Err("min_ack_delay must not be greater than max_ack_delay");
+                    }
+                }
                 Ok(())
             }
         }
@@ -130,6 +160,27 @@ pub struct PreferredAddress {
 }
 
 impl PreferredAddress {
+    /// Create a new preferred address, which is advertised by the server.
+    ///
+    /// A server that does not have an address of one family can use the unspecified address with
+    /// port 0 for it, such as `0.0.0.0:0`, the client will not use it.
+    ///
+    /// The `connection_id` is the connection ID of sequence number 1 issued by the server, and the
+    /// `stateless_reset_token` is associated with it.
+    pub fn new(
+        address_v4: SocketAddrV4,
+        address_v6: SocketAddrV6,
+        connection_id: ConnectionId,
+        stateless_reset_token: ResetToken,
+    ) -> Self {
+        Self {
+            address_v4,
+            address_v6,
+            connection_id,
+            stateless_reset_token,
+        }
+    }
+
     pub fn encoding_size(&self) -> usize {
         6 + 18 + self.connection_id.encoding_size() + self.stateless_reset_token.encoding_size()
     }
@@ -142,7 +193,12 @@ mod test {
     use std::net::Ipv4Addr;
 
     use super::{codec::WriteParameters, *};
-    use crate::{cid::be_connection_id, token::RESET_TOKEN_SIZE};
+    use crate::{
+        cid::be_connection_id,
+        entropy::{OsEntropy, SeededEntropy},
+        token::RESET_TOKEN_SIZE,
+        varint::WriteVarInt,
+    };
 
     #[test]
     fn coding() {
@@ -177,13 +233,16 @@ mod test {
             .initial_source_connection_id(init_cid)
             .retry_source_connection_id(init_cid)
             .max_datagram_frame_size(VarInt::from_u32(65535))
+            .min_ack_delay(VarInt::from_u32(1000))
+            .enable_multipath(true)
             .grease_quic_bit(false)
+            .grease(false)
             .build()
             .unwrap()
             .into();
 
         let mut buf = bytes::BytesMut::new();
-        buf.put_parameters(&params);
+        buf.put_parameters(&params, &OsEntropy);
         let params2 = codec::be_parameters(&buf).unwrap().1;
         assert_eq!(params, params2);
     }
@@ -206,6 +265,93 @@ mod test {
         assert!(build_result.is_err());
     }
 
+    #[test]
+    fn validate_out_of_range() {
+        let valid = |set: fn(&mut Parameters)| {
+            let mut params = Parameters::default();
+            set(&mut params);
+            params.validate()
+        };
+
+        assert!(valid(|p| _ = p.set_max_udp_payload_size(VarInt::from_u32(1199))).is_err());
+        assert!(valid(|p| _ = p.set_max_udp_payload_size(VarInt::from_u32(1200))).is_ok());
+        assert!(valid(|p| _ = p.set_max_udp_payload_size(VarInt::from_u32(65527))).is_ok());
+        assert!(valid(|p| _ = p.set_max_udp_payload_size(VarInt::from_u32(65528))).is_err());
+
+        assert!(valid(|p| _ = p.set_ack_delay_exponent(VarInt::from_u32(20))).is_ok());
+        assert!(valid(|p| _ = p.set_ack_delay_exponent(VarInt::from_u32(21))).is_err());
+
+        assert!(valid(|p| _ = p.set_max_ack_delay(VarInt::from_u32((1 << 14) - 1))).is_ok());
+        assert!(valid(|p| _ = p.set_max_ack_delay(VarInt::from_u32(1 << 14))).is_err());
+
+        // 流数量的上限不能超过2^60，否则流ID无法编码
+        fn max_streams(n: u64) -> VarInt {
+            VarInt::from_u64(n).unwrap()
+        }
+        assert!(valid(|p| _ = p.set_initial_max_streams_bidi(max_streams(1 << 60))).is_ok());
+        assert!(valid(|p| _ = p.set_initial_max_streams_bidi(max_streams((1 << 60) + 1))).is_err());
+        assert!(valid(|p| _ = p.set_initial_max_streams_uni(max_streams((1 << 60) + 1))).is_err());
+
+        assert!(valid(|p| _ = p.set_active_connection_id_limit(VarInt::from_u32(1))).is_err());
+        assert!(valid(|p| _ = p.set_active_connection_id_limit(VarInt::from_u32(2))).is_ok());
+
+        // min_ack_delay以微秒为单位，max_ack_delay以毫秒为单位
+        assert!(valid(|p| _ = p.set_min_ack_delay(Some(VarInt::from_u32(1_000_000)))).is_ok());
+        assert!(valid(|p| _ = p.set_min_ack_delay(Some(VarInt::from_u32(1_000_001)))).is_err());
+    }
+
+    #[test]
+    fn absent_max_datagram_frame_size() {
+        let params = codec::be_parameters(&[]).unwrap().1;
+        assert_eq!(params.max_datagram_frame_size(), VarInt::from_u32(0));
+        assert_eq!(params.min_ack_delay(), None);
+        assert!(!params.enable_multipath());
+
+        // 包号空间以连接ID序号标识路径，与新版multipath草案不兼容，不认草案的参数id
+        let mut buf = bytes::BytesMut::new();
+        buf.put_varint(&VarInt::from_u64(0x0f739bbc1b666d06).unwrap());
+        buf.put_varint(&VarInt::from_u32(0));
+        let params = codec::be_parameters(&buf).unwrap().1;
+        assert!(!params.enable_multipath());
+    }
+
+    #[test]
+    fn grease_params() {
+        let params = Parameters::default();
+        assert!(params.grease());
+        let mut buf = bytes::BytesMut::new();
+        buf.put_parameters(&params, &OsEntropy);
+
+        let mut without_grease = params;
+        without_grease.set_grease(false);
+        let mut plain = bytes::BytesMut::new();
+        plain.put_parameters(&without_grease, &OsEntropy);
+        assert!(buf.len() > plain.len());
+
+        // GREASE参数的内容取自给定的熵源，同样的种子得到同样的编码
+        let encode = |seed| {
+            let mut buf = bytes::BytesMut::new();
+            buf.put_parameters(&params, &SeededEntropy::new(seed));
+            buf
+        };
+        assert_eq!(encode(1), encode(1));
+        assert_ne!(encode(1), encode(2));
+
+        // 对端的GREASE传输参数被忽略，而不是报错
+        let decoded = codec::be_parameters(&buf).unwrap().1;
+        assert_eq!(decoded, without_grease);
+
+        let mut peer = bytes::BytesMut::new();
+        for (id, value) in [(27u32, &[][..]), (31 * 5 + 27, &[0xde, 0xad][..])] {
+            peer.put_varint(&VarInt::from_u32(id));
+            peer.put_varint(&VarInt::try_from(value.len()).unwrap());
+            peer.extend_from_slice(value);
+        }
+        peer.extend_from_slice(&plain);
+        let decoded = codec::be_parameters(&peer).unwrap().1;
+        assert_eq!(decoded, without_grease);
+    }
+
     #[test]
     fn default_params_test() {
         let params = Parameters::default();
@@ -36,9 +36,18 @@ pub struct ClientParameters {
     initial_source_connection_id: Option<ConnectionId>,
     #[getset(get_copy = "pub", set = "pub")]
     max_datagram_frame_size: VarInt,
+    // ack frequency扩展，单位为微秒，未通告则不支持该扩展
+    #[getset(get_copy = "pub", set = "pub")]
+    min_ack_delay: Option<VarInt>,
+    // multipath扩展，双方都通告才能同时使用多条路径
+    #[getset(get_copy = "pub", set = "pub")]
+    enable_multipath: bool,
     // TOOD: 对此传输参数的支持
     #[getset(get_copy = "pub", set = "pub")]
     grease_quic_bit: bool,
+    // 附带一个保留的传输参数，防止对端的实现僵化，只对本地参数有意义
+    #[getset(get_copy = "pub", set = "pub")]
+    grease: bool,
 }
 
 impl Default for ClientParameters {
@@ -59,7 +68,10 @@ impl Default for ClientParameters {
             active_connection_id_limit: params.active_connection_id_limit,
             initial_source_connection_id: params.initial_source_connection_id,
             max_datagram_frame_size: params.max_datagram_frame_size,
+            min_ack_delay: params.min_ack_delay,
+            enable_multipath: params.enable_multipath,
             grease_quic_bit: params.grease_quic_bit,
+            grease: params.grease,
         }
     }
 }
@@ -113,7 +125,10 @@ impl ClientParametersBuilder {
             max_datagram_frame_size: builder
                 .max_datagram_frame_size
                 .unwrap_or(default.max_datagram_frame_size),
+            min_ack_delay: builder.min_ack_delay.unwrap_or(default.min_ack_delay),
+            enable_multipath: builder.enable_multipath.unwrap_or(default.enable_multipath),
             grease_quic_bit: builder.grease_quic_bit.unwrap_or(default.grease_quic_bit),
+            grease: builder.grease.unwrap_or(default.grease),
         };
         params.validate()?;
         Ok(params)
@@ -137,7 +152,10 @@ impl From<ClientParameters> for Parameters {
             active_connection_id_limit: value.active_connection_id_limit,
             initial_source_connection_id: value.initial_source_connection_id,
             max_datagram_frame_size: value.max_datagram_frame_size,
+            min_ack_delay: value.min_ack_delay,
+            enable_multipath: value.enable_multipath,
             grease_quic_bit: value.grease_quic_bit,
+            grease: value.grease,
             ..Default::default()
         }
     }
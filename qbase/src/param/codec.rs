@@ -6,10 +6,23 @@ use nom::{bytes::complete::take, combinator::map};
 use super::{Parameters, PreferredAddress};
 use crate::{
     cid::{be_connection_id, ConnectionId, WriteConnectionId, MAX_CID_SIZE},
+    entropy::EntropySource,
     token::{be_reset_token, ResetToken, WriteResetToken},
     varint::{be_varint, VarInt, WriteVarInt},
 };
 
+/// The transport parameter id of min_ack_delay, defined by the ack frequency extension.
+const MIN_ACK_DELAY_ID: u64 = 0xff04de1b;
+
+/// The transport parameter id of enable_multipath.
+///
+/// The extension keeps a packet number space per path, identified by the sequence number of the
+/// connection ID the packets are sent to, and acknowledges them with the PATH_ACK frames, as the
+/// early multipath drafts did. The later drafts changed the path identifiers and the wire format,
+/// so a private codepoint is used rather than the draft's one, and only the peers of this
+/// implementation negotiate the extension.
+const EXPERIMENTAL_MULTIPATH_ID: u64 = 0x676d71756963;
+
 pub fn be_parameters(input: &[u8]) -> nom::IResult<&[u8], Parameters> {
     let be_connection_id = |input, len: VarInt| {
         let len = len.into_inner() as usize;
@@ -40,6 +53,10 @@ pub fn be_parameters(input: &[u8]) -> nom::IResult<&[u8], Parameters> {
 
     let mut remain = input;
     let mut tp = Parameters::default();
+    // The absence of max_datagram_frame_size means that the peer does not support DATAGRAM frames
+    tp.max_datagram_frame_size = VarInt::default();
+    // GREASE is only meaningful for the local parameters
+    tp.grease = false;
     while !remain.is_empty() {
         let tag: VarInt;
         let len: VarInt;
@@ -66,10 +83,13 @@ pub fn be_parameters(input: &[u8]) -> nom::IResult<&[u8], Parameters> {
             0x0f => (remain, tp.initial_source_connection_id) = be_connection_id(remain, len)?,
             0x10 => (remain, tp.retry_source_connection_id) = be_connection_id(remain, len)?,
             0x20 => (remain, tp.max_datagram_frame_size) = be_varint(remain)?,
+            MIN_ACK_DELAY_ID => (remain, tp.min_ack_delay) = map(be_varint, Some)(remain)?,
+            EXPERIMENTAL_MULTIPATH_ID => tp.enable_multipath = true,
             // 0x2ab2 => tp.grease_quic_bit = true,
             _ => {
                 // Ref. `<https://www.rfc-editor.org/rfc/rfc9000.html#name-new-transport-parameters>
-                // An endpoint MUST ignore transport parameters that it does not support.
+                // An endpoint MUST ignore transport parameters that it does not support,
+                // including the reserved ones of the form 31 * N + 27 sent for GREASE.
 
                 // take it, and ignore it
                 (remain, ..) = take(len)(remain)?;
@@ -81,12 +101,14 @@ pub fn be_parameters(input: &[u8]) -> nom::IResult<&[u8], Parameters> {
 }
 
 pub trait WriteParameters {
-    fn put_parameters(&mut self, params: &Parameters);
+    /// Write the transport parameters, the content of the reserved transport parameter is
+    /// filled by the `entropy` if GREASE is enabled.
+    fn put_parameters(&mut self, params: &Parameters, entropy: &dyn EntropySource);
     fn put_preferred_address(&mut self, addr: &super::PreferredAddress);
 }
 
 impl<T: BufMut> WriteParameters for T {
-    fn put_parameters(&mut self, params: &Parameters) {
+    fn put_parameters(&mut self, params: &Parameters, entropy: &dyn EntropySource) {
         let put_varint = |buf: &mut Self, tag: u8, varint: VarInt| {
             if varint.into_inner() > 0 {
                 buf.put_u8(tag);
@@ -148,6 +170,21 @@ impl<T: BufMut> WriteParameters for T {
         put_connection_id(self, 0x0f, &params.initial_source_connection_id);
         put_connection_id(self, 0x10, &params.retry_source_connection_id);
         put_varint(self, 0x20, params.max_datagram_frame_size);
+        if let Some(min_ack_delay) = params.min_ack_delay {
+            // 参数id超过了u8，需要用varint编码
+            self.put_varint(&VarInt::from_u64(MIN_ACK_DELAY_ID).unwrap());
+            self.put_varint(&unsafe {
+                VarInt::from_u64_unchecked(min_ack_delay.encoding_size() as u64)
+            });
+            self.put_varint(&min_ack_delay);
+        }
+        if params.enable_multipath {
+            self.put_varint(&VarInt::from_u64(EXPERIMENTAL_MULTIPATH_ID).unwrap());
+            self.put_u8(0);
+        }
+        if params.grease {
+            put_grease_parameter(self, entropy);
+        }
         // if params.grease_quic_bit {
         //     self.put_varint(&VarInt::from_u32(0x2ab2));
         //     self.put_u8(0);
@@ -166,6 +203,24 @@ impl<T: BufMut> WriteParameters for T {
     }
 }
 
+/// Write a reserved transport parameter, whose id is of the form `31 * N + 27`, with random
+/// content drawn from the `entropy`.
+///
+/// See [reserved transport parameters](https://www.rfc-editor.org/rfc/rfc9000.html#name-reserved-transport-paramete)
+/// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
+fn put_grease_parameter(buf: &mut impl BufMut, entropy: &dyn EntropySource) {
+    // 前4字节为N，取u32范围内的随机值，参数id不会超过varint的范围；第5字节决定内容的长度
+    let mut random = [0u8; 5];
+    entropy.fill_bytes(&mut random);
+    let id = 31 * u32::from_be_bytes([random[0], random[1], random[2], random[3]]) as u64 + 27;
+    let mut value = [0u8; 16];
+    let len = random[4] as usize % (value.len() + 1);
+    entropy.fill_bytes(&mut value[..len]);
+    buf.put_varint(&VarInt::from_u64(id).unwrap());
+    buf.put_varint(&VarInt::try_from(len).unwrap());
+    buf.put_slice(&value[..len]);
+}
+
 pub fn be_preferred_address(input: &[u8]) -> nom::IResult<&[u8], super::PreferredAddress> {
     use nom::bytes::streaming::take;
 
@@ -0,0 +1,304 @@
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::sid::Role;
+
+/// The type of a packet in the qlog events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketType {
+    Initial,
+    Handshake,
+    ZeroRtt,
+    OneRtt,
+}
+
+impl PacketType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            PacketType::Initial => "initial",
+            PacketType::Handshake => "handshake",
+            PacketType::ZeroRtt => "0RTT",
+            PacketType::OneRtt => "1RTT",
+        }
+    }
+}
+
+/// The data of the `transport:packet_sent` and `transport:packet_received` events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketEvent {
+    pub packet_type: PacketType,
+    pub packet_number: u64,
+    /// The size of the packet in bytes, including the header and the AEAD tag.
+    pub length: usize,
+}
+
+/// The data of the `recovery:metrics_updated` event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetricsUpdated {
+    pub min_rtt: Duration,
+    pub smoothed_rtt: Duration,
+    pub latest_rtt: Duration,
+    pub rtt_variance: Duration,
+    pub congestion_window: u64,
+}
+
+/// The data of the `recovery:packet_lost` event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketLost {
+    pub packet_type: PacketType,
+    pub packet_number: u64,
+}
+
+/// The congestion state in the `recovery:congestion_state_updated` event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CongestionState {
+    SlowStart,
+    CongestionAvoidance,
+    ApplicationLimited,
+    Recovery,
+}
+
+impl CongestionState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CongestionState::SlowStart => "slow_start",
+            CongestionState::CongestionAvoidance => "congestion_avoidance",
+            CongestionState::ApplicationLimited => "application_limited",
+            CongestionState::Recovery => "recovery",
+        }
+    }
+}
+
+/// The trigger of the `recovery:congestion_state_updated` event, absent for packet loss.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CongestionTrigger {
+    PersistentCongestion,
+    Ecn,
+}
+
+impl CongestionTrigger {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CongestionTrigger::PersistentCongestion => "persistent_congestion",
+            CongestionTrigger::Ecn => "ECN",
+        }
+    }
+}
+
+/// The data of the `recovery:congestion_state_updated` event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CongestionStateUpdated {
+    pub new: CongestionState,
+    pub trigger: Option<CongestionTrigger>,
+}
+
+/// A qlog event, see [`QlogSink`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QlogEvent {
+    PacketSent(PacketEvent),
+    PacketReceived(PacketEvent),
+    MetricsUpdated(MetricsUpdated),
+    PacketLost(PacketLost),
+    CongestionStateUpdated(CongestionStateUpdated),
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+impl QlogEvent {
+    /// Returns the name of the event, in the form of `category:event`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            QlogEvent::PacketSent(_) => "transport:packet_sent",
+            QlogEvent::PacketReceived(_) => "transport:packet_received",
+            QlogEvent::MetricsUpdated(_) => "recovery:metrics_updated",
+            QlogEvent::PacketLost(_) => "recovery:packet_lost",
+            QlogEvent::CongestionStateUpdated(_) => "recovery:congestion_state_updated",
+        }
+    }
+
+    /// Returns the `data` field of the event, serialized as a JSON object.
+    pub fn data_json(&self) -> String {
+        let mut json = String::new();
+        // 写入String不会失败
+        _ = match self {
+            QlogEvent::PacketSent(packet) | QlogEvent::PacketReceived(packet) => write!(
+                json,
+                r#"{{"header":{{"packet_type":"{}","packet_number":{}}},"raw":{{"length":{}}}}}"#,
+                packet.packet_type.as_str(),
+                packet.packet_number,
+                packet.length
+            ),
+            QlogEvent::MetricsUpdated(metrics) => write!(
+                json,
+                r#"{{"min_rtt":{:.3},"smoothed_rtt":{:.3},"latest_rtt":{:.3},"rtt_variance":{:.3},"congestion_window":{}}}"#,
+                millis(metrics.min_rtt),
+                millis(metrics.smoothed_rtt),
+                millis(metrics.latest_rtt),
+                millis(metrics.rtt_variance),
+                metrics.congestion_window
+            ),
+            QlogEvent::PacketLost(lost) => write!(
+                json,
+                r#"{{"header":{{"packet_type":"{}","packet_number":{}}}}}"#,
+                lost.packet_type.as_str(),
+                lost.packet_number
+            ),
+            QlogEvent::CongestionStateUpdated(updated) => match updated.trigger {
+                Some(trigger) => write!(
+                    json,
+                    r#"{{"new":"{}","trigger":"{}"}}"#,
+                    updated.new.as_str(),
+                    trigger.as_str()
+                ),
+                None => write!(json, r#"{{"new":"{}"}}"#, updated.new.as_str()),
+            },
+        };
+        json
+    }
+}
+
+/// The sink of the [qlog](https://datatracker.ietf.org/doc/html/draft-ietf-quic-qlog-main-schema)
+/// events of a connection, for debugging.
+///
+/// The events are emitted by the sending task, the packet dispatching of each space, the loss
+/// detection and the congestion controller. No event is generated if no sink is attached.
+///
+/// Only [`QlogSink::log`] is required, the other methods are shortcuts for each kind of event.
+/// [`JsonSeqSink`] writes the events in the JSON-SEQ format.
+pub trait QlogSink: Send + Sync {
+    /// Record an event.
+    fn log(&self, event: QlogEvent);
+
+    /// Record a `transport:packet_sent` event.
+    fn packet_sent(&self, packet: PacketEvent) {
+        self.log(QlogEvent::PacketSent(packet));
+    }
+
+    /// Record a `transport:packet_received` event.
+    fn packet_received(&self, packet: PacketEvent) {
+        self.log(QlogEvent::PacketReceived(packet));
+    }
+
+    /// Record a `recovery:metrics_updated` event.
+    fn metrics_updated(&self, metrics: MetricsUpdated) {
+        self.log(QlogEvent::MetricsUpdated(metrics));
+    }
+
+    /// Record a `recovery:packet_lost` event.
+    fn packet_lost(&self, lost: PacketLost) {
+        self.log(QlogEvent::PacketLost(lost));
+    }
+
+    /// Record a `recovery:congestion_state_updated` event.
+    fn congestion_state_updated(&self, updated: CongestionStateUpdated) {
+        self.log(QlogEvent::CongestionStateUpdated(updated));
+    }
+}
+
+/// A [`QlogSink`] writes the events into `W`, in the qlog JSON-SEQ format.
+///
+/// A header record describing the trace is written first, then each event is a record, which
+/// starts with the record separator (0x1E) and ends with a line feed. The `time` of each event
+/// is the milliseconds elapsed since the sink was created, which is the `reference_time`.
+///
+/// The errors of writing are ignored, the sink stops writing after the first error.
+pub struct JsonSeqSink<W> {
+    writer: Mutex<Option<W>>,
+    reference_time: Instant,
+}
+
+const RECORD_SEPARATOR: u8 = 0x1e;
+
+impl<W: Write + Send> JsonSeqSink<W> {
+    /// Create a sink for the trace of the endpoint of the `role`, the header record is written
+    /// immediately.
+    pub fn new(mut writer: W, role: Role) -> io::Result<Self> {
+        let reference_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let vantage_point = match role {
+            Role::Client => "client",
+            Role::Server => "server",
+        };
+        writer.write_all(&[RECORD_SEPARATOR])?;
+        writeln!(
+            writer,
+            r#"{{"qlog_version":"0.3","qlog_format":"JSON-SEQ","trace":{{"common_fields":{{"reference_time":{:.3},"time_format":"relative"}},"vantage_point":{{"type":"{}"}}}}}}"#,
+            millis(reference_time),
+            vantage_point
+        )?;
+        Ok(Self {
+            writer: Mutex::new(Some(writer)),
+            reference_time: Instant::now(),
+        })
+    }
+
+    /// Stop writing, and return the writer.
+    pub fn into_inner(self) -> Option<W> {
+        self.writer.into_inner().unwrap()
+    }
+}
+
+impl<W: Write + Send> QlogSink for JsonSeqSink<W> {
+    fn log(&self, event: QlogEvent) {
+        let time = millis(self.reference_time.elapsed());
+        let mut guard = self.writer.lock().unwrap();
+        let Some(writer) = guard.as_mut() else {
+            return;
+        };
+        let result = writer.write_all(&[RECORD_SEPARATOR]).and_then(|_| {
+            writeln!(
+                writer,
+                r#"{{"time":{time:.3},"name":"{}","data":{}}}"#,
+                event.name(),
+                event.data_json()
+            )
+        });
+        if result.is_err() {
+            guard.take();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_data_json() {
+        let sent = QlogEvent::PacketSent(PacketEvent {
+            packet_type: PacketType::Initial,
+            packet_number: 0,
+            length: 1200,
+        });
+        assert_eq!(sent.name(), "transport:packet_sent");
+        assert_eq!(
+            sent.data_json(),
+            r#"{"header":{"packet_type":"initial","packet_number":0},"raw":{"length":1200}}"#
+        );
+
+        let metrics = QlogEvent::MetricsUpdated(MetricsUpdated {
+            min_rtt: Duration::from_micros(10_500),
+            smoothed_rtt: Duration::from_millis(12),
+            latest_rtt: Duration::from_millis(11),
+            rtt_variance: Duration::from_millis(3),
+            congestion_window: 14720,
+        });
+        assert_eq!(
+            metrics.data_json(),
+            r#"{"min_rtt":10.500,"smoothed_rtt":12.000,"latest_rtt":11.000,"rtt_variance":3.000,"congestion_window":14720}"#
+        );
+
+        let updated = QlogEvent::CongestionStateUpdated(CongestionStateUpdated {
+            new: CongestionState::Recovery,
+            trigger: Some(CongestionTrigger::Ecn),
+        });
+        assert_eq!(updated.data_json(), r#"{"new":"recovery","trigger":"ECN"}"#);
+    }
+}
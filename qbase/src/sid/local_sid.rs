@@ -13,11 +13,11 @@ use crate::{
 /// Local stream IDs management.
 #[derive(Debug)]
 struct LocalStreamIds<BLOCKED> {
-    role: Role,                 // Our role
-    max: [u64; 2],              // The maximum stream ID we can create
-    unallocated: [u64; 2],      // The stream ID that we have not used
-    wakers: [Option<Waker>; 2], // Used for waiting for the MaxStream frame notification from peer when we have exhausted the creation of stream IDs
-    blocked: BLOCKED,           // The StreamsBlocked frames that will be sent to peer
+    role: Role,              // Our role
+    max: [u64; 2],           // The maximum stream ID we can create
+    unallocated: [u64; 2],   // The stream ID that we have not used
+    wakers: [Vec<Waker>; 2], // Used for waiting for the MaxStream frame notification from peer when we have exhausted the creation of stream IDs
+    blocked: BLOCKED,        // The StreamsBlocked frames that will be sent to peer
 }
 
 impl<BLOCKED> LocalStreamIds<BLOCKED>
@@ -31,7 +31,7 @@ where
             role,
             max: [max_bi_streams, max_uni_streams],
             unallocated: [0, 0],
-            wakers: [None, None],
+            wakers: [Vec::new(), Vec::new()],
             blocked,
         }
     }
@@ -53,7 +53,8 @@ where
         // RFC9000: MAX_STREAMS frames that do not increase the stream limit MUST be ignored.
         if *max_streams < val {
             *max_streams = val;
-            if let Some(waker) = self.wakers[dir as usize].take() {
+            // All the queued openers compete for the new stream IDs
+            for waker in self.wakers[dir as usize].drain(..) {
                 waker.wake();
             }
         }
@@ -69,15 +70,19 @@ where
             *cur += 1;
             Poll::Ready(Some(StreamId::new(self.role, dir, id)))
         } else {
-            assert!(self.wakers[idx].is_none());
-            // waiting for MAX_STREAMS frame from peer
-            self.wakers[idx] = Some(cx.waker().clone());
-            // if Poll::Pending is returned, connection can send a STREAMS_BLOCKED frame to peer
-            self.blocked.send_frame([StreamsBlockedFrame::with(
-                dir,
-                VarInt::from_u64(self.max[idx])
-                    .expect("max_streams limit must be less than VARINT_MAX"),
-            )]);
+            let wakers = &mut self.wakers[idx];
+            // Only the first blocked opener sends a STREAMS_BLOCKED frame to peer,
+            // the others just wait for the MAX_STREAMS frame from peer.
+            if wakers.is_empty() {
+                self.blocked.send_frame([StreamsBlockedFrame::with(
+                    dir,
+                    VarInt::from_u64(self.max[idx])
+                        .expect("max_streams limit must be less than VARINT_MAX"),
+                )]);
+            }
+            if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                wakers.push(cx.waker().clone());
+            }
             Poll::Pending
         }
     }
@@ -142,6 +147,8 @@ where
     ///
     /// Return Pending when the stream IDs in the `dir` direction are exhausted,
     /// until receiving the [`MaxStreamsFrame`](`crate::frame::MaxStreamsFrame`) from peer.
+    /// Multiple openers can wait at the same time, they are all woken up once the limit
+    /// is increased, and only one [`StreamsBlockedFrame`] is sent for each blocked limit.
     ///
     /// Return None if the stream IDs in the `dir` direction finally exceed 2^60,
     /// but it is very very hard to happen.
@@ -197,15 +204,15 @@ mod tests {
             Poll::Ready(Some(StreamId(0)))
         );
         assert_eq!(local.poll_alloc_sid(&mut cx, Dir::Bi), Poll::Pending);
-        assert!(local.0.lock().unwrap().wakers[0].is_some());
+        assert!(!local.0.lock().unwrap().wakers[0].is_empty());
         local.recv_max_streams_frame(&MaxStreamsFrame::Bi(VarInt::from_u32(1)));
-        let _ = local.0.lock().unwrap().wakers[0].take();
+        assert!(local.0.lock().unwrap().wakers[0].is_empty());
         assert_eq!(
             local.poll_alloc_sid(&mut cx, Dir::Bi),
             Poll::Ready(Some(StreamId(4)))
         );
         assert_eq!(local.poll_alloc_sid(&mut cx, Dir::Bi), Poll::Pending);
-        assert!(local.0.lock().unwrap().wakers[0].is_some());
+        assert!(!local.0.lock().unwrap().wakers[0].is_empty());
 
         local.recv_max_streams_frame(&MaxStreamsFrame::Uni(VarInt::from_u32(2)));
         assert_eq!(
@@ -221,6 +228,56 @@ mod tests {
             Poll::Ready(Some(StreamId(10)))
         );
         assert_eq!(local.poll_alloc_sid(&mut cx, Dir::Uni), Poll::Pending);
-        assert!(local.0.lock().unwrap().wakers[1].is_some());
+        assert!(!local.0.lock().unwrap().wakers[1].is_empty());
+    }
+
+    #[test]
+    fn test_queued_openers() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        use futures::task::{waker, ArcWake};
+
+        #[derive(Default)]
+        struct Woken(AtomicBool);
+
+        impl ArcWake for Woken {
+            fn wake_by_ref(arc_self: &Arc<Self>) {
+                arc_self.0.store(true, Ordering::Release);
+            }
+        }
+
+        let blocked = StreamsBlockedFrameTx::default();
+        let local = ArcLocalStreamIds::new(Role::Server, 0, 0, blocked.clone());
+        let (first, second) = (Arc::new(Woken::default()), Arc::new(Woken::default()));
+        let (first_waker, second_waker) = (waker(first.clone()), waker(second.clone()));
+        let mut first_cx = Context::from_waker(&first_waker);
+        let mut second_cx = Context::from_waker(&second_waker);
+        assert_eq!(
+            local.poll_alloc_sid(&mut first_cx, Dir::Uni),
+            Poll::Ready(Some(StreamId(3)))
+        );
+
+        // Two openers are blocked at the same time, only one STREAMS_BLOCKED frame is sent
+        assert_eq!(local.poll_alloc_sid(&mut first_cx, Dir::Uni), Poll::Pending);
+        assert_eq!(
+            local.poll_alloc_sid(&mut second_cx, Dir::Uni),
+            Poll::Pending
+        );
+        assert_eq!(
+            local.poll_alloc_sid(&mut second_cx, Dir::Uni),
+            Poll::Pending
+        );
+        assert_eq!(local.0.lock().unwrap().wakers[1].len(), 2);
+        assert_eq!(blocked.len(), 1);
+
+        local.recv_max_streams_frame(&MaxStreamsFrame::Uni(VarInt::from_u32(1)));
+        assert!(first.0.load(Ordering::Acquire) && second.0.load(Ordering::Acquire));
+        assert_eq!(
+            local.poll_alloc_sid(&mut second_cx, Dir::Uni),
+            Poll::Ready(Some(StreamId(7)))
+        );
+        // The other opener is blocked again at the new limit
+        assert_eq!(local.poll_alloc_sid(&mut first_cx, Dir::Uni), Poll::Pending);
+        assert_eq!(blocked.len(), 2);
     }
 }
@@ -0,0 +1,111 @@
+use std::{
+    fmt::Debug,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Poll, Waker},
+    time::{Duration, Instant},
+};
+
+/// The source of time of a connection.
+///
+/// All the timers of a connection, such as the idle timer, the loss detection timer and the
+/// path validation timer, read the current time and wait for their deadlines through the clock,
+/// rather than the system clock directly.
+///
+/// The clock backed by the async runtime is used by default, a [`MockClock`] can be injected
+/// instead to drive the timers deterministically in tests.
+pub trait Clock: Send + Sync + Debug {
+    /// Returns the current time of the clock.
+    fn now(&self) -> Instant;
+
+    /// Returns a future that resolves once the clock reaches the `deadline`.
+    fn sleep_until(&self, deadline: Instant) -> Pin<Box<dyn Future<Output = ()> + Send>>;
+}
+
+/// The shared version of [`Clock`].
+pub type ArcClock = Arc<dyn Clock>;
+
+#[derive(Debug)]
+struct MockTime {
+    now: Instant,
+    wakers: Vec<Waker>,
+}
+
+/// A clock that never goes forward by itself, the time only advances when
+/// [`MockClock::advance`] is called.
+///
+/// The futures returned by [`Clock::sleep_until`] resolve once the clock is advanced beyond
+/// their deadlines, so the timers can be tested without real waiting.
+#[derive(Debug, Clone)]
+pub struct MockClock(Arc<Mutex<MockTime>>);
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockClock {
+    /// Create a new mock clock, starting at the current time of the system.
+    pub fn new() -> Self {
+        Self(Arc::new(Mutex::new(MockTime {
+            now: Instant::now(),
+            wakers: Vec::new(),
+        })))
+    }
+
+    /// Move the clock forward by `duration`, wake up the sleepers whose deadline is reached.
+    pub fn advance(&self, duration: Duration) {
+        let mut time = self.0.lock().unwrap();
+        time.now += duration;
+        // 未到期的睡眠者被唤醒后会重新注册
+        for waker in time.wakers.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.0.lock().unwrap().now
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        let time = self.0.clone();
+        Box::pin(futures::future::poll_fn(move |cx| {
+            let mut time = time.lock().unwrap();
+            if time.now >= deadline {
+                return Poll::Ready(());
+            }
+            if !time.wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                time.wakers.push(cx.waker().clone());
+            }
+            Poll::Pending
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_mock_clock() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let deadline = start + Duration::from_secs(10);
+        let sleep = tokio::spawn(clock.sleep_until(deadline));
+
+        clock.advance(Duration::from_secs(5));
+        tokio::task::yield_now().await;
+        assert!(!sleep.is_finished());
+        assert_eq!(clock.now(), start + Duration::from_secs(5));
+
+        clock.advance(Duration::from_secs(5));
+        sleep.await.unwrap();
+        assert_eq!(clock.now(), deadline);
+        // 已经到期的截止时间立即返回
+        clock.sleep_until(start).await;
+    }
+}
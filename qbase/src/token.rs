@@ -11,6 +11,13 @@ use crate::{
 
 pub const RESET_TOKEN_SIZE: usize = 16;
 
+/// The smallest possible size of a stateless reset packet, 5 bytes of unpredictable bits
+/// followed by the 16 bytes stateless reset token.
+///
+/// See [stateless reset](https://www.rfc-editor.org/rfc/rfc9000.html#name-stateless-reset)
+/// of [QUIC](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
+pub const MIN_STATELESS_RESET_SIZE: usize = 5 + RESET_TOKEN_SIZE;
+
 #[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
 pub struct ResetToken([u8; RESET_TOKEN_SIZE]);
 
@@ -28,6 +35,26 @@ impl ResetToken {
     pub fn encoding_size(&self) -> usize {
         RESET_TOKEN_SIZE
     }
+
+    /// Get the potential stateless reset token from a received datagram, which is the last 16
+    /// bytes of the datagram.
+    ///
+    /// Return [`None`] if the datagram is too short to be a stateless reset.
+    pub fn from_datagram_tail(datagram: &[u8]) -> Option<Self> {
+        if datagram.len() < MIN_STATELESS_RESET_SIZE {
+            return None;
+        }
+        Some(Self::new(&datagram[datagram.len() - RESET_TOKEN_SIZE..]))
+    }
+
+    /// Compare two tokens in constant time, to avoid leaking the token by timing.
+    pub fn ct_eq(&self, other: &Self) -> bool {
+        self.0
+            .iter()
+            .zip(other.0.iter())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+    }
 }
 
 pub fn be_reset_token(input: &[u8]) -> IResult<&[u8], ResetToken> {
@@ -113,6 +140,11 @@ impl ReceiveFrame<NewTokenFrame> for ArcTokenRegistry {
 
     fn recv_frame(&self, frame: &NewTokenFrame) -> Result<Self::Output, crate::error::Error> {
         match self.deref() {
+            TokenRegistry::Client(_) if frame.token.is_empty() => Err(Error::new(
+                ErrorKind::ProtocolViolation,
+                frame.frame_type(),
+                "Client received NewTokenFrame with empty token",
+            )),
             TokenRegistry::Client((server_name, client)) => {
                 client.sink(server_name, frame.token.clone());
                 Ok(())
@@ -184,6 +216,27 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_recv_new_token_frame() {
+        use super::*;
+
+        let client = ArcTokenRegistry::default_sink("localhost".to_string());
+        let frame = NewTokenFrame { token: vec![1, 2] };
+        assert_eq!(client.recv_frame(&frame), Ok(()));
+
+        let empty = NewTokenFrame { token: vec![] };
+        assert_eq!(
+            client.recv_frame(&empty).map_err(|e| e.kind()),
+            Err(ErrorKind::ProtocolViolation)
+        );
+
+        let server = ArcTokenRegistry::default_provider();
+        assert_eq!(
+            server.recv_frame(&frame).map_err(|e| e.kind()),
+            Err(ErrorKind::ProtocolViolation)
+        );
+    }
+
     #[test]
     fn test_write_reset_token() {
         use super::WriteResetToken;
@@ -4,9 +4,10 @@ use std::{
 };
 
 use crate::{
-    congestion::{AckedPkt, Algorithm, SentPkt, MSS},
+    congestion::{AckedPkt, SentPkt, MSS},
     delivery_rate::Rate,
     min_max::MinMax,
+    CongestionConfig, CongestionController, PacingRate,
 };
 
 mod model;
@@ -138,16 +139,36 @@ pub(crate) struct Bbr {
     // one ack-eliciting or PADDING frame and have not been acknowledged or
     // declared lost. The size does not include IP or UDP overhead.
     pub bytes_in_flight: u64,
+    // The congestion window at the beginning, in bytes.
+    initial_cwnd: u64,
+    // The congestion window never shrinks below it, in bytes.
+    min_cwnd: u64,
+    // The congestion window never grows beyond it, in bytes.
+    max_cwnd: u64,
+    // The RTT assumed before the first RTT sample is taken.
+    initial_rtt: Duration,
+}
+
+impl Default for Bbr {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Bbr {
     pub fn new() -> Self {
+        Self::with_config(&CongestionConfig::default())
+    }
+
+    pub fn with_config(config: &CongestionConfig) -> Self {
         let now = Instant::now();
+        let (initial_cwnd, min_cwnd, max_cwnd) =
+            config.windows(INITIAL_CWND, (MINIMUM_WINDOW_PACKETS * MSS) as u64);
         let mut bbr = Bbr {
             state: BbrStateMachine::Startup,
             pacing_rate: 0,
             send_quantum: 0,
-            cwnd: INITIAL_CWND,
+            cwnd: initial_cwnd,
             btlbw: 0,
             btlbwfilter: MinMax::default(),
             delivery_rate: Rate::default(),
@@ -180,14 +201,18 @@ impl Bbr {
             packet_delivered: 0,
             bytes_in_flight: 0,
             bytes_lost_in_total: 0,
+            initial_cwnd,
+            min_cwnd,
+            max_cwnd,
+            initial_rtt: config.initial_rtt(),
         };
         bbr.on_connection_init();
         bbr
     }
 }
 
-impl Algorithm for Bbr {
-    fn on_sent(&mut self, sent: &mut SentPkt, _: usize, _: Instant) {
+impl CongestionController for Bbr {
+    fn on_packet_sent(&mut self, sent: &mut SentPkt, _: Instant) {
         self.delivery_rate.on_packet_sent(
             sent,
             self.bytes_in_flight as usize,
@@ -201,15 +226,15 @@ impl Algorithm for Bbr {
     //  todo: VecDeque 是否有必要
     fn on_ack(&mut self, packets: VecDeque<AckedPkt>, now: Instant) {
         self.newly_acked_bytes = 0;
-        self.newly_lost_bytes = 0;
         self.packet_delivered = 0;
-        self.last_ack_packet_sent_time = now;
         self.prior_bytes_in_flight = self.bytes_in_flight;
         self.ack_time = now;
 
         for mut ack in packets {
             self.delivery_rate.update_rate_sample(&ack, now);
             self.bytes_in_flight = self.bytes_in_flight.saturating_sub(ack.size as u64);
+            self.newly_acked_bytes += ack.size as u64;
+            self.last_ack_packet_sent_time = ack.time_sent;
             self.packet_delivered = self
                 .packet_delivered
                 .max(self.delivery_rate.delivered() as u64);
@@ -230,19 +255,59 @@ impl Algorithm for Bbr {
         }
 
         self.update_control_parameters();
+        // 丢包已经在本次调整cwnd时考虑过了
+        self.newly_lost_bytes = 0;
+    }
+
+    fn on_app_limited(&mut self) {
+        self.delivery_rate.update_app_limited(true);
+    }
+
+    // 4.2.3.4 Modulating cwnd in Loss Recovery
+    fn on_congestion_event(&mut self, lost: &SentPkt, now: Instant) {
+        self.bytes_in_flight = self.bytes_in_flight.saturating_sub(lost.size as u64);
+        self.newly_lost_bytes += lost.size as u64;
+        self.bytes_lost_in_total += lost.size as u64;
+
+        // 恢复期间发出的包丢失，不再重复进入恢复
+        if self
+            .recovery_epoch_start
+            .is_some_and(|start| lost.time_sent <= start)
+        {
+            return;
+        }
+
+        // enter recovery, packet conservation for one round trip
+        self.save_cwnd();
+        self.in_recovery = true;
+        self.recovery_epoch_start = Some(now);
+        self.packet_conservation = true;
+        self.next_round_delivered = self.delivery_rate.delivered();
+        self.cwnd = (self.bytes_in_flight + self.newly_acked_bytes.max(MSS as u64))
+            .max(self.min_pipe_cwnd())
+            .clamp(self.min_cwnd, self.max_cwnd);
+    }
+
+    // 与RTO的处理相同，保存当前cwnd以便恢复，并降到最小窗口
+    fn on_persistent_congestion(&mut self) {
+        self.save_cwnd();
+        self.cwnd = self.min_cwnd;
+    }
+
+    fn on_packet_discarded(&mut self, discarded: &SentPkt) {
+        self.bytes_in_flight = self.bytes_in_flight.saturating_sub(discarded.size as u64);
     }
 
-    fn on_congestion_event(&mut self, _: &SentPkt, _: Instant) {
-        // todo: enter_recovery
-        // update newly lost bytes, set BBR.packet_conservation = true
+    fn can_send(&self, _: Instant) -> usize {
+        self.cwnd.saturating_sub(self.bytes_in_flight) as usize
     }
 
     fn cwnd(&self) -> u64 {
         self.cwnd
     }
 
-    fn pacing_rate(&self) -> Option<u64> {
-        Some(self.pacing_rate)
+    fn pacing_rate(&self) -> Option<PacingRate> {
+        Some(PacingRate::from_bytes_per_sec(self.pacing_rate))
     }
 }
 
@@ -283,8 +348,9 @@ mod tests {
 
     use crate::{
         bbr::{BbrStateMachine, HIGH_GAIN, INITIAL_CWND, MSS},
-        congestion::{AckedPkt, Algorithm, SentPkt},
+        congestion::{AckedPkt, SentPkt},
         rtt::INITIAL_RTT,
+        CongestionController,
     };
 
     #[test]
@@ -312,7 +378,7 @@ mod tests {
                 size: MSS,
                 ..Default::default()
             };
-            bbr.on_sent(&mut sent, MSS, now);
+            bbr.on_packet_sent(&mut sent, now);
         }
         assert_eq!(bbr.bytes_in_flight, 10 * MSS as u64);
     }
@@ -373,7 +439,7 @@ mod tests {
                 time_sent: start_time,
                 ..Default::default()
             };
-            bbr.on_sent(&mut sent, 0, start_time);
+            bbr.on_packet_sent(&mut sent, start_time);
 
             let mut ack: AckedPkt = sent.into();
             ack.rtt = rtt;
@@ -1,5 +1,3 @@
-use std::time::Instant;
-
 // 4.1.  Maintaining the Network Path Model
 // This model includes two estimated parameters: self.BtlBw, and self.RTprop.
 use super::{Bbr, RTPROP_FILTER_LEN};
@@ -43,7 +41,7 @@ impl Bbr {
     pub(super) fn update_rtprop(&mut self) {
         let sample_rtt = self.delivery_rate.sample_rtt();
 
-        let now = Instant::now();
+        let now = self.ack_time;
         self.is_rtprop_expired =
             now.saturating_duration_since(self.rtprop_stamp) > RTPROP_FILTER_LEN;
 
@@ -3,7 +3,6 @@ use std::time::Instant;
 use rand::Rng;
 
 use super::{Bbr, BbrStateMachine, HIGH_GAIN, PROBE_RTT_DURATION};
-use crate::rtt::INITIAL_RTT;
 
 // BBRGainCycleLen: the number of phases in the BBR ProbeBW gain cycle: 8.
 const GAIN_CYCLE_LEN: usize = 8;
@@ -13,7 +12,7 @@ const PACING_GAIN_CYCLE: [f64; GAIN_CYCLE_LEN] = [1.25, 0.75, 1.0, 1.0, 1.0, 1.0
 
 impl Bbr {
     pub(super) fn init(&mut self) {
-        self.rtprop = INITIAL_RTT;
+        self.rtprop = self.initial_rtt;
         self.rtprop_stamp = Instant::now();
         self.probe_rtt_done_stamp = None;
         self.probe_rtt_round_done = false;
@@ -163,7 +162,7 @@ impl Bbr {
         // C.app_limited = (BW.delivered + packets_in_flight) ? : 1
         self.delivery_rate.update_app_limited(true);
 
-        let now = Instant::now();
+        let now = self.ack_time;
         if let Some(probe_rtt_done_stamp) = self.probe_rtt_done_stamp {
             if self.is_round_start {
                 self.probe_rtt_round_done = true;
@@ -196,7 +195,10 @@ mod tests {
 
     use std::time::{Duration, Instant};
 
-    use crate::bbr::{tests::simulate_round_trip, BbrStateMachine, HIGH_GAIN, INITIAL_CWND, MSS};
+    use crate::bbr::{
+        tests::simulate_round_trip, BbrStateMachine, HIGH_GAIN, INITIAL_CWND, MSS,
+        RTPROP_FILTER_LEN,
+    };
 
     #[test]
     fn test_bbr_init() {
@@ -245,6 +247,42 @@ mod tests {
         assert!(bbr.is_filled_pipe);
     }
 
+    #[test]
+    fn test_bbr_exit_startup_on_bandwidth_plateau() {
+        let mut bbr = super::Bbr::new();
+        let mut now = Instant::now();
+        let rtt = Duration::from_millis(100);
+
+        simulate_round_trip(&mut bbr, now, rtt, 0, 10, MSS);
+        assert_eq!(bbr.state, BbrStateMachine::Startup);
+
+        // the delivery rate stops growing, the pipe is filled after 3 rounds without growth
+        for round in 1..5 {
+            now += rtt;
+            simulate_round_trip(&mut bbr, now, rtt, round * 10, round * 10 + 10, MSS);
+        }
+        assert!(bbr.is_filled_pipe);
+        assert_ne!(bbr.state, BbrStateMachine::Startup);
+    }
+
+    #[test]
+    fn test_bbr_enter_probe_rtt() {
+        let mut bbr = super::Bbr::new();
+        let mut now = Instant::now();
+        let rtt = Duration::from_millis(100);
+
+        simulate_round_trip(&mut bbr, now, rtt, 0, 10, MSS);
+        assert_eq!(bbr.rtprop, rtt);
+        assert_ne!(bbr.state, BbrStateMachine::ProbeRTT);
+
+        // no lower rtt is sampled within 10s, the rtprop expires
+        now += RTPROP_FILTER_LEN + Duration::from_secs(1);
+        simulate_round_trip(&mut bbr, now, rtt * 2, 10, 20, MSS);
+        assert_eq!(bbr.state, BbrStateMachine::ProbeRTT);
+        assert!(bbr.cwnd <= bbr.min_pipe_cwnd());
+        assert_eq!(bbr.pacing_gain, 1.0);
+    }
+
     #[test]
     fn test_bbr_check_drain() {
         let mut bbr = super::Bbr::new();
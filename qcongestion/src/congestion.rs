@@ -1,45 +1,152 @@
 use std::{
     cmp::Ordering,
     collections::VecDeque,
-    sync::{Arc, Mutex},
+    ops::RangeInclusive,
+    sync::{
+        atomic::{self, AtomicUsize},
+        Arc, Mutex,
+    },
     task::{Context, Poll, Waker},
     time::{Duration, Instant},
 };
 
-use qbase::frame::{AckFrame, EcnCounts};
+use qbase::{
+    frame::{AckFrame, AckFrequencyFrame, EcnCounts},
+    packet::Ecn,
+    qlog::{
+        CongestionState, CongestionStateUpdated, CongestionTrigger, MetricsUpdated, PacketLost,
+        PacketType, QlogSink,
+    },
+    time::ArcClock,
+};
 use qrecovery::space::Epoch;
+use thiserror::Error;
 
 use crate::{
-    bbr::{self, INITIAL_CWND},
+    bbr,
+    ecn::{EcnState, EcnValidator},
     new_reno::NewReno,
     pacing::{self, Pacer},
-    rtt::{ArcRtt, INITIAL_RTT},
-    MayLoss, RetirePktRecord,
+    rtt::ArcRtt,
+    CongestionController, MayLoss, PacingRate, RetirePktRecord,
 };
 
-const K_GRANULARITY: Duration = Duration::from_millis(1);
-const K_PACKET_THRESHOLD: usize = 3;
+/// The packet threshold of the loss detection: an unacknowledged packet is declared lost once
+/// this many packets sent after it have been acknowledged, see
+/// [Section 6.1.1](https://www.rfc-editor.org/rfc/rfc9002.html#name-packet-threshold) of RFC 9002.
+pub const K_PACKET_THRESHOLD: usize = 3;
+/// The persistent congestion is established when the ack-eliciting packets lost in a row span
+/// longer than this multiple of the PTO, see
+/// [Section 7.6](https://www.rfc-editor.org/rfc/rfc9002.html#name-persistent-congestion) of RFC 9002.
+pub const K_PERSISTENT_CONGESTION_THRESHOLD: u32 = 3;
+// PTO 超时后，最多发送的探测包数量
+const MAX_PTO_PROBES: u8 = 2;
 const MAX_SENT_DELAY: Duration = Duration::from_millis(30);
 
 ///  default datagram size in bytes.
 pub const MSS: usize = 1200;
 
 /// The [`CongestionAlgorithm`] enum represents different congestion control algorithms that can be used.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum CongestionAlgorithm {
     Bbr,
+    #[default]
     NewReno,
 }
 
+impl CongestionAlgorithm {
+    /// Create a new [`CongestionController`] which implements the algorithm.
+    pub fn controller(&self) -> Box<dyn CongestionController> {
+        self.controller_with(&CongestionConfig::default())
+    }
+
+    /// Create a new [`CongestionController`] which implements the algorithm, with the windows
+    /// overridden by the `config`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `config` is invalid, read [`CongestionConfig::validate`].
+    pub fn controller_with(&self, config: &CongestionConfig) -> Box<dyn CongestionController> {
+        match self {
+            CongestionAlgorithm::Bbr => Box::new(bbr::Bbr::with_config(config)),
+            CongestionAlgorithm::NewReno => Box::new(NewReno::with_config(config)),
+        }
+    }
+}
+
+/// The overrides of the congestion control of a path, for testing or tuning.
+///
+/// The windows are in bytes. The defaults of the [`CongestionAlgorithm`] are used for the fields
+/// left unset.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CongestionConfig {
+    /// The initial congestion window, such as `10 * MSS` for IW10.
+    pub initial_window: Option<u64>,
+    /// The congestion window never shrinks below it, even on persistent congestion.
+    pub min_window: Option<u64>,
+    /// The congestion window never grows beyond it.
+    pub max_window: Option<u64>,
+    /// The RTT assumed before the first RTT sample is taken, which seeds the PTO, it's
+    /// [`INITIAL_RTT`](crate::INITIAL_RTT) by default.
+    pub initial_rtt: Option<Duration>,
+}
+
+/// The error of an invalid [`CongestionConfig`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum CongestionConfigError {
+    /// The `min_window` is larger than the `max_window`.
+    #[error("the min_window {min} exceeds the max_window {max}")]
+    InvertedWindows { min: u64, max: u64 },
+    /// The `initial_rtt` is zero, which can not seed the pacing rate and the PTO.
+    #[error("the initial_rtt must not be zero")]
+    ZeroInitialRtt,
+}
+
+impl CongestionConfig {
+    /// Check that the config can be applied to any [`CongestionAlgorithm`].
+    ///
+    /// The controllers created with an invalid config may panic, so the config given by the
+    /// user should be validated first.
+    pub fn validate(&self) -> Result<(), CongestionConfigError> {
+        if let (Some(min), Some(max)) = (self.min_window, self.max_window) {
+            if min > max {
+                return Err(CongestionConfigError::InvertedWindows { min, max });
+            }
+        }
+        if self.initial_rtt == Some(Duration::ZERO) {
+            return Err(CongestionConfigError::ZeroInitialRtt);
+        }
+        Ok(())
+    }
+
+    /// The RTT assumed before the first RTT sample is taken.
+    pub fn initial_rtt(&self) -> Duration {
+        self.initial_rtt.unwrap_or(crate::INITIAL_RTT)
+    }
+
+    // 返回(初始窗口, 最小窗口, 最大窗口)，初始窗口被限制在最小与最大窗口之间
+    pub(crate) fn windows(&self, initial: u64, min: u64) -> (u64, u64, u64) {
+        let min = self.min_window.unwrap_or(min);
+        let max = self.max_window.unwrap_or(u64::MAX);
+        assert!(min <= max, "min_window must not exceed max_window");
+        let initial = self.initial_window.unwrap_or(initial).clamp(min, max);
+        (initial, min, max)
+    }
+}
+
 /// Imple RFC 9002 Appendix A. Loss Recovery
 /// See [Appendix A](https://datatracker.ietf.org/doc/html/rfc9002#name-loss-recovery-pseudocode)
-pub struct CongestionController {
-    algorithm: Box<dyn Algorithm + Send>,
-    // The Round-Trip Time (RTT) estimator.
+pub struct LossRecovery {
+    algorithm: Box<dyn CongestionController>,
+    // The Round-Trip Time (RTT) estimator, shared with the path.
     rtt: ArcRtt,
     loss_timer: LossDetectionTimer,
     // The number of times a PTO has been sent without receiving an acknowledgment.
     // Use to pto backoff
     pto_count: u32,
+    // The number of probe packets to be sent in each epoch after the PTO timer expired.
+    pending_probes: [u8; Epoch::count()],
+    // The local max_ack_delay, used to decide when to send an AckFrame.
     max_ack_delay: Duration,
     // The time the most recent ack-eliciting packet was sent.
     time_of_last_ack_eliciting_packet: [Option<Instant>; Epoch::count()],
@@ -52,6 +159,10 @@ pub struct CongestionController {
     sent_packets: [VecDeque<SentPkt>; Epoch::count()],
     // pacer is used to control the burst rate
     pacer: pacing::Pacer,
+    // Whether the packets are paced, or sent in bursts.
+    pacing: bool,
+    // The size of a full packet, the send quota and the pacing are measured in it.
+    mtu: usize,
     // The time the last packet was sent.
     last_sent_time: Instant,
     // Records of received packets for each epoch.
@@ -66,28 +177,33 @@ pub struct CongestionController {
     has_handshake_keys: bool,
     // Whether the handshake is complete.
     is_handshake_done: bool,
+    // The ECN validation of the path.
+    ecn: EcnValidator,
+    // 未设置时不产生任何qlog事件
+    qlog: Option<Arc<dyn QlogSink>>,
+    // 计时器读取当前时间的来源，测试中可替换为模拟时钟
+    clock: ArcClock,
 }
 
-impl CongestionController {
+impl LossRecovery {
     // A.4. Initialization
     fn new(
-        algorithm: CongestionAlgorithm,
+        algorithm: Box<dyn CongestionController>,
+        rtt: ArcRtt,
         max_ack_delay: Duration,
         loss: [Box<dyn MayLoss>; 3],
         retire: [Box<dyn RetirePktRecord>; 3],
+        qlog: Option<Arc<dyn QlogSink>>,
+        clock: ArcClock,
     ) -> Self {
-        let algorithm: Box<dyn Algorithm> = match algorithm {
-            CongestionAlgorithm::Bbr => Box::new(bbr::Bbr::new()),
-            CongestionAlgorithm::NewReno => Box::new(NewReno::new()),
-        };
-
-        let now = Instant::now();
-        CongestionController {
+        let now = clock.now();
+        LossRecovery {
             algorithm,
-            rtt: ArcRtt::new(),
+            rtt,
             loss_timer: LossDetectionTimer::default(),
             max_ack_delay,
             pto_count: 0,
+            pending_probes: [0; Epoch::count()],
             time_of_last_ack_eliciting_packet: [None, None, None],
             largest_acked_packet: [None, None, None],
             loss_time: [None, None, None],
@@ -97,14 +213,43 @@ impl CongestionController {
                 RcvdRecords::new(Epoch::Handshake),
                 RcvdRecords::new(Epoch::Data),
             ],
-            pacer: Pacer::new(INITIAL_RTT, INITIAL_CWND, MSS, now, None),
+            pacer: Pacer::new(rtt.smoothed_rtt(), algorithm.cwnd(), MSS, now, None),
+            pacing: true,
+            mtu: MSS,
             last_sent_time: now,
             send_waker: None,
             loss_handlers: loss,
             retire_handlers: retire,
             has_handshake_keys: false,
             is_handshake_done: false,
+            ecn: EcnValidator::default(),
+            qlog,
+            clock,
+        }
+    }
+
+    // 未启用pacing时返回None，否则优先使用拥塞控制算法给出的速率，没有则由cwnd和srtt推算
+    fn pacing_rate(&self) -> Option<PacingRate> {
+        if !self.pacing {
+            return None;
         }
+        let rate = self.algorithm.pacing_rate().unwrap_or_else(|| {
+            PacingRate::from_window(self.algorithm.cwnd(), self.rtt.smoothed_rtt())
+        });
+        Some(rate)
+    }
+
+    // pacer的令牌不足一个包时需要等待的时间，未启用pacing则无需等待
+    fn pacing_delay(&self, now: Instant) -> Duration {
+        if !self.pacing {
+            return Duration::ZERO;
+        }
+        let rate = self
+            .algorithm
+            .pacing_rate()
+            .map(|rate| rate.bytes_per_sec());
+        let (srtt, cwnd) = (self.rtt.smoothed_rtt(), self.algorithm.cwnd());
+        self.pacer.delay(srtt, cwnd, self.mtu, now, rate)
     }
 
     // A.5. On Sending a Packet
@@ -118,11 +263,15 @@ impl CongestionController {
         now: Instant,
     ) {
         let mut sent = SentPkt::new(pn, sent_bytes, now);
+        sent.in_flight = in_flight;
+        sent.ecn_marked = self.ecn.on_packet_sent();
         if in_flight {
             if ack_eliciting {
                 self.time_of_last_ack_eliciting_packet[space] = Some(now);
+                // 探测包不标记任何包丢失，只要是可引起确认的包，都可以作为探测包
+                self.pending_probes[space] = self.pending_probes[space].saturating_sub(1);
             }
-            self.algorithm.on_sent(&mut sent, sent_bytes, now);
+            self.algorithm.on_packet_sent(&mut sent, now);
             self.set_loss_timer();
         }
 
@@ -151,26 +300,29 @@ impl CongestionController {
         self.largest_acked_packet[space] =
             Some(largest_acked.max(self.largest_acked_packet[space].unwrap_or(0)));
 
-        let (newly_acked_packets, latest_rtt) = self.get_newly_acked_packets(space, ack_frame);
-        if newly_acked_packets.is_empty() {
+        let (newly_acked_packets, latest_rtt, ecn_marked) =
+            self.get_newly_acked_packets(space, ack_frame, now);
+        if newly_acked_packets.is_empty() && latest_rtt.is_none() {
             return;
         }
 
-        let ack_delay = Duration::from_millis(ack_frame.delay.into());
+        let ack_delay = self.rtt.decode_ack_delay(ack_frame.delay.into_inner());
         if let Some(latest_rtt) = latest_rtt {
             self.rtt.update(latest_rtt, ack_delay);
         }
 
-        // Process ECN information if present.
-        if let Some(ecn) = ack_frame.ecn {
-            self.process_ecn(space, ecn)
-        }
+        // Process ECN information, the absence of it is also checked.
+        let largest_time_sent = newly_acked_packets.iter().map(|p| p.time_sent).max();
+        self.process_ecn(space, ack_frame.ecn, ecn_marked, largest_time_sent, now);
 
-        let lost_packets = self.remove_loss_packets(space, now);
+        let (lost_packets, persistent_congestion) = self.remove_loss_packets(space, now);
         if !lost_packets.is_empty() {
-            self.on_packets_lost(lost_packets.into_iter(), space);
+            self.on_packets_lost(lost_packets.into_iter(), space, persistent_congestion, now);
         }
         self.algorithm.on_ack(newly_acked_packets, now);
+        if latest_rtt.is_some() {
+            self.qlog_metrics_updated();
+        }
 
         if self.server_completed_address_validation() {
             self.pto_count = 0;
@@ -182,40 +334,99 @@ impl CongestionController {
         &mut self,
         epoch: Epoch,
         ack_frame: &AckFrame,
-    ) -> (VecDeque<AckedPkt>, Option<Duration>) {
+        now: Instant,
+    ) -> (VecDeque<AckedPkt>, Option<Duration>, usize) {
         let mut newly_acked_packets: VecDeque<AckedPkt> = VecDeque::new();
         let largest_acked: u64 = ack_frame.largest.into();
         let mut latest_rtt = None;
+        let mut ecn_marked = 0;
         for range in ack_frame.iter() {
             for pn in range {
-                let acked: Option<AckedPkt> = self.sent_packets[epoch]
+                let acked: Option<(AckedPkt, bool)> = self.sent_packets[epoch]
                     .binary_search_by_key(&pn, |p| p.pn)
                     .ok()
+                    .filter(|&idx| !self.sent_packets[epoch][idx].is_acked)
                     .map(|idx| {
                         self.rcvd_records[epoch].ack(pn, &self.retire_handlers);
-                        self.sent_packets[epoch][idx].is_acked = true;
-                        self.sent_packets[epoch][idx].clone().into()
+                        let sent = &mut self.sent_packets[epoch][idx];
+                        sent.is_acked = true;
+                        ecn_marked += sent.ecn_marked as usize;
+                        let mut acked: AckedPkt = sent.clone().into();
+                        acked.rtt = now.saturating_duration_since(sent.time_sent);
+                        (acked, sent.in_flight)
                     });
-                if let Some(ack) = acked {
+                if let Some((ack, in_flight)) = acked {
                     // largest is newly ackd, update latest_rtt
                     if pn == largest_acked {
                         latest_rtt = Some(ack.rtt);
                     }
-                    newly_acked_packets.push_back(ack);
+                    // 只有计入在途的包才反馈给拥塞控制算法
+                    if in_flight {
+                        newly_acked_packets.push_back(ack);
+                    }
                 }
             }
         }
         self.slide_sent_packets(epoch);
-        (newly_acked_packets, latest_rtt)
+        (newly_acked_packets, latest_rtt, ecn_marked)
     }
 
     // A.8. Setting the Loss Detection Timer
-    fn on_packets_lost(&mut self, packets: impl Iterator<Item = SentPkt>, epoch: Epoch) {
-        let now = Instant::now();
+    fn on_packets_lost(
+        &mut self,
+        packets: impl Iterator<Item = SentPkt>,
+        epoch: Epoch,
+        persistent_congestion: bool,
+        now: Instant,
+    ) {
+        let mut ecn_marked = 0;
+        let mut congested = false;
         for lost in packets {
-            self.algorithm.on_congestion_event(&lost, now);
+            ecn_marked += lost.ecn_marked as usize;
+            // 未计入在途的包，如PMTU探测包，其丢失并不意味着拥塞
+            if lost.in_flight {
+                self.algorithm.on_congestion_event(&lost, now);
+                congested = true;
+            }
+            if let Some(qlog) = &self.qlog {
+                qlog.packet_lost(PacketLost {
+                    packet_type: packet_type(epoch),
+                    packet_number: lost.pn,
+                });
+            }
             self.loss_handlers[epoch].may_loss(lost.pn);
         }
+        self.ecn.on_packets_lost(ecn_marked);
+        if persistent_congestion {
+            self.algorithm.on_persistent_congestion();
+        }
+        if congested {
+            self.qlog_congestion_state_updated(None);
+        }
+    }
+
+    fn qlog_metrics_updated(&self) {
+        if let Some(qlog) = &self.qlog {
+            let rtt = self.rtt.sample();
+            qlog.metrics_updated(MetricsUpdated {
+                min_rtt: rtt.min_rtt,
+                smoothed_rtt: rtt.smoothed_rtt,
+                latest_rtt: rtt.latest_rtt,
+                rtt_variance: rtt.rttvar,
+                congestion_window: self.algorithm.cwnd(),
+            });
+        }
+    }
+
+    // 拥塞控制算法进入恢复期，拥塞窗口随之减小
+    fn qlog_congestion_state_updated(&self, trigger: Option<CongestionTrigger>) {
+        if let Some(qlog) = &self.qlog {
+            qlog.congestion_state_updated(CongestionStateUpdated {
+                new: CongestionState::Recovery,
+                trigger,
+            });
+            self.qlog_metrics_updated();
+        }
     }
 
     fn set_loss_timer(&mut self) {
@@ -230,7 +441,7 @@ impl CongestionController {
             return;
         }
 
-        if let Some(t) = self.get_pto_timeout() {
+        if let (Some(t), _) = self.get_pto_time_and_space() {
             self.loss_timer.update(t);
         }
     }
@@ -240,15 +451,17 @@ impl CongestionController {
         let (earliest_loss_time, space) = self.get_loss_time_and_space();
         // lost timeout
         if earliest_loss_time.is_some() {
-            let loss_packet = self.remove_loss_packets(space, now);
-            assert!(!loss_packet.is_empty());
-            self.on_packets_lost(loss_packet.into_iter(), space);
+            // RTT可能在此期间增大，这时计时器会按新的时间阈值重新设定
+            let (loss_packets, persistent_congestion) = self.remove_loss_packets(space, now);
+            if !loss_packets.is_empty() {
+                self.on_packets_lost(loss_packets.into_iter(), space, persistent_congestion, now);
+            }
             self.set_loss_timer();
             return;
         }
 
         // probe timeout
-        let _ = if self.no_ack_eliciting_in_flight() {
+        let probe_space = if self.no_ack_eliciting_in_flight() {
             assert!(!self.server_completed_address_validation());
             // Client sends an anti-deadlock packet: Initial is padded
             // to earn more anti-amplification credit,
@@ -258,11 +471,19 @@ impl CongestionController {
             } else {
                 Some(Epoch::Initial)
             }
-        } else if self.get_pto_timeout().is_some() {
-            Some(Epoch::Data)
         } else {
-            None
+            match self.get_pto_time_and_space() {
+                (Some(_), space) => Some(space),
+                (None, _) => None,
+            }
         };
+        // 发送一到两个可引起确认的探测包，可以超出拥塞窗口，但仍受抗放大限制
+        if let Some(space) = probe_space {
+            self.pending_probes[space] = MAX_PTO_PROBES;
+            if let Some(waker) = self.send_waker.take() {
+                waker.wake();
+            }
+        }
         self.pto_count += 1;
 
         self.set_loss_timer();
@@ -283,23 +504,24 @@ impl CongestionController {
     }
 
     fn get_pto_time(&self, epoch: Epoch) -> Duration {
-        let smoothed_rtt = self.rtt.smoothed_rtt();
-        let rttvar = self.rtt.rttvar();
-        let mut duration = smoothed_rtt + std::cmp::max(K_GRANULARITY, rttvar * 4);
-        // 握手已完成, 则应该考虑 max_ack_delay
-        if epoch == Epoch::Data && self.is_handshake_done {
-            duration += self.max_ack_delay
-        }
-        duration * 2_u32.pow(self.pto_count)
+        // 握手已完成, 则应该考虑对端的 max_ack_delay
+        let with_max_ack_delay = epoch == Epoch::Data && self.is_handshake_done;
+        self.rtt.pto(with_max_ack_delay) * 2_u32.pow(self.pto_count)
     }
 
-    fn get_pto_timeout(&self) -> Option<Instant> {
+    fn get_pto_time_and_space(&self) -> (Option<Instant>, Epoch) {
         let mut duration = self.get_pto_time(Epoch::Initial);
         if self.no_ack_eliciting_in_flight() {
-            return Some(Instant::now() + duration);
+            let space = if self.has_handshake_keys {
+                Epoch::Handshake
+            } else {
+                Epoch::Initial
+            };
+            return (Some(self.clock.now() + duration), space);
         }
 
         let mut pto_time = None;
+        let mut pto_space = Epoch::Initial;
         for &space in Epoch::iter() {
             if self.time_of_last_ack_eliciting_packet[space].is_none() {
                 continue;
@@ -308,28 +530,40 @@ impl CongestionController {
                 // An endpoint MUST NOT set its PTO timer for the Application Data
                 // packet number space until the handshake is confirmed
                 if !self.is_handshake_done {
-                    return pto_time;
+                    return (pto_time, pto_space);
                 }
-                duration += self.max_ack_delay * 2_u32.pow(self.pto_count);
+                duration += self.rtt.max_ack_delay() * 2_u32.pow(self.pto_count);
             }
             let new_time = self.time_of_last_ack_eliciting_packet[space].unwrap() + duration;
             if pto_time.is_none() || new_time < pto_time.unwrap() {
                 pto_time = Some(new_time);
+                pto_space = space;
             }
         }
-        pto_time
+        (pto_time, pto_space)
     }
 
-    fn remove_loss_packets(&mut self, space: Epoch, now: Instant) -> Vec<SentPkt> {
-        assert!(self.largest_acked_packet[space].is_some());
-        let largest_acked = self.largest_acked_packet[space].unwrap();
+    // A.10. Detecting Lost Packets
+    //
+    // 早于最大已确认包发送的未确认包，满足包数阈值或时间阈值之一即判定为丢失；
+    // 尚未满足的，按时间阈值设定loss_time，由丢包检测计时器到期时再判定。
+    // 同时返回是否进入了持续拥塞
+    fn remove_loss_packets(&mut self, space: Epoch, now: Instant) -> (Vec<SentPkt>, bool) {
         self.loss_time[space] = None;
+        let Some(largest_acked) = self.largest_acked_packet[space] else {
+            return (Vec::new(), false);
+        };
 
+        // 9/8 * max(smoothed_rtt, latest_rtt)，且不小于计时器粒度
         let loss_delay = self.rtt.loss_delay();
-        let lost_send_time = now.checked_sub(loss_delay).unwrap();
+        // (smoothed_rtt + max(4*rttvar, kGranularity) + max_ack_delay) * 3
+        let pc_duration = self.rtt.pto(true) * K_PERSISTENT_CONGESTION_THRESHOLD;
+        let first_rtt_sample = self.rtt.first_sample_time();
+        // 当前这段连续丢失的在途包中，最早的发送时间；中间有包被确认则重新开始
+        let mut pc_start: Option<Instant> = None;
+        let mut persistent_congestion = false;
 
         let mut loss_packets = Vec::new();
-        let mut loss_pn = Vec::new();
 
         let mut largest_ack_index = 0;
         while largest_ack_index != self.sent_packets[space].len()
@@ -342,21 +576,23 @@ impl CongestionController {
         while i != self.sent_packets[space].len() && self.sent_packets[space][i].pn < largest_acked
         {
             if self.sent_packets[space][i].is_acked {
+                pc_start = None;
                 i += 1;
                 continue;
             }
             // 距离 largest ack index 相差超过 threshold 即为丢包
-            if self.sent_packets[space][i].time_sent <= lost_send_time
-                || largest_ack_index - i >= K_PACKET_THRESHOLD
-            {
+            let loss_time = self.sent_packets[space][i].time_sent + loss_delay;
+            if loss_time <= now || largest_ack_index - i >= K_PACKET_THRESHOLD {
                 if let Some(loss) = self.sent_packets[space].remove(i) {
-                    let pn = loss.pn;
-                    loss_pn.push(pn);
+                    // 只考虑首个RTT样本之后发出的包
+                    if loss.in_flight && first_rtt_sample.is_some_and(|t| loss.time_sent > t) {
+                        let start = *pc_start.get_or_insert(loss.time_sent);
+                        persistent_congestion |= loss.time_sent - start > pc_duration;
+                    }
                     loss_packets.push(loss);
                     largest_ack_index -= 1;
                 }
             } else {
-                let loss_time = self.sent_packets[space][i].time_sent + loss_delay;
                 self.loss_time[space] = match self.loss_time[space] {
                     Some(lt) => Some(lt.min(loss_time)),
                     None => Some(loss_time),
@@ -366,7 +602,63 @@ impl CongestionController {
         }
 
         self.slide_sent_packets(space);
-        loss_packets
+        (loss_packets, persistent_congestion)
+    }
+
+    // 6.4. Discarding Keys and Packet State
+    fn discard_space(&mut self, space: Epoch) {
+        // 被丢弃的包不再计入在途，但它们既没被确认也不算丢失，不能触发拥塞事件
+        for sent in std::mem::take(&mut self.sent_packets[space]) {
+            if sent.in_flight && !sent.is_acked {
+                self.algorithm.on_packet_discarded(&sent);
+            }
+        }
+        self.time_of_last_ack_eliciting_packet[space] = None;
+        self.largest_acked_packet[space] = None;
+        self.loss_time[space] = None;
+        self.pending_probes[space] = 0;
+        self.rcvd_records[space] = RcvdRecords::new(space);
+        self.pto_count = 0;
+        self.set_loss_timer();
+    }
+
+    // 包号空间被替换，发出的包不再可能被确认，交给丢包处理重传其中的帧，但不算拥塞
+    fn discard_sent(&mut self, space: Epoch) {
+        for sent in std::mem::take(&mut self.sent_packets[space]) {
+            if sent.is_acked {
+                continue;
+            }
+            if sent.in_flight {
+                self.algorithm.on_packet_discarded(&sent);
+            }
+            self.loss_handlers[space].may_loss(sent.pn);
+        }
+        self.time_of_last_ack_eliciting_packet[space] = None;
+        self.largest_acked_packet[space] = None;
+        self.loss_time[space] = None;
+        self.pending_probes[space] = 0;
+        self.set_loss_timer();
+    }
+
+    /// Checks whether a datagram should be sent to carry the ACK frames, even if the
+    /// congestion window does not allow sending more data.
+    fn should_send_ack(&self, now: Instant) -> bool {
+        // 1. 有需要立即发送的 ack，如乱序、Initial/Handshake 包
+        // 2. 有 ack 要发送, 且距离上次发送时间大于 max ack dely
+        // 3. 距离上次发送时间大于 max sent delay
+        let elapsed = now.saturating_duration_since(self.last_sent_time);
+        if self
+            .rcvd_records
+            .iter()
+            .any(RcvdRecords::need_immediate_ack)
+        {
+            return true;
+        }
+        let need_ack = self
+            .rcvd_records
+            .iter()
+            .any(|records| records.need_ack(self.max_ack_delay, now).is_some());
+        (need_ack && elapsed >= self.max_ack_delay) || elapsed >= MAX_SENT_DELAY
     }
 
     fn slide_sent_packets(&mut self, space: Epoch) {
@@ -391,38 +683,85 @@ impl CongestionController {
         self.has_handshake_keys || self.is_handshake_done
     }
 
-    fn process_ecn(&mut self, _: Epoch, _: EcnCounts) {
-        todo!()
+    // B.7. Process ECN Information
+    fn process_ecn(
+        &mut self,
+        space: Epoch,
+        ecn: Option<EcnCounts>,
+        ecn_marked: usize,
+        largest_time_sent: Option<Instant>,
+        now: Instant,
+    ) {
+        let ce_increase = self.ecn.on_ack(space, ecn_marked, ecn);
+        if ce_increase > 0 {
+            // CE标记等同于一次拥塞事件，但并没有包真正丢失，不能从在途字节中扣除
+            let marked = SentPkt {
+                time_sent: largest_time_sent.unwrap_or(now),
+                size: 0,
+                ..Default::default()
+            };
+            self.algorithm.on_congestion_event(&marked, now);
+            self.qlog_congestion_state_updated(Some(CongestionTrigger::Ecn));
+        }
+    }
+}
+
+// 0-RTT包与1-RTT包同属Data空间，无法区分，都记作1-RTT包
+fn packet_type(epoch: Epoch) -> PacketType {
+    match epoch {
+        Epoch::Initial => PacketType::Initial,
+        Epoch::Handshake => PacketType::Handshake,
+        Epoch::Data => PacketType::OneRtt,
     }
 }
 
 /// Shared congestion controller
 #[derive(Clone)]
-pub struct ArcCC(Arc<Mutex<CongestionController>>);
+pub struct ArcCC(Arc<Mutex<LossRecovery>>, Arc<AtomicUsize>);
 
 impl ArcCC {
+    /// Create a new shared congestion controller, which drives the given congestion control
+    /// `algorithm`, see [`CongestionController`] for more details.
+    ///
+    /// The PTO timer is armed from the `rtt` estimator, which is updated by the received
+    /// AckFrames.
+    ///
+    /// If the `qlog` sink is given, the lost packets, the congestion events and the updated
+    /// metrics are recorded to it.
+    ///
+    /// The loss detection timer and the PTO timer read the current time from the `clock`.
     pub fn new(
-        algorithm: CongestionAlgorithm,
+        algorithm: Box<dyn CongestionController>,
+        rtt: ArcRtt,
         max_ack_delay: Duration,
         loss: [Box<dyn MayLoss>; 3],
         retire: [Box<dyn RetirePktRecord>; 3],
+        qlog: Option<Arc<dyn QlogSink>>,
+        clock: ArcClock,
     ) -> Self {
-        ArcCC(Arc::new(Mutex::new(CongestionController::new(
-            algorithm,
-            max_ack_delay,
-            loss,
-            retire,
-        ))))
+        let recovery = LossRecovery::new(algorithm, rtt, max_ack_delay, loss, retire, qlog, clock);
+        let window = recovery.algorithm.can_send(recovery.clock.now());
+        ArcCC(
+            Arc::new(Mutex::new(recovery)),
+            Arc::new(AtomicUsize::new(window)),
+        )
+    }
+
+    // 调度时要比较所有路径的可用窗口，为免逐个加锁，在在途字节数可能变化的操作之后更新快照
+    fn update_window(&self, recovery: &LossRecovery) {
+        let window = recovery.algorithm.can_send(recovery.clock.now());
+        self.1.store(window, atomic::Ordering::Relaxed);
     }
 }
 
 impl super::CongestionControl for ArcCC {
     fn do_tick(&self) {
         let mut guard = self.0.lock().unwrap();
-        let now = Instant::now();
+        let now = guard.clock.now();
         if guard.loss_timer.is_timeout(now) {
             guard.on_loss_timeout(now);
         }
+        self.update_window(&guard);
         if let Some(waker) = guard.send_waker.take() {
             waker.wake();
         }
@@ -431,34 +770,41 @@ impl super::CongestionControl for ArcCC {
     fn poll_send(&self, cx: &mut Context<'_>) -> Poll<usize> {
         let mut guard = self.0.lock().unwrap();
         guard.send_waker = Some(cx.waker().clone());
-        let now = Instant::now();
+        guard.ecn.update_marking();
+        let now = guard.clock.now();
         if guard.loss_timer.is_timeout(now) {
             guard.on_loss_timeout(now);
         }
 
         let srtt = guard.rtt.smoothed_rtt();
         let cwnd = guard.algorithm.cwnd();
-        let mtu = MSS;
-        let rate = guard.algorithm.pacing_rate();
-        let tokens = guard.pacer.schedule(srtt, cwnd, mtu, now, rate);
+        let mtu = guard.mtu;
+        let rate = guard
+            .algorithm
+            .pacing_rate()
+            .map(|rate| rate.bytes_per_sec());
+        let window = guard.algorithm.can_send(now);
+        self.1.store(window, atomic::Ordering::Relaxed);
+        let tokens = if guard.pacing {
+            guard.pacer.schedule(srtt, cwnd, mtu, now, rate).min(window)
+        } else {
+            window
+        };
         if tokens >= mtu {
             return Poll::Ready(tokens);
         }
 
-        let mut need_ack = false;
-        for &epoch in Epoch::iter() {
-            if guard.rcvd_records[epoch]
-                .need_ack(guard.max_ack_delay)
-                .is_some()
-            {
-                need_ack = true;
-                break;
-            }
+        // PTO 探测包可以超出拥塞窗口
+        let probes = guard
+            .pending_probes
+            .iter()
+            .map(|&n| n as usize)
+            .sum::<usize>();
+        if probes > 0 {
+            return Poll::Ready(tokens.max(probes * mtu));
         }
-        // 1. 有 ack 要发送, 且距离上次发送时间大于 max ack dely
-        // 2. 距离上次发送时间大于 max sent delay
-        let elapsed = now.saturating_duration_since(guard.last_sent_time);
-        if (need_ack && elapsed >= guard.max_ack_delay) || elapsed >= MAX_SENT_DELAY {
+
+        if guard.should_send_ack(now) {
             return Poll::Ready(tokens);
         }
         Poll::Pending
@@ -466,7 +812,12 @@ impl super::CongestionControl for ArcCC {
 
     fn need_ack(&self, space: Epoch) -> Option<(u64, Instant)> {
         let guard = self.0.lock().unwrap();
-        guard.rcvd_records[space].need_ack(guard.max_ack_delay)
+        let now = guard.clock.now();
+        guard.rcvd_records[space].need_ack(guard.max_ack_delay, now)
+    }
+
+    fn need_probe(&self, space: Epoch) -> bool {
+        self.0.lock().unwrap().pending_probes[space] > 0
     }
 
     fn on_pkt_sent(
@@ -479,35 +830,92 @@ impl super::CongestionControl for ArcCC {
         ack: Option<u64>,
     ) {
         let mut guard = self.0.lock().unwrap();
-        let now = Instant::now();
+        let now = guard.clock.now();
         guard.on_packet_sent(pn, epoch, is_ack_eliciting, in_flight, sent_bytes, now);
 
         guard.last_sent_time = now;
         if let Some(largest_acked) = ack {
             guard.rcvd_records[epoch].on_ack_sent(pn, largest_acked);
         }
+        self.update_window(&guard);
     }
 
     fn on_ack(&self, space: Epoch, ack_frame: &AckFrame) {
         let mut guard = self.0.lock().unwrap();
-        let now = Instant::now();
+        let now = guard.clock.now();
         guard.on_ack_rcvd(space, ack_frame, now);
+        self.update_window(&guard);
+    }
+
+    fn on_ack_frequency(&self, frame: &AckFrequencyFrame) {
+        let mut guard = self.0.lock().unwrap();
+        guard.max_ack_delay = Duration::from_micros(frame.request_max_ack_delay.into_inner());
+        guard.rcvd_records[Epoch::Data].on_ack_frequency(
+            frame.ack_eliciting_threshold.into_inner(),
+            frame.reordering_threshold.into_inner(),
+        );
+    }
+
+    fn on_immediate_ack(&self) {
+        self.0.lock().unwrap().rcvd_records[Epoch::Data].on_immediate_ack();
     }
 
     fn on_pkt_rcvd(&self, epoch: Epoch, pn: u64, is_ack_eliciting: bool) {
-        if !is_ack_eliciting {
-            return;
-        }
         let mut guard = self.0.lock().unwrap();
-        guard.rcvd_records[epoch].on_pkt_rcvd(pn);
-        let now = Instant::now();
-        guard.on_datagram_rcvd(now);
+        let now = guard.clock.now();
+        // 不可引起确认的包也要记录，否则其包号会被误认为缺失
+        let guard = &mut *guard;
+        let evicted = guard.rcvd_records[epoch].on_pkt_rcvd(pn, is_ack_eliciting, now);
+        // 记录的区间过多，最旧的一段不再等待对端确认，直接失活
+        for pn in evicted.into_iter().flatten() {
+            guard.retire_handlers[epoch].retire(pn);
+        }
+        if is_ack_eliciting {
+            guard.on_datagram_rcvd(now);
+        }
+    }
+
+    fn ecn(&self) -> Option<Ecn> {
+        self.0.lock().unwrap().ecn.is_marking().then_some(Ecn::Ect0)
+    }
+
+    fn ecn_state(&self) -> EcnState {
+        self.0.lock().unwrap().ecn.state()
     }
 
     fn pto_time(&self, epoch: Epoch) -> Duration {
         self.0.lock().unwrap().get_pto_time(epoch)
     }
 
+    fn pacing_rate(&self) -> Option<PacingRate> {
+        self.0.lock().unwrap().pacing_rate()
+    }
+
+    fn pacing_delay(&self) -> Duration {
+        let guard = self.0.lock().unwrap();
+        guard.pacing_delay(guard.clock.now())
+    }
+
+    fn set_pacing_enabled(&self, enabled: bool) {
+        self.0.lock().unwrap().pacing = enabled;
+    }
+
+    fn set_mtu(&self, mtu: usize) {
+        self.0.lock().unwrap().mtu = mtu.max(MSS);
+    }
+
+    fn congestion_window(&self) -> u64 {
+        self.0.lock().unwrap().algorithm.cwnd()
+    }
+
+    fn on_app_limited(&self) {
+        self.0.lock().unwrap().algorithm.on_app_limited();
+    }
+
+    fn available_window(&self) -> usize {
+        self.1.load(atomic::Ordering::Relaxed)
+    }
+
     fn on_get_handshake_keys(&self) {
         let mut gurad = self.0.lock().unwrap();
         gurad.has_handshake_keys = true;
@@ -518,17 +926,49 @@ impl super::CongestionControl for ArcCC {
         guard.is_handshake_done = true;
         guard.rtt.on_handshake_done();
     }
+
+    fn discard_epoch(&self, epoch: Epoch) {
+        let mut guard = self.0.lock().unwrap();
+        guard.discard_space(epoch);
+        self.update_window(&guard);
+    }
+
+    fn discard_sent(&self, epoch: Epoch) {
+        let mut guard = self.0.lock().unwrap();
+        guard.discard_sent(epoch);
+        self.update_window(&guard);
+    }
+
+    fn reset_ack_state(&self, epoch: Epoch) {
+        self.0.lock().unwrap().rcvd_records[epoch].reset();
+    }
 }
 
+// 未确认的ack-eliciting包超过这么多个，立即发送ack，即每2个包确认一次
+const DEFAULT_ACK_ELICITING_THRESHOLD: u64 = 1;
+// 乱序超过这么多个包，立即发送ack，1即RFC 9000中有包乱序就立即确认
+const DEFAULT_REORDERING_THRESHOLD: u64 = 1;
+// 最多记录这么多段不连续的包号，超出时最旧的一段提前失活，避免乱序或丢包导致记录无限增长
+const MAX_RCVD_RANGES: usize = 64;
+
 /// The [`RcvdRecords`] struct is used to maintain records of received packets for each epoch.
 /// It tracks acknowledged packets and determines when an ACK frame should be sent.
 /// It also retires packets that have been acknowledged by an ACK frame that has already sent and which has been confirmed by the peer.
 struct RcvdRecords {
     epoch: Epoch,
     need_ack: bool,
+    // 上次发送ack之后收到的ack-eliciting包数
+    unacked_eliciting: u64,
+    // 可由对端的ACK_FREQUENCY帧调整
+    ack_eliciting_threshold: u64,
+    reordering_threshold: u64,
     last_ack_sent: Option<(u64, u64)>,
+    // 收到过的最大包号及其接收时间，不因确认而清除，用于判断乱序
+    largest_rcvd: Option<(u64, Instant)>,
+    // 上次发送ack之后，待确认的最大包号及其接收时间
     largest_recv_time: Option<(u64, Instant)>,
-    rcvd_queue: VecDeque<u64>,
+    // 收到的包号按连续区间合并、升序排列，不超过MAX_RCVD_RANGES段
+    rcvd_ranges: VecDeque<RangeInclusive<u64>>,
 }
 
 impl RcvdRecords {
@@ -536,54 +976,172 @@ impl RcvdRecords {
         Self {
             epoch,
             need_ack: false,
+            unacked_eliciting: 0,
+            ack_eliciting_threshold: DEFAULT_ACK_ELICITING_THRESHOLD,
+            reordering_threshold: DEFAULT_REORDERING_THRESHOLD,
             last_ack_sent: None,
+            largest_rcvd: None,
             largest_recv_time: None,
-            rcvd_queue: VecDeque::new(),
+            rcvd_ranges: VecDeque::new(),
         }
     }
 
-    fn on_pkt_rcvd(&mut self, pn: u64) {
-        // An endpoint MUST acknowledge all ack-eliciting Initial and Handshake packets immediately
-        if self.epoch == Epoch::Initial || self.epoch == Epoch::Handshake {
+    // 接收的包号空间被替换，之前的接收记录作废，但保留对端要求的确认频率
+    fn reset(&mut self) {
+        let (ack_eliciting_threshold, reordering_threshold) =
+            (self.ack_eliciting_threshold, self.reordering_threshold);
+        *self = Self::new(self.epoch);
+        self.on_ack_frequency(ack_eliciting_threshold, reordering_threshold);
+    }
+
+    /// Records the received packet `pn`.
+    ///
+    /// Every packet is recorded, so that the gaps between the packet numbers are told correctly,
+    /// but only the ack-eliciting packets count toward the thresholds of sending an ACK frame.
+    ///
+    /// The packet numbers are kept as at most [`MAX_RCVD_RANGES`] ranges, if there are more, the
+    /// oldest range is dropped and returned, the caller should retire the packets in it.
+    fn on_pkt_rcvd(
+        &mut self,
+        pn: u64,
+        is_ack_eliciting: bool,
+        now: Instant,
+    ) -> Option<RangeInclusive<u64>> {
+        if is_ack_eliciting {
+            // An endpoint MUST acknowledge all ack-eliciting Initial and Handshake packets immediately
+            if self.epoch == Epoch::Initial || self.epoch == Epoch::Handshake {
+                self.need_ack = true;
+            }
+            // See [Section 13.2.2](https://www.rfc-editor.org/rfc/rfc9000.html#name-acknowledgment-frequency)
+            // A receiver SHOULD send an ACK frame after receiving at least two ack-eliciting packets.
+            self.unacked_eliciting += 1;
+            if self.unacked_eliciting > self.ack_eliciting_threshold {
+                self.need_ack = true;
+            }
+        }
+
+        let evicted = self.insert_rcvd(pn);
+
+        // 已确认的包号会从rcvd_ranges中移除，乱序要与收到过的最大包号比较
+        let largest = self.largest_rcvd.map(|(largest, _)| largest);
+        match self.largest_rcvd {
+            // 上次ack之后只收到了更小的包号，仍以最大包号构造ack
+            Some((largest, recv_time)) if pn < largest => {
+                if is_ack_eliciting {
+                    self.largest_recv_time.get_or_insert((largest, recv_time));
+                }
+            }
+            _ => {
+                self.largest_rcvd = Some((pn, now));
+                // 只有可引起确认的包才需要在max_ack_delay内被确认
+                if is_ack_eliciting {
+                    self.largest_recv_time = Some((pn, now));
+                }
+            }
+        }
+        if is_ack_eliciting && self.is_reordered(pn, largest) {
             self.need_ack = true;
         }
-        // See [Section 13.2.1](https://www.rfc-editor.org/rfc/rfc9000.html#name-sending-ack-frames)
-        // An endpoint SHOULD generate and send an ACK frame without delay when it receives an ack-eliciting packet either:
-        // 1. When the received packet has a packet number less than another ack-eliciting packet that has been received
-        // 2. when the packet has a packet number larger than the highest-numbered ack-eliciting packet that has been
-        // received and there are missing packets between that packet and this packet.
-        if let Some(&largest) = self.rcvd_queue.back() {
-            if pn < largest || pn - largest > 1 {
-                self.need_ack = true;
+        evicted
+    }
+
+    // 将包号并入相邻的区间，能与前后区间相接就合并，否则新增一段
+    fn insert_rcvd(&mut self, pn: u64) -> Option<RangeInclusive<u64>> {
+        let index = self.rcvd_ranges.partition_point(|r| *r.start() <= pn);
+        let prev = index.checked_sub(1).map(|i| *self.rcvd_ranges[i].end());
+        if prev.is_some_and(|end| end >= pn) {
+            return None;
+        }
+        let joins_prev = prev.is_some_and(|end| end + 1 == pn);
+        let joins_next = self
+            .rcvd_ranges
+            .get(index)
+            .is_some_and(|next| *next.start() == pn + 1);
+        match (joins_prev, joins_next) {
+            (true, true) => {
+                let next = self.rcvd_ranges.remove(index).unwrap();
+                let prev = &mut self.rcvd_ranges[index - 1];
+                *prev = *prev.start()..=*next.end();
+            }
+            (true, false) => {
+                let prev = &mut self.rcvd_ranges[index - 1];
+                *prev = *prev.start()..=pn;
             }
-            if pn >= largest {
-                self.largest_recv_time = Some((pn, Instant::now()));
-                if pn > largest {
-                    self.rcvd_queue.push_back(pn);
-                    return;
+            (false, true) => {
+                let next = &mut self.rcvd_ranges[index];
+                *next = pn..=*next.end();
+            }
+            (false, false) => {
+                self.rcvd_ranges.insert(index, pn..=pn);
+                if self.rcvd_ranges.len() > MAX_RCVD_RANGES {
+                    return self.rcvd_ranges.pop_front();
                 }
             }
-        } else {
-            self.largest_recv_time = Some((pn, Instant::now()));
+        }
+        None
+    }
+
+    /// Checks whether the packet `pn` just received is reordered enough to be acknowledged
+    /// immediately, `largest` is the largest packet number received before it.
+    ///
+    /// See [Expediting Reordering](https://datatracker.ietf.org/doc/html/draft-ietf-quic-ack-frequency#name-expediting-reordering)
+    /// of [QUIC Acknowledgment Frequency](https://datatracker.ietf.org/doc/html/draft-ietf-quic-ack-frequency)
+    /// for more details.
+    fn is_reordered(&self, pn: u64, largest: Option<u64>) -> bool {
+        let Some(largest) = largest else {
+            return false;
         };
+        match self.reordering_threshold {
+            0 => false,
+            // See [Section 13.2.1](https://www.rfc-editor.org/rfc/rfc9000.html#name-sending-ack-frames)
+            // An endpoint SHOULD generate and send an ACK frame without delay when it receives an ack-eliciting packet either:
+            // 1. When the received packet has a packet number less than another ack-eliciting packet that has been received
+            // 2. when the packet has a packet number larger than the highest-numbered ack-eliciting packet that has been
+            // received and there are missing packets between that packet and this packet.
+            1 => pn < largest || pn - largest > 1,
+            threshold => {
+                // 找到上次ack之后最小的缺失包号，其后收到的包号超出threshold个，才立即确认
+                let largest = largest.max(pn);
+                let reported = self.last_ack_sent.map(|(_, largest_acked)| largest_acked);
+                // 相邻两段之间即是缺失的包号
+                self.rcvd_ranges
+                    .iter()
+                    .map(|range| *range.end())
+                    .zip(self.rcvd_ranges.iter().skip(1))
+                    .find(|(prev, _)| reported.map_or(true, |reported| *prev >= reported))
+                    .is_some_and(|(prev, _)| largest - (prev + 1) >= threshold)
+            }
+        }
+    }
 
-        let index = self.rcvd_queue.partition_point(|&x| x < pn);
+    /// Called when an ACK_FREQUENCY frame is received, which changes the thresholds for
+    /// sending an ACK frame immediately.
+    fn on_ack_frequency(&mut self, ack_eliciting_threshold: u64, reordering_threshold: u64) {
+        self.ack_eliciting_threshold = ack_eliciting_threshold;
+        self.reordering_threshold = reordering_threshold;
+    }
 
-        if self.rcvd_queue.is_empty() || self.rcvd_queue[index] != pn {
-            self.rcvd_queue.insert(index, pn);
-        }
+    /// Called when an IMMEDIATE_ACK frame is received, the received packets should be
+    /// acknowledged immediately.
+    fn on_immediate_ack(&mut self) {
+        self.need_ack = true;
+    }
+
+    /// Checks whether an ACK frame needs to be sent right now, without waiting for the
+    /// max_ack_delay, such as the received packets are reordered.
+    fn need_immediate_ack(&self) -> bool {
+        self.need_ack && self.largest_recv_time.is_some()
     }
 
     /// Checks whether an ACK frame needs to be sent.
     /// Returns [`Some`] if it's time to send an ACK based on the maximum delay.
-    fn need_ack(&self, max_delay: Duration) -> Option<(u64, Instant)> {
+    fn need_ack(&self, max_delay: Duration, now: Instant) -> Option<(u64, Instant)> {
         if self.need_ack {
             return self.largest_recv_time;
         }
         // All ack-eliciting 0-RTT and 1-RTT packets  MUST acknowledge within its advertised max_ack_delay
         if let Some((largest, recv_time)) = self.largest_recv_time {
-            let now = Instant::now();
-            if now - recv_time >= max_delay {
+            if now.saturating_duration_since(recv_time) >= max_delay {
                 return Some((largest, recv_time));
             }
         }
@@ -596,6 +1154,7 @@ impl RcvdRecords {
         self.last_ack_sent = Some((pn, largest_acked));
         self.largest_recv_time = None;
         self.need_ack = false;
+        self.unacked_eliciting = 0;
     }
 
     /// Processes an acknowledged (ACK) packet.
@@ -603,13 +1162,20 @@ impl RcvdRecords {
     fn ack(&mut self, ack: u64, retire_records: &[Box<dyn RetirePktRecord>; 3]) {
         if let Some((pn, largest_acked)) = self.last_ack_sent {
             if ack == pn {
-                self.rcvd_queue
-                    .iter()
-                    .filter(|&&pn| pn <= largest_acked)
-                    .for_each(|pn| {
-                        retire_records[self.epoch].retire(*pn);
-                    });
-                self.rcvd_queue.retain(|&pn| pn > largest_acked);
+                while let Some(range) = self.rcvd_ranges.pop_front() {
+                    if *range.start() > largest_acked {
+                        self.rcvd_ranges.push_front(range);
+                        break;
+                    }
+                    let end = (*range.end()).min(largest_acked);
+                    for pn in *range.start()..=end {
+                        retire_records[self.epoch].retire(pn);
+                    }
+                    if end < *range.end() {
+                        self.rcvd_ranges.push_front(end + 1..=*range.end());
+                        break;
+                    }
+                }
             }
         }
     }
@@ -655,6 +1221,8 @@ pub struct SentPkt {
     pub tx_in_flight: usize,
     pub lost: u64,
     pub is_acked: bool,
+    pub in_flight: bool,
+    pub ecn_marked: bool,
 }
 
 impl Default for SentPkt {
@@ -670,6 +1238,8 @@ impl Default for SentPkt {
             tx_in_flight: 0,
             lost: 0,
             is_acked: false,
+            in_flight: true,
+            ecn_marked: false,
         }
     }
 }
@@ -687,6 +1257,8 @@ impl SentPkt {
             tx_in_flight: 0,
             lost: 0,
             is_acked: false,
+            in_flight: true,
+            ecn_marked: false,
         }
     }
 }
@@ -709,18 +1281,6 @@ impl Ord for SentPkt {
     }
 }
 
-pub trait Algorithm: Send {
-    fn on_sent(&mut self, sent: &mut SentPkt, sent_bytes: usize, now: Instant);
-
-    fn on_ack(&mut self, packet: VecDeque<AckedPkt>, now: Instant);
-
-    fn on_congestion_event(&mut self, lost: &SentPkt, now: Instant);
-
-    fn cwnd(&self) -> u64;
-
-    fn pacing_rate(&self) -> Option<u64>;
-}
-
 #[derive(Default)]
 struct LossDetectionTimer {
     timeout: Option<Instant>,
@@ -742,9 +1302,10 @@ impl LossDetectionTimer {
 
 #[cfg(test)]
 mod tests {
-    use qbase::varint::VarInt;
+    use qbase::{time::MockClock, varint::VarInt};
 
     use super::*;
+    use crate::INITIAL_RTT;
 
     #[test]
     fn test_on_packet_sent_multiple_packets() {
@@ -791,14 +1352,15 @@ mod tests {
         congestion.largest_acked_packet[space] = Some(5);
         congestion.sent_packets[space][4].is_acked = true;
         congestion.sent_packets[space].pop_back();
-        let lost_packets = congestion.remove_loss_packets(space, now);
+        let (lost_packets, _) = congestion.remove_loss_packets(space, now);
         assert_eq!(lost_packets.len(), 2);
         for (i, lost) in lost_packets.iter().enumerate() {
             assert_eq!(lost.pn, i as u64 + 1);
         }
         assert_eq!(congestion.sent_packets[space].len(), 2);
-        // loss delay =  333*1.25
-        let loss_packets = congestion.remove_loss_packets(space, now + Duration::from_millis(417));
+        // loss delay = 333 * 9/8
+        let (loss_packets, _) =
+            congestion.remove_loss_packets(space, now + Duration::from_millis(417));
         // 3,4 因为超时丢包
         assert_eq!(loss_packets.len(), 2);
         for (i, lost) in loss_packets.iter().enumerate() {
@@ -880,48 +1442,386 @@ mod tests {
 
     #[test]
     fn test_ack_record() {
+        let now = Instant::now();
         let max_ack_delay = Duration::from_millis(100);
         let mut ack_reocrd = RcvdRecords::new(Epoch::Initial);
-        ack_reocrd.on_pkt_rcvd(1);
-        assert!(ack_reocrd.need_ack(max_ack_delay).is_some());
+        ack_reocrd.on_pkt_rcvd(1, true, now);
+        assert!(ack_reocrd.need_ack(max_ack_delay, now).is_some());
 
-        ack_reocrd.on_pkt_rcvd(1);
-        assert_eq!(ack_reocrd.rcvd_queue.len(), 1);
+        ack_reocrd.on_pkt_rcvd(1, true, now);
+        assert_eq!(ack_reocrd.rcvd_ranges, vec![1..=1]);
 
         ack_reocrd.on_ack_sent(1, 1);
         assert_eq!(ack_reocrd.last_ack_sent, Some((1, 1)));
-        assert!(ack_reocrd.need_ack(max_ack_delay).is_none());
+        assert!(ack_reocrd.need_ack(max_ack_delay, now).is_none());
 
-        ack_reocrd.on_pkt_rcvd(3);
-        assert_eq!(ack_reocrd.rcvd_queue, vec![1, 3]);
+        ack_reocrd.on_pkt_rcvd(3, true, now);
+        assert_eq!(ack_reocrd.rcvd_ranges, vec![1..=1, 3..=3]);
 
-        ack_reocrd.on_pkt_rcvd(0);
-        assert_eq!(ack_reocrd.rcvd_queue, vec![0, 1, 3]);
-        assert_eq!(ack_reocrd.need_ack(max_ack_delay).unwrap().0, 3);
+        ack_reocrd.on_pkt_rcvd(0, true, now);
+        assert_eq!(ack_reocrd.rcvd_ranges, vec![0..=1, 3..=3]);
+        assert_eq!(ack_reocrd.need_ack(max_ack_delay, now).unwrap().0, 3);
 
-        ack_reocrd.on_pkt_rcvd(5);
-        ack_reocrd.on_pkt_rcvd(7);
-        assert_eq!(ack_reocrd.rcvd_queue, vec![0, 1, 3, 5, 7]);
-        assert_eq!(ack_reocrd.need_ack(max_ack_delay).unwrap().0, 7);
+        ack_reocrd.on_pkt_rcvd(5, true, now);
+        ack_reocrd.on_pkt_rcvd(7, true, now);
+        assert_eq!(ack_reocrd.rcvd_ranges, vec![0..=1, 3..=3, 5..=5, 7..=7]);
+        assert_eq!(ack_reocrd.need_ack(max_ack_delay, now).unwrap().0, 7);
 
         // pn 2 ack 0,1,3,5,7
         ack_reocrd.on_ack_sent(2, 7);
-        ack_reocrd.on_pkt_rcvd(9);
-        assert_eq!(ack_reocrd.rcvd_queue, vec![0, 1, 3, 5, 7, 9]);
+        ack_reocrd.on_pkt_rcvd(9, true, now);
+        assert_eq!(
+            ack_reocrd.rcvd_ranges,
+            vec![0..=1, 3..=3, 5..=5, 7..=7, 9..=9]
+        );
 
         // pn 3 ack 0,1,3,5,7,9
         ack_reocrd.on_ack_sent(3, 9);
 
         // recv pn 2 ack, ingore
         ack_reocrd.ack(2, &[Box::new(Mock), Box::new(Mock), Box::new(Mock)]);
-        assert_eq!(ack_reocrd.rcvd_queue, vec![0, 1, 3, 5, 7, 9]);
-
-        ack_reocrd.on_pkt_rcvd(11);
-        assert_eq!(ack_reocrd.rcvd_queue, vec![0, 1, 3, 5, 7, 9, 11]);
+        assert_eq!(
+            ack_reocrd.rcvd_ranges,
+            vec![0..=1, 3..=3, 5..=5, 7..=7, 9..=9]
+        );
+
+        ack_reocrd.on_pkt_rcvd(11, true, now);
+        assert_eq!(
+            ack_reocrd.rcvd_ranges,
+            vec![0..=1, 3..=3, 5..=5, 7..=7, 9..=9, 11..=11]
+        );
         // recv pn 3 ack, ret
 
         ack_reocrd.ack(3, &[Box::new(Mock), Box::new(Mock), Box::new(Mock)]);
-        assert_eq!(ack_reocrd.rcvd_queue, vec![11]);
+        assert_eq!(ack_reocrd.rcvd_ranges, vec![11..=11]);
+    }
+
+    #[test]
+    fn test_ack_every_two_eliciting_packets() {
+        let now = Instant::now();
+        let max_ack_delay = Duration::from_millis(100);
+        let mut ack_record = RcvdRecords::new(Epoch::Data);
+        ack_record.on_pkt_rcvd(0, true, now);
+        // 1-RTT包可以延迟确认
+        assert!(ack_record.need_ack(max_ack_delay, now).is_none());
+        ack_record.on_pkt_rcvd(1, true, now);
+        // 收到第2个ack-eliciting包，立即确认
+        assert_eq!(ack_record.need_ack(max_ack_delay, now).unwrap().0, 1);
+
+        ack_record.on_ack_sent(0, 1);
+        ack_record.on_pkt_rcvd(2, true, now);
+        assert!(ack_record.need_ack(max_ack_delay, now).is_none());
+        // 超过max_ack_delay后也要确认
+        let later = now + Duration::from_millis(10);
+        assert_eq!(
+            ack_record
+                .need_ack(Duration::from_millis(5), later)
+                .unwrap()
+                .0,
+            2
+        );
+    }
+
+    #[test]
+    fn test_ack_frequency_threshold() {
+        let now = Instant::now();
+        let max_ack_delay = Duration::from_millis(100);
+        let mut ack_record = RcvdRecords::new(Epoch::Data);
+        // 对端要求每10个ack-eliciting包确认一次
+        ack_record.on_ack_frequency(9, 1);
+        for pn in 0..9 {
+            ack_record.on_pkt_rcvd(pn, true, now);
+            assert!(ack_record.need_ack(max_ack_delay, now).is_none());
+        }
+        ack_record.on_pkt_rcvd(9, true, now);
+        assert_eq!(ack_record.need_ack(max_ack_delay, now).unwrap().0, 9);
+
+        ack_record.on_ack_sent(0, 9);
+        for pn in 10..18 {
+            ack_record.on_pkt_rcvd(pn, true, now);
+            assert!(ack_record.need_ack(max_ack_delay, now).is_none());
+        }
+        // 乱序仍然会立即确认
+        ack_record.on_pkt_rcvd(21, true, now);
+        assert_eq!(ack_record.need_ack(max_ack_delay, now).unwrap().0, 21);
+
+        // IMMEDIATE_ACK帧要求立即确认
+        ack_record.on_ack_sent(1, 21);
+        ack_record.on_pkt_rcvd(22, true, now);
+        assert!(ack_record.need_ack(max_ack_delay, now).is_none());
+        ack_record.on_immediate_ack();
+        assert_eq!(ack_record.need_ack(max_ack_delay, now).unwrap().0, 22);
+    }
+
+    #[test]
+    fn test_ack_frequency_reordering_threshold() {
+        let now = Instant::now();
+        let max_ack_delay = Duration::from_millis(100);
+        let mut ack_record = RcvdRecords::new(Epoch::Data);
+        ack_record.on_ack_frequency(100, 0);
+        // 不因乱序立即确认
+        for pn in [0, 2, 1, 5] {
+            ack_record.on_pkt_rcvd(pn, true, now);
+            assert!(ack_record.need_ack(max_ack_delay, now).is_none());
+        }
+
+        let mut ack_record = RcvdRecords::new(Epoch::Data);
+        ack_record.on_ack_frequency(100, 3);
+        // 缺失的包号1，其后收到的最大包号超出3个时，才立即确认
+        for pn in [0, 2, 3] {
+            ack_record.on_pkt_rcvd(pn, true, now);
+            assert!(ack_record.need_ack(max_ack_delay, now).is_none());
+        }
+        ack_record.on_pkt_rcvd(4, true, now);
+        assert_eq!(ack_record.need_ack(max_ack_delay, now).unwrap().0, 4);
+    }
+
+    #[test]
+    fn test_immediate_ack_on_reordering() {
+        let now = Instant::now();
+        let max_ack_delay = Duration::from_millis(100);
+        let mut ack_record = RcvdRecords::new(Epoch::Data);
+        ack_record.on_pkt_rcvd(0, true, now);
+        ack_record.on_pkt_rcvd(1, true, now);
+        ack_record.on_ack_sent(0, 1);
+        ack_record.ack(0, &[Box::new(Mock), Box::new(Mock), Box::new(Mock)]);
+        assert!(ack_record.rcvd_ranges.is_empty());
+
+        // 已确认的包号被移除后，仍能发现缺失了2、3
+        ack_record.on_pkt_rcvd(4, true, now);
+        assert!(ack_record.need_immediate_ack());
+        assert_eq!(ack_record.need_ack(max_ack_delay, now).unwrap().0, 4);
+
+        // 填补空缺的包也要立即确认，且以收到过的最大包号构造ack
+        ack_record.on_ack_sent(1, 4);
+        ack_record.on_pkt_rcvd(2, true, now);
+        assert!(ack_record.need_immediate_ack());
+        assert_eq!(ack_record.need_ack(max_ack_delay, now).unwrap().0, 4);
+
+        // 按序到达的包可以延迟确认
+        ack_record.on_ack_sent(2, 4);
+        ack_record.on_pkt_rcvd(5, true, now);
+        assert!(!ack_record.need_immediate_ack());
+        assert!(ack_record.need_ack(max_ack_delay, now).is_none());
+    }
+
+    #[test]
+    fn test_send_ack_without_delay_on_reordering() {
+        let mut congestion = create_congestion_controller_for_test();
+        let now = congestion.clock.now();
+        congestion.rcvd_records[Epoch::Data].on_pkt_rcvd(0, true, now);
+        // 刚发送过数据，按序的包等待max_ack_delay之后再确认
+        assert!(!congestion.should_send_ack(now));
+
+        // 乱序时不必等待max_ack_delay
+        congestion.rcvd_records[Epoch::Data].on_pkt_rcvd(2, true, now);
+        assert!(congestion.should_send_ack(now));
+    }
+
+    #[test]
+    fn test_non_ack_eliciting_packets() {
+        let now = Instant::now();
+        let mut ack_record = RcvdRecords::new(Epoch::Data);
+        ack_record.on_pkt_rcvd(0, true, now);
+        // 不可引起确认的包不计入阈值，也不需要被确认
+        ack_record.on_pkt_rcvd(1, false, now);
+        ack_record.on_pkt_rcvd(2, false, now);
+        assert!(!ack_record.need_ack);
+        assert_eq!(ack_record.unacked_eliciting, 1);
+        assert_eq!(ack_record.largest_recv_time.map(|(pn, _)| pn), Some(0));
+        // 但它们的包号已收到，其后的包没有缺失，不是乱序
+        ack_record.on_pkt_rcvd(3, true, now);
+        assert!(ack_record.need_ack);
+        assert_eq!(ack_record.unacked_eliciting, 2);
+        assert!(!ack_record.is_reordered(3, Some(2)));
+
+        let mut ack_record = RcvdRecords::new(Epoch::Data);
+        ack_record.on_pkt_rcvd(0, false, now);
+        assert!(ack_record.need_ack(Duration::ZERO, now).is_none());
+        ack_record.on_pkt_rcvd(1, true, now);
+        assert!(!ack_record.need_ack);
+        assert_eq!(ack_record.largest_recv_time.map(|(pn, _)| pn), Some(1));
+    }
+
+    #[test]
+    fn test_rcvd_ranges_bounded() {
+        let now = Instant::now();
+        let mut ack_record = RcvdRecords::new(Epoch::Data);
+        // 连续的包号，哪怕都不可引起确认，也只占一段
+        for pn in 0..10_000 {
+            assert!(ack_record.on_pkt_rcvd(pn, false, now).is_none());
+        }
+        assert_eq!(ack_record.rcvd_ranges, vec![0..=9_999]);
+
+        // 填补空缺时与前后两段合并
+        ack_record.on_pkt_rcvd(10_002, false, now);
+        ack_record.on_pkt_rcvd(10_001, false, now);
+        assert_eq!(ack_record.rcvd_ranges, vec![0..=9_999, 10_001..=10_002]);
+        ack_record.on_pkt_rcvd(10_000, false, now);
+        assert_eq!(ack_record.rcvd_ranges, vec![0..=10_002]);
+
+        // 每隔一个包丢一个，区间数受限，超出时淘汰最旧的一段
+        let mut evicted = vec![];
+        for i in 1..=MAX_RCVD_RANGES as u64 {
+            evicted.extend(ack_record.on_pkt_rcvd(10_002 + 2 * i, true, now));
+        }
+        assert_eq!(evicted, vec![0..=10_002]);
+        assert_eq!(ack_record.rcvd_ranges.len(), MAX_RCVD_RANGES);
+        assert_eq!(ack_record.rcvd_ranges.front(), Some(&(10_004..=10_004)));
+    }
+
+    #[test]
+    fn test_probe_timeout_without_ack() {
+        let mut congestion = create_congestion_controller_for_test();
+        let now = Instant::now();
+        congestion.on_packet_sent(0, Epoch::Initial, true, true, 1000, now);
+
+        let pto = congestion.get_pto_time(Epoch::Initial);
+        assert_eq!(pto, INITIAL_RTT * 3);
+        assert_eq!(congestion.loss_timer.timeout, Some(now + pto));
+        assert!(!congestion.loss_timer.is_timeout(now + pto / 2));
+        assert_eq!(congestion.pending_probes[Epoch::Initial], 0);
+
+        // 没有收到任何确认，PTO超时后需要发送探测包
+        let expired = now + pto + Duration::from_millis(1);
+        assert!(congestion.loss_timer.is_timeout(expired));
+        congestion.on_loss_timeout(expired);
+        assert_eq!(congestion.pending_probes[Epoch::Initial], MAX_PTO_PROBES);
+        // 探测不会标记任何包丢失
+        assert_eq!(congestion.sent_packets[Epoch::Initial].len(), 1);
+        // 指数退避
+        assert_eq!(congestion.pto_count, 1);
+        assert_eq!(congestion.get_pto_time(Epoch::Initial), pto * 2);
+        assert_eq!(congestion.loss_timer.timeout, Some(now + pto * 2));
+
+        congestion.on_packet_sent(1, Epoch::Initial, true, true, 1000, expired);
+        assert_eq!(
+            congestion.pending_probes[Epoch::Initial],
+            MAX_PTO_PROBES - 1
+        );
+        congestion.on_packet_sent(2, Epoch::Initial, true, true, 1000, expired);
+        assert_eq!(congestion.pending_probes[Epoch::Initial], 0);
+        assert_eq!(congestion.loss_timer.timeout, Some(expired + pto * 2));
+    }
+
+    #[test]
+    fn test_ce_marks_reduce_cwnd() {
+        let mut congestion = LossRecovery::new(
+            CongestionAlgorithm::NewReno.controller(),
+            ArcRtt::new(),
+            Duration::from_millis(100),
+            [Box::new(Mock), Box::new(Mock), Box::new(Mock)],
+            [Box::new(Mock), Box::new(Mock), Box::new(Mock)],
+            None,
+            Arc::new(MockClock::new()),
+        );
+        let now = Instant::now();
+        congestion.ecn.update_marking();
+        for pn in 0..4 {
+            congestion.on_packet_sent(pn, Epoch::Data, true, true, 1000, now);
+            assert!(congestion.sent_packets[Epoch::Data][pn as usize].ecn_marked);
+        }
+        let initial_cwnd = congestion.algorithm.cwnd();
+
+        let mut ack_frame = AckFrame {
+            largest: VarInt::from_u32(1),
+            delay: VarInt::from_u32(0),
+            first_range: VarInt::from_u32(1),
+            ranges: vec![],
+            ecn: None,
+        };
+        ack_frame.set_ecn(EcnCounts {
+            ect0: VarInt::from_u32(2),
+            ect1: VarInt::from_u32(0),
+            ce: VarInt::from_u32(0),
+        });
+        let acked = now + Duration::from_millis(10);
+        congestion.on_ack_rcvd(Epoch::Data, &ack_frame, acked);
+        assert_eq!(congestion.ecn.state(), EcnState::Capable);
+        assert!(congestion.algorithm.cwnd() >= initial_cwnd);
+        let cwnd = congestion.algorithm.cwnd();
+
+        // 对端反馈了CE标记，拥塞窗口减小，但没有包被判定丢失
+        ack_frame.largest = VarInt::from_u32(3);
+        ack_frame.set_ecn(EcnCounts {
+            ect0: VarInt::from_u32(3),
+            ect1: VarInt::from_u32(0),
+            ce: VarInt::from_u32(1),
+        });
+        congestion.on_ack_rcvd(Epoch::Data, &ack_frame, acked);
+        assert_eq!(congestion.ecn.state(), EcnState::Capable);
+        assert!(congestion.algorithm.cwnd() < cwnd);
+        assert!(congestion.sent_packets[Epoch::Data].is_empty());
+    }
+
+    #[test]
+    fn test_discard_space() {
+        let mut congestion = create_congestion_controller_for_test();
+        let now = Instant::now();
+        for pn in 0..3 {
+            congestion.on_packet_sent(pn, Epoch::Initial, true, true, 1000, now);
+        }
+        congestion.on_packet_sent(0, Epoch::Handshake, true, true, 1000, now);
+        congestion.rcvd_records[Epoch::Initial].on_pkt_rcvd(0, true, now);
+        let can_send = congestion.algorithm.can_send(now);
+
+        congestion.discard_space(Epoch::Initial);
+        assert!(congestion.sent_packets[Epoch::Initial].is_empty());
+        assert_eq!(
+            congestion.time_of_last_ack_eliciting_packet[Epoch::Initial],
+            None
+        );
+        assert_eq!(
+            congestion.rcvd_records[Epoch::Initial].need_ack(Duration::ZERO),
+            None
+        );
+        // 被丢弃的包不再计入在途，也不会被判为丢失
+        assert_eq!(congestion.algorithm.can_send(now), can_send + 3000);
+        // Handshake空间的状态不受影响，PTO计时器改由它来设置
+        assert_eq!(congestion.sent_packets[Epoch::Handshake].len(), 1);
+        let pto = congestion.get_pto_time(Epoch::Handshake);
+        assert_eq!(congestion.loss_timer.timeout, Some(now + pto));
+    }
+
+    #[test]
+    fn test_discard_sent() {
+        let lost = LostPns::default();
+        let mut congestion = create_congestion_controller_with_lost_pns(&lost);
+        let now = Instant::now();
+        let can_send = congestion.algorithm.can_send(now);
+        for pn in 0..4 {
+            congestion.on_packet_sent(pn, Epoch::Data, true, true, 1000, now);
+        }
+        congestion.on_ack_rcvd(Epoch::Data, &ack_frame(1), now);
+        congestion.rcvd_records[Epoch::Data].on_pkt_rcvd(0, true, now);
+
+        // 未被确认的包都交给丢包处理重传，且不再计入在途
+        congestion.discard_sent(Epoch::Data);
+        assert_eq!(*lost.0.lock().unwrap(), vec![0, 2, 3]);
+        assert!(congestion.sent_packets[Epoch::Data].is_empty());
+        assert_eq!(congestion.loss_time[Epoch::Data], None);
+        assert_eq!(congestion.algorithm.can_send(now), can_send);
+        // 接收记录不受影响
+        assert!(congestion.rcvd_records[Epoch::Data].largest_rcvd.is_some());
+    }
+
+    #[test]
+    fn test_reset_ack_state() {
+        let mut records = RcvdRecords::new(Epoch::Data);
+        let now = Instant::now();
+        records.on_ack_frequency(9, 0);
+        records.on_pkt_rcvd(0, true, now);
+        records.on_pkt_rcvd(2, true, now);
+        records.on_ack_sent(5, 2);
+
+        records.reset();
+        assert_eq!(records.last_ack_sent, None);
+        assert_eq!(records.largest_rcvd, None);
+        assert!(records.rcvd_ranges.is_empty());
+        assert!(!records.need_ack);
+        // 对端要求的确认频率仍然有效
+        assert_eq!(records.ack_eliciting_threshold, 9);
+        assert_eq!(records.reordering_threshold, 0);
     }
 
     struct Mock;
@@ -933,12 +1833,298 @@ mod tests {
         fn retire(&self, _: u64) {}
     }
 
-    fn create_congestion_controller_for_test() -> CongestionController {
-        CongestionController::new(
-            CongestionAlgorithm::Bbr,
+    #[derive(Clone, Default)]
+    struct LostPns(Arc<Mutex<Vec<u64>>>);
+
+    impl MayLoss for LostPns {
+        fn may_loss(&self, pn: u64) {
+            self.0.lock().unwrap().push(pn);
+        }
+    }
+
+    fn create_congestion_controller_with_lost_pns(lost: &LostPns) -> LossRecovery {
+        LossRecovery::new(
+            CongestionAlgorithm::NewReno.controller(),
+            ArcRtt::new(),
+            Duration::from_millis(100),
+            [
+                Box::new(lost.clone()),
+                Box::new(lost.clone()),
+                Box::new(lost.clone()),
+            ],
+            [Box::new(Mock), Box::new(Mock), Box::new(Mock)],
+            None,
+            Arc::new(MockClock::new()),
+        )
+    }
+
+    fn ack_frame(largest: u32) -> AckFrame {
+        AckFrame {
+            largest: VarInt::from_u32(largest),
+            delay: VarInt::from_u32(0),
+            first_range: VarInt::from_u32(0),
+            ranges: vec![],
+            ecn: None,
+        }
+    }
+
+    #[test]
+    fn test_packet_threshold_loss() {
+        let lost = LostPns::default();
+        let mut congestion = create_congestion_controller_with_lost_pns(&lost);
+        let now = Instant::now();
+        for pn in 0..6 {
+            congestion.on_packet_sent(pn, Epoch::Data, true, true, 1000, now);
+        }
+        let cwnd = congestion.algorithm.cwnd();
+
+        // 只确认了5，0~2之后都已有至少3个包发出，判定为丢失并通知重传
+        congestion.on_ack_rcvd(Epoch::Data, &ack_frame(5), now);
+        assert_eq!(*lost.0.lock().unwrap(), vec![0, 1, 2]);
+        let remaining = congestion.sent_packets[Epoch::Data]
+            .iter()
+            .map(|sent| sent.pn)
+            .collect::<Vec<_>>();
+        assert_eq!(remaining, vec![3, 4, 5]);
+        // 丢包通知了拥塞控制算法，拥塞窗口减小
+        assert!(congestion.algorithm.cwnd() < cwnd);
+
+        // 3、4尚未满足时间阈值，丢包检测计时器按时间阈值设定
+        let loss_time = now + congestion.rtt.loss_delay();
+        assert_eq!(congestion.loss_time[Epoch::Data], Some(loss_time));
+        assert_eq!(congestion.loss_timer.timeout, Some(loss_time));
+    }
+
+    #[test]
+    fn test_time_threshold_loss() {
+        let lost = LostPns::default();
+        let mut congestion = create_congestion_controller_with_lost_pns(&lost);
+        let now = Instant::now();
+        congestion.on_packet_sent(0, Epoch::Data, true, true, 1000, now);
+        congestion.on_packet_sent(1, Epoch::Data, true, true, 1000, now);
+
+        // 确认1，0既不满足包数阈值，也未超过时间阈值
+        congestion.on_ack_rcvd(Epoch::Data, &ack_frame(1), now);
+        assert!(lost.0.lock().unwrap().is_empty());
+        let loss_time = congestion.loss_time[Epoch::Data].unwrap();
+        assert_eq!(loss_time, now + congestion.rtt.loss_delay());
+        assert_eq!(congestion.loss_timer.timeout, Some(loss_time));
+
+        // 计时器到期之前不会判定丢包
+        assert!(!congestion.loss_timer.is_timeout(loss_time));
+        let expired = loss_time + Duration::from_millis(1);
+        assert!(congestion.loss_timer.is_timeout(expired));
+        congestion.on_loss_timeout(expired);
+        assert_eq!(*lost.0.lock().unwrap(), vec![0]);
+        assert_eq!(congestion.loss_time[Epoch::Data], None);
+        assert!(congestion.sent_packets[Epoch::Data]
+            .iter()
+            .all(|sent| sent.pn != 0));
+    }
+
+    #[test]
+    fn test_persistent_congestion() {
+        let lost = LostPns::default();
+        let mut congestion = create_congestion_controller_with_lost_pns(&lost);
+        // 持续拥塞只考虑首个RTT样本之后发出的包
+        congestion
+            .rtt
+            .update(Duration::from_millis(10), Duration::ZERO);
+        let pc_duration = congestion.rtt.pto(true) * K_PERSISTENT_CONGESTION_THRESHOLD;
+
+        // 1~3连续丢失，横跨了两倍的持续拥塞时长，期间没有任何包被确认
+        let start = Instant::now() + Duration::from_millis(1);
+        let end = start + pc_duration * 2;
+        congestion.on_packet_sent(1, Epoch::Data, true, true, 1000, start);
+        congestion.on_packet_sent(2, Epoch::Data, true, true, 1000, start + pc_duration);
+        congestion.on_packet_sent(3, Epoch::Data, true, true, 1000, end);
+        congestion.on_packet_sent(4, Epoch::Data, true, true, 1000, end);
+        congestion.on_packet_sent(5, Epoch::Data, true, true, 1000, end);
+        // 被确认的是一个不计入在途的包，不会让拥塞窗口再增长
+        congestion.on_packet_sent(6, Epoch::Data, false, false, 50, end);
+
+        congestion.on_ack_rcvd(Epoch::Data, &ack_frame(6), end);
+        assert_eq!(*lost.0.lock().unwrap(), vec![1, 2, 3]);
+        assert_eq!(congestion.algorithm.cwnd(), 2 * MSS as u64);
+    }
+
+    #[test]
+    fn test_pacing() {
+        let mut congestion = LossRecovery::new(
+            CongestionAlgorithm::NewReno.controller(),
+            ArcRtt::new(),
+            Duration::from_millis(100),
+            [Box::new(Mock), Box::new(Mock), Box::new(Mock)],
+            [Box::new(Mock), Box::new(Mock), Box::new(Mock)],
+            None,
+            Arc::new(MockClock::new()),
+        );
+        congestion
+            .rtt
+            .update(Duration::from_millis(100), Duration::ZERO);
+        let cwnd = congestion.algorithm.cwnd();
+        let rate = congestion.pacing_rate().unwrap();
+        assert_eq!(
+            rate,
+            PacingRate::from_window(cwnd, Duration::from_millis(100))
+        );
+
+        // 一个srtt内，发送1.25倍cwnd的数据
+        let packets = (cwnd as usize * 5 / 4).div_ceil(MSS);
+        let interval = (0..packets).map(|_| rate.delay(MSS)).sum::<Duration>();
+        let expected = Duration::from_millis(100);
+        assert!(interval.abs_diff(expected) < Duration::from_millis(2));
+
+        // 一次突发用完了pacer的令牌，需要等待令牌补足一个包
+        let now = congestion.clock.now();
+        assert_eq!(congestion.pacing_delay(now), Duration::ZERO);
+        let mut pn = 0;
+        while congestion.pacing_delay(now).is_zero() {
+            congestion.on_packet_sent(pn, Epoch::Data, true, true, MSS, now);
+            pn += 1;
+        }
+        // 初始的令牌恰好是最小突发的10个包
+        assert_eq!(pn, 10);
+        assert!(congestion.pacing_delay(now) <= rate.delay(MSS));
+
+        congestion.pacing = false;
+        assert_eq!(congestion.pacing_rate(), None);
+        assert_eq!(congestion.pacing_delay(now), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_congestion_config() {
+        let config = CongestionConfig {
+            initial_window: Some(32 * MSS as u64),
+            initial_rtt: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        for algorithm in [CongestionAlgorithm::NewReno, CongestionAlgorithm::Bbr] {
+            let mut congestion = LossRecovery::new(
+                algorithm.controller_with(&config),
+                ArcRtt::with_initial_rtt(config.initial_rtt()),
+                Duration::from_millis(100),
+                [Box::new(Mock), Box::new(Mock), Box::new(Mock)],
+                [Box::new(Mock), Box::new(Mock), Box::new(Mock)],
+                None,
+                Arc::new(MockClock::new()),
+            );
+            // 在收到任何RTT样本之前，PTO由配置的初始RTT决定
+            assert_eq!(
+                congestion.get_pto_time(Epoch::Initial),
+                Duration::from_millis(50) * 3
+            );
+
+            // 第一轮可以发送初始窗口那么多的包
+            congestion.pacing = false;
+            let now = congestion.clock.now();
+            let mut pn = 0;
+            while congestion.algorithm.can_send(now) >= MSS {
+                congestion.on_packet_sent(pn, Epoch::Data, true, true, MSS, now);
+                pn += 1;
+            }
+            assert_eq!(pn, 32);
+        }
+    }
+
+    #[test]
+    fn test_validate_congestion_config() {
+        assert_eq!(CongestionConfig::default().validate(), Ok(()));
+        let config = CongestionConfig {
+            min_window: Some(4 * MSS as u64),
+            max_window: Some(4 * MSS as u64),
+            ..Default::default()
+        };
+        assert_eq!(config.validate(), Ok(()));
+
+        let config = CongestionConfig {
+            min_window: Some(8 * MSS as u64),
+            max_window: Some(4 * MSS as u64),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.validate(),
+            Err(CongestionConfigError::InvertedWindows {
+                min: 8 * MSS as u64,
+                max: 4 * MSS as u64
+            })
+        );
+
+        let config = CongestionConfig {
+            initial_rtt: Some(Duration::ZERO),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.validate(),
+            Err(CongestionConfigError::ZeroInitialRtt)
+        );
+    }
+
+    #[test]
+    fn test_poll_send_by_mtu() {
+        use crate::CongestionControl;
+
+        let cc = ArcCC::new(
+            CongestionAlgorithm::NewReno.controller(),
+            ArcRtt::new(),
+            Duration::from_millis(100),
+            [Box::new(Mock), Box::new(Mock), Box::new(Mock)],
+            [Box::new(Mock), Box::new(Mock), Box::new(Mock)],
+            None,
+            Arc::new(MockClock::new()),
+        );
+        cc.set_pacing_enabled(false);
+        // 初始窗口为10个MSS，发送9个之后只剩下一个MSS的窗口
+        let now = cc.0.lock().unwrap().clock.now();
+        for pn in 0..9 {
+            cc.0.lock()
+                .unwrap()
+                .on_packet_sent(pn, Epoch::Data, true, true, MSS, now);
+        }
+        let mut cx = Context::from_waker(Waker::noop());
+        assert_eq!(cc.poll_send(&mut cx), Poll::Ready(MSS));
+
+        // 探测到更大的MTU后，剩余的窗口不足以发送一个满载的包
+        cc.set_mtu(1452);
+        assert_eq!(cc.poll_send(&mut cx), Poll::Pending);
+    }
+
+    #[test]
+    fn test_available_window_snapshot() {
+        use crate::CongestionControl;
+
+        let cc = ArcCC::new(
+            CongestionAlgorithm::NewReno.controller(),
+            ArcRtt::new(),
+            Duration::from_millis(100),
+            [Box::new(Mock), Box::new(Mock), Box::new(Mock)],
+            [Box::new(Mock), Box::new(Mock), Box::new(Mock)],
+            None,
+            Arc::new(MockClock::new()),
+        );
+        let window = cc.available_window();
+        assert_eq!(window, 10 * MSS);
+
+        cc.on_pkt_sent(Epoch::Data, 0, true, MSS, true, None);
+        assert_eq!(cc.available_window(), window - MSS);
+        // 持有锁时读取快照不会阻塞
+        let guard = cc.0.lock().unwrap();
+        assert_eq!(cc.available_window(), window - MSS);
+        drop(guard);
+
+        cc.discard_sent(Epoch::Data);
+        assert_eq!(cc.available_window(), window);
+    }
+
+    fn create_congestion_controller_for_test() -> LossRecovery {
+        LossRecovery::new(
+            CongestionAlgorithm::Bbr.controller(),
+            ArcRtt::new(),
             Duration::from_millis(100),
             [Box::new(Mock), Box::new(Mock), Box::new(Mock)],
             [Box::new(Mock), Box::new(Mock), Box::new(Mock)],
+            None,
+            Arc::new(MockClock::new()),
         )
     }
 }
@@ -0,0 +1,188 @@
+use qbase::{frame::EcnCounts, varint::VarInt};
+use qrecovery::space::Epoch;
+
+// 测试阶段标记ECT(0)的数据包数量
+const ECN_TESTING_PACKETS: usize = 10;
+
+/// The state of the ECN validation of a path.
+///
+/// See [ECN validation](https://www.rfc-editor.org/rfc/rfc9000.html#name-ecn-validation)
+/// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum EcnState {
+    /// The first packets are being sent with ECT(0) to test the path.
+    #[default]
+    Testing,
+    /// The testing packets have been sent, waiting for the acknowledgements to validate.
+    Unknown,
+    /// The path and the peer support ECN, packets are sent with ECT(0).
+    Capable,
+    /// The validation failed, ECN is disabled on the path.
+    Failed,
+}
+
+/// The validator of the ECN counts fed back by the peer in AckFrames.
+#[derive(Debug, Default)]
+pub(crate) struct EcnValidator {
+    state: EcnState,
+    // 本轮发送的包是否标记ECT(0)，只在每次poll_send时更新，保证记录与实际发出的标记一致
+    marking: bool,
+    // 测试阶段标记发出的包数
+    testing_sent: usize,
+    // 测试阶段标记的包中被判定丢失的包数
+    testing_lost: usize,
+    // 各空间已确认的对端反馈的ECN计数
+    counts: [Option<EcnCounts>; Epoch::count()],
+}
+
+impl EcnValidator {
+    pub(crate) fn state(&self) -> EcnState {
+        self.state
+    }
+
+    pub(crate) fn is_marking(&self) -> bool {
+        self.marking
+    }
+
+    /// Decide whether the packets to be sent are marked with ECT(0).
+    pub(crate) fn update_marking(&mut self) {
+        if self.state == EcnState::Testing && self.testing_sent >= ECN_TESTING_PACKETS {
+            self.state = EcnState::Unknown;
+        }
+        self.marking = matches!(self.state, EcnState::Testing | EcnState::Capable);
+    }
+
+    /// Return whether the packet sent is marked with ECT(0).
+    pub(crate) fn on_packet_sent(&mut self) -> bool {
+        if self.marking && self.state == EcnState::Testing {
+            self.testing_sent += 1;
+        }
+        self.marking
+    }
+
+    /// Called with the number of lost packets which were marked with ECT(0).
+    pub(crate) fn on_packets_lost(&mut self, marked: usize) {
+        if !matches!(self.state, EcnState::Testing | EcnState::Unknown) {
+            return;
+        }
+        // 测试阶段标记的包全部丢失，可能是网络设备丢弃了带ECN标记的包
+        self.testing_lost += marked;
+        if self.testing_lost >= ECN_TESTING_PACKETS {
+            self.state = EcnState::Failed;
+            self.marking = false;
+        }
+    }
+
+    /// Validate the ECN counts of an AckFrame, which newly acknowledges `marked` packets sent
+    /// with ECT(0), return the increase of the CE count.
+    pub(crate) fn on_ack(&mut self, space: Epoch, marked: usize, ecn: Option<EcnCounts>) -> u64 {
+        if self.state == EcnState::Failed {
+            return 0;
+        }
+        let Some(ecn) = ecn else {
+            // 对端或路径清除了ECN标记
+            if marked > 0 {
+                self.fail();
+            }
+            return 0;
+        };
+
+        let zero = VarInt::from_u32(0);
+        let prev = self.counts[space].unwrap_or(EcnCounts {
+            ect0: zero,
+            ect1: zero,
+            ce: zero,
+        });
+        // 乱序到达的旧确认，计数不会减少
+        if ecn.ect0 < prev.ect0 || ecn.ect1 < prev.ect1 || ecn.ce < prev.ce {
+            return 0;
+        }
+        self.counts[space] = Some(ecn);
+
+        let ect0_increase = ecn.ect0.into_inner() - prev.ect0.into_inner();
+        let ect1_increase = ecn.ect1.into_inner() - prev.ect1.into_inner();
+        let ce_increase = ecn.ce.into_inner() - prev.ce.into_inner();
+        // 从未发送过ECT(1)，或者新确认的标记包没有被全部计数
+        if ect1_increase > 0 || ect0_increase + ce_increase < marked as u64 {
+            self.fail();
+            return 0;
+        }
+        if marked > 0 && matches!(self.state, EcnState::Testing | EcnState::Unknown) {
+            self.state = EcnState::Capable;
+        }
+        ce_increase
+    }
+
+    fn fail(&mut self) {
+        self.state = EcnState::Failed;
+        self.marking = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(ect0: u32, ect1: u32, ce: u32) -> Option<EcnCounts> {
+        Some(EcnCounts {
+            ect0: VarInt::from_u32(ect0),
+            ect1: VarInt::from_u32(ect1),
+            ce: VarInt::from_u32(ce),
+        })
+    }
+
+    #[test]
+    fn test_ecn_validation() {
+        let mut ecn = EcnValidator::default();
+        ecn.update_marking();
+        for _ in 0..ECN_TESTING_PACKETS {
+            assert!(ecn.on_packet_sent());
+        }
+        ecn.update_marking();
+        assert_eq!(ecn.state(), EcnState::Unknown);
+        assert!(!ecn.on_packet_sent());
+
+        assert_eq!(ecn.on_ack(Epoch::Initial, 2, counts(1, 0, 1)), 1);
+        assert_eq!(ecn.state(), EcnState::Capable);
+        ecn.update_marking();
+        assert!(ecn.on_packet_sent());
+
+        // 乱序到达的旧确认被忽略
+        assert_eq!(ecn.on_ack(Epoch::Initial, 0, counts(1, 0, 0)), 0);
+        assert_eq!(ecn.state(), EcnState::Capable);
+        // 其他空间的计数单独计算
+        assert_eq!(ecn.on_ack(Epoch::Data, 1, counts(1, 0, 0)), 0);
+        assert_eq!(ecn.state(), EcnState::Capable);
+    }
+
+    #[test]
+    fn test_ecn_validation_failed() {
+        let mut ecn = EcnValidator::default();
+        ecn.update_marking();
+        assert!(ecn.on_packet_sent());
+        assert!(ecn.on_packet_sent());
+        // 新确认的标记包没有被全部计数
+        assert_eq!(ecn.on_ack(Epoch::Initial, 2, counts(1, 0, 0)), 0);
+        assert_eq!(ecn.state(), EcnState::Failed);
+        ecn.update_marking();
+        assert!(!ecn.on_packet_sent());
+
+        // 确认帧中没有ECN计数
+        let mut ecn = EcnValidator::default();
+        ecn.update_marking();
+        assert!(ecn.on_packet_sent());
+        ecn.on_ack(Epoch::Initial, 1, None);
+        assert_eq!(ecn.state(), EcnState::Failed);
+
+        // 测试阶段标记的包全部丢失
+        let mut ecn = EcnValidator::default();
+        ecn.update_marking();
+        for _ in 0..ECN_TESTING_PACKETS {
+            ecn.on_packet_sent();
+        }
+        ecn.on_packets_lost(ECN_TESTING_PACKETS - 1);
+        assert_eq!(ecn.state(), EcnState::Testing);
+        ecn.on_packets_lost(1);
+        assert_eq!(ecn.state(), EcnState::Failed);
+    }
+}
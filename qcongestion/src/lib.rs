@@ -1,15 +1,27 @@
 use std::{
+    collections::VecDeque,
     task::{Context, Poll},
     time::{Duration, Instant},
 };
 
-pub use congestion::{ArcCC, CongestionAlgorithm, MSS};
-use qbase::frame::AckFrame;
+pub use congestion::{
+    AckedPkt, ArcCC, CongestionAlgorithm, CongestionConfig, CongestionConfigError, SentPkt,
+    K_PACKET_THRESHOLD, K_PERSISTENT_CONGESTION_THRESHOLD, MSS,
+};
+pub use ecn::EcnState;
+pub use new_reno::NewReno;
+pub use pacing::PacingRate;
+use qbase::{
+    frame::{AckFrame, AckFrequencyFrame},
+    packet::Ecn,
+};
 use qrecovery::space::Epoch;
+pub use rtt::{ArcRtt, RttEstimator, RttSample, INITIAL_RTT, TIME_THRESHOLD};
 
 mod bbr;
 mod congestion;
 mod delivery_rate;
+mod ecn;
 mod min_max;
 mod new_reno;
 mod pacing;
@@ -30,6 +42,13 @@ pub trait CongestionControl {
     /// An [`Option`] containing the largest packet ID and the time it was received if an AckFrame is needed.
     fn need_ack(&self, space: Epoch) -> Option<(u64, Instant)>;
 
+    /// Checks if a probe packet should be sent in the next packet for the given epoch.
+    ///
+    /// When the PTO timer expires, one or two ack-eliciting packets are sent as probes, a PING
+    /// frame should be sent if there is no other ack-eliciting frame. The probes are allowed to
+    /// exceed the congestion window, but not the anti-amplification limit.
+    fn need_probe(&self, space: Epoch) -> bool;
+
     /// Records the sending of a packet, which may affect congestion control state.
     /// # Parameters
     /// - `pn`: The packet number of the sent packet.
@@ -50,22 +69,164 @@ pub trait CongestionControl {
     /// Updates the congestion control state upon receiving an AckFrame.
     fn on_ack(&self, space: Epoch, ack_frame: &AckFrame);
 
+    /// Updates the acknowledgement frequency of the data space, requested by the peer with an
+    /// ACK_FREQUENCY frame.
+    ///
+    /// See [ACK_FREQUENCY Frame](https://datatracker.ietf.org/doc/html/draft-ietf-quic-ack-frequency#name-ack_frequency-frame)
+    /// for more details.
+    fn on_ack_frequency(&self, frame: &AckFrequencyFrame);
+
+    /// Acknowledges the received packets of the data space immediately, requested by the peer
+    /// with an IMMEDIATE_ACK frame.
+    fn on_immediate_ack(&self);
+
     /// Records the receipt of a packet, which may influence future packet transmissions.
     /// # Parameters
     /// - `pn`: The packet number of the received packet.
     /// - `is_ack_elicition`: A boolean indicating whether the received packet is ack-eliciting.
     fn on_pkt_rcvd(&self, space: Epoch, pn: u64, is_ack_elicition: bool);
 
+    /// Returns the ECN codepoint that the datagrams just read should be marked with.
+    ///
+    /// The first packets on a path are sent with ECT(0) to test whether the path and the peer
+    /// support ECN, then the ECN counts in the received AckFrames are validated. The marking stops
+    /// if the validation fails.
+    fn ecn(&self) -> Option<Ecn>;
+
+    /// Returns the current state of the ECN validation, see [`EcnState`] for more details.
+    fn ecn_state(&self) -> EcnState;
+
     /// Retrieves the current path's PTO duration.
     /// # Returns
     /// The current PTO duration for the given epoch.
     fn pto_time(&self, epoch: Epoch) -> Duration;
 
+    /// Retrieves the pacing rate of the path.
+    ///
+    /// It is the pacing rate of the congestion control algorithm if it has one, otherwise derived
+    /// from the congestion window and the smoothed RTT.
+    ///
+    /// Return [`None`] if pacing is disabled.
+    fn pacing_rate(&self) -> Option<PacingRate>;
+
+    /// Returns how long the sending task should wait before the pacer allows the next packet,
+    /// rather than sending the packets in bursts.
+    ///
+    /// The pacer is the only place the packets are paced, [`CongestionControl::poll_send`] does
+    /// not give any send quota before this delay elapses. Return [`Duration::ZERO`] if a packet
+    /// can be sent now, or pacing is disabled.
+    fn pacing_delay(&self) -> Duration;
+
+    /// Enable or disable pacing, it is enabled by default.
+    ///
+    /// When disabled, the whole available congestion window can be sent in a burst.
+    fn set_pacing_enabled(&self, enabled: bool);
+
+    /// Set the size of a full packet of the path, it is [`MSS`] until a larger MTU of the path is
+    /// discovered.
+    ///
+    /// [`CongestionControl::poll_send`] does not give any send quota smaller than it, except for
+    /// the acknowledgments, and the pacer releases the packets of this size.
+    fn set_mtu(&self, mtu: usize);
+
+    /// Returns the current congestion window of the path in bytes.
+    fn congestion_window(&self) -> u64;
+
+    /// Called by the sending task when it runs out of data to send before the congestion window
+    /// is filled, that is, the sender is application-limited.
+    ///
+    /// The packets sent from now on are marked as app-limited, until the congestion window is
+    /// filled again. The samples of the acknowledgment of them underestimate the capacity of the
+    /// path, so the algorithms should not grow the congestion window based on them.
+    fn on_app_limited(&self);
+
+    /// Returns how many bytes can be sent now without exceeding the congestion window, that is,
+    /// the congestion window minus the bytes in flight.
+    ///
+    /// Unlike [`CongestionControl::poll_send`], the pacing and the probes are not taken into
+    /// account, it is used to compare the paths when scheduling.
+    ///
+    /// It is a snapshot taken when the packets are sent, acknowledged or declared lost, reading
+    /// it does not block the sending task of the path.
+    fn available_window(&self) -> usize;
+
     /// Handles the update of the handshake key state.
     fn on_get_handshake_keys(&self);
 
     /// Indicates that the handshake process has been completed.
     fn on_handshake_done(&self);
+
+    /// Called when the keys of the packet number space `epoch` are discarded.
+    ///
+    /// The sent packets of the space are removed from the bytes in flight, and the loss
+    /// detection and the acknowledgment state of the space are reset, see
+    /// [Section 6.4](https://www.rfc-editor.org/rfc/rfc9002.html#name-discarding-keys-and-packet-) of RFC 9002.
+    fn discard_epoch(&self, epoch: Epoch);
+
+    /// Called when the packets of the path are no longer sent in the packet number space of
+    /// `epoch`, such as the multipath extension binds the path to another packet number space.
+    ///
+    /// The sent packets not acknowledged yet are reported to [`MayLoss`] so that the frames in
+    /// them are retransmitted, and removed from the bytes in flight without a congestion event.
+    fn discard_sent(&self, epoch: Epoch);
+
+    /// Called when the packets received on the path belong to another packet number space of
+    /// `epoch`, the records of the received packets and the pending acknowledgment are reset.
+    ///
+    /// The acknowledgment frequency requested by the peer is kept.
+    fn reset_ack_state(&self, epoch: Epoch);
+}
+
+/// The [`CongestionController`] trait defines the interface of the congestion control algorithms,
+/// which decide how many bytes can be sent on a path.
+///
+/// [`NewReno`] is used by default, a custom algorithm can be injected when the path is created.
+pub trait CongestionController: Send {
+    /// Called when an in-flight packet is sent.
+    fn on_packet_sent(&mut self, sent: &mut SentPkt, now: Instant);
+
+    /// Called with the newly acknowledged packets when an AckFrame is received.
+    ///
+    /// The packets sent while the sender was application-limited are marked with
+    /// [`AckedPkt::is_app_limited`].
+    fn on_ack(&mut self, packets: VecDeque<AckedPkt>, now: Instant);
+
+    /// Called when the sender runs out of data before the congestion window is filled, read
+    /// [`CongestionControl::on_app_limited`].
+    ///
+    /// The algorithm should mark the packets sent afterwards with [`SentPkt::is_app_limited`],
+    /// until the congestion window is filled again.
+    fn on_app_limited(&mut self);
+
+    /// Called when a sent packet is declared lost.
+    fn on_congestion_event(&mut self, lost: &SentPkt, now: Instant);
+
+    /// Called after the lost packets are reported by [`on_congestion_event`], if they establish
+    /// the persistent congestion, the congestion window should be collapsed to the minimum.
+    ///
+    /// See [Section 7.6](https://www.rfc-editor.org/rfc/rfc9002.html#name-persistent-congestion)
+    /// of RFC 9002 for more details.
+    ///
+    /// [`on_congestion_event`]: CongestionController::on_congestion_event
+    fn on_persistent_congestion(&mut self);
+
+    /// Called when an in-flight packet is removed without being acknowledged or declared lost,
+    /// because the keys of its packet number space are discarded.
+    ///
+    /// The packet is no longer in flight, but it is not a signal of congestion.
+    fn on_packet_discarded(&mut self, discarded: &SentPkt);
+
+    /// Returns the number of bytes that can be sent at `now`, that is, the part of the
+    /// congestion window which is not occupied by the bytes in flight.
+    fn can_send(&self, now: Instant) -> usize;
+
+    /// Returns the current congestion window in bytes.
+    fn cwnd(&self) -> u64;
+
+    /// Returns the pacing rate of the algorithm.
+    ///
+    /// If [`None`], the pacing rate is derived from the congestion window and the smoothed RTT.
+    fn pacing_rate(&self) -> Option<PacingRate>;
 }
 
 /// The [`MayLoss`] trait is used to handle potential packet losses.
@@ -1,6 +1,9 @@
 use std::{collections::VecDeque, time::Instant};
 
-use crate::congestion::{AckedPkt, Algorithm, MSS};
+use crate::{
+    congestion::{AckedPkt, SentPkt, MSS},
+    CongestionConfig, CongestionController, PacingRate,
+};
 
 // The upper bound for the initial window will be
 // min (10*MSS, max (2*MSS, 14600))
@@ -8,10 +11,16 @@ use crate::congestion::{AckedPkt, Algorithm, MSS};
 const INIT_CWND: u64 = 10 * MSS as u64;
 const INFINITRE_SSTHRESH: u64 = u64::MAX;
 const LOSS_REDUCTION_FACTOR: f64 = 0.5;
+const MINIMUM_WINDOW: u64 = 2 * MSS as u64;
 
-pub(super) struct NewReno {
+/// The NewReno congestion control algorithm, which is the default [`CongestionController`].
+///
+/// See [NewReno](https://www.rfc-editor.org/rfc/rfc9002#name-congestion-control) of [RFC 9002](https://www.rfc-editor.org/rfc/rfc9002).
+pub struct NewReno {
     // Congestion window.
     cwnd: u64,
+    // The sum of the size in bytes of all sent packets that have not been acknowledged or declared lost.
+    bytes_in_flight: u64,
     // Slow start threshold.
     ssthresh: u64,
     // The number of bytes that have been ACKed.
@@ -19,15 +28,43 @@ pub(super) struct NewReno {
     bytes_acked: u64,
     // The time at which the most recent loss recovery period started.
     recovery_start_time: Option<Instant>,
+    // Whether the sender is application-limited, the packets sent in this period do not grow
+    // the congestion window.
+    // https://www.rfc-editor.org/rfc/rfc9002#name-underutilizing-the-congesti
+    app_limited: bool,
+    // The congestion window never shrinks below it.
+    min_window: u64,
+    // The congestion window never grows beyond it.
+    max_window: u64,
+}
+
+impl Default for NewReno {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl NewReno {
-    pub(super) fn new() -> Self {
+    pub fn new() -> Self {
+        Self::with_config(&CongestionConfig::default())
+    }
+
+    /// Create a NewReno controller with the windows overridden by the `config`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `min_window` of the `config` is larger than its `max_window`.
+    pub fn with_config(config: &CongestionConfig) -> Self {
+        let (cwnd, min_window, max_window) = config.windows(INIT_CWND, MINIMUM_WINDOW);
         NewReno {
-            cwnd: INIT_CWND,
+            cwnd,
+            bytes_in_flight: 0,
             ssthresh: INFINITRE_SSTHRESH,
             bytes_acked: 0,
             recovery_start_time: None,
+            app_limited: false,
+            min_window,
+            max_window,
         }
     }
 
@@ -39,7 +76,7 @@ impl NewReno {
     }
 
     fn on_per_ack(&mut self, ack: &AckedPkt) {
-        if self.in_congestion_recovery(&ack.time_sent) {
+        if self.in_congestion_recovery(&ack.time_sent) || ack.is_app_limited {
             return;
         }
         // In slow start
@@ -61,35 +98,63 @@ impl NewReno {
                 self.cwnd += MSS as u64;
             }
         }
+        self.cwnd = self.cwnd.min(self.max_window);
     }
 }
 
-impl Algorithm for NewReno {
-    fn on_sent(&mut self, _: &mut crate::congestion::SentPkt, _: usize, _: std::time::Instant) {}
+impl CongestionController for NewReno {
+    fn on_packet_sent(&mut self, sent: &mut SentPkt, _: Instant) {
+        self.bytes_in_flight += sent.size as u64;
+        // 拥塞窗口被填满，不再受限于应用
+        if self.bytes_in_flight >= self.cwnd {
+            self.app_limited = false;
+        }
+        sent.is_app_limited = self.app_limited;
+    }
 
-    fn on_ack(&mut self, packet: VecDeque<AckedPkt>, _: std::time::Instant) {
+    fn on_ack(&mut self, packet: VecDeque<AckedPkt>, _: Instant) {
         for acked in packet {
+            self.bytes_in_flight = self.bytes_in_flight.saturating_sub(acked.size as u64);
             self.on_per_ack(&acked);
         }
     }
 
-    fn on_congestion_event(&mut self, lost: &crate::congestion::SentPkt, now: std::time::Instant) {
+    fn on_app_limited(&mut self) {
+        self.app_limited = self.bytes_in_flight < self.cwnd;
+    }
+
+    fn on_congestion_event(&mut self, lost: &SentPkt, now: Instant) {
+        self.bytes_in_flight = self.bytes_in_flight.saturating_sub(lost.size as u64);
         if self.in_congestion_recovery(&lost.time_sent) {
             return;
         }
         self.recovery_start_time = Some(now);
         self.cwnd = (self.cwnd as f64 * LOSS_REDUCTION_FACTOR) as u64;
-        self.cwnd = self.cwnd.max(2 * MSS as u64);
+        self.cwnd = self.cwnd.max(self.min_window);
 
         self.bytes_acked = (self.bytes_acked as f64 * LOSS_REDUCTION_FACTOR) as u64;
         self.ssthresh = self.cwnd;
     }
 
+    fn on_persistent_congestion(&mut self) {
+        self.cwnd = self.min_window;
+        self.bytes_acked = 0;
+        self.recovery_start_time = None;
+    }
+
+    fn on_packet_discarded(&mut self, discarded: &SentPkt) {
+        self.bytes_in_flight = self.bytes_in_flight.saturating_sub(discarded.size as u64);
+    }
+
+    fn can_send(&self, _: Instant) -> usize {
+        self.cwnd.saturating_sub(self.bytes_in_flight) as usize
+    }
+
     fn cwnd(&self) -> u64 {
         self.cwnd
     }
 
-    fn pacing_rate(&self) -> Option<u64> {
+    fn pacing_rate(&self) -> Option<PacingRate> {
         None
     }
 }
@@ -98,7 +163,6 @@ impl Algorithm for NewReno {
 mod tests {
 
     use super::*;
-    use crate::congestion::SentPkt;
 
     #[test]
     fn test_reno_init() {
@@ -171,6 +235,120 @@ mod tests {
         assert_eq!(reno.recovery_start_time, Some(time_lost));
     }
 
+    #[test]
+    fn test_reno_loss_recovery_cycle() {
+        let mut reno = NewReno::new();
+        let now = Instant::now();
+
+        // fill the congestion window
+        let mut sent_pkts = generate_sent(0, 10, now);
+        for sent in sent_pkts.iter_mut() {
+            reno.on_packet_sent(sent, now);
+        }
+        assert_eq!(reno.can_send(now), 0);
+
+        // the first packet is lost, the others are acked
+        let lost = sent_pkts.remove(0);
+        let loss_time = now + std::time::Duration::from_millis(100);
+        reno.on_congestion_event(&lost, loss_time);
+        assert_eq!(reno.cwnd(), INIT_CWND / 2);
+        reno.on_ack(
+            sent_pkts.into_iter().map(AckedPkt::from).collect(),
+            loss_time,
+        );
+        // the packets sent before the recovery period do not grow the window
+        assert_eq!(reno.cwnd(), INIT_CWND / 2);
+        assert_eq!(reno.can_send(loss_time), (INIT_CWND / 2) as usize);
+
+        // the packets sent after the recovery period grow the window again
+        let sent_time = loss_time + std::time::Duration::from_millis(1);
+        let mut sent_pkts = generate_sent(10, 15, sent_time);
+        for sent in sent_pkts.iter_mut() {
+            reno.on_packet_sent(sent, sent_time);
+        }
+        assert_eq!(reno.can_send(sent_time), 0);
+        reno.on_ack(
+            sent_pkts.into_iter().map(AckedPkt::from).collect(),
+            sent_time,
+        );
+        assert_eq!(reno.cwnd(), INIT_CWND / 2 + MSS as u64);
+    }
+
+    #[test]
+    fn test_reno_app_limited() {
+        let mut reno = NewReno::new();
+        let now = Instant::now();
+
+        // 只有少量数据可发，拥塞窗口远未被填满
+        let mut sent_pkts = generate_sent(0, 2, now);
+        reno.on_app_limited();
+        for sent in sent_pkts.iter_mut() {
+            reno.on_packet_sent(sent, now);
+            assert!(sent.is_app_limited);
+        }
+        reno.on_ack(sent_pkts.into_iter().map(AckedPkt::from).collect(), now);
+        assert_eq!(reno.cwnd(), INIT_CWND);
+
+        // 填满拥塞窗口后，应用受限的阶段结束，确认再次增大拥塞窗口
+        let mut sent_pkts = generate_sent(2, 12, now);
+        for sent in sent_pkts.iter_mut() {
+            reno.on_packet_sent(sent, now);
+        }
+        assert!(!sent_pkts.last().unwrap().is_app_limited);
+        reno.on_ack(
+            sent_pkts.into_iter().skip(9).map(AckedPkt::from).collect(),
+            now,
+        );
+        assert_eq!(reno.cwnd(), INIT_CWND + MSS as u64);
+    }
+
+    #[test]
+    fn test_reno_window_bounds() {
+        let config = CongestionConfig {
+            initial_window: Some(4 * MSS as u64),
+            min_window: Some(4 * MSS as u64),
+            max_window: Some(12 * MSS as u64),
+            ..Default::default()
+        };
+        let mut reno = NewReno::with_config(&config);
+        let now = Instant::now();
+        assert_eq!(reno.cwnd(), 4 * MSS as u64);
+
+        // 慢启动不会让拥塞窗口超过最大窗口
+        reno.on_ack(generate_acks(0, 20), now);
+        assert_eq!(reno.cwnd(), 12 * MSS as u64);
+
+        // 丢包与持续拥塞都不会让拥塞窗口低于最小窗口
+        let lost = SentPkt {
+            pn: 20,
+            size: MSS,
+            time_sent: now,
+            ..Default::default()
+        };
+        reno.on_congestion_event(&lost, now + std::time::Duration::from_millis(100));
+        assert_eq!(reno.cwnd(), 6 * MSS as u64);
+        reno.on_persistent_congestion();
+        assert_eq!(reno.cwnd(), 4 * MSS as u64);
+
+        // 初始窗口被限制在最小与最大窗口之间
+        let config = CongestionConfig {
+            initial_window: Some(64 * MSS as u64),
+            ..config
+        };
+        assert_eq!(NewReno::with_config(&config).cwnd(), 12 * MSS as u64);
+    }
+
+    fn generate_sent(start: u64, end: u64, time_sent: Instant) -> Vec<SentPkt> {
+        (start..end)
+            .map(|pn| SentPkt {
+                pn,
+                size: MSS,
+                time_sent,
+                ..Default::default()
+            })
+            .collect()
+    }
+
     fn generate_acks(start: usize, end: usize) -> VecDeque<AckedPkt> {
         let mut acks = VecDeque::with_capacity(end - start);
         for i in start..end {
@@ -8,6 +8,44 @@ const MAX_BURST_SIZE: u64 = 128;
 // ensures that variations in RTT do not result in underutilization of the congestion window.
 const N: f64 = 1.25;
 
+/// The pacing rate given by a congestion control algorithm, in bytes per second.
+///
+/// The sending task can use it to compute the delay between packets, so that packets are spread
+/// out over time rather than sent in bursts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PacingRate(u64);
+
+impl PacingRate {
+    /// Create a pacing rate from bytes per second.
+    pub fn from_bytes_per_sec(rate: u64) -> Self {
+        Self(rate)
+    }
+
+    /// Derive the pacing rate from the congestion window and the smoothed RTT, for the algorithms
+    /// which do not give a pacing rate.
+    ///
+    /// See [section 7.7](https://www.rfc-editor.org/rfc/rfc9002.html#name-pacing) of RFC 9002.
+    pub(crate) fn from_window(cwnd: u64, smoothed_rtt: Duration) -> Self {
+        // rate = N * congestion_window / smoothed_rtt
+        let rtt = smoothed_rtt.as_secs_f64().max(f64::EPSILON);
+        Self((N * cwnd as f64 / rtt) as u64)
+    }
+
+    /// Returns the pacing rate in bytes per second.
+    pub fn bytes_per_sec(&self) -> u64 {
+        self.0
+    }
+
+    /// Returns the time it takes to send `size` bytes at this rate, that is, how long the
+    /// sending task should wait before sending the next packet.
+    pub fn delay(&self, size: usize) -> Duration {
+        if self.0 == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(size as f64 / self.0 as f64)
+    }
+}
+
 pub(super) struct Pacer {
     capacity: u64,
     cwnd: u64,
@@ -63,8 +101,7 @@ impl Pacer {
         let rate = match rate {
             Some(r) => r,
             // RFC 9002 7.7. Pacing
-            // rate = N * congestion_window / smoothed_rtt
-            None => (N * cwnd as f64 / srtt.as_secs_f64()) as u64,
+            None => PacingRate::from_window(cwnd, srtt).bytes_per_sec(),
         };
 
         // Update the last_burst_time and tokens
@@ -79,6 +116,29 @@ impl Pacer {
         self.tokens.min(mtu as u64) as usize
     }
 
+    // 距离令牌足够发送一个mtu大小的包还需要等待多久，不改变pacer的状态
+    pub(super) fn delay(
+        &self,
+        srtt: Duration,
+        cwnd: u64,
+        mtu: usize,
+        now: Instant,
+        rate: Option<u64>,
+    ) -> Duration {
+        let rate = match rate {
+            Some(r) => r,
+            None => PacingRate::from_window(cwnd, srtt).bytes_per_sec(),
+        };
+        let elapsed = now.saturating_duration_since(self.last_burst_time);
+        let tokens = self
+            .tokens
+            .saturating_add((elapsed.as_secs_f64() * rate as f64) as u64);
+        if tokens >= mtu as u64 {
+            return Duration::ZERO;
+        }
+        PacingRate::from_bytes_per_sec(rate).delay(mtu - tokens as usize)
+    }
+
     fn calculate_capacity(smoothed_rtt: Duration, cwnd: u64, mtu: usize, rate: Option<u64>) -> u64 {
         let rtt = smoothed_rtt.as_nanos().max(1);
 
@@ -100,6 +160,25 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_pacing_rate_delay() {
+        let rate = PacingRate::from_bytes_per_sec(1_200_000);
+        assert_eq!(rate.delay(1200), Duration::from_millis(1));
+        assert_eq!(rate.delay(12_000), Duration::from_millis(10));
+        assert_eq!(
+            PacingRate::from_bytes_per_sec(0).delay(1200),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn test_pacing_rate_from_window() {
+        // 1.25 * 120_000 / 100ms
+        let rate = PacingRate::from_window(120_000, Duration::from_millis(100));
+        assert_eq!(rate.bytes_per_sec(), 1_500_000);
+        assert_eq!(rate.delay(1500), Duration::from_millis(1));
+    }
+
     #[test]
     fn test_pacer_initialization() {
         let now = Instant::now();
@@ -187,6 +266,28 @@ mod tests {
         assert_eq!(packet_size, 1500);
     }
 
+    #[test]
+    fn test_delay() {
+        let srtt = Duration::from_millis(100);
+        let cwnd = 2_000_000;
+        let mtu: usize = 1500;
+        let now = Instant::now();
+        // 16MB/s
+        let rate = Some(16_000_000);
+        let mut pacer = Pacer::new(srtt, cwnd, mtu, now, rate);
+        assert_eq!(pacer.delay(srtt, cwnd, mtu, now, rate), Duration::ZERO);
+
+        pacer.on_sent(16_000);
+        let delay = pacer.delay(srtt, cwnd, mtu, now, rate);
+        assert_eq!(delay, Duration::from_secs_f64(1500.0 / 16_000_000.0));
+        // 等待足够久之后，令牌足以发送一个包
+        assert_eq!(pacer.schedule(srtt, cwnd, mtu, now + delay, rate), mtu);
+        assert_eq!(
+            pacer.delay(srtt, cwnd, mtu, now + delay, rate),
+            Duration::ZERO
+        );
+    }
+
     #[test]
     fn test_schedule_with_rate() {
         let srtt = Duration::from_millis(100);
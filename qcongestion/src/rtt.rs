@@ -3,13 +3,48 @@ use std::{
     time::{Duration, Instant},
 };
 
+/// The RTT assumed before the first RTT sample is taken, see
+/// [Section 6.2.2](https://www.rfc-editor.org/rfc/rfc9002.html#name-handshakes-and-new-paths) of RFC 9002.
 pub const INITIAL_RTT: Duration = Duration::from_millis(333);
 const GRANULARITY: Duration = Duration::from_millis(1);
-const TIME_THRESHOLD: f32 = 1.125;
+/// The time threshold of the loss detection: an unacknowledged packet sent before an acknowledged
+/// one is declared lost once it has been in flight for longer than this multiple of
+/// `max(smoothed_rtt, latest_rtt)`, see
+/// [Section 6.1.2](https://www.rfc-editor.org/rfc/rfc9002.html#name-time-threshold) of RFC 9002.
+pub const TIME_THRESHOLD: f32 = 1.125;
+/// The default value of the peer's `max_ack_delay` transport parameter, used until the
+/// transport parameters of the peer are received.
+const DEFAULT_MAX_ACK_DELAY: Duration = Duration::from_millis(25);
+/// The default value of the peer's `ack_delay_exponent` transport parameter.
+const DEFAULT_ACK_DELAY_EXPONENT: u8 = 3;
+/// Values of the `max_ack_delay` transport parameter of 2^14 or greater are invalid, so no
+/// valid ack delay exceeds it.
+const MAX_ACK_DELAY_LIMIT: Duration = Duration::from_millis(1 << 14);
 
+/// A snapshot of the RTT estimation of a path, for metrics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RttSample {
+    /// The most recent RTT sample.
+    pub latest_rtt: Duration,
+    /// The exponentially weighted moving average of the RTT samples.
+    pub smoothed_rtt: Duration,
+    /// The variation of the RTT samples.
+    pub rttvar: Duration,
+    /// The minimum RTT observed on the path, ignoring the ack delay.
+    pub min_rtt: Duration,
+}
+
+/// The RTT estimator of a path.
+///
+/// It's updated with the RTT sample and the ack delay carried by each AckFrame that newly
+/// acknowledges the largest packet number, and provides the PTO duration.
+///
+/// See [section 5](https://www.rfc-editor.org/rfc/rfc9002#name-estimating-the-round-trip-t)
+/// of [RFC 9002](https://www.rfc-editor.org/rfc/rfc9002) for more details.
 #[derive(Debug, Clone)]
-pub struct Rtt {
+pub struct RttEstimator {
     max_ack_delay: Duration,
+    ack_delay_exponent: u8,
     first_rtt_sample: Option<Instant>,
     latest_rtt: Duration,
     smoothed_rtt: Duration,
@@ -18,21 +53,27 @@ pub struct Rtt {
     is_handshake_confirmed: bool,
 }
 
-impl Default for Rtt {
+impl Default for RttEstimator {
     fn default() -> Self {
+        Self::with_initial_rtt(INITIAL_RTT)
+    }
+}
+
+impl RttEstimator {
+    /// Create a new estimator which assumes the `initial_rtt` before the first RTT sample.
+    pub fn with_initial_rtt(initial_rtt: Duration) -> Self {
         Self {
-            max_ack_delay: Duration::from_millis(0),
+            max_ack_delay: DEFAULT_MAX_ACK_DELAY,
+            ack_delay_exponent: DEFAULT_ACK_DELAY_EXPONENT,
             first_rtt_sample: None,
             latest_rtt: Duration::from_millis(0),
-            smoothed_rtt: INITIAL_RTT,
-            rttvar: INITIAL_RTT / 2,
+            smoothed_rtt: initial_rtt,
+            rttvar: initial_rtt / 2,
             min_rtt: Duration::from_millis(0),
             is_handshake_confirmed: false,
         }
     }
-}
 
-impl Rtt {
     fn update(&mut self, latest_rtt: Duration, mut ack_delay: Duration) {
         self.latest_rtt = latest_rtt;
         if self.first_rtt_sample.is_none() {
@@ -76,15 +117,41 @@ impl Rtt {
             GRANULARITY,
         )
     }
+
+    fn pto(&self, with_max_ack_delay: bool) -> Duration {
+        let mut pto = self.smoothed_rtt + std::cmp::max(self.rttvar * 4, GRANULARITY);
+        if with_max_ack_delay {
+            pto += self.max_ack_delay;
+        }
+        pto
+    }
+
+    fn sample(&self) -> RttSample {
+        RttSample {
+            latest_rtt: self.latest_rtt,
+            smoothed_rtt: self.smoothed_rtt,
+            rttvar: self.rttvar,
+            min_rtt: self.min_rtt,
+        }
+    }
 }
 
+/// The shared version of [`RttEstimator`].
+///
+/// It's owned by the path, and shared with the congestion controller of the path.
 #[derive(Debug, Clone, Default)]
-pub struct ArcRtt(Arc<Mutex<Rtt>>);
+pub struct ArcRtt(Arc<Mutex<RttEstimator>>);
 
-/// 对外只需暴露ArcRtt，Rtt成为内部实现
 impl ArcRtt {
     pub fn new() -> Self {
-        Self(Arc::new(Mutex::new(Rtt::default())))
+        Self(Arc::new(Mutex::new(RttEstimator::default())))
+    }
+
+    /// Create a new estimator which assumes the `initial_rtt` before the first RTT sample.
+    pub fn with_initial_rtt(initial_rtt: Duration) -> Self {
+        Self(Arc::new(Mutex::new(RttEstimator::with_initial_rtt(
+            initial_rtt,
+        ))))
     }
 
     pub fn update(&self, latest_rtt: Duration, ack_delay: Duration) {
@@ -99,6 +166,49 @@ impl ArcRtt {
         self.0.lock().unwrap().on_handshake_done();
     }
 
+    /// Set the `max_ack_delay` transport parameter of the peer, which limits the ack delay
+    /// of the RTT samples after the handshake is confirmed, and extends the PTO duration of
+    /// the data space.
+    pub fn set_max_ack_delay(&self, max_ack_delay: Duration) {
+        self.0.lock().unwrap().max_ack_delay = max_ack_delay;
+    }
+
+    pub fn max_ack_delay(&self) -> Duration {
+        self.0.lock().unwrap().max_ack_delay
+    }
+
+    /// Set the `ack_delay_exponent` transport parameter of the peer, which scales the
+    /// `Ack Delay` field of the AckFrames sent by the peer.
+    pub fn set_ack_delay_exponent(&self, ack_delay_exponent: u8) {
+        self.0.lock().unwrap().ack_delay_exponent = ack_delay_exponent;
+    }
+
+    /// Decode the `Ack Delay` field of an AckFrame sent by the peer into a duration.
+    ///
+    /// The field is chosen by the peer, the result is clamped to the peer's `max_ack_delay` once
+    /// the handshake is confirmed, and to 2^14 milliseconds, the limit of `max_ack_delay`, before
+    /// that.
+    pub fn decode_ack_delay(&self, delay: u64) -> Duration {
+        let rtt = self.0.lock().unwrap();
+        // 对端给出的值可能很大，移位会溢出
+        let scale = 1u64
+            .checked_shl(rtt.ack_delay_exponent as u32)
+            .unwrap_or(u64::MAX);
+        let ack_delay = Duration::from_micros(delay.saturating_mul(scale));
+        match rtt.is_handshake_confirmed {
+            true => ack_delay.min(rtt.max_ack_delay),
+            false => ack_delay.min(MAX_ACK_DELAY_LIMIT),
+        }
+    }
+
+    /// Returns the PTO duration without backoff.
+    ///
+    /// The peer's `max_ack_delay` is only included for the data space, once the handshake
+    /// is confirmed.
+    pub fn pto(&self, with_max_ack_delay: bool) -> Duration {
+        self.0.lock().unwrap().pto(with_max_ack_delay)
+    }
+
     pub fn smoothed_rtt(&self) -> Duration {
         self.0.lock().unwrap().smoothed_rtt
     }
@@ -106,7 +216,99 @@ impl ArcRtt {
     pub fn rttvar(&self) -> Duration {
         self.0.lock().unwrap().rttvar
     }
+
+    pub fn sample(&self) -> RttSample {
+        self.0.lock().unwrap().sample()
+    }
+
+    /// Returns the time when the first RTT sample was taken, [`None`] if there is no sample yet.
+    pub fn first_sample_time(&self) -> Option<Instant> {
+        self.0.lock().unwrap().first_rtt_sample
+    }
+
+    /// Take over the RTT estimation of `other`, for a new path which is known to have the same
+    /// RTT, such as a path whose peer address is only changed by a NAT rebinding.
+    pub fn inherit(&self, other: &ArcRtt) {
+        let estimator = other.0.lock().unwrap().clone();
+        *self.0.lock().unwrap() = estimator;
+    }
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+
+    fn assert_near(actual: Duration, expected: Duration) {
+        let diff = actual.max(expected) - actual.min(expected);
+        assert!(
+            diff < Duration::from_micros(10),
+            "{actual:?} != {expected:?}"
+        );
+    }
+
+    #[test]
+    fn test_first_rtt_sample() {
+        let rtt = ArcRtt::new();
+        assert_eq!(rtt.pto(false), INITIAL_RTT * 3);
+
+        rtt.update(Duration::from_millis(100), Duration::from_millis(10));
+        let sample = rtt.sample();
+        assert_eq!(sample.latest_rtt, Duration::from_millis(100));
+        assert_eq!(sample.smoothed_rtt, Duration::from_millis(100));
+        assert_eq!(sample.rttvar, Duration::from_millis(50));
+        assert_eq!(sample.min_rtt, Duration::from_millis(100));
+        assert_eq!(rtt.pto(false), Duration::from_millis(300));
+        assert_eq!(rtt.pto(true), Duration::from_millis(325));
+    }
+
+    #[test]
+    fn test_decode_ack_delay() {
+        let rtt = ArcRtt::new();
+        assert_eq!(rtt.decode_ack_delay(1000), Duration::from_millis(8));
+        rtt.set_ack_delay_exponent(0);
+        assert_eq!(rtt.decode_ack_delay(1000), Duration::from_millis(1));
+
+        // 对端给出的超大值不会溢出
+        rtt.set_ack_delay_exponent(20);
+        let max_varint = (1 << 62) - 1;
+        assert_eq!(rtt.decode_ack_delay(max_varint), MAX_ACK_DELAY_LIMIT);
+        rtt.set_max_ack_delay(Duration::from_millis(10));
+        rtt.on_handshake_done();
+        assert_eq!(rtt.decode_ack_delay(max_varint), Duration::from_millis(10));
+        rtt.set_ack_delay_exponent(3);
+        assert_eq!(rtt.decode_ack_delay(1000), Duration::from_millis(8));
+    }
+
+    #[test]
+    fn test_ack_delay_clamped_by_max_ack_delay() {
+        let rtt = ArcRtt::new();
+        rtt.set_max_ack_delay(Duration::from_millis(10));
+        rtt.update(Duration::from_millis(100), Duration::ZERO);
+
+        // before the handshake is confirmed, the ack delay is not limited:
+        // adjusted_rtt = 160 - 60 = 100
+        rtt.update(Duration::from_millis(160), Duration::from_millis(60));
+        assert_near(rtt.smoothed_rtt(), Duration::from_millis(100));
+
+        rtt.on_handshake_done();
+        // adjusted_rtt = 160 - min(60, 10) = 150
+        rtt.update(Duration::from_millis(160), Duration::from_millis(60));
+        let sample = rtt.sample();
+        assert_near(sample.smoothed_rtt, Duration::from_micros(106_250));
+        assert_eq!(sample.min_rtt, Duration::from_millis(100));
+        assert_eq!(sample.latest_rtt, Duration::from_millis(160));
+    }
+
+    #[test]
+    fn test_inherit() {
+        let rtt = ArcRtt::new();
+        rtt.set_max_ack_delay(Duration::from_millis(10));
+        rtt.update(Duration::from_millis(100), Duration::ZERO);
+
+        let rebound = ArcRtt::new();
+        rebound.inherit(&rtt);
+        assert_eq!(rebound.sample(), rtt.sample());
+        assert_eq!(rebound.max_ack_delay(), Duration::from_millis(10));
+        assert_eq!(rebound.pto(false), rtt.pto(false));
+    }
+}
@@ -0,0 +1,20 @@
+use std::{future::Future, pin::Pin, time::Instant};
+
+pub use qbase::time::{ArcClock, Clock, MockClock};
+
+/// The clock backed by the tokio runtime, which is the default [`Clock`] of the connections.
+///
+/// The time is read from the system, and the sleeps are driven by the timer of the tokio
+/// runtime, so it also follows the paused time of the tokio runtime in tests.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TokioClock;
+
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        tokio::time::Instant::now().into_std()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(tokio::time::sleep_until(deadline.into()))
+    }
+}
@@ -4,19 +4,23 @@ use std::{
     io,
     ops::{Deref, DerefMut},
     sync::{Arc, Mutex},
-    time::{Duration, Instant},
+    time::Duration,
 };
 
-use closing::ClosingConnection;
+use builder::ConnectionBuilder;
+use bytes::Bytes;
+use closing::{CcfPackets, ClosingConnection, RcvdCcf};
 use draining::DrainingConnection;
-use futures::channel::mpsc;
+use futures::{channel::mpsc, Stream};
 use qbase::{
     cid::{self, ConnectionId},
     error::{Error, ErrorKind},
-    packet::{DataPacket, RetryHeader},
+    frame::{ConnectionCloseFrame, FrameType},
+    packet::{DataPacket, Ecn, RetryPacket, VersionNegotiationHeader},
     param::Parameters,
-    sid::{Role, StreamId},
+    sid::StreamId,
     token::ArcTokenRegistry,
+    varint::VarInt,
 };
 use qrecovery::{
     recv,
@@ -24,27 +28,37 @@ use qrecovery::{
     send,
     streams::{self, Ext},
 };
-use qunreliable::{DatagramReader, DatagramWriter};
+use qunreliable::{DatagramError, DatagramReader, DatagramSendPolicy, DatagramWriter};
 use raw::Connection;
-use tokio::task::JoinHandle;
+use rustls::pki_types::CertificateDer;
+use state::{ArcConnectionState, ConnectionState};
+use stats::ConnectionStats;
+use tokio::{sync::watch, task::JoinHandle};
 
 use crate::{
+    clock::ArcClock,
     conn::ConnState::{Closed, Closing, Draining, Invalid, Normal},
-    path::Pathway,
+    error::{CloseReason, ConnError},
+    path::{PathEvent, PathScheduler, Pathway},
     router::{Router, RouterRegistry},
-    tls::ArcTlsSession,
     usc::ArcUsc,
 };
 
+pub mod ack_frequency;
+pub mod builder;
 pub mod closing;
 pub mod draining;
+pub mod idle;
 pub mod parameters;
 pub mod raw;
 pub mod scope;
+pub mod state;
+pub mod stats;
 pub mod transmit;
+pub mod version;
 
-pub type PacketEntry = mpsc::UnboundedSender<(DataPacket, Pathway, ArcUsc)>;
-pub type RcvdPackets = mpsc::UnboundedReceiver<(DataPacket, Pathway, ArcUsc)>;
+pub type PacketEntry = mpsc::UnboundedSender<(DataPacket, Ecn, Pathway, ArcUsc)>;
+pub type RcvdPackets = mpsc::UnboundedReceiver<(DataPacket, Ecn, Pathway, ArcUsc)>;
 
 pub type ArcLocalCids = cid::ArcLocalCids<RouterRegistry<ArcReliableFrameDeque>>;
 pub type ArcRemoteCids = cid::ArcRemoteCids<ArcReliableFrameDeque>;
@@ -68,6 +82,7 @@ impl ConnState {
     fn try_enter_closing(
         &mut self,
         error: Error,
+        ccf: ConnectionCloseFrame,
     ) -> Option<([JoinHandle<RcvdPackets>; 4], Duration)> {
         let conn = core::mem::replace(self, Invalid);
         let Normal(connection) = conn else {
@@ -79,13 +94,16 @@ impl ConnState {
 
         let pto_time = connection.max_pto_duration().unwrap();
         // 尝试进入Closing状态
-        let hs = connection.hs.try_into().ok();
+        let version = connection.version();
+        let hs = (connection.hs.try_into().ok())
+            .map(|hs: ClosingHandshakeScope| hs.with_version(version));
         let one_rtt = connection.data.try_into().ok();
 
         let recv_packets = connection.join_handles;
 
         *self = match (hs, one_rtt) {
             (None, None) => {
+                connection.pathes.release();
                 let local_cids = connection.cid_registry.local.active_cids();
                 let draining_connection = DrainingConnection::new(local_cids, error);
                 Draining(draining_connection)
@@ -94,8 +112,16 @@ impl ConnState {
                 let local_cids = connection.cid_registry.local.active_cids();
                 let initial_scid = connection.initial_scid;
                 let last_dcid = connection.cid_registry.remote.latest_dcid();
-                let closing_connection =
-                    ClosingConnection::new(error, local_cids, hs, one_rtt, initial_scid, last_dcid);
+                let closing_connection = ClosingConnection::new(
+                    error,
+                    &ccf,
+                    local_cids,
+                    hs,
+                    one_rtt,
+                    initial_scid,
+                    last_dcid,
+                    connection.clock.clone(),
+                );
                 tokio::spawn({
                     let pathes = connection.pathes;
                     let closing_connection = closing_connection.clone();
@@ -104,6 +130,8 @@ impl ConnState {
                             let (pathway, path) = path.pair_mut();
                             closing_connection.send_ccf(path.usc(), *pathway).await;
                         }
+                        // 之后收到的包由Router交给closing_connection，不再需要路径
+                        pathes.release();
                     }
                 });
                 Closing(closing_connection)
@@ -113,7 +141,9 @@ impl ConnState {
         Some((recv_packets, pto_time))
     }
 
-    fn enter_draining(&mut self, error: Error) -> Option<Duration> {
+    /// Enter the draining state from the raw state, if `reply` is true, a CONNECTION_CLOSE frame
+    /// with NO_ERROR is sent once in response to the received one.
+    fn enter_draining(&mut self, error: Error, reply: bool) -> Option<Duration> {
         let conn = core::mem::replace(self, Invalid);
         let Normal(connection) = conn else {
             // has been closing/draining
@@ -122,40 +152,93 @@ impl ConnState {
         };
         connection.abort_with_error(&error);
 
+        let pto_time = connection.max_pto_duration();
         let local_cids = connection.cid_registry.local.active_cids();
+        let last_dcid = connection.cid_registry.remote.latest_dcid();
+        let active_path = connection
+            .pathes
+            .active_pathway()
+            .zip(connection.pathes.active_path());
+        if let (true, Some(last_dcid), Some((pathway, path))) = (reply, last_dcid, active_path) {
+            let version = connection.version();
+            let hs = (connection.hs.try_into().ok())
+                .map(|hs: ClosingHandshakeScope| hs.with_version(version));
+            let one_rtt: Option<ClosingOneRttScope> = connection.data.try_into().ok();
+            let ccf = ConnectionCloseFrame::new_quic(ErrorKind::None, FrameType::Padding, "");
+            let initial_scid = connection.initial_scid;
+            let ccf_packets =
+                CcfPackets::new(hs.as_ref(), one_rtt.as_ref(), &ccf, last_dcid, initial_scid);
+            let usc = path.usc().clone();
+            // 只在当前路径上回复一次，之后不再发送任何包
+            tokio::spawn(async move { ccf_packets.send_via(&usc, pathway).await });
+        }
+        connection.pathes.release();
         *self = Draining(DrainingConnection::new(local_cids, error));
 
-        connection.max_pto_duration()
+        pto_time
+    }
+
+    /// Receiving a CONNECTION_CLOSE frame in the closing state, enter the draining state, no more
+    /// CONNECTION_CLOSE frame will be sent.
+    ///
+    /// Return `false` if the connection is not in the closing state.
+    fn closing_to_draining(&mut self) -> bool {
+        let conn = core::mem::replace(self, Invalid);
+        let Closing(closing) = conn else {
+            *self = conn;
+            return false;
+        };
+        *self = Draining(DrainingConnection::new(closing.local_cids, closing.error));
+        true
     }
 
     fn no_vaiable_path(&mut self) {
+        let error = Error::with_default_fty(ErrorKind::NoViablePath, "No viable path");
+        self.close_silently(error);
+    }
+
+    /// Discard the connection state immediately, without sending a CONNECTION_CLOSE frame or
+    /// entering the closing/draining state.
+    fn close_silently(&mut self, error: Error) {
         let conn = core::mem::replace(self, Invalid);
         // no need to reset the state to conn
         let Normal(connection) = conn else { return };
-        let error = Error::with_default_fty(ErrorKind::NoViablePath, "No viable path");
         connection.abort_with_error(&error);
 
+        connection.pathes.release();
         let local_cids = &connection.cid_registry.local;
         local_cids.active_cids().iter().for_each(Router::remove);
         *self = Closed(error)
     }
 
+    /// The closing or draining period ends, all the resources of the connection are released,
+    /// only the error is kept to answer the application.
     fn die(&mut self) {
         let conn = core::mem::replace(self, Invalid);
-        let local_cids = match conn {
-            Closing(conn) => conn.local_cids,
-            Draining(conn) => conn.local_cids,
-            Closed(..) => return,
+        let (local_cids, error) = match conn {
+            Closing(conn) => (conn.local_cids, conn.error),
+            Draining(conn) => (conn.local_cids, conn.error),
+            Closed(error) => {
+                *self = Closed(error);
+                return;
+            }
             Normal(..) | Invalid => unreachable!(),
         };
 
         for cid in local_cids {
             Router::remove(&cid);
         }
+        *self = Closed(error);
     }
 }
 #[derive(Clone)]
-pub struct ArcConnection(Arc<Mutex<ConnState>>);
+pub struct ArcConnection(
+    Arc<Mutex<ConnState>>,
+    ArcConnectionState,
+    ConnError,
+    // 关闭后的计时器仍使用连接的时钟
+    ArcClock,
+);
 
 impl Debug for ArcConnection {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -164,38 +247,24 @@ impl Debug for ArcConnection {
 }
 
 impl ArcConnection {
+    /// Create a new client connection with the default settings, a shortcut of
+    /// [`ConnectionBuilder::client`].
+    ///
+    /// Use the [`ConnectionBuilder`] to choose the versions, the congestion control algorithm,
+    /// the session cache for 0-RTT or the qlog sink.
     pub fn new_client(
         initial_scid: ConnectionId,
         server_name: String,
-        mut parameters: Parameters,
+        parameters: Parameters,
         streams_ctrl: Box<dyn qbase::sid::ControlConcurrency>,
         tls_config: Arc<rustls::ClientConfig>,
         token_registry: ArcTokenRegistry,
     ) -> Self {
-        let Ok(server_name) = server_name.try_into() else {
-            panic!("server_name is not valid")
-        };
-
-        parameters.set_initial_source_connection_id(Some(initial_scid));
-
-        let initial_dcid = ConnectionId::random_gen(8);
-        let tls_session = ArcTlsSession::new_client(server_name, tls_config.clone(), &parameters);
-        let initial_keys = ArcTlsSession::initial_keys(
-            tls_config.crypto_provider(),
-            rustls::Side::Client,
-            initial_dcid,
-        );
-        let connection = Connection::new(
-            Role::Client,
-            parameters,
-            tls_session,
-            initial_scid,
-            initial_dcid,
-            initial_keys,
-            streams_ctrl,
-            token_registry,
-        );
-        connection.into()
+        ConnectionBuilder::client(initial_scid, server_name, tls_config)
+            .with_parameters(parameters)
+            .with_streams_controller(streams_ctrl)
+            .with_token_registry(token_registry)
+            .build()
     }
 
     pub fn add_initial_path(&self, pathway: Pathway, usc: ArcUsc) {
@@ -205,29 +274,27 @@ impl ArcConnection {
         }
     }
 
+    /// Create a new server connection with the default settings, a shortcut of
+    /// [`ConnectionBuilder::server`].
+    ///
+    /// The `original_destination_connection_id` and `retry_source_connection_id` in the
+    /// `parameters` should be set by the caller, which knows whether the client has been retried.
+    ///
+    /// Use the [`ConnectionBuilder`] to choose the congestion control algorithm or the qlog sink.
     pub fn new_server(
         initial_scid: ConnectionId,
         initial_dcid: ConnectionId,
         initial_keys: rustls::quic::Keys,
-        mut parameters: Parameters,
+        parameters: Parameters,
         streams_ctrl: Box<dyn qbase::sid::ControlConcurrency>,
         tls_config: Arc<rustls::ServerConfig>,
         token_registry: ArcTokenRegistry,
     ) -> Self {
-        parameters.set_original_destination_connection_id(Some(initial_dcid));
-
-        let tls_session = ArcTlsSession::new_server(tls_config.clone(), &parameters);
-        let connection = Connection::new(
-            Role::Server,
-            parameters,
-            tls_session,
-            initial_scid,
-            initial_dcid,
-            initial_keys,
-            streams_ctrl,
-            token_registry,
-        );
-        connection.into()
+        ConnectionBuilder::server(initial_scid, initial_dcid, initial_keys, tls_config)
+            .with_parameters(parameters)
+            .with_streams_controller(streams_ctrl)
+            .with_token_registry(token_registry)
+            .build()
     }
 
     pub async fn open_bi_stream(
@@ -244,13 +311,13 @@ impl ArcConnection {
             };
 
             (
-                connection.params.remote.clone(),
+                connection.params.clone(),
                 connection.streams.clone(),
                 connection.error.clone(),
             )
         };
 
-        let remote_params = remote_params.read().await?;
+        let remote_params = remote_params.read_for_0rtt().await?;
 
         let result = data_streams
             .open_bi(remote_params.initial_max_stream_data_bidi_remote().into())
@@ -271,13 +338,13 @@ impl ArcConnection {
             };
 
             (
-                connection.params.remote.clone(),
+                connection.params.clone(),
                 connection.streams.clone(),
                 connection.error.clone(),
             )
         };
 
-        let remote_params = remote_params.read().await?;
+        let remote_params = remote_params.read_for_0rtt().await?;
 
         let result = data_streams
             .open_uni(remote_params.initial_max_stream_data_uni().into())
@@ -367,40 +434,136 @@ impl ArcConnection {
         datagram_flow.writer(remote_params.max_datagram_frame_size().into())
     }
 
+    /// Sends an unreliable datagram to the peer.
+    ///
+    /// The datagram is carried by a single DATAGRAM frame, it will never be retransmitted, but the
+    /// packets carrying it are still counted for congestion control. If the congestion window is
+    /// exhausted, the queued datagrams are dropped or kept according to the policy set by
+    /// [`ArcConnection::set_datagram_send_policy`].
+    ///
+    /// This method waits for the peer's transport parameters, the datagram is rejected with
+    /// [`DatagramError::Unsupported`] if the peer did not advertise `max_datagram_frame_size`, or
+    /// with [`DatagramError::TooLarge`] if it does not fit into a frame within the peer's limit.
+    pub async fn send_datagram(&self, data: Bytes) -> Result<(), DatagramError> {
+        self.datagram_writer().await?.try_send(data)
+    }
+
+    /// Returns a stream of datagrams received from the peer.
+    ///
+    /// Only one receiving stream can exist at the same time, see [`ArcConnection::datagram_reader`].
+    /// The stream ends if there is already one, or once the connection is closing or closed.
+    pub fn recv_datagram(&self) -> impl Stream<Item = Bytes> {
+        futures::stream::unfold(self.datagram_reader().ok(), |reader| async move {
+            let mut reader = reader?;
+            let data = reader.recv().await.ok()?;
+            Some((data, Some(reader)))
+        })
+    }
+
+    /// Returns a stream of the events of the paths, such as the validations and the migrations,
+    /// read [`PathEvent`] for more details.
+    ///
+    /// Only the events emitted after this call are received. The stream ends once the paths of
+    /// the connection are all gone, or at once if the connection is already closing or closed.
+    pub fn path_events(&self) -> impl Stream<Item = PathEvent> {
+        use futures::StreamExt;
+        let guard = self.0.lock().unwrap();
+        let events = match *guard {
+            Normal(ref connection) => Some(connection.path_events()),
+            _ => None,
+        };
+        futures::stream::iter(events).flatten()
+    }
+
     /// Gracefully closes the connection.
     ///
     /// Closes the connection with a specified error.
     /// This function is intended for use by the application layer to signal an
     /// error and initiate the connection closure.
+    ///
+    /// Calling this method multiple times is harmless, only the first call will
+    /// take effect, and the connection close frame will be sent only once.
+    ///
+    /// The application error code 0 is used, read [`ArcConnection::close_with_code`] to close
+    /// the connection with another application error code.
     pub fn close(&self, msg: impl Into<Cow<'static, str>>) {
+        self.close_with_code(VarInt::from_u32(0), msg);
+    }
+
+    /// Closes the connection with the application-defined `error_code` and the reason `msg`,
+    /// which are carried by the CONNECTION_CLOSE frame of type 0x1d.
+    ///
+    /// Before the handshake is confirmed, the peer receives a CONNECTION_CLOSE frame with the
+    /// transport error code APPLICATION_ERROR and an empty reason in the Handshake packet instead,
+    /// to avoid revealing the application state.
+    pub fn close_with_code(&self, error_code: VarInt, msg: impl Into<Cow<'static, str>>) {
         let mut guard = self.0.lock().unwrap();
         if let Normal(connection) = guard.deref_mut() {
-            let error = Error::with_default_fty(ErrorKind::Application, msg);
+            let msg = msg.into();
+            // only the first error takes effect, the others are ignored
+            if !connection.error.set_app_error(error_code, msg.clone()) {
+                return;
+            }
+            let error = Error::with_default_fty(ErrorKind::Application, msg.clone());
             log::info!("Connection is closed by application: {}", error);
-            connection.error.set_app_error(error.clone());
             drop(guard);
-            self.should_enter_closing(error);
+            let ccf = ConnectionCloseFrame::new_app(error_code, msg);
+            self.should_enter_closing(error, ccf);
         }
     }
 
+    /// Returns why the connection is closed, [`None`] if the connection is not closed yet.
+    ///
+    /// The reason tells whether the connection is closed by us or by the peer, with a transport
+    /// error code or an application error code, and the reason phrase. After the connection is
+    /// closed, the operations on the connection and its streams fail with the corresponding error.
+    pub fn close_reason(&self) -> Option<CloseReason> {
+        self.2.close_reason()
+    }
+
+    /// Gracefully closes the connection after the pending data is delivered.
+    ///
+    /// No new streams can be opened once this method is called, the streams that have been
+    /// opened can still be written. When all the data written to the streams has been
+    /// acknowledged by the peer, the connection is closed as [`ArcConnection::close`] does:
+    /// a CONNECTION_CLOSE frame is sent, the connection stays in the closing state for 3 times
+    /// of the PTO, and then all the resources are released.
+    ///
+    /// If the connection is closed by other reasons while waiting, such as idle timeout, the
+    /// method returns immediately.
+    pub async fn close_gracefully(&self, msg: impl Into<Cow<'static, str>>) {
+        let data_streams = {
+            let guard = self.0.lock().unwrap();
+            let Normal(connection) = guard.deref() else {
+                return;
+            };
+            connection.streams.clone()
+        };
+
+        data_streams.stop_opening();
+        data_streams.flushed().await;
+        self.close(msg);
+    }
+
     /// This function transitioning connection to a `Closing` state and
     /// initiating a background task to manage the closing handshake. This task awaits
     /// confirmation from the peer (Connection Close Frame) within a timeout derived
     /// from the connection's Path Termination Timeout (PTO).  Upon successful
     /// confirmation, any remaining data is drained.  If the timeout expires without
     /// confirmation, the connection is forcefully terminated.
-    fn should_enter_closing(&self, error: Error) {
+    fn should_enter_closing(&self, error: Error, ccf: ConnectionCloseFrame) {
         let mut guard = self.0.lock().unwrap();
         let state = guard.deref_mut();
         if !matches!(state, Normal(..)) {
             return;
         }
-        let Some((handles, pto)) = state.try_enter_closing(error) else {
+        let Some((handles, pto)) = state.try_enter_closing(error, ccf) else {
             return;
         };
 
         match state {
             Closing(closing) => {
+                _ = self.1.transition(ConnectionState::Closing);
                 tokio::spawn({
                     let mut closing = closing.clone();
                     async move {
@@ -410,23 +573,12 @@ impl ArcConnection {
                         let (r1, r2, r3, r4) = tokio::try_join!(h1, h2, h3, h4).unwrap();
                         drop((r1, r2)); // ccf in initial is turstless, 0rtt dont transmit ccf
                         let mut rcvd_packets = r3.chain(r4);
-                        while let Some((packet, pathway, usc)) = rcvd_packets.next().await {
+                        while let Some((packet, _ecn, pathway, usc)) = rcvd_packets.next().await {
                             closing.recv_packet_via_pathway(packet, pathway, usc).await;
                         }
                     }
                 });
-                tokio::spawn({
-                    let conn = self.clone();
-                    let rcvd_ccf = closing.get_rcvd_ccf();
-                    async move {
-                        let start = Instant::now();
-                        let time = pto * 3;
-                        match tokio::time::timeout(time, rcvd_ccf.did_recv()).await {
-                            Ok(_) => conn.draining(pto * 3 - start.elapsed()),
-                            Err(_) => conn.die(),
-                        }
-                    }
-                });
+                self.spawn_closing_timer(closing.get_rcvd_ccf(), pto);
             }
             Draining(..) => {
                 drop(guard);
@@ -437,8 +589,51 @@ impl ArcConnection {
         }
     }
 
-    pub fn enter_draining(&self, error: Error) {
-        let Some(pto) = self.0.lock().unwrap().deref_mut().enter_draining(error) else {
+    /// Wait for the CONNECTION_CLOSE frame from the peer in the closing state, which lasts for 3
+    /// times of the PTO.
+    ///
+    /// If the peer's CONNECTION_CLOSE frame is received, enter the draining state for the rest of
+    /// the period, otherwise the connection is released when the period ends.
+    fn spawn_closing_timer(&self, rcvd_ccf: RcvdCcf, pto: Duration) {
+        tokio::spawn({
+            let conn = self.clone();
+            let clock = self.3.clone();
+            async move {
+                let start = clock.now();
+                let time = pto * 3;
+                tokio::select! {
+                    _ = rcvd_ccf.did_recv() => {
+                        let elapsed = clock.now().saturating_duration_since(start);
+                        conn.closing_to_draining(time.saturating_sub(elapsed));
+                    }
+                    _ = clock.sleep_until(start + time) => conn.die(),
+                }
+            }
+        });
+    }
+
+    /// Enter the draining state from the closing state, for the `remaining` time of the closing
+    /// period.
+    fn closing_to_draining(&self, remaining: Duration) {
+        let mut guard = self.0.lock().unwrap();
+        if guard.closing_to_draining() {
+            drop(guard);
+            self.draining(remaining);
+        }
+    }
+
+    /// Enter the draining state from the raw state, for 3 times of the PTO.
+    ///
+    /// When a CONNECTION_CLOSE frame is received, a CONNECTION_CLOSE frame is sent once in
+    /// response, while a stateless reset is not responded.
+    pub fn enter_draining(&self, error: Error, reply: bool) {
+        let Some(pto) = self
+            .0
+            .lock()
+            .unwrap()
+            .deref_mut()
+            .enter_draining(error, reply)
+        else {
             // has been closed
             return;
         };
@@ -450,18 +645,189 @@ impl ArcConnection {
     /// Can only be called internally, and the app should not care this method.
     pub(crate) fn draining(&self, remaining: Duration) {
         assert!(matches!(self.0.lock().unwrap().deref_mut(), Draining(..)));
+        _ = self.1.transition(ConnectionState::Draining);
 
+        let sleep = self.3.sleep_until(self.3.now() + remaining);
         tokio::spawn({
             let conn = self.clone();
             async move {
-                tokio::time::sleep(remaining).await;
+                sleep.await;
                 conn.die();
             }
         });
     }
 
     pub(crate) fn no_vaiable_path(self) {
-        self.0.lock().unwrap().no_vaiable_path();
+        let mut guard = self.0.lock().unwrap();
+        guard.no_vaiable_path();
+        self.on_closed_silently(&guard);
+    }
+
+    /// The connection has been idle for too long, closed silently.
+    pub(crate) fn idle_timeout(self, error: Error) {
+        let mut guard = self.0.lock().unwrap();
+        guard.close_silently(error);
+        self.on_closed_silently(&guard);
+    }
+
+    /// The connection attempt is abandoned, closed silently.
+    pub(crate) fn abandon(self, error: Error) {
+        let mut guard = self.0.lock().unwrap();
+        guard.close_silently(error);
+        self.on_closed_silently(&guard);
+    }
+
+    // 只有从Normal状态静默关闭时才会直接进入Closed，Closing/Draining状态仍需等待其计时结束
+    fn on_closed_silently(&self, state: &ConnState) {
+        if matches!(state, Closed(..)) {
+            _ = self.1.transition(ConnectionState::Closed);
+        }
+    }
+
+    /// Abandon the path on the `pathway`, read [`Connection::abandon_path`] for more details.
+    ///
+    /// Return `false` if the connection is not in the normal state.
+    pub fn abandon_path(&self, pathway: Pathway) -> bool {
+        let guard = self.0.lock().unwrap();
+        match *guard {
+            Normal(ref connection) => connection.abandon_path(pathway),
+            _ => false,
+        }
+    }
+
+    /// Migrate the connection to the `pathway` deliberately, read [`Connection::migrate`] for more
+    /// details.
+    ///
+    /// Return `false` if the connection is not in the normal state, or the peer has disabled the
+    /// active migration.
+    pub fn migrate(&self, pathway: Pathway, usc: ArcUsc) -> bool {
+        let guard = self.0.lock().unwrap();
+        match *guard {
+            Normal(ref connection) => connection.migrate(pathway, usc),
+            _ => false,
+        }
+    }
+
+    /// Enable or disable the keep-alive of the connection, read [`Connection::set_keep_alive`]
+    /// for more details.
+    pub fn set_keep_alive(&self, interval: Option<Duration>) {
+        let guard = self.0.lock().unwrap();
+        if let Normal(ref connection) = *guard {
+            connection.set_keep_alive(interval);
+        }
+    }
+
+    /// Set the scheduler deciding which paths carry the new stream data, read
+    /// [`Connection::set_path_scheduler`] for more details.
+    pub fn set_path_scheduler(&self, scheduler: Arc<dyn PathScheduler>) {
+        let guard = self.0.lock().unwrap();
+        if let Normal(ref connection) = *guard {
+            connection.set_path_scheduler(scheduler);
+        }
+    }
+
+    /// Set what to do with the queued datagrams when the congestion window is exhausted, read
+    /// [`Connection::set_datagram_send_policy`] for more details.
+    pub fn set_datagram_send_policy(&self, policy: DatagramSendPolicy) {
+        let guard = self.0.lock().unwrap();
+        if let Normal(ref connection) = *guard {
+            connection.set_datagram_send_policy(policy);
+        }
+    }
+
+    /// Send a PING frame to the peer, read [`Connection::send_ping`] for more details.
+    pub fn send_ping(&self) {
+        let guard = self.0.lock().unwrap();
+        if let Normal(ref connection) = *guard {
+            connection.send_ping();
+        }
+    }
+
+    /// The transport parameters of the peer, read [`Connection::peer_transport_parameters`] for
+    /// more details.
+    ///
+    /// Return [`None`] if the parameters are not available yet, or the connection is closing,
+    /// draining or closed.
+    pub fn peer_transport_parameters(&self) -> Option<Arc<Parameters>> {
+        let guard = self.0.lock().unwrap();
+        match *guard {
+            Normal(ref connection) => connection.peer_transport_parameters(),
+            _ => None,
+        }
+    }
+
+    /// Our own transport parameters, read [`Connection::local_transport_parameters`] for more
+    /// details.
+    ///
+    /// Return [`None`] if the connection is closing, draining or closed.
+    pub fn local_transport_parameters(&self) -> Option<Arc<Parameters>> {
+        let guard = self.0.lock().unwrap();
+        match *guard {
+            Normal(ref connection) => Some(connection.local_transport_parameters()),
+            _ => None,
+        }
+    }
+
+    /// The application protocol negotiated by ALPN, such as `h3`, read
+    /// [`Connection::alpn_protocol`] for more details.
+    ///
+    /// Return [`None`] if no protocol is negotiated, or the handshake is not completed yet, wait
+    /// for [`ArcConnection::handshake_completed`] before calling it. After the connection is
+    /// closing, draining or closed, [`None`] is returned too.
+    pub fn alpn_protocol(&self) -> Option<Vec<u8>> {
+        let guard = self.0.lock().unwrap();
+        match *guard {
+            Normal(ref connection) => connection.alpn_protocol(),
+            _ => None,
+        }
+    }
+
+    /// The certificate chain presented by the peer, the end-entity certificate comes first, read
+    /// [`Connection::peer_certificates`] for more details.
+    ///
+    /// It can be used to authorize the peer beyond the certificate verifier of rustls, such as
+    /// pinning the server certificate, or identifying the client with mutual TLS. Return [`None`]
+    /// if the peer presents no certificate, or the handshake is not completed yet. After the
+    /// connection is closing, draining or closed, [`None`] is returned too.
+    pub fn peer_certificates(&self) -> Option<Vec<CertificateDer<'static>>> {
+        let guard = self.0.lock().unwrap();
+        match *guard {
+            Normal(ref connection) => connection.peer_certificates(),
+            _ => None,
+        }
+    }
+
+    /// For server, the server name requested by the client through SNI, which is available once
+    /// the ClientHello is received, read [`Connection::server_name`] for more details.
+    ///
+    /// Return [`None`] for client, or the client does not send SNI, or the connection is closing,
+    /// draining or closed.
+    pub fn server_name(&self) -> Option<String> {
+        let guard = self.0.lock().unwrap();
+        match *guard {
+            Normal(ref connection) => connection.server_name(),
+            _ => None,
+        }
+    }
+
+    /// Send a NEW_TOKEN frame to the client, read [`Connection::send_new_token`] for more details.
+    pub fn send_new_token(&self, token: Vec<u8>) {
+        let guard = self.0.lock().unwrap();
+        if let Normal(ref connection) = *guard {
+            connection.send_new_token(token);
+        }
+    }
+
+    /// Return a snapshot of the statistics of the connection, read [`Connection::stats`] for more
+    /// details.
+    ///
+    /// Return [`None`] if the connection is closing, draining or closed.
+    pub fn stats(&self) -> Option<ConnectionStats> {
+        let guard = self.0.lock().unwrap();
+        match *guard {
+            Normal(ref connection) => Some(connection.stats()),
+            _ => None,
+        }
     }
 
     /// Dismiss the connection, remove it from the global router.
@@ -470,6 +836,51 @@ impl ArcConnection {
     /// When the connection "die", is must enter the closing state or draining state first.
     fn die(self) {
         self.0.lock().unwrap().die();
+        _ = self.1.transition(ConnectionState::Closed);
+    }
+
+    /// Wait for the handshake to be completed.
+    ///
+    /// Resolves when the 1-RTT keys are installed and the peer's transport parameters have been
+    /// received, after which it is safe to send 1-RTT data. If the handshake fails, or the
+    /// connection is closed before the handshake completes, the connection error is returned.
+    pub async fn handshake_completed(&self) -> Result<(), Error> {
+        if self.1.connected().await {
+            return Ok(());
+        }
+
+        match self.0.lock().unwrap().deref() {
+            Closing(closing) => Err(closing.error.clone()),
+            Draining(draining) => Err(draining.error.clone()),
+            Closed(error) => Err(error.clone()),
+            // 状态机应先于ConnectionState切换，但没有什么能保证这一点，不能因此panic
+            Normal(..) | Invalid => Err(Error::with_default_fty(
+                ErrorKind::Internal,
+                "connection state is inconsistent",
+            )),
+        }
+    }
+
+    /// Returns the current lifecycle state of the connection.
+    pub fn state(&self) -> ConnectionState {
+        self.1.current()
+    }
+
+    /// Returns a receiver to observe the lifecycle state changes of the connection, read
+    /// [`ArcConnectionState::subscribe`] for more details.
+    pub fn subscribe(&self) -> watch::Receiver<ConnectionState> {
+        self.1.subscribe()
+    }
+
+    /// The address of the pathway has been validated, such as by a retry token, the
+    /// anti-amplification limit of the path is lifted.
+    pub fn on_address_validated(&self, pathway: Pathway) {
+        let guard = self.0.lock().unwrap();
+        if let Normal(ref connection) = *guard {
+            if let Some(path) = connection.pathes.try_get(&pathway).try_unwrap() {
+                path.anti_amplifier.grant();
+            }
+        }
     }
 
     pub fn update_path_recv_time(&self, pathway: Pathway) {
@@ -479,25 +890,20 @@ impl ArcConnection {
         }
     }
 
-    pub fn recv_retry_packet(&self, retry: &RetryHeader) {
+    /// Handle a Version Negotiation packet, read [`Connection::handle_version_negotiation`] for
+    /// more details.
+    pub fn recv_version_negotiation(&self, vn: &VersionNegotiationHeader) {
         let guard = self.0.lock().unwrap();
         if let Normal(ref connection) = *guard {
-            *connection.token.lock().unwrap() = retry.token.to_vec();
-            connection
-                .cid_registry
-                .remote
-                .revise_initial_dcid(retry.scid);
-            let sent_record = connection.initial.space.sent_packets();
-            let mut guard = sent_record.recv();
-            for i in 0..guard.largest_pn() {
-                for frame in guard.may_loss_pkt(i) {
-                    connection
-                        .initial
-                        .crypto_stream
-                        .outgoing()
-                        .may_loss_data(&frame);
-                }
-            }
+            connection.handle_version_negotiation(&vn.versions);
+        }
+    }
+
+    /// Handle a Retry packet, read [`Connection::handle_retry`] for more details.
+    pub fn recv_retry_packet(&self, retry: RetryPacket) {
+        let guard = self.0.lock().unwrap();
+        if let Normal(ref connection) = *guard {
+            connection.handle_retry(retry);
         }
     }
 
@@ -510,7 +916,14 @@ impl ArcConnection {
 impl From<Connection> for ArcConnection {
     fn from(normal_conn: Connection) -> Self {
         let conn_error = normal_conn.error.clone();
-        let connection = ArcConnection(Arc::new(Mutex::new(ConnState::Normal(normal_conn))));
+        let state = normal_conn.state.clone();
+        let clock = normal_conn.clock.clone();
+        let connection = ArcConnection(
+            Arc::new(Mutex::new(ConnState::Normal(normal_conn))),
+            state,
+            conn_error.clone(),
+            clock,
+        );
 
         tokio::spawn({
             let conn = connection.clone();
@@ -521,9 +934,16 @@ impl From<Connection> for ArcConnection {
                 };
                 match kind {
                     crate::error::ConnErrorKind::Application => {} // resolved by ArcConnection::close
-                    crate::error::ConnErrorKind::Transport => conn.should_enter_closing(err),
-                    crate::error::ConnErrorKind::CcfReceived => conn.enter_draining(err),
+                    crate::error::ConnErrorKind::Transport => {
+                        let ccf = ConnectionCloseFrame::from(err.clone());
+                        conn.should_enter_closing(err, ccf)
+                    }
+                    crate::error::ConnErrorKind::CcfReceived => conn.enter_draining(err, true),
                     crate::error::ConnErrorKind::NoViablePath => conn.no_vaiable_path(),
+                    crate::error::ConnErrorKind::IdleTimeout => conn.idle_timeout(err),
+                    crate::error::ConnErrorKind::HandshakeTimeout => conn.abandon(err),
+                    crate::error::ConnErrorKind::StatelessReset => conn.enter_draining(err, false),
+                    crate::error::ConnErrorKind::NoCommonVersion => conn.abandon(err),
                 }
             }
         });
@@ -532,4 +952,269 @@ impl From<Connection> for ArcConnection {
     }
 }
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use bytes::BytesMut;
+    use qbase::{
+        cid::RandomCidGenerator,
+        entropy::OsEntropy,
+        packet::{header::OneRttHeader, DataHeader, SpinBit},
+    };
+    use rustls::{ClientConfig, RootCertStore};
+
+    use super::*;
+    use crate::{
+        clock::{MockClock, TokioClock},
+        usc::UscRegistry,
+    };
+
+    fn is_routed(cid: ConnectionId, pathway: Pathway, usc: &ArcUsc) -> bool {
+        let packet = DataPacket {
+            header: DataHeader::Short(OneRttHeader {
+                spin: SpinBit::default(),
+                dcid: cid,
+            }),
+            bytes: BytesMut::new(),
+            offset: 0,
+        };
+        Router::try_to_route_packet_from(packet, Ecn::default(), pathway, usc).is_ok()
+    }
+
+    // 在全局路由中注册一个连接ID，模拟连接签发过的连接ID
+    fn registered_cid() -> ConnectionId {
+        let cid = ConnectionId::random_gen(8);
+        let (entry, _) = mpsc::unbounded();
+        Router::registry(
+            cid,
+            ArcReliableFrameDeque::with_capacity(0),
+            [entry.clone(), entry.clone(), entry.clone(), entry],
+            Arc::new(RandomCidGenerator::new(Arc::new(OsEntropy))),
+        );
+        cid
+    }
+
+    fn any_pathway() -> (Pathway, ArcUsc) {
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let pathway = Pathway::Direct {
+            local: usc.local_addr().unwrap(),
+            remote: "127.0.0.1:4433".parse().unwrap(),
+        };
+        (pathway, usc)
+    }
+
+    #[tokio::test]
+    async fn test_release_resources() {
+        let clock = MockClock::new();
+        let tls_config = Arc::new(
+            ClientConfig::builder()
+                .with_root_certificates(RootCertStore::empty())
+                .with_no_client_auth(),
+        );
+        let conn = ConnectionBuilder::client(
+            ConnectionId::random_gen(8),
+            "localhost".to_owned(),
+            tls_config,
+        )
+        .with_clock(Arc::new(clock.clone()))
+        .build();
+        let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let pathway = Pathway::Direct {
+            local: usc.local_addr().unwrap(),
+            remote: peer.local_addr().unwrap(),
+        };
+        conn.add_initial_path(pathway, usc.clone());
+        let mut datagram = [0u8; 1500];
+        let recv = peer.recv(&mut datagram);
+        tokio::time::timeout(Duration::from_secs(1), recv)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let (local_cids, pathes, path) = {
+            let guard = conn.0.lock().unwrap();
+            let Normal(connection) = guard.deref() else {
+                panic!("the connection is not normal");
+            };
+            let path = connection.pathes.get(&pathway).unwrap().clone();
+            let local_cids = connection.cid_registry.local.active_cids();
+            (local_cids, connection.pathes.clone(), path)
+        };
+        assert!(path.is_sending());
+
+        // 握手尚未开始，直接进入draining状态
+        conn.close("closed by test");
+        assert_eq!(conn.state(), ConnectionState::Draining);
+        // 路径立即停止发送，监视任务结束后从集合中移除
+        assert!(!path.is_sending());
+        let removed = async {
+            while !pathes.is_empty() {
+                tokio::task::yield_now().await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(1), removed)
+            .await
+            .unwrap();
+        // draining期间，本地的连接ID仍然被路由，对端的包被丢弃
+        assert!(local_cids.iter().all(|cid| is_routed(*cid, pathway, &usc)));
+
+        clock.advance(Duration::from_secs(60));
+        tokio::task::yield_now().await;
+        assert_eq!(conn.state(), ConnectionState::Closed);
+        assert!(matches!(conn.0.lock().unwrap().deref(), Closed(..)));
+        assert!(local_cids.iter().all(|cid| !is_routed(*cid, pathway, &usc)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_released_after_draining() {
+        let error = Error::with_default_fty(ErrorKind::Application, "closed by test");
+        let cid = registered_cid();
+        let (pathway, usc) = any_pathway();
+        let local_cids = vec![cid];
+        let conn = ArcConnection(
+            Arc::new(Mutex::new(Draining(DrainingConnection::new(
+                local_cids, error,
+            )))),
+            ArcConnectionState::default(),
+            ConnError::default(),
+            Arc::new(TokioClock),
+        );
+
+        conn.draining(Duration::from_millis(10));
+        tokio::time::advance(Duration::from_millis(9)).await;
+        tokio::task::yield_now().await;
+        assert!(matches!(conn.0.lock().unwrap().deref(), Draining(..)));
+        assert_eq!(conn.state(), ConnectionState::Draining);
+        assert!(is_routed(cid, pathway, &usc));
+
+        tokio::time::advance(Duration::from_millis(1)).await;
+        tokio::task::yield_now().await;
+        assert_eq!(conn.state(), ConnectionState::Closed);
+        assert!(!is_routed(cid, pathway, &usc));
+        // the connection is released, only the error is kept
+        assert!(matches!(
+            conn.0.lock().unwrap().deref(),
+            Closed(error) if error.kind() == ErrorKind::Application
+        ));
+        let Err(error) = conn.open_uni_stream().await else {
+            panic!("the closed connection can not open streams");
+        };
+        assert_eq!(error.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[tokio::test]
+    async fn test_handshake_failed() {
+        let error = Error::with_default_fty(ErrorKind::Crypto(42), "handshake failed");
+        let local_cids = vec![ConnectionId::random_gen(8)];
+        let conn = ArcConnection(
+            Arc::new(Mutex::new(Draining(DrainingConnection::new(
+                local_cids, error,
+            )))),
+            ArcConnectionState::default(),
+            ConnError::default(),
+            Arc::new(TokioClock),
+        );
+
+        let handshake_completed = tokio::spawn({
+            let conn = conn.clone();
+            async move { conn.handshake_completed().await }
+        });
+        conn.1.transition(ConnectionState::Handshaking).unwrap();
+        conn.draining(Duration::from_millis(10));
+        let error = handshake_completed.await.unwrap().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Crypto(42));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_completed_with_inconsistent_state() {
+        let conn = ArcConnection(
+            Arc::new(Mutex::new(Invalid)),
+            ArcConnectionState::default(),
+            ConnError::default(),
+            Arc::new(TokioClock),
+        );
+        // ConnectionState已经关闭，而状态机没有跟着切换，返回错误而不是panic
+        conn.1.transition(ConnectionState::Closing).unwrap();
+        let error = conn.handshake_completed().await.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Internal);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_closing_to_draining() {
+        let error = Error::with_default_fty(ErrorKind::Application, "closed by test");
+        let ccf = ConnectionCloseFrame::from(error.clone());
+        let cid = registered_cid();
+        let (pathway, usc) = any_pathway();
+        let local_cids = vec![cid];
+        let closing = ClosingConnection::new(
+            error,
+            &ccf,
+            local_cids,
+            None,
+            None,
+            ConnectionId::random_gen(8),
+            None,
+            Arc::new(TokioClock),
+        );
+        let conn = ArcConnection(
+            Arc::new(Mutex::new(Closing(closing.clone()))),
+            ArcConnectionState::default(),
+            ConnError::default(),
+            Arc::new(TokioClock),
+        );
+        conn.1.transition(ConnectionState::Closing).unwrap();
+
+        // closing状态持续3个PTO，即150ms
+        conn.spawn_closing_timer(closing.get_rcvd_ccf(), Duration::from_millis(50));
+        tokio::task::yield_now().await;
+        tokio::time::advance(Duration::from_millis(10)).await;
+        closing.get_rcvd_ccf().on_ccf_rcvd();
+        tokio::task::yield_now().await;
+        assert!(matches!(conn.0.lock().unwrap().deref(), Draining(..)));
+        assert_eq!(conn.state(), ConnectionState::Draining);
+
+        // 收到对端的CCF后，即使超过了重发间隔，也不再重发CCF
+        tokio::time::advance(Duration::from_millis(110)).await;
+        tokio::task::yield_now().await;
+        for _ in 0..10 {
+            assert!(!closing.on_packet_rcvd());
+        }
+        // draining只持续closing剩余的时间，期间连接ID仍被路由
+        assert_eq!(conn.state(), ConnectionState::Draining);
+        assert!(is_routed(cid, pathway, &usc));
+
+        tokio::time::advance(Duration::from_millis(30)).await;
+        tokio::task::yield_now().await;
+        assert_eq!(conn.state(), ConnectionState::Closed);
+        assert!(matches!(conn.0.lock().unwrap().deref(), Closed(..)));
+        assert!(!is_routed(cid, pathway, &usc));
+    }
+
+    #[test]
+    fn test_resend_ccf_by_clock() {
+        let clock = MockClock::new();
+        let error = Error::with_default_fty(ErrorKind::Application, "closed by test");
+        let ccf = ConnectionCloseFrame::from(error.clone());
+        let closing = ClosingConnection::new(
+            error,
+            &ccf,
+            vec![ConnectionId::random_gen(8)],
+            None,
+            None,
+            ConnectionId::random_gen(8),
+            None,
+            Arc::new(clock.clone()),
+        );
+        // 模拟时钟不走，只有收到足够多的包才重发CCF
+        for _ in 0..5 {
+            assert!(!closing.on_packet_rcvd());
+        }
+        assert!(closing.on_packet_rcvd());
+
+        // 拨快模拟时钟，距上次发送CCF足够久，收到包即重发
+        assert!(!closing.on_packet_rcvd());
+        clock.advance(Duration::from_millis(150));
+        assert!(closing.on_packet_rcvd());
+    }
+}
@@ -0,0 +1,279 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bytes::BufMut;
+use qbase::{
+    error::{Error as QuicError, ErrorKind},
+    frame::{io::WriteFrame, AckFrequencyFrame, BeFrame, ImmediateAckFrame, ReceiveFrame},
+    varint::VarInt,
+};
+use thiserror::Error;
+
+#[derive(Debug, Default)]
+struct AckFrequency {
+    // 本端通告的min_ack_delay，未通告则不接受ACK_FREQUENCY帧
+    local_min_ack_delay: Option<Duration>,
+    // 对端通告的min_ack_delay，未通告则不能发送ACK_FREQUENCY/IMMEDIATE_ACK帧
+    remote_min_ack_delay: Option<Duration>,
+    next_sequence: u64,
+    // 对端确认的最新的ACK_FREQUENCY帧，其中的max_ack_delay用于计算PTO
+    largest_acked: Option<(u64, Duration)>,
+    largest_rcvd_sequence: Option<u64>,
+    // 需要对端立即确认，下个1-RTT包携带IMMEDIATE_ACK帧
+    immediate_ack: bool,
+}
+
+/// The error of requesting the peer to change its acknowledgement frequency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum AckFrequencyError {
+    /// The peer does not advertise the `min_ack_delay` transport parameter, or its transport
+    /// parameters have not been received yet.
+    #[error("the peer does not support the ack frequency extension")]
+    NotNegotiated,
+    /// The requested max ack delay is less than the `min_ack_delay` of the peer.
+    #[error("the max ack delay {0:?} is less than the min_ack_delay of the peer")]
+    MaxAckDelayTooSmall(Duration),
+}
+
+/// The state of the ack frequency extension of a connection.
+///
+/// The extension is enabled in one direction, only if the receiver of the ACK_FREQUENCY frames
+/// advertises the `min_ack_delay` transport parameter. The received ACK_FREQUENCY frames are
+/// checked here, and then applied to the path on which they arrived.
+///
+/// See [QUIC Acknowledgment Frequency](https://datatracker.ietf.org/doc/html/draft-ietf-quic-ack-frequency)
+/// for more details.
+#[derive(Debug, Default, Clone)]
+pub struct ArcAckFrequency(Arc<Mutex<AckFrequency>>);
+
+impl ArcAckFrequency {
+    /// Create a new state with the `min_ack_delay` transport parameter of ourselves.
+    pub fn new(local_min_ack_delay: Option<Duration>) -> Self {
+        Self(Arc::new(Mutex::new(AckFrequency {
+            local_min_ack_delay,
+            ..Default::default()
+        })))
+    }
+
+    /// Called with the `min_ack_delay` transport parameter of the peer.
+    pub fn on_remote_params(&self, remote_min_ack_delay: Option<Duration>) {
+        self.0.lock().unwrap().remote_min_ack_delay = remote_min_ack_delay;
+    }
+
+    /// Returns whether the peer supports the ack frequency extension.
+    pub fn is_negotiated(&self) -> bool {
+        self.0.lock().unwrap().remote_min_ack_delay.is_some()
+    }
+
+    /// Generate an ACK_FREQUENCY frame to ask the peer to send an acknowledgement after
+    /// receiving more than `ack_eliciting_threshold` ack-eliciting packets, or waiting for
+    /// `max_ack_delay`, or seeing more than `reordering_threshold` out-of-order packets.
+    pub fn request(
+        &self,
+        ack_eliciting_threshold: u64,
+        max_ack_delay: Duration,
+        reordering_threshold: u64,
+    ) -> Result<AckFrequencyFrame, AckFrequencyError> {
+        let mut guard = self.0.lock().unwrap();
+        let min_ack_delay = guard
+            .remote_min_ack_delay
+            .ok_or(AckFrequencyError::NotNegotiated)?;
+        if max_ack_delay < min_ack_delay {
+            return Err(AckFrequencyError::MaxAckDelayTooSmall(max_ack_delay));
+        }
+        let sequence_number = VarInt::from_u64(guard.next_sequence).unwrap();
+        guard.next_sequence += 1;
+        Ok(AckFrequencyFrame {
+            sequence_number,
+            ack_eliciting_threshold: VarInt::from_u64(ack_eliciting_threshold)
+                .expect("ack eliciting threshold must be less than 2^62"),
+            request_max_ack_delay: VarInt::from_u64(max_ack_delay.as_micros() as u64)
+                .expect("max ack delay must be less than 2^62 microseconds"),
+            reordering_threshold: VarInt::from_u64(reordering_threshold)
+                .expect("reordering threshold must be less than 2^62"),
+        })
+    }
+
+    /// Called when the packet carrying an ACK_FREQUENCY frame sent by us is acknowledged.
+    ///
+    /// From then on, the peer delays its acknowledgements by up to the requested max ack delay,
+    /// which replaces the `max_ack_delay` transport parameter of the peer in the PTO
+    /// calculation. Return the requested max ack delay if the frame is the newest acknowledged
+    /// one, or [`None`] if a newer frame has been acknowledged.
+    pub fn on_frame_acked(&self, frame: &AckFrequencyFrame) -> Option<Duration> {
+        let mut guard = self.0.lock().unwrap();
+        let sequence = frame.sequence_number.into_inner();
+        if guard
+            .largest_acked
+            .is_some_and(|(largest, _)| sequence <= largest)
+        {
+            return None;
+        }
+        let max_ack_delay = Duration::from_micros(frame.request_max_ack_delay.into_inner());
+        guard.largest_acked = Some((sequence, max_ack_delay));
+        Some(max_ack_delay)
+    }
+
+    /// The max ack delay requested by the newest acknowledged ACK_FREQUENCY frame, read
+    /// [`ArcAckFrequency::on_frame_acked`] for more details.
+    pub fn acked_max_ack_delay(&self) -> Option<Duration> {
+        self.0
+            .lock()
+            .unwrap()
+            .largest_acked
+            .map(|(_, max_ack_delay)| max_ack_delay)
+    }
+
+    /// Ask the peer to acknowledge immediately, an IMMEDIATE_ACK frame will be sent in the next
+    /// 1-RTT packet. Nothing happens if the peer does not support the ack frequency extension.
+    pub fn immediate_ack(&self) {
+        let mut guard = self.0.lock().unwrap();
+        if guard.remote_min_ack_delay.is_some() {
+            guard.immediate_ack = true;
+        }
+    }
+
+    /// Try to write an IMMEDIATE_ACK frame into the `buf`, if it was asked to, or a probe
+    /// packet is being sent, the peer should acknowledge it as soon as possible.
+    ///
+    /// Returns the number of bytes written.
+    pub fn try_read_immediate_ack(&self, mut buf: &mut [u8], need_probe: bool) -> usize {
+        let mut guard = self.0.lock().unwrap();
+        if guard.remote_min_ack_delay.is_none() || !(guard.immediate_ack || need_probe) {
+            return 0;
+        }
+        if buf.remaining_mut() < ImmediateAckFrame.encoding_size() {
+            return 0;
+        }
+        buf.put_frame(&ImmediateAckFrame);
+        guard.immediate_ack = false;
+        ImmediateAckFrame.encoding_size()
+    }
+}
+
+impl ReceiveFrame<AckFrequencyFrame> for ArcAckFrequency {
+    /// Whether the frame is the newest one, which should be applied.
+    type Output = bool;
+
+    fn recv_frame(&self, frame: &AckFrequencyFrame) -> Result<Self::Output, QuicError> {
+        let mut guard = self.0.lock().unwrap();
+        let Some(min_ack_delay) = guard.local_min_ack_delay else {
+            return Err(QuicError::new(
+                ErrorKind::ProtocolViolation,
+                frame.frame_type(),
+                "min_ack_delay is not advertised",
+            ));
+        };
+        let max_ack_delay = Duration::from_micros(frame.request_max_ack_delay.into_inner());
+        if max_ack_delay < min_ack_delay {
+            return Err(QuicError::new(
+                ErrorKind::ProtocolViolation,
+                frame.frame_type(),
+                "request max ack delay is less than min_ack_delay",
+            ));
+        }
+        // 乱序到达的旧帧被忽略
+        let sequence = frame.sequence_number.into_inner();
+        if guard
+            .largest_rcvd_sequence
+            .is_some_and(|largest| sequence <= largest)
+        {
+            return Ok(false);
+        }
+        guard.largest_rcvd_sequence = Some(sequence);
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(sequence: u32, request_max_ack_delay: u32) -> AckFrequencyFrame {
+        AckFrequencyFrame {
+            sequence_number: VarInt::from_u32(sequence),
+            ack_eliciting_threshold: VarInt::from_u32(9),
+            request_max_ack_delay: VarInt::from_u32(request_max_ack_delay),
+            reordering_threshold: VarInt::from_u32(1),
+        }
+    }
+
+    #[test]
+    fn test_request() {
+        let ack_frequency = ArcAckFrequency::default();
+        assert_eq!(
+            ack_frequency.request(9, Duration::from_millis(25), 1),
+            Err(AckFrequencyError::NotNegotiated)
+        );
+
+        ack_frequency.on_remote_params(Some(Duration::from_millis(1)));
+        assert_eq!(
+            ack_frequency.request(9, Duration::from_micros(999), 1),
+            Err(AckFrequencyError::MaxAckDelayTooSmall(
+                Duration::from_micros(999)
+            ))
+        );
+        let first = ack_frequency
+            .request(9, Duration::from_millis(25), 1)
+            .unwrap();
+        assert_eq!(first, frame(0, 25_000));
+        let second = ack_frequency
+            .request(9, Duration::from_millis(5), 1)
+            .unwrap();
+        assert_eq!(second, frame(1, 5_000));
+    }
+
+    #[test]
+    fn test_frame_acked() {
+        let ack_frequency = ArcAckFrequency::default();
+        assert_eq!(ack_frequency.acked_max_ack_delay(), None);
+        assert_eq!(
+            ack_frequency.on_frame_acked(&frame(1, 5_000)),
+            Some(Duration::from_millis(5))
+        );
+        // 新的帧已被确认，旧帧的确认不再生效
+        assert_eq!(ack_frequency.on_frame_acked(&frame(0, 25_000)), None);
+        assert_eq!(
+            ack_frequency.acked_max_ack_delay(),
+            Some(Duration::from_millis(5))
+        );
+        assert_eq!(
+            ack_frequency.on_frame_acked(&frame(2, 10_000)),
+            Some(Duration::from_millis(10))
+        );
+    }
+
+    #[test]
+    fn test_recv_frame() {
+        let ack_frequency = ArcAckFrequency::default();
+        assert!(ack_frequency.recv_frame(&frame(0, 25_000)).is_err());
+
+        let ack_frequency = ArcAckFrequency::new(Some(Duration::from_millis(1)));
+        assert!(ack_frequency.recv_frame(&frame(0, 999)).is_err());
+        assert_eq!(ack_frequency.recv_frame(&frame(1, 25_000)), Ok(true));
+        // 旧的帧被忽略
+        assert_eq!(ack_frequency.recv_frame(&frame(0, 25_000)), Ok(false));
+        assert_eq!(ack_frequency.recv_frame(&frame(1, 25_000)), Ok(false));
+        assert_eq!(ack_frequency.recv_frame(&frame(2, 25_000)), Ok(true));
+    }
+
+    #[test]
+    fn test_immediate_ack() {
+        let ack_frequency = ArcAckFrequency::default();
+        let mut buf = [0u8; 8];
+        // 对端不支持，不发送IMMEDIATE_ACK帧
+        ack_frequency.immediate_ack();
+        assert_eq!(ack_frequency.try_read_immediate_ack(&mut buf, true), 0);
+
+        ack_frequency.on_remote_params(Some(Duration::from_millis(1)));
+        assert_eq!(ack_frequency.try_read_immediate_ack(&mut buf, false), 0);
+        ack_frequency.immediate_ack();
+        assert_eq!(ack_frequency.try_read_immediate_ack(&mut buf, false), 1);
+        assert_eq!(buf[0], 0x1f);
+        assert_eq!(ack_frequency.try_read_immediate_ack(&mut buf, false), 0);
+        // 探测包总是携带IMMEDIATE_ACK帧
+        assert_eq!(ack_frequency.try_read_immediate_ack(&mut buf, true), 1);
+    }
+}
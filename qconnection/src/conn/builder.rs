@@ -0,0 +1,931 @@
+use std::{
+    net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6},
+    sync::Arc,
+    time::Duration,
+};
+
+use qbase::{
+    cid::{ArcCidGenerator, ConnectionId, RandomCidGenerator},
+    entropy::{ArcEntropy, OsEntropy},
+    param::{Parameters, PreferredAddress},
+    qlog::QlogSink,
+    sid::{handy::ConsistentConcurrency, ControlConcurrency, Role},
+    token::ArcTokenRegistry,
+};
+use qcongestion::{CongestionAlgorithm, CongestionConfig, CongestionConfigError};
+
+use super::{
+    raw::Connection,
+    version::{initial_keys_version, Versions, QUIC_VERSION_1},
+    ArcConnection,
+};
+use crate::{
+    clock::{ArcClock, TokioClock},
+    router::Router,
+    tls::{ArcTlsSession, SessionCache},
+};
+
+/// The role-specific part of a client [`ConnectionBuilder`].
+pub struct Client {
+    server_name: String,
+    tls_config: Arc<rustls::ClientConfig>,
+    supported_versions: Vec<u32>,
+    session_cache: Option<Arc<dyn SessionCache>>,
+}
+
+/// The role-specific part of a server [`ConnectionBuilder`].
+pub struct Server {
+    initial_dcid: ConnectionId,
+    initial_keys: rustls::quic::Keys,
+    tls_config: Arc<rustls::ServerConfig>,
+    version: u32,
+    preferred_address: Option<(Option<SocketAddrV4>, Option<SocketAddrV6>)>,
+    disable_active_migration: bool,
+}
+
+/// A builder of [`ArcConnection`].
+///
+/// Only the TLS configuration and the connection IDs are required, which are given by
+/// [`ConnectionBuilder::client`] or [`ConnectionBuilder::server`]. The others are optional:
+///
+/// - the transport parameters, the default [`Parameters`] if not set;
+/// - the stream concurrency controller, a [`ConsistentConcurrency`] with the initial stream
+///   limits of the parameters if not set;
+/// - the congestion control algorithm, the default [`CongestionAlgorithm`] if not set;
+/// - the token registry, the default sink or provider of the role if not set;
+/// - the qlog sink, no event is recorded if not set;
+/// - the keep-alive interval, disabled if not set;
+/// - the clock of the timers, a [`TokioClock`] if not set;
+/// - the source of randomness, an [`OsEntropy`] if not set.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::{sync::Arc, time::Duration};
+///
+/// use qbase::cid::ConnectionId;
+/// use qconnection::conn::builder::ConnectionBuilder;
+///
+/// # fn build(tls_config: Arc<rustls::ClientConfig>) {
+/// let connection = ConnectionBuilder::client(
+///     ConnectionId::random_gen(8),
+///     "localhost".to_owned(),
+///     tls_config,
+/// )
+/// .with_keep_alive(Duration::from_secs(10))
+/// .build();
+/// # }
+/// ```
+pub struct ConnectionBuilder<R> {
+    role: R,
+    initial_scid: ConnectionId,
+    parameters: Parameters,
+    streams_ctrl: Option<Box<dyn ControlConcurrency>>,
+    congestion_algorithm: CongestionAlgorithm,
+    congestion_config: CongestionConfig,
+    token_registry: Option<ArcTokenRegistry>,
+    qlog: Option<Arc<dyn QlogSink>>,
+    keep_alive: Option<Duration>,
+    // 未设置时保持连接的默认值，Some(None)表示不限制握手时间
+    handshake_timeout: Option<Option<Duration>>,
+    max_mtu: Option<usize>,
+    clock: ArcClock,
+    entropy: ArcEntropy,
+    cid_generator: Option<ArcCidGenerator>,
+}
+
+impl<R> ConnectionBuilder<R> {
+    fn with_role(role: R, initial_scid: ConnectionId) -> Self {
+        Self {
+            role,
+            initial_scid,
+            parameters: Parameters::default(),
+            streams_ctrl: None,
+            congestion_algorithm: CongestionAlgorithm::default(),
+            congestion_config: CongestionConfig::default(),
+            token_registry: None,
+            qlog: None,
+            keep_alive: None,
+            handshake_timeout: None,
+            max_mtu: None,
+            clock: Arc::new(TokioClock),
+            entropy: Arc::new(OsEntropy),
+            cid_generator: None,
+        }
+    }
+
+    /// Set the local transport parameters.
+    ///
+    /// The `initial_source_connection_id` is always overwritten with the initial SCID.
+    pub fn with_parameters(mut self, parameters: Parameters) -> Self {
+        self.parameters = parameters;
+        self
+    }
+
+    /// Set the controller of the concurrency of the streams opened by the peer.
+    pub fn with_streams_controller(mut self, streams_ctrl: Box<dyn ControlConcurrency>) -> Self {
+        self.streams_ctrl = Some(streams_ctrl);
+        self
+    }
+
+    /// Set the congestion control algorithm, each path uses a new controller of it.
+    pub fn with_congestion_control(mut self, algorithm: CongestionAlgorithm) -> Self {
+        self.congestion_algorithm = algorithm;
+        self
+    }
+
+    /// Override the congestion windows and the initial RTT of the paths, for testing or tuning.
+    ///
+    /// The initial RTT seeds the PTO of the paths before any RTT sample is taken.
+    ///
+    /// Return an error if the `config` is invalid, such as its `min_window` exceeds the
+    /// `max_window`, read [`CongestionConfig::validate`] for more details.
+    pub fn with_congestion_config(
+        mut self,
+        config: CongestionConfig,
+    ) -> Result<Self, CongestionConfigError> {
+        config.validate()?;
+        self.congestion_config = config;
+        Ok(self)
+    }
+
+    /// Set the token registry, which saves the tokens of a client or issues the tokens of a
+    /// server.
+    pub fn with_token_registry(mut self, token_registry: ArcTokenRegistry) -> Self {
+        self.token_registry = Some(token_registry);
+        self
+    }
+
+    /// Record the events of the connection to the `qlog` sink, read [`QlogSink`] for more
+    /// details.
+    pub fn with_qlog(mut self, qlog: Arc<dyn QlogSink>) -> Self {
+        self.qlog = Some(qlog);
+        self
+    }
+
+    /// Enable the keep-alive, read [`Connection::set_keep_alive`] for more details.
+    pub fn with_keep_alive(mut self, interval: Duration) -> Self {
+        self.keep_alive = Some(interval);
+        self
+    }
+
+    /// Set the time limit for the handshake to be completed, `None` means no limit. Read
+    /// [`Connection::set_handshake_timeout`] for more details.
+    pub fn with_handshake_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.handshake_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the ceiling of the path MTU discovery, read [`Connection::set_max_mtu`] for more
+    /// details.
+    pub fn with_max_mtu(mut self, max_mtu: usize) -> Self {
+        self.max_mtu = Some(max_mtu);
+        self
+    }
+
+    /// Set the clock of the timers of the connection, read [`Connection::new`] for more details.
+    pub fn with_clock(mut self, clock: ArcClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Set the source of randomness of the connection, read [`Connection::new`] for more details.
+    ///
+    /// The DCID of the first Initial packet of a client is also filled by it.
+    pub fn with_entropy(mut self, entropy: ArcEntropy) -> Self {
+        self.entropy = entropy;
+        self
+    }
+
+    /// Set the generator of the connection IDs issued by the connection, read
+    /// [`Connection::new`] for more details.
+    ///
+    /// The connection ID in the preferred address of a server is also generated by it. If not
+    /// set, the connection IDs are random bytes filled by the source of randomness.
+    pub fn with_cid_generator(mut self, cid_generator: ArcCidGenerator) -> Self {
+        self.cid_generator = Some(cid_generator);
+        self
+    }
+}
+
+impl ConnectionBuilder<Client> {
+    /// Start building a client connection to the `server_name`.
+    ///
+    /// # Panics
+    ///
+    /// [`ConnectionBuilder::build`] panics if the `server_name` is not a valid server name.
+    pub fn client(
+        initial_scid: ConnectionId,
+        server_name: String,
+        tls_config: Arc<rustls::ClientConfig>,
+    ) -> Self {
+        let client = Client {
+            server_name,
+            tls_config,
+            supported_versions: vec![QUIC_VERSION_1],
+            session_cache: None,
+        };
+        Self::with_role(client, initial_scid)
+    }
+
+    /// Set the QUIC versions supported by the client, in the order of preference, the first one
+    /// is offered to the server. Read [`Versions`] for more details.
+    pub fn with_versions(mut self, versions: impl IntoIterator<Item = u32>) -> Self {
+        self.role.supported_versions = versions.into_iter().collect();
+        self
+    }
+
+    /// Try to send 0-RTT data with the session cache, read [`Connection::enable_0rtt`] for more
+    /// details.
+    pub fn with_session_cache(mut self, session_cache: Arc<dyn SessionCache>) -> Self {
+        self.role.session_cache = Some(session_cache);
+        self
+    }
+
+    /// Build the client connection.
+    pub fn build(self) -> ArcConnection {
+        let Self {
+            role:
+                Client {
+                    server_name,
+                    tls_config,
+                    supported_versions,
+                    session_cache,
+                },
+            initial_scid,
+            mut parameters,
+            streams_ctrl,
+            congestion_algorithm,
+            congestion_config,
+            token_registry,
+            qlog,
+            keep_alive,
+            handshake_timeout,
+            max_mtu,
+            clock,
+            entropy,
+            cid_generator,
+        } = self;
+        let Ok(tls_server_name) = server_name.clone().try_into() else {
+            panic!("server_name is not valid")
+        };
+
+        parameters.set_initial_source_connection_id(Some(initial_scid));
+
+        let cid_generator =
+            cid_generator.unwrap_or_else(|| Arc::new(RandomCidGenerator::new(entropy.clone())));
+        let initial_dcid = ConnectionId::gen_with_mark(&*entropy, 8, 0, 0xFF);
+        let versions = Versions::new(supported_versions);
+        let keys_version = initial_keys_version(versions.offered()).unwrap();
+        let tls_session = ArcTlsSession::new_client(
+            tls_server_name,
+            tls_config.clone(),
+            &parameters,
+            keys_version,
+            &*entropy,
+        );
+        let initial_keys = ArcTlsSession::initial_keys(
+            tls_config.crypto_provider(),
+            rustls::Side::Client,
+            initial_dcid,
+            keys_version,
+        );
+        let streams_ctrl = streams_ctrl.unwrap_or_else(|| default_streams_ctrl(&parameters));
+        let token_registry =
+            token_registry.unwrap_or_else(|| ArcTokenRegistry::default_sink(server_name.clone()));
+        let mut connection = Connection::new(
+            Role::Client,
+            parameters,
+            tls_session,
+            initial_scid,
+            initial_dcid,
+            initial_keys,
+            versions,
+            tls_config.crypto_provider().clone(),
+            streams_ctrl,
+            congestion_algorithm,
+            congestion_config,
+            token_registry,
+            qlog,
+            clock,
+            entropy,
+            cid_generator,
+        );
+        if let Some(session_cache) = session_cache {
+            connection.enable_0rtt(&server_name, session_cache);
+        }
+        if keep_alive.is_some() {
+            connection.set_keep_alive(keep_alive);
+        }
+        if let Some(handshake_timeout) = handshake_timeout {
+            connection.set_handshake_timeout(handshake_timeout);
+        }
+        if let Some(max_mtu) = max_mtu {
+            connection.set_max_mtu(max_mtu);
+        }
+        connection.into()
+    }
+}
+
+impl ConnectionBuilder<Server> {
+    /// Start building a server connection, for the client whose initial SCID is the
+    /// `initial_dcid`.
+    ///
+    /// The `initial_keys` are derived from the DCID of the first Initial packet of the client.
+    pub fn server(
+        initial_scid: ConnectionId,
+        initial_dcid: ConnectionId,
+        initial_keys: rustls::quic::Keys,
+        tls_config: Arc<rustls::ServerConfig>,
+    ) -> Self {
+        let server = Server {
+            initial_dcid,
+            initial_keys,
+            tls_config,
+            version: QUIC_VERSION_1,
+            preferred_address: None,
+            disable_active_migration: false,
+        };
+        Self::with_role(server, initial_scid)
+    }
+
+    /// Set the QUIC version of the client's Initial packets, QUIC version 1 by default.
+    ///
+    /// The server responds with the same version, and derives the Handshake and 1-RTT keys with
+    /// it. The `initial_keys` given in [`ConnectionBuilder::server`] should be derived with the
+    /// same version too.
+    pub fn with_version(mut self, version: u32) -> Self {
+        self.role.version = version;
+        self
+    }
+
+    /// Advertise the preferred address to the client, which the client migrates to after the
+    /// handshake. At least one of the `address_v4` and `address_v6` should be provided.
+    ///
+    /// A new connection ID and its stateless reset token are issued in the `preferred_address`
+    /// transport parameter. The server should be listening on the preferred address, otherwise
+    /// the client stays on the original address.
+    ///
+    /// See [server's preferred address](https://www.rfc-editor.org/rfc/rfc9000.html#name-servers-preferred-address)
+    /// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
+    pub fn with_preferred_address(
+        mut self,
+        address_v4: Option<SocketAddrV4>,
+        address_v6: Option<SocketAddrV6>,
+    ) -> Self {
+        self.role.preferred_address = Some((address_v4, address_v6));
+        self
+    }
+
+    /// Advertise the `disable_active_migration` transport parameter, the client must not migrate
+    /// actively on the address used during the handshake.
+    ///
+    /// The NAT rebindings of the client are still allowed, and so is the migration to the preferred
+    /// address, read [`ConnectionBuilder::with_preferred_address`]. The packets of a client migrating
+    /// actively anyway are dropped, the connection stays on the original path.
+    ///
+    /// See [section 9](https://www.rfc-editor.org/rfc/rfc9000.html#name-connection-migration)
+    /// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
+    pub fn disable_active_migration(mut self) -> Self {
+        self.role.disable_active_migration = true;
+        self
+    }
+
+    /// Build the server connection.
+    ///
+    /// The `original_destination_connection_id` and `retry_source_connection_id` in the
+    /// parameters should be set by the caller, which knows whether the client has been retried.
+    pub fn build(self) -> ArcConnection {
+        let Self {
+            role:
+                Server {
+                    initial_dcid,
+                    initial_keys,
+                    tls_config,
+                    version,
+                    preferred_address,
+                    disable_active_migration,
+                },
+            initial_scid,
+            mut parameters,
+            streams_ctrl,
+            congestion_algorithm,
+            congestion_config,
+            token_registry,
+            qlog,
+            keep_alive,
+            handshake_timeout,
+            max_mtu,
+            clock,
+            entropy,
+            cid_generator,
+        } = self;
+        parameters.set_initial_source_connection_id(Some(initial_scid));
+        parameters.set_statelss_reset_token(Some(Router::reset_token(&initial_scid)));
+        if disable_active_migration {
+            parameters.set_disable_active_migration(true);
+        }
+        let cid_generator =
+            cid_generator.unwrap_or_else(|| Arc::new(RandomCidGenerator::new(entropy.clone())));
+        if let Some((address_v4, address_v6)) = preferred_address {
+            // 没有提供的地址族，以未指定的地址占位
+            let address_v4 = address_v4.unwrap_or(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0));
+            let address_v6 =
+                address_v6.unwrap_or(SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, 0, 0, 0));
+            let preferred_cid = cid_generator.generate_cid();
+            parameters.set_preferred_address(Some(PreferredAddress::new(
+                address_v4,
+                address_v6,
+                preferred_cid,
+                Router::reset_token(&preferred_cid),
+            )));
+        }
+
+        // 未知的版本退回到QUIC v1
+        let versions = Versions::new(vec![version]);
+        let keys_version = initial_keys_version(versions.current()).unwrap();
+        let tls_session =
+            ArcTlsSession::new_server(tls_config.clone(), &parameters, keys_version, &*entropy);
+        let streams_ctrl = streams_ctrl.unwrap_or_else(|| default_streams_ctrl(&parameters));
+        let token_registry = token_registry.unwrap_or_else(ArcTokenRegistry::default_provider);
+        let connection = Connection::new(
+            Role::Server,
+            parameters,
+            tls_session,
+            initial_scid,
+            initial_dcid,
+            initial_keys,
+            versions,
+            tls_config.crypto_provider().clone(),
+            streams_ctrl,
+            congestion_algorithm,
+            congestion_config,
+            token_registry,
+            qlog,
+            clock,
+            entropy,
+            cid_generator,
+        );
+        if keep_alive.is_some() {
+            connection.set_keep_alive(keep_alive);
+        }
+        if let Some(handshake_timeout) = handshake_timeout {
+            connection.set_handshake_timeout(handshake_timeout);
+        }
+        if let Some(max_mtu) = max_mtu {
+            connection.set_max_mtu(max_mtu);
+        }
+        connection.into()
+    }
+}
+
+// 未指定时，按照本地通告的初始流数量限制对端的并发流
+fn default_streams_ctrl(parameters: &Parameters) -> Box<dyn ControlConcurrency> {
+    Box::new(ConsistentConcurrency::new(
+        parameters.initial_max_streams_bidi().into_inner(),
+        parameters.initial_max_streams_uni().into_inner(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use bytes::BytesMut;
+    use qbase::{
+        entropy::SeededEntropy,
+        error::{Error, ErrorKind},
+        frame::PathChallengeFrame,
+        packet::{long, DataHeader, DataPacket, Ecn, GetDcid, GetScid, Packet, PacketReader},
+        qlog::{JsonSeqSink, PacketEvent, PacketType, QlogEvent},
+    };
+    use rustls::{quic::Version, ClientConfig, RootCertStore, Side};
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::{
+        clock::MockClock,
+        conn::{state::ConnectionState, version::QUIC_VERSION_2, ConnState::Normal},
+        path::{Pathway, BASE_PLPMTU},
+        tls::tests::tls_configs,
+        usc::{ArcUsc, UscRegistry},
+    };
+
+    fn client_builder() -> ConnectionBuilder<Client> {
+        let tls_config = Arc::new(
+            ClientConfig::builder()
+                .with_root_certificates(RootCertStore::empty())
+                .with_no_client_auth(),
+        );
+        ConnectionBuilder::client(
+            ConnectionId::random_gen(8),
+            "localhost".to_owned(),
+            tls_config,
+        )
+    }
+
+    fn try_read_ping(conn: &ArcConnection) -> usize {
+        let guard = conn.0.lock().unwrap();
+        let Normal(ref connection) = *guard else {
+            panic!("the connection is not normal");
+        };
+        connection.idle_timer.try_read_ping(&mut [0u8; 8])
+    }
+
+    #[tokio::test]
+    async fn test_keep_alive() {
+        // 默认不开启keep-alive
+        let clock = MockClock::new();
+        let conn = client_builder().with_clock(Arc::new(clock.clone())).build();
+        clock.advance(Duration::from_millis(50));
+        tokio::task::yield_now().await;
+        assert_eq!(try_read_ping(&conn), 0);
+
+        let clock = MockClock::new();
+        let conn = client_builder()
+            .with_clock(Arc::new(clock.clone()))
+            .with_keep_alive(Duration::from_millis(20))
+            .build();
+        clock.advance(Duration::from_millis(19));
+        tokio::task::yield_now().await;
+        assert_eq!(try_read_ping(&conn), 0);
+        clock.advance(Duration::from_millis(1));
+        tokio::task::yield_now().await;
+        assert_eq!(try_read_ping(&conn), 1);
+    }
+
+    #[tokio::test]
+    async fn test_handshake_timeout() {
+        let clock = MockClock::new();
+        let conn = client_builder()
+            .with_clock(Arc::new(clock.clone()))
+            .with_handshake_timeout(Some(Duration::from_millis(100)))
+            .build();
+
+        // 对端从不响应，握手无法完成
+        let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let pathway = Pathway::Direct {
+            local: usc.local_addr().unwrap(),
+            remote: peer.local_addr().unwrap(),
+        };
+        conn.add_initial_path(pathway, usc);
+
+        clock.advance(Duration::from_millis(99));
+        tokio::task::yield_now().await;
+        assert_eq!(conn.state(), ConnectionState::Initial);
+        clock.advance(Duration::from_millis(1));
+        let result = tokio::time::timeout(Duration::from_secs(1), conn.handshake_completed())
+            .await
+            .expect("the handshake should time out");
+        assert_eq!(
+            result,
+            Err(Error::with_default_fty(
+                ErrorKind::None,
+                "Handshake timeout"
+            ))
+        );
+        assert_eq!(conn.state(), ConnectionState::Closed);
+    }
+
+    #[test]
+    fn test_explicit_default_handshake_timeout() {
+        use crate::conn::raw::DEFAULT_HANDSHAKE_TIMEOUT;
+
+        assert_eq!(client_builder().handshake_timeout, None);
+        // 显式设置为默认值，也要交给连接，而不是被当作未设置
+        let builder = client_builder().with_handshake_timeout(Some(DEFAULT_HANDSHAKE_TIMEOUT));
+        assert_eq!(
+            builder.handshake_timeout,
+            Some(Some(DEFAULT_HANDSHAKE_TIMEOUT))
+        );
+        let builder = client_builder().with_handshake_timeout(None);
+        assert_eq!(builder.handshake_timeout, Some(None));
+    }
+
+    #[tokio::test]
+    async fn test_max_mtu() {
+        let conn = client_builder().with_max_mtu(1300).build();
+
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let pathway = Pathway::Direct {
+            local: usc.local_addr().unwrap(),
+            remote: "127.0.0.1:4433".parse().unwrap(),
+        };
+        let guard = conn.0.lock().unwrap();
+        let Normal(ref connection) = *guard else {
+            panic!("the connection is not normal");
+        };
+        let path = connection.pathes.get_or_create(pathway, usc);
+        // 路径MTU探测的上限由builder指定，探测之前仍以最小的MTU发送
+        assert_eq!(path.mtu.max_mtu(), 1300);
+        assert_eq!(path.current_mtu(), BASE_PLPMTU);
+    }
+
+    #[test]
+    fn test_invalid_congestion_config() {
+        let config = CongestionConfig {
+            min_window: Some(2 * qcongestion::MSS as u64),
+            max_window: Some(qcongestion::MSS as u64),
+            ..Default::default()
+        };
+        assert!(matches!(
+            client_builder().with_congestion_config(config),
+            Err(CongestionConfigError::InvertedWindows { .. })
+        ));
+
+        let config = CongestionConfig {
+            initial_rtt: Some(Duration::ZERO),
+            ..Default::default()
+        };
+        assert!(matches!(
+            client_builder().with_congestion_config(config),
+            Err(CongestionConfigError::ZeroInitialRtt)
+        ));
+
+        let config = CongestionConfig {
+            initial_window: Some(20 * qcongestion::MSS as u64),
+            ..Default::default()
+        };
+        let builder = client_builder().with_congestion_config(config).unwrap();
+        assert_eq!(builder.congestion_config, config);
+    }
+
+    #[derive(Default)]
+    struct QlogEvents(Mutex<Vec<QlogEvent>>);
+
+    impl QlogSink for QlogEvents {
+        fn log(&self, event: QlogEvent) {
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    type Unrouted = (DataPacket, Ecn, Pathway, ArcUsc);
+
+    /// Bind a new usc, the datagrams received are routed to the connections, and the packets of
+    /// unknown connections are sent to `unrouted`.
+    fn bind_usc(unrouted: mpsc::UnboundedSender<Unrouted>) -> ArcUsc {
+        let recv_task = |usc: ArcUsc| async move {
+            let mut receiver = usc.receiver();
+            while let Ok(msg_count) = receiver.recv().await {
+                for (data, hdr) in receiver.take_datagrams(msg_count) {
+                    let pathway = Pathway::Direct {
+                        local: hdr.dst,
+                        remote: hdr.src,
+                    };
+                    let ecn = hdr.ecn.map(Ecn::from).unwrap_or_default();
+                    Router::route_datagram(data, ecn, pathway, &usc, |packet| {
+                        if let Packet::Data(packet) = packet {
+                            _ = unrouted.send((packet, ecn, pathway, usc.clone()));
+                        }
+                    });
+                }
+            }
+        };
+        UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), recv_task).unwrap()
+    }
+
+    fn sent(packet_type: PacketType) -> impl Fn(&QlogEvent) -> bool {
+        move |event| matches!(event, QlogEvent::PacketSent(p) if p.packet_type == packet_type)
+    }
+
+    fn received(packet_type: PacketType) -> impl Fn(&QlogEvent) -> bool {
+        move |event| matches!(event, QlogEvent::PacketReceived(p) if p.packet_type == packet_type)
+    }
+
+    fn position(events: &[QlogEvent], f: impl Fn(&QlogEvent) -> bool) -> usize {
+        events
+            .iter()
+            .position(f)
+            .expect("the event is not recorded")
+    }
+
+    #[tokio::test]
+    async fn test_qlog_handshake() {
+        let (server_config, client_config) = tls_configs(true);
+        let (client_unrouted, _client_unrouted) = mpsc::unbounded_channel();
+        let (server_unrouted, mut incomings) = mpsc::unbounded_channel();
+        let client_usc = bind_usc(client_unrouted);
+        let server_usc = bind_usc(server_unrouted);
+
+        let client_events = Arc::new(QlogEvents::default());
+        let client = ConnectionBuilder::client(
+            ConnectionId::random_gen(8),
+            "localhost".to_owned(),
+            client_config,
+        )
+        .with_qlog(client_events.clone())
+        .build();
+        let pathway = Pathway::Direct {
+            local: client_usc.local_addr().unwrap(),
+            remote: server_usc.local_addr().unwrap(),
+        };
+        client.add_initial_path(pathway, client_usc);
+
+        // 与服务端一样，由客户端的第一个Initial包创建连接
+        let incoming = tokio::time::timeout(Duration::from_secs(1), incomings.recv());
+        let (mut packet, ecn, pathway, usc) = incoming.await.unwrap().unwrap();
+        let server_scid = ConnectionId::random_gen(8);
+        let DataHeader::Long(long::DataHeader::Initial(hdr)) = &mut packet.header else {
+            panic!("the first packet of the client is not an Initial packet");
+        };
+        let (client_dcid, client_scid) = (*hdr.get_dcid(), *hdr.get_scid());
+        hdr.dcid = server_scid;
+        let initial_keys = ArcTlsSession::initial_keys(
+            server_config.crypto_provider(),
+            Side::Server,
+            client_dcid,
+            Version::V1,
+        );
+        let mut parameters = Parameters::default();
+        parameters.set_original_destination_connection_id(Some(client_dcid));
+        let server_events = Arc::new(QlogEvents::default());
+        let server =
+            ConnectionBuilder::server(server_scid, client_scid, initial_keys, server_config)
+                .with_parameters(parameters)
+                .with_qlog(server_events.clone())
+                .build();
+        server.add_initial_path(pathway, usc.clone());
+        assert!(Router::alias(client_dcid, &server_scid));
+        Router::try_to_route_packet_from(packet, ecn, pathway, &usc).unwrap();
+
+        let handshake =
+            async { tokio::try_join!(client.handshake_completed(), server.handshake_completed()) };
+        tokio::time::timeout(Duration::from_secs(2), handshake)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let client_events = client_events.0.lock().unwrap().clone();
+        let server_events = server_events.0.lock().unwrap().clone();
+        // 客户端以Initial包开始握手，收到服务端的Initial包后才能发送Handshake包
+        assert!(matches!(
+            client_events.first(),
+            Some(QlogEvent::PacketSent(PacketEvent {
+                packet_type: PacketType::Initial,
+                packet_number: 0,
+                ..
+            }))
+        ));
+        let initial_received = position(&client_events, received(PacketType::Initial));
+        assert!(initial_received < position(&client_events, received(PacketType::Handshake)));
+        assert!(initial_received < position(&client_events, sent(PacketType::Handshake)));
+        // 服务端对客户端Initial包的确认带来了RTT样本
+        assert!(client_events[initial_received..]
+            .iter()
+            .any(|event| matches!(event, QlogEvent::MetricsUpdated(_))));
+        // 服务端由客户端的Initial包开始握手，并以Initial包和Handshake包回应
+        assert!(matches!(
+            server_events.first(),
+            Some(QlogEvent::PacketReceived(PacketEvent {
+                packet_type: PacketType::Initial,
+                packet_number: 0,
+                ..
+            }))
+        ));
+        assert!(server_events.iter().any(sent(PacketType::Initial)));
+        assert!(server_events.iter().any(sent(PacketType::Handshake)));
+        assert!(server_events.iter().any(received(PacketType::Handshake)));
+
+        // 记录下的握手过程可以序列化为JSON-SEQ格式，每个事件一条记录
+        let sink = JsonSeqSink::new(Vec::new(), Role::Client).unwrap();
+        client_events.iter().for_each(|event| sink.log(*event));
+        let output = String::from_utf8(sink.into_inner().unwrap()).unwrap();
+        let records = output
+            .split('\x1e')
+            .filter(|record| !record.is_empty())
+            .collect::<Vec<_>>();
+        assert_eq!(records.len(), client_events.len() + 1);
+        assert!(records.iter().all(|record| record.ends_with('\n')));
+        assert!(records[0].starts_with(r#"{"qlog_version":"0.3","qlog_format":"JSON-SEQ""#));
+        assert!(records[0].contains(r#""vantage_point":{"type":"client"}"#));
+        assert!(records[1].contains(r#""name":"transport:packet_sent""#));
+        assert!(records[1].contains(r#""packet_type":"initial","packet_number":0"#));
+    }
+
+    // 用给定种子的连接发出第一个Initial包，签发连接ID并发出路径挑战，返回这些随机产生的值
+    async fn seeded_randoms(seed: u64) -> (ConnectionId, Vec<ConnectionId>, PathChallengeFrame) {
+        let conn = client_builder()
+            .with_entropy(Arc::new(SeededEntropy::new(seed)))
+            .build();
+        let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let pathway = Pathway::Direct {
+            local: usc.local_addr().unwrap(),
+            remote: peer.local_addr().unwrap(),
+        };
+        conn.add_initial_path(pathway, usc);
+
+        let mut datagram = [0u8; 1500];
+        let recv = peer.recv(&mut datagram);
+        let len = tokio::time::timeout(Duration::from_secs(1), recv)
+            .await
+            .unwrap()
+            .unwrap();
+        let packet = PacketReader::new(BytesMut::from(&datagram[..len]), 8).next();
+        let Some(Ok(Packet::Data(DataPacket {
+            header: DataHeader::Long(long::DataHeader::Initial(hdr)),
+            ..
+        }))) = packet
+        else {
+            panic!("the first packet of the client is not an Initial packet");
+        };
+
+        // 对端的传输参数允许3个活跃的连接ID，再签发2个；然后验证路径，发出挑战
+        let (initial_scid, local_cids, path) = {
+            let guard = conn.0.lock().unwrap();
+            let Normal(ref connection) = *guard else {
+                panic!("the connection is not normal");
+            };
+            let path = connection.pathes.get(&pathway).unwrap().clone();
+            (
+                connection.initial_scid,
+                connection.cid_registry.local.clone(),
+                path,
+            )
+        };
+        local_cids.set_limit(3).unwrap();
+        let issued = local_cids
+            .active_cids()
+            .into_iter()
+            .filter(|cid| *cid != initial_scid)
+            .collect::<Vec<_>>();
+        path.begin_validation();
+        let challenge = async {
+            loop {
+                match path.challenge_sndbuf().take() {
+                    Some(challenge) => break challenge,
+                    None => tokio::task::yield_now().await,
+                }
+            }
+        };
+        let challenge = tokio::time::timeout(Duration::from_secs(1), challenge)
+            .await
+            .unwrap();
+
+        // 签发的连接ID全局唯一，移除其路由，以免同一种子的下一个连接因冲突而重新生成
+        for cid in &issued {
+            Router::remove(cid);
+        }
+        (*hdr.get_dcid(), issued, challenge)
+    }
+
+    #[tokio::test]
+    async fn test_seeded_entropy() {
+        // 同一种子的两个连接，产生的Initial包DCID、签发的连接ID和路径挑战完全相同
+        let (dcid, issued, challenge) = seeded_randoms(42).await;
+        assert_eq!(issued.len(), 2);
+        assert_eq!(seeded_randoms(42).await, (dcid, issued.clone(), challenge));
+
+        // 不同的种子则各不相同
+        let (other_dcid, other_issued, other_challenge) = seeded_randoms(43).await;
+        assert_ne!(other_dcid, dcid);
+        assert_ne!(other_issued, issued);
+        assert_ne!(other_challenge, challenge);
+    }
+
+    #[tokio::test]
+    async fn test_versions_and_qlog() {
+        let events = Arc::new(QlogEvents::default());
+        let conn = client_builder()
+            .with_versions([QUIC_VERSION_2, QUIC_VERSION_1])
+            .with_qlog(events.clone())
+            .build();
+
+        let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let pathway = Pathway::Direct {
+            local: usc.local_addr().unwrap(),
+            remote: peer.local_addr().unwrap(),
+        };
+        conn.add_initial_path(pathway, usc);
+
+        let mut datagram = [0u8; 1500];
+        let recv = peer.recv(&mut datagram);
+        let len = tokio::time::timeout(Duration::from_secs(1), recv)
+            .await
+            .unwrap()
+            .unwrap();
+        // 首选的版本被用于第一个Initial包，包类型按v2编码为0b01
+        assert_eq!(datagram[0] & 0xf0, 0xd0);
+        assert_eq!(datagram[1..5], QUIC_VERSION_2.to_be_bytes());
+        assert!(len >= 1200);
+        let packet = PacketReader::new(BytesMut::from(&datagram[..len]), 8).next();
+        assert!(matches!(
+            packet,
+            Some(Ok(Packet::Data(DataPacket {
+                header: DataHeader::Long(long::DataHeader::Initial(ref hdr)),
+                ..
+            }))) if hdr.version == QUIC_VERSION_2
+        ));
+        let events = events.0.lock().unwrap();
+        assert!(matches!(
+            events.first(),
+            Some(QlogEvent::PacketSent(packet)) if packet.packet_type == PacketType::Initial
+        ));
+    }
+}
@@ -1,7 +1,7 @@
 use std::{
     future::Future,
     io::IoSlice,
-    ops::DerefMut,
+    ops::{Deref, DerefMut},
     pin::Pin,
     sync::{
         atomic::{AtomicUsize, Ordering},
@@ -13,13 +13,13 @@ use std::{
 
 use qbase::{
     cid::ConnectionId,
-    error::Error,
-    frame::ConnectionCloseFrame,
+    error::{Error, ErrorKind},
+    frame::{ConnectionCloseFrame, FrameType},
     packet::{long, DataHeader, DataPacket},
 };
 
 use super::scope::{data::ClosingOneRttScope, handshake::ClosingHandshakeScope, RecvPacket};
-use crate::{path::Pathway, usc::ArcUsc};
+use crate::{clock::ArcClock, path::Pathway, usc::ArcUsc};
 
 pub struct CcfPackets {
     handshake: Option<([u8; qcongestion::MSS], usize)>,
@@ -30,13 +30,24 @@ impl CcfPackets {
     pub fn new(
         hs: Option<&ClosingHandshakeScope>,
         one_rtt: Option<&ClosingOneRttScope>,
-        error: &Error,
+        ccf: &ConnectionCloseFrame,
         last_dcid: ConnectionId,
         initial_scid: ConnectionId,
     ) -> Self {
-        let ccf = ConnectionCloseFrame::from(error.clone());
         let handshake = hs.map({
             |hs| {
+                // See [section-10.2.3](https://www.rfc-editor.org/rfc/rfc9000.html#section-10.2.3)
+                // of [RFC9000](https://www.rfc-editor.org/rfc/rfc9000.html): the application
+                // close reason must not be revealed before the handshake is confirmed, a
+                // CONNECTION_CLOSE with an APPLICATION_ERROR code and empty reason is sent instead.
+                let ccf = match ccf {
+                    ConnectionCloseFrame::App(_) => ConnectionCloseFrame::new_quic(
+                        ErrorKind::Application,
+                        FrameType::Padding,
+                        "",
+                    ),
+                    ConnectionCloseFrame::Quic(_) => ccf.clone(),
+                };
                 let mut packet = [0; qcongestion::MSS];
                 let size = hs.assemble_ccf_packet(&mut packet, &ccf, initial_scid, last_dcid);
                 (packet, size)
@@ -45,7 +56,7 @@ impl CcfPackets {
         let one_rtt = one_rtt.map({
             |one_rtt| {
                 let mut packet = [0; qcongestion::MSS];
-                let size = one_rtt.assemble_ccf_packet(&mut packet, &ccf, last_dcid);
+                let size = one_rtt.assemble_ccf_packet(&mut packet, ccf, last_dcid);
                 (packet, size)
             }
         });
@@ -63,6 +74,17 @@ impl CcfPackets {
             .as_ref()
             .map(|(packet, size)| IoSlice::new(&packet[..*size]))
     }
+
+    /// Send the packets carrying the CONNECTION_CLOSE frame via the `pathway`.
+    pub async fn send_via(&self, usc: &ArcUsc, pathway: Pathway) {
+        let packets: &[IoSlice] = match (self.handshake(), self.one_rtt()) {
+            (Some(hs_packet), Some(one_rtt_packet)) => &[hs_packet, one_rtt_packet],
+            (Some(hs_packet), None) => &[hs_packet],
+            (None, Some(one_rtt_packet)) => &[one_rtt_packet],
+            _ => return,
+        };
+        _ = usc.send_all_via_pathway(packets, pathway, None).await;
+    }
 }
 
 #[derive(Clone)]
@@ -75,6 +97,7 @@ pub struct ClosingConnection {
     pub rcvd_packets: Arc<AtomicUsize>,
     pub last_send_ccf: Arc<Mutex<Instant>>,
     pub revd_ccf: RcvdCcf,
+    pub clock: ArcClock,
 
     pub ccf_packets: Option<Arc<CcfPackets>>,
 }
@@ -82,16 +105,18 @@ pub struct ClosingConnection {
 impl ClosingConnection {
     pub fn new(
         error: Error,
+        ccf: &ConnectionCloseFrame,
         local_cids: Vec<ConnectionId>,
         hs: Option<ClosingHandshakeScope>,
         one_rtt: Option<ClosingOneRttScope>,
         initial_scid: ConnectionId,
         last_dcid: Option<ConnectionId>,
+        clock: ArcClock,
     ) -> Self {
         let ccf_packets = last_dcid.map(|last_dcid| {
             let hs = hs.as_ref();
             let one_rtt = one_rtt.as_ref();
-            CcfPackets::new(hs, one_rtt, &error, last_dcid, initial_scid)
+            CcfPackets::new(hs, one_rtt, ccf, last_dcid, initial_scid)
         });
         Self {
             local_cids,
@@ -99,8 +124,9 @@ impl ClosingConnection {
             one_rtt,
             error,
             rcvd_packets: Arc::new(AtomicUsize::new(0)),
-            last_send_ccf: Arc::new(Mutex::new(Instant::now())),
+            last_send_ccf: Arc::new(Mutex::new(clock.now())),
             revd_ccf: RcvdCcf::default(),
+            clock,
             ccf_packets: ccf_packets.map(Arc::new),
         }
     }
@@ -112,22 +138,12 @@ impl ClosingConnection {
         pathway: Pathway,
         usc: ArcUsc,
     ) {
-        self.rcvd_packets.fetch_add(1, Ordering::Release);
+        // 已收到对端的CCF，进入Draining状态，不再处理任何包
+        if self.revd_ccf.is_rcvd() {
+            return;
+        }
 
-        let should_send_ccf = {
-            let mut last_send_ccf = self.last_send_ccf.lock().unwrap();
-
-            // TODO: 数值从配置中读取, 还是直接固定值?
-            if self.rcvd_packets.load(Ordering::Acquire) > 5
-                || last_send_ccf.elapsed() > Duration::from_millis(100)
-            {
-                self.rcvd_packets.store(0, Ordering::Release);
-                *last_send_ccf = Instant::now();
-                true
-            } else {
-                false
-            }
-        };
+        let should_send_ccf = self.on_packet_rcvd();
 
         match packet.header {
             DataHeader::Short(_) => self.parse_1rtt_packet(packet),
@@ -135,20 +151,37 @@ impl ClosingConnection {
             _ => { /* turstless, just ignore */ }
         };
 
-        if should_send_ccf {
+        if should_send_ccf && !self.revd_ccf.is_rcvd() {
             self.send_ccf(&usc, pathway).await;
         }
     }
 
+    /// Count the received packet, return whether the CONNECTION_CLOSE frame should be sent again.
+    ///
+    /// Once the peer's CONNECTION_CLOSE frame is received, it is never sent again.
+    pub fn on_packet_rcvd(&self) -> bool {
+        if self.revd_ccf.is_rcvd() {
+            return false;
+        }
+        self.rcvd_packets.fetch_add(1, Ordering::Release);
+
+        let mut last_send_ccf = self.last_send_ccf.lock().unwrap();
+        let now = self.clock.now();
+        // TODO: 数值从配置中读取, 还是直接固定值?
+        if self.rcvd_packets.load(Ordering::Acquire) > 5
+            || now.saturating_duration_since(*last_send_ccf) > Duration::from_millis(100)
+        {
+            self.rcvd_packets.store(0, Ordering::Release);
+            *last_send_ccf = now;
+            true
+        } else {
+            false
+        }
+    }
+
     pub async fn send_ccf(&self, usc: &ArcUsc, pathway: Pathway) {
         if let Some(ccf_packets) = self.ccf_packets.as_ref() {
-            let packets: &[IoSlice] = match (ccf_packets.handshake(), ccf_packets.one_rtt()) {
-                (Some(hs_packet), Some(one_rtt_packet)) => &[hs_packet, one_rtt_packet],
-                (Some(hs_packet), None) => &[hs_packet],
-                (None, Some(one_rtt_packet)) => &[one_rtt_packet],
-                _ => return,
-            };
-            _ = usc.send_all_via_pathway(packets, pathway).await;
+            ccf_packets.send_via(usc, pathway).await;
         }
     }
 
@@ -189,6 +222,10 @@ impl RcvdCcf {
         self.clone()
     }
 
+    pub fn is_rcvd(&self) -> bool {
+        matches!(self.0.lock().unwrap().deref(), RcvdCcfState::Rcvd)
+    }
+
     pub fn on_ccf_rcvd(&self) {
         let mut guard = self.0.lock().unwrap();
         if let RcvdCcfState::Pending(waker) = guard.deref_mut() {
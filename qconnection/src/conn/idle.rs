@@ -0,0 +1,234 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use bytes::BufMut;
+use qbase::frame::{io::WriteFrame, BeFrame, PingFrame};
+use tokio::sync::Notify;
+
+use crate::clock::{ArcClock, TokioClock};
+
+#[derive(Debug)]
+struct IdleTimer {
+    last_active: Instant,
+    timeout: Option<Duration>,
+    keep_alive: Option<Duration>,
+    ping: bool,
+}
+
+/// The idle timer of a connection.
+///
+/// The timer is reset whenever a packet is received from the peer, or an ack-eliciting packet
+/// is sent. Once the connection has been idle for longer than the negotiated idle timeout, the
+/// connection should be closed silently, without sending a CONNECTION_CLOSE frame.
+///
+/// If keep-alive is enabled, a PING frame will be sent before the timer expires to keep the
+/// connection open.
+///
+/// The effective idle timeout is at least three times the current PTO, so that the connection
+/// will not be closed during the loss recovery, even though a small idle timeout is negotiated.
+///
+/// See [idle timeout](https://www.rfc-editor.org/rfc/rfc9000.html#name-idle-timeout)
+/// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
+#[derive(Debug, Clone)]
+pub struct ArcIdleTimer {
+    timer: Arc<Mutex<IdleTimer>>,
+    changed: Arc<Notify>,
+    clock: ArcClock,
+}
+
+impl Default for ArcIdleTimer {
+    fn default() -> Self {
+        Self::new(Arc::new(TokioClock))
+    }
+}
+
+impl ArcIdleTimer {
+    /// Create a new idle timer, which reads the time from the `clock`.
+    pub fn new(clock: ArcClock) -> Self {
+        Self {
+            timer: Arc::new(Mutex::new(IdleTimer {
+                last_active: clock.now(),
+                timeout: None,
+                keep_alive: None,
+                ping: false,
+            })),
+            changed: Arc::default(),
+            clock,
+        }
+    }
+
+    /// Negotiate the idle timeout with the `max_idle_timeout` transport parameters of both
+    /// endpoints, the effective value is the minimum of the two.
+    ///
+    /// A zero value means that the endpoint disables the idle timeout, if both endpoints disable
+    /// it, the connection will never be closed due to idle.
+    pub fn negotiate(&self, local: Duration, remote: Duration) {
+        let timeout = match (local.is_zero(), remote.is_zero()) {
+            (true, true) => None,
+            (true, false) => Some(remote),
+            (false, true) => Some(local),
+            (false, false) => Some(local.min(remote)),
+        };
+        self.timer.lock().unwrap().timeout = timeout;
+        self.changed.notify_one();
+    }
+
+    /// Enable or disable the keep-alive, with the interval between two PING frames.
+    ///
+    /// The PING frame is sent at least once every half of the idle timeout, even though a longer
+    /// interval is specified, so that the connection will not be closed due to idle.
+    pub fn set_keep_alive(&self, interval: Option<Duration>) {
+        self.timer.lock().unwrap().keep_alive = interval;
+        self.changed.notify_one();
+    }
+
+    /// Called when a packet is received from the peer.
+    pub fn on_rcvd(&self) {
+        self.timer.lock().unwrap().last_active = self.clock.now();
+    }
+
+    /// Called when an ack-eliciting packet is sent.
+    pub fn on_ack_eliciting_sent(&self) {
+        let mut timer = self.timer.lock().unwrap();
+        timer.last_active = self.clock.now();
+        timer.ping = false;
+    }
+
+    /// Try to write a PING frame into the buffer if the keep-alive requires it.
+    ///
+    /// Return the number of bytes written.
+    pub fn try_read_ping(&self, mut buf: &mut [u8]) -> usize {
+        let mut timer = self.timer.lock().unwrap();
+        let size = PingFrame.encoding_size();
+        if !timer.ping || buf.remaining_mut() < size {
+            return 0;
+        }
+        buf.put_frame(&PingFrame);
+        timer.ping = false;
+        size
+    }
+
+    /// Wait until the connection has been idle for longer than the idle timeout.
+    ///
+    /// The idle timeout is no less than three times the current PTO returned by `pto`, which is
+    /// read again each time the timer fires, see
+    /// [Section 10.1](https://www.rfc-editor.org/rfc/rfc9000.html#section-10.1-4) of RFC 9000.
+    ///
+    /// Meanwhile, the PING frames for keep-alive are requested in time.
+    pub async fn expired(&self, pto: impl Fn() -> Duration) {
+        loop {
+            let notified = self.changed.notified();
+            let min_timeout = pto() * 3;
+            let deadline = {
+                let mut timer = self.timer.lock().unwrap();
+                let now = self.clock.now();
+                let timeout = timer.timeout.map(|timeout| timeout.max(min_timeout));
+                let idle_deadline = timeout.map(|timeout| timer.last_active + timeout);
+                if idle_deadline.is_some_and(|deadline| deadline <= now) {
+                    return;
+                }
+                let ping_deadline = timer.keep_alive.map(|interval| {
+                    let interval = match timeout {
+                        Some(timeout) => interval.min(timeout / 2),
+                        None => interval,
+                    };
+                    timer.last_active + interval
+                });
+                match ping_deadline {
+                    Some(deadline) if deadline <= now => {
+                        timer.ping = true;
+                        idle_deadline
+                    }
+                    Some(deadline) => Some(deadline),
+                    None => idle_deadline,
+                }
+            };
+
+            match deadline {
+                Some(deadline) => tokio::select! {
+                    _ = notified => {},
+                    _ = self.clock.sleep_until(deadline) => {},
+                },
+                None => notified.await,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 推进暂停的时钟，并让出执行权，使到期的计时器得以运行
+    async fn advance(duration: Duration) {
+        tokio::time::advance(duration).await;
+        tokio::task::yield_now().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle_timeout() {
+        let idle_timer = ArcIdleTimer::default();
+        idle_timer.negotiate(Duration::from_millis(600), Duration::from_millis(200));
+
+        let expired = tokio::spawn({
+            let idle_timer = idle_timer.clone();
+            async move { idle_timer.expired(|| Duration::ZERO).await }
+        });
+        advance(Duration::from_millis(150)).await;
+        idle_timer.on_rcvd();
+        advance(Duration::from_millis(150)).await;
+        assert!(!expired.is_finished());
+        advance(Duration::from_millis(100)).await;
+        assert!(expired.is_finished());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle_timeout_no_less_than_3_pto() {
+        let idle_timer = ArcIdleTimer::default();
+        idle_timer.negotiate(Duration::from_millis(100), Duration::ZERO);
+        let pto = Arc::new(Mutex::new(Duration::from_millis(100)));
+
+        let expired = tokio::spawn({
+            let idle_timer = idle_timer.clone();
+            let pto = pto.clone();
+            async move { idle_timer.expired(|| *pto.lock().unwrap()).await }
+        });
+        // 协商的空闲超时只有100ms，但至少要等待3个PTO
+        advance(Duration::from_millis(250)).await;
+        assert!(!expired.is_finished());
+        // 丢包恢复期间PTO增大，空闲超时随之延长
+        *pto.lock().unwrap() = Duration::from_millis(200);
+        advance(Duration::from_millis(200)).await;
+        assert!(!expired.is_finished());
+        advance(Duration::from_millis(250)).await;
+        assert!(expired.is_finished());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_keep_alive() {
+        let idle_timer = ArcIdleTimer::default();
+        idle_timer.negotiate(Duration::from_millis(200), Duration::ZERO);
+        idle_timer.set_keep_alive(Some(Duration::from_secs(1)));
+
+        let expired = tokio::spawn({
+            let idle_timer = idle_timer.clone();
+            async move { idle_timer.expired(|| Duration::ZERO).await }
+        });
+        let mut buf = [0u8; 8];
+        for _ in 0..4 {
+            advance(Duration::from_millis(130)).await;
+            // the interval is shortened to half of the idle timeout
+            assert_eq!(idle_timer.try_read_ping(&mut buf), 1);
+            assert_eq!(idle_timer.try_read_ping(&mut buf), 0);
+            idle_timer.on_ack_eliciting_sent();
+        }
+        assert!(!expired.is_finished());
+
+        idle_timer.set_keep_alive(None);
+        advance(Duration::from_millis(250)).await;
+        assert_eq!(idle_timer.try_read_ping(&mut buf), 0);
+        assert!(expired.is_finished());
+    }
+}
@@ -4,7 +4,11 @@ use std::{
     sync::{Arc, Mutex},
 };
 
-use qbase::{error::Error, param::Parameters};
+use qbase::{
+    cid::ConnectionId,
+    error::{Error, ErrorKind},
+    param::Parameters,
+};
 use tokio::sync::Notify;
 
 #[derive(Debug)]
@@ -44,6 +48,13 @@ impl<T: Clone> SharedFuture<T> {
         matches!(self.0.lock().unwrap().deref(), SharedFutureState::Ready(..))
     }
 
+    fn try_get(&self) -> Option<T> {
+        match self.0.lock().unwrap().deref() {
+            SharedFutureState::Ready(ready) => Some(ready.clone()),
+            SharedFutureState::Demand(_) => None,
+        }
+    }
+
     fn set_with(&self, with: impl FnOnce() -> T) {
         let mut state = self.0.lock().unwrap();
         match state.deref() {
@@ -57,8 +68,14 @@ impl<T: Clone> SharedFuture<T> {
 }
 
 /// A struct to asynchronously get the peer's transport parameters.
+///
+/// The parameters are ready as soon as TLS decoded them, they are accepted only after they have
+/// been validated, read [`RemoteParameters::accepted`].
 #[derive(Default, Debug, Clone)]
-pub struct RemoteParameters(Arc<SharedFuture<Result<Arc<Parameters>, Error>>>);
+pub struct RemoteParameters(
+    Arc<SharedFuture<Result<Arc<Parameters>, Error>>>,
+    Arc<SharedFuture<bool>>,
+);
 
 impl RemoteParameters {
     /// Create a new [`RemoteParameters`] in demand state.
@@ -75,6 +92,14 @@ impl RemoteParameters {
         Ok(self.0.get().await?)
     }
 
+    /// Get the transport parameters from the peer without waiting.
+    ///
+    /// Return [`None`] if the parameters are not ready yet, or the connection is closed before
+    /// they are ready.
+    pub fn try_read(&self) -> Option<Arc<Parameters>> {
+        self.0.try_get()?.ok()
+    }
+
     /// Check if the transport parameters are ready or the connection is closed.
     pub fn is_ready(&self) -> bool {
         self.0.is_ready()
@@ -93,6 +118,20 @@ impl RemoteParameters {
     /// this method will wake all blocked [`RemoteParameters::on_conn_error`] calls.
     pub fn on_conn_error(&self, error: &Error) {
         self.0.set_with(|| Err(error.clone()));
+        self.1.set_with(|| false);
+    }
+
+    /// Called when the peer's transport parameters are validated and applied to the connection.
+    pub fn accept(&self) {
+        self.1.set_with(|| true);
+    }
+
+    /// Asynchronously wait for the peer's transport parameters to be validated.
+    ///
+    /// Return `true` if they are accepted, or `false` if the connection is closed before that,
+    /// including the case that the parameters are invalid.
+    pub async fn accepted(&self) -> bool {
+        self.1.get().await
     }
 }
 
@@ -103,16 +142,33 @@ impl RemoteParameters {
 /// The remote parameters are the parameters of the remote endpoint, its [`RemoteParameters`] the
 /// is not ready when the structure is created. Other components can asynchronously get the remote
 /// parameters by calling [`RemoteParameters::read`].
+///
+/// For the client attempting 0-RTT, the remembered parameters of the server are used to send
+/// 0-RTT data before the remote parameters are ready, read [`ConnParameters::read_for_0rtt`].
 #[derive(Debug, Clone)]
 pub struct ConnParameters {
     pub local: Arc<Parameters>,
     pub remote: RemoteParameters,
+    pub remembered: Option<Arc<Parameters>>,
 }
 
 impl ConnParameters {
     /// Create a new [`ConnParameters`] with the local parameters and the remote parameters.
     pub fn new(local: Arc<Parameters>, remote: RemoteParameters) -> Self {
-        Self { local, remote }
+        Self {
+            local,
+            remote,
+            remembered: None,
+        }
+    }
+
+    /// Get the peer's transport parameters, or the remembered parameters if 0-RTT is attempted
+    /// and the handshake is still in progress.
+    pub async fn read_for_0rtt(&self) -> io::Result<Arc<Parameters>> {
+        match &self.remembered {
+            Some(remembered) if !self.remote.is_ready() => Ok(remembered.clone()),
+            _ => self.remote.read().await,
+        }
     }
 
     /// Called when a connection error occurs, read [`RemoteParameters::on_conn_error`] for more.
@@ -120,3 +176,252 @@ impl ConnParameters {
         self.remote.on_conn_error(error);
     }
 }
+
+/// Check that the transport parameters confirmed by the server, which accepted the 0-RTT data,
+/// do not reduce the limits remembered by the client, that might be violated by the 0-RTT data.
+///
+/// See [values of transport parameters for 0-RTT](https://www.rfc-editor.org/rfc/rfc9000.html#name-values-of-transport-paramet)
+/// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
+pub fn check_remembered(remembered: &Parameters, confirmed: &Parameters) -> Result<(), Error> {
+    let limits = [
+        (
+            "active_connection_id_limit",
+            remembered.active_connection_id_limit(),
+            confirmed.active_connection_id_limit(),
+        ),
+        (
+            "initial_max_data",
+            remembered.initial_max_data(),
+            confirmed.initial_max_data(),
+        ),
+        (
+            "initial_max_stream_data_bidi_local",
+            remembered.initial_max_stream_data_bidi_local(),
+            confirmed.initial_max_stream_data_bidi_local(),
+        ),
+        (
+            "initial_max_stream_data_bidi_remote",
+            remembered.initial_max_stream_data_bidi_remote(),
+            confirmed.initial_max_stream_data_bidi_remote(),
+        ),
+        (
+            "initial_max_stream_data_uni",
+            remembered.initial_max_stream_data_uni(),
+            confirmed.initial_max_stream_data_uni(),
+        ),
+        (
+            "initial_max_streams_bidi",
+            remembered.initial_max_streams_bidi(),
+            confirmed.initial_max_streams_bidi(),
+        ),
+        (
+            "initial_max_streams_uni",
+            remembered.initial_max_streams_uni(),
+            confirmed.initial_max_streams_uni(),
+        ),
+        (
+            "max_datagram_frame_size",
+            remembered.max_datagram_frame_size(),
+            confirmed.max_datagram_frame_size(),
+        ),
+    ];
+    match limits
+        .into_iter()
+        .find(|(_, remembered, confirmed)| confirmed < remembered)
+    {
+        Some((name, remembered, confirmed)) => Err(Error::with_default_fty(
+            ErrorKind::ProtocolViolation,
+            format!("{name} is reduced from {remembered} to {confirmed} after 0-RTT is accepted"),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Check that the `initial_source_connection_id` transport parameter of the peer matches the
+/// source connection ID of the Initial packets received from the peer.
+///
+/// See [authenticating connection IDs](https://www.rfc-editor.org/rfc/rfc9000.html#name-authenticating-connection-)
+/// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
+pub fn check_initial_source_cid(
+    params: &Parameters,
+    initial_scid: Option<ConnectionId>,
+) -> Result<(), Error> {
+    match params.initial_source_connection_id() {
+        Some(cid) if Some(cid) == initial_scid => Ok(()),
+        Some(_) => Err(Error::with_default_fty(
+            ErrorKind::TransportParameter,
+            "initial_source_connection_id mismatch",
+        )),
+        None => Err(Error::with_default_fty(
+            ErrorKind::TransportParameter,
+            "missing initial_source_connection_id",
+        )),
+    }
+}
+
+/// For client, check that the server echoes the connection IDs the client actually used, the
+/// `original_destination_connection_id` must be the DCID of the first Initial packet sent by the
+/// client, and the `retry_source_connection_id` must be the SCID of the Retry packet if one was
+/// received, or absent otherwise.
+///
+/// See [authenticating connection IDs](https://www.rfc-editor.org/rfc/rfc9000.html#name-authenticating-connection-)
+/// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
+pub fn check_server_cids(
+    params: &Parameters,
+    original_dcid: ConnectionId,
+    retry_scid: Option<ConnectionId>,
+) -> Result<(), Error> {
+    if *params.original_destination_connection_id() != Some(original_dcid) {
+        return Err(Error::with_default_fty(
+            ErrorKind::TransportParameter,
+            "original_destination_connection_id mismatch",
+        ));
+    }
+    if params.retry_source_connection_id() != retry_scid {
+        return Err(Error::with_default_fty(
+            ErrorKind::TransportParameter,
+            "retry_source_connection_id mismatch",
+        ));
+    }
+    Ok(())
+}
+
+/// For server, check that the client does not send the transport parameters which can only be
+/// sent by the server: `original_destination_connection_id`, `preferred_address`,
+/// `retry_source_connection_id` and `stateless_reset_token`.
+///
+/// See [transport parameter definitions](https://www.rfc-editor.org/rfc/rfc9000.html#name-transport-parameter-definit)
+/// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
+pub fn check_client_params(params: &Parameters) -> Result<(), Error> {
+    let server_only = [
+        (
+            "original_destination_connection_id",
+            params.original_destination_connection_id().is_some(),
+        ),
+        ("preferred_address", params.preferred_address().is_some()),
+        (
+            "retry_source_connection_id",
+            params.retry_source_connection_id().is_some(),
+        ),
+        (
+            "stateless_reset_token",
+            params.statelss_reset_token().is_some(),
+        ),
+    ];
+    match server_only.iter().find(|(_, present)| *present) {
+        Some((name, _)) => Err(Error::with_default_fty(
+            ErrorKind::TransportParameter,
+            format!("client sent the server-only {name}"),
+        )),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use qbase::varint::VarInt;
+
+    use super::*;
+
+    #[test]
+    fn test_check_remembered() {
+        let remembered = Parameters::default();
+        let mut confirmed = remembered;
+        assert!(check_remembered(&remembered, &confirmed).is_ok());
+
+        confirmed.set_initial_max_data(VarInt::from_u32(1 << 20));
+        assert!(check_remembered(&remembered, &confirmed).is_ok());
+        let error = check_remembered(&confirmed, &remembered).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ProtocolViolation);
+    }
+
+    #[test]
+    fn test_check_initial_source_cid() {
+        let initial_scid = ConnectionId::random_gen(8);
+        let mut params = Parameters::default();
+        let error = check_initial_source_cid(&params, Some(initial_scid)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TransportParameter);
+
+        params.set_initial_source_connection_id(Some(initial_scid));
+        assert!(check_initial_source_cid(&params, Some(initial_scid)).is_ok());
+        let error = check_initial_source_cid(&params, Some(ConnectionId::random_gen(8)));
+        assert_eq!(error.unwrap_err().kind(), ErrorKind::TransportParameter);
+    }
+
+    #[test]
+    fn test_check_server_cids() {
+        let original_dcid = ConnectionId::random_gen(8);
+        let retry_scid = ConnectionId::random_gen(8);
+        let mut params = Parameters::default();
+        let error = check_server_cids(&params, original_dcid, None).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TransportParameter);
+
+        params.set_original_destination_connection_id(Some(original_dcid));
+        assert!(check_server_cids(&params, original_dcid, None).is_ok());
+        // 发生了Retry，但服务端没有回显retry_source_connection_id
+        let error = check_server_cids(&params, original_dcid, Some(retry_scid)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TransportParameter);
+
+        params.set_retry_source_connection_id(Some(retry_scid));
+        assert!(check_server_cids(&params, original_dcid, Some(retry_scid)).is_ok());
+        assert!(check_server_cids(&params, original_dcid, None).is_err());
+        let error = check_server_cids(&params, ConnectionId::random_gen(8), Some(retry_scid));
+        assert_eq!(error.unwrap_err().kind(), ErrorKind::TransportParameter);
+    }
+
+    #[test]
+    fn test_check_client_params() {
+        let mut params = Parameters::default();
+        params.set_initial_source_connection_id(Some(ConnectionId::random_gen(8)));
+        assert!(check_client_params(&params).is_ok());
+
+        params.set_retry_source_connection_id(Some(ConnectionId::random_gen(8)));
+        let error = check_client_params(&params).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TransportParameter);
+    }
+
+    #[tokio::test]
+    async fn test_read_for_0rtt() {
+        let mut remembered = Parameters::default();
+        remembered.set_initial_max_streams_bidi(VarInt::from_u32(8));
+        let mut params = ConnParameters::new(Default::default(), RemoteParameters::new());
+        params.remembered = Some(Arc::new(remembered));
+        // 握手完成之前使用记忆的参数
+        let read = params.read_for_0rtt().await.unwrap();
+        assert_eq!(read.initial_max_streams_bidi(), VarInt::from_u32(8));
+
+        assert!(params.remote.try_read().is_none());
+        params.remote.write(Arc::new(Parameters::default()));
+        assert!(params.remote.try_read().is_some());
+        let read = params.read_for_0rtt().await.unwrap();
+        assert_eq!(
+            read.initial_max_streams_bidi(),
+            Parameters::default().initial_max_streams_bidi()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_accepted() {
+        let remote = RemoteParameters::new();
+        let waiter = tokio::spawn({
+            let remote = remote.clone();
+            async move { remote.accepted().await }
+        });
+        // 参数就绪并不意味着已被接受
+        remote.write(Arc::new(Parameters::default()));
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+        remote.accept();
+        assert!(waiter.await.unwrap());
+
+        // 参数未通过验证，连接因此关闭
+        let remote = RemoteParameters::new();
+        remote.write(Arc::new(Parameters::default()));
+        let error = Error::with_default_fty(ErrorKind::TransportParameter, "invalid");
+        remote.on_conn_error(&error);
+        assert!(!remote.accepted().await);
+        // 连接关闭之后不能再被接受
+        remote.accept();
+        assert!(!remote.accepted().await);
+    }
+}
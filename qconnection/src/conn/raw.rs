@@ -1,49 +1,84 @@
 use std::{
+    net::SocketAddr,
     ops::Deref,
-    sync::{Arc, Mutex},
-    time::Duration,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
 use futures::channel::mpsc;
 use qbase::{
-    cid::ConnectionId,
+    cid::{ArcCidGenerator, ConnectionId},
+    entropy::ArcEntropy,
     error::Error,
     flow::FlowController,
-    frame::{MaxStreamsFrame, ReceiveFrame, StreamCtlFrame},
-    packet::keys::ArcKeys,
-    param::Parameters,
+    frame::{
+        MaxStreamsFrame, NewConnectionIdFrame, NewTokenFrame, PingFrame, ReceiveFrame, SendFrame,
+        StreamCtlFrame,
+    },
+    packet::{keys::ArcKeys, RetryPacket},
+    param::{Parameters, PreferredAddress},
+    qlog::QlogSink,
     sid::{ControlConcurrency, Role},
     token::{ArcTokenRegistry, TokenRegistry},
+    varint::VarInt,
+};
+use qcongestion::{
+    CongestionAlgorithm, CongestionConfig, CongestionControl, MayLoss, RetirePktRecord,
 };
-use qcongestion::{CongestionControl, MayLoss, RetirePktRecord};
 use qrecovery::{reliable::ArcReliableFrameDeque, space::Epoch};
-use qunreliable::DatagramFlow;
-use rustls::quic::Keys;
-use tokio::{sync::Notify, task::JoinHandle};
+use qunreliable::{DatagramFlow, DatagramSendPolicy};
+use rustls::{crypto::CryptoProvider, pki_types::CertificateDer, quic::Keys, Side};
+use tokio::{
+    sync::Notify,
+    task::{AbortHandle, JoinHandle},
+};
 
 use super::{
-    parameters::ConnParameters,
+    ack_frequency::{AckFrequencyError, ArcAckFrequency},
+    idle::ArcIdleTimer,
+    parameters::{
+        check_client_params, check_initial_source_cid, check_remembered, check_server_cids,
+        ConnParameters,
+    },
     scope::{
-        data::{DataMayLoss, DataScope},
+        data::{DataMayLoss, DataReaderContext, DataScope},
         handshake::{HandshakeMayloss, HandshakeScope},
         initial::{InitialMayLoss, InitialScope},
+        RecvContext,
     },
+    state::ArcConnectionState,
+    stats::{ArcPacketCounters, ConnectionStats},
+    version::{initial_keys_version, ArcVersions, Versions},
     ArcLocalCids, ArcRemoteCids, CidRegistry, DataStreams, Handshake, RcvdPackets,
 };
 use crate::{
+    clock::ArcClock,
     error::ConnError,
-    path::{ArcPath, ArcPathes, Path, Pathway},
+    path::{
+        ArcPath, ArcPathEvents, ArcPathes, Path, PathContext, PathEvent, PathScheduler, Pathway,
+        Reinjection, SchedulerHandle, DEFAULT_MAX_PLPMTU,
+    },
     router::Router,
-    tls::ArcTlsSession,
+    tls::{ArcTlsSession, SessionCache},
+    usc::ArcUsc,
 };
 
+/// The default time limit for the handshake to be completed, read
+/// [`Connection::set_handshake_timeout`] for more details.
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub struct Connection {
     pub initial_scid: ConnectionId,
+    initial_dcid: ConnectionId,
     pub token: Arc<Mutex<Vec<u8>>>,
     pub pathes: ArcPathes,
     pub cid_registry: CidRegistry,
     // handshake done的信号
     pub handshake: Handshake,
+    pub state: ArcConnectionState,
     pub flow_ctrl: FlowController,
     pub error: ConnError,
 
@@ -59,9 +94,47 @@ pub struct Connection {
 
     pub tls_session: ArcTlsSession,
     pub params: ConnParameters,
+
+    pub idle_timer: ArcIdleTimer,
+    idle_task: AbortHandle,
+    // 握手超时的起算时刻，更改超时时间不会重新计时
+    created_at: Instant,
+    handshake_task: Mutex<AbortHandle>,
+    pub ack_frequency: ArcAckFrequency,
+
+    versions: ArcVersions,
+    crypto_provider: Arc<CryptoProvider>,
+    // The SCID of the Retry packet, only one Retry packet is allowed
+    retry_scid: Arc<Mutex<Option<ConnectionId>>>,
+    // 是否启用spin bit，未指定则每条路径随机决定
+    spin_enabled: Arc<Mutex<Option<bool>>>,
+    // 路径MTU探测的上限，作用于之后创建的路径
+    max_mtu: Arc<AtomicUsize>,
+    // 各空间收发、丢失的包的计数，所有路径共享
+    counters: ArcPacketCounters,
+    // 所有计时器读取时间的来源
+    pub(crate) clock: ArcClock,
 }
 
 impl Connection {
+    /// Create a new connection.
+    ///
+    /// All the timers of the connection, including the idle timer, the handshake timer, and the
+    /// loss detection timers and the path validation timers of its paths, read the time from the
+    /// `clock`, which is a [`TokioClock`] usually. A [`MockClock`] can be given in tests to fire
+    /// the timers without real waiting.
+    ///
+    /// The data of the path challenges are filled by the `entropy` source, which is an
+    /// [`OsEntropy`] usually. The connection IDs issued by the connection are produced by the
+    /// `cid_generator`, which is a [`RandomCidGenerator`] usually.
+    ///
+    /// Each path uses a new controller of the `congestion_algorithm`, whose windows and initial
+    /// RTT are overridden by the `congestion_config`.
+    ///
+    /// [`OsEntropy`]: qbase::entropy::OsEntropy
+    /// [`RandomCidGenerator`]: qbase::cid::RandomCidGenerator
+    /// [`TokioClock`]: crate::clock::TokioClock
+    /// [`MockClock`]: crate::clock::MockClock
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         role: Role,
@@ -70,8 +143,16 @@ impl Connection {
         initial_scid: ConnectionId,
         initial_dcid: ConnectionId,
         initial_keys: Keys,
+        versions: Versions,
+        crypto_provider: Arc<CryptoProvider>,
         streams_ctrl: Box<dyn ControlConcurrency>,
+        congestion_algorithm: CongestionAlgorithm,
+        congestion_config: CongestionConfig,
         token_registry: ArcTokenRegistry,
+        qlog: Option<Arc<dyn QlogSink>>,
+        clock: ArcClock,
+        entropy: ArcEntropy,
+        cid_generator: ArcCidGenerator,
     ) -> Self {
         let (initial_packets_entry, rcvd_initial_packets) = mpsc::unbounded();
         let (zero_rtt_packets_entry, rcvd_0rtt_packets) = mpsc::unbounded();
@@ -82,6 +163,16 @@ impl Connection {
         let initial = InitialScope::new(ArcKeys::with_keys(initial_keys));
         let hs = HandshakeScope::default();
         let data = DataScope::default();
+        // ack frame中的Ack Delay以本端通告的ack_delay_exponent编码
+        let ack_delay_exponent = local_params.ack_delay_exponent().into_inner() as u8;
+        initial
+            .space
+            .rcvd_packets()
+            .set_ack_delay_exponent(ack_delay_exponent);
+        hs.space
+            .rcvd_packets()
+            .set_ack_delay_exponent(ack_delay_exponent);
+        data.spaces.set_ack_delay_exponent(ack_delay_exponent);
 
         let router_registry = Router::registry(
             initial_scid,
@@ -90,10 +181,24 @@ impl Connection {
                 initial_packets_entry,
                 zero_rtt_packets_entry,
                 hs_packets_entry,
-                one_rtt_packets_entry,
+                one_rtt_packets_entry.clone(),
             ],
+            cid_generator,
         );
-        let local_cids = ArcLocalCids::new(initial_scid, router_registry);
+        let local_cids = match local_params.preferred_address() {
+            // 服务端在preferred_address传输参数中发布序号为1的连接ID
+            Some(preferred_address) if role == Role::Server => {
+                let preferred_cid = preferred_address.connection_id();
+                let reset_token = preferred_address.stateless_reset_token();
+                _ = Router::alias(preferred_cid, &initial_scid);
+                ArcLocalCids::with_preferred_cid(
+                    initial_scid,
+                    (preferred_cid, reset_token),
+                    router_registry,
+                )
+            }
+            _ => ArcLocalCids::new(initial_scid, router_registry),
+        };
         let remote_cids = ArcRemoteCids::new(
             initial_dcid,
             local_params.active_connection_id_limit().into(),
@@ -101,11 +206,24 @@ impl Connection {
         );
         let cid_registry = CidRegistry::new(local_cids, remote_cids);
         let handshake = Handshake::new(role, reliable_frames.clone());
-        let flow_ctrl = FlowController::with_parameter(65535, 65535);
+        let state = ArcConnectionState::default();
+        let flow_ctrl =
+            FlowController::with_parameter(65535, local_params.initial_max_data().into());
         let conn_error = ConnError::default();
+        let idle_timer = ArcIdleTimer::new(clock.clone());
+        let local_min_ack_delay = local_params.min_ack_delay();
+        let ack_frequency = ArcAckFrequency::new(
+            local_min_ack_delay.map(|delay| Duration::from_micros(delay.into_inner())),
+        );
 
-        let streams = DataStreams::new(role, &local_params, streams_ctrl, reliable_frames.clone());
-        let datagrams = DatagramFlow::new(0);
+        let streams = DataStreams::new(
+            role,
+            &local_params,
+            streams_ctrl,
+            reliable_frames.clone(),
+            flow_ctrl.recver(),
+        );
+        let datagrams = DatagramFlow::new(local_params.max_datagram_frame_size().into());
 
         let token = match token_registry.deref() {
             TokenRegistry::Client((server_name, client)) => {
@@ -113,29 +231,64 @@ impl Connection {
             }
             TokenRegistry::Server(_) => Arc::new(Mutex::new(vec![])),
         };
+        let remote_params = tls_session.keys_upgrade(
+            [
+                &initial.crypto_stream,
+                &hs.crypto_stream,
+                &data.crypto_stream,
+            ],
+            hs.keys.clone(),
+            data.one_rtt_keys.clone(),
+            conn_error.clone(),
+            handshake.clone(),
+            state.clone(),
+        );
+
+        let versions = ArcVersions::new(versions);
+        let spin_enabled = Arc::new(Mutex::new(None));
+        let max_mtu = Arc::new(AtomicUsize::new(DEFAULT_MAX_PLPMTU));
+        let counters = ArcPacketCounters::default();
+        let scheduler = SchedulerHandle::default();
+        let path_events = ArcPathEvents::default();
         let path_creator = Box::new({
+            let scheduler = scheduler.clone();
+            let path_events = path_events.clone();
+            let remote_params = remote_params.clone();
+            let spin_enabled = spin_enabled.clone();
+            let max_mtu = max_mtu.clone();
+            let counters = counters.clone();
+            let qlog = qlog.clone();
+            let clock = clock.clone();
+            let entropy = entropy.clone();
             let cid_registry = cid_registry.clone();
             let flow_ctrl = flow_ctrl.clone();
             let handshake = handshake.clone();
+            let ack_frequency = ack_frequency.clone();
 
             let gen_readers = {
                 let initial = initial.clone();
                 let hs = hs.clone();
                 let data = data.clone();
-                let reliable_frames = reliable_frames.clone();
-                let streams = streams.clone();
-                let datagrams = datagrams.clone();
                 let token = token.clone();
-                move |path: &Path| {
+                let versions = versions.clone();
+                let data_ctx = DataReaderContext {
+                    versions: versions.clone(),
+                    reliable_frames: reliable_frames.clone(),
+                    streams: streams.clone(),
+                    datagrams: datagrams.clone(),
+                    idle_timer: idle_timer.clone(),
+                    ack_frequency: ack_frequency.clone(),
+                };
+                move |path: &Path, reinjection: Reinjection| {
                     (
-                        initial.reader(token.clone()),
-                        hs.reader(),
+                        initial.reader(token.clone(), versions.clone()),
+                        hs.reader(versions.clone()),
                         data.reader(
+                            data_ctx.clone(),
                             path.challenge_sndbuf(),
                             path.response_sndbuf(),
-                            reliable_frames.clone(),
-                            streams.clone(),
-                            datagrams.clone(),
+                            reinjection,
+                            path.spaces().clone(),
                         ),
                     )
                 }
@@ -145,39 +298,87 @@ impl Connection {
             let hs = hs.clone();
             let data = data.clone();
 
-            let initial_may_loss =
-                InitialMayLoss::new(initial.space.clone(), initial.crypto_stream.outgoing());
-            let hs_may_loss = HandshakeMayloss::new(hs.space.clone(), hs.crypto_stream.outgoing());
-            let data_may_loss = DataMayLoss::new(
-                data.space.clone(),
-                reliable_frames.clone(),
-                streams.clone(),
-                data.crypto_stream.outgoing(),
+            let initial_may_loss = InitialMayLoss::new(
+                initial.space.clone(),
+                initial.crypto_stream.outgoing(),
+                counters.clone(),
+            );
+            let hs_may_loss = HandshakeMayloss::new(
+                hs.space.clone(),
+                hs.crypto_stream.outgoing(),
+                counters.clone(),
             );
+            let reliable_frames = reliable_frames.clone();
+            let streams = streams.clone();
 
             move |pathway, usc| {
                 let scid = cid_registry.local.active_cids()[0];
                 let dcid = cid_registry.remote.apply_dcid();
+                // 多路径下，各路径在各自的包号空间中收发1rtt数据包
+                let spaces = data.spaces.path_spaces();
+                let data_may_loss = DataMayLoss::new(
+                    spaces.clone(),
+                    reliable_frames.clone(),
+                    streams.clone(),
+                    data.crypto_stream.outgoing(),
+                    counters.clone(),
+                );
                 let loss: [Box<dyn MayLoss>; 3] = [
                     Box::new(initial_may_loss.clone()),
                     Box::new(hs_may_loss.clone()),
-                    Box::new(data_may_loss.clone()),
+                    Box::new(data_may_loss),
                 ];
                 let retire: [Box<dyn RetirePktRecord>; 3] = [
                     Box::new(initial.clone()),
                     Box::new(hs.clone()),
-                    Box::new(data.clone()),
+                    Box::new(spaces.clone()),
                 ];
 
-                let path = ArcPath::new(usc, scid, dcid, loss, retire);
+                let controller = congestion_algorithm.controller_with(&congestion_config);
+                let ctx = PathContext {
+                    role,
+                    initial_rtt: congestion_config.initial_rtt(),
+                    max_mtu: max_mtu.load(Ordering::Relaxed),
+                    counters: counters.clone(),
+                    qlog: qlog.clone(),
+                    clock: clock.clone(),
+                    entropy: entropy.clone(),
+                    spaces,
+                };
+                let path = ArcPath::new(usc, scid, dcid, controller, loss, retire, ctx);
+                if let Some(enabled) = *spin_enabled.lock().unwrap() {
+                    path.set_spin_enabled(enabled);
+                }
+                tokio::spawn({
+                    let remote_params = remote_params.clone();
+                    let ack_frequency = ack_frequency.clone();
+                    let path = path.clone();
+                    async move {
+                        if let Ok(remote_params) = remote_params.read().await {
+                            // 对端确认了ACK_FREQUENCY帧之后，以其中请求的max_ack_delay为准
+                            let max_ack_delay = ack_frequency.acked_max_ack_delay().unwrap_or(
+                                Duration::from_millis(remote_params.max_ack_delay().into_inner()),
+                            );
+                            path.set_max_ack_delay(max_ack_delay);
+                            let ack_delay_exponent = remote_params.ack_delay_exponent();
+                            path.set_ack_delay_exponent(ack_delay_exponent.into_inner() as u8);
+                            // 对端能接收的最大数据报大小，是PMTU探测的上限
+                            let max_udp_payload_size = remote_params.max_udp_payload_size();
+                            path.mtu
+                                .limit_max_mtu(max_udp_payload_size.into_inner() as usize);
+                            path.cc.set_mtu(path.mtu.current_mtu());
+                        }
+                    }
+                });
                 if !handshake.is_handshake_done() {
                     if role == Role::Client {
                         path.anti_amplifier.grant();
                     }
                 } else {
+                    path.cc.on_handshake_done();
                     path.begin_validation();
                 }
-                path.begin_sending(pathway, &flow_ctrl, &gen_readers);
+                path.begin_sending(pathway, &scheduler, &path_events, &flow_ctrl, &gen_readers);
                 path
             }
         });
@@ -187,7 +388,17 @@ impl Connection {
                 conn_error.no_viable_path();
             }
         });
-        let pathes = ArcPathes::new(path_creator, on_no_path);
+        let pathes = ArcPathes::new(
+            path_creator,
+            on_no_path,
+            &scheduler,
+            path_events,
+            clock.clone(),
+        );
+        if local_params.disable_active_migration() {
+            pathes.refuse_active_migration();
+        }
+        tokio::spawn(pathes.sweep_idle_paths_periodically());
 
         let validate = {
             let tls_session = tls_session.clone();
@@ -204,40 +415,74 @@ impl Connection {
         };
 
         let notify = Arc::new(Notify::new());
+        let recv_ctx = RecvContext {
+            pathes: pathes.clone(),
+            handshake: handshake.clone(),
+            idle_timer: idle_timer.clone(),
+            notify: notify.clone(),
+            conn_error: conn_error.clone(),
+        };
         let join_initial = initial.build(
             rcvd_initial_packets,
-            &pathes,
+            &recv_ctx,
             &cid_registry.remote,
-            &notify,
-            &conn_error,
             validate,
         );
 
-        let join_hs = hs.build(rcvd_hs_packets, &pathes, &notify, &conn_error);
-
-        let remote_params = tls_session.keys_upgrade(
-            [
-                &initial.crypto_stream,
-                &hs.crypto_stream,
-                &data.crypto_stream,
-            ],
-            hs.keys.clone(),
-            data.one_rtt_keys.clone(),
-            conn_error.clone(),
-            handshake.clone(),
-        );
+        // 服务端首次成功处理Handshake包后，丢弃Initial密钥，客户端则是在首次发送Handshake包时
+        let discard_initial = {
+            let initial_keys = initial.keys.clone();
+            move |path: &Path| {
+                if role == Role::Server && initial_keys.invalid().is_some() {
+                    path.cc.on_get_handshake_keys();
+                    path.cc.discard_epoch(Epoch::Initial);
+                }
+            }
+        };
+        let join_hs = hs.build(rcvd_hs_packets, &recv_ctx, discard_initial);
 
+        let local_idle_timeout = local_params.max_idle_timeout();
+        let local_multipath = local_params.enable_multipath();
         let params = ConnParameters::new(local_params.into(), remote_params.clone());
+        let retry_scid = Arc::new(Mutex::new(None));
         tokio::spawn({
+            let retry_scid = retry_scid.clone();
             let streams = streams.clone();
+            let flow_ctrl = flow_ctrl.clone();
             let conn_error = conn_error.clone();
             let cid_registry = cid_registry.clone();
+            let idle_timer = idle_timer.clone();
+            let ack_frequency = ack_frequency.clone();
+            let pathes = pathes.clone();
+            let params = remote_params.clone();
+            let one_rtt_packets_entry = one_rtt_packets_entry.clone();
             async move {
                 let remote_params = remote_params.read().await;
                 let Ok(remote_params) = remote_params else {
                     return;
                 };
 
+                let initial_scid = cid_registry.remote.initial_dcid();
+                if let Err(error) = check_initial_source_cid(&remote_params, initial_scid) {
+                    conn_error.on_error(error);
+                    return;
+                }
+                if role == Role::Client {
+                    // Retry只会发生在握手之前，此时已经确定
+                    let retry_scid = *retry_scid.lock().unwrap();
+                    if let Err(error) = check_server_cids(&remote_params, initial_dcid, retry_scid)
+                    {
+                        conn_error.on_error(error);
+                        return;
+                    }
+                } else if let Err(error) = check_client_params(&remote_params) {
+                    conn_error.on_error(error);
+                    return;
+                }
+
+                // 只会增大，0Rtt被拒绝后新的参数不会使已使用的额度失效
+                flow_ctrl.reset_send_window(remote_params.initial_max_data().into());
+
                 // pretend to receive the MAX_STREAM frames
                 _ = streams.recv_frame(&StreamCtlFrame::MaxStreams(MaxStreamsFrame::Bi(
                     remote_params.initial_max_streams_bidi(),
@@ -249,32 +494,136 @@ impl Connection {
                 let active_cid_limit = remote_params.active_connection_id_limit().into();
                 if let Err(e) = cid_registry.local.set_limit(active_cid_limit) {
                     conn_error.on_error(e);
+                    return;
+                }
+
+                if let Some(reset_token) = remote_params.statelss_reset_token() {
+                    cid_registry.remote.set_initial_reset_token(*reset_token);
+                    Router::add_reset_token(*reset_token, one_rtt_packets_entry.clone());
                 }
+                if let Some(preferred_address) = remote_params.preferred_address() {
+                    // 首选地址的连接ID序号为1，如同收到了携带它的NEW_CONNECTION_ID帧
+                    let frame = NewConnectionIdFrame {
+                        sequence: VarInt::from_u32(1),
+                        retire_prior_to: VarInt::from_u32(0),
+                        id: preferred_address.connection_id(),
+                        reset_token: preferred_address.stateless_reset_token(),
+                    };
+                    let remote_cids = Router::reset_tokens(
+                        cid_registry.remote.clone(),
+                        one_rtt_packets_entry.clone(),
+                    );
+                    if let Err(error) = remote_cids.recv_frame(&frame) {
+                        conn_error.on_error(error);
+                        return;
+                    }
+                }
+
+                idle_timer.negotiate(local_idle_timeout, remote_params.max_idle_timeout());
+
+                let remote_min_ack_delay = remote_params.min_ack_delay();
+                ack_frequency.on_remote_params(
+                    remote_min_ack_delay.map(|delay| Duration::from_micros(delay.into_inner())),
+                );
+
+                // 双方都通告了enable_multipath，才能同时使用多条路径
+                if local_multipath && remote_params.enable_multipath() {
+                    pathes.enable_multipath();
+                }
+                if remote_params.disable_active_migration() {
+                    pathes.disable_active_migration();
+                }
+                params.accept();
             }
         });
+        let idle_task = tokio::spawn({
+            let idle_timer = idle_timer.clone();
+            let conn_error = conn_error.clone();
+            let pathes = pathes.clone();
+            async move {
+                // 空闲超时至少为活跃路径当前PTO的3倍
+                let pto = || {
+                    pathes
+                        .active_path()
+                        .map_or(Duration::ZERO, |path| path.cc.pto_time(Epoch::Data))
+                };
+                idle_timer.expired(pto).await;
+                conn_error.on_idle_timeout();
+            }
+        })
+        .abort_handle();
+        let created_at = clock.now();
+        let handshake_task = Mutex::new(spawn_handshake_timer(
+            state.clone(),
+            conn_error.clone(),
+            clock.clone(),
+            created_at + DEFAULT_HANDSHAKE_TIMEOUT,
+        ));
+
+        if role == Role::Client {
+            tokio::spawn({
+                let state = state.clone();
+                let tls_session = tls_session.clone();
+                let data = data.clone();
+                let reliable_frames = reliable_frames.clone();
+                let streams = streams.clone();
+                let counters = counters.clone();
+                async move {
+                    if !state.connected().await {
+                        return;
+                    }
+                    // 1-RTT密钥已经就绪，不会再发送0-RTT包
+                    if tls_session.is_0rtt_rejected() {
+                        data.on_0rtt_rejected(&reliable_frames, &streams, &counters);
+                    } else {
+                        data.on_0rtt_accepted();
+                    }
+                }
+            });
+        }
+
+        if role == Role::Client {
+            tokio::spawn({
+                let state = state.clone();
+                let remote_params = params.remote.clone();
+                let pathes = pathes.clone();
+                async move {
+                    if !state.connected().await {
+                        return;
+                    }
+                    let Ok(remote_params) = remote_params.read().await else {
+                        return;
+                    };
+                    if let Some(preferred_address) = remote_params.preferred_address() {
+                        migrate_to_preferred_address(&pathes, &preferred_address);
+                    }
+                }
+            });
+        }
 
         let (join_0rtt, join_1rtt) = data.build(
-            &pathes,
-            &handshake,
+            &recv_ctx,
             &reliable_frames,
             &streams,
             &datagrams,
             &cid_registry,
             &flow_ctrl,
-            &notify,
-            &conn_error,
+            &ack_frequency,
             rcvd_0rtt_packets,
             rcvd_1rtt_packets,
+            &one_rtt_packets_entry,
             token_registry,
         );
         let join_handles = [join_initial, join_0rtt, join_hs, join_1rtt];
 
         Self {
             initial_scid,
+            initial_dcid,
             token,
             pathes,
             cid_registry,
             handshake,
+            state,
             flow_ctrl,
             streams,
             reliable_frames,
@@ -287,9 +636,93 @@ impl Connection {
             error: conn_error,
             params,
             tls_session,
+            idle_timer,
+            idle_task,
+            created_at,
+            handshake_task,
+            ack_frequency,
+            versions,
+            crypto_provider,
+            retry_scid,
+            spin_enabled,
+            max_mtu,
+            counters,
+            clock,
         }
     }
 
+    /// For client, try to send 0-RTT data to the server named `server_name`.
+    ///
+    /// The transport parameters confirmed by the server will be stored into the `session_cache`
+    /// once the handshake is completed, for the future connections.
+    ///
+    /// If both the TLS session ticket and the remembered parameters of the server are available,
+    /// the 0-RTT keys are installed, and the connection-level flow control limit and the stream
+    /// limits are pre-seeded with the remembered parameters. Return `false` if the connection
+    /// falls back to 1-RTT.
+    ///
+    /// If the server accepts the 0-RTT data, but the confirmed parameters reduce the remembered
+    /// limits, the connection will be closed with a PROTOCOL_VIOLATION error.
+    pub fn enable_0rtt(&mut self, server_name: &str, session_cache: Arc<dyn SessionCache>) -> bool {
+        let remembered = self
+            .tls_session
+            .load_0rtt(server_name, session_cache.as_ref());
+
+        tokio::spawn({
+            let server_name = server_name.to_owned();
+            let state = self.state.clone();
+            let remote_params = self.params.remote.clone();
+            let tls_session = self.tls_session.clone();
+            let conn_error = self.error.clone();
+            let remembered = remembered.as_ref().map(|(_, params)| params.clone());
+            async move {
+                if !state.connected().await {
+                    return;
+                }
+                let Ok(confirmed) = remote_params.read().await else {
+                    return;
+                };
+                if let Some(remembered) = remembered {
+                    if !tls_session.is_0rtt_rejected() {
+                        if let Err(error) = check_remembered(&remembered, &confirmed) {
+                            conn_error.on_error(error);
+                            return;
+                        }
+                    }
+                }
+                session_cache.store(&server_name, confirmed);
+            }
+        });
+
+        let Some((keys, remembered)) = remembered else {
+            return false;
+        };
+        self.data.zero_rtt_keys.set_keys(keys);
+        self.flow_ctrl
+            .reset_send_window(remembered.initial_max_data().into());
+        _ = self
+            .streams
+            .recv_frame(&StreamCtlFrame::MaxStreams(MaxStreamsFrame::Bi(
+                remembered.initial_max_streams_bidi(),
+            )));
+        _ = self
+            .streams
+            .recv_frame(&StreamCtlFrame::MaxStreams(MaxStreamsFrame::Uni(
+                remembered.initial_max_streams_uni(),
+            )));
+        self.params.remembered = Some(remembered);
+        true
+    }
+
+    /// The server rejected the 0-RTT data, read [`DataScope::on_0rtt_rejected`] for more details.
+    ///
+    /// This is called automatically once the handshake is completed, if the server did not
+    /// accept the early data.
+    pub fn on_zero_rtt_rejected(&self) {
+        self.data
+            .on_0rtt_rejected(&self.reliable_frames, &self.streams, &self.counters);
+    }
+
     pub fn max_pto_duration(&self) -> Option<Duration> {
         self.pathes
             .iter()
@@ -303,12 +736,2080 @@ impl Connection {
         self.streams.on_conn_error(error);
         self.params.on_conn_error(error);
         self.tls_session.abort();
+        self.pathes.iter().for_each(|path| path.stop_sending());
+        self.idle_task.abort();
+        self.handshake_task.lock().unwrap().abort();
         self.notify.notify_waiters();
     }
 
+    /// Handle the versions listed in a Version Negotiation packet sent by the server.
+    ///
+    /// If a mutually supported version is selected, the handshake is restarted with the version,
+    /// read [`Connection::restart_handshake`] for more details. If none of the versions is
+    /// supported, the connection attempt is abandoned with a [`NoCommonVersion`] error.
+    ///
+    /// The Version Negotiation packet is ignored once the handshake has progressed, because it
+    /// must be injected by an attacker. Read [`Versions`] for more details about the downgrade
+    /// protection.
+    ///
+    /// [`NoCommonVersion`]: super::version::NoCommonVersion
+    pub fn handle_version_negotiation(&self, versions: &[u32]) {
+        if self.hs.keys.get_local_keys().is_some() {
+            return;
+        }
+        match self.versions.on_version_negotiation(versions) {
+            Ok(Some(version)) => self.restart_handshake(version),
+            Ok(None) => {}
+            Err(no_common_version) => self.error.on_no_common_version(no_common_version),
+        }
+    }
+
+    /// Handle a Retry packet sent by the server.
+    ///
+    /// The Retry packet is discarded if its integrity tag is invalid, its version is not the one
+    /// in use, or the handshake has progressed. Otherwise, the SCID of the Retry packet is adopted
+    /// as the new DCID, the retry token is carried by the subsequent Initial packets, and the
+    /// Initial keys are re-derived with the new DCID.
+    ///
+    /// Only one Retry packet is processed per connection, the subsequent ones are discarded.
+    ///
+    /// See [retry packet](https://www.rfc-editor.org/rfc/rfc9000.html#name-retry-packet)
+    /// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
+    pub fn handle_retry(&self, retry: RetryPacket) {
+        if self.hs.keys.get_local_keys().is_some()
+            || retry.token.is_empty()
+            || retry.scid == self.initial_dcid
+            || retry.version != self.versions.current()
+            || !retry.verify_integrity(&self.initial_dcid)
+        {
+            return;
+        }
+
+        let mut retry_scid = self.retry_scid.lock().unwrap();
+        if retry_scid.is_some() {
+            return;
+        }
+        *retry_scid = Some(retry.scid);
+
+        *self.token.lock().unwrap() = retry.token.clone();
+        self.cid_registry.remote.revise_initial_dcid(retry.scid);
+        self.rederive_initial_keys(retry.scid);
+        // The packet numbers of the Initial space continue to increase, the data sent before
+        // are retransmitted in the new Initial packets with the new keys and the token.
+        self.retransmit_initial_data();
+    }
+
+    /// Restart the handshake with the `version` selected by the version negotiation.
+    ///
+    /// Besides the Initial keys, the Handshake and 1-RTT keys are derived with the version too,
+    /// so a new TLS session is started, whose ClientHello is sent from the beginning of the
+    /// Initial crypto stream. The data sent in the abandoned attempt is discarded rather than
+    /// retransmitted, and so are the 0-RTT keys of it, the 0-RTT data will be retransmitted in
+    /// the 1-RTT packets.
+    fn restart_handshake(&self, version: u32) {
+        self.rederive_initial_keys(self.initial_dcid);
+        // 之前的Initial包都视为丢失，但其中的ClientHello不再重传
+        let sent_record = self.initial.space.sent_packets();
+        let mut guard = sent_record.recv();
+        for pn in 0..guard.largest_pn() {
+            guard.may_loss_pkt(pn).for_each(drop);
+        }
+        drop(guard);
+        self.initial.crypto_stream.outgoing().restart();
+        self.on_zero_rtt_rejected();
+        let version = initial_keys_version(version).expect("selected a known version");
+        self.tls_session.restart(version);
+    }
+
+    /// The QUIC version in use, which the long header packets are sent with.
+    pub fn version(&self) -> u32 {
+        self.versions.current()
+    }
+
+    /// Re-derive the Initial keys with the `dcid` and the current version.
+    fn rederive_initial_keys(&self, dcid: ConnectionId) {
+        let version =
+            initial_keys_version(self.versions.current()).expect("selected a known version");
+        let initial_keys =
+            ArcTlsSession::initial_keys(&self.crypto_provider, Side::Client, dcid, version);
+        self.initial.keys.replace_keys(initial_keys);
+    }
+
+    /// Mark all the data sent in the Initial packets as lost, so that they will be retransmitted
+    /// in the new Initial packets after the Retry.
+    fn retransmit_initial_data(&self) {
+        let sent_record = self.initial.space.sent_packets();
+        let mut guard = sent_record.recv();
+        for pn in 0..guard.largest_pn() {
+            for frame in guard.may_loss_pkt(pn) {
+                self.initial.crypto_stream.outgoing().may_loss_data(&frame);
+            }
+        }
+    }
+
+    /// Abandon the path on the `pathway`, its tasks are stopped and the connection ID it used is
+    /// retired.
+    ///
+    /// Return `false` if there is no such path, or it is the only path of the connection, read
+    /// [`Paths::abandon`] for more details.
+    ///
+    /// [`Paths::abandon`]: crate::path::Paths::abandon
+    pub fn abandon_path(&self, pathway: Pathway) -> bool {
+        self.pathes.abandon(pathway)
+    }
+
+    /// Migrate the connection to the `pathway` deliberately, the packets on it are sent and
+    /// received by the `usc`. The connection migrates once the new path is validated.
+    ///
+    /// Return `false` if the peer advertised the `disable_active_migration` transport parameter,
+    /// read [`Paths::migrate_actively`] for more details.
+    ///
+    /// [`Paths::migrate_actively`]: crate::path::Paths::migrate_actively
+    pub fn migrate(&self, pathway: Pathway, usc: ArcUsc) -> bool {
+        self.pathes.migrate_actively(pathway, usc)
+    }
+
+    /// Enable or disable the keep-alive of the connection.
+    ///
+    /// If enabled, a PING frame will be sent when the connection has been idle for the `interval`,
+    /// and at least once every half of the negotiated idle timeout, so that the connection will
+    /// not be closed due to idle timeout. Read [`ArcIdleTimer`] for more details.
+    pub fn set_keep_alive(&self, interval: Option<Duration>) {
+        self.idle_timer.set_keep_alive(interval);
+    }
+
+    /// Set the time limit for the handshake to be completed, counted from the creation of the
+    /// connection, [`DEFAULT_HANDSHAKE_TIMEOUT`] by default.
+    ///
+    /// If the handshake is not completed in time, the connection attempt is abandoned silently
+    /// with a [`HandshakeTimeout`] error. It is independent of the idle timeout, which only limits
+    /// how long the connection can stay silent. `None` means no limit.
+    ///
+    /// [`HandshakeTimeout`]: crate::error::ConnErrorKind::HandshakeTimeout
+    pub fn set_handshake_timeout(&self, timeout: Option<Duration>) {
+        let mut handshake_task = self.handshake_task.lock().unwrap();
+        handshake_task.abort();
+        if let Some(timeout) = timeout {
+            *handshake_task = spawn_handshake_timer(
+                self.state.clone(),
+                self.error.clone(),
+                self.clock.clone(),
+                self.created_at + timeout,
+            );
+        }
+    }
+
+    /// Set the maximum number of the paths of the connection, and the maximum number of the
+    /// unvalidated paths among them, read [`Paths::set_max_paths`] for more details.
+    ///
+    /// [`Paths::set_max_paths`]: crate::path::Paths::set_max_paths
+    pub fn set_max_paths(&self, max_paths: usize, max_unvalidated_paths: usize) {
+        self.pathes.set_max_paths(max_paths, max_unvalidated_paths);
+    }
+
+    /// Set the scheduler deciding which paths carry the new stream data once the multipath
+    /// extension is negotiated, read [`Paths::set_scheduler`] for more details.
+    ///
+    /// [`Paths::set_scheduler`]: crate::path::Paths::set_scheduler
+    pub fn set_path_scheduler(&self, scheduler: Arc<dyn PathScheduler>) {
+        self.pathes.set_scheduler(scheduler);
+    }
+
+    /// Set what to do with the queued datagrams when the congestion window is exhausted, they
+    /// are dropped by default, read [`DatagramSendPolicy`] for more details.
+    pub fn set_datagram_send_policy(&self, policy: DatagramSendPolicy) {
+        self.datagrams.set_send_policy(policy);
+    }
+
+    /// Set how long a validated path other than the active one can stay idle before it is
+    /// abandoned, read [`Paths::set_idle_path_timeout`] for more details.
+    ///
+    /// [`Paths::set_idle_path_timeout`]: crate::path::Paths::set_idle_path_timeout
+    pub fn set_idle_path_timeout(&self, timeout: Option<Duration>) {
+        self.pathes.set_idle_path_timeout(timeout);
+    }
+
+    /// Enable or disable the latency spin bit on all the paths of the connection, including the
+    /// paths created later.
+    ///
+    /// If not specified, the spin bit is disabled randomly on one in every 16 paths for privacy,
+    /// read [`ArcSpinBit`] for more details.
+    ///
+    /// [`ArcSpinBit`]: crate::path::ArcSpinBit
+    pub fn set_spin_enabled(&self, enabled: bool) {
+        *self.spin_enabled.lock().unwrap() = Some(enabled);
+        for path in self.pathes.iter() {
+            path.set_spin_enabled(enabled);
+        }
+    }
+
+    /// Set the ceiling of the path MTU discovery of the paths created later, it is
+    /// [`DEFAULT_MAX_PLPMTU`] by default. Read [`PathMtu`] for more details.
+    ///
+    /// [`PathMtu`]: crate::path::PathMtu
+    pub fn set_max_mtu(&self, max_mtu: usize) {
+        self.max_mtu.store(max_mtu, Ordering::Relaxed);
+    }
+
+    /// Send a PING frame to the peer, to elicit an acknowledgement.
+    ///
+    /// The PING frame is queued with the other reliable frames and carried by the next 1-RTT
+    /// packet sent on the active path. It is useful to keep the connection alive, to probe the
+    /// path, or to confirm a key update. Just like the other reliable frames, the PING frame is
+    /// sent again if the packet carrying it is lost.
+    pub fn send_ping(&self) {
+        self.reliable_frames.send_frame([PingFrame]);
+    }
+
+    /// Send a NEW_TOKEN frame carrying the `token` to the client, which can be used in the
+    /// Initial packet of a future connection to validate the client's address.
+    ///
+    /// Only the server can send the NEW_TOKEN frame, and the `token` must not be empty.
+    pub fn send_new_token(&self, token: Vec<u8>) {
+        debug_assert!(!token.is_empty(), "NEW_TOKEN frame with empty token");
+        self.reliable_frames.send_frame([NewTokenFrame { token }]);
+    }
+
+    /// Ask the peer to change its acknowledgement frequency, with an ACK_FREQUENCY frame.
+    ///
+    /// The peer sends an acknowledgement after receiving more than `ack_eliciting_threshold`
+    /// ack-eliciting packets, or `max_ack_delay` after receiving an ack-eliciting packet, or
+    /// seeing more than `reordering_threshold` out-of-order packets, 0 means never acknowledging
+    /// immediately due to reordering.
+    ///
+    /// Return an error if the peer does not advertise the `min_ack_delay` transport parameter,
+    /// or the `max_ack_delay` is less than it. Read [`ArcAckFrequency`] for more details.
+    pub fn request_ack_frequency(
+        &self,
+        ack_eliciting_threshold: u64,
+        max_ack_delay: Duration,
+        reordering_threshold: u64,
+    ) -> Result<(), AckFrequencyError> {
+        let frame = self.ack_frequency.request(
+            ack_eliciting_threshold,
+            max_ack_delay,
+            reordering_threshold,
+        )?;
+        self.reliable_frames.send_frame([frame]);
+        Ok(())
+    }
+
+    /// The transport parameters of the peer, [`None`] before the handshake provides them.
+    pub fn peer_transport_parameters(&self) -> Option<Arc<Parameters>> {
+        self.params.remote.try_read()
+    }
+
+    /// The transport parameters of our own, which are sent to the peer.
+    pub fn local_transport_parameters(&self) -> Arc<Parameters> {
+        self.params.local.clone()
+    }
+
+    /// The application protocol negotiated by ALPN, read [`ArcTlsSession::alpn_protocol`] for
+    /// more details.
+    pub fn alpn_protocol(&self) -> Option<Vec<u8>> {
+        self.tls_session.alpn_protocol()
+    }
+
+    /// The certificate chain presented by the peer, read [`ArcTlsSession::peer_certificates`]
+    /// for more details.
+    pub fn peer_certificates(&self) -> Option<Vec<CertificateDer<'static>>> {
+        self.tls_session.peer_certificates()
+    }
+
+    /// The server name requested by the client through SNI, read [`ArcTlsSession::server_name`]
+    /// for more details.
+    pub fn server_name(&self) -> Option<String> {
+        self.tls_session.server_name()
+    }
+
+    /// Return a snapshot of the statistics of the connection.
+    ///
+    /// The packet counters are atomic and are read without any lock, the other fields are read
+    /// from the active path, the flow controllers and the streams, each takes a short lock.
+    pub fn stats(&self) -> ConnectionStats {
+        let mut stats = ConnectionStats::default();
+        self.counters.load_into(&mut stats);
+        if let Some(path) = self.pathes.active_path() {
+            let rtt = path.rtt();
+            stats.smoothed_rtt = Some(rtt.smoothed_rtt);
+            stats.min_rtt = Some(rtt.min_rtt);
+            stats.congestion_window = Some(path.cc.congestion_window());
+        }
+        stats.send_max_data = self.flow_ctrl.sender.max_data();
+        stats.recv_max_data = self.flow_ctrl.recver.max_data();
+        stats.data_blocked_frames = self.flow_ctrl.recver.data_blocked_frames();
+        stats.paths = self.pathes.len();
+        stats.streams = self.streams.active_streams();
+        stats.datagrams_dropped = self.datagrams.dropped_datagrams();
+        stats
+    }
+
+    /// Subscribe the events of the paths emitted from now on, such as the validations and the
+    /// migrations, read [`PathEvent`] for more details.
+    pub fn path_events(&self) -> mpsc::UnboundedReceiver<PathEvent> {
+        self.pathes.subscribe_events()
+    }
+
+    /// Return the active path of the connection, which is used for new transmissions.
+    pub fn active_path(&self) -> Option<ArcPath> {
+        self.pathes.active_path()
+    }
+
     pub fn update_path_recv_time(&self, pathway: Pathway) {
         if let Some(path) = self.pathes.try_get(&pathway).try_unwrap() {
             path.update_recv_time();
         }
     }
 }
+
+/// Spawn the task to abandon the connection with a handshake timeout error, if the connection
+/// does not reach the [`ConnectionState::Connected`] state before the `deadline`.
+///
+/// The task ends once the handshake is completed, or the connection is closed before that.
+///
+/// [`ConnectionState::Connected`]: super::state::ConnectionState::Connected
+fn spawn_handshake_timer(
+    state: ArcConnectionState,
+    conn_error: ConnError,
+    clock: ArcClock,
+    deadline: Instant,
+) -> AbortHandle {
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = state.connected() => {}
+            _ = clock.sleep_until(deadline) => conn_error.on_handshake_timeout(),
+        }
+    })
+    .abort_handle()
+}
+
+/// Select the pathway to the `preferred_address` of the server, which has the same local address
+/// as the `active` pathway, so the address family is the same.
+///
+/// Return [`None`] if the server does not provide an address of that family.
+fn preferred_pathway(active: Pathway, preferred_address: &PreferredAddress) -> Option<Pathway> {
+    let Pathway::Direct { local, .. } = active else {
+        return None;
+    };
+    let remote = match local {
+        SocketAddr::V4(_) => SocketAddr::V4(preferred_address.address_v4()),
+        SocketAddr::V6(_) => SocketAddr::V6(preferred_address.address_v6()),
+    };
+    if remote.ip().is_unspecified() || remote.port() == 0 {
+        return None;
+    }
+    Some(Pathway::Direct { local, remote })
+}
+
+/// For client, try to migrate to the `preferred_address` of the server once the handshake is
+/// completed.
+///
+/// A new path to the preferred address is validated, the connection migrates to it if the
+/// validation succeeds. Otherwise, the path is marked as inactive and removed, the connection
+/// stays on the original path.
+///
+/// See [server's preferred address](https://www.rfc-editor.org/rfc/rfc9000.html#name-servers-preferred-address)
+/// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
+fn migrate_to_preferred_address(pathes: &ArcPathes, preferred_address: &PreferredAddress) {
+    let (Some(active), Some(path)) = (pathes.active_pathway(), pathes.active_path()) else {
+        return;
+    };
+    let Some(pathway) = preferred_pathway(active, preferred_address) else {
+        return;
+    };
+    pathes.get_or_create(pathway, path.usc().clone());
+    pathes.migrate_to(pathway);
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{Bytes, BytesMut};
+    use futures::StreamExt;
+    use qbase::{
+        cid::RandomCidGenerator,
+        entropy::OsEntropy,
+        error::ErrorKind,
+        frame::{
+            AckFrame, ConnectionCloseFrame, DataBlockedFrame, Frame, FrameReader,
+            HandshakeDoneFrame, ReliableFrame, StreamFrame,
+        },
+        packet::{
+            decrypt::{decrypt_packet, decrypt_packet_for_path, remove_protection_of_short_packet},
+            encrypt::{encode_short_first_byte, encrypt_packet, protect_header},
+            header::GetType,
+            long,
+            retry::retry_integrity_tag,
+            DataHeader, DataPacket, Ecn, KeyPhaseBit, Packet, PacketReader,
+        },
+        param::ClientParameters,
+        qlog::{PacketEvent, PacketType, QlogEvent},
+        sid::{handy::ConsistentConcurrency, StreamId},
+        token::ResetToken,
+    };
+    use qrecovery::{crypto::CryptoStream, reliable::GuaranteedFrame, space::InitialSpace};
+    use rustls::{ClientConfig, RootCertStore};
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+    use crate::{
+        clock::{MockClock, TokioClock},
+        conn::{
+            state::ConnectionState,
+            stats::SpaceStats,
+            transmit::initial::InitialSpaceReader,
+            version::{QUIC_VERSION_1, QUIC_VERSION_2},
+            ArcConnection,
+        },
+        error::{CloseCode, CloseInitiator, CloseReason, ConnErrorKind},
+        path::{RedundantScheduler, ValidationError},
+        tls::MemorySessionCache,
+        usc::UscRegistry,
+    };
+
+    fn client_connection() -> Connection {
+        client_connection_with_qlog(None)
+    }
+
+    fn client_connection_with_qlog(qlog: Option<Arc<dyn QlogSink>>) -> Connection {
+        client_connection_with(Parameters::default(), qlog, Arc::new(TokioClock))
+    }
+
+    fn client_connection_with(
+        local_params: Parameters,
+        qlog: Option<Arc<dyn QlogSink>>,
+        clock: ArcClock,
+    ) -> Connection {
+        client_connection_with_versions(local_params, qlog, clock, Versions::default())
+    }
+
+    fn client_connection_with_versions(
+        local_params: Parameters,
+        qlog: Option<Arc<dyn QlogSink>>,
+        clock: ArcClock,
+        versions: Versions,
+    ) -> Connection {
+        client_connection_with_congestion(local_params, qlog, clock, versions, Default::default())
+    }
+
+    fn client_connection_with_congestion(
+        local_params: Parameters,
+        qlog: Option<Arc<dyn QlogSink>>,
+        clock: ArcClock,
+        versions: Versions,
+        congestion_config: CongestionConfig,
+    ) -> Connection {
+        let tls_config = Arc::new(
+            ClientConfig::builder()
+                .with_root_certificates(RootCertStore::empty())
+                .with_no_client_auth(),
+        );
+        let keys_version = initial_keys_version(versions.offered()).unwrap();
+        let tls_session = ArcTlsSession::new_client(
+            "localhost".try_into().unwrap(),
+            tls_config.clone(),
+            &local_params,
+            keys_version,
+            &OsEntropy,
+        );
+        let initial_dcid = ConnectionId::random_gen(8);
+        let initial_keys = ArcTlsSession::initial_keys(
+            tls_config.crypto_provider(),
+            Side::Client,
+            initial_dcid,
+            keys_version,
+        );
+        Connection::new(
+            Role::Client,
+            local_params,
+            tls_session,
+            ConnectionId::random_gen(8),
+            initial_dcid,
+            initial_keys,
+            versions,
+            tls_config.crypto_provider().clone(),
+            Box::new(ConsistentConcurrency::new(0, 0)),
+            CongestionAlgorithm::default(),
+            congestion_config,
+            ArcTokenRegistry::default_sink("localhost".to_owned()),
+            qlog,
+            clock,
+            Arc::new(OsEntropy),
+            Arc::new(RandomCidGenerator::default()),
+        )
+    }
+
+    fn retry_packet(
+        odcid: &ConnectionId,
+        dcid: &ConnectionId,
+        scid: &ConnectionId,
+        token: &[u8],
+    ) -> RetryPacket {
+        let mut bytes = vec![0xf0, 0x00, 0x00, 0x00, 0x01];
+        for cid in [dcid, scid] {
+            bytes.push(cid.len() as u8);
+            bytes.extend_from_slice(cid);
+        }
+        bytes.extend_from_slice(token);
+        let integrity = retry_integrity_tag(QUIC_VERSION_1, odcid, &bytes);
+        bytes.extend_from_slice(&integrity);
+
+        match PacketReader::new(BytesMut::from(&bytes[..]), 8).next() {
+            Some(Ok(Packet::Retry(retry))) => retry,
+            _ => unreachable!(),
+        }
+    }
+
+    fn initial_tag(keys: &ArcKeys) -> Vec<u8> {
+        let keys = keys.get_local_keys().unwrap();
+        let tag = keys.local.packet.encrypt_in_place(0, &[], &mut []).unwrap();
+        tag.as_ref().to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_handle_retry() {
+        let conn = client_connection();
+        let odcid = conn.initial_dcid;
+        let scid = conn.initial_scid;
+        let tag = initial_tag(&conn.initial.keys);
+
+        // the integrity tag is not computed with the original dcid, ignored
+        let retry_scid = ConnectionId::random_gen(8);
+        let forged = retry_packet(&retry_scid, &scid, &retry_scid, b"token");
+        conn.handle_retry(forged);
+        assert_eq!(initial_tag(&conn.initial.keys), tag);
+        assert!(conn.token.lock().unwrap().is_empty());
+
+        conn.handle_retry(retry_packet(&odcid, &scid, &retry_scid, b"token"));
+        assert_ne!(initial_tag(&conn.initial.keys), tag);
+        let expected_keys = ArcKeys::with_keys(ArcTlsSession::initial_keys(
+            &conn.crypto_provider,
+            Side::Client,
+            retry_scid,
+            rustls::quic::Version::V1,
+        ));
+        assert_eq!(initial_tag(&conn.initial.keys), initial_tag(&expected_keys));
+        assert_eq!(conn.token.lock().unwrap().as_slice(), b"token");
+        assert_eq!(conn.cid_registry.remote.latest_dcid(), Some(retry_scid));
+
+        // only one Retry packet is processed, the subsequent ones are discarded silently
+        let another_scid = ConnectionId::random_gen(8);
+        conn.handle_retry(retry_packet(&odcid, &scid, &another_scid, b"another"));
+        assert_eq!(initial_tag(&conn.initial.keys), initial_tag(&expected_keys));
+        assert_eq!(conn.token.lock().unwrap().as_slice(), b"token");
+        assert_eq!(conn.cid_registry.remote.latest_dcid(), Some(retry_scid));
+        assert!(conn.error.close_reason().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_restart_handshake() {
+        let versions = Versions::new(vec![QUIC_VERSION_2, QUIC_VERSION_1]);
+        let clock = Arc::new(TokioClock);
+        let conn = client_connection_with_versions(Parameters::default(), None, clock, versions);
+        let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let pathway = Pathway::Direct {
+            local: usc.local_addr().unwrap(),
+            remote: peer.local_addr().unwrap(),
+        };
+        conn.pathes.get_or_create(pathway, usc);
+
+        let mut datagram = [0u8; 1500];
+        let recv = peer.recv(&mut datagram);
+        tokio::time::timeout(Duration::from_secs(1), recv)
+            .await
+            .unwrap()
+            .unwrap();
+        // v2的Initial包，包类型为0b01
+        assert_eq!(datagram[0] & 0xf0, 0xd0);
+        assert_eq!(datagram[1..5], QUIC_VERSION_2.to_be_bytes());
+
+        conn.handle_version_negotiation(&[QUIC_VERSION_1]);
+        assert_eq!(conn.version(), QUIC_VERSION_1);
+        let expected_keys = ArcKeys::with_keys(ArcTlsSession::initial_keys(
+            &conn.crypto_provider,
+            Side::Client,
+            conn.initial_dcid,
+            rustls::quic::Version::V1,
+        ));
+        assert_eq!(initial_tag(&conn.initial.keys), initial_tag(&expected_keys));
+
+        // 以v1重新发送新的ClientHello
+        let recv = peer.recv(&mut datagram);
+        let len = tokio::time::timeout(Duration::from_secs(1), recv)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(len >= 1200);
+        assert_eq!(datagram[0] & 0xf0, 0xc0);
+        assert_eq!(datagram[1..5], QUIC_VERSION_1.to_be_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_0rtt_cache_miss() {
+        let mut conn = client_connection();
+        let session_cache = Arc::new(MemorySessionCache::default());
+        session_cache.store("localhost", Arc::new(Parameters::default()));
+        // 没有可用的会话票据，退回到1Rtt
+        assert!(!conn.enable_0rtt("localhost", session_cache.clone()));
+        assert!(conn.data.zero_rtt_keys.get_local_keys().is_none());
+        assert!(conn.params.remembered.is_none());
+
+        let mut conn = client_connection();
+        assert!(!conn.enable_0rtt("example.com", session_cache));
+        assert!(conn.params.remembered.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_wrong_original_dcid() {
+        let conn = client_connection();
+        let mut params = Parameters::default();
+        params.set_initial_source_connection_id(conn.cid_registry.remote.initial_dcid());
+        params.set_original_destination_connection_id(Some(ConnectionId::random_gen(8)));
+        conn.params.remote.write(Arc::new(params));
+
+        let (error, _) = conn.error.clone().await;
+        assert_eq!(error.kind(), ErrorKind::TransportParameter);
+        // 参数没有被接受，即使TLS握手完成也不会进入Connected状态
+        conn.abort_with_error(&error);
+        assert!(!conn.params.remote.accepted().await);
+    }
+
+    #[tokio::test]
+    async fn test_client_first_flight() {
+        let conn = client_connection();
+        let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let pathway = Pathway::Direct {
+            local: usc.local_addr().unwrap(),
+            remote: peer.local_addr().unwrap(),
+        };
+        conn.pathes.get_or_create(pathway, usc);
+
+        let mut datagram = [0u8; 1500];
+        let recv = peer.recv(&mut datagram);
+        let len = tokio::time::timeout(Duration::from_secs(1), recv)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(len >= 1200);
+        let packet = PacketReader::new(BytesMut::from(&datagram[..len]), 8).next();
+        assert!(matches!(
+            packet,
+            Some(Ok(Packet::Data(DataPacket {
+                header: DataHeader::Long(long::DataHeader::Initial(_)),
+                ..
+            })))
+        ));
+        // 整个ClientHello都在第一个数据报中
+        tokio::task::yield_now().await;
+        assert_eq!(conn.stats().initial.packets_sent, 1);
+        assert!(peer.try_recv(&mut datagram).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_paced_sending() {
+        // 拥塞窗口容得下所有的包，发送速率只受pacing限制：1.25 * 40 * MSS / 1s = 60KB/s，每个包20ms
+        let congestion_config = CongestionConfig {
+            initial_window: Some(40 * qcongestion::MSS as u64),
+            initial_rtt: Some(Duration::from_secs(1)),
+            ..Default::default()
+        };
+        let conn = client_connection_with_congestion(
+            Parameters::default(),
+            None,
+            Arc::new(TokioClock),
+            Versions::default(),
+            congestion_config,
+        );
+        let mut writer = conn.initial.crypto_stream.writer();
+        writer.write_all(&[0u8; 15000]).await.unwrap();
+
+        let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let pathway = Pathway::Direct {
+            local: usc.local_addr().unwrap(),
+            remote: peer.local_addr().unwrap(),
+        };
+        conn.pathes.get_or_create(pathway, usc);
+
+        let mut datagram = [0u8; 1500];
+        let mut arrivals = Vec::new();
+        while let Ok(recv) =
+            tokio::time::timeout(Duration::from_millis(200), peer.recv(&mut datagram)).await
+        {
+            recv.unwrap();
+            arrivals.push(tokio::time::Instant::now());
+        }
+        assert!(arrivals.len() > 10, "{}", arrivals.len());
+        // 最初的突发是pacer的最小突发，10个包
+        let burst = arrivals[9] - arrivals[0];
+        assert!(burst < Duration::from_millis(15), "{burst:?}");
+        // 此后令牌每补足一个包才发送一个，彼此间隔约20ms
+        for gap in arrivals[9..].windows(2).map(|w| w[1] - w[0]) {
+            assert!(gap >= Duration::from_millis(15), "{gap:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pto_with_mock_clock() {
+        let clock = MockClock::new();
+        let conn = client_connection_with(Parameters::default(), None, Arc::new(clock.clone()));
+        let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let pathway = Pathway::Direct {
+            local: usc.local_addr().unwrap(),
+            remote: peer.local_addr().unwrap(),
+        };
+        conn.pathes.get_or_create(pathway, usc);
+
+        let mut datagram = [0u8; 1500];
+        let recv = peer.recv(&mut datagram);
+        tokio::time::timeout(Duration::from_secs(1), recv)
+            .await
+            .unwrap()
+            .unwrap();
+        // 模拟时钟不走，PTO不会超时，没有探测包
+        tokio::task::yield_now().await;
+        assert_eq!(conn.stats().initial.packets_sent, 1);
+        assert!(peer.try_recv(&mut datagram).is_err());
+
+        // 拨快模拟时钟越过PTO，无需真实等待即发出探测包
+        let pto = conn
+            .pathes
+            .get(&pathway)
+            .unwrap()
+            .cc
+            .pto_time(Epoch::Initial);
+        clock.advance(pto + Duration::from_millis(20));
+        let recv = peer.recv(&mut datagram);
+        let len = tokio::time::timeout(Duration::from_secs(1), recv)
+            .await
+            .unwrap()
+            .unwrap();
+        let packet = PacketReader::new(BytesMut::from(&datagram[..len]), 8).next();
+        assert!(matches!(
+            packet,
+            Some(Ok(Packet::Data(DataPacket {
+                header: DataHeader::Long(long::DataHeader::Initial(_)),
+                ..
+            })))
+        ));
+    }
+
+    #[derive(Default)]
+    struct QlogEvents(Mutex<Vec<QlogEvent>>);
+
+    impl QlogSink for QlogEvents {
+        fn log(&self, event: QlogEvent) {
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_qlog_first_flight() {
+        let events = Arc::new(QlogEvents::default());
+        let conn = client_connection_with_qlog(Some(events.clone()));
+        let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let pathway = Pathway::Direct {
+            local: usc.local_addr().unwrap(),
+            remote: peer.local_addr().unwrap(),
+        };
+        conn.pathes.get_or_create(pathway, usc);
+
+        let mut datagram = [0u8; 1500];
+        let recv = peer.recv(&mut datagram);
+        let len = tokio::time::timeout(Duration::from_secs(1), recv)
+            .await
+            .unwrap()
+            .unwrap();
+        // 第一个数据报只有一个Initial包，填充后的大小即为数据报的大小
+        let events = events.0.lock().unwrap();
+        assert_eq!(
+            events.first(),
+            Some(&QlogEvent::PacketSent(PacketEvent {
+                packet_type: PacketType::Initial,
+                packet_number: 0,
+                length: len,
+            }))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stats_first_flight() {
+        let conn = client_connection();
+        let stats = conn.stats();
+        assert_eq!(stats.initial.bytes_sent, 0);
+        assert_eq!(stats.paths, 0);
+        assert_eq!(stats.smoothed_rtt, None);
+
+        let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let pathway = Pathway::Direct {
+            local: usc.local_addr().unwrap(),
+            remote: peer.local_addr().unwrap(),
+        };
+        conn.pathes.get_or_create(pathway, usc);
+
+        let mut datagram = [0u8; 1500];
+        let recv = peer.recv(&mut datagram);
+        let len = tokio::time::timeout(Duration::from_secs(1), recv)
+            .await
+            .unwrap()
+            .unwrap();
+        let stats = conn.stats();
+        assert_eq!(stats.initial.packets_sent, 1);
+        assert_eq!(stats.initial.bytes_sent, len as u64);
+        assert_eq!(stats.initial.packets_received, 0);
+        assert_eq!(stats.handshake, SpaceStats::default());
+        assert_eq!(stats.paths, 1);
+        assert!(stats.smoothed_rtt.is_some());
+        assert!(stats.congestion_window.is_some_and(|cwnd| cwnd > 0));
+    }
+
+    #[tokio::test]
+    async fn test_discard_handshake_keys_on_confirmation() {
+        let conn = client_connection();
+        let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let pathway = Pathway::Direct {
+            local: usc.local_addr().unwrap(),
+            remote: peer.local_addr().unwrap(),
+        };
+        conn.pathes.get_or_create(pathway, usc);
+
+        let handshake_keys = ArcTlsSession::initial_keys(
+            &conn.crypto_provider,
+            Side::Client,
+            ConnectionId::random_gen(8),
+            rustls::quic::Version::V1,
+        );
+        conn.hs.keys.set_keys(handshake_keys);
+        // 还有Handshake数据未被确认，握手也尚未确认，Handshake密钥不能被丢弃
+        let mut writer = conn.hs.crypto_stream.writer();
+        writer.write_all(b"client finished").await.unwrap();
+        // 等到数据被发送出去
+        let mut datagram = [0u8; 1500];
+        let recv = peer.recv(&mut datagram);
+        tokio::time::timeout(Duration::from_secs(1), recv)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(conn.hs.keys.get_local_keys().is_some());
+        assert!(!conn.handshake.is_handshake_done());
+
+        // 收到HANDSHAKE_DONE帧，握手确认，即使数据仍未被确认，Handshake密钥也被丢弃
+        conn.handshake.recv_frame(&HandshakeDoneFrame).unwrap();
+        let discarded = async {
+            while conn.hs.keys.get_local_keys().is_some() {
+                tokio::task::yield_now().await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(1), discarded)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_drop_initial_packets_after_keys_discarded() {
+        let conn = client_connection();
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let pathway = Pathway::Direct {
+            local: usc.local_addr().unwrap(),
+            remote: "127.0.0.1:4433".parse().unwrap(),
+        };
+        // 未加密的Initial包，1字节包号和20字节载荷
+        let initial_packet = || {
+            let mut packet = vec![0xc0];
+            packet.extend_from_slice(&QUIC_VERSION_1.to_be_bytes());
+            for cid in [&conn.initial_scid, &ConnectionId::random_gen(8)] {
+                packet.push(cid.len() as u8);
+                packet.extend_from_slice(cid);
+            }
+            packet.extend_from_slice(&[0, 21]);
+            packet.extend_from_slice(&[0u8; 21]);
+            BytesMut::from(&packet[..])
+        };
+
+        // 客户端首次发送Handshake包时丢弃Initial密钥
+        conn.initial.keys.invalid();
+        assert!(!conn.join_handles[0].is_finished());
+        // 迟到的Initial包使接收任务关闭通道并退出
+        Router::route_datagram(initial_packet(), Ecn::NotEct, pathway, &usc, |_| {
+            panic!("should be routed")
+        });
+        let finished = async {
+            while !conn.join_handles[0].is_finished() {
+                tokio::task::yield_now().await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(1), finished)
+            .await
+            .unwrap();
+
+        // 通道已关闭，之后的Initial包在路由时直接被丢弃，不再被解析
+        Router::route_datagram(initial_packet(), Ecn::NotEct, pathway, &usc, |_| {
+            panic!("should be routed")
+        });
+    }
+
+    #[tokio::test]
+    async fn test_crypto_buffer_exceeded() {
+        let conn = client_connection();
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let pathway = Pathway::Direct {
+            local: usc.local_addr().unwrap(),
+            remote: "127.0.0.1:4433".parse().unwrap(),
+        };
+
+        // 服务端的加密数据已经推进到了远超客户端缓存上限的偏移
+        let server_crypto_stream = CryptoStream::new(4096, 4096);
+        let outgoing = server_crypto_stream.outgoing();
+        let mut writer = server_crypto_stream.writer();
+        let mut buffer = [0u8; 1500];
+        for _ in 0..32 {
+            writer.write_all(&[0u8; 1024]).await.unwrap();
+            while let Some((frame, _)) = outgoing.try_read_data(&mut buffer) {
+                outgoing.on_data_acked(&frame);
+            }
+        }
+        writer.write_all(b"server hello").await.unwrap();
+
+        let server_keys = ArcTlsSession::initial_keys(
+            &conn.crypto_provider,
+            Side::Server,
+            conn.initial_dcid,
+            rustls::quic::Version::V1,
+        );
+        let reader = InitialSpaceReader {
+            token: Arc::default(),
+            keys: ArcKeys::with_keys(server_keys),
+            space: InitialSpace::with_capacity(16),
+            crypto_stream_outgoing: outgoing,
+            versions: ArcVersions::new(Versions::default()),
+        };
+        let mut datagram = [0u8; 1200];
+        let (padding, _, _) = reader
+            .try_read(
+                &mut datagram,
+                ConnectionId::random_gen(8),
+                conn.initial_scid,
+                None,
+                false,
+            )
+            .unwrap();
+        let (_, _, _, len, _, _) = padding(&mut datagram, 1200);
+        Router::route_datagram(datagram[..len].into(), Ecn::NotEct, pathway, &usc, |_| {});
+
+        let (error, _) = conn.error.clone().await;
+        assert_eq!(error.kind(), ErrorKind::CryptoBufferExceeded);
+    }
+
+    #[tokio::test]
+    async fn test_initial_max_data() {
+        let local_params = ClientParameters::builder()
+            .initial_max_data(VarInt::from_u32(1000))
+            .initial_max_stream_data_bidi_remote(VarInt::from_u32(4000))
+            .initial_max_streams_bidi(VarInt::from_u32(1))
+            .build()
+            .unwrap();
+        let conn = client_connection_with(local_params.into(), None, Arc::new(TokioClock));
+        assert_eq!(conn.stats().recv_max_data, 1000);
+
+        // 对端无需等待MAX_DATA帧，立即就能发送通告的initial_max_data字节
+        let sid = StreamId::from(VarInt::from_u32(1));
+        let recv_data = |offset, len| {
+            let frame = StreamFrame::new(sid, offset, len);
+            let new_data_size = conn
+                .streams
+                .recv_frame(&(frame, Bytes::from(vec![0u8; len])))
+                .unwrap();
+            conn.flow_ctrl.recver.on_new_rcvd(new_data_size)
+        };
+        assert_eq!(recv_data(0, 1000).unwrap(), 1000);
+        // 超过initial_max_data，连接级流量控制出错
+        assert!(recv_data(1000, 1).is_err());
+
+        // 对端受阻于连接级流量控制，收到的DATA_BLOCKED帧计入统计
+        assert_eq!(conn.stats().data_blocked_frames, 0);
+        let data_blocked = DataBlockedFrame {
+            limit: VarInt::from_u32(1000),
+        };
+        conn.flow_ctrl.recver.recv_frame(&data_blocked).unwrap();
+        conn.flow_ctrl.recver.recv_frame(&data_blocked).unwrap();
+        assert_eq!(conn.stats().data_blocked_frames, 2);
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_1rtt_packet() {
+        let conn = client_connection();
+        let ((client_keys, client_secrets), (server_keys, _)) = crate::tls::tests::one_rtt_keys();
+        conn.data.one_rtt_keys.set_keys(client_keys, client_secrets);
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let pathway = Pathway::Direct {
+            local: usc.local_addr().unwrap(),
+            remote: "127.0.0.1:4433".parse().unwrap(),
+        };
+
+        // 服务端发来的1rtt包，1字节包号，携带若干DATA_BLOCKED帧，以PADDING帧补足头部保护的采样
+        let one_rtt_packet = |pn: u8, frames: usize| {
+            let mut packet = vec![0x40];
+            encode_short_first_byte(&mut packet[0], 1, KeyPhaseBit::default());
+            packet.extend_from_slice(&conn.initial_scid);
+            let pn_offset = packet.len();
+            packet.push(pn);
+            for _ in 0..frames {
+                packet.extend_from_slice(&[0x14, 0x43, 0xe8]);
+            }
+            let tag_len = server_keys.local.packet.tag_len();
+            packet.resize(pn_offset + 1 + 20 + tag_len, 0);
+            let packet_key = server_keys.local.packet.as_ref();
+            encrypt_packet(packet_key, pn as u64, &mut packet, pn_offset + 1);
+            protect_header(server_keys.local.header.as_ref(), &mut packet, pn_offset, 1);
+            BytesMut::from(&packet[..])
+        };
+        let route = |datagram| {
+            Router::route_datagram(datagram, Ecn::NotEct, pathway, &usc, |_| {
+                panic!("should be routed")
+            })
+        };
+        let duplicated = one_rtt_packet(0, 1);
+        route(duplicated.clone());
+        route(duplicated);
+        route(one_rtt_packet(1, 2));
+
+        // 重复的包在解析帧之前就被丢弃，之后的包照常处理；包按序处理，
+        // 只有最后一个包被处理后才能收到3个DATA_BLOCKED帧，此时前面的包都已处理完
+        let received = async {
+            while conn.stats().data_blocked_frames < 3 {
+                tokio::task::yield_now().await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(1), received)
+            .await
+            .unwrap();
+        let stats = conn.stats();
+        assert_eq!(stats.data.packets_received, 2);
+        assert_eq!(stats.data_blocked_frames, 3);
+    }
+
+    #[tokio::test]
+    async fn test_bounded_paths() {
+        let conn = client_connection();
+        conn.set_max_paths(4, 2);
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let pathway = |port: u16| Pathway::Direct {
+            local: usc.local_addr().unwrap(),
+            remote: SocketAddr::from(([127, 0, 0, 1], port)),
+        };
+
+        let active = pathway(10000);
+        conn.pathes.get_or_create(active, usc.clone());
+        // 伪造大量源地址，路径数量始终有界
+        for port in 10001..10101 {
+            conn.pathes.get_or_create(pathway(port), usc.clone());
+            assert!(conn.pathes.len() <= 3);
+        }
+        // 活跃路径不会被淘汰，最新创建的路径也不会
+        assert_eq!(conn.pathes.active_pathway(), Some(active));
+        assert!(conn.pathes.contains_key(&active));
+        assert!(conn.pathes.contains_key(&pathway(10100)));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_bounded_paths_concurrently() {
+        let conn = client_connection();
+        conn.set_max_paths(4, 2);
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let local = usc.local_addr().unwrap();
+        let pathway = move |port: u16| Pathway::Direct {
+            local,
+            remote: SocketAddr::from(([127, 0, 0, 1], port)),
+        };
+        conn.pathes.get_or_create(pathway(10000), usc.clone());
+
+        // 多个任务同时伪造源地址创建路径，路径数量也不会超过上限
+        let tasks = (0..4u16).map(|i| {
+            let pathes = conn.pathes.clone();
+            let usc = usc.clone();
+            tokio::spawn(async move {
+                for port in 10001 + i * 100..10101 + i * 100 {
+                    pathes.get_or_create(pathway(port), usc.clone());
+                    assert!(pathes.len() <= 3);
+                }
+            })
+        });
+        for task in tasks.collect::<Vec<_>>() {
+            task.await.unwrap();
+        }
+        assert!(conn.pathes.len() <= 3);
+    }
+
+    #[tokio::test]
+    async fn test_nat_rebinding() {
+        let clock = MockClock::new();
+        let conn = client_connection_with(Parameters::default(), None, Arc::new(clock.clone()));
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let rebound_peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let original = Pathway::Direct {
+            local: usc.local_addr().unwrap(),
+            remote: peer.local_addr().unwrap(),
+        };
+        let path = conn.pathes.get_or_create(original, usc.clone());
+        conn.handshake.recv_frame(&HandshakeDoneFrame).unwrap();
+
+        // 原路径上已经有了RTT样本
+        path.cc.on_pkt_sent(Epoch::Data, 0, true, 100, true, None);
+        clock.advance(Duration::from_millis(10));
+        path.cc.on_ack(
+            Epoch::Data,
+            &AckFrame {
+                largest: VarInt::from_u32(0),
+                delay: VarInt::from_u32(0),
+                first_range: VarInt::from_u32(0),
+                ranges: vec![],
+                ecn: None,
+            },
+        );
+        assert_eq!(path.rtt().latest_rtt, Duration::from_millis(10));
+
+        // 对端的端口因NAT重绑定而变化
+        let rebound = Pathway::Direct {
+            local: usc.local_addr().unwrap(),
+            remote: rebound_peer.local_addr().unwrap(),
+        };
+        assert!(rebound.is_rebinding_of(&original));
+        let rebound_path = conn.pathes.get_or_rebind(original, rebound, usc.clone());
+        // 新路径需要验证，但沿用原路径的RTT估计
+        assert!(rebound_path.has_begun_validation());
+        assert_eq!(rebound_path.rtt(), path.rtt());
+
+        // 对端的IP也可能因NAT重绑定而变化，同样需要验证，但RTT估计重新开始
+        let readdressed = Pathway::Direct {
+            local: usc.local_addr().unwrap(),
+            remote: SocketAddr::from(([127, 0, 0, 2], peer.local_addr().unwrap().port())),
+        };
+        assert!(readdressed.is_rebinding_of(&original));
+        let readdressed_path = conn
+            .pathes
+            .get_or_rebind(original, readdressed, usc.clone());
+        assert!(readdressed_path.has_begun_validation());
+        assert_ne!(readdressed_path.rtt(), path.rtt());
+
+        // 验证通过之前仍使用原路径，连接不受影响
+        assert_eq!(conn.pathes.active_pathway(), Some(original));
+        assert_eq!(conn.pathes.len(), 3);
+        tokio::task::yield_now().await;
+        assert!(conn.error.close_reason().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_validate_path_on_migration() {
+        let conn = client_connection();
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let mut pathways = Vec::new();
+        for _ in 0..2 {
+            let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            pathways.push(Pathway::Direct {
+                local: usc.local_addr().unwrap(),
+                remote: peer.local_addr().unwrap(),
+            });
+        }
+        conn.pathes.get_or_create(pathways[0], usc.clone());
+        // 握手完成前创建的路径不会开始验证
+        let path = conn.pathes.get_or_create(pathways[1], usc.clone());
+        assert!(!path.has_begun_validation());
+        conn.handshake.recv_frame(&HandshakeDoneFrame).unwrap();
+
+        // 对端在该路径上发送了非探测包，开始验证，验证通过后才迁移
+        conn.pathes.on_non_probing_packet(pathways[1]);
+        assert!(path.has_begun_validation());
+        assert_eq!(conn.pathes.active_pathway(), Some(pathways[0]));
+        let challenge = async {
+            loop {
+                match path.challenge_sndbuf().take() {
+                    Some(challenge) => break challenge,
+                    None => tokio::task::yield_now().await,
+                }
+            }
+        };
+        let challenge = tokio::time::timeout(Duration::from_secs(1), challenge)
+            .await
+            .unwrap();
+        path.recv_response(challenge.into());
+        assert_eq!(path.validated().await, Ok(()));
+        let migrated = async {
+            while conn.pathes.active_pathway() != Some(pathways[1]) {
+                tokio::task::yield_now().await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(1), migrated)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sweep_idle_path() {
+        let clock = MockClock::new();
+        let conn = client_connection_with(Parameters::default(), None, Arc::new(clock.clone()));
+        conn.set_idle_path_timeout(Some(Duration::from_millis(150)));
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let mut pathways = Vec::new();
+        for _ in 0..2 {
+            let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            pathways.push(Pathway::Direct {
+                local: usc.local_addr().unwrap(),
+                remote: peer.local_addr().unwrap(),
+            });
+        }
+        conn.pathes.get_or_create(pathways[0], usc.clone());
+        conn.handshake.recv_frame(&HandshakeDoneFrame).unwrap();
+
+        // 握手确认后新建的路径需要验证，回应其挑战使验证通过
+        let path = conn.pathes.get_or_create(pathways[1], usc.clone());
+        let challenge = async {
+            loop {
+                match path.challenge_sndbuf().take() {
+                    Some(challenge) => break challenge,
+                    None => tokio::task::yield_now().await,
+                }
+            }
+        };
+        let challenge = tokio::time::timeout(Duration::from_secs(1), challenge)
+            .await
+            .unwrap();
+        path.recv_response(challenge.into());
+        assert_eq!(path.validated().await, Ok(()));
+        assert!(path.is_validated());
+
+        // 每100ms清理一次，第一次清理时尚未闲置超时，该路径被保留
+        clock.advance(Duration::from_millis(100));
+        tokio::task::yield_now().await;
+        assert!(conn.pathes.contains_key(&pathways[1]));
+
+        // 已验证但不活跃的路径闲置超时后，在下一次清理时被移除，活跃路径始终保留
+        clock.advance(Duration::from_millis(100));
+        let swept = async {
+            while conn.pathes.contains_key(&pathways[1]) {
+                tokio::task::yield_now().await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(1), swept)
+            .await
+            .unwrap();
+        assert!(!path.is_sending());
+        assert!(conn.pathes.contains_key(&pathways[0]));
+        assert_eq!(conn.pathes.active_pathway(), Some(pathways[0]));
+    }
+
+    #[tokio::test]
+    async fn test_path_events() {
+        let conn = client_connection();
+        let mut events = conn.path_events();
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let mut pathways = Vec::new();
+        for _ in 0..2 {
+            let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            pathways.push(Pathway::Direct {
+                local: usc.local_addr().unwrap(),
+                remote: peer.local_addr().unwrap(),
+            });
+        }
+        conn.pathes.get_or_create(pathways[0], usc.clone());
+        conn.handshake.recv_frame(&HandshakeDoneFrame).unwrap();
+
+        // 回应新路径的挑战，验证通过
+        let path = conn.pathes.get_or_create(pathways[1], usc.clone());
+        let challenge = async {
+            loop {
+                match path.challenge_sndbuf().take() {
+                    Some(challenge) => break challenge,
+                    None => tokio::task::yield_now().await,
+                }
+            }
+        };
+        let challenge = tokio::time::timeout(Duration::from_secs(1), challenge)
+            .await
+            .unwrap();
+        path.recv_response(challenge.into());
+        assert_eq!(
+            tokio::time::timeout(Duration::from_secs(1), events.next())
+                .await
+                .unwrap(),
+            Some(PathEvent::PathValidated(pathways[1]))
+        );
+
+        // 迁移到已验证的路径，再放弃原路径
+        conn.pathes.migrate_to(pathways[1]);
+        assert_eq!(
+            tokio::time::timeout(Duration::from_secs(1), events.next())
+                .await
+                .unwrap(),
+            Some(PathEvent::MigrationCompleted {
+                from: pathways[0],
+                to: pathways[1]
+            })
+        );
+        assert!(conn.abandon_path(pathways[0]));
+        assert_eq!(
+            tokio::time::timeout(Duration::from_secs(1), events.next())
+                .await
+                .unwrap(),
+            Some(PathEvent::PathAbandoned(pathways[0]))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_abandon_path() {
+        let conn = client_connection();
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let mut pathways = Vec::new();
+        let mut paths = Vec::new();
+        for _ in 0..2 {
+            let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let pathway = Pathway::Direct {
+                local: usc.local_addr().unwrap(),
+                remote: peer.local_addr().unwrap(),
+            };
+            paths.push(conn.pathes.get_or_create(pathway, usc.clone()));
+            pathways.push(pathway);
+        }
+        assert!(paths.iter().all(|path| path.is_sending()));
+
+        // 放弃活跃路径，另一条路径成为活跃路径
+        assert!(conn.abandon_path(pathways[0]));
+        assert!(!paths[0].is_sending());
+        assert!(paths[1].is_sending());
+        assert_eq!(conn.pathes.len(), 1);
+        let active = conn.pathes.active_path().unwrap();
+        assert!(std::ptr::eq::<Path>(&*active, &*paths[1]));
+
+        // 已经被放弃的路径，以及唯一的路径，都无法放弃
+        assert!(!conn.abandon_path(pathways[0]));
+        assert!(!conn.abandon_path(pathways[1]));
+        tokio::task::yield_now().await;
+        assert!(paths[1].is_sending());
+        assert_eq!(conn.stats().paths, 1);
+    }
+
+    #[tokio::test]
+    async fn test_drop_datagrams_when_congestion_limited() {
+        let clock = MockClock::new();
+        let conn = client_connection_with(Parameters::default(), None, Arc::new(clock.clone()));
+        let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let pathway = Pathway::Direct {
+            local: usc.local_addr().unwrap(),
+            remote: peer.local_addr().unwrap(),
+        };
+        let path = conn.pathes.get_or_create(pathway, usc);
+
+        // 没有确认，发满路径的拥塞窗口
+        let mut pn = 0;
+        while path.cc.available_window() >= qcongestion::MSS {
+            path.cc
+                .on_pkt_sent(Epoch::Data, pn, true, qcongestion::MSS, true, None);
+            pn += 1;
+        }
+        // 发送任务在下一个tick被唤醒，发现拥塞窗口已经耗尽：tick任务先运行，再唤醒发送任务
+        clock.advance(Duration::from_millis(10));
+        for _ in 0..3 {
+            tokio::task::yield_now().await;
+        }
+
+        // 默认丢弃：数据报在发送时就被丢弃并计数，而不是进入队列
+        let writer = conn.datagrams.writer(1200).unwrap();
+        for _ in 0..3 {
+            writer.send(b"hello").unwrap();
+        }
+        assert_eq!(conn.stats().datagrams_dropped, 3);
+        let mut buf = [0u8; 64];
+        assert!(conn.datagrams.try_read_datagram(&mut buf).is_none());
+
+        // 阻塞：数据报留在队列中，等待拥塞窗口
+        conn.set_datagram_send_policy(DatagramSendPolicy::Block);
+        writer.send(b"hello").unwrap();
+        assert_eq!(conn.stats().datagrams_dropped, 3);
+        assert!(conn.datagrams.try_read_datagram(&mut buf).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_multipath_scheduling() {
+        let conn = client_connection();
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let mut pathways = Vec::new();
+        let mut paths = Vec::new();
+        for _ in 0..2 {
+            let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let pathway = Pathway::Direct {
+                local: usc.local_addr().unwrap(),
+                remote: peer.local_addr().unwrap(),
+            };
+            paths.push(conn.pathes.get_or_create(pathway, usc.clone()));
+            pathways.push(pathway);
+            if !conn.handshake.is_handshake_done() {
+                conn.handshake.recv_frame(&HandshakeDoneFrame).unwrap();
+            }
+        }
+
+        // 握手确认后新建的路径需要验证，回应其挑战使验证通过
+        let challenge = async {
+            loop {
+                match paths[1].challenge_sndbuf().take() {
+                    Some(challenge) => break challenge,
+                    None => tokio::task::yield_now().await,
+                }
+            }
+        };
+        let challenge = tokio::time::timeout(Duration::from_secs(1), challenge)
+            .await
+            .unwrap();
+        paths[1].recv_response(challenge.into());
+        assert_eq!(paths[1].validated().await, Ok(()));
+
+        // 未协商multipath，只有活跃路径承载新的流数据
+        assert!(conn.pathes.is_scheduled(pathways[0]));
+        assert!(!conn.pathes.is_scheduled(pathways[1]));
+
+        conn.pathes.enable_multipath();
+        let is_full = |i: usize| paths[i].cc.available_window() < qcongestion::MSS;
+        let mut sent = [0; 2];
+        for pn in 0..200 {
+            if is_full(0) && is_full(1) {
+                break;
+            }
+            // 拥塞窗口已满的路径不被调度
+            let i = (0..2)
+                .find(|&i| conn.pathes.is_scheduled(pathways[i]))
+                .unwrap();
+            assert!(!is_full(i));
+            paths[i]
+                .cc
+                .on_pkt_sent(Epoch::Data, pn, true, qcongestion::MSS, true, None);
+            sent[i] += 1;
+        }
+        // 一条路径的拥塞窗口满了之后，溢出到另一条路径
+        assert!(sent[0] > 0 && sent[1] > 0, "{sent:?}");
+        // 两条路径的拥塞窗口都满了，仍调度其中一条，PTO探测包可以携带流数据
+        let scheduled = pathways.iter().filter(|&&p| conn.pathes.is_scheduled(p));
+        assert_eq!(scheduled.count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_redundant_scheduling() {
+        let conn = client_connection();
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let mut pathways = Vec::new();
+        let mut paths = Vec::new();
+        for _ in 0..2 {
+            let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let pathway = Pathway::Direct {
+                local: usc.local_addr().unwrap(),
+                remote: peer.local_addr().unwrap(),
+            };
+            paths.push(conn.pathes.get_or_create(pathway, usc.clone()));
+            pathways.push(pathway);
+            if !conn.handshake.is_handshake_done() {
+                conn.handshake.recv_frame(&HandshakeDoneFrame).unwrap();
+            }
+        }
+        let challenge = async {
+            loop {
+                match paths[1].challenge_sndbuf().take() {
+                    Some(challenge) => break challenge,
+                    None => tokio::task::yield_now().await,
+                }
+            }
+        };
+        let challenge = tokio::time::timeout(Duration::from_secs(1), challenge)
+            .await
+            .unwrap();
+        paths[1].recv_response(challenge.into());
+        assert_eq!(paths[1].validated().await, Ok(()));
+
+        conn.set_path_scheduler(Arc::new(RedundantScheduler));
+        let frame = StreamFrame::new(StreamId::from(VarInt::from_u32(0)), 0, 5);
+        // 未协商multipath，不冗余发送
+        conn.pathes.reinject(pathways[0], &frame, b"hello");
+        let mut buf = [0u8; 64];
+        assert!(paths[1].reinject_sndbuf().try_read(&mut buf).is_none());
+
+        conn.pathes.enable_multipath();
+        assert!(pathways.iter().all(|&p| conn.pathes.is_scheduled(p)));
+        conn.pathes.reinject(pathways[0], &frame, b"hello");
+        // 副本只进入其他路径
+        assert!(paths[0].reinject_sndbuf().try_read(&mut buf).is_none());
+        let (copy, n) = paths[1].reinject_sndbuf().try_read(&mut buf).unwrap();
+        assert_eq!(copy.range(), frame.range());
+        assert!(n > 5);
+        assert_eq!(&buf[n - 5..n], b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_send_on_multiple_paths() {
+        let conn = client_connection();
+        let (keys, secrets) = crate::tls::tests::client_one_rtt_keys();
+        conn.data.one_rtt_keys.set_keys(keys, secrets);
+        let mut params = Parameters::default();
+        params.set_initial_source_connection_id(conn.cid_registry.remote.initial_dcid());
+        params.set_original_destination_connection_id(Some(conn.initial_dcid));
+        params.set_initial_max_data(VarInt::from_u32(1 << 20));
+        params.set_initial_max_stream_data_uni(VarInt::from_u32(1 << 20));
+        params.set_initial_max_streams_uni(VarInt::from_u32(1));
+        conn.params.remote.write(Arc::new(params));
+        // 第二条路径需要对端提供的另一个连接ID
+        let frame = NewConnectionIdFrame {
+            sequence: VarInt::from_u32(1),
+            retire_prior_to: VarInt::from_u32(0),
+            id: ConnectionId::random_gen(8),
+            reset_token: ResetToken::random_gen(),
+        };
+        conn.cid_registry.remote.recv_frame(&frame).unwrap();
+
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let mut peers = Vec::new();
+        let mut paths = Vec::new();
+        for _ in 0..2 {
+            let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let pathway = Pathway::Direct {
+                local: usc.local_addr().unwrap(),
+                remote: peer.local_addr().unwrap(),
+            };
+            paths.push(conn.pathes.get_or_create(pathway, usc.clone()));
+            peers.push(peer);
+            if !conn.handshake.is_handshake_done() {
+                conn.handshake.recv_frame(&HandshakeDoneFrame).unwrap();
+            }
+        }
+        let challenge = async {
+            loop {
+                match paths[1].challenge_sndbuf().take() {
+                    Some(challenge) => break challenge,
+                    None => tokio::task::yield_now().await,
+                }
+            }
+        };
+        let challenge = tokio::time::timeout(Duration::from_secs(1), challenge)
+            .await
+            .unwrap();
+        paths[1].recv_response(challenge.into());
+        assert_eq!(paths[1].validated().await, Ok(()));
+        conn.pathes.enable_multipath();
+
+        // 没有确认，一条路径的拥塞窗口发满之后，其余的流数据只能由另一条路径发送
+        let (_, mut writer) = conn.streams.open_uni(1 << 20).await.unwrap().unwrap();
+        tokio::spawn(async move { writer.write_all(&[0u8; 64 * 1024]).await });
+
+        // 对端用同样的1rtt密钥解开数据报中的1rtt包，统计以路径标识path_id加密的包的个数
+        let (hpk, pk) = conn.data.one_rtt_keys.get_local_keys().unwrap();
+        let count_short_packets = |peer: tokio::net::UdpSocket, path_id: u32| {
+            let (hpk, pk) = (hpk.clone(), pk.clone());
+            async move {
+                let mut datagram = [0u8; 1500];
+                let mut count = 0;
+                while let Ok(recv) =
+                    tokio::time::timeout(Duration::from_millis(200), peer.recv(&mut datagram)).await
+                {
+                    let len = recv.unwrap();
+                    let (_, pk) = pk.lock_guard().get_local();
+                    for packet in PacketReader::new(BytesMut::from(&datagram[..len]), 8) {
+                        let Ok(Packet::Data(mut packet)) = packet else {
+                            continue;
+                        };
+                        if !matches!(packet.header, DataHeader::Short(_)) {
+                            continue;
+                        }
+                        let Some((undecoded_pn, _)) = remove_protection_of_short_packet(
+                            hpk.as_ref(),
+                            packet.bytes.as_mut(),
+                            packet.offset,
+                        ) else {
+                            continue;
+                        };
+                        let pn = undecoded_pn.decode(0);
+                        let body_offset = packet.offset + undecoded_pn.size();
+                        let bytes = packet.bytes.as_mut();
+                        if decrypt_packet_for_path(pk.as_ref(), path_id, pn, bytes, body_offset)
+                            .is_ok()
+                        {
+                            count += 1;
+                        }
+                    }
+                }
+                count
+            }
+        };
+        let mut peers = peers.into_iter();
+        let (first, second) = tokio::join!(
+            count_short_packets(peers.next().unwrap(), 0),
+            count_short_packets(peers.next().unwrap(), 1)
+        );
+        assert!(first >= 5 && second >= 5, "{first} {second}");
+
+        // 各路径在所用连接ID的序号对应的空间中发包，包号各自从0开始
+        assert_eq!(paths[0].spaces().sending_id(), 0);
+        assert_eq!(paths[1].spaces().sending_id(), 1);
+        let sent_packets = conn.data.spaces.sent_packets(1).unwrap();
+        let sent = sent_packets.recv().largest_pn();
+        assert!(sent >= second as u64, "{sent} {second}");
+    }
+
+    #[test]
+    fn test_preferred_pathway() {
+        let preferred_address = PreferredAddress::new(
+            "0.0.0.0:0".parse().unwrap(),
+            "[::1]:4433".parse().unwrap(),
+            ConnectionId::random_gen(8),
+            ResetToken::new(&[0x01; 16]),
+        );
+        let v4 = Pathway::Direct {
+            local: "127.0.0.1:1000".parse().unwrap(),
+            remote: "127.0.0.1:443".parse().unwrap(),
+        };
+        // 服务端没有提供IPv4的首选地址，留在原路径
+        assert_eq!(preferred_pathway(v4, &preferred_address), None);
+        let v6 = Pathway::Direct {
+            local: "[::1]:1000".parse().unwrap(),
+            remote: "[::1]:443".parse().unwrap(),
+        };
+        let expected = Pathway::Direct {
+            local: "[::1]:1000".parse().unwrap(),
+            remote: "[::1]:4433".parse().unwrap(),
+        };
+        assert_eq!(preferred_pathway(v6, &preferred_address), Some(expected));
+    }
+
+    // 服务端在传输参数中给出首选地址，握手完成后客户端向其迁移；返回原路径和首选地址的路径
+    async fn offer_preferred_address(
+        conn: &Connection,
+        usc: &ArcUsc,
+        peer: &tokio::net::UdpSocket,
+        preferred_peer: &tokio::net::UdpSocket,
+    ) -> (Pathway, Pathway) {
+        let original = Pathway::Direct {
+            local: usc.local_addr().unwrap(),
+            remote: peer.local_addr().unwrap(),
+        };
+        conn.pathes.get_or_create(original, usc.clone());
+
+        let SocketAddr::V4(address_v4) = preferred_peer.local_addr().unwrap() else {
+            unreachable!()
+        };
+        let preferred_cid = ConnectionId::random_gen(8);
+        let preferred_address = PreferredAddress::new(
+            address_v4,
+            "[::]:0".parse().unwrap(),
+            preferred_cid,
+            ResetToken::new(&[0x01; 16]),
+        );
+        let mut params = Parameters::default();
+        params.set_initial_source_connection_id(conn.cid_registry.remote.initial_dcid());
+        params.set_original_destination_connection_id(Some(conn.initial_dcid));
+        params.set_preferred_address(Some(preferred_address));
+        conn.params.remote.write(Arc::new(params));
+        _ = conn.state.transition(ConnectionState::Handshaking);
+        conn.state.transition(ConnectionState::Connected).unwrap();
+
+        let preferred = Pathway::Direct {
+            local: usc.local_addr().unwrap(),
+            remote: preferred_peer.local_addr().unwrap(),
+        };
+        let migrating = async {
+            while !conn.pathes.contains_key(&preferred) {
+                tokio::task::yield_now().await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(1), migrating)
+            .await
+            .unwrap();
+        // 首选地址的连接ID作为序号为1的连接ID
+        assert_eq!(conn.cid_registry.remote.latest_dcid(), Some(preferred_cid));
+        (original, preferred)
+    }
+
+    #[tokio::test]
+    async fn test_migrate_to_preferred_address() {
+        let conn = client_connection();
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let preferred_peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let (original, preferred) =
+            offer_preferred_address(&conn, &usc, &peer, &preferred_peer).await;
+
+        // 向首选地址发起路径验证，验证通过之前仍使用原路径
+        let path = conn.pathes.get(&preferred).unwrap().clone();
+        assert!(path.has_begun_validation());
+        assert_eq!(conn.pathes.active_pathway(), Some(original));
+    }
+
+    #[tokio::test]
+    async fn test_preferred_address_validation_failed() {
+        let clock = MockClock::new();
+        let conn = client_connection_with(Parameters::default(), None, Arc::new(clock.clone()));
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let preferred_peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let (original, preferred) =
+            offer_preferred_address(&conn, &usc, &peer, &preferred_peer).await;
+
+        // 首选地址从不回应挑战，所有的挑战都超时后验证失败；期间原路径上一直有包到达
+        let path = conn.pathes.get(&preferred).unwrap().clone();
+        let timeout = async {
+            loop {
+                conn.update_path_recv_time(original);
+                clock.advance(Duration::from_secs(1));
+                tokio::task::yield_now().await;
+            }
+        };
+        let result = tokio::select! {
+            result = path.validated() => result,
+            _ = timeout => unreachable!(),
+        };
+        assert_eq!(result, Err(ValidationError::Timeout));
+
+        // 迁移失败，连接仍使用原路径，且不受影响
+        tokio::task::yield_now().await;
+        assert_eq!(conn.pathes.active_pathway(), Some(original));
+        assert!(conn.error.close_reason().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_respect_disabled_active_migration() {
+        let conn = client_connection();
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let another_usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let original = Pathway::Direct {
+            local: usc.local_addr().unwrap(),
+            remote: peer.local_addr().unwrap(),
+        };
+        let migrated = Pathway::Direct {
+            local: another_usc.local_addr().unwrap(),
+            remote: peer.local_addr().unwrap(),
+        };
+        conn.pathes.get_or_create(original, usc.clone());
+
+        let mut params = Parameters::default();
+        params.set_initial_source_connection_id(conn.cid_registry.remote.initial_dcid());
+        params.set_original_destination_connection_id(Some(conn.initial_dcid));
+        params.set_disable_active_migration(true);
+        conn.params.remote.write(Arc::new(params));
+        let disabled = async {
+            while !conn.pathes.is_active_migration_disabled() {
+                tokio::task::yield_now().await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(1), disabled)
+            .await
+            .unwrap();
+
+        // 服务端禁止了主动迁移，客户端不会换用新的本地地址
+        assert!(!conn.migrate(migrated, another_usc));
+        assert!(!conn.pathes.contains_key(&migrated));
+        assert_eq!(conn.pathes.active_pathway(), Some(original));
+    }
+
+    #[tokio::test]
+    async fn test_refuse_active_migration() {
+        let mut local_params = Parameters::default();
+        local_params.set_disable_active_migration(true);
+        let conn = client_connection_with(local_params, None, Arc::new(TokioClock));
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let another_usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let local = usc.local_addr().unwrap();
+        let original = Pathway::Direct {
+            local,
+            remote: "127.0.0.1:5000".parse().unwrap(),
+        };
+        conn.pathes.get_or_create(original, usc.clone());
+
+        // NAT重绑定仍然允许
+        let rebound = Pathway::Direct {
+            local,
+            remote: "127.0.0.1:5001".parse().unwrap(),
+        };
+        assert!(!conn.pathes.is_migration_refused(rebound, true));
+
+        // 对端换到了首选地址之类的其他本地地址，也不是在握手地址上的主动迁移
+        let preferred = Pathway::Direct {
+            local: another_usc.local_addr().unwrap(),
+            remote: "127.0.0.1:5000".parse().unwrap(),
+        };
+        assert!(!conn.pathes.is_migration_refused(preferred, false));
+
+        // 对端在握手地址上主动迁移，其包被丢弃，但连接并不关闭
+        let migrated = Pathway::Direct {
+            local,
+            remote: "127.0.0.2:6000".parse().unwrap(),
+        };
+        assert!(conn.pathes.is_migration_refused(migrated, false));
+        assert!(conn.error.close_reason().is_none());
+
+        // 本地主动迁移到的路径不受影响
+        conn.pathes.get_or_create(migrated, usc);
+        assert!(!conn.pathes.is_migration_refused(migrated, false));
+    }
+
+    #[tokio::test]
+    async fn test_stateless_reset() {
+        let conn = client_connection();
+        let (keys, secrets) = crate::tls::tests::client_one_rtt_keys();
+        conn.data.one_rtt_keys.set_keys(keys, secrets);
+        // 对端的重置令牌由其连接ID派生，之后对端丢失了连接状态
+        let server_cid = ConnectionId::random_gen(8);
+        let mut params = Parameters::default();
+        params.set_initial_source_connection_id(conn.cid_registry.remote.initial_dcid());
+        params.set_original_destination_connection_id(Some(conn.initial_dcid));
+        params.set_statelss_reset_token(Some(Router::reset_token(&server_cid)));
+        conn.params.remote.write(Arc::new(params));
+        assert!(conn.params.remote.accepted().await);
+
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let pathway = Pathway::Direct {
+            local: usc.local_addr().unwrap(),
+            remote: "127.0.0.1:4433".parse().unwrap(),
+        };
+        // 无状态重置的连接ID是随机的，只能凭其末尾的重置令牌交付给连接
+        let reset = Router::stateless_reset(&server_cid, 100).unwrap();
+        Router::route_datagram(reset[..].into(), Ecn::NotEct, pathway, &usc, |_| {
+            panic!("the stateless reset should be delivered to the connection")
+        });
+
+        let (_, kind) = tokio::time::timeout(Duration::from_secs(1), conn.error.clone())
+            .await
+            .unwrap();
+        assert_eq!(kind, ConnErrorKind::StatelessReset);
+
+        // 不认识的令牌，数据报无法路由
+        let reset = Router::stateless_reset(&ConnectionId::random_gen(8), 100).unwrap();
+        let mut unrouted = 0;
+        Router::route_datagram(reset[..].into(), Ecn::NotEct, pathway, &usc, |_| {
+            unrouted += 1
+        });
+        assert_eq!(unrouted, 1);
+    }
+
+    #[tokio::test]
+    async fn test_closed_by_peer_with_app_code() {
+        let conn = client_connection();
+        let conn_error = conn.error.clone();
+        let conn = ArcConnection::from(conn);
+        assert!(conn.close_reason().is_none());
+
+        // 模拟收到对端应用层的CONNECTION_CLOSE帧
+        let ccf = ConnectionCloseFrame::new_app(VarInt::from_u32(0x10c), "request cancelled");
+        conn_error.on_ccf_rcvd(&ccf);
+        let expected = CloseReason {
+            initiator: CloseInitiator::Remote,
+            code: CloseCode::Application(VarInt::from_u32(0x10c)),
+            reason: "request cancelled".into(),
+        };
+        assert_eq!(conn.close_reason(), Some(expected.clone()));
+
+        // 连接进入draining状态后，流操作返回对应的错误
+        let draining = async {
+            while conn.state() != ConnectionState::Draining {
+                tokio::task::yield_now().await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(1), draining)
+            .await
+            .unwrap();
+        let Err(error) = conn.open_uni_stream().await else {
+            panic!("the closed connection can not open streams");
+        };
+        assert!(error.to_string().contains("request cancelled"));
+        // 本地随后的关闭不会覆盖对端的关闭原因
+        conn.close("too late");
+        assert_eq!(conn.close_reason(), Some(expected));
+    }
+
+    #[tokio::test]
+    async fn test_transport_parameters() {
+        let mut local_params = Parameters::default();
+        local_params.set_max_datagram_frame_size(VarInt::from_u32(1200));
+        let conn = client_connection_with(local_params, None, Arc::new(TokioClock));
+        assert_eq!(
+            conn.local_transport_parameters().max_datagram_frame_size(),
+            VarInt::from_u32(1200)
+        );
+        assert!(conn.peer_transport_parameters().is_none());
+
+        // 模拟握手过程中收到对端的传输参数
+        let mut remote_params = Parameters::default();
+        remote_params.set_max_datagram_frame_size(VarInt::from_u32(1350));
+        conn.params.remote.write(Arc::new(remote_params));
+        let peer_params = conn.peer_transport_parameters().unwrap();
+        assert_eq!(
+            peer_params.max_datagram_frame_size(),
+            VarInt::from_u32(1350)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_max_ack_delay_on_ack_frequency_acked() {
+        let conn = client_connection();
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let pathway = Pathway::Direct {
+            local: usc.local_addr().unwrap(),
+            remote: "127.0.0.1:4433".parse().unwrap(),
+        };
+        let path = conn.pathes.get_or_create(pathway, usc);
+        path.cc.on_handshake_done();
+        // 对端默认的max_ack_delay为25ms
+        let pto = path.cc.pto_time(Epoch::Data);
+
+        conn.ack_frequency
+            .on_remote_params(Some(Duration::from_millis(1)));
+        conn.request_ack_frequency(9, Duration::from_millis(5), 1)
+            .unwrap();
+        // 模拟在1Rtt数据包中发送ACK_FREQUENCY帧
+        let mut buf = [0u8; 1200];
+        let (frame, _) = conn.reliable_frames.try_read(&mut buf).unwrap();
+        assert!(matches!(frame, ReliableFrame::AckFrequency(_)));
+        let pn = {
+            let sent_packets = conn.data.space.sent_packets();
+            let mut send_guard = sent_packets.send();
+            let (pn, _) = send_guard.next_pn();
+            send_guard.record_frame(GuaranteedFrame::Reliable(frame));
+            pn
+        };
+        // 帧被确认之前，PTO仍以对端通告的max_ack_delay计算
+        assert_eq!(path.cc.pto_time(Epoch::Data), pto);
+
+        let on_data_acked =
+            conn.data
+                .data_acked_handler(&conn.streams, &conn.pathes, &conn.ack_frequency);
+        on_data_acked(&(
+            0,
+            AckFrame {
+                largest: VarInt::from_u32(pn as u32),
+                delay: VarInt::from_u32(0),
+                first_range: VarInt::from_u32(0),
+                ranges: vec![],
+                ecn: None,
+            },
+        ));
+        assert_eq!(
+            path.cc.pto_time(Epoch::Data),
+            pto - Duration::from_millis(20)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_ping() {
+        let conn = client_connection();
+        let (keys, secrets) = crate::tls::tests::client_one_rtt_keys();
+        conn.data.one_rtt_keys.set_keys(keys, secrets);
+        // PMTU探测包同样携带Ping帧，不做探测
+        conn.set_max_mtu(qcongestion::MSS);
+        let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let pathway = Pathway::Direct {
+            local: usc.local_addr().unwrap(),
+            remote: peer.local_addr().unwrap(),
+        };
+        let path = conn.pathes.get_or_create(pathway, usc);
+
+        // 对端用同样的1rtt密钥解开数据报中的1rtt包，返回携带Ping帧的包的包号，以及该包是否需要确认
+        let (hpk, pk) = conn.data.one_rtt_keys.get_local_keys().unwrap();
+        let recv_ping = |datagram: &[u8]| {
+            let (_, pk) = pk.lock_guard().get_local();
+            PacketReader::new(BytesMut::from(datagram), 8).find_map(|packet| {
+                let Ok(Packet::Data(mut packet)) = packet else {
+                    return None;
+                };
+                if !matches!(packet.header, DataHeader::Short(_)) {
+                    return None;
+                }
+                let pty = packet.header.get_type();
+                let (undecoded_pn, _) = remove_protection_of_short_packet(
+                    hpk.as_ref(),
+                    packet.bytes.as_mut(),
+                    packet.offset,
+                )?;
+                let pn = undecoded_pn.decode(0);
+                let body_offset = packet.offset + undecoded_pn.size();
+                let len =
+                    decrypt_packet(pk.as_ref(), pn, packet.bytes.as_mut(), body_offset).ok()?;
+                let mut body = packet.bytes.split_off(body_offset);
+                body.truncate(len);
+                FrameReader::new(body.freeze(), pty).find_map(|frame| match frame.unwrap() {
+                    (Frame::Ping(_), is_ack_eliciting) => Some((pn, is_ack_eliciting)),
+                    _ => None,
+                })
+            })
+        };
+
+        // 首批数据报中没有Ping帧
+        let mut datagram = [0u8; 1500];
+        while let Ok(recv) =
+            tokio::time::timeout(Duration::from_millis(100), peer.recv(&mut datagram)).await
+        {
+            let len = recv.unwrap();
+            assert!(recv_ping(&datagram[..len]).is_none());
+        }
+        let latest_rtt = path.rtt().latest_rtt;
+
+        conn.send_ping();
+        let ping = async {
+            loop {
+                let len = peer.recv(&mut datagram).await.unwrap();
+                if let Some(ping) = recv_ping(&datagram[..len]) {
+                    break ping;
+                }
+            }
+        };
+        let (pn, is_ack_eliciting) = tokio::time::timeout(Duration::from_secs(1), ping)
+            .await
+            .unwrap();
+        // 携带Ping帧的包需要确认，对端收到后必须回应ACK
+        assert!(is_ack_eliciting);
+
+        // 对端的确认为路径带来新的RTT样本
+        path.cc.on_ack(
+            Epoch::Data,
+            &AckFrame {
+                largest: VarInt::from_u32(pn as u32),
+                delay: VarInt::from_u32(0),
+                first_range: VarInt::from_u32(0),
+                ranges: vec![],
+                ecn: None,
+            },
+        );
+        let rtt = path.rtt().latest_rtt;
+        assert_ne!(rtt, latest_rtt);
+        assert!(rtt < Duration::from_millis(100), "{rtt:?}");
+    }
+}
@@ -1,18 +1,34 @@
 pub mod data;
 pub mod handshake;
 pub mod initial;
+pub mod spaces;
 
-use std::future::Future;
+use std::{future::Future, sync::Arc};
 
-pub use data::{ClosingOneRttScope, DataScope};
+pub use data::{ClosingOneRttScope, DataReaderContext, DataScope};
 pub use handshake::{ClosingHandshakeScope, HandshakeScope};
 pub use initial::InitialScope;
 use qbase::{
     frame::{Frame, FrameReader},
-    packet::{decrypt::decrypt_packet, header::GetType, DataPacket},
+    packet::{decrypt::decrypt_packet_for_path, header::GetType, DataPacket},
 };
+pub use spaces::{DataSpaces, PathSpaces};
 use tokio::sync::Notify;
 
+use super::{idle::ArcIdleTimer, Handshake, RcvdPackets};
+use crate::{error::ConnError, path::ArcPathes};
+
+/// The components of the connection shared by the tasks which receive the packets of each space.
+#[derive(Clone)]
+pub struct RecvContext {
+    pub pathes: ArcPathes,
+    pub handshake: Handshake,
+    pub idle_timer: ArcIdleTimer,
+    // 连接关闭时通知各接收任务退出
+    pub notify: Arc<Notify>,
+    pub conn_error: ConnError,
+}
+
 pub trait RecvPacket {
     fn has_rcvd_ccf(&self, packet: DataPacket) -> bool;
 
@@ -22,14 +38,38 @@ pub trait RecvPacket {
         mut packet: DataPacket,
         body_offset: usize,
     ) -> bool {
-        decrypt_packet(key, pn, packet.bytes.as_mut(), body_offset).unwrap();
-        let body = packet.bytes.split_off(body_offset);
+        Self::decrypt_and_parse_for_path(key, 0, pn, packet, body_offset)
+    }
+
+    // 多路径下，空间0以外的1rtt包，路径标识参与nonce的计算；路径标识为0时与decrypt_packet无异
+    fn decrypt_and_parse_for_path(
+        key: &dyn rustls::quic::PacketKey,
+        path_id: u32,
+        pn: u64,
+        mut packet: DataPacket,
+        body_offset: usize,
+    ) -> bool {
+        let decrypted =
+            decrypt_packet_for_path(key, path_id, pn, packet.bytes.as_mut(), body_offset);
+        let Ok(pkt_len) = decrypted else {
+            return false;
+        };
+        let mut body = packet.bytes.split_off(body_offset);
+        body.truncate(pkt_len);
         FrameReader::new(body.freeze(), packet.header.get_type())
             .filter_map(|frame| frame.ok())
             .any(|(f, _)| matches!(f, Frame::Close(_)))
     }
 }
 
+/// Stop receiving the packets of a space whose keys are discarded.
+///
+/// The channel of the received packets is closed, so that the subsequent packets of the space are
+/// dropped by the router at once, rather than being queued and parsed.
+fn stop_receiving(rcvd_packets: &mut RcvdPackets) {
+    rcvd_packets.close();
+}
+
 async fn any<F, T>(fut: F, notify: &Notify) -> Option<T>
 where
     F: Future<Output = Option<T>>,
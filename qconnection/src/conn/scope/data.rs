@@ -1,4 +1,7 @@
-use std::sync::Arc;
+use std::{
+    ops::DerefMut,
+    sync::{Arc, Mutex},
+};
 
 use bytes::{BufMut, Bytes};
 use futures::{channel::mpsc, StreamExt};
@@ -7,41 +10,49 @@ use qbase::{
     error::{Error as QuicError, ErrorKind},
     flow,
     frame::{
-        io::WriteFrame, AckFrame, BeFrame, ConnectionCloseFrame, Frame, FrameReader,
+        io::WriteFrame, AckFrame, BeFrame, ConnectionCloseFrame, Frame, FrameReader, FrameType,
         PathChallengeFrame, PathResponseFrame, ReceiveFrame, ReliableFrame, SendFrame,
         StreamCtlFrame, StreamFrame,
     },
     handshake::Handshake,
     packet::{
         decrypt::{
-            decrypt_packet, remove_protection_of_long_packet, remove_protection_of_short_packet,
+            decrypt_packet, decrypt_packet_for_path, remove_protection_of_long_packet,
+            remove_protection_of_short_packet,
         },
-        encrypt::{encode_short_first_byte, encrypt_packet, protect_header},
+        encrypt::{encode_short_first_byte, encrypt_packet_for_path, protect_header},
+        error::Error as PacketError,
         header::{
             short::{io::WriteShortHeader, OneRttHeader},
-            EncodeHeader, GetType,
+            EncodeHeader, GetDcid, GetType,
         },
         keys::{ArcKeys, ArcOneRttKeys, ArcOneRttPacketKeys, HeaderProtectionKeys},
         number::WritePacketNumber,
         r#type::Type,
         DataPacket, PacketNumber,
     },
-    token::ArcTokenRegistry,
+    qlog::PacketType,
+    sid::Role,
+    token::{ArcTokenRegistry, ResetToken},
 };
-use qcongestion::{CongestionControl, MayLoss, RetirePktRecord, MSS};
+use qcongestion::{CongestionControl, MayLoss, MSS};
 use qrecovery::{
     crypto::{CryptoStream, CryptoStreamOutgoing},
-    reliable::{ArcRcvdPktRecords, ArcReliableFrameDeque, GuaranteedFrame},
+    reliable::{ArcReliableFrameDeque, GuaranteedFrame},
     space::{DataSpace, Epoch},
 };
 use qunreliable::DatagramFlow;
-use tokio::{sync::Notify, task::JoinHandle};
+use tokio::task::JoinHandle;
 
-use super::any;
+use super::{any, stop_receiving, DataSpaces, PathSpaces, RecvContext};
 use crate::{
-    conn::{transmit::data::DataSpaceReader, CidRegistry, DataStreams, RcvdPackets},
+    conn::{
+        ack_frequency::ArcAckFrequency, idle::ArcIdleTimer, stats::ArcPacketCounters,
+        transmit::data::DataSpaceReader, version::ArcVersions, CidRegistry, DataStreams,
+        PacketEntry, RcvdPackets,
+    },
     error::ConnError,
-    path::{ArcPathes, Path, SendBuffer},
+    path::{ArcPathes, Path, Reinjection, SendBuffer},
     pipe,
     router::Router,
 };
@@ -51,16 +62,34 @@ pub struct DataScope {
     pub zero_rtt_keys: ArcKeys,
     pub one_rtt_keys: ArcOneRttKeys,
     pub space: DataSpace,
+    // 多路径下各路径的包号空间，空间0即是space
+    pub spaces: DataSpaces,
     pub crypto_stream: CryptoStream,
+    // 发送过的0Rtt数据包的包号，0Rtt被拒绝时，这些包中的帧需要在1Rtt空间重传
+    pub sent_0rtt_pkts: Arc<Mutex<Vec<u64>>>,
+}
+
+/// The connection-level components shared by the [`DataSpaceReader`]s of all paths.
+#[derive(Clone)]
+pub struct DataReaderContext {
+    pub versions: ArcVersions,
+    pub reliable_frames: ArcReliableFrameDeque,
+    pub streams: DataStreams,
+    pub datagrams: DatagramFlow,
+    pub idle_timer: ArcIdleTimer,
+    pub ack_frequency: ArcAckFrequency,
 }
 
 impl Default for DataScope {
     fn default() -> Self {
+        let space = DataSpace::with_capacity(16);
         Self {
             zero_rtt_keys: ArcKeys::new_pending(),
             one_rtt_keys: ArcOneRttKeys::new_pending(),
-            space: DataSpace::with_capacity(16),
-            crypto_stream: CryptoStream::new(4096, 4096),
+            spaces: DataSpaces::new(space.clone()),
+            space,
+            crypto_stream: CryptoStream::new(4096, 16384),
+            sent_0rtt_pkts: Default::default(),
         }
     }
 }
@@ -69,19 +98,24 @@ impl DataScope {
     #[allow(clippy::too_many_arguments)]
     pub fn build(
         &self,
-        pathes: &ArcPathes,
-        handshake: &Handshake<ArcReliableFrameDeque>,
+        ctx: &RecvContext,
         reliable_frames: &ArcReliableFrameDeque,
         streams: &DataStreams,
         datagrams: &DatagramFlow,
         cid_registry: &CidRegistry,
         flow_ctrl: &flow::FlowController,
-        notify: &Arc<Notify>,
-        conn_error: &ConnError,
+        ack_frequency: &ArcAckFrequency,
         rcvd_0rtt_packets: RcvdPackets,
         rcvd_1rtt_packets: RcvdPackets,
+        one_rtt_packets_entry: &PacketEntry,
         recv_new_token: ArcTokenRegistry,
     ) -> (JoinHandle<RcvdPackets>, JoinHandle<RcvdPackets>) {
+        let RecvContext {
+            pathes,
+            handshake,
+            conn_error,
+            ..
+        } = ctx;
         let (ack_frames_entry, rcvd_ack_frames) = mpsc::unbounded();
         // 连接级的
         let (max_data_frames_entry, rcvd_max_data_frames) = mpsc::unbounded();
@@ -96,17 +130,78 @@ impl DataScope {
         let (stream_frames_entry, rcvd_stream_frames) = mpsc::unbounded();
         let (datagram_frames_entry, rcvd_datagram_frames) = mpsc::unbounded();
 
+        // ACK帧确认空间0中的包，PATH_ACK帧确认其指明的空间中的包
+        let dispatch_ack_frame = {
+            let conn_error = conn_error.clone();
+            let pathes = pathes.clone();
+            let spaces = self.spaces.clone();
+            move |id: u64, f: AckFrame, frame_type: FrameType, path: &Path| {
+                // 空间已随发送它的路径释放，其中的包都已被判定丢失
+                let Some(sent_pkt_records) = spaces.sent_packets(id) else {
+                    return;
+                };
+                // 确认了从未发送过的包，不能据此更新拥塞控制、MTU以及密钥阶段等任何状态
+                if f.largest.into_inner() >= sent_pkt_records.recv().largest_pn() {
+                    conn_error.on_error(QuicError::new(
+                        ErrorKind::ProtocolViolation,
+                        frame_type,
+                        "acknowledge a packet that was never sent",
+                    ));
+                    return;
+                }
+                let on_path_acked = |path: &Path| {
+                    path.cc.on_ack(Epoch::Data, &f);
+                    path.mtu.on_ack(&f);
+                    // 探测包被确认后，以新的MTU衡量发送配额
+                    path.cc.set_mtu(path.mtu.current_mtu());
+                };
+                // 确认可经由任一路径返回，由在该空间中发送的路径更新拥塞控制和MTU
+                if path.spaces().sending_id() == id {
+                    on_path_acked(path);
+                } else if let Some(path) = pathes.iter().find(|p| p.spaces().sending_id() == id) {
+                    on_path_acked(&path);
+                }
+                _ = ack_frames_entry.unbounded_send((id, f))
+            }
+        };
         let dispatch_data_frame = {
             let conn_error = conn_error.clone();
-            move |frame: Frame, pty: Type, path: &Path| match frame {
+            let local_cids = cid_registry.local.clone();
+            let ack_frequency = ack_frequency.clone();
+            let pathes = pathes.clone();
+            let spaces = self.spaces.clone();
+            move |frame: Frame, pty: Type, dcid: &ConnectionId, path: &Path| match frame {
                 Frame::Ack(f) => {
-                    path.cc.on_ack(Epoch::Data, &f);
-                    _ = ack_frames_entry.unbounded_send(f)
+                    let frame_type = f.frame_type();
+                    dispatch_ack_frame(0, f, frame_type, path)
+                }
+                Frame::PathAck(f) if pathes.is_multipath_enabled() => {
+                    let frame_type = f.frame_type();
+                    dispatch_ack_frame(f.path_id.into_inner(), f.ack, frame_type, path)
                 }
+                Frame::PathAck(f) => conn_error.on_error(QuicError::new(
+                    ErrorKind::ProtocolViolation,
+                    f.frame_type(),
+                    "PATH_ACK frame without the multipath extension",
+                )),
                 Frame::NewToken(f) => _ = new_token_frames_entry.unbounded_send(f),
                 Frame::MaxData(f) => _ = max_data_frames_entry.unbounded_send(f),
                 Frame::NewConnectionId(f) => _ = new_cid_frames_entry.unbounded_send(f),
-                Frame::RetireConnectionId(f) => _ = retire_cid_frames_entry.unbounded_send(f),
+                // The sequence number MUST NOT refer to the DCID of the packet containing the frame
+                Frame::RetireConnectionId(f)
+                    if local_cids.get(f.sequence.into_inner()).as_ref() == Some(dcid) =>
+                {
+                    conn_error.on_error(QuicError::new(
+                        ErrorKind::ProtocolViolation,
+                        f.frame_type(),
+                        "retire the connection ID of the packet carrying the frame",
+                    ))
+                }
+                Frame::RetireConnectionId(f) => {
+                    // 以该连接ID收包的空间随之移除
+                    spaces.remove_receiving(f.sequence.into_inner());
+                    _ = retire_cid_frames_entry.unbounded_send(f)
+                }
                 Frame::HandshakeDone(f) => _ = handshake_done_frames_entry.unbounded_send(f),
                 Frame::DataBlocked(f) => _ = data_blocked_frames_entry.unbounded_send(f),
                 Frame::Challenge(f) => path.recv_challenge(f),
@@ -116,41 +211,25 @@ impl DataScope {
                 Frame::Crypto(f, bytes) => _ = crypto_frames_entry.unbounded_send((f, bytes)),
                 Frame::Datagram(f, data) => _ = datagram_frames_entry.unbounded_send((f, data)),
                 Frame::Close(f) if matches!(pty, Type::Short(_)) => conn_error.on_ccf_rcvd(&f),
+                // 每条路径各自决定何时发送ack，只作用于收到该帧的路径
+                Frame::AckFrequency(f) => match ack_frequency.recv_frame(&f) {
+                    Ok(true) => path.cc.on_ack_frequency(&f),
+                    Ok(false) => {}
+                    Err(e) => conn_error.on_error(e),
+                },
+                Frame::ImmediateAck(_) => path.cc.on_immediate_ack(),
                 _ => {}
             }
         };
-        let on_data_acked = {
-            let data_streams = streams.clone();
-            let crypto_stream_outgoing = self.crypto_stream.outgoing();
-            let sent_pkt_records = self.space.sent_packets();
-            move |ack_frame: &AckFrame| {
-                let mut recv_guard = sent_pkt_records.recv();
-                recv_guard.update_largest(ack_frame.largest.into_inner());
-
-                for pn in ack_frame.iter().flat_map(|r| r.rev()) {
-                    for frame in recv_guard.on_pkt_acked(pn) {
-                        match frame {
-                            GuaranteedFrame::Stream(stream_frame) => {
-                                data_streams.on_data_acked(stream_frame)
-                            }
-                            GuaranteedFrame::Crypto(crypto_frame) => {
-                                crypto_stream_outgoing.on_data_acked(&crypto_frame)
-                            }
-                            GuaranteedFrame::Reliable(ReliableFrame::Stream(
-                                StreamCtlFrame::ResetStream(reset_frame),
-                            )) => data_streams.on_reset_acked(reset_frame),
-                            _ => { /* nothing to do */ }
-                        }
-                    }
-                }
-            }
-        };
+        let on_data_acked = self.data_acked_handler(streams, pathes, ack_frequency);
 
         // Assemble the pipelines of frame processing
         // TODO: pipe rcvd_new_token_frames
         let local_cids_with_router = Router::revoke(cid_registry.local.clone());
-        pipe!(rcvd_retire_cid_frames |> local_cids_with_router, recv_frame);
-        pipe!(@error(conn_error) rcvd_new_cid_frames |> cid_registry.remote, recv_frame);
+        pipe!(@error(conn_error) rcvd_retire_cid_frames |> local_cids_with_router, recv_frame);
+        let remote_cids_with_router =
+            Router::reset_tokens(cid_registry.remote.clone(), one_rtt_packets_entry.clone());
+        pipe!(@error(conn_error) rcvd_new_cid_frames |> remote_cids_with_router, recv_frame);
         pipe!(rcvd_max_data_frames |> flow_ctrl.sender, recv_frame);
         pipe!(rcvd_data_blocked_frames |> flow_ctrl.recver, recv_frame);
         pipe!(@error(conn_error) rcvd_handshake_done_frames |> *handshake, recv_frame);
@@ -171,50 +250,111 @@ impl DataScope {
 
         let join_handler0 = self.parse_rcvd_0rtt_packet_and_dispatch_frames(
             rcvd_0rtt_packets,
-            pathes.clone(),
+            ctx,
             dispatch_data_frame.clone(),
-            notify.clone(),
-            conn_error.clone(),
         );
         let join_handler1 = self.parse_rcvd_1rtt_packet_and_dispatch_frames(
             rcvd_1rtt_packets,
-            pathes.clone(),
+            !handshake.role(),
+            ctx,
+            cid_registry.clone(),
             dispatch_data_frame,
-            notify.clone(),
-            conn_error.clone(),
         );
         (join_handler0, join_handler1)
     }
 
+    // 处理数据空间中被确认的包所携带的帧
+    pub(crate) fn data_acked_handler(
+        &self,
+        streams: &DataStreams,
+        pathes: &ArcPathes,
+        ack_frequency: &ArcAckFrequency,
+    ) -> impl Fn(&(u64, AckFrame)) + Send + 'static {
+        let data_streams = streams.clone();
+        let pathes = pathes.clone();
+        let ack_frequency = ack_frequency.clone();
+        let crypto_stream_outgoing = self.crypto_stream.outgoing();
+        let spaces = self.spaces.clone();
+        let one_rtt_keys = self.one_rtt_keys.clone();
+        move |(id, ack_frame): &(u64, AckFrame)| {
+            let Some(sent_pkt_records) = spaces.sent_packets(*id) else {
+                return;
+            };
+            let mut recv_guard = sent_pkt_records.recv();
+            recv_guard.update_largest(ack_frame.largest.into_inner());
+
+            // an acknowledged packet confirms the current key phase, the ACK has been validated
+            // when it was dispatched. The key updates are tracked in the packet number space 0.
+            if *id == 0 {
+                if let Some((_, pk)) = one_rtt_keys.get_local_keys() {
+                    pk.lock_guard().on_pkt_acked(ack_frame.largest.into_inner());
+                }
+            }
+
+            for pn in ack_frame.iter().flat_map(|r| r.rev()) {
+                for frame in recv_guard.on_pkt_acked(pn) {
+                    match frame {
+                        GuaranteedFrame::Stream(stream_frame) => {
+                            data_streams.on_data_acked(stream_frame)
+                        }
+                        GuaranteedFrame::Crypto(crypto_frame) => {
+                            crypto_stream_outgoing.on_data_acked(&crypto_frame)
+                        }
+                        GuaranteedFrame::Reliable(ReliableFrame::Stream(
+                            StreamCtlFrame::ResetStream(reset_frame),
+                        )) => data_streams.on_reset_acked(reset_frame),
+                        // 对端开始按照请求的max_ack_delay延迟确认，PTO也随之计算
+                        GuaranteedFrame::Reliable(ReliableFrame::AckFrequency(frame)) => {
+                            if let Some(max_ack_delay) = ack_frequency.on_frame_acked(&frame) {
+                                for path in pathes.iter() {
+                                    path.set_max_ack_delay(max_ack_delay);
+                                }
+                            }
+                        }
+                        _ => { /* nothing to do */ }
+                    }
+                }
+            }
+        }
+    }
+
     fn parse_rcvd_0rtt_packet_and_dispatch_frames(
         &self,
         mut rcvd_packets: RcvdPackets,
-        pathes: ArcPathes,
-        dispatch_frame: impl Fn(Frame, Type, &Path) + Send + 'static,
-        notify: Arc<Notify>,
-        conn_error: ConnError,
+        ctx: &RecvContext,
+        dispatch_frame: impl Fn(Frame, Type, &ConnectionId, &Path) + Send + 'static,
     ) -> JoinHandle<RcvdPackets> {
+        let RecvContext {
+            pathes,
+            handshake,
+            idle_timer,
+            notify,
+            conn_error,
+        } = ctx.clone();
         tokio::spawn({
             let rcvd_pkt_records = self.space.rcvd_packets();
             let keys = self.zero_rtt_keys.clone();
             async move {
-                while let Some((mut packet, pathway, usc)) = any(rcvd_packets.next(), &notify).await
+                while let Some((mut packet, ecn, pathway, usc)) =
+                    any(rcvd_packets.next(), &notify).await
                 {
+                    // 0-RTT packets are useless after the handshake is confirmed
+                    if handshake.is_handshake_done() {
+                        stop_receiving(&mut rcvd_packets);
+                        keys.invalid();
+                        break;
+                    }
                     let pty = packet.header.get_type();
+                    let dcid = *packet.header.get_dcid();
                     let Some(keys) = any(keys.get_remote_keys(), &notify).await else {
                         break;
                     };
-                    let undecoded_pn = match remove_protection_of_long_packet(
+                    let Some(undecoded_pn) = remove_protection_of_long_packet(
                         keys.remote.header.as_ref(),
                         packet.bytes.as_mut(),
                         packet.offset,
-                    ) {
-                        Ok(Some(pn)) => pn,
-                        Ok(None) => continue,
-                        Err(invalid_reserved_bits) => {
-                            conn_error.on_error(invalid_reserved_bits.into());
-                            break;
-                        }
+                    ) else {
+                        continue;
                     };
 
                     let pn = match rcvd_pkt_records.decode_pn(undecoded_pn) {
@@ -229,24 +369,34 @@ impl DataScope {
                         packet.bytes.as_mut(),
                         body_offset,
                     );
-                    let Ok(pkt_len) = decrypted else { continue };
+                    let pkt_len = match decrypted {
+                        Ok(pkt_len) => pkt_len,
+                        Err(invalid_reserved_bits @ PacketError::InvalidReservedBits(..)) => {
+                            conn_error.on_error(invalid_reserved_bits.into());
+                            break;
+                        }
+                        Err(_) => continue,
+                    };
 
                     let path = pathes.get_or_create(pathway, usc);
                     path.on_rcvd(packet.bytes.len());
+                    path.on_packet_rcvd(PacketType::ZeroRtt, pn, packet.bytes.len());
+                    idle_timer.on_rcvd();
 
                     let _header = packet.bytes.split_to(body_offset);
                     packet.bytes.truncate(pkt_len);
 
-                    match FrameReader::new(packet.bytes.freeze(), pty).try_fold(
-                        false,
-                        |is_ack_packet, frame| {
+                    // 0-RTT包只由客户端发送
+                    match FrameReader::new(packet.bytes.freeze(), pty)
+                        .sent_by(Role::Client)
+                        .try_fold(false, |is_ack_packet, frame| {
                             let (frame, is_ack_eliciting) = frame?;
-                            dispatch_frame(frame, pty, &path);
+                            dispatch_frame(frame, pty, &dcid, &path);
                             Ok(is_ack_packet || is_ack_eliciting)
-                        },
-                    ) {
+                        }) {
                         Ok(is_ack_packet) => {
                             rcvd_pkt_records.register_pn(pn);
+                            rcvd_pkt_records.register_ecn(ecn);
                             path.cc.on_pkt_rcvd(Epoch::Data, pn, is_ack_packet);
                         }
                         Err(e) => conn_error.on_error(e),
@@ -260,62 +410,169 @@ impl DataScope {
     fn parse_rcvd_1rtt_packet_and_dispatch_frames(
         &self,
         mut rcvd_packets: RcvdPackets,
-        pathes: ArcPathes,
-        dispatch_frame: impl Fn(Frame, Type, &Path) + Send + 'static,
-        notify: Arc<Notify>,
-        conn_error: ConnError,
+        peer: Role,
+        ctx: &RecvContext,
+        cid_registry: CidRegistry,
+        dispatch_frame: impl Fn(Frame, Type, &ConnectionId, &Path) + Send + 'static,
     ) -> JoinHandle<RcvdPackets> {
+        let RecvContext {
+            pathes,
+            idle_timer,
+            notify,
+            conn_error,
+            ..
+        } = ctx.clone();
         tokio::spawn({
-            let rcvd_pkt_records = self.space.rcvd_packets();
+            let spaces = self.spaces.clone();
             let keys = self.one_rtt_keys.clone();
+            let CidRegistry {
+                local: local_cids,
+                remote: remote_cids,
+            } = cid_registry;
             async move {
-                while let Some((mut packet, pathway, usc)) = any(rcvd_packets.next(), &notify).await
+                let mut largest_pn = None;
+                // 最近一个触发迁移的非探测包所使用的连接ID
+                let mut active_dcid = None;
+                while let Some((mut packet, ecn, pathway, usc)) =
+                    any(rcvd_packets.next(), &notify).await
                 {
                     let pty = packet.header.get_type();
+                    let dcid = *packet.header.get_dcid();
                     let Some((hpk, pk)) = any(keys.get_remote_keys(), &notify).await else {
                         break;
                     };
-                    let (undecoded_pn, key_phase) = match remove_protection_of_short_packet(
+                    // 多路径下，以连接ID的序号标识收包的空间，不认识的连接ID只可能是无状态重置
+                    let space_id = match pathes.is_multipath_enabled() {
+                        true => local_cids.sequence_of(&dcid).unwrap_or(0),
+                        false => 0,
+                    };
+                    let rcvd_pkt_records = spaces.rcvd_packets(space_id, dcid);
+                    // 无法解密的包，可能是对端发来的无状态重置
+                    let reset_token = ResetToken::from_datagram_tail(&packet.bytes);
+                    let is_stateless_reset =
+                        || reset_token.is_some_and(|token| remote_cids.is_stateless_reset(&token));
+                    let Some((undecoded_pn, key_phase)) = remove_protection_of_short_packet(
                         hpk.as_ref(),
                         packet.bytes.as_mut(),
                         packet.offset,
-                    ) {
-                        Ok(Some(pn)) => pn,
-                        Ok(None) => continue,
-                        Err(invalid_reserved_bits) => {
-                            conn_error.on_error(invalid_reserved_bits.into());
+                    ) else {
+                        if is_stateless_reset() {
+                            conn_error.on_stateless_reset();
                             break;
                         }
+                        continue;
                     };
 
                     let pn = match rcvd_pkt_records.decode_pn(undecoded_pn) {
                         Ok(pn) => pn,
+                        Err(_) if is_stateless_reset() => {
+                            conn_error.on_stateless_reset();
+                            break;
+                        }
                         // TooOld/TooLarge/HasRcvd
                         Err(_e) => continue,
                     };
                     let body_offset = packet.offset + undecoded_pn.size();
-                    let pk = pk.lock_guard().get_remote(key_phase, pn);
-                    let decrypted =
-                        decrypt_packet(pk.as_ref(), pn, packet.bytes.as_mut(), body_offset);
-                    let Ok(pkt_len) = decrypted else { continue };
+                    let mut pk_guard = pk.lock_guard();
+                    // 密钥更新只由空间0中的包发起，其他空间的包只能使用已有的密钥
+                    let pk = match space_id {
+                        0 => Some(pk_guard.get_remote(key_phase, pn)),
+                        _ => pk_guard.get_remote_of_phase(key_phase),
+                    };
+                    let decrypted = match (pk, u32::try_from(space_id)) {
+                        (Some(pk), Ok(path_id)) => decrypt_packet_for_path(
+                            pk.as_ref(),
+                            path_id,
+                            pn,
+                            packet.bytes.as_mut(),
+                            body_offset,
+                        ),
+                        _ => Err(PacketError::DecryptPacketFailure),
+                    };
+                    let pkt_len = match decrypted {
+                        Ok(pkt_len) => pkt_len,
+                        Err(invalid_reserved_bits @ PacketError::InvalidReservedBits(..)) => {
+                            conn_error.on_error(invalid_reserved_bits.into());
+                            break;
+                        }
+                        Err(_) if is_stateless_reset() => {
+                            conn_error.on_stateless_reset();
+                            break;
+                        }
+                        Err(_) => continue,
+                    };
 
-                    let path = pathes.get_or_create(pathway, usc);
+                    // 主动迁移必须换用新的连接ID，连接ID不变而对端地址变化，可能是NAT重绑定
+                    let active = pathes.active_pathway().filter(|active| {
+                        active_dcid == Some(dcid) && pathway.is_rebinding_of(active)
+                    });
+                    // 拒绝了对端的主动迁移，直接丢弃该路径上的包，也不为其创建路径
+                    if pathes.is_migration_refused(pathway, active.is_some()) {
+                        continue;
+                    }
+                    // the peer may have initiated a key update
+                    if space_id == 0 {
+                        if let Err(error) = pk_guard.on_pkt_rcvd(key_phase, pn) {
+                            conn_error.on_error(error);
+                            break;
+                        }
+                    }
+                    drop(pk_guard);
+
+                    let path = match active {
+                        Some(active) => pathes.get_or_rebind(active, pathway, usc),
+                        None => pathes.get_or_create(pathway, usc),
+                    };
                     path.on_rcvd(packet.bytes.len());
+                    path.on_packet_rcvd(PacketType::OneRtt, pn, packet.bytes.len());
+                    idle_timer.on_rcvd();
+                    // 路径换用了对端的连接ID，之后的确认都针对新的空间，旧空间的确认状态作废
+                    if path
+                        .spaces()
+                        .bind_receiving(space_id, rcvd_pkt_records.clone())
+                    {
+                        path.cc.reset_ack_state(Epoch::Data);
+                    }
 
                     let _header = packet.bytes.split_to(body_offset);
                     packet.bytes.truncate(pkt_len);
 
-                    match FrameReader::new(packet.bytes.freeze(), pty).try_fold(
-                        false,
-                        |is_ack_packet, frame| {
-                            let (frame, is_ack_eliciting) = frame?;
-                            dispatch_frame(frame, pty, &path);
-                            Ok(is_ack_packet || is_ack_eliciting)
-                        },
-                    ) {
-                        Ok(is_ack_packet) => {
+                    match FrameReader::new(packet.bytes.freeze(), pty)
+                        .sent_by(peer)
+                        .try_fold(
+                            (false, true),
+                            |(is_ack_packet, is_probing_packet), frame| {
+                                let (frame, is_ack_eliciting) = frame?;
+                                let is_probing = matches!(
+                                    frame,
+                                    Frame::Padding(_)
+                                        | Frame::Challenge(_)
+                                        | Frame::Response(_)
+                                        | Frame::NewConnectionId(_)
+                                );
+                                dispatch_frame(frame, pty, &dcid, &path);
+                                Ok((
+                                    is_ack_packet || is_ack_eliciting,
+                                    is_probing_packet && is_probing,
+                                ))
+                            },
+                        ) {
+                        Ok((is_ack_packet, is_probing_packet)) => {
                             rcvd_pkt_records.register_pn(pn);
+                            rcvd_pkt_records.register_ecn(ecn);
                             path.cc.on_pkt_rcvd(Epoch::Data, pn, is_ack_packet);
+                            if let Type::Short(one_rtt) = pty {
+                                path.on_spin_rcvd(pn, *one_rtt);
+                            }
+                            // 只有收到最大包号的非探测包，才会触发连接迁移，乱序到达的包不会。
+                            // 多路径下各路径并行收发，只有空间0中的包才会触发迁移
+                            if space_id == 0 && largest_pn.is_none_or(|largest| pn > largest) {
+                                largest_pn = Some(pn);
+                                if !is_probing_packet {
+                                    active_dcid = Some(dcid);
+                                    pathes.on_non_probing_packet(pathway);
+                                }
+                            }
                         }
                         Err(e) => conn_error.on_error(e),
                     }
@@ -381,73 +638,141 @@ impl DataScope {
 
     pub fn reader(
         &self,
+        ctx: DataReaderContext,
         challenge_sndbuf: SendBuffer<PathChallengeFrame>,
         response_sndbuf: SendBuffer<PathResponseFrame>,
-        reliable_frames: ArcReliableFrameDeque,
-        streams: DataStreams,
-        datagrams: DatagramFlow,
+        reinjection: Reinjection,
+        path_spaces: PathSpaces,
     ) -> DataSpaceReader {
+        let DataReaderContext {
+            versions,
+            reliable_frames,
+            streams,
+            datagrams,
+            idle_timer,
+            ack_frequency,
+        } = ctx;
         DataSpaceReader {
             space: self.space.clone(),
+            path_spaces,
             zero_rtt_keys: self.zero_rtt_keys.clone(),
             one_rtt_keys: self.one_rtt_keys.clone(),
+            sent_0rtt_pkts: self.sent_0rtt_pkts.clone(),
+            versions,
             challenge_sndbuf,
             response_sndbuf,
+            reinjection,
             crypto_stream_outgoing: self.crypto_stream.outgoing(),
             reliable_frames,
             streams,
             datagrams,
+            idle_timer,
+            ack_frequency,
         }
     }
 }
 
-impl RetirePktRecord for DataScope {
-    fn retire(&self, pn: u64) {
-        self.space.rcvd_packets().write().retire(pn);
+impl DataScope {
+    /// Called when the 1-RTT keys are ready and the server accepts the 0-RTT data.
+    ///
+    /// No more 0-RTT packets will be sent, the 0-RTT keys are discarded, along with the packet
+    /// numbers of the sent 0-RTT packets, which are only kept in case the 0-RTT data is rejected.
+    pub fn on_0rtt_accepted(&self) {
+        self.zero_rtt_keys.invalid();
+        self.sent_0rtt_pkts.lock().unwrap().clear();
+    }
+
+    /// Called when the server rejects the 0-RTT data.
+    ///
+    /// The 0-RTT keys are discarded, and the frames sent in the 0-RTT packets are treated as lost,
+    /// so that they will be retransmitted in 1-RTT packets. The stream data retransmitted does
+    /// not count towards the connection-level flow control limit again. The rejected 0-RTT packets
+    /// are counted as lost in the `counters`.
+    ///
+    /// See [0-RTT](https://www.rfc-editor.org/rfc/rfc9001.html#name-0-rtt)
+    /// of [RFC 9001](https://www.rfc-editor.org/rfc/rfc9001.html) for more details.
+    pub fn on_0rtt_rejected(
+        &self,
+        reliable_frames: &ArcReliableFrameDeque,
+        streams: &DataStreams,
+        counters: &ArcPacketCounters,
+    ) {
+        self.zero_rtt_keys.invalid();
+
+        let sent_0rtt_pkts = core::mem::take(self.sent_0rtt_pkts.lock().unwrap().deref_mut());
+        // 0-RTT包都在空间0中发送
+        let may_loss = DataMayLoss::new(
+            self.spaces.path_spaces(),
+            reliable_frames.clone(),
+            streams.clone(),
+            self.crypto_stream.outgoing(),
+            counters.clone(),
+        );
+        // 已被判定丢失的包不会重复反馈其中的帧，也不重复计数
+        for pn in sent_0rtt_pkts {
+            if may_loss.retransmit(pn) {
+                counters.on_packet_lost(Epoch::Data, true);
+            }
+        }
     }
 }
 
 #[derive(Clone)]
 pub struct DataMayLoss {
-    space: DataSpace,
+    // 丢包判定总是针对路径当前发送所在的空间
+    spaces: PathSpaces,
     reliable_frames: ArcReliableFrameDeque,
     data_streams: DataStreams,
     outgoing: CryptoStreamOutgoing,
+    counters: ArcPacketCounters,
 }
 
 impl DataMayLoss {
     pub fn new(
-        space: DataSpace,
+        spaces: PathSpaces,
         reliable_frames: ArcReliableFrameDeque,
         data_streams: DataStreams,
         outgoing: CryptoStreamOutgoing,
+        counters: ArcPacketCounters,
     ) -> Self {
         Self {
-            space,
+            spaces,
             reliable_frames,
             data_streams,
             outgoing,
+            counters,
         }
     }
-}
-impl MayLoss for DataMayLoss {
-    fn may_loss(&self, pn: u64) {
-        for frame in self.space.sent_packets().recv().may_loss_pkt(pn) {
+
+    // 将包中的帧重新放入发送队列，返回是否有帧需要重传
+    fn retransmit(&self, pn: u64) -> bool {
+        let mut retransmitted = false;
+        let (_, sent_pkt_records) = self.spaces.sending();
+        for frame in sent_pkt_records.recv().may_loss_pkt(pn) {
             match frame {
                 GuaranteedFrame::Stream(f) => self.data_streams.may_loss_data(&f),
                 GuaranteedFrame::Reliable(f) => self.reliable_frames.send_frame([f]),
                 GuaranteedFrame::Crypto(f) => self.outgoing.may_loss_data(&f),
             }
+            retransmitted = true;
         }
+        retransmitted
+    }
+}
+
+impl MayLoss for DataMayLoss {
+    fn may_loss(&self, pn: u64) {
+        let retransmitted = self.retransmit(pn);
+        self.counters.on_packet_lost(Epoch::Data, retransmitted);
     }
 }
 
 #[derive(Clone)]
 pub struct ClosingOneRttScope {
     keys: (HeaderProtectionKeys, ArcOneRttPacketKeys),
-    rcvd_pkt_records: ArcRcvdPktRecords,
-    // 发包时用得着
-    next_sending_pn: (u64, PacketNumber),
+    spaces: DataSpaces,
+    // 发包时用得着：所在的空间，空间0以外的还有该空间对应的对端连接ID，以及包号
+    next_sending_pn: (u64, Option<ConnectionId>, (u64, PacketNumber)),
 }
 
 impl ClosingOneRttScope {
@@ -459,6 +784,9 @@ impl ClosingOneRttScope {
     ) -> usize {
         let (hpk, pk) = &self.keys;
         let hpk = &hpk.local;
+        // 多路径下，包号所在空间的连接ID与该空间一一对应，不能使用别的连接ID
+        let (space_id, space_dcid, (pn, encoded_pn)) = self.next_sending_pn;
+        let dcid = space_dcid.unwrap_or(dcid);
 
         let spin = Default::default();
         let hdr = OneRttHeader { spin, dcid };
@@ -467,7 +795,6 @@ impl ClosingOneRttScope {
         let tag_len = pk.tag_len();
         let payload_buf = &mut payload_tag[..payload_tag_len - tag_len];
 
-        let (pn, encoded_pn) = self.next_sending_pn;
         let (mut pn_buf, mut body_buf) = payload_buf.split_at_mut(encoded_pn.size());
 
         let body_size = body_buf.remaining_mut();
@@ -489,7 +816,13 @@ impl ClosingOneRttScope {
 
         let (key_phase, pk) = pk.lock_guard().get_local();
         encode_short_first_byte(&mut buf[0], pn_len, key_phase);
-        encrypt_packet(pk.as_ref(), pn, &mut buf[..sent_size], hdr_len + pn_len);
+        encrypt_packet_for_path(
+            pk.as_ref(),
+            space_id as u32,
+            pn,
+            &mut buf[..sent_size],
+            hdr_len + pn_len,
+        );
         protect_header(hpk.as_ref(), &mut buf[..sent_size], hdr_len, pn_len);
 
         sent_size
@@ -503,12 +836,17 @@ impl TryFrom<DataScope> for ClosingOneRttScope {
         let Some(keys) = data.one_rtt_keys.invalid() else {
             return Err(());
         };
-        let rcvd_pkt_records = data.space.rcvd_packets();
-        let next_sending_pn = data.space.sent_packets().send().next_pn();
+        // 多路径下，在最近使用的空间中发送CONNECTION_CLOSE帧
+        let next_sending_pn = match data.spaces.latest_sending() {
+            Some((id, dcid, sent_pkt_records)) => {
+                (id, Some(dcid), sent_pkt_records.send().next_pn())
+            }
+            None => (0, None, data.space.sent_packets().send().next_pn()),
+        };
 
         Ok(Self {
             keys,
-            rcvd_pkt_records,
+            spaces: data.spaces,
             next_sending_pn,
         })
     }
@@ -516,22 +854,237 @@ impl TryFrom<DataScope> for ClosingOneRttScope {
 
 impl super::RecvPacket for ClosingOneRttScope {
     fn has_rcvd_ccf(&self, mut packet: DataPacket) -> bool {
-        let (undecoded_pn, key_phase) = match remove_protection_of_short_packet(
+        let (space_id, rcvd_pkt_records) = self.spaces.receiving_of(packet.header.get_dcid());
+        let Ok(path_id) = u32::try_from(space_id) else {
+            return false;
+        };
+        let Some((undecoded_pn, key_phase)) = remove_protection_of_short_packet(
             self.keys.0.remote.as_ref(),
             packet.bytes.as_mut(),
             packet.offset,
-        ) {
-            Ok(Some(pn)) => pn,
-            _ => return false,
+        ) else {
+            return false;
         };
 
-        let pn = match self.rcvd_pkt_records.decode_pn(undecoded_pn) {
+        let pn = match rcvd_pkt_records.decode_pn(undecoded_pn) {
             Ok(pn) => pn,
             // TooOld/TooLarge/HasRcvd
             Err(_e) => return false,
         };
         let body_offset = packet.offset + undecoded_pn.size();
-        let pk = self.keys.1.lock_guard().get_remote(key_phase, pn);
-        Self::decrypt_and_parse(pk.as_ref(), pn, packet, body_offset)
+        let pk = match space_id {
+            0 => self.keys.1.lock_guard().get_remote(key_phase, pn),
+            _ => match self.keys.1.lock_guard().get_remote_of_phase(key_phase) {
+                Some(pk) => pk,
+                None => return false,
+            },
+        };
+        Self::decrypt_and_parse_for_path(pk.as_ref(), path_id, pn, packet, body_offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use qbase::{
+        flow::ArcRecvController,
+        frame::{HandshakeDoneFrame, MaxDataFrame},
+        param::Parameters,
+        sid::{handy::DemandConcurrency, Role},
+        varint::VarInt,
+    };
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+    use crate::{
+        clock::TokioClock,
+        conn::stats::ConnectionStats,
+        path::{ArcPathEvents, SchedulerHandle},
+    };
+
+    #[tokio::test]
+    async fn test_0rtt_rejected() {
+        let reliable_frames = ArcReliableFrameDeque::with_capacity(0);
+        let streams = DataStreams::new(
+            Role::Client,
+            &Parameters::default(),
+            Box::new(DemandConcurrency),
+            reliable_frames.clone(),
+            ArcRecvController::default(),
+        );
+        let data = DataScope::default();
+
+        let (_sid, mut writer) = streams.open_uni(1024).await.unwrap().unwrap();
+        writer.write_all(b"early data").await.unwrap();
+
+        // 模拟在0Rtt数据包中发送流数据
+        let mut buf = [0u8; 1200];
+        let (frame, _, fresh_bytes) = streams.try_read_data(&mut buf, usize::MAX).unwrap();
+        assert_eq!(fresh_bytes, 10);
+        {
+            let sent_packets = data.space.sent_packets();
+            let mut send_guard = sent_packets.send();
+            let (pn, _) = send_guard.next_pn();
+            send_guard.record_frame(GuaranteedFrame::Stream(frame));
+            data.sent_0rtt_pkts.lock().unwrap().push(pn);
+        }
+        assert!(streams.try_read_data(&mut buf, usize::MAX).is_none());
+
+        let counters = ArcPacketCounters::default();
+        data.on_0rtt_rejected(&reliable_frames, &streams, &counters);
+        assert!(data.zero_rtt_keys.get_local_keys().is_none());
+        assert!(data.sent_0rtt_pkts.lock().unwrap().is_empty());
+        // 0Rtt中的数据在1Rtt中重传，不再计入连接级流量控制
+        let (frame, _, fresh_bytes) = streams.try_read_data(&mut buf, usize::MAX).unwrap();
+        assert_eq!(frame.range(), 0..10);
+        assert_eq!(fresh_bytes, 0);
+        // 重复调用没有影响
+        data.on_0rtt_rejected(&reliable_frames, &streams, &counters);
+        assert!(streams.try_read_data(&mut buf, usize::MAX).is_none());
+        let mut stats = ConnectionStats::default();
+        counters.load_into(&mut stats);
+        assert_eq!(stats.data.packets_lost, 1);
+        assert_eq!(stats.retransmissions, 1);
+
+        writer.cancel(0);
+    }
+
+    #[test]
+    fn test_0rtt_accepted() {
+        let data = DataScope::default();
+        data.sent_0rtt_pkts.lock().unwrap().extend([0, 1, 2]);
+
+        data.on_0rtt_accepted();
+        assert!(data.zero_rtt_keys.get_local_keys().is_none());
+        assert!(data.sent_0rtt_pkts.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_lost_frames_retransmitted() {
+        let reliable_frames = ArcReliableFrameDeque::with_capacity(0);
+        let streams = DataStreams::new(
+            Role::Client,
+            &Parameters::default(),
+            Box::new(DemandConcurrency),
+            reliable_frames.clone(),
+            ArcRecvController::default(),
+        );
+        let data = DataScope::default();
+        let counters = ArcPacketCounters::default();
+        let may_loss = DataMayLoss::new(
+            data.spaces.path_spaces(),
+            reliable_frames.clone(),
+            streams.clone(),
+            data.crypto_stream.outgoing(),
+            counters.clone(),
+        );
+
+        let (_sid, mut writer) = streams.open_uni(1024).await.unwrap().unwrap();
+        writer.write_all(b"hello world").await.unwrap();
+
+        // 包中携带STREAM帧和MAX_DATA帧，ACK帧不会被记录
+        let mut buf = [0u8; 1200];
+        let (frame, _, _) = streams.try_read_data(&mut buf, usize::MAX).unwrap();
+        let max_data = |n: u32| MaxDataFrame {
+            max_data: VarInt::from_u32(n),
+        };
+        let lost_pn = {
+            let sent_packets = data.space.sent_packets();
+            let mut send_guard = sent_packets.send();
+            let (pn, _) = send_guard.next_pn();
+            send_guard.record_frame(GuaranteedFrame::Stream(frame));
+            send_guard.record_frame(GuaranteedFrame::Reliable(max_data(100).into()));
+            pn
+        };
+        assert!(streams.try_read_data(&mut buf, usize::MAX).is_none());
+        // 丢包前已经发出了更新的MAX_DATA，尚未被读取
+        reliable_frames.send_frame([max_data(200)]);
+
+        may_loss.may_loss(lost_pn);
+        // 丢失的流数据出现在之后的包中
+        let (frame, _, fresh_bytes) = streams.try_read_data(&mut buf, usize::MAX).unwrap();
+        assert_eq!(frame.range(), 0..11);
+        assert_eq!(fresh_bytes, 0);
+        // 只重传最新的MAX_DATA
+        let (frame, _) = reliable_frames.try_read(&mut buf).unwrap();
+        assert_eq!(frame, ReliableFrame::MaxData(max_data(200)));
+        assert!(reliable_frames.try_read(&mut buf).is_none());
+
+        let mut stats = ConnectionStats::default();
+        counters.load_into(&mut stats);
+        assert_eq!(stats.data.packets_lost, 1);
+        assert_eq!(stats.retransmissions, 1);
+
+        writer.cancel(0);
+    }
+
+    #[tokio::test]
+    async fn test_handshake_done_retransmitted() {
+        let reliable_frames = ArcReliableFrameDeque::with_capacity(0);
+        let streams = DataStreams::new(
+            Role::Server,
+            &Parameters::default(),
+            Box::new(DemandConcurrency),
+            reliable_frames.clone(),
+            ArcRecvController::default(),
+        );
+        let data = DataScope::default();
+        let may_loss = DataMayLoss::new(
+            data.spaces.path_spaces(),
+            reliable_frames.clone(),
+            streams.clone(),
+            data.crypto_stream.outgoing(),
+            ArcPacketCounters::default(),
+        );
+        let server = Handshake::new_server(reliable_frames.clone());
+        let client = Handshake::<ArcReliableFrameDeque>::new_client();
+        let pathes = ArcPathes::new(
+            Box::new(|_, _| unreachable!("no path is created")),
+            Arc::new(|| {}),
+            &SchedulerHandle::default(),
+            ArcPathEvents::default(),
+            Arc::new(TokioClock),
+        );
+        let on_data_acked = data.data_acked_handler(&streams, &pathes, &ArcAckFrequency::default());
+
+        // 模拟在1Rtt数据包中发送HANDSHAKE_DONE帧
+        let send_handshake_done = || {
+            let mut buf = [0u8; 1200];
+            let (frame, _) = reliable_frames.try_read(&mut buf).unwrap();
+            assert_eq!(frame, ReliableFrame::HandshakeDone(HandshakeDoneFrame));
+            assert!(reliable_frames.try_read(&mut buf).is_none());
+            let sent_packets = data.space.sent_packets();
+            let mut send_guard = sent_packets.send();
+            let (pn, _) = send_guard.next_pn();
+            send_guard.record_frame(GuaranteedFrame::Reliable(frame));
+            pn
+        };
+
+        let Handshake::Server(server_handshake) = &server else {
+            unreachable!()
+        };
+        server_handshake.done();
+        let lost_pn = send_handshake_done();
+
+        // 第一个携带HANDSHAKE_DONE帧的包丢失，帧被重传
+        may_loss.may_loss(lost_pn);
+        let pn = send_handshake_done();
+        assert_ne!(pn, lost_pn);
+
+        // 客户端收到重传的HANDSHAKE_DONE帧后确认握手，该包被确认后不再重传
+        client.recv_frame(&HandshakeDoneFrame).unwrap();
+        assert!(client.is_handshake_done());
+        client.confirmed().await;
+        on_data_acked(&(
+            0,
+            AckFrame {
+                largest: VarInt::from_u32(pn as u32),
+                delay: VarInt::from_u32(0),
+                first_range: VarInt::from_u32(0),
+                ranges: vec![],
+                ecn: None,
+            },
+        ));
+        may_loss.may_loss(pn);
+        assert!(reliable_frames.try_read(&mut [0u8; 1200]).is_none());
     }
 }
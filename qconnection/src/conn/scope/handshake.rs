@@ -8,6 +8,7 @@ use qbase::{
     packet::{
         decrypt::{decrypt_packet, remove_protection_of_long_packet},
         encrypt::{encode_long_first_byte, encrypt_packet, protect_header},
+        error::Error as PacketError,
         header::{
             long::io::{LongHeaderBuilder, WriteLongHeader},
             EncodeHeader, GetType,
@@ -16,6 +17,7 @@ use qbase::{
         number::WritePacketNumber,
         DataPacket, PacketNumber,
     },
+    qlog::PacketType,
     varint::{EncodeBytes, VarInt, WriteVarInt},
 };
 use qcongestion::{CongestionControl, MayLoss, RetirePktRecord, MSS};
@@ -24,12 +26,16 @@ use qrecovery::{
     reliable::ArcRcvdPktRecords,
     space::{Epoch, HandshakeSpace},
 };
-use tokio::{sync::Notify, task::JoinHandle};
+use tokio::task::JoinHandle;
 
-use super::any;
+use super::{any, stop_receiving, RecvContext};
 use crate::{
-    conn::{transmit::handshake::HandshakeSpaceReader, RcvdPackets},
-    error::ConnError,
+    conn::{
+        stats::ArcPacketCounters,
+        transmit::handshake::HandshakeSpaceReader,
+        version::{ArcVersions, QUIC_VERSION_1},
+        RcvdPackets,
+    },
     path::{ArcPathes, Path},
     pipe,
 };
@@ -46,27 +52,32 @@ impl Default for HandshakeScope {
         Self {
             keys: ArcKeys::new_pending(),
             space: HandshakeSpace::with_capacity(16),
-            crypto_stream: CryptoStream::new(4096, 4096),
+            crypto_stream: CryptoStream::new(4096, 16384),
         }
     }
 }
 
 impl HandshakeScope {
+    /// Start the task to receive the Handshake packets.
+    ///
+    /// `on_rcvd` is called with the path once a Handshake packet is processed successfully, the
+    /// server discards the Initial keys then.
+    ///
+    /// Once the handshake is confirmed, the Handshake keys are discarded, see [`Self::discard`].
     pub fn build(
         &self,
         rcvd_packets: RcvdPackets,
-        pathes: &ArcPathes,
-        notify: &Arc<Notify>,
-        conn_error: &ConnError,
+        ctx: &RecvContext,
+        on_rcvd: impl Fn(&Path) + Send + 'static,
     ) -> JoinHandle<RcvdPackets> {
         let (crypto_frames_entry, rcvd_crypto_frames) = mpsc::unbounded();
         let (ack_frames_entry, rcvd_ack_frames) = mpsc::unbounded();
 
         let dispatch_frame = {
-            let conn_error = conn_error.clone();
+            let conn_error = ctx.conn_error.clone();
             move |frame: Frame, path: &Path| match frame {
                 Frame::Ack(f) => {
-                    path.cc.on_ack(Epoch::Initial, &f);
+                    path.cc.on_ack(Epoch::Handshake, &f);
                     _ = ack_frames_entry.unbounded_send(f);
                 }
                 Frame::Close(f) => conn_error.on_ccf_rcvd(&f),
@@ -90,49 +101,76 @@ impl HandshakeScope {
             }
         };
 
+        let conn_error = &ctx.conn_error;
         pipe!(@error(conn_error) rcvd_crypto_frames |> self.crypto_stream.incoming(), recv_frame);
         pipe!(rcvd_ack_frames |> on_data_acked);
-        self.parse_rcvd_packets_and_dispatch_frames(
-            rcvd_packets,
-            pathes,
-            dispatch_frame,
-            notify,
-            conn_error,
-        )
+
+        tokio::spawn({
+            let scope = self.clone();
+            let pathes = ctx.pathes.clone();
+            let handshake = ctx.handshake.clone();
+            let notify = ctx.notify.clone();
+            async move {
+                if any(handshake.confirmed(), &notify).await.is_some() {
+                    scope.discard(&pathes);
+                }
+            }
+        });
+
+        self.parse_rcvd_packets_and_dispatch_frames(rcvd_packets, ctx, dispatch_frame, on_rcvd)
+    }
+
+    /// Discard the Handshake keys once the handshake is confirmed, along with the loss detection
+    /// and acknowledgment state of the Handshake space on all the pathes.
+    ///
+    /// The Handshake packets are neither sent nor received anymore, even if some of the sent
+    /// Handshake packets are not acknowledged, see
+    /// [section 4.9.2](https://www.rfc-editor.org/rfc/rfc9001.html#section-4.9.2) of RFC 9001.
+    pub fn discard(&self, pathes: &ArcPathes) {
+        if self.keys.invalid().is_none() {
+            return;
+        }
+        for path in pathes.iter() {
+            path.cc.discard_epoch(Epoch::Handshake);
+            path.cc.on_handshake_done();
+        }
     }
 
     fn parse_rcvd_packets_and_dispatch_frames(
         &self,
         mut rcvd_packets: RcvdPackets,
-        pathes: &ArcPathes,
+        ctx: &RecvContext,
         dispatch_frame: impl Fn(Frame, &Path) + Send + 'static,
-        notify: &Arc<Notify>,
-        conn_error: &ConnError,
+        on_rcvd: impl Fn(&Path) + Send + 'static,
     ) -> JoinHandle<RcvdPackets> {
-        let pathes = pathes.clone();
-        let conn_error = conn_error.clone();
-        let notify = notify.clone();
+        let RecvContext {
+            pathes,
+            handshake,
+            idle_timer,
+            notify,
+            conn_error,
+        } = ctx.clone();
         tokio::spawn({
             let rcvd_pkt_records = self.space.rcvd_packets();
             let keys = self.keys.clone();
             async move {
-                while let Some((mut packet, pathway, usc)) = any(rcvd_packets.next(), &notify).await
+                while let Some((mut packet, ecn, pathway, usc)) =
+                    any(rcvd_packets.next(), &notify).await
                 {
                     let pty = packet.header.get_type();
                     let Some(keys) = any(keys.get_remote_keys(), &notify).await else {
+                        // 否则连接正在关闭，通道仍用于接收CONNECTION_CLOSE帧
+                        if handshake.is_handshake_done() {
+                            stop_receiving(&mut rcvd_packets);
+                        }
                         break;
                     };
-                    let undecoded_pn = match remove_protection_of_long_packet(
+                    let Some(undecoded_pn) = remove_protection_of_long_packet(
                         keys.remote.header.as_ref(),
                         packet.bytes.as_mut(),
                         packet.offset,
-                    ) {
-                        Ok(Some(pn)) => pn,
-                        Ok(None) => continue,
-                        Err(invalid_reserved_bits) => {
-                            conn_error.on_error(invalid_reserved_bits.into());
-                            break;
-                        }
+                    ) else {
+                        continue;
                     };
 
                     let pn = match rcvd_pkt_records.decode_pn(undecoded_pn) {
@@ -147,10 +185,19 @@ impl HandshakeScope {
                         packet.bytes.as_mut(),
                         body_offset,
                     );
-                    let Ok(pkt_len) = decrypted else { continue };
+                    let pkt_len = match decrypted {
+                        Ok(pkt_len) => pkt_len,
+                        Err(invalid_reserved_bits @ PacketError::InvalidReservedBits(..)) => {
+                            conn_error.on_error(invalid_reserved_bits.into());
+                            break;
+                        }
+                        Err(_) => continue,
+                    };
 
                     let path = pathes.get_or_create(pathway, usc);
                     path.on_rcvd(packet.bytes.len());
+                    path.on_packet_rcvd(PacketType::Handshake, pn, packet.bytes.len());
+                    idle_timer.on_rcvd();
 
                     let _header = packet.bytes.split_to(body_offset);
                     packet.bytes.truncate(pkt_len);
@@ -171,7 +218,9 @@ impl HandshakeScope {
                     ) {
                         Ok(is_ack_packet) => {
                             rcvd_pkt_records.register_pn(pn);
+                            rcvd_pkt_records.register_ecn(ecn);
                             path.cc.on_pkt_rcvd(Epoch::Handshake, pn, is_ack_packet);
+                            on_rcvd(&path);
                         }
                         Err(e) => conn_error.on_error(e),
                     }
@@ -181,11 +230,12 @@ impl HandshakeScope {
         })
     }
 
-    pub fn reader(&self) -> HandshakeSpaceReader {
+    pub fn reader(&self, versions: ArcVersions) -> HandshakeSpaceReader {
         HandshakeSpaceReader {
             keys: self.keys.clone(),
             space: self.space.clone(),
             crypto_stream_outgoing: self.crypto_stream.outgoing(),
+            versions,
         }
     }
 }
@@ -196,9 +246,16 @@ pub struct ClosingHandshakeScope {
     rcvd_pkt_records: ArcRcvdPktRecords,
     // 发包时用得着
     next_sending_pn: (u64, PacketNumber),
+    version: u32,
 }
 
 impl ClosingHandshakeScope {
+    /// Send the Handshake packets with the QUIC `version` in use, QUIC version 1 by default.
+    pub fn with_version(mut self, version: u32) -> Self {
+        self.version = version;
+        self
+    }
+
     pub fn assemble_ccf_packet(
         &self,
         buf: &mut [u8; MSS],
@@ -211,7 +268,9 @@ impl ClosingHandshakeScope {
             self.keys.local.header.as_ref(),
         );
 
-        let hdr = LongHeaderBuilder::with_cid(dcid, scid).handshake();
+        let hdr = LongHeaderBuilder::with_cid(dcid, scid)
+            .with_version(self.version)
+            .handshake();
         let (mut hdr_buf, payload_tag) = buf.split_at_mut(hdr.size() + 2);
         let payload_tag_len = payload_tag.len();
         let tag_len = pk.tag_len();
@@ -262,19 +321,19 @@ impl TryFrom<HandshakeScope> for ClosingHandshakeScope {
             keys,
             rcvd_pkt_records,
             next_sending_pn,
+            version: QUIC_VERSION_1,
         })
     }
 }
 
 impl super::RecvPacket for ClosingHandshakeScope {
     fn has_rcvd_ccf(&self, mut packet: DataPacket) -> bool {
-        let undecoded_pn = match remove_protection_of_long_packet(
+        let Some(undecoded_pn) = remove_protection_of_long_packet(
             self.keys.remote.header.as_ref(),
             packet.bytes.as_mut(),
             packet.offset,
-        ) {
-            Ok(Some(pn)) => pn,
-            _ => return false,
+        ) else {
+            return false;
         };
 
         let pn = match self.rcvd_pkt_records.decode_pn(undecoded_pn) {
@@ -291,19 +350,32 @@ impl super::RecvPacket for ClosingHandshakeScope {
 pub struct HandshakeMayloss {
     space: HandshakeSpace,
     outgoing: CryptoStreamOutgoing,
+    counters: ArcPacketCounters,
 }
 
 impl HandshakeMayloss {
-    pub fn new(space: HandshakeSpace, outgoing: CryptoStreamOutgoing) -> Self {
-        Self { space, outgoing }
+    pub fn new(
+        space: HandshakeSpace,
+        outgoing: CryptoStreamOutgoing,
+        counters: ArcPacketCounters,
+    ) -> Self {
+        Self {
+            space,
+            outgoing,
+            counters,
+        }
     }
 }
 
 impl MayLoss for HandshakeMayloss {
     fn may_loss(&self, pn: u64) {
+        let mut retransmitted = false;
         for frame in self.space.sent_packets().recv().may_loss_pkt(pn) {
             self.outgoing.may_loss_data(&frame);
+            retransmitted = true;
         }
+        self.counters
+            .on_packet_lost(Epoch::Handshake, retransmitted);
     }
 }
 
@@ -5,23 +5,27 @@ use qbase::{
     frame::{AckFrame, Frame, FrameReader, ReceiveFrame},
     packet::{
         decrypt::{decrypt_packet, remove_protection_of_long_packet},
+        error::Error as PacketError,
         header::{GetScid, GetType},
         keys::ArcKeys,
         long, DataHeader,
     },
+    qlog::PacketType,
 };
 use qcongestion::{CongestionControl, MayLoss, RetirePktRecord};
 use qrecovery::{
     crypto::{CryptoStream, CryptoStreamOutgoing},
     space::{Epoch, InitialSpace},
 };
-use tokio::{sync::Notify, task::JoinHandle};
+use tokio::task::JoinHandle;
 
-use super::any;
+use super::{any, stop_receiving, RecvContext};
 use crate::{
-    conn::{transmit::initial::InitialSpaceReader, ArcRemoteCids, RcvdPackets},
-    error::ConnError,
-    path::{ArcPath, ArcPathes, Path},
+    conn::{
+        stats::ArcPacketCounters, transmit::initial::InitialSpaceReader, version::ArcVersions,
+        ArcRemoteCids, RcvdPackets,
+    },
+    path::{ArcPath, Path},
     pipe,
 };
 
@@ -36,7 +40,7 @@ impl InitialScope {
     // Initial keys应该是预先知道的，或者传入dcid，可以构造出来
     pub fn new(keys: ArcKeys) -> Self {
         let space = InitialSpace::with_capacity(16);
-        let crypto_stream = CryptoStream::new(4096, 4096);
+        let crypto_stream = CryptoStream::new(4096, 16384);
 
         Self {
             keys,
@@ -48,10 +52,8 @@ impl InitialScope {
     pub fn build(
         &self,
         rcvd_packets: RcvdPackets,
-        pathes: &ArcPathes,
+        ctx: &RecvContext,
         remote_cids: &ArcRemoteCids,
-        notify: &Arc<Notify>,
-        conn_error: &ConnError,
         validate: impl Fn(&[u8], ArcPath) + Send + 'static,
     ) -> JoinHandle<RcvdPackets> {
         let (crypto_frames_entry, rcvd_crypto_frames) = mpsc::unbounded();
@@ -84,57 +86,53 @@ impl InitialScope {
             }
         };
 
+        let conn_error = &ctx.conn_error;
         pipe!(@error(conn_error) rcvd_crypto_frames |> self.crypto_stream.incoming(), recv_frame);
         pipe!(rcvd_ack_frames |> on_data_acked);
 
         self.parse_rcvd_packets_and_dispatch_frames(
             rcvd_packets,
-            pathes,
+            ctx,
             remote_cids,
             dispatch_frame,
-            notify,
-            conn_error,
             validate,
         )
     }
 
-    #[allow(clippy::too_many_arguments)]
     fn parse_rcvd_packets_and_dispatch_frames(
         &self,
         mut rcvd_packets: RcvdPackets,
-        pathes: &ArcPathes,
+        ctx: &RecvContext,
         remote_cids: &ArcRemoteCids,
         dispatch_frame: impl Fn(Frame, &Path) + Send + 'static,
-        notify: &Arc<Notify>,
-        conn_error: &ConnError,
         validate: impl Fn(&[u8], ArcPath) + Send + 'static,
     ) -> JoinHandle<RcvdPackets> {
-        let pathes = pathes.clone();
-        let conn_error = conn_error.clone();
+        let RecvContext {
+            pathes,
+            notify,
+            conn_error,
+            ..
+        } = ctx.clone();
         tokio::spawn({
             let rcvd_pkt_records = self.space.rcvd_packets();
             let keys = self.keys.clone();
             let remote_cids = remote_cids.clone();
-            let notify = notify.clone();
 
             async move {
-                while let Some((mut packet, pathway, usc)) = any(rcvd_packets.next(), &notify).await
+                while let Some((mut packet, ecn, pathway, usc)) =
+                    any(rcvd_packets.next(), &notify).await
                 {
                     let pty = packet.header.get_type();
                     let Some(keys) = any(keys.get_remote_keys(), &notify).await else {
+                        stop_receiving(&mut rcvd_packets);
                         break;
                     };
-                    let undecoded_pn = match remove_protection_of_long_packet(
+                    let Some(undecoded_pn) = remove_protection_of_long_packet(
                         keys.remote.header.as_ref(),
                         packet.bytes.as_mut(),
                         packet.offset,
-                    ) {
-                        Ok(Some(pn)) => pn,
-                        Ok(None) => continue,
-                        Err(invalid_reserved_bits) => {
-                            conn_error.on_error(invalid_reserved_bits.into());
-                            break;
-                        }
+                    ) else {
+                        continue;
                     };
 
                     let pn = match rcvd_pkt_records.decode_pn(undecoded_pn) {
@@ -149,10 +147,18 @@ impl InitialScope {
                         packet.bytes.as_mut(),
                         body_offset,
                     );
-                    let Ok(pkt_len) = decrypted else { continue };
+                    let pkt_len = match decrypted {
+                        Ok(pkt_len) => pkt_len,
+                        Err(invalid_reserved_bits @ PacketError::InvalidReservedBits(..)) => {
+                            conn_error.on_error(invalid_reserved_bits.into());
+                            break;
+                        }
+                        Err(_) => continue,
+                    };
 
                     let path = pathes.get_or_create(pathway, usc);
                     path.on_rcvd(packet.bytes.len());
+                    path.on_packet_rcvd(PacketType::Initial, pn, packet.bytes.len());
 
                     let _header = packet.bytes.split_to(body_offset);
                     packet.bytes.truncate(pkt_len);
@@ -175,6 +181,7 @@ impl InitialScope {
                     ) {
                         Ok(is_ack_packet) => {
                             rcvd_pkt_records.register_pn(pn);
+                            rcvd_pkt_records.register_ecn(ecn);
                             path.cc.on_pkt_rcvd(Epoch::Initial, pn, is_ack_packet);
                         }
                         Err(e) => {
@@ -198,12 +205,13 @@ impl InitialScope {
         })
     }
 
-    pub fn reader(&self, token: Arc<Mutex<Vec<u8>>>) -> InitialSpaceReader {
+    pub fn reader(&self, token: Arc<Mutex<Vec<u8>>>, versions: ArcVersions) -> InitialSpaceReader {
         InitialSpaceReader {
             token,
             keys: self.keys.clone(),
             space: self.space.clone(),
             crypto_stream_outgoing: self.crypto_stream.outgoing(),
+            versions,
         }
     }
 }
@@ -212,19 +220,31 @@ impl InitialScope {
 pub struct InitialMayLoss {
     pub space: InitialSpace,
     pub outgoing: CryptoStreamOutgoing,
+    pub counters: ArcPacketCounters,
 }
 
 impl InitialMayLoss {
-    pub fn new(space: InitialSpace, outgoing: CryptoStreamOutgoing) -> Self {
-        Self { space, outgoing }
+    pub fn new(
+        space: InitialSpace,
+        outgoing: CryptoStreamOutgoing,
+        counters: ArcPacketCounters,
+    ) -> Self {
+        Self {
+            space,
+            outgoing,
+            counters,
+        }
     }
 }
 
 impl MayLoss for InitialMayLoss {
     fn may_loss(&self, pn: u64) {
+        let mut retransmitted = false;
         for frame in self.space.sent_packets().recv().may_loss_pkt(pn) {
             self.outgoing.may_loss_data(&frame);
+            retransmitted = true;
         }
+        self.counters.on_packet_lost(Epoch::Initial, retransmitted);
     }
 }
 
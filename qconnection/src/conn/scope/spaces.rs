@@ -0,0 +1,282 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use qbase::cid::ConnectionId;
+use qcongestion::RetirePktRecord;
+use qrecovery::{
+    reliable::{ArcRcvdPktRecords, ArcSentPktRecords, GuaranteedFrame},
+    space::DataSpace,
+};
+
+type SentPktRecords = ArcSentPktRecords<GuaranteedFrame>;
+
+/// The packet number spaces of the data packets.
+///
+/// Without the multipath extension, all the paths share the packet number space 0, the one of
+/// the [`DataSpace`], which the 0-RTT packets are sent in as well.
+///
+/// With the multipath extension, each path has its own packet number spaces. The packets sent to
+/// a connection ID of the peer are numbered in the space identified by the sequence number of the
+/// connection ID, and the packets received with a connection ID of the local endpoint are
+/// recorded in the space identified by the sequence number of that connection ID. The spaces
+/// other than 0 are created when they are used for the first time, a sending space is removed
+/// when the path sending in it switches to another connection ID or ends, and a receiving space
+/// is removed when its connection ID is retired.
+#[derive(Clone)]
+pub struct DataSpaces {
+    base: DataSpace,
+    others: Arc<Mutex<OtherSpaces>>,
+}
+
+#[derive(Default)]
+struct OtherSpaces {
+    // 以对端连接ID的序号标识，记下连接ID，关闭连接时要用它发送CONNECTION_CLOSE帧
+    sending: HashMap<u64, (ConnectionId, SentPktRecords)>,
+    // 以本端连接ID的序号标识，记下连接ID，关闭连接时据此找到收包的空间
+    receiving: HashMap<u64, (ConnectionId, ArcRcvdPktRecords)>,
+    ack_delay_exponent: Option<u8>,
+}
+
+impl DataSpaces {
+    /// Create the packet number spaces, of which the space 0 is the `base` one.
+    pub fn new(base: DataSpace) -> Self {
+        Self {
+            base,
+            others: Arc::default(),
+        }
+    }
+
+    /// Set the `ack_delay_exponent` the ACK Delay of the ACK frames sent in all the receiving
+    /// spaces is encoded with, including the ones created later.
+    pub fn set_ack_delay_exponent(&self, exponent: u8) {
+        self.base.rcvd_packets().set_ack_delay_exponent(exponent);
+        let mut others = self.others.lock().unwrap();
+        others.ack_delay_exponent = Some(exponent);
+        for (_, rcvd_pkt_records) in others.receiving.values() {
+            rcvd_pkt_records.set_ack_delay_exponent(exponent);
+        }
+    }
+
+    /// Returns the records of the packets sent in the space `id`, or `None` if there is no such
+    /// space, it has never been used or has been released.
+    pub fn sent_packets(&self, id: u64) -> Option<SentPktRecords> {
+        match id {
+            0 => Some(self.base.sent_packets()),
+            id => {
+                let others = self.others.lock().unwrap();
+                others.sending.get(&id).map(|(_, records)| records.clone())
+            }
+        }
+    }
+
+    /// Returns the records of the packets received in the space `id` with the connection ID
+    /// `cid`, the space is created if it does not exist.
+    pub fn rcvd_packets(&self, id: u64, cid: ConnectionId) -> ArcRcvdPktRecords {
+        if id == 0 {
+            return self.base.rcvd_packets();
+        }
+        let mut others = self.others.lock().unwrap();
+        let ack_delay_exponent = others.ack_delay_exponent;
+        let (_, records) = others.receiving.entry(id).or_insert_with(|| {
+            let records = ArcRcvdPktRecords::with_capacity(16);
+            if let Some(exponent) = ack_delay_exponent {
+                records.set_ack_delay_exponent(exponent);
+            }
+            (cid, records)
+        });
+        records.clone()
+    }
+
+    /// Remove the receiving space `id`, called when its connection ID is retired.
+    pub fn remove_receiving(&self, id: u64) {
+        if id != 0 {
+            self.others.lock().unwrap().receiving.remove(&id);
+        }
+    }
+
+    /// Returns the id and the records of the space the packets with the connection ID `cid` are
+    /// received in, the space 0 if it is not one of the others.
+    pub fn receiving_of(&self, cid: &ConnectionId) -> (u64, ArcRcvdPktRecords) {
+        let others = self.others.lock().unwrap();
+        others
+            .receiving
+            .iter()
+            .find(|(_, (space_cid, _))| space_cid == cid)
+            .map(|(id, (_, records))| (*id, records.clone()))
+            .unwrap_or_else(|| (0, self.base.rcvd_packets()))
+    }
+
+    /// Returns the latest sending space other than 0 still in use, along with the connection ID
+    /// of the peer the packets in it are sent to.
+    pub fn latest_sending(&self) -> Option<(u64, ConnectionId, SentPktRecords)> {
+        let others = self.others.lock().unwrap();
+        others
+            .sending
+            .iter()
+            .max_by_key(|(id, _)| **id)
+            .map(|(id, (cid, records))| (*id, *cid, records.clone()))
+    }
+
+    fn bind_sending(&self, id: u64, dcid: ConnectionId) -> SentPktRecords {
+        if id == 0 {
+            return self.base.sent_packets();
+        }
+        let mut others = self.others.lock().unwrap();
+        let (_, records) = others
+            .sending
+            .entry(id)
+            .or_insert_with(|| (dcid, ArcSentPktRecords::with_capacity(16)));
+        records.clone()
+    }
+
+    fn release_sending(&self, id: u64) {
+        if id != 0 {
+            self.others.lock().unwrap().sending.remove(&id);
+        }
+    }
+
+    /// Create the [`PathSpaces`] of a new path, which sends and receives in the space 0.
+    pub fn path_spaces(&self) -> PathSpaces {
+        PathSpaces {
+            state: Arc::new(Mutex::new(PathSpacesState {
+                sending: (0, self.base.sent_packets()),
+                receiving: (0, self.base.rcvd_packets()),
+            })),
+            spaces: self.clone(),
+        }
+    }
+}
+
+/// The packet number spaces a path currently sends and receives the 1-RTT packets in, read
+/// [`DataSpaces`].
+///
+/// The path sends in the space of the connection ID of the peer it uses, it switches to another
+/// space when it switches to another connection ID. The packets in flight in the previous space
+/// can not be acknowledged anymore, they should be declared lost by the congestion controller
+/// before switching, so that their frames are retransmitted.
+///
+/// The path receives in the space of the connection ID of the latest packet received on it, the
+/// acknowledgments the path sends are for this space.
+#[derive(Clone)]
+pub struct PathSpaces {
+    spaces: DataSpaces,
+    state: Arc<Mutex<PathSpacesState>>,
+}
+
+struct PathSpacesState {
+    sending: (u64, SentPktRecords),
+    receiving: (u64, ArcRcvdPktRecords),
+}
+
+impl PathSpaces {
+    /// Returns the id and the records of the space the path sends in.
+    pub fn sending(&self) -> (u64, SentPktRecords) {
+        self.state.lock().unwrap().sending.clone()
+    }
+
+    /// Returns the id of the space the path sends in.
+    pub fn sending_id(&self) -> u64 {
+        self.state.lock().unwrap().sending.0
+    }
+
+    /// Returns the id and the records of the space the path receives in.
+    pub fn receiving(&self) -> (u64, ArcRcvdPktRecords) {
+        self.state.lock().unwrap().receiving.clone()
+    }
+
+    /// Switch the path to send in the space `id`, with the connection ID `dcid` of the peer.
+    ///
+    /// The previous space is released if it is not the space 0, the packets sent in it must have
+    /// been handled as lost.
+    pub fn bind_sending(&self, id: u64, dcid: ConnectionId) -> SentPktRecords {
+        let records = self.spaces.bind_sending(id, dcid);
+        let previous = {
+            let mut state = self.state.lock().unwrap();
+            core::mem::replace(&mut state.sending, (id, records.clone()))
+        };
+        if previous.0 != id {
+            self.spaces.release_sending(previous.0);
+        }
+        records
+    }
+
+    /// Switch the path to receive in the space `id`, whose records are `rcvd_pkt_records`.
+    ///
+    /// Returns whether the space is changed, if so, the acknowledgment state of the congestion
+    /// controller of the path should be reset, for it is of the previous space.
+    pub fn bind_receiving(&self, id: u64, rcvd_pkt_records: ArcRcvdPktRecords) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.receiving.0 == id {
+            return false;
+        }
+        state.receiving = (id, rcvd_pkt_records);
+        true
+    }
+
+    /// Release the space the path sends in, called when the path ends.
+    ///
+    /// The packets sent in it must have been handled as lost.
+    pub fn release(&self) {
+        let id = self.sending_id();
+        self.spaces.release_sending(id);
+    }
+}
+
+impl RetirePktRecord for PathSpaces {
+    fn retire(&self, pn: u64) {
+        self.receiving().1.write().retire(pn);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bind_sending() {
+        let spaces = DataSpaces::new(DataSpace::with_capacity(16));
+        let path_spaces = spaces.path_spaces();
+        assert_eq!(path_spaces.sending_id(), 0);
+
+        let dcid1 = ConnectionId::from_slice(&[1; 8]);
+        let records = path_spaces.bind_sending(1, dcid1);
+        assert_eq!(records.send().next_pn().0, 0);
+        assert_eq!(path_spaces.sending_id(), 1);
+        let (id, cid, _) = spaces.latest_sending().unwrap();
+        assert_eq!((id, cid), (1, dcid1));
+
+        // 换用新的连接ID，包号从0开始，旧的空间被释放
+        let dcid2 = ConnectionId::from_slice(&[2; 8]);
+        let records = path_spaces.bind_sending(2, dcid2);
+        assert_eq!(records.send().next_pn().0, 0);
+        assert!(spaces.sent_packets(1).is_none());
+        assert!(spaces.sent_packets(2).is_some());
+
+        path_spaces.release();
+        assert!(spaces.sent_packets(2).is_none());
+        assert!(spaces.latest_sending().is_none());
+        // 空间0始终存在
+        assert!(spaces.sent_packets(0).is_some());
+    }
+
+    #[test]
+    fn test_bind_receiving() {
+        let spaces = DataSpaces::new(DataSpace::with_capacity(16));
+        spaces.set_ack_delay_exponent(5);
+        let path_spaces = spaces.path_spaces();
+
+        let cid = ConnectionId::from_slice(&[1; 8]);
+        let records = spaces.rcvd_packets(1, cid);
+        assert!(path_spaces.bind_receiving(1, records.clone()));
+        assert!(!path_spaces.bind_receiving(1, records));
+        assert_eq!(path_spaces.receiving().0, 1);
+        assert_eq!(spaces.receiving_of(&cid).0, 1);
+
+        spaces.remove_receiving(1);
+        let other = ConnectionId::from_slice(&[2; 8]);
+        assert_eq!(spaces.receiving_of(&cid).0, 0);
+        assert_eq!(spaces.receiving_of(&other).0, 0);
+    }
+}
@@ -0,0 +1,196 @@
+use std::sync::Arc;
+
+use thiserror::Error;
+use tokio::sync::watch;
+
+/// The lifecycle states of a QUIC connection.
+///
+/// ```text
+/// Initial ──> Handshaking ──> Connected
+///    │             │              │
+///    └─────────────┴──────┬───────┘
+///                         v
+///                      Closing ──> Draining ──> Closed
+/// ```
+///
+/// Any state other than [`ConnectionState::Closed`] can move to the closing, draining or closed
+/// state directly, for example, a connection is closed silently on idle timeout, or enters
+/// the draining state on receiving a CONNECTION_CLOSE frame.
+///
+/// See [connection lifecycle](https://www.rfc-editor.org/rfc/rfc9000.html#name-connections)
+/// and [connection termination](https://www.rfc-editor.org/rfc/rfc9000.html#name-connection-termination)
+/// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConnectionState {
+    /// The connection is created, no Handshake keys yet.
+    #[default]
+    Initial,
+    /// The Handshake keys are installed, the handshake is in progress.
+    Handshaking,
+    /// The handshake is completed, the connection is established.
+    Connected,
+    /// A CONNECTION_CLOSE frame has been sent, waiting for the closing period to end.
+    Closing,
+    /// A CONNECTION_CLOSE frame has been received, waiting for the draining period to end.
+    Draining,
+    /// All the resources of the connection have been released.
+    Closed,
+}
+
+impl ConnectionState {
+    /// Returns whether the connection can move from this state to the `to` state.
+    pub fn can_transition_to(self, to: ConnectionState) -> bool {
+        use ConnectionState::*;
+        match (self, to) {
+            (Initial, Handshaking) | (Handshaking, Connected) => true,
+            (Initial | Handshaking | Connected, Closing) => true,
+            (Initial | Handshaking | Connected | Closing, Draining) => true,
+            (Closed, _) => false,
+            (_, Closed) => true,
+            _ => false,
+        }
+    }
+}
+
+/// The error of an illegal transition of the [`ConnectionState`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("illegal connection state transition from {from:?} to {to:?}")]
+pub struct IllegalTransition {
+    pub from: ConnectionState,
+    pub to: ConnectionState,
+}
+
+/// The shared [`ConnectionState`] of a connection, which can be observed by subscribers.
+#[derive(Debug, Clone)]
+pub struct ArcConnectionState(Arc<watch::Sender<ConnectionState>>);
+
+impl Default for ArcConnectionState {
+    fn default() -> Self {
+        let (tx, _rx) = watch::channel(ConnectionState::default());
+        Self(Arc::new(tx))
+    }
+}
+
+impl ArcConnectionState {
+    /// Returns the current state of the connection.
+    pub fn current(&self) -> ConnectionState {
+        *self.0.borrow()
+    }
+
+    /// Move the connection to the `to` state, the subscribers will be notified.
+    ///
+    /// Return an [`IllegalTransition`] error if the transition is not allowed, read
+    /// [`ConnectionState::can_transition_to`], the state is not changed in this case.
+    pub fn transition(&self, to: ConnectionState) -> Result<(), IllegalTransition> {
+        let mut result = Ok(());
+        self.0.send_if_modified(|from| {
+            if from.can_transition_to(to) {
+                log::trace!("connection state transition from {from:?} to {to:?}");
+                *from = to;
+                true
+            } else {
+                result = Err(IllegalTransition { from: *from, to });
+                false
+            }
+        });
+        result
+    }
+
+    /// Returns a receiver to observe the state changes of the connection.
+    ///
+    /// For example, waiting for the connection to be established:
+    ///
+    /// ```rust, ignore
+    /// let mut state = connection.subscribe();
+    /// let established = state
+    ///     .wait_for(|state| *state >= ConnectionState::Connected)
+    ///     .await
+    ///     .is_ok_and(|state| *state == ConnectionState::Connected);
+    /// ```
+    pub fn subscribe(&self) -> watch::Receiver<ConnectionState> {
+        self.0.subscribe()
+    }
+
+    /// Wait for the handshake to be completed.
+    ///
+    /// Return `true` if the connection reaches the [`ConnectionState::Connected`] state, or
+    /// `false` if the connection is closing, draining or closed before that.
+    pub async fn connected(&self) -> bool {
+        let mut state = self.subscribe();
+        let reached = state
+            .wait_for(|state| *state >= ConnectionState::Connected)
+            .await
+            .map(|state| *state);
+        matches!(reached, Ok(ConnectionState::Connected))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConnectionState::*, *};
+
+    #[test]
+    fn test_lifecycle() {
+        let state = ArcConnectionState::default();
+        assert_eq!(state.current(), Initial);
+        assert!(state.transition(Connected).is_err());
+        assert!(state.transition(Handshaking).is_ok());
+        assert!(state.transition(Connected).is_ok());
+        assert!(state.transition(Closing).is_ok());
+        assert!(state.transition(Draining).is_ok());
+        assert_eq!(
+            state.transition(Connected),
+            Err(IllegalTransition {
+                from: Draining,
+                to: Connected
+            })
+        );
+        assert!(state.transition(Closing).is_err());
+        assert_eq!(state.current(), Draining);
+        assert!(state.transition(Closed).is_ok());
+        assert!(state.transition(Closed).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_subscribe() {
+        let state = ArcConnectionState::default();
+        let mut rx = state.subscribe();
+        let task = tokio::spawn(async move {
+            rx.wait_for(|state| *state >= Connected)
+                .await
+                .map(|state| *state)
+                .unwrap()
+        });
+        state.transition(Handshaking).unwrap();
+        state.transition(Connected).unwrap();
+        assert_eq!(task.await.unwrap(), Connected);
+    }
+
+    #[tokio::test]
+    async fn test_connected() {
+        let state = ArcConnectionState::default();
+        // 模拟握手过程
+        tokio::spawn({
+            let state = state.clone();
+            async move {
+                tokio::task::yield_now().await;
+                state.transition(Handshaking).unwrap();
+                tokio::task::yield_now().await;
+                state.transition(Connected).unwrap();
+            }
+        });
+        assert!(state.connected().await);
+        // 已经完成握手，立即返回
+        assert!(state.connected().await);
+
+        let state = ArcConnectionState::default();
+        tokio::spawn({
+            let state = state.clone();
+            async move {
+                state.transition(Handshaking).unwrap();
+                state.transition(Closing).unwrap();
+            }
+        });
+        assert!(!state.connected().await);
+    }
+}
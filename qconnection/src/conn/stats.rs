@@ -0,0 +1,165 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use qbase::qlog::PacketType;
+use qrecovery::space::Epoch;
+
+/// The statistics of the packets in a space, see [`ConnectionStats`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SpaceStats {
+    pub packets_sent: u64,
+    /// The size of the packets sent, including the header and the AEAD tag.
+    pub bytes_sent: u64,
+    pub packets_received: u64,
+    /// The size of the packets received and decrypted successfully.
+    pub bytes_received: u64,
+    pub packets_lost: u64,
+}
+
+/// A snapshot of the statistics of a connection.
+///
+/// The 0-RTT and 1-RTT packets are counted in the `data` space. The RTT and the congestion window
+/// are the ones of the active path, [`None`] if there is no active path.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionStats {
+    pub initial: SpaceStats,
+    pub handshake: SpaceStats,
+    pub data: SpaceStats,
+    /// The number of the lost packets, whose frames were queued to be sent again.
+    pub retransmissions: u64,
+    pub smoothed_rtt: Option<Duration>,
+    pub min_rtt: Option<Duration>,
+    pub congestion_window: Option<u64>,
+    /// The connection-level limit on the data we can send, advertised by the peer.
+    pub send_max_data: u64,
+    /// The connection-level limit on the data the peer can send, advertised to the peer.
+    pub recv_max_data: u64,
+    /// The number of the DATA_BLOCKED frames received, a growing number means the peer is often
+    /// blocked by the connection-level limit, and the receive window may be too small.
+    pub data_blocked_frames: u64,
+    pub paths: usize,
+    pub streams: usize,
+    /// The number of the datagrams dropped due to congestion, see [`DatagramSendPolicy::Drop`],
+    /// or being too large to fit into a packet on the path.
+    ///
+    /// [`DatagramSendPolicy::Drop`]: qunreliable::DatagramSendPolicy::Drop
+    pub datagrams_dropped: u64,
+}
+
+#[derive(Debug, Default)]
+struct SpaceCounters {
+    packets_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+    packets_received: AtomicU64,
+    bytes_received: AtomicU64,
+    packets_lost: AtomicU64,
+}
+
+impl SpaceCounters {
+    fn load(&self) -> SpaceStats {
+        SpaceStats {
+            packets_sent: self.packets_sent.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            packets_received: self.packets_received.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            packets_lost: self.packets_lost.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    spaces: [SpaceCounters; 3],
+    retransmissions: AtomicU64,
+}
+
+/// The packet counters of a connection, shared by all the paths and the spaces.
+///
+/// The counters are atomic, updating them on the sending and receiving path takes no lock.
+#[derive(Debug, Default, Clone)]
+pub struct ArcPacketCounters(Arc<Counters>);
+
+impl ArcPacketCounters {
+    /// Called when a packet of `length` bytes is sent.
+    pub fn on_packet_sent(&self, packet_type: PacketType, length: usize) {
+        let space = &self.0.spaces[epoch(packet_type) as usize];
+        space.packets_sent.fetch_add(1, Ordering::Relaxed);
+        space.bytes_sent.fetch_add(length as u64, Ordering::Relaxed);
+    }
+
+    /// Called when a packet of `length` bytes is received and decrypted.
+    pub fn on_packet_rcvd(&self, packet_type: PacketType, length: usize) {
+        let space = &self.0.spaces[epoch(packet_type) as usize];
+        space.packets_received.fetch_add(1, Ordering::Relaxed);
+        space
+            .bytes_received
+            .fetch_add(length as u64, Ordering::Relaxed);
+    }
+
+    /// Called when a packet of the `epoch` space is declared lost, `retransmitted` is whether
+    /// any frame in it is queued to be sent again.
+    pub fn on_packet_lost(&self, epoch: Epoch, retransmitted: bool) {
+        let space = &self.0.spaces[epoch as usize];
+        space.packets_lost.fetch_add(1, Ordering::Relaxed);
+        if retransmitted {
+            self.0.retransmissions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Fill the packet counters into the `stats`.
+    pub fn load_into(&self, stats: &mut ConnectionStats) {
+        stats.initial = self.0.spaces[Epoch::Initial as usize].load();
+        stats.handshake = self.0.spaces[Epoch::Handshake as usize].load();
+        stats.data = self.0.spaces[Epoch::Data as usize].load();
+        stats.retransmissions = self.0.retransmissions.load(Ordering::Relaxed);
+    }
+}
+
+fn epoch(packet_type: PacketType) -> Epoch {
+    match packet_type {
+        PacketType::Initial => Epoch::Initial,
+        PacketType::Handshake => Epoch::Handshake,
+        PacketType::ZeroRtt | PacketType::OneRtt => Epoch::Data,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counters() {
+        let counters = ArcPacketCounters::default();
+        counters.on_packet_sent(PacketType::Initial, 1200);
+        counters.on_packet_rcvd(PacketType::Initial, 1200);
+        counters.on_packet_sent(PacketType::Handshake, 80);
+        // 0-RTT和1-RTT包都计入数据空间
+        counters.on_packet_sent(PacketType::ZeroRtt, 100);
+        counters.on_packet_sent(PacketType::OneRtt, 40);
+        counters.on_packet_lost(Epoch::Data, true);
+        counters.on_packet_lost(Epoch::Data, false);
+
+        let mut stats = ConnectionStats::default();
+        counters.load_into(&mut stats);
+        assert_eq!(
+            stats.initial,
+            SpaceStats {
+                packets_sent: 1,
+                bytes_sent: 1200,
+                packets_received: 1,
+                bytes_received: 1200,
+                packets_lost: 0,
+            }
+        );
+        assert_eq!(stats.handshake.bytes_sent, 80);
+        assert_eq!(stats.data.packets_sent, 2);
+        assert_eq!(stats.data.bytes_sent, 140);
+        assert_eq!(stats.data.packets_lost, 2);
+        assert_eq!(stats.retransmissions, 1);
+    }
+}
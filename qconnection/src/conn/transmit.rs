@@ -1,3 +1,23 @@
+use bytes::BufMut;
+use qbase::frame::{io::WriteFrame, BeFrame, PingFrame};
+
 pub mod data;
 pub mod handshake;
 pub mod initial;
+
+/// Try to write a PING frame into the buffer for a PTO probe packet.
+///
+/// A probe packet must be ack-eliciting, the PING frame is written only if the packet needs to be
+/// a probe and carries no ack-eliciting frame yet. Return the number of bytes written.
+pub(crate) fn try_read_probe_ping(
+    mut buf: &mut [u8],
+    need_probe: bool,
+    is_ack_eliciting: bool,
+) -> usize {
+    let size = PingFrame.encoding_size();
+    if !need_probe || is_ack_eliciting || buf.remaining_mut() < size {
+        return 0;
+    }
+    buf.put_frame(&PingFrame);
+    size
+}
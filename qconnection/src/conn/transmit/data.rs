@@ -1,12 +1,19 @@
-use std::{sync::Arc, time::Instant};
+use std::{
+    sync::{Arc, Mutex},
+    time::Instant,
+};
 
 use bytes::BufMut;
 use qbase::{
     cid::ConnectionId,
-    frame::{PathChallengeFrame, PathResponseFrame, STREAM_FRAME_MAX_ENCODING_SIZE},
+    frame::{
+        io::WriteFrame, BeFrame, PathChallengeFrame, PathResponseFrame, PingFrame,
+        STREAM_FRAME_MAX_ENCODING_SIZE,
+    },
     packet::{
         encrypt::{
-            encode_long_first_byte, encode_short_first_byte, encrypt_packet, protect_header,
+            encode_long_first_byte, encode_short_first_byte, encrypt_packet,
+            encrypt_packet_for_path, protect_header,
         },
         header::{WriteLongHeader, WriteShortHeader},
         keys::{ArcKeys, ArcOneRttKeys, ArcOneRttPacketKeys},
@@ -22,20 +29,33 @@ use qrecovery::{
 use qunreliable::DatagramFlow;
 use rustls::quic::HeaderProtectionKey;
 
-use crate::{conn::DataStreams, path::SendBuffer};
+use crate::{
+    conn::{
+        ack_frequency::ArcAckFrequency, idle::ArcIdleTimer, scope::PathSpaces,
+        version::ArcVersions, DataStreams,
+    },
+    path::{Reinjection, SendBuffer},
+};
 
 #[derive(Clone)]
 pub struct DataSpaceReader {
     pub(crate) space: DataSpace,
+    // 1rtt数据包在路径当前所在的空间中收发，0rtt数据包总在空间0中
+    pub(crate) path_spaces: PathSpaces,
     pub(crate) zero_rtt_keys: ArcKeys,
     pub(crate) one_rtt_keys: ArcOneRttKeys,
+    pub(crate) sent_0rtt_pkts: Arc<Mutex<Vec<u64>>>,
+    pub(crate) versions: ArcVersions,
     // 数据源
     pub(crate) challenge_sndbuf: SendBuffer<PathChallengeFrame>,
     pub(crate) response_sndbuf: SendBuffer<PathResponseFrame>,
+    pub(crate) reinjection: Reinjection,
     pub(crate) crypto_stream_outgoing: CryptoStreamOutgoing,
     pub(crate) reliable_frames: ArcReliableFrameDeque,
     pub(crate) streams: DataStreams,
     pub(crate) datagrams: DatagramFlow,
+    pub(crate) idle_timer: ArcIdleTimer,
+    pub(crate) ack_frequency: ArcAckFrequency,
     // 为了各个流的公平性，包括不可靠数据帧，需要额外维护一些信息
 }
 
@@ -44,6 +64,17 @@ impl DataSpaceReader {
         self.one_rtt_keys.get_local_keys()
     }
 
+    /// 丢弃在当前MTU下无论如何都装不进1rtt数据包的数据报，否则它们会一直堵在队首
+    pub fn drop_oversized_datagrams(&self, mtu: usize, dcid: &ConnectionId) -> usize {
+        // 尚无1rtt密钥时不丢弃，数据报可以等到1rtt数据包再发送
+        let Some((_, pk)) = self.one_rtt_keys() else {
+            return 0;
+        };
+        // 1rtt包头(1字节首字节 + dcid)，至少1字节的包号，AEAD tag，以及1字节的DATAGRAM帧类型
+        let overhead = 1 + dcid.len() + 1 + pk.tag_len() + 1;
+        self.datagrams.drop_oversized(mtu.saturating_sub(overhead))
+    }
+
     /// Returns (pn, is_ack_eliciting, is_just_ack, sent_size, fresh_bytes, in_flight, sent_ack) or None
     #[allow(clippy::type_complexity)]
     pub fn try_read_1rtt(
@@ -53,6 +84,7 @@ impl DataSpaceReader {
         dcid: ConnectionId,
         spin: SpinBit,
         ack_pkt: Option<(u64, Instant)>,
+        need_probe: bool,
         (hpk, pk): (Arc<dyn HeaderProtectionKey>, ArcOneRttPacketKeys),
     ) -> Option<(u64, bool, bool, usize, usize, bool, Option<u64>)> {
         // 0. 检查1rtt keys是否有效，没有则回退到0rtt包
@@ -68,7 +100,7 @@ impl DataSpaceReader {
         let payload_buf = &mut payload_tag[..payload_tag_len - tag_len];
 
         // 2. 锁定发送记录器，生成pn，如果pn大小不够，直接返回
-        let sent_pkt_records = self.space.sent_packets();
+        let (space_id, sent_pkt_records) = self.path_spaces.sending();
         let mut send_guard = sent_pkt_records.send();
         let (pn, encoded_pn) = send_guard.next_pn();
         if payload_buf.remaining_mut() <= encoded_pn.size() {
@@ -99,13 +131,31 @@ impl DataSpaceReader {
             body_buf = &mut body_buf[n..];
         }
 
+        // 保活，在空闲超时之前发送Ping帧
+        let n = self.idle_timer.try_read_ping(body_buf);
+        if n > 0 {
+            send_guard.record_trivial();
+            is_ack_eliciting = true;
+            is_just_ack = false;
+            in_flight = true;
+            body_buf = &mut body_buf[n..];
+        }
+
         // 4. 检查是否需要发送Ack，若是，且符合（constraints + buf）节制，生成ack并写入，但发送记录并不记录
+        //    空间0以外的空间，以PATH_ACK帧确认
         let mut sent_ack = None;
         if let Some((largest, recv_time)) = ack_pkt {
-            let rcvd_pkt_records = self.space.rcvd_packets();
-            let n = rcvd_pkt_records
-                .read_ack_frame_util(body_buf, largest, recv_time)
-                .unwrap();
+            let (rcvd_space_id, rcvd_pkt_records) = self.path_spaces.receiving();
+            let n = match rcvd_space_id {
+                0 => rcvd_pkt_records.read_ack_frame_util(body_buf, largest, recv_time),
+                id => rcvd_pkt_records.read_path_ack_frame_util(
+                    body_buf,
+                    VarInt::from_u64(id).expect("sequence number is a varint"),
+                    largest,
+                    recv_time,
+                ),
+            }
+            .unwrap();
             send_guard.record_trivial();
             sent_ack = Some(largest);
             body_buf = &mut body_buf[n..];
@@ -133,6 +183,18 @@ impl DataSpaceReader {
 
         // 8. 检查Datagrams是否需要发送，若有，且符合(constraints + buf) 节制，写入，burst、发包记录都记录
         while let Some((_frame, n)) = self.datagrams.try_read_datagram(body_buf) {
+            // Datagram帧不重传，发送记录并不记录该帧，但要消耗包号，计入拥塞控制
+            send_guard.record_trivial();
+            body_buf = &mut body_buf[n..];
+            is_ack_eliciting = true;
+            is_just_ack = false;
+            in_flight = true;
+        }
+
+        // 冗余调度下，其他路径上发送的流数据的副本，副本本身不重传，但按其承载的流数据记录，
+        // 副本被确认即确认了这段流数据，副本丢失时若这段流数据尚未被确认则重传
+        while let Some((frame, n)) = self.reinjection.try_read(body_buf) {
+            send_guard.record_frame(GuaranteedFrame::Stream(frame));
             body_buf = &mut body_buf[n..];
             is_ack_eliciting = true;
             is_just_ack = false;
@@ -142,6 +204,9 @@ impl DataSpaceReader {
         // 9. 检查DataStreams是否需要发送，若有，且符合（constraints + buf）节制，写入，burst、发包记录都记录
         let mut fresh_bytes = 0;
         while let Some((frame, n, m)) = self.streams.try_read_data(body_buf, flow_limit) {
+            // 流数据位于写入内容的末尾
+            self.reinjection
+                .on_stream_sent(&frame, &body_buf[n - frame.len()..n]);
             send_guard.record_frame(GuaranteedFrame::Stream(frame));
             flow_limit -= m;
             fresh_bytes += m;
@@ -151,6 +216,28 @@ impl DataSpaceReader {
             in_flight = true;
         }
 
+        // 需要对端立即确认，比如PTO探测包，若对端支持ack frequency扩展，携带IMMEDIATE_ACK帧
+        let n = self
+            .ack_frequency
+            .try_read_immediate_ack(body_buf, need_probe);
+        if n > 0 {
+            send_guard.record_trivial();
+            is_ack_eliciting = true;
+            is_just_ack = false;
+            in_flight = true;
+            body_buf = &mut body_buf[n..];
+        }
+
+        // PTO超时，需要发送探测包，若没有其他可引起确认的帧，则发送一个Ping帧
+        let n = super::try_read_probe_ping(body_buf, need_probe, is_ack_eliciting);
+        if n > 0 {
+            send_guard.record_trivial();
+            is_ack_eliciting = true;
+            is_just_ack = false;
+            in_flight = true;
+            body_buf = &mut body_buf[n..];
+        }
+
         drop(send_guard); // 持有这把锁的时间越短越好，毕竟下面的加密可能会有点耗时
 
         let hdr_len = hdr_buf.len();
@@ -179,11 +266,24 @@ impl DataSpaceReader {
         hdr_buf.put_short_header(&hdr);
         pn_buf.put_packet_number(encoded_pn);
 
-        // 11 保护包头，加密数据
-        let pk_guard = pk.lock_guard();
+        // 11 保护包头，加密数据。密钥更新只在空间0中跟踪，路径标识参与nonce的计算
+        let mut pk_guard = pk.lock_guard();
         let (key_phase, pk) = pk_guard.get_local();
         encode_short_first_byte(&mut buf[0], pn_len, key_phase);
-        encrypt_packet(pk.as_ref(), pn, &mut buf[..sent_size], hdr_len + pn_len);
+        encrypt_packet_for_path(
+            pk.as_ref(),
+            space_id as u32,
+            pn,
+            &mut buf[..sent_size],
+            hdr_len + pn_len,
+        );
+        if space_id == 0 {
+            pk_guard.on_pkt_sent(pn);
+        }
+        drop(pk_guard);
+        if is_ack_eliciting {
+            self.idle_timer.on_ack_eliciting_sent();
+        }
         protect_header(hpk.as_ref(), &mut buf[..sent_size], hdr_len, pn_len);
 
         Some((
@@ -197,6 +297,67 @@ impl DataSpaceReader {
         ))
     }
 
+    /// Read a PMTU probe packet, which is a 1-RTT packet carrying a PING frame and padded to
+    /// fill the whole `buf`.
+    ///
+    /// Returns (pn, sent_size) or None
+    pub fn try_read_mtu_probe(
+        &self,
+        buf: &mut [u8],
+        dcid: ConnectionId,
+        spin: SpinBit,
+        (hpk, pk): (Arc<dyn HeaderProtectionKey>, ArcOneRttPacketKeys),
+    ) -> Option<(u64, usize)> {
+        let hdr = OneRttHeader { spin, dcid };
+        if buf.len() < hdr.size() + 20 {
+            return None;
+        }
+        let sent_size = buf.len();
+        let (mut hdr_buf, payload_tag) = buf.split_at_mut(hdr.size());
+        let payload_tag_len = payload_tag.len();
+        let tag_len = pk.tag_len();
+        let payload_buf = &mut payload_tag[..payload_tag_len - tag_len];
+
+        let (space_id, sent_pkt_records) = self.path_spaces.sending();
+        let mut send_guard = sent_pkt_records.send();
+        let (pn, encoded_pn) = send_guard.next_pn();
+        if payload_buf.remaining_mut() <= encoded_pn.size() + PingFrame.encoding_size() {
+            return None;
+        }
+        let (mut pn_buf, mut body_buf) = payload_buf.split_at_mut(encoded_pn.size());
+
+        // 探测包只包含一个Ping帧，其余全部填充，丢失了也无需重传
+        body_buf.put_frame(&PingFrame);
+        let padding_len = body_buf.remaining_mut();
+        body_buf.put_bytes(0, padding_len);
+        send_guard.record_trivial();
+        drop(send_guard);
+
+        let hdr_len = hdr_buf.len();
+        let pn_len = pn_buf.len();
+        hdr_buf.put_short_header(&hdr);
+        pn_buf.put_packet_number(encoded_pn);
+
+        let mut pk_guard = pk.lock_guard();
+        let (key_phase, pk) = pk_guard.get_local();
+        encode_short_first_byte(&mut buf[0], pn_len, key_phase);
+        encrypt_packet_for_path(
+            pk.as_ref(),
+            space_id as u32,
+            pn,
+            &mut buf[..sent_size],
+            hdr_len + pn_len,
+        );
+        if space_id == 0 {
+            pk_guard.on_pkt_sent(pn);
+        }
+        drop(pk_guard);
+        self.idle_timer.on_ack_eliciting_sent();
+        protect_header(hpk.as_ref(), &mut buf[..sent_size], hdr_len, pn_len);
+
+        Some((pn, sent_size))
+    }
+
     /// Returns (pn, is_ack_eliciting, sent_size, fresh_bytes, in_flight) or None
     pub fn try_read_0rtt(
         &self,
@@ -209,7 +370,9 @@ impl DataSpaceReader {
         let k = self.zero_rtt_keys.get_local_keys()?;
 
         // 2. 生成包头，预留2字节len，根据包头大小，配合constraints、剩余空间，检查是否能发送，不能的话，直接返回
-        let hdr = LongHeaderBuilder::with_cid(dcid, scid).zero_rtt();
+        let hdr = LongHeaderBuilder::with_cid(dcid, scid)
+            .with_version(self.versions.current())
+            .zero_rtt();
         // length字段预留2字节, 20字节为最小Payload长度，为了保护包头的Sample至少16字节
         if buf.len() < hdr.size() + 2 + 20 {
             return None;
@@ -265,6 +428,8 @@ impl DataSpaceReader {
 
         // 7. 检查Datagrams是否需要发送，若有，且符合(constraints + buf) 节制，写入，burst、发包记录都记录
         while let Some((_frame, n)) = self.datagrams.try_read_datagram(body_buf) {
+            // Datagram帧不重传，发送记录并不记录该帧，但要消耗包号，计入拥塞控制
+            send_guard.record_trivial();
             body_buf = &mut body_buf[n..];
             is_ack_eliciting = true;
             in_flight = true;
@@ -308,6 +473,7 @@ impl DataSpaceReader {
             pn_len,
         );
 
+        self.sent_0rtt_pkts.lock().unwrap().push(pn);
         // 0RTT包不能发送Ack
         Some((pn, is_ack_eliciting, sent_size, fresh_bytes, in_flight))
     }
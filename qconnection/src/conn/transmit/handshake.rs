@@ -13,11 +13,14 @@ use qbase::{
 };
 use qrecovery::{crypto::CryptoStreamOutgoing, space::HandshakeSpace};
 
+use crate::conn::version::ArcVersions;
+
 #[derive(Clone)]
 pub struct HandshakeSpaceReader {
     pub(crate) keys: ArcKeys,
     pub(crate) space: HandshakeSpace,
     pub(crate) crypto_stream_outgoing: CryptoStreamOutgoing,
+    pub(crate) versions: ArcVersions,
 }
 
 impl HandshakeSpaceReader {
@@ -27,12 +30,15 @@ impl HandshakeSpaceReader {
         scid: ConnectionId,
         dcid: ConnectionId,
         ack_pkt: Option<(u64, Instant)>,
+        need_probe: bool,
     ) -> Option<(u64, bool, bool, usize, bool, Option<u64>)> {
         // 1. 判定keys是否有效，无效或者尚未拿到，直接返回
         let k = self.keys.get_local_keys()?;
 
         // 2. 生成包头，预留2字节len，根据包头大小，配合constraints、剩余空间，检查是否能发送，不能的话，直接返回
-        let hdr = LongHeaderBuilder::with_cid(dcid, scid).handshake();
+        let hdr = LongHeaderBuilder::with_cid(dcid, scid)
+            .with_version(self.versions.current())
+            .handshake();
         // length字段预留2字节, 20字节为最小Payload长度，为了保护包头的Sample至少16字节
         if buf.len() < hdr.size() + 2 + 20 {
             return None;
@@ -76,6 +82,16 @@ impl HandshakeSpaceReader {
             is_just_ack = false;
             in_flight = true;
         }
+        // PTO超时，需要发送探测包，若没有其他可引起确认的帧，则发送一个Ping帧
+        let n = super::try_read_probe_ping(body_buf, need_probe, is_ack_eliciting);
+        if n > 0 {
+            send_guard.record_trivial();
+            is_ack_eliciting = true;
+            is_just_ack = false;
+            in_flight = true;
+            body_buf = &mut body_buf[n..];
+        }
+
         drop(send_guard); // 持有这把锁的时间越短越好，毕竟下面的加密可能会有点耗时
 
         // 7. 填充，保护头部，加密
@@ -16,12 +16,15 @@ use qbase::{
 };
 use qrecovery::{crypto::CryptoStreamOutgoing, space::InitialSpace};
 
+use crate::conn::version::ArcVersions;
+
 #[derive(Clone)]
 pub struct InitialSpaceReader {
     pub(crate) token: Arc<Mutex<Vec<u8>>>,
     pub(crate) keys: ArcKeys,
     pub(crate) space: InitialSpace,
     pub(crate) crypto_stream_outgoing: CryptoStreamOutgoing,
+    pub(crate) versions: ArcVersions,
 }
 
 impl InitialSpaceReader {
@@ -32,6 +35,7 @@ impl InitialSpaceReader {
         scid: ConnectionId,
         dcid: ConnectionId,
         ack_pkt: Option<(u64, Instant)>,
+        need_probe: bool,
     ) -> Option<(
         impl FnOnce(&mut [u8], usize) -> (u64, bool, bool, usize, bool, Option<u64>),
         usize,
@@ -42,7 +46,9 @@ impl InitialSpaceReader {
 
         // 2. 生成包头，预留2字节len，根据包头大小，配合constraints、剩余空间，检查是否能发送，不能的话，直接返回
         let token = self.token.lock().unwrap();
-        let hdr = LongHeaderBuilder::with_cid(dcid, scid).initial(token.clone());
+        let hdr = LongHeaderBuilder::with_cid(dcid, scid)
+            .with_version(self.versions.current())
+            .initial(token.clone());
         // length字段预留2字节, 20字节为最小Payload长度，为了保护包头的Sample至少16字节
         if buf.len() < hdr.size() + 2 + 20 {
             return None;
@@ -86,6 +92,16 @@ impl InitialSpaceReader {
             is_ack_eliciting = true;
             in_flight = true;
         }
+        // PTO超时，需要发送探测包，若没有其他可引起确认的帧，则发送一个Ping帧
+        let n = super::try_read_probe_ping(body_buf, need_probe, is_ack_eliciting);
+        if n > 0 {
+            send_guard.record_trivial();
+            is_ack_eliciting = true;
+            is_just_ack = false;
+            in_flight = true;
+            body_buf = &mut body_buf[n..];
+        }
+
         drop(send_guard); // 持有这把锁的时间越短越好，毕竟下面的加密可能会有点耗时
 
         let hdr_len = hdr_buf.len();
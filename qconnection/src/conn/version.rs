@@ -0,0 +1,169 @@
+use std::sync::{Arc, Mutex};
+
+pub use qbase::packet::r#type::long::{QUIC_VERSION_1, QUIC_VERSION_2};
+use thiserror::Error;
+
+/// Return the version used to derive the Initial keys of the QUIC `version`, or [`None`] if the
+/// version is unknown.
+///
+/// The TLS session derives the Handshake and 1-RTT keys with the same version, see
+/// [cryptography changes](https://www.rfc-editor.org/rfc/rfc9369.html#name-cryptography-changes)
+/// of [RFC 9369](https://www.rfc-editor.org/rfc/rfc9369.html).
+pub fn initial_keys_version(version: u32) -> Option<rustls::quic::Version> {
+    match version {
+        QUIC_VERSION_1 => Some(rustls::quic::Version::V1),
+        QUIC_VERSION_2 => Some(rustls::quic::Version::V2),
+        _ => None,
+    }
+}
+
+/// None of the versions listed in the Version Negotiation packet is supported by the client.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("No mutually supported version, the server supports {0:x?}")]
+pub struct NoCommonVersion(pub Vec<u32>);
+
+/// The QUIC versions of a client connection, used to handle the Version Negotiation packets.
+///
+/// The first supported version is offered in the first Initial packet. If the server does not
+/// support it, it will respond with a Version Negotiation packet, then the client selects the most
+/// preferred version that both endpoints support, and restarts the handshake with it.
+///
+/// Only one round of version negotiation is allowed, and the Version Negotiation packets listing
+/// the originally offered version are discarded, so that an attacker can not downgrade the version
+/// by injecting a Version Negotiation packet.
+///
+/// See [version negotiation](https://www.rfc-editor.org/rfc/rfc9000.html#name-version-negotiation)
+/// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Versions {
+    supported: Vec<u32>,
+    offered: u32,
+    current: u32,
+}
+
+impl Default for Versions {
+    fn default() -> Self {
+        Self::new(vec![QUIC_VERSION_1])
+    }
+}
+
+impl Versions {
+    /// Create a new [`Versions`] with the supported versions, in the order of preference.
+    ///
+    /// The unknown versions are ignored, and QUIC version 1 is used if no version is known.
+    pub fn new(mut supported: Vec<u32>) -> Self {
+        supported.retain(|version| initial_keys_version(*version).is_some());
+        if supported.is_empty() {
+            supported.push(QUIC_VERSION_1);
+        }
+        let offered = supported[0];
+        Self {
+            supported,
+            offered,
+            current: offered,
+        }
+    }
+
+    /// The version originally offered in the first Initial packet.
+    pub fn offered(&self) -> u32 {
+        self.offered
+    }
+
+    /// The version in use currently, which differs from the offered one after the negotiation.
+    pub fn current(&self) -> u32 {
+        self.current
+    }
+
+    /// Handle the versions listed in a Version Negotiation packet.
+    ///
+    /// Return the version selected to restart the handshake with, or [`None`] if the packet
+    /// should be ignored. Return [`NoCommonVersion`] if none of the listed versions is supported,
+    /// in which case the connection attempt should be abandoned.
+    pub fn on_version_negotiation(
+        &mut self,
+        versions: &[u32],
+    ) -> Result<Option<u32>, NoCommonVersion> {
+        // only one round of version negotiation, the later ones may be injected
+        if self.current != self.offered {
+            return Ok(None);
+        }
+        // the server does support the offered version, it must be a forged packet
+        if versions.contains(&self.offered) {
+            return Ok(None);
+        }
+        let selected = self
+            .supported
+            .iter()
+            .find(|version| versions.contains(version))
+            .ok_or_else(|| NoCommonVersion(versions.to_vec()))?;
+        self.current = *selected;
+        Ok(Some(*selected))
+    }
+}
+
+/// The shared [`Versions`] of a connection.
+///
+/// The long header packets are sent with the current version, which changes once the client
+/// restarts the handshake after the version negotiation.
+#[derive(Debug, Clone)]
+pub struct ArcVersions(Arc<Mutex<Versions>>);
+
+impl ArcVersions {
+    /// Create a new [`ArcVersions`].
+    pub fn new(versions: Versions) -> Self {
+        Self(Arc::new(Mutex::new(versions)))
+    }
+
+    /// The version in use currently, read [`Versions::current`] for more details.
+    pub fn current(&self) -> u32 {
+        self.0.lock().unwrap().current()
+    }
+
+    /// Handle the versions listed in a Version Negotiation packet, read
+    /// [`Versions::on_version_negotiation`] for more details.
+    pub fn on_version_negotiation(&self, versions: &[u32]) -> Result<Option<u32>, NoCommonVersion> {
+        self.0.lock().unwrap().on_version_negotiation(versions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_version() {
+        let mut versions = Versions::new(vec![QUIC_VERSION_2, 0x0a0a_0a0a, QUIC_VERSION_1]);
+        assert_eq!(versions.offered(), QUIC_VERSION_2);
+
+        assert_eq!(
+            versions.on_version_negotiation(&[0x1a2a_3a4a, QUIC_VERSION_1]),
+            Ok(Some(QUIC_VERSION_1))
+        );
+        assert_eq!(versions.current(), QUIC_VERSION_1);
+        // only one round of version negotiation
+        assert_eq!(versions.on_version_negotiation(&[QUIC_VERSION_2]), Ok(None));
+        assert_eq!(versions.current(), QUIC_VERSION_1);
+    }
+
+    #[test]
+    fn test_downgrade() {
+        let mut versions = Versions::new(vec![QUIC_VERSION_2, QUIC_VERSION_1]);
+        // listing the offered version means that the packet is forged
+        assert_eq!(
+            versions.on_version_negotiation(&[QUIC_VERSION_1, QUIC_VERSION_2]),
+            Ok(None)
+        );
+        assert_eq!(versions.current(), QUIC_VERSION_2);
+    }
+
+    #[test]
+    fn test_no_common_version() {
+        let mut versions = Versions::default();
+        assert_eq!(versions.offered(), QUIC_VERSION_1);
+        assert_eq!(
+            versions.on_version_negotiation(&[0x1a2a_3a4a]),
+            Err(NoCommonVersion(vec![0x1a2a_3a4a]))
+        );
+        assert_eq!(versions.current(), QUIC_VERSION_1);
+    }
+}
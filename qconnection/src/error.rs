@@ -1,6 +1,7 @@
 use std::{
+    borrow::Cow,
     pin::Pin,
-    sync::Arc,
+    sync::{Arc, Mutex},
     task::{Context, Poll},
 };
 
@@ -8,14 +9,76 @@ use qbase::{
     error::{Error, ErrorKind},
     frame::ConnectionCloseFrame,
     util::Future,
+    varint::VarInt,
 };
 
+use crate::conn::version::NoCommonVersion;
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ConnErrorKind {
     Application,
     Transport,
     CcfReceived,
     NoViablePath,
+    IdleTimeout,
+    HandshakeTimeout,
+    StatelessReset,
+    NoCommonVersion,
+}
+
+/// Which endpoint initiated the closure of the connection, see [`CloseReason`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseInitiator {
+    /// The connection is closed by us, by the application or due to an error detected locally.
+    Local,
+    /// The connection is closed by the peer, with a CONNECTION_CLOSE frame or a stateless reset.
+    Remote,
+}
+
+/// The error code the connection is closed with, see [`CloseReason`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseCode {
+    /// A transport error code, carried by the CONNECTION_CLOSE frame of type 0x1c.
+    Transport(ErrorKind),
+    /// An application-defined error code, carried by the CONNECTION_CLOSE frame of type 0x1d.
+    Application(VarInt),
+}
+
+/// Why the connection is closed.
+///
+/// The connections closed silently, such as idle timeout, are closed by the [`Local`] endpoint
+/// with a transport error code.
+///
+/// [`Local`]: CloseInitiator::Local
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseReason {
+    pub initiator: CloseInitiator,
+    pub code: CloseCode,
+    pub reason: Cow<'static, str>,
+}
+
+impl CloseReason {
+    fn local(error: &Error) -> Self {
+        Self {
+            initiator: CloseInitiator::Local,
+            code: CloseCode::Transport(error.kind()),
+            reason: error.reason().clone(),
+        }
+    }
+}
+
+impl From<&ConnectionCloseFrame> for CloseReason {
+    fn from(ccf: &ConnectionCloseFrame) -> Self {
+        let code = match ccf {
+            ConnectionCloseFrame::Quic(frame) => CloseCode::Transport(frame.error_kind),
+            ConnectionCloseFrame::App(frame) => CloseCode::Application(frame.error_code),
+        };
+        Self {
+            initiator: CloseInitiator::Remote,
+            code,
+            reason: ccf.reason().clone(),
+        }
+    }
 }
 
 /// Connection error, which is None first, and external can poll query whether an error has occurred.
@@ -39,9 +102,27 @@ pub enum ConnErrorKind {
 /// # }
 /// ```
 #[derive(Default, Debug, Clone)]
-pub struct ConnError(Arc<Future<(Error, ConnErrorKind)>>);
+pub struct ConnError(
+    Arc<Future<(Error, ConnErrorKind)>>,
+    Arc<Mutex<Option<CloseReason>>>,
+);
 
 impl ConnError {
+    // 只有第一个错误生效，关闭原因与之保持一致
+    fn assign(&self, error: Error, kind: ConnErrorKind, reason: CloseReason) -> bool {
+        let mut close_reason = self.1.lock().unwrap();
+        let assigned = self.0.assign((error, kind)).is_ok();
+        if assigned {
+            *close_reason = Some(reason);
+        }
+        assigned
+    }
+
+    /// Returns why the connection is closed, [`None`] if no error has occurred yet.
+    pub fn close_reason(&self) -> Option<CloseReason> {
+        self.1.lock().unwrap().clone()
+    }
+
     /// Returns a `ConnError` instance that can be used to track connection errors.
     ///
     /// This method simply clones the current `ConnError` instance and returns it.
@@ -52,26 +133,69 @@ impl ConnError {
 
     /// When a connection close frame is received, it will change the state and wake the external if necessary.
     pub fn on_ccf_rcvd(&self, ccf: &ConnectionCloseFrame) {
-        _ = self
-            .0
-            .assign((Error::from(ccf.clone()), ConnErrorKind::CcfReceived));
+        let error = Error::from(ccf.clone());
+        self.assign(error, ConnErrorKind::CcfReceived, ccf.into());
     }
 
     pub fn on_error(&self, error: Error) {
-        _ = self.0.assign((error, ConnErrorKind::Transport));
+        let reason = CloseReason::local(&error);
+        self.assign(error, ConnErrorKind::Transport, reason);
     }
 
-    /// App actively close the connection with an error
-    pub fn set_app_error(&self, error: Error) {
-        _ = self.0.assign((error, ConnErrorKind::Application));
+    /// App actively close the connection with an application-defined `error_code`.
+    ///
+    /// Return `false` if any error has already occurred, in which case the connection is being
+    /// closed already and the new error will be ignored.
+    pub fn set_app_error(&self, error_code: VarInt, reason: impl Into<Cow<'static, str>>) -> bool {
+        let reason = CloseReason {
+            initiator: CloseInitiator::Local,
+            code: CloseCode::Application(error_code),
+            reason: reason.into(),
+        };
+        let error = Error::with_default_fty(ErrorKind::Application, reason.reason.clone());
+        self.assign(error, ConnErrorKind::Application, reason)
+    }
+
+    fn on_local_error(&self, error: Error, kind: ConnErrorKind) {
+        let reason = CloseReason::local(&error);
+        self.assign(error, kind, reason);
     }
 
     pub fn no_viable_path(&self) {
-        _ = self.0.assign((
-            // the error wont been read(
-            Error::with_default_fty(ErrorKind::NoViablePath, "No viable path"),
-            ConnErrorKind::NoViablePath,
-        ));
+        // the error wont been read(
+        let error = Error::with_default_fty(ErrorKind::NoViablePath, "No viable path");
+        self.on_local_error(error, ConnErrorKind::NoViablePath);
+    }
+
+    /// The connection has been idle for longer than the idle timeout, it should be closed silently.
+    pub fn on_idle_timeout(&self) {
+        let error = Error::with_default_fty(ErrorKind::None, "Idle timeout");
+        self.on_local_error(error, ConnErrorKind::IdleTimeout);
+    }
+
+    /// The handshake is not completed within the handshake timeout, the connection attempt should
+    /// be abandoned silently.
+    pub fn on_handshake_timeout(&self) {
+        let error = Error::with_default_fty(ErrorKind::None, "Handshake timeout");
+        self.on_local_error(error, ConnErrorKind::HandshakeTimeout);
+    }
+
+    /// None of the versions listed in the Version Negotiation packet is supported, the connection
+    /// attempt should be abandoned without sending any packet.
+    pub fn on_no_common_version(&self, error: NoCommonVersion) {
+        let error = Error::with_default_fty(ErrorKind::None, error.to_string());
+        self.on_local_error(error, ConnErrorKind::NoCommonVersion);
+    }
+
+    /// A stateless reset is received from the peer, the connection should enter the draining state
+    /// immediately without sending any packet.
+    pub fn on_stateless_reset(&self) {
+        let error = Error::with_default_fty(ErrorKind::None, "Stateless reset");
+        let reason = CloseReason {
+            initiator: CloseInitiator::Remote,
+            ..CloseReason::local(&error)
+        };
+        self.assign(error, ConnErrorKind::StatelessReset, reason);
     }
 }
 
@@ -107,7 +231,7 @@ mod tests {
             }
         });
 
-        let ccf = ConnectionCloseFrame::new(ErrorKind::Internal, None, "Test close frame".into());
+        let ccf = ConnectionCloseFrame::new_quic(ErrorKind::Internal, Padding, "Test close frame");
         conn_error.on_ccf_rcvd(&ccf);
 
         _ = task.await;
@@ -143,9 +267,44 @@ mod tests {
             }
         });
 
-        let error = Error::new(ErrorKind::Internal, Padding, "Test app error");
-        conn_error.set_app_error(error);
+        assert!(conn_error.set_app_error(VarInt::from_u32(0), "Test app error"));
 
         _ = task.await;
     }
+
+    #[test]
+    fn test_app_error_after_error() {
+        let conn_error = ConnError::default();
+        conn_error.on_error(Error::new(ErrorKind::Internal, Padding, "Test error"));
+
+        assert!(!conn_error.set_app_error(VarInt::from_u32(0), "Test app error"));
+        let (error, kind) = conn_error.0.try_get().unwrap();
+        assert_eq!(kind, ConnErrorKind::Transport);
+        assert_eq!(error.kind(), ErrorKind::Internal);
+        let reason = conn_error.close_reason().unwrap();
+        assert_eq!(reason.initiator, CloseInitiator::Local);
+        assert_eq!(reason.code, CloseCode::Transport(ErrorKind::Internal));
+    }
+
+    #[test]
+    fn test_close_reason_of_app_ccf() {
+        let conn_error = ConnError::default();
+        assert!(conn_error.close_reason().is_none());
+
+        let ccf = ConnectionCloseFrame::new_app(VarInt::from_u32(0x100), "bye");
+        conn_error.on_ccf_rcvd(&ccf);
+        // 之后的错误被忽略
+        conn_error.on_idle_timeout();
+        assert_eq!(
+            conn_error.close_reason(),
+            Some(CloseReason {
+                initiator: CloseInitiator::Remote,
+                code: CloseCode::Application(VarInt::from_u32(0x100)),
+                reason: "bye".into(),
+            })
+        );
+        let (error, kind) = conn_error.0.try_get().unwrap();
+        assert_eq!(kind, ConnErrorKind::CcfReceived);
+        assert_eq!(error.kind(), ErrorKind::Application);
+    }
 }
@@ -1,5 +1,6 @@
 use std::net::SocketAddr;
 
+pub mod clock;
 pub mod conn;
 pub mod error;
 pub mod path;
@@ -14,6 +15,61 @@ pub mod usc;
 ///   - 配合GSO，携带segment size的形式 发送，内核发送优化，将是最高效的发送方法
 pub trait Sendmsg {
     fn sendmsg(&mut self, msg: &[u8], dest: SocketAddr) -> std::io::Result<usize>;
+
+    /// 携带ttl设置发送，仅对本次发送生效，不会修改socket默认的ttl。IPv4设置IP_TTL，IPv6设置hop limit。
+    ///
+    /// 若平台不支持逐次设置ttl，应返回错误，而不是以默认ttl发送出去。
+    fn sendmsg_with_ttl(
+        &mut self,
+        msg: &[u8],
+        dest: SocketAddr,
+        ttl: u8,
+    ) -> std::io::Result<usize> {
+        _ = (msg, dest, ttl);
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+
+    /// 携带segment size，以GSO的形式发送，`buf`将被按照`segment_size`切分成多个数据报，
+    /// 最后一个数据报可以比`segment_size`小。返回被接受发送的字节数。
+    ///
+    /// 默认实现退化成逐个segment调用[`Sendmsg::sendmsg`]发送，适用于不支持GSO的平台。
+    /// 若返回的错误是`EIO`或`EMSGSIZE`，意味着网卡不支持GSO或者segment过大，
+    /// 调用者应当减小segment size后重试。
+    fn sendmsg_gso(
+        &mut self,
+        buf: &[u8],
+        segment_size: u16,
+        dest: SocketAddr,
+    ) -> std::io::Result<usize> {
+        if segment_size == 0 {
+            return Err(std::io::ErrorKind::InvalidInput.into());
+        }
+        let mut sent = 0;
+        for segment in buf.chunks(segment_size as usize) {
+            match self.sendmsg(segment, dest) {
+                Ok(n) => sent += n,
+                Err(e) if sent > 0 && e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(sent)
+    }
+
+    /// 多个数据报通过一次系统调用发送，它们可以发往不同的目标，返回实际发送出去的数据报个数。
+    ///
+    /// 默认实现退化成逐个调用[`Sendmsg::sendmsg`]发送。若只发送了部分数据报，会返回已发送的个数，
+    /// 调用者应从该位置开始重试发送剩余的数据报；若一个都没发送出去，才返回错误。
+    fn sendmmsg(&mut self, msgs: &[(&[u8], SocketAddr)]) -> std::io::Result<usize> {
+        let mut sent = 0;
+        for (msg, dest) in msgs {
+            match self.sendmsg(msg, *dest) {
+                Ok(_) => sent += 1,
+                Err(_) if sent > 0 => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(sent)
+    }
 }
 
 #[cfg(test)]
@@ -1,25 +1,48 @@
-use std::sync::Arc;
+use std::{
+    future::Future,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
 
+use bytes::Bytes;
 use dashmap::DashMap;
 use deref_derive::{Deref, DerefMut};
-use qbase::cid::{ArcCidCell, ConnectionId};
-use qcongestion::{CongestionControl, MayLoss, RetirePktRecord};
-use qrecovery::reliable::ArcReliableFrameDeque;
+use qbase::{
+    cid::{ArcCidCell, ConnectionId},
+    frame::StreamFrame,
+};
+use qcongestion::{CongestionControl, CongestionController, MayLoss, RetirePktRecord, MSS};
+use qrecovery::{reliable::ArcReliableFrameDeque, space::Epoch};
 
 mod anti_amplifier;
+mod event;
+mod mtu;
 mod pathway;
 mod raw;
 mod read;
+mod scheduler;
+mod spin;
 mod state;
 mod util;
 
 pub use anti_amplifier::ArcAntiAmplifier;
+pub use event::{ArcPathEvents, PathEvent};
+pub use mtu::{ArcPathMtu, PathMtu, BASE_PLPMTU, DEFAULT_MAX_PLPMTU, MAX_PLPMTU};
 pub use pathway::{Pathway, RelayAddr};
-pub use raw::Path;
+pub use raw::{Path, PathContext, ValidationError};
 pub use read::ReadIntoDatagrams;
-pub use util::{RecvBuffer, SendBuffer};
+pub use scheduler::{
+    MinRttScheduler, PathScheduler, PathStatus, RedundantScheduler, Reinjection,
+    RoundRobinScheduler, SchedulerHandle,
+};
+pub use spin::ArcSpinBit;
+pub use util::{RecvBuffer, ReinjectBuffer, SendBuffer};
 
-use crate::usc::ArcUsc;
+use crate::{clock::ArcClock, usc::ArcUsc};
 
 /// The shared version of [`Path`].
 #[derive(Clone, Deref)]
@@ -33,13 +56,30 @@ impl ArcPath {
         usc: ArcUsc,
         scid: ConnectionId,
         dcid: ArcCidCell<ArcReliableFrameDeque>,
+        controller: Box<dyn CongestionController>,
         loss: [Box<dyn MayLoss>; 3],
         retire: [Box<dyn RetirePktRecord>; 3],
+        ctx: PathContext,
     ) -> Self {
-        Self(Arc::new(Path::new(usc, scid, dcid, loss, retire)))
+        Self(Arc::new(Path::new(
+            usc, scid, dcid, controller, loss, retire, ctx,
+        )))
     }
 }
 
+/// The default maximum number of the paths of a connection, read [`Paths::set_max_paths`].
+pub const DEFAULT_MAX_PATHS: usize = 8;
+
+/// The default maximum number of the unvalidated paths of a connection, read
+/// [`Paths::set_max_paths`].
+pub const DEFAULT_MAX_UNVALIDATED_PATHS: usize = 4;
+
+/// How often the loss detection timer of a path is checked.
+const TICK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How often the idle paths are swept, read [`Paths::set_idle_path_timeout`].
+const SWEEP_INTERVAL: Duration = Duration::from_millis(100);
+
 /// The set of all paths of a connection.
 ///
 /// GM-QUIC supports multiple paths for a connection, each path corresponds to a [`Pathway`].
@@ -50,12 +90,50 @@ impl ArcPath {
 ///
 /// This structure is also responsible for automatically removing a path from the set when it becomes
 /// inactive and terminating a connection when no path is available.
+///
+/// One of the paths is the active path, which is used for new transmissions. The first path is the
+/// active path, when a non-probing packet is received on other path, the connection will [migrate]
+/// to that path once the path is validated.
+///
+/// The number of the paths is bounded, so that an attacker spoofing many source addresses can not
+/// exhaust the memory by forcing the creation of paths, read [`Paths::set_max_paths`]. And the
+/// paths left behind by migrations are swept once they are idle, read
+/// [`Paths::set_idle_path_timeout`].
+///
+/// Without the multipath extension, only the active path carries new stream data. Once it is
+/// negotiated, the validated paths carry stream data concurrently as the [`PathScheduler`]
+/// decides, read [`Paths::is_scheduled`].
+///
+/// The validations, the migrations and the abandonments of the paths are emitted as
+/// [`PathEvent`]s, read [`ArcPathEvents`].
+///
+/// Either endpoint may advertise the `disable_active_migration` transport parameter, the
+/// endpoint advertising it refuses the active migrations of the peer, read
+/// [`Paths::refuse_active_migration`], and the peer must not migrate actively, read
+/// [`Paths::disable_active_migration`].
+///
+/// [migrate]: https://www.rfc-editor.org/rfc/rfc9000.html#name-connection-migration
 #[derive(Deref, DerefMut)]
 pub struct Paths {
     #[deref]
-    map: DashMap<Pathway, ArcPath>,
+    map: Arc<DashMap<Pathway, ArcPath>>,
+    max_paths: AtomicUsize,
+    max_unvalidated_paths: AtomicUsize,
+    // 创建路径时持有，腾出空间与插入新路径一并完成，并发创建的路径不会超过上限
+    creating: Mutex<()>,
+    idle_path_timeout: Mutex<Option<Duration>>,
+    active: Arc<Mutex<Option<Pathway>>>,
+    migrating: Arc<Mutex<Option<Pathway>>>,
+    // 握手所用的本地地址，disable_active_migration仅约束该地址上的迁移
+    handshake_local: Mutex<Option<SocketAddr>>,
+    refuse_active_migration: AtomicBool,
+    disable_active_migration: AtomicBool,
+    multipath: AtomicBool,
+    scheduler: Mutex<Arc<dyn PathScheduler>>,
+    events: ArcPathEvents,
     creator: Box<dyn Fn(Pathway, ArcUsc) -> ArcPath + Send + Sync + 'static>,
     on_no_path: Arc<dyn Fn() + Send + Sync + 'static>,
+    clock: ArcClock,
 }
 
 impl Paths {
@@ -68,14 +146,32 @@ impl Paths {
     /// - `on_no_path`: A function that will be called when there is no path in the set, this usually
     ///    means that the connection is no longer available. This function can set a connection error
     ///    and directly terminate the connection.
+    ///
+    /// The [`PathEvent`]s are emitted to the `events`, and the idle time of the paths is measured
+    /// by the `clock`.
     pub fn new(
         creator: Box<dyn Fn(Pathway, ArcUsc) -> ArcPath + Send + Sync + 'static>,
         on_no_path: Arc<dyn Fn() + Send + Sync + 'static>,
+        events: ArcPathEvents,
+        clock: ArcClock,
     ) -> Self {
         Self {
-            map: DashMap::new(),
+            map: Arc::default(),
+            max_paths: AtomicUsize::new(DEFAULT_MAX_PATHS),
+            max_unvalidated_paths: AtomicUsize::new(DEFAULT_MAX_UNVALIDATED_PATHS),
+            creating: Mutex::new(()),
+            idle_path_timeout: Mutex::new(None),
+            active: Arc::default(),
+            migrating: Arc::default(),
+            handshake_local: Mutex::new(None),
+            refuse_active_migration: AtomicBool::new(false),
+            disable_active_migration: AtomicBool::new(false),
+            multipath: AtomicBool::new(false),
+            scheduler: Mutex::new(Arc::new(MinRttScheduler)),
+            events,
             on_no_path,
             creator,
+            clock,
         }
     }
 
@@ -87,11 +183,30 @@ impl Paths {
     /// When a path is created, a task will be started to monitor the path. When the path is inactive,
     /// the path will be removed from the set. If there are no paths in the set, the function specified
     /// by `on_no_path`(read [`Paths::new`]) will be called.
+    ///
+    /// If the limits set by [`Paths::set_max_paths`] are reached, the least recently used
+    /// unvalidated path is abandoned to make room for the new path. Making room and inserting the
+    /// new path are done atomically, so the limits hold even if paths are created concurrently.
     pub fn get_or_create(&self, pathway: Pathway, usc: ArcUsc) -> ArcPath {
+        let _creating = match self.map.contains_key(&pathway) {
+            true => None,
+            false => {
+                let creating = self.creating.lock().unwrap();
+                // 等待锁期间，该路径可能已被其他任务创建
+                if !self.map.contains_key(&pathway) {
+                    self.make_room();
+                }
+                Some(creating)
+            }
+        };
+
         let pathes = self.map.clone();
+        let active = self.active.clone();
         let on_no_path = self.on_no_path.clone();
+        let events = self.events.clone();
 
-        self.map
+        let path = self
+            .map
             .entry(pathway)
             .or_insert_with(|| {
                 let path = (self.creator)(pathway, usc);
@@ -99,14 +214,50 @@ impl Paths {
                 tokio::spawn({
                     let state = state.clone();
                     let cc = path.cc.clone();
+                    let clock = path.clock.clone();
+                    let spaces = path.spaces().clone();
+                    let this = Arc::downgrade(&path.0);
+                    let mut validation = path.validation.subscribe();
                     async move {
+                        // 与Path::validated的等待者互不影响
+                        let validated = async move {
+                            match validation.wait_for(Option::is_some).await {
+                                Ok(result) => *result,
+                                Err(_) => None,
+                            }
+                        };
+                        tokio::pin!(validated);
+                        let mut is_validated = false;
                         loop {
                             tokio::select! {
                                 _ = state.has_been_inactivated() => break,
-                                _ = tokio::time::sleep(std::time::Duration::from_millis(10)) => cc.do_tick(),
+                                result = &mut validated, if !is_validated => {
+                                    is_validated = true;
+                                    if result == Some(Ok(())) {
+                                        events.emit(PathEvent::PathValidated(pathway));
+                                    }
+                                }
+                                _ = clock.sleep_until(clock.now() + TICK_INTERVAL) => cc.do_tick(),
                             }
                         }
-                        pathes.remove(&pathway);
+                        // 路径自己的包号空间中在途的包再也不会被确认，判定为丢失，帧在其他路径上重传
+                        if spaces.sending_id() != 0 {
+                            cc.discard_sent(Epoch::Data);
+                            spaces.release();
+                        }
+                        // 该路径可能已被放弃，同一pathway上又创建了新的路径，不能误删
+                        let removed = pathes.remove_if(&pathway, |_, path| {
+                            std::ptr::eq(Arc::as_ptr(&path.0), this.as_ptr())
+                        });
+                        // 被显式放弃的路径已从集合中移除，在放弃时就发出了事件
+                        if removed.is_some() {
+                            events.emit(PathEvent::PathAbandoned(pathway));
+                        }
+                        let mut active = active.lock().unwrap();
+                        if *active == Some(pathway) {
+                            // 活跃路径失效，退回到其他任意一条路径
+                            *active = pathes.iter().next().map(|entry| *entry.key());
+                        }
                         if pathes.is_empty() {
                             (on_no_path)();
                         }
@@ -115,7 +266,426 @@ impl Paths {
                 path
             })
             .value()
-            .clone()
+            .clone();
+        // 第一条路径，即为活跃路径
+        self.active.lock().unwrap().get_or_insert(pathway);
+        self.handshake_local
+            .lock()
+            .unwrap()
+            .get_or_insert(pathway.local_addr());
+        path
+    }
+
+    /// Get the path on the `pathway`, to which the peer is rebound from the path on `from`, read
+    /// [`Pathway::is_rebinding_of`].
+    ///
+    /// The path is created as [`Paths::get_or_create`] does, it is validated before the connection
+    /// migrates to it. If only the port of the peer is changed, the new path takes over the RTT
+    /// estimation of the original path, rather than starting over from the initial RTT. The
+    /// congestion window still starts over conservatively, and the original path is kept until it
+    /// becomes inactive, in case the packets on the new pathway are spoofed.
+    ///
+    /// See [section 9.4](https://www.rfc-editor.org/rfc/rfc9000.html#name-loss-detection-and-congesti)
+    /// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
+    pub fn get_or_rebind(&self, from: Pathway, pathway: Pathway, usc: ArcUsc) -> ArcPath {
+        let existed = self.map.contains_key(&pathway);
+        let path = self.get_or_create(pathway, usc);
+        if !existed {
+            // 对端IP也变了，多半已不是同一条网络路径，RTT估计须重新开始
+            let same_ip = from.remote_addr().ip() == pathway.remote_addr().ip();
+            if let Some(original) = self.map.get(&from).filter(|_| same_ip) {
+                path.rtt.inherit(&original.rtt);
+            }
+        }
+        path
+    }
+
+    /// Set the maximum number of the paths, and the maximum number of the unvalidated paths
+    /// among them, [`DEFAULT_MAX_PATHS`] and [`DEFAULT_MAX_UNVALIDATED_PATHS`] by default.
+    ///
+    /// The unvalidated paths are cheap for an attacker to create, so they are limited separately.
+    /// When a new path is about to be created and a limit is reached, the least recently used
+    /// unvalidated path is abandoned, read [`Paths::abandon`]. If all the paths are validated,
+    /// the least recently used one is abandoned instead. The active path is never abandoned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the limits is zero.
+    pub fn set_max_paths(&self, max_paths: usize, max_unvalidated_paths: usize) {
+        assert!(
+            max_paths > 0 && max_unvalidated_paths > 0,
+            "the limits of the paths must be positive"
+        );
+        self.max_paths.store(max_paths, Ordering::Relaxed);
+        self.max_unvalidated_paths
+            .store(max_unvalidated_paths, Ordering::Relaxed);
+    }
+
+    // 为即将创建的新路径腾出空间，按最近收包时间淘汰，优先淘汰未验证的路径
+    fn make_room(&self) {
+        let max_paths = self.max_paths.load(Ordering::Relaxed);
+        let max_unvalidated_paths = self.max_unvalidated_paths.load(Ordering::Relaxed);
+        loop {
+            let active = self.active_pathway();
+            let candidates = self
+                .map
+                .iter()
+                .filter(|entry| Some(*entry.key()) != active)
+                .map(|entry| (*entry.key(), entry.is_validated(), entry.recv_time()))
+                .collect::<Vec<_>>();
+            let unvalidated = candidates
+                .iter()
+                .filter(|(_, validated, _)| !validated)
+                .count();
+
+            // 已失活的路径收包时间为None，最先被淘汰
+            let victim = if unvalidated >= max_unvalidated_paths
+                || (self.map.len() >= max_paths && unvalidated > 0)
+            {
+                candidates
+                    .iter()
+                    .filter(|(_, validated, _)| !validated)
+                    .min_by_key(|(_, _, recv_time)| *recv_time)
+            } else if self.map.len() >= max_paths {
+                candidates.iter().min_by_key(|(_, _, recv_time)| *recv_time)
+            } else {
+                return;
+            };
+            match victim {
+                Some((pathway, ..)) if self.abandon(*pathway) => {}
+                _ => return,
+            }
+        }
+    }
+
+    /// Set how long a validated path other than the active one can stay idle, that is, no packet
+    /// is received on it, before it is abandoned. `None` means 3 times the PTO of the path, which
+    /// is the default.
+    ///
+    /// After the connection migrates, the previous path is left behind, the idle paths are swept
+    /// periodically and abandoned as [`Paths::abandon`] does, so that the paths do not pile up on
+    /// the clients which migrate often. The active path is never swept, neither are the paths being
+    /// validated, which are abandoned if the validation fails.
+    pub fn set_idle_path_timeout(&self, timeout: Option<Duration>) {
+        *self.idle_path_timeout.lock().unwrap() = timeout;
+    }
+
+    fn sweep_idle_paths(&self) {
+        let active = self.active_pathway();
+        let migrating = *self.migrating.lock().unwrap();
+        let idle_path_timeout = *self.idle_path_timeout.lock().unwrap();
+        let now = self.clock.now();
+        let idle_pathways = self
+            .map
+            .iter()
+            .filter(|entry| {
+                let pathway = Some(*entry.key());
+                pathway != active && pathway != migrating && entry.is_validated()
+            })
+            .filter(|entry| {
+                let timeout =
+                    idle_path_timeout.unwrap_or_else(|| entry.cc.pto_time(Epoch::Data) * 3);
+                // 已失活的路径由其监视任务移除
+                entry
+                    .recv_time()
+                    .is_some_and(|recv_time| now.duration_since(recv_time) >= timeout)
+            })
+            .map(|entry| *entry.key())
+            .collect::<Vec<_>>();
+        for pathway in idle_pathways {
+            self.abandon(pathway);
+        }
+    }
+
+    /// Return the active path, which is used for new transmissions.
+    pub fn active_path(&self) -> Option<ArcPath> {
+        let active = (*self.active.lock().unwrap())?;
+        self.map.get(&active).map(|path| path.clone())
+    }
+
+    /// Return the pathway of the active path.
+    pub fn active_pathway(&self) -> Option<Pathway> {
+        *self.active.lock().unwrap()
+    }
+
+    /// Enable the multipath extension, called when both endpoints advertise the
+    /// `enable_multipath` transport parameter.
+    ///
+    /// It follows the early drafts of [Multipath Extension for QUIC](https://datatracker.ietf.org/doc/draft-ietf-quic-multipath/),
+    /// each path sends and receives the 1-RTT packets in the packet number space of the connection
+    /// ID it uses, which are acknowledged by the PATH_ACK frames, read [`DataSpaces`]. The later
+    /// drafts changed the path identifiers and the wire format, so it is negotiated with a private
+    /// transport parameter, only between the peers of this implementation.
+    ///
+    /// [`DataSpaces`]: crate::conn::scope::DataSpaces
+    pub fn enable_multipath(&self) {
+        self.multipath.store(true, Ordering::Release);
+    }
+
+    /// Returns whether the multipath extension is enabled.
+    pub fn is_multipath_enabled(&self) -> bool {
+        self.multipath.load(Ordering::Acquire)
+    }
+
+    /// Set the [`PathScheduler`] deciding which paths carry the new stream data once the
+    /// multipath extension is negotiated, [`MinRttScheduler`] by default.
+    pub fn set_scheduler(&self, scheduler: Arc<dyn PathScheduler>) {
+        *self.scheduler.lock().unwrap() = scheduler;
+    }
+
+    // 可承载流数据的路径：活跃路径以及已验证的路径，拥塞窗口已满的不参与调度
+    fn schedulable_paths(&self) -> Vec<PathStatus> {
+        let active = self.active_pathway();
+        let mut paths = self
+            .map
+            .iter()
+            .filter(|entry| Some(*entry.key()) == active || entry.is_validated())
+            .map(|entry| PathStatus {
+                pathway: *entry.key(),
+                smoothed_rtt: entry.rtt().smoothed_rtt,
+                available_window: entry.cc.available_window(),
+            })
+            .collect::<Vec<_>>();
+        // 所有路径的拥塞窗口都满了时，只有PTO探测包能发出，它们仍参与调度，使探测包可携带流数据
+        if paths.iter().any(|status| status.available_window >= MSS) {
+            paths.retain(|status| status.available_window >= MSS);
+        }
+        paths
+    }
+
+    /// Returns whether the path on the `pathway` may carry new stream data now.
+    ///
+    /// Without the multipath extension, only the active path carries new stream data. The other
+    /// paths still send the path validation frames, the acknowledgments, the control frames and
+    /// the retransmitted data.
+    ///
+    /// With the multipath extension, the active path and the validated paths can all carry new
+    /// stream data, each is limited by its own congestion controller. The paths whose congestion
+    /// window is full are not scheduled unless all of them are full, in which case only the probe
+    /// packets can be sent. Among them, the [`PathScheduler`] decides, read
+    /// [`Paths::set_scheduler`].
+    ///
+    /// It is a query without side effects, read [`Paths::on_stream_sent`].
+    pub fn is_scheduled(&self, pathway: Pathway) -> bool {
+        if !self.is_multipath_enabled() {
+            return self.active_pathway() == Some(pathway);
+        }
+
+        let paths = self.schedulable_paths();
+        if !paths.iter().any(|status| status.pathway == pathway) {
+            return false;
+        }
+        let scheduler = self.scheduler.lock().unwrap().clone();
+        scheduler.is_scheduled(pathway, &paths)
+    }
+
+    /// Called when the path on the `pathway` has sent new stream data, read
+    /// [`PathScheduler::on_stream_sent`].
+    pub fn on_stream_sent(&self, pathway: Pathway) {
+        if self.is_multipath_enabled() {
+            let scheduler = self.scheduler.lock().unwrap().clone();
+            scheduler.on_stream_sent(pathway);
+        }
+    }
+
+    /// Called when the stream `frame` carrying the `data` is sent on the path on the `pathway`.
+    ///
+    /// If the [`PathScheduler`] is redundant, a copy of the stream frame is queued to be sent on
+    /// each of the other schedulable paths, read [`PathScheduler::is_redundant`].
+    pub fn reinject(&self, pathway: Pathway, frame: &StreamFrame, data: &[u8]) {
+        if !self.is_multipath_enabled() || !self.scheduler.lock().unwrap().is_redundant() {
+            return;
+        }
+
+        let data = Bytes::copy_from_slice(data);
+        for status in self.schedulable_paths() {
+            if status.pathway == pathway {
+                continue;
+            }
+            if let Some(path) = self.map.get(&status.pathway) {
+                path.reinject_sndbuf.write(frame.clone(), data.clone());
+            }
+        }
+    }
+
+    /// Refuse the active migrations of the peer, called when the local endpoint advertised the
+    /// `disable_active_migration` transport parameter.
+    ///
+    /// Only the deliberate migrations on the address used during the handshake are refused, read
+    /// [`Paths::is_migration_refused`]. The NAT rebindings of the peer, and the migrations to the
+    /// preferred address of a server are still allowed.
+    ///
+    /// See [section 9](https://www.rfc-editor.org/rfc/rfc9000.html#name-connection-migration)
+    /// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
+    pub fn refuse_active_migration(&self) {
+        self.refuse_active_migration.store(true, Ordering::Release);
+    }
+
+    /// Forbid the local endpoint to migrate actively, called when the peer advertised the
+    /// `disable_active_migration` transport parameter.
+    ///
+    /// Once disabled, [`Paths::migrate_actively`] refuses to migrate. The migration to the
+    /// preferred address of the server is not affected, which is not an active migration on the
+    /// address used during the handshake.
+    pub fn disable_active_migration(&self) {
+        self.disable_active_migration.store(true, Ordering::Release);
+    }
+
+    /// Returns whether the peer has forbidden the local endpoint to migrate actively, read
+    /// [`Paths::disable_active_migration`].
+    pub fn is_active_migration_disabled(&self) -> bool {
+        self.disable_active_migration.load(Ordering::Acquire)
+    }
+
+    /// Migrate the connection to the `pathway` deliberately, for example, the local endpoint
+    /// switches to another network interface.
+    ///
+    /// The path is created if it does not exist, and its validation is started, the connection
+    /// migrates to it once it is validated, read [`Paths::migrate_to`].
+    ///
+    /// Return `false` without doing anything if the peer has disabled the active migration, read
+    /// [`Paths::disable_active_migration`].
+    pub fn migrate_actively(&self, pathway: Pathway, usc: ArcUsc) -> bool {
+        if self.is_active_migration_disabled() {
+            return false;
+        }
+        self.get_or_create(pathway, usc);
+        self.migrate_to(pathway);
+        true
+    }
+
+    /// Returns whether the packets received on the `pathway` should be dropped, because the peer
+    /// migrated actively while the active migration is refused, read
+    /// [`Paths::refuse_active_migration`]. `rebinding` tells whether the pathway is regarded as a
+    /// NAT rebinding of the active one.
+    ///
+    /// A new pathway which is neither a NAT rebinding nor on another local address, such as the
+    /// preferred address of a server, is a deliberate migration of the peer on the address used
+    /// during the handshake. The pathways already known, for example the ones the local endpoint
+    /// migrated to, are never refused.
+    ///
+    /// The packets on such a pathway must be dropped without generating a Stateless Reset, the
+    /// connection is not closed, and no path is created for them.
+    ///
+    /// See [section 9](https://www.rfc-editor.org/rfc/rfc9000.html#section-9-5)
+    /// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
+    pub fn is_migration_refused(&self, pathway: Pathway, rebinding: bool) -> bool {
+        if !self.refuse_active_migration.load(Ordering::Acquire)
+            || rebinding
+            || self.map.contains_key(&pathway)
+        {
+            return false;
+        }
+        *self.handshake_local.lock().unwrap() == Some(pathway.local_addr())
+    }
+
+    /// Called when a non-probing packet with the largest packet number so far is received on the
+    /// `pathway`.
+    ///
+    /// If the `pathway` is not the active one, its validation is started if not yet, and the
+    /// connection will migrate to it, but only after the path is validated. Reordered packets are filtered by the caller, which only calls this
+    /// for the packet with the largest packet number, so that spurious migrations do not happen.
+    ///
+    /// See [section 9.3](https://www.rfc-editor.org/rfc/rfc9000.html#name-responding-to-connection-mi)
+    /// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
+    pub fn on_non_probing_packet(&self, pathway: Pathway) {
+        if *self.active.lock().unwrap() == Some(pathway) {
+            // 回到了当前活跃路径，之前可能正在进行的迁移作废
+            self.migrating.lock().unwrap().take();
+            return;
+        }
+        self.migrate_to(pathway);
+    }
+
+    /// Migrate the connection to the path on the `pathway` once it is validated.
+    ///
+    /// The validation of the path is started if it has not been, read [`Path::begin_validation`].
+    /// The active path is not changed if the validation fails, or the connection migrates to
+    /// another path in the meantime.
+    pub fn migrate_to(&self, pathway: Pathway) {
+        let Some(path) = self.map.get(&pathway).map(|path| path.clone()) else {
+            return;
+        };
+        // 未验证的路径不会被迁移，迁移前路径可能尚未开始验证，例如在握手完成前创建的路径
+        path.begin_validation();
+        if self.migrating.lock().unwrap().replace(pathway) == Some(pathway) {
+            // 已经在迁移到该路径了
+            return;
+        }
+
+        let pathes = self.map.clone();
+        let active = self.active.clone();
+        let migrating = self.migrating.clone();
+        let events = self.events.clone();
+        tokio::spawn(async move {
+            let validated = path.validated().await.is_ok();
+            let mut migrating = migrating.lock().unwrap();
+            // 迁移期间可能又迁移到了别的路径
+            if *migrating != Some(pathway) {
+                return;
+            }
+            migrating.take();
+            if validated && pathes.contains_key(&pathway) {
+                let from = active.lock().unwrap().replace(pathway);
+                if let Some(from) = from.filter(|from| *from != pathway) {
+                    events.emit(PathEvent::MigrationCompleted { from, to: pathway });
+                }
+            }
+        });
+    }
+
+    /// Abandon the path on the `pathway` explicitly, for example, the connection has migrated to
+    /// another path, and the old one is no longer needed.
+    ///
+    /// The path is removed from the set at once, its sending task is stopped, its validation is
+    /// cancelled, and the connection ID it used is retired, with a RETIRE_CONNECTION_ID frame
+    /// being sent to the peer. If it is the active path, another path becomes the active one.
+    ///
+    /// Return `false` if there is no path on the `pathway`, or it is the only path of the
+    /// connection, which can not be abandoned, otherwise the connection would have no path to use.
+    pub fn abandon(&self, pathway: Pathway) -> bool {
+        let mut active = self.active.lock().unwrap();
+        // 不能放弃唯一的路径，否则连接将无路可用
+        if self.map.len() <= 1 {
+            return false;
+        }
+        let Some((_, path)) = self.map.remove(&pathway) else {
+            return false;
+        };
+        if *active == Some(pathway) {
+            *active = self.map.iter().next().map(|entry| *entry.key());
+        }
+        drop(active);
+
+        let mut migrating = self.migrating.lock().unwrap();
+        if *migrating == Some(pathway) {
+            migrating.take();
+        }
+        drop(migrating);
+
+        path.stop_sending();
+        path.response_rcvbuf.dismiss();
+        // 监视任务随之结束，RETIRE_CONNECTION_ID帧也在此时发出
+        path.state.to_inactive();
+        self.events.emit(PathEvent::PathAbandoned(pathway));
+        true
+    }
+
+    /// Release all the paths, called once the connection is closed.
+    ///
+    /// The paths stop sending and become inactive, so that their monitoring tasks end and remove
+    /// them from the set, as [`Paths::abandon`] does to a single path.
+    pub fn release(&self) {
+        for path in self.map.iter() {
+            path.stop_sending();
+            path.response_rcvbuf.dismiss();
+            path.state.to_inactive();
+        }
+    }
+
+    /// Subscribe the [`PathEvent`]s emitted from now on, read [`ArcPathEvents`].
+    pub fn subscribe_events(&self) -> futures::channel::mpsc::UnboundedReceiver<PathEvent> {
+        self.events.subscribe()
     }
 }
 
@@ -127,10 +697,37 @@ impl ArcPathes {
     /// Create a new [`ArcPathes`].
     ///
     /// Read [`Paths::new`] for more information.
+    ///
+    /// The `scheduler` handle is bound to the paths, which is given to the sending tasks of the
+    /// paths by the `creator`, read [`SchedulerHandle`]. So are the `events`, which the sending
+    /// tasks emit [`PathEvent::AmplificationLimited`] to.
     pub fn new(
         creator: Box<dyn Fn(Pathway, ArcUsc) -> ArcPath + Send + Sync + 'static>,
         on_no_path: Arc<dyn Fn() + Send + Sync + 'static>,
+        scheduler: &SchedulerHandle,
+        events: ArcPathEvents,
+        clock: ArcClock,
     ) -> Self {
-        Self(Arc::new(Paths::new(creator, on_no_path)))
+        let pathes = Arc::new(Paths::new(creator, on_no_path, events, clock));
+        scheduler.bind(&pathes);
+        Self(pathes)
+    }
+
+    /// Sweep the idle paths periodically, read [`Paths::set_idle_path_timeout`].
+    ///
+    /// The paths are held weakly by the returned future, which ends once the paths are dropped.
+    /// It is up to the connection to spawn it, along with its other tasks.
+    pub fn sweep_idle_paths_periodically(&self) -> impl Future<Output = ()> + Send + 'static {
+        let pathes = Arc::downgrade(&self.0);
+        let clock = self.clock.clone();
+        async move {
+            loop {
+                clock.sleep_until(clock.now() + SWEEP_INTERVAL).await;
+                let Some(pathes) = pathes.upgrade() else {
+                    break;
+                };
+                pathes.sweep_idle_paths();
+            }
+        }
     }
 }
@@ -20,6 +20,10 @@ pub struct AntiAmplifier<const N: usize> {
     // Each time data is received, credit is increased;
     // each time data is sent, credit is consumed.
     credit: AtomicUsize,
+    // The total amount of data received from and sent to the unvalidated address.
+    // Only counted before the address is validated.
+    rcvd: AtomicUsize,
+    sent: AtomicUsize,
     // If the credit is exhausted, it needs to wait until
     // new data is received before it can continue to send.
     waker: AtomicWaker,
@@ -36,10 +40,33 @@ impl<const N: usize> AntiAmplifier<N> {
         if self.state.load(Ordering::Acquire) != Self::NORMAL {
             return;
         }
+        self.rcvd.fetch_add(amount, Ordering::AcqRel);
         self.credit.fetch_add(amount * N, Ordering::AcqRel);
         self.waker.wake();
     }
 
+    /// Return how many bytes may still be sent to the address.
+    ///
+    /// Once the address is validated, there is no limit anymore, [`usize::MAX`] is returned.
+    /// If the validation failed, nothing can be sent anymore.
+    pub fn budget(&self) -> usize {
+        match self.state.load(Ordering::Acquire) {
+            Self::GRANTED => usize::MAX,
+            Self::ABORTED => 0,
+            _ => self.credit.load(Ordering::Acquire),
+        }
+    }
+
+    /// The total amount of data received from the address before it is validated.
+    pub fn bytes_received(&self) -> usize {
+        self.rcvd.load(Ordering::Acquire)
+    }
+
+    /// The total amount of data sent to the address before it is validated.
+    pub fn bytes_sent(&self) -> usize {
+        self.sent.load(Ordering::Acquire)
+    }
+
     /// This function must only be called by one at a time, and the amount of data sent
     /// must be feed back to the anti-amplifier before poll_apply can be called again.
     pub fn poll_balance(&self, cx: &mut Context<'_>) -> Poll<Option<usize>> {
@@ -74,7 +101,13 @@ impl<const N: usize> AntiAmplifier<N> {
 
     pub fn on_sent(&self, amount: usize) {
         if self.state.load(Ordering::Acquire) == Self::NORMAL {
-            self.credit.fetch_sub(amount, Ordering::AcqRel);
+            self.sent.fetch_add(amount, Ordering::AcqRel);
+            // 不应超发，但即使超发了也不能下溢，否则额度会变成一个极大值
+            _ = self
+                .credit
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |credit| {
+                    Some(credit.saturating_sub(amount))
+                });
         }
     }
 
@@ -89,6 +122,10 @@ impl<const N: usize> AntiAmplifier<N> {
             )
             .is_ok()
         {
+            // 验证通过后，不再计数
+            self.credit.store(0, Ordering::Release);
+            self.rcvd.store(0, Ordering::Release);
+            self.sent.store(0, Ordering::Release);
             self.waker.wake();
         }
     }
@@ -178,4 +215,42 @@ mod tests {
         anti_amplifier.on_sent(5);
         assert_eq!(anti_amplifier.credit.load(Ordering::Acquire), 1);
     }
+
+    #[test]
+    fn test_budget() {
+        let anti_amplifier = ArcAntiAmplifier::<3>::default();
+        assert_eq!(anti_amplifier.budget(), 0);
+
+        anti_amplifier.on_rcvd(100);
+        assert_eq!(anti_amplifier.budget(), 300);
+        assert_eq!(anti_amplifier.bytes_received(), 100);
+
+        anti_amplifier.on_sent(200);
+        assert_eq!(anti_amplifier.budget(), 100);
+        assert_eq!(anti_amplifier.bytes_sent(), 200);
+
+        // over sent should not underflow
+        anti_amplifier.on_sent(200);
+        assert_eq!(anti_amplifier.budget(), 0);
+        assert_eq!(anti_amplifier.bytes_sent(), 400);
+
+        anti_amplifier.grant();
+        assert_eq!(anti_amplifier.budget(), usize::MAX);
+        assert_eq!(anti_amplifier.bytes_received(), 0);
+        assert_eq!(anti_amplifier.bytes_sent(), 0);
+
+        // no longer counted after validated
+        anti_amplifier.on_rcvd(100);
+        anti_amplifier.on_sent(100);
+        assert_eq!(anti_amplifier.bytes_received(), 0);
+        assert_eq!(anti_amplifier.bytes_sent(), 0);
+    }
+
+    #[test]
+    fn test_budget_after_abort() {
+        let anti_amplifier = ArcAntiAmplifier::<3>::default();
+        anti_amplifier.on_rcvd(100);
+        anti_amplifier.abort();
+        assert_eq!(anti_amplifier.budget(), 0);
+    }
 }
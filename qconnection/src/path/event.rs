@@ -0,0 +1,77 @@
+use std::sync::{Arc, Mutex};
+
+use futures::channel::mpsc;
+
+use super::Pathway;
+
+/// The events of the paths of a connection, read [`ArcPathEvents`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathEvent {
+    /// The path on the pathway is validated, it can carry data without the anti-amplification
+    /// limit.
+    PathValidated(Pathway),
+    /// The connection migrated from the active path on `from` to the validated path on `to`.
+    MigrationCompleted { from: Pathway, to: Pathway },
+    /// The path on the pathway is abandoned, or it became inactive, for example, its validation
+    /// failed.
+    PathAbandoned(Pathway),
+    /// The path on the pathway has not been validated, and it can not send anything until more
+    /// data is received from the peer, due to the anti-amplification limit.
+    AmplificationLimited(Pathway),
+}
+
+/// The subscribers of the [`PathEvent`]s of a connection.
+///
+/// The events are produced by the path validation and the migration, each subscriber receives all
+/// the events emitted after it subscribed. The subscribers whose receiver is dropped are removed
+/// when the next event is emitted.
+#[derive(Debug, Clone, Default)]
+pub struct ArcPathEvents(Arc<Mutex<Vec<mpsc::UnboundedSender<PathEvent>>>>);
+
+impl ArcPathEvents {
+    /// Subscribe the events emitted from now on.
+    pub fn subscribe(&self) -> mpsc::UnboundedReceiver<PathEvent> {
+        let (tx, rx) = mpsc::unbounded();
+        self.0.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Emit the `event` to all the subscribers.
+    pub fn emit(&self, event: PathEvent) {
+        self.0
+            .lock()
+            .unwrap()
+            .retain(|tx| tx.unbounded_send(event).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use futures::StreamExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_subscribe() {
+        let pathway = Pathway::Direct {
+            local: SocketAddr::from(([127, 0, 0, 1], 4433)),
+            remote: SocketAddr::from(([127, 0, 0, 1], 4434)),
+        };
+        let events = ArcPathEvents::default();
+        // 订阅之前的事件收不到
+        events.emit(PathEvent::AmplificationLimited(pathway));
+
+        let mut first = events.subscribe();
+        let second = events.subscribe();
+        events.emit(PathEvent::PathValidated(pathway));
+        assert_eq!(first.next().await, Some(PathEvent::PathValidated(pathway)));
+
+        // 已不再接收的订阅者被移除
+        drop(second);
+        events.emit(PathEvent::PathAbandoned(pathway));
+        assert_eq!(events.0.lock().unwrap().len(), 1);
+        assert_eq!(first.next().await, Some(PathEvent::PathAbandoned(pathway)));
+    }
+}
@@ -0,0 +1,272 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use qbase::frame::AckFrame;
+
+/// The smallest maximum datagram size that QUIC requires every path to support, the search
+/// starts from it.
+pub const BASE_PLPMTU: usize = 1200;
+/// The default ceiling of the search, which fits a 1500 bytes Ethernet MTU over IPv6.
+pub const DEFAULT_MAX_PLPMTU: usize = 1452;
+/// The largest datagram size that can be searched, limited by the size of the sending and
+/// receiving buffers.
+pub const MAX_PLPMTU: usize = 1472;
+/// The number of consecutive lost probes of the same size, after which the size is considered
+/// to be black-holed.
+const MAX_PROBES: u8 = 3;
+/// The search completes when the gap between the confirmed size and the black-holed size is
+/// smaller than this value.
+const SEARCH_GRANULARITY: usize = 8;
+
+#[derive(Debug, Clone, Copy)]
+struct Probe {
+    pn: u64,
+    size: usize,
+    deadline: Instant,
+}
+
+/// The Datagram Packetization Layer Path MTU Discovery(DPLPMTUD) of a path.
+///
+/// The search starts from [`BASE_PLPMTU`], padded 1-RTT packets carrying a PING frame are sent
+/// as probes at increasing sizes. A probe is confirmed once it is acknowledged, and the size of
+/// the probe becomes the current MTU. If a probe is not acknowledged in time, it is lost and
+/// will be retried, after 3 losses the size is considered to be black-holed and
+/// the search continues with smaller sizes, the current MTU stays at the last confirmed size.
+///
+/// The loss of a probe is not a signal of congestion, the probes are not counted in flight.
+///
+/// See [RFC 8899](https://www.rfc-editor.org/rfc/rfc8899.html) and
+/// [section 14.3](https://www.rfc-editor.org/rfc/rfc9000.html#name-datagram-packetization-laye)
+/// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
+#[derive(Debug)]
+pub struct PathMtu {
+    // The largest size confirmed by an acknowledged probe.
+    plpmtu: usize,
+    // The ceiling of the search.
+    max_plpmtu: usize,
+    // The smallest size that is considered to be black-holed.
+    black_hole: Option<usize>,
+    // The probe in flight.
+    probe: Option<Probe>,
+    // The number of consecutive lost probes of the current probe size.
+    lost_probes: u8,
+}
+
+impl PathMtu {
+    /// Create a new [`PathMtu`] with the ceiling of the search, which is clamped to
+    /// [`BASE_PLPMTU`]..=[`MAX_PLPMTU`].
+    pub fn new(max_plpmtu: usize) -> Self {
+        Self {
+            plpmtu: BASE_PLPMTU,
+            max_plpmtu: max_plpmtu.clamp(BASE_PLPMTU, MAX_PLPMTU),
+            black_hole: None,
+            probe: None,
+            lost_probes: 0,
+        }
+    }
+
+    /// The last confirmed MTU of the path.
+    pub fn current_mtu(&self) -> usize {
+        self.plpmtu
+    }
+
+    /// The ceiling of the search.
+    pub fn max_mtu(&self) -> usize {
+        self.max_plpmtu
+    }
+
+    /// Lower the ceiling of the search, for example, to the `max_udp_payload_size` transport
+    /// parameter of the peer.
+    pub fn limit_max_mtu(&mut self, max_plpmtu: usize) {
+        self.max_plpmtu = self.max_plpmtu.min(max_plpmtu.max(BASE_PLPMTU));
+        self.plpmtu = self.plpmtu.min(self.max_plpmtu);
+    }
+
+    /// Return the size of the next probe, or [`None`] if a probe is in flight or the search has
+    /// completed.
+    pub fn probe_size(&mut self, now: Instant) -> Option<usize> {
+        if let Some(probe) = self.probe {
+            if now < probe.deadline {
+                return None;
+            }
+            self.on_probe_lost(probe);
+        }
+
+        let high = match self.black_hole {
+            Some(black_hole) => (black_hole - 1).min(self.max_plpmtu),
+            // 先尝试上限，大多数路径都支持
+            None => return (self.max_plpmtu > self.plpmtu).then_some(self.max_plpmtu),
+        };
+        if high < self.plpmtu + SEARCH_GRANULARITY {
+            return None;
+        }
+        Some((self.plpmtu + high + 1) / 2)
+    }
+
+    /// Called when a probe packet is sent, the probe is considered lost if it is not
+    /// acknowledged within `timeout`.
+    pub fn on_probe_sent(&mut self, pn: u64, size: usize, now: Instant, timeout: Duration) {
+        self.probe = Some(Probe {
+            pn,
+            size,
+            deadline: now + timeout,
+        });
+    }
+
+    /// Called when an AckFrame is received in the data space.
+    pub fn on_ack(&mut self, ack_frame: &AckFrame) {
+        let Some(probe) = self.probe else {
+            return;
+        };
+        if ack_frame.iter().any(|range| range.contains(&probe.pn)) {
+            self.probe = None;
+            self.lost_probes = 0;
+            self.plpmtu = self.plpmtu.max(probe.size);
+        }
+    }
+
+    fn on_probe_lost(&mut self, probe: Probe) {
+        self.probe = None;
+        self.lost_probes += 1;
+        if self.lost_probes >= MAX_PROBES {
+            // 该大小的数据包被黑洞吞掉，回退到上次确认的大小，继续搜索更小的
+            self.lost_probes = 0;
+            self.black_hole = Some(self.black_hole.map_or(probe.size, |s| s.min(probe.size)));
+        }
+    }
+}
+
+impl Default for PathMtu {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_PLPMTU)
+    }
+}
+
+/// The shared version of [`PathMtu`].
+#[derive(Debug, Clone, Default)]
+pub struct ArcPathMtu(Arc<Mutex<PathMtu>>);
+
+impl ArcPathMtu {
+    /// Create a new [`ArcPathMtu`], read [`PathMtu::new`] for more details.
+    pub fn new(max_plpmtu: usize) -> Self {
+        Self(Arc::new(Mutex::new(PathMtu::new(max_plpmtu))))
+    }
+
+    /// Read [`PathMtu::current_mtu`].
+    pub fn current_mtu(&self) -> usize {
+        self.0.lock().unwrap().current_mtu()
+    }
+
+    /// Read [`PathMtu::max_mtu`].
+    pub fn max_mtu(&self) -> usize {
+        self.0.lock().unwrap().max_mtu()
+    }
+
+    /// Read [`PathMtu::limit_max_mtu`].
+    pub fn limit_max_mtu(&self, max_plpmtu: usize) {
+        self.0.lock().unwrap().limit_max_mtu(max_plpmtu);
+    }
+
+    /// Read [`PathMtu::probe_size`].
+    pub fn probe_size(&self, now: Instant) -> Option<usize> {
+        self.0.lock().unwrap().probe_size(now)
+    }
+
+    /// Read [`PathMtu::on_probe_sent`].
+    pub fn on_probe_sent(&self, pn: u64, size: usize, now: Instant, timeout: Duration) {
+        self.0.lock().unwrap().on_probe_sent(pn, size, now, timeout);
+    }
+
+    /// Read [`PathMtu::on_ack`].
+    pub fn on_ack(&self, ack_frame: &AckFrame) {
+        self.0.lock().unwrap().on_ack(ack_frame);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use qbase::varint::VarInt;
+
+    use super::*;
+
+    fn ack(pn: u64) -> AckFrame {
+        AckFrame {
+            largest: VarInt::from_u64(pn).unwrap(),
+            delay: VarInt::from_u32(0),
+            first_range: VarInt::from_u32(0),
+            ranges: vec![],
+            ecn: None,
+        }
+    }
+
+    #[test]
+    fn test_search_up_to_ceiling() {
+        let mut mtu = PathMtu::default();
+        let now = Instant::now();
+        let timeout = Duration::from_millis(100);
+        assert_eq!(mtu.current_mtu(), BASE_PLPMTU);
+
+        assert_eq!(mtu.probe_size(now), Some(DEFAULT_MAX_PLPMTU));
+        mtu.on_probe_sent(1, DEFAULT_MAX_PLPMTU, now, timeout);
+        assert_eq!(mtu.probe_size(now), None);
+        // 普通数据包的确认不影响探测
+        mtu.on_ack(&ack(0));
+        assert_eq!(mtu.current_mtu(), BASE_PLPMTU);
+
+        mtu.on_ack(&ack(1));
+        assert_eq!(mtu.current_mtu(), DEFAULT_MAX_PLPMTU);
+        assert_eq!(mtu.probe_size(now), None);
+    }
+
+    #[test]
+    fn test_black_holed_probe() {
+        let mut mtu = PathMtu::default();
+        let mut now = Instant::now();
+        let timeout = Duration::from_millis(100);
+        let mut pn = 0;
+
+        // 上限被黑洞吞掉
+        for _ in 0..MAX_PROBES {
+            assert_eq!(mtu.probe_size(now), Some(DEFAULT_MAX_PLPMTU));
+            mtu.on_probe_sent(pn, DEFAULT_MAX_PLPMTU, now, timeout);
+            pn += 1;
+            now += timeout;
+        }
+        assert_eq!(mtu.current_mtu(), BASE_PLPMTU);
+
+        // 二分搜索，确认一个较小的大小
+        let size = mtu.probe_size(now).unwrap();
+        assert!(size > BASE_PLPMTU && size < DEFAULT_MAX_PLPMTU);
+        mtu.on_probe_sent(pn, size, now, timeout);
+        mtu.on_ack(&ack(pn));
+        pn += 1;
+        assert_eq!(mtu.current_mtu(), size);
+
+        // 更大的大小又被黑洞吞掉，回退到上次确认的大小
+        let larger = mtu.probe_size(now).unwrap();
+        assert!(larger > size && larger < DEFAULT_MAX_PLPMTU);
+        for _ in 0..MAX_PROBES {
+            assert_eq!(mtu.probe_size(now), Some(larger));
+            mtu.on_probe_sent(pn, larger, now, timeout);
+            pn += 1;
+            now += timeout;
+            // 迟到的确认不会被采纳
+            mtu.on_ack(&ack(pn - 2));
+        }
+        assert_eq!(mtu.probe_size(now).map(|s| s < larger), Some(true));
+        assert_eq!(mtu.current_mtu(), size);
+    }
+
+    #[test]
+    fn test_limit_max_mtu() {
+        let mut mtu = PathMtu::new(MAX_PLPMTU + 100);
+        assert_eq!(mtu.probe_size(Instant::now()), Some(MAX_PLPMTU));
+        mtu.limit_max_mtu(1300);
+        assert_eq!(mtu.probe_size(Instant::now()), Some(1300));
+        mtu.limit_max_mtu(1000);
+        assert_eq!(mtu.probe_size(Instant::now()), None);
+        assert_eq!(mtu.current_mtu(), BASE_PLPMTU);
+    }
+}
@@ -40,6 +40,28 @@ impl Pathway {
             Pathway::Relay { remote, .. } => remote.agent,
         }
     }
+
+    /// Returns whether only the address of the peer differs from the `other` pathway, which may be
+    /// the result of a NAT rebinding.
+    ///
+    /// A NAT may change the port of the peer, as well as its IP address. Such a change on an
+    /// existing connection ID is treated as a possible rebinding, and the new path is validated
+    /// before the connection migrates to it.
+    ///
+    /// See [section 9.3](https://www.rfc-editor.org/rfc/rfc9000.html#name-responding-to-connection-mi)
+    /// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
+    pub fn is_rebinding_of(&self, other: &Pathway) -> bool {
+        match (self, other) {
+            (
+                Pathway::Direct { local, remote },
+                Pathway::Direct {
+                    local: other_local,
+                    remote: other_remote,
+                },
+            ) => local == other_local && remote != other_remote,
+            _ => false,
+        }
+    }
 }
 
 impl PartialEq for Pathway {
@@ -258,4 +280,31 @@ mod tests {
         map.insert(direct2, 2);
         assert_eq!(map.get(&direct1), Some(2).as_ref());
     }
+
+    #[test]
+    fn test_rebinding() {
+        let local: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+        let original = Pathway::Direct {
+            local,
+            remote: "192.168.1.1:5678".parse().unwrap(),
+        };
+        let rebound = Pathway::Direct {
+            local,
+            remote: "192.168.1.1:5679".parse().unwrap(),
+        };
+        let readdressed = Pathway::Direct {
+            local,
+            remote: "192.168.1.2:5678".parse().unwrap(),
+        };
+        let migrated = Pathway::Direct {
+            local: "127.0.0.1:4321".parse().unwrap(),
+            remote: "192.168.1.1:5678".parse().unwrap(),
+        };
+        assert!(rebound.is_rebinding_of(&original));
+        assert!(readdressed.is_rebinding_of(&original));
+        assert!(readdressed.is_rebinding_of(&rebound));
+        assert!(!original.is_rebinding_of(&original));
+        assert!(!migrated.is_rebinding_of(&original));
+        assert!(!migrated.is_rebinding_of(&rebound));
+    }
 }
@@ -1,31 +1,79 @@
 use std::{
-    sync::{atomic::AtomicBool, Arc},
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
     time::Duration,
 };
 
 use qbase::{
     cid::{ArcCidCell, ConnectionId},
+    entropy::ArcEntropy,
     flow::FlowController,
     frame::{PathChallengeFrame, PathResponseFrame},
+    packet::SpinBit,
+    qlog::{PacketEvent, PacketType, QlogSink},
+    sid::Role,
+};
+use qcongestion::{
+    ArcCC, ArcRtt, CongestionControl, CongestionController, EcnState, MayLoss, RetirePktRecord,
+    RttSample,
 };
-use qcongestion::{ArcCC, CongestionAlgorithm, CongestionControl, MayLoss, RetirePktRecord};
-use qrecovery::{reliable::ArcReliableFrameDeque, space::Epoch};
-use tokio::time::timeout;
+use qrecovery::reliable::ArcReliableFrameDeque;
+use thiserror::Error;
+use tokio::{sync::watch, task::AbortHandle};
 
 use super::{
     anti_amplifier::{ArcAntiAmplifier, ANTI_FACTOR},
+    mtu::ArcPathMtu,
     read::ReadIntoDatagrams,
+    spin::ArcSpinBit,
     state::ArcPathState,
-    util::{RecvBuffer, SendBuffer},
-    Pathway,
+    util::{RecvBuffer, ReinjectBuffer, SendBuffer},
+    ArcPathEvents, Pathway, Reinjection, SchedulerHandle,
 };
 use crate::{
-    conn::transmit::{
-        data::DataSpaceReader, handshake::HandshakeSpaceReader, initial::InitialSpaceReader,
+    clock::ArcClock,
+    conn::{
+        scope::PathSpaces,
+        stats::ArcPacketCounters,
+        transmit::{
+            data::DataSpaceReader, handshake::HandshakeSpaceReader, initial::InitialSpaceReader,
+        },
     },
     usc::ArcUsc,
 };
 
+/// The maximum number of times the [`PathChallengeFrame`] is sent during path validation.
+const MAX_CHALLENGE_TIMES: usize = 3;
+
+/// The reason why the path validation failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum ValidationError {
+    /// No correct [`PathResponseFrame`] was received before the last challenge timed out.
+    #[error("path validation timed out")]
+    Timeout,
+    /// The path validation was cancelled, the path is no longer used.
+    #[error("path validation was cancelled")]
+    Cancelled,
+}
+
+/// The settings and the components of the connection, used to create a [`Path`].
+///
+/// Read [`Path::new`] for what each of them is used for.
+#[derive(Clone)]
+pub struct PathContext {
+    pub role: Role,
+    pub initial_rtt: Duration,
+    pub max_mtu: usize,
+    pub counters: ArcPacketCounters,
+    pub qlog: Option<Arc<dyn QlogSink>>,
+    pub clock: ArcClock,
+    pub entropy: ArcEntropy,
+    pub spaces: PathSpaces,
+}
+
 /// A single path of a connection.
 ///
 /// This is a path in QUIC, it also corresponds to the real network path([`Pathway`]). Each path is
@@ -48,18 +96,34 @@ use crate::{
 pub struct Path {
     pub anti_amplifier: ArcAntiAmplifier<ANTI_FACTOR>,
     pub cc: ArcCC,
+    pub mtu: ArcPathMtu,
+    pub(super) rtt: ArcRtt,
     pub(super) usc: ArcUsc,
+    pub(super) role: Role,
     pub(super) dcid: ArcCidCell<ArcReliableFrameDeque>,
     pub(super) scid: ConnectionId,
-    pub(super) spin: Arc<AtomicBool>,
+    pub(super) spin: ArcSpinBit,
     pub(super) challenge_sndbuf: SendBuffer<PathChallengeFrame>,
     pub(super) response_sndbuf: SendBuffer<PathResponseFrame>,
     pub(super) response_rcvbuf: RecvBuffer<PathResponseFrame>,
+    pub(super) reinject_sndbuf: ReinjectBuffer,
     pub(super) state: ArcPathState,
+    pub(super) sending_task: Arc<Mutex<Option<AbortHandle>>>,
+    pub(super) validating: Arc<AtomicBool>,
+    // 路径验证的结果，可被多个任务同时等待
+    pub(super) validation: Arc<watch::Sender<Option<Result<(), ValidationError>>>>,
+    pub(super) counters: ArcPacketCounters,
+    pub(super) qlog: Option<Arc<dyn QlogSink>>,
+    pub(super) clock: ArcClock,
+    pub(super) entropy: ArcEntropy,
+    pub(super) spaces: PathSpaces,
 }
 
 impl Path {
-    /// Create a new path.
+    /// Create a new path, the settings of the connection are given by the `ctx`.
+    ///
+    /// The `role` is the role of the endpoint, which decides how the latency spin bit is set, see
+    /// [`ArcSpinBit`] for more details, and when the Initial keys are discarded.
     ///
     /// The `scid` is the initial source connection id of the connection, the scid is used for
     /// assmebling long header packets.
@@ -70,36 +134,142 @@ impl Path {
     /// connections id for security reasons. [`ArcCidCell`] is a structure through which the path
     /// can asynchronously obtain an available connection ID.
     ///
+    /// The `controller` is the congestion control algorithm used by this path, which limits how
+    /// many bytes the sending task can send, see [`CongestionController`] for more details. The
+    /// path owns a RTT estimator, which is shared with the congestion controller to arm the PTO
+    /// timer, the estimator assumes the `initial_rtt` before the first RTT sample is taken.
+    ///
+    /// The packets sent and received on this path are counted in the `counters`, which are shared
+    /// by all the paths of the connection. If the `qlog` sink is given, they are also recorded to
+    /// it, along with the events of the congestion controller.
+    ///
     /// `loss` and `retire` are used to feed back the lost packets and the retired packets to the
     /// space. They are arrays, each element corresponds to a space: intiial space, handshake space,
     /// and data space.
     ///
+    /// The `max_mtu` is the ceiling of the path MTU discovery, see [`PathMtu`] for more details.
+    ///
+    /// The timers of the path, such as the loss detection timer and the path validation timer,
+    /// read the time from the `clock`. The data of the [`PathChallengeFrame`]s is filled by the
+    /// `entropy` source.
+    ///
+    /// The 1-RTT packets are sent and received in the packet number `spaces` of the path, which
+    /// are the shared space 0 unless the multipath extension is enabled, read [`PathSpaces`].
+    ///
+    /// [`PathMtu`]: super::PathMtu
     pub fn new(
         usc: ArcUsc,
         scid: ConnectionId,
         dcid: ArcCidCell<ArcReliableFrameDeque>,
+        controller: Box<dyn CongestionController>,
         loss: [Box<dyn MayLoss>; 3],
         retire: [Box<dyn RetirePktRecord>; 3],
+        ctx: PathContext,
     ) -> Self {
+        let PathContext {
+            role,
+            initial_rtt,
+            max_mtu,
+            counters,
+            qlog,
+            clock,
+            entropy,
+            spaces,
+        } = ctx;
+        let rtt = ArcRtt::with_initial_rtt(initial_rtt);
         Self {
             usc,
+            role,
             dcid: dcid.clone(),
             scid,
             cc: ArcCC::new(
-                CongestionAlgorithm::Bbr,
+                controller,
+                rtt.clone(),
                 Duration::from_micros(100),
                 loss,
                 retire,
+                qlog.clone(),
+                clock.clone(),
             ),
+            rtt,
+            mtu: ArcPathMtu::new(max_mtu),
             anti_amplifier: ArcAntiAmplifier::<ANTI_FACTOR>::default(),
-            spin: Arc::new(AtomicBool::new(false)),
+            spin: ArcSpinBit::new(role),
             challenge_sndbuf: SendBuffer::default(),
             response_sndbuf: SendBuffer::default(),
             response_rcvbuf: RecvBuffer::default(),
-            state: ArcPathState::new(dcid),
+            reinject_sndbuf: ReinjectBuffer::default(),
+            state: ArcPathState::new(dcid, clock.clone()),
+            sending_task: Arc::default(),
+            validating: Arc::default(),
+            validation: Arc::new(watch::channel(None).0),
+            counters,
+            qlog,
+            clock,
+            entropy,
+            spaces,
         }
     }
 
+    /// Returns the last confirmed MTU of this path, see [`PathMtu`] for more details.
+    ///
+    /// The full datagrams assembled by the sending task are of this size.
+    ///
+    /// [`PathMtu`]: super::PathMtu
+    pub fn current_mtu(&self) -> usize {
+        self.mtu.current_mtu()
+    }
+
+    /// Returns the packet number spaces the 1-RTT packets are sent and received in on this path.
+    pub fn spaces(&self) -> &PathSpaces {
+        &self.spaces
+    }
+
+    /// Returns a snapshot of the RTT estimation of this path.
+    pub fn rtt(&self) -> RttSample {
+        self.rtt.sample()
+    }
+
+    /// Returns the state of the ECN validation of this path.
+    ///
+    /// The datagrams sent on the path are marked with ECT(0) while it is testing or capable.
+    pub fn ecn_state(&self) -> EcnState {
+        self.cc.ecn_state()
+    }
+
+    /// Set the `max_ack_delay` transport parameter of the peer to the RTT estimator.
+    pub fn set_max_ack_delay(&self, max_ack_delay: Duration) {
+        self.rtt.set_max_ack_delay(max_ack_delay);
+    }
+
+    /// Set the `ack_delay_exponent` transport parameter of the peer to the RTT estimator.
+    pub fn set_ack_delay_exponent(&self, ack_delay_exponent: u8) {
+        self.rtt.set_ack_delay_exponent(ack_delay_exponent);
+    }
+
+    /// Enable or disable the pacing of the sending task, it is enabled by default.
+    ///
+    /// When enabled, the packets are spread over the RTT according to the pacing rate of the
+    /// congestion controller, or `congestion_window / smoothed_rtt` if it does not give one. Low
+    /// latency applications may disable it to send the available congestion window in a burst.
+    pub fn set_pacing_enabled(&self, enabled: bool) {
+        self.cc.set_pacing_enabled(enabled);
+    }
+
+    /// Enable or disable the latency spin bit of the 1-RTT packets sent on this path.
+    ///
+    /// By default, the spin bit is disabled randomly on one in every 16 paths for privacy, read
+    /// [`ArcSpinBit`] for more details.
+    pub fn set_spin_enabled(&self, enabled: bool) {
+        self.spin.set_enabled(enabled);
+    }
+
+    /// Called when a 1-RTT packet with the largest packet number so far is received, the spin bit
+    /// to send is updated according to the spin bit of it.
+    pub fn on_spin_rcvd(&self, pn: u64, spin: SpinBit) {
+        self.spin.on_rcvd(pn, spin);
+    }
+
     /// Called when a [`PathResponseFrame`] is received.
     pub fn recv_response(&self, frame: PathResponseFrame) {
         self.response_rcvbuf.write(frame);
@@ -110,51 +280,91 @@ impl Path {
         self.response_sndbuf.write(frame.into());
     }
 
+    /// Validate the path, return a future that resolves when the [`path validation`] completes.
+    ///
+    /// A [`PathChallengeFrame`] with 8 random bytes will be sent to the peer, and the future waits
+    /// for a [`PathResponseFrame`] carrying the same data. If the response is not received in time,
+    /// the same challenge will be sent again, at most 3 times. The first timeout is a PTO, and it is
+    /// doubled after each retry(exponential backoff), so a delayed but correct response to an earlier
+    /// challenge is still accepted. Wrong responses are ignored.
+    ///
+    /// [`path validation`]: https://www.rfc-editor.org/rfc/rfc9000.html#name-path-validation
+    pub fn validate(&self) -> impl Future<Output = Result<(), ValidationError>> + Send + 'static {
+        let challenge_sndbuf = self.challenge_sndbuf.clone();
+        let response_rcvbuf = self.response_rcvbuf.clone();
+        let pto = self.rtt.pto(true);
+        let challenge = PathChallengeFrame::from_entropy(&*self.entropy);
+        validate(
+            challenge,
+            challenge_sndbuf,
+            response_rcvbuf,
+            pto,
+            self.clock.clone(),
+        )
+    }
+
     /// Start the [`path verification`] task.
     ///
-    /// The path verification task will send a [`PathChallengeFrame`] to the peer, and wait for the
-    /// response. If the response is received, the path is verified and the anti-amplifier limit is
-    /// grant.
+    /// The path verification task runs [`Path::validate`] in background. If the response is
+    /// received, the path is verified and the anti-amplifier limit is grant. Before that, the path
+    /// is subject to the anti-amplifier limit. If the path verification fails, the path will be
+    /// marked as inactive.
     ///
-    /// The validate task will be executed at most 3 times, each time the task will wait for a PTO.
-    /// If the response is not received within the PTO, the task will be executed again(send the same
-    /// challenge frame). If the response is not received after 3 times, the path verification fails
-    /// and the path will be marked as inactive.
+    /// The path is validated only once, the calls after the first one do nothing.
     ///
     /// [`path verification`]: https://www.rfc-editor.org/rfc/rfc9000.html#name-path-validation
     pub fn begin_validation(&self) {
+        if self.validating.swap(true, Ordering::AcqRel) {
+            return;
+        }
         let anti_amplifier = self.anti_amplifier.clone();
-        let challenge_sndbuf = self.challenge_sndbuf.clone();
-        let response_rcvbuf = self.response_rcvbuf.clone();
-        // THINK: 这里应该只需要一个ArcRtt，并不需congestion controller出面
-        let congestion_ctrl = self.cc.clone();
         let state = self.state.clone();
+        let validation = self.validation.clone();
+        let validate = self.validate();
         tokio::spawn(async move {
-            let challenge = PathChallengeFrame::random();
-            for _ in 0..3 {
-                let pto = congestion_ctrl.pto_time(Epoch::Data);
-                challenge_sndbuf.write(challenge);
-                match timeout(pto, response_rcvbuf.receive()).await {
-                    Ok(Some(response)) if *response == *challenge => {
-                        anti_amplifier.grant();
-                        return;
-                    }
-                    // 外部发生变化，导致路径验证任务作废
-                    Ok(None) => return,
-                    // 超时或者收到不对的response，按"停-等协议"，继续再发一次Challenge，最多3次
-                    _ => continue,
+            let result = validate.await;
+            validation.send_replace(Some(result));
+            match result {
+                Ok(()) => anti_amplifier.grant(),
+                // 外部发生变化，导致路径验证任务作废
+                Err(ValidationError::Cancelled) => {}
+                Err(ValidationError::Timeout) => {
+                    anti_amplifier.abort();
+                    state.to_inactive();
                 }
             }
-            anti_amplifier.abort();
-            state.to_inactive();
         });
     }
 
+    /// Wait for the path validation started by [`Path::begin_validation`] to complete.
+    ///
+    /// Only the result of the validation is returned, multiple tasks can wait for it at the same
+    /// time. If the validation is never started, it will never complete.
+    pub async fn validated(&self) -> Result<(), ValidationError> {
+        let mut validation = self.validation.subscribe();
+        let result = validation
+            .wait_for(Option::is_some)
+            .await
+            .expect("the sender is held by the path");
+        (*result).unwrap()
+    }
+
+    /// Returns whether the path validation has been started by [`Path::begin_validation`].
+    pub fn has_begun_validation(&self) -> bool {
+        self.validating.load(Ordering::Acquire)
+    }
+
+    /// Returns whether the path has been validated successfully.
+    pub fn is_validated(&self) -> bool {
+        matches!(*self.validation.borrow(), Some(Ok(())))
+    }
+
     /// Start the sending task of the path.
     ///
     /// The sending task will read data from the space readers and send them to the peer via the
     /// [`Pathway`]. The sending task will continue to run until the path is marked as inactive or
-    /// connection is closed.
+    /// connection is closed, or it is stopped by [`Path::stop_sending`]. If the sending task has
+    /// been started before, the previous one will be aborted.
     ///
     /// While sending, if a UDP error occurs, the path will be marked as inactive and the sending
     /// task will be terminated.
@@ -163,40 +373,101 @@ impl Path {
     /// read data from the space readers and fill the datagrams. You can also check the space readers
     /// ([`InitialSpaceReader`], [`HandshakeSpaceReader`], [`DataSpaceReader`]) to know how data is
     /// read from the space.
-    pub fn begin_sending<G>(&self, pathway: Pathway, flow_ctrl: &FlowController, gen_readers: G)
-    where
-        G: Fn(&Path) -> (InitialSpaceReader, HandshakeSpaceReader, DataSpaceReader),
+    ///
+    /// Whether the path carries new stream data is decided by the `scheduler`, read
+    /// [`Paths::is_scheduled`]. The [`Reinjection`] of the path is given to the data space reader,
+    /// for the redundant scheduler.
+    ///
+    /// Once the sending task is blocked by the anti-amplification limit, a
+    /// [`PathEvent::AmplificationLimited`] is emitted to the `events`.
+    ///
+    /// [`Paths::is_scheduled`]: super::Paths::is_scheduled
+    /// [`PathEvent::AmplificationLimited`]: super::PathEvent::AmplificationLimited
+    pub fn begin_sending<G>(
+        &self,
+        pathway: Pathway,
+        scheduler: &SchedulerHandle,
+        events: &ArcPathEvents,
+        flow_ctrl: &FlowController,
+        gen_readers: G,
+    ) where
+        G: Fn(&Path, Reinjection) -> (InitialSpaceReader, HandshakeSpaceReader, DataSpaceReader),
     {
         let usc = self.usc.clone();
         let state = self.state.clone();
-        let space_readers = gen_readers(self);
+        let cc = self.cc.clone();
+        let clock = self.clock.clone();
+        let reinjection =
+            Reinjection::new(pathway, self.reinject_sndbuf.clone(), scheduler.clone());
+        let space_readers = gen_readers(self, reinjection);
         let read_into_datagram = ReadIntoDatagrams {
+            pathway,
+            scheduler: scheduler.clone(),
+            events: events.clone(),
+            is_amplification_limited: AtomicBool::new(false),
+            role: self.role,
             scid: self.scid,
             dcid: self.dcid.clone(),
             cc: self.cc.clone(),
+            mtu: self.mtu.clone(),
             anti_amplifier: self.anti_amplifier.clone(),
             spin: self.spin.clone(),
             send_flow_ctrl: flow_ctrl.sender(),
             initial_space_reader: space_readers.0,
             handshake_space_reader: space_readers.1,
             data_space_reader: space_readers.2,
+            counters: self.counters.clone(),
+            qlog: self.qlog.clone(),
+            clock: self.clock.clone(),
         };
 
-        tokio::spawn(async move {
+        let sending_task = tokio::spawn(async move {
             let mut datagrams = Vec::with_capacity(4);
             loop {
+                // pacer的令牌不足一个包时，poll_send不会给出额度，睡到令牌补足的时刻再读，而不是等待周期性的tick
+                let pacing_delay = cc.pacing_delay();
+                let paced = clock.sleep_until(clock.now() + pacing_delay);
                 let io_vecs = tokio::select! {
                     _ = state.has_been_inactivated() => break,
+                    _ = paced, if !pacing_delay.is_zero() => continue,
                     io_vecs = read_into_datagram.read(&mut datagrams) => io_vecs,
                 };
                 let Some(io_vecs) = io_vecs else { break };
-                let send_all = usc.send_all_via_pathway(&io_vecs, pathway);
+                let send_all = usc.send_all_via_pathway(&io_vecs, pathway, cc.ecn());
                 if let Err(_udp_error) = send_all.await {
                     state.to_inactive();
                     break;
                 }
             }
         });
+        let previous = self
+            .sending_task
+            .lock()
+            .unwrap()
+            .replace(sending_task.abort_handle());
+        if let Some(previous) = previous {
+            previous.abort();
+        }
+    }
+
+    /// Stop the sending task of the path, if it is running.
+    ///
+    /// The sending task will also stop automatically when the path is inactive, this method is used
+    /// to stop it explicitly, for example, when the connection is closing, the path should not send
+    /// any data except the connection close frame, which is sent directly via the [`ArcUsc`].
+    pub fn stop_sending(&self) {
+        if let Some(sending_task) = self.sending_task.lock().unwrap().take() {
+            sending_task.abort();
+        }
+    }
+
+    /// Returns whether the sending task of the path is running.
+    pub fn is_sending(&self) -> bool {
+        self.sending_task
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|sending_task| !sending_task.is_finished())
     }
 
     /// Get the buffer that can read the [`PathChallengeFrame`] path wants to send.
@@ -209,6 +480,11 @@ impl Path {
         self.response_sndbuf.clone()
     }
 
+    /// Get the buffer that can read the copies of the stream frames sent on the other paths.
+    pub fn reinject_sndbuf(&self) -> ReinjectBuffer {
+        self.reinject_sndbuf.clone()
+    }
+
     /// Sets the receive time to the current instant, and updates the anti-amplifier limit.
     #[inline]
     pub fn on_rcvd(&self, amount: usize) {
@@ -216,15 +492,139 @@ impl Path {
         self.update_recv_time();
     }
 
+    /// Count the packet received in the statistics of the connection, and record a
+    /// `transport:packet_received` event if a qlog sink is attached.
+    pub fn on_packet_rcvd(&self, packet_type: PacketType, packet_number: u64, length: usize) {
+        self.counters.on_packet_rcvd(packet_type, length);
+        if let Some(qlog) = &self.qlog {
+            qlog.packet_received(PacketEvent {
+                packet_type,
+                packet_number,
+                length,
+            });
+        }
+    }
+
+    /// Return how many bytes may still be sent on the path before it is validated.
+    ///
+    /// See [section 8.1](https://www.rfc-editor.org/rfc/rfc9000.html#name-address-validation)
+    /// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
+    #[inline]
+    pub fn anti_amplification_budget(&self) -> usize {
+        self.anti_amplifier.budget()
+    }
+
     /// Sets the receive time to the current instant.
     #[inline]
     pub fn update_recv_time(&self) {
         self.state.update_recv_time()
     }
 
+    /// Returns the time when the last packet was received on the path, [`None`] if the path is
+    /// inactive.
+    #[inline]
+    pub fn recv_time(&self) -> Option<std::time::Instant> {
+        self.state.recv_time()
+    }
+
     /// Get the udp socket controller of the path.
     #[inline]
     pub fn usc(&self) -> &ArcUsc {
         &self.usc
     }
 }
+
+async fn validate(
+    challenge: PathChallengeFrame,
+    challenge_sndbuf: SendBuffer<PathChallengeFrame>,
+    response_rcvbuf: RecvBuffer<PathResponseFrame>,
+    mut pto: Duration,
+    clock: ArcClock,
+) -> Result<(), ValidationError> {
+    for _ in 0..MAX_CHALLENGE_TIMES {
+        challenge_sndbuf.write(challenge);
+        let deadline = clock.now() + pto;
+        loop {
+            let response = tokio::select! {
+                response = response_rcvbuf.receive() => response,
+                // 超时，按"停-等协议"，退避后再发一次Challenge，最多3次
+                _ = clock.sleep_until(deadline) => break,
+            };
+            match response {
+                Some(response) if *response == *challenge => return Ok(()),
+                // 收到不对的response，忽略，继续等待直到超时
+                Some(_) => continue,
+                None => return Err(ValidationError::Cancelled),
+            }
+        }
+        pto *= 2;
+    }
+    Err(ValidationError::Timeout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::TokioClock;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_validate_with_delayed_response() {
+        let challenge_sndbuf = SendBuffer::<PathChallengeFrame>::default();
+        let response_rcvbuf = RecvBuffer::<PathResponseFrame>::default();
+        let pto = Duration::from_millis(20);
+        let task = tokio::spawn(validate(
+            PathChallengeFrame::random(),
+            challenge_sndbuf.clone(),
+            response_rcvbuf.clone(),
+            pto,
+            Arc::new(TokioClock),
+        ));
+
+        tokio::task::yield_now().await;
+        let challenge = challenge_sndbuf.take().unwrap();
+        // a wrong response should be ignored
+        response_rcvbuf.write(PathChallengeFrame::random().into());
+        tokio::task::yield_now().await;
+        assert!(challenge_sndbuf.take().is_none());
+        // the response is delayed, arrives after the first challenge timed out and resent
+        tokio::time::advance(pto).await;
+        tokio::task::yield_now().await;
+        assert_eq!(challenge_sndbuf.take(), Some(challenge));
+        response_rcvbuf.write(challenge.into());
+
+        assert_eq!(task.await.unwrap(), Ok(()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_validate_timeout() {
+        let challenge_sndbuf = SendBuffer::<PathChallengeFrame>::default();
+        let response_rcvbuf = RecvBuffer::<PathResponseFrame>::default();
+        let pto = Duration::from_millis(5);
+        let result = validate(
+            PathChallengeFrame::random(),
+            challenge_sndbuf,
+            response_rcvbuf,
+            pto,
+            Arc::new(TokioClock),
+        )
+        .await;
+        assert_eq!(result, Err(ValidationError::Timeout));
+    }
+
+    #[tokio::test]
+    async fn test_validate_cancelled() {
+        let challenge_sndbuf = SendBuffer::<PathChallengeFrame>::default();
+        let response_rcvbuf = RecvBuffer::<PathResponseFrame>::default();
+        response_rcvbuf.dismiss();
+        let pto = Duration::from_millis(5);
+        let result = validate(
+            PathChallengeFrame::random(),
+            challenge_sndbuf,
+            response_rcvbuf,
+            pto,
+            Arc::new(TokioClock),
+        )
+        .await;
+        assert_eq!(result, Err(ValidationError::Cancelled));
+    }
+}
@@ -10,33 +10,63 @@ use std::{
 use qbase::{
     cid::{ArcCidCell, ConnectionId},
     flow::ArcSendControler,
-    packet::SpinBit,
+    qlog::{PacketEvent, PacketType, QlogSink},
+    sid::Role,
 };
 use qcongestion::{ArcCC, CongestionControl, MSS};
 use qrecovery::{reliable::ArcReliableFrameDeque, space::Epoch};
 
 use super::{
     anti_amplifier::ANTI_FACTOR,
+    mtu::{ArcPathMtu, MAX_PLPMTU},
+    spin::ArcSpinBit,
     util::{ApplyConstraints, Constraints},
-    ArcAntiAmplifier,
+    ArcAntiAmplifier, ArcPathEvents, PathEvent, Pathway, SchedulerHandle,
 };
-use crate::conn::transmit::{
-    data::DataSpaceReader, handshake::HandshakeSpaceReader, initial::InitialSpaceReader,
+use crate::{
+    clock::ArcClock,
+    conn::{
+        stats::ArcPacketCounters,
+        transmit::{
+            data::DataSpaceReader, handshake::HandshakeSpaceReader, initial::InitialSpaceReader,
+        },
+    },
 };
 
 pub struct ReadIntoDatagrams {
+    pub(super) pathway: Pathway,
+    pub(super) scheduler: SchedulerHandle,
+    pub(super) events: ArcPathEvents,
+    // 是否正受限于抗放大攻击的限制，只在受限的开始时发出事件
+    pub(super) is_amplification_limited: AtomicBool,
+    pub(super) role: Role,
     pub(super) scid: ConnectionId,
     pub(super) dcid: ArcCidCell<ArcReliableFrameDeque>,
-    pub(super) spin: Arc<AtomicBool>,
+    pub(super) spin: ArcSpinBit,
     pub(super) cc: ArcCC,
+    pub(super) mtu: ArcPathMtu,
     pub(super) anti_amplifier: ArcAntiAmplifier<ANTI_FACTOR>,
     pub(super) send_flow_ctrl: ArcSendControler,
     pub(super) initial_space_reader: InitialSpaceReader,
     pub(super) handshake_space_reader: HandshakeSpaceReader,
     pub(super) data_space_reader: DataSpaceReader,
+    pub(super) counters: ArcPacketCounters,
+    pub(super) qlog: Option<Arc<dyn QlogSink>>,
+    pub(super) clock: ArcClock,
 }
 
 impl ReadIntoDatagrams {
+    fn on_packet_sent(&self, packet_type: PacketType, packet_number: u64, length: usize) {
+        self.counters.on_packet_sent(packet_type, length);
+        if let Some(qlog) = &self.qlog {
+            qlog.packet_sent(PacketEvent {
+                packet_type,
+                packet_number,
+                length,
+            });
+        }
+    }
+
     fn read_into_datagram(
         &self,
         constraints: &mut Constraints,
@@ -48,10 +78,11 @@ impl ReadIntoDatagrams {
         let send_quota = buffer.len();
 
         let ack_pkt = self.cc.need_ack(Epoch::Initial);
+        let need_probe = self.cc.need_probe(Epoch::Initial);
         // 按顺序发，先发Initial空间的，到Initial数据包
         if let Some((padding, len, is_just_ack)) = self
             .initial_space_reader
-            .try_read(buffer, self.scid, dcid, ack_pkt)
+            .try_read(buffer, self.scid, dcid, ack_pkt, need_probe)
         {
             // 若真的只包含ack， 后续只会追加padding，追加的padding也可以看成是新的InitialPacket数据包
             constraints.commit(len, is_just_ack);
@@ -61,7 +92,13 @@ impl ReadIntoDatagrams {
                 self.read_other_space(constraints, flow_limit, remain, dcid)
             };
 
-            let padding_len = if wrote == 0 { MSS.min(send_quota) } else { 0 };
+            // 含有Initial包的数据报，要填充至至少1200字节。其他空间的数据包，尤其是1rtt数据包之后，
+            // 无法追加padding，所以padding只能填充在Initial包中：将合并在其后的数据包整体后移，
+            // 让出填充的空间
+            let padding_len = MSS.min(send_quota).saturating_sub(wrote);
+            if wrote > 0 && padding_len > len {
+                buffer.copy_within(len..len + wrote, padding_len);
+            }
             let (pn, is_ack_eliciting, is_just_ack, sent_bytes, in_flight, sent_ack) =
                 padding(buffer, padding_len);
             self.cc.on_pkt_sent(
@@ -72,6 +109,7 @@ impl ReadIntoDatagrams {
                 in_flight,
                 sent_ack,
             );
+            self.on_packet_sent(PacketType::Initial, pn, sent_bytes);
             // 减除initial数据包已经commit的
             constraints.commit(sent_bytes - len, is_just_ack);
             (wrote + sent_bytes, fresh_bytes)
@@ -105,6 +143,7 @@ impl ReadIntoDatagrams {
                     in_flight,
                     None,
                 );
+                self.on_packet_sent(PacketType::ZeroRtt, pn, sent_bytes);
                 buffer = &mut buffer[sent_bytes..];
                 // 0Rtt数据包不会发送Ack
                 constraints.commit(sent_bytes, false);
@@ -130,8 +169,8 @@ impl ReadIntoDatagrams {
         // 最后尝试写1rtt数据包
         if let Some(keys) = one_rtt_keys {
             let ack_pkt = self.cc.need_ack(Epoch::Data);
-            let spin = self.spin.load(Ordering::Relaxed);
-            let spin = SpinBit::from(spin);
+            let need_probe = self.cc.need_probe(Epoch::Data);
+            let spin = self.spin.load();
             if let Some((
                 pn,
                 is_ack_eliciting,
@@ -142,7 +181,7 @@ impl ReadIntoDatagrams {
                 sent_ack,
             )) = self
                 .data_space_reader
-                .try_read_1rtt(buffer, flow_limit, dcid, spin, ack_pkt, keys)
+                .try_read_1rtt(buffer, flow_limit, dcid, spin, ack_pkt, need_probe, keys)
             {
                 self.cc.on_pkt_sent(
                     Epoch::Data,
@@ -152,6 +191,7 @@ impl ReadIntoDatagrams {
                     in_flight,
                     sent_ack,
                 );
+                self.on_packet_sent(PacketType::OneRtt, pn, sent_bytes);
                 constraints.commit(sent_bytes, is_just_ack);
                 written += sent_bytes;
                 fresh_bytes += fresh_len;
@@ -169,9 +209,10 @@ impl ReadIntoDatagrams {
     ) -> usize {
         // 再尝试写handshake空间的
         let ack_pkt = self.cc.need_ack(Epoch::Handshake);
+        let need_probe = self.cc.need_probe(Epoch::Handshake);
         if let Some((pn, is_ack_eliciting, is_just_ack, sent_bytes, in_flight, sent_ack)) = self
             .handshake_space_reader
-            .try_read(buffer, self.scid, dcid, ack_pkt)
+            .try_read(buffer, self.scid, dcid, ack_pkt, need_probe)
         {
             self.cc.on_pkt_sent(
                 Epoch::Handshake,
@@ -181,24 +222,105 @@ impl ReadIntoDatagrams {
                 in_flight,
                 sent_ack,
             );
+            self.on_packet_sent(PacketType::Handshake, pn, sent_bytes);
+            // 客户端首次发送Handshake包时丢弃Initial密钥，握手期间只有这一条路径，
+            // 见RFC 9001 4.9.1
+            if self.role == Role::Client && self.initial_space_reader.keys.invalid().is_some() {
+                self.cc.on_get_handshake_keys();
+                self.cc.discard_epoch(Epoch::Initial);
+            }
             constraints.commit(sent_bytes, is_just_ack);
             return sent_bytes;
         }
         0
     }
 
+    /// Try to read a PMTU probe into the datagram, the probe is sent alone in a datagram.
+    ///
+    /// The probe is not counted in flight, so that its loss will not be considered as a signal
+    /// of congestion, but it is still limited by the anti-amplification limit.
+    fn read_mtu_probe(
+        &self,
+        constraints: &Constraints,
+        datagram: &mut [u8; MAX_PLPMTU],
+        dcid: ConnectionId,
+    ) -> usize {
+        let now = self.clock.now();
+        let Some(keys) = self.data_space_reader.one_rtt_keys() else {
+            return 0;
+        };
+        let Some(probe_size) = self.mtu.probe_size(now) else {
+            return 0;
+        };
+        if !constraints.is_available_for(probe_size) {
+            return 0;
+        }
+        let spin = self.spin.load();
+        let Some((pn, sent_bytes)) = self.data_space_reader.try_read_mtu_probe(
+            &mut datagram[..probe_size],
+            dcid,
+            spin,
+            keys,
+        ) else {
+            return 0;
+        };
+        self.cc
+            .on_pkt_sent(Epoch::Data, pn, true, sent_bytes, false, None);
+        self.on_packet_sent(PacketType::OneRtt, pn, sent_bytes);
+        self.mtu
+            .on_probe_sent(pn, probe_size, now, self.cc.pto_time(Epoch::Data));
+        sent_bytes
+    }
+
+    // 多路径下，路径在所用连接ID的序号对应的空间中发包。换用了连接ID，旧空间中在途的包再也
+    // 无法被确认，先将它们判定为丢失，其中的帧在新空间中重传
+    fn bind_sending_space(&self, seq: u64, dcid: ConnectionId) {
+        let space_id = match self.scheduler.is_multipath_enabled() {
+            true => seq,
+            false => 0,
+        };
+        let path_spaces = &self.data_space_reader.path_spaces;
+        if path_spaces.sending_id() != space_id {
+            self.cc.discard_sent(Epoch::Data);
+            path_spaces.bind_sending(space_id, dcid);
+        }
+    }
+
     fn poll_read_inner(
         &self,
         cx: &mut Context<'_>,
-        buffers: &mut Vec<[u8; MSS]>,
-    ) -> Poll<Option<(usize, usize)>> {
-        let send_quota = core::task::ready!(self.cc.poll_send(cx));
-        let Some(dcid) = core::task::ready!(self.dcid.poll_borrow_cid(cx)) else {
-            return Poll::Ready(None);
+        buffers: &mut Vec<[u8; MAX_PLPMTU]>,
+    ) -> Poll<Option<(usize, usize, usize)>> {
+        let send_quota = match self.cc.poll_send(cx) {
+            Poll::Ready(send_quota) => {
+                self.data_space_reader.datagrams.on_window_available();
+                send_quota
+            }
+            Poll::Pending => {
+                // 拥塞窗口耗尽（而非受限于pacing），按照策略丢弃排队中的数据报，不让它们延迟到达
+                if self.cc.available_window() < self.mtu.current_mtu() {
+                    self.data_space_reader.datagrams.on_congestion_limited();
+                }
+                return Poll::Pending;
+            }
         };
-        let Some(credit_limit) = core::task::ready!(self.anti_amplifier.poll_balance(cx)) else {
+        let Some((seq, dcid)) = core::task::ready!(self.dcid.poll_borrow_cid_with_seq(cx)) else {
             return Poll::Ready(None);
         };
+        self.bind_sending_space(seq, dcid);
+        let credit_limit = match self.anti_amplifier.poll_balance(cx) {
+            Poll::Ready(Some(credit_limit)) => credit_limit,
+            Poll::Ready(None) => return Poll::Ready(None),
+            Poll::Pending => {
+                if !self.is_amplification_limited.swap(true, Ordering::Relaxed) {
+                    self.events
+                        .emit(PathEvent::AmplificationLimited(self.pathway));
+                }
+                return Poll::Pending;
+            }
+        };
+        self.is_amplification_limited
+            .store(false, Ordering::Relaxed);
         // 流量控制，受控于对方允许的最大数据，不得超过
         // 作用于新数据，Stream帧中的新数据
         // 当流量限制为0的时候，仍然可以发送Stream中的旧数据，以及其他帧
@@ -207,8 +329,25 @@ impl ReadIntoDatagrams {
             // 返回None，表示结束
             return Poll::Ready(None);
         };
-        let flow_limit = send_flow_credit.available();
+        // 未被调度的路径不发送新的流数据，但仍可发送其他帧以及重传的旧数据
+        let flow_limit = if self.scheduler.is_scheduled(self.pathway) {
+            send_flow_credit.available()
+        } else {
+            0
+        };
         let mut constraints = Constraints::new(credit_limit, send_quota);
+        let mtu = self.mtu.current_mtu();
+        self.data_space_reader.drop_oversized_datagrams(mtu, &dcid);
+
+        if buffers.is_empty() {
+            buffers.push([0; MAX_PLPMTU]);
+        }
+        let probe_size = self.read_mtu_probe(&constraints, &mut buffers[0], dcid);
+        if probe_size > 0 {
+            self.dcid.return_back();
+            self.anti_amplifier.on_sent(probe_size);
+            return Poll::Ready(Some((1, probe_size, probe_size)));
+        }
 
         // 遍历，填充每一个包
 
@@ -222,13 +361,13 @@ impl ReadIntoDatagrams {
             let datagram = match buffers.get_mut(buffers_used) {
                 Some(buffer) => buffer,
                 None => {
-                    buffers.push([0; MSS]);
+                    buffers.push([0; MAX_PLPMTU]);
                     &mut buffers[buffers_used]
                 }
             };
 
             let (datagram_size, fresh_bytes) =
-                self.read_into_datagram(&mut constraints, flow_limit, datagram, dcid);
+                self.read_into_datagram(&mut constraints, flow_limit, &mut datagram[..mtu], dcid);
             // 啥也没读到，就结束吧
             // TODO: 若因没有数据可发，将waker挂载到数据控制器上一份，包括帧数据、流数据，
             //       一旦有任何数据发送，唤醒该任务发一次
@@ -242,11 +381,16 @@ impl ReadIntoDatagrams {
 
             // 本数据报尚未被填满，如果本数据报包含一个1rtt数据包，在“后面填充padding”是不行的，因为那些padding会被认为是1rtt的一部分
             // 就会导致发送出的数据包无法被对端解析，所以这里直接break掉
-            if datagram_size < MSS {
+            if datagram_size < mtu {
                 break;
             }
         }
 
+        // 数据已经读完，但拥塞控制仍允许发送至少一个满载的包，说明受限于应用而非网络
+        if constraints.is_available_for(mtu) {
+            self.cc.on_app_limited();
+        }
+
         if buffers_used == 0 {
             // 就算Constraints允许发送，但也不一定真的有数据供发送
             return Poll::Pending;
@@ -257,17 +401,29 @@ impl ReadIntoDatagrams {
         // 最终将要发送前，反馈给各个限制条件。除了拥塞控制的，在每个Epoch发包后，都已直接反馈给cc过了
         self.anti_amplifier.on_sent(total_bytes);
         send_flow_credit.post_sent(total_fresh_bytes);
+        if total_fresh_bytes > 0 {
+            self.scheduler.on_stream_sent(self.pathway);
+        }
         // 返回这个后，datagrams肯定等着被发送了
-        Poll::Ready(Some((buffers_used, last_buffer_written)))
+        Poll::Ready(Some((buffers_used, mtu, last_buffer_written)))
     }
 
-    pub async fn read<'ds>(&self, buffers: &'ds mut Vec<[u8; MSS]>) -> Option<Vec<IoSlice<'ds>>> {
-        let (buffers_used, last_buffer_written) =
+    /// Read the datagrams to be sent into the `buffers`.
+    ///
+    /// All datagrams are of the [current mtu] of the path except the last one, so that they can
+    /// be sent in one GSO call. A PMTU probe is always sent alone.
+    ///
+    /// [current mtu]: super::Path::current_mtu
+    pub async fn read<'ds>(
+        &self,
+        buffers: &'ds mut Vec<[u8; MAX_PLPMTU]>,
+    ) -> Option<Vec<IoSlice<'ds>>> {
+        let (buffers_used, segment_size, last_buffer_written) =
             core::future::poll_fn(|cx| self.poll_read_inner(cx, buffers)).await?;
 
         debug_assert!(buffers_used > 0);
         let datagrams = (0..buffers_used - 1)
-            .map(|i| IoSlice::new(&buffers[i]))
+            .map(|i| IoSlice::new(&buffers[i][..segment_size]))
             .chain(Some(IoSlice::new(
                 &buffers[buffers_used - 1][..last_buffer_written],
             )))
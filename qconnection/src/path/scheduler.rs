@@ -0,0 +1,267 @@
+use std::{
+    sync::{Arc, Mutex, OnceLock, Weak},
+    time::Duration,
+};
+
+use qbase::frame::StreamFrame;
+
+use super::{Paths, Pathway, ReinjectBuffer};
+
+/// The status of a path which can carry new stream data, given to the [`PathScheduler`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PathStatus {
+    pub pathway: Pathway,
+    pub smoothed_rtt: Duration,
+    /// How many bytes can be sent on the path without exceeding its congestion window.
+    pub available_window: usize,
+}
+
+/// Decides which paths carry the new stream data, once the multipath extension is negotiated.
+///
+/// Each path has its own sending task, before a path assembles a datagram, the scheduler is asked
+/// whether the path may carry new stream data, read [`Paths::is_scheduled`]. The paths which are
+/// not scheduled still send the other frames, such as the acknowledgments and the retransmissions.
+///
+/// There are three built-in policies:
+/// - [`MinRttScheduler`]: the path with the lowest RTT first, which is the default.
+/// - [`RoundRobinScheduler`]: the paths take turns.
+/// - [`RedundantScheduler`]: all the paths, and the stream data is sent on each of them.
+pub trait PathScheduler: Send + Sync {
+    /// Return whether the path on the `pathway` may carry new stream data now.
+    ///
+    /// The `paths` are the ones which can carry stream data, that is, the active path and the
+    /// validated paths whose congestion window is not full, including the one on the `pathway`.
+    /// If the congestion windows of all of them are full, only the probe packets can be sent, and
+    /// all of them are given, so that the probes can carry new stream data as well.
+    ///
+    /// It is only a query, which may be asked many times without sending anything, so it must not
+    /// change the state of the scheduler, read [`PathScheduler::on_stream_sent`].
+    fn is_scheduled(&self, pathway: Pathway, paths: &[PathStatus]) -> bool;
+
+    /// Called when the path on the `pathway` has sent new stream data after being scheduled.
+    fn on_stream_sent(&self, _pathway: Pathway) {}
+
+    /// Return whether the stream data sent on one path is sent again on the other scheduled
+    /// paths, `false` by default.
+    ///
+    /// The copies are sent only once, they are not retransmitted themselves. But a copy is
+    /// recorded as the stream data it carries, whose acknowledgment acknowledges the stream data,
+    /// and whose loss makes the stream data retransmitted if it is not acknowledged yet.
+    fn is_redundant(&self) -> bool {
+        false
+    }
+}
+
+/// Schedule the path with the lowest smoothed RTT, so the stream data fills the fastest path
+/// first, and overflows to the slower ones once its congestion window is full.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MinRttScheduler;
+
+impl PathScheduler for MinRttScheduler {
+    fn is_scheduled(&self, pathway: Pathway, paths: &[PathStatus]) -> bool {
+        paths
+            .iter()
+            .min_by_key(|status| status.smoothed_rtt)
+            .is_some_and(|status| status.pathway == pathway)
+    }
+}
+
+/// Schedule the paths in turn, regardless of their RTT.
+///
+/// The turn passes to the next path once the scheduled path has sent new stream data.
+#[derive(Debug, Default)]
+pub struct RoundRobinScheduler {
+    // 上一次发送了流数据的路径
+    last: Mutex<Option<Pathway>>,
+}
+
+impl PathScheduler for RoundRobinScheduler {
+    fn is_scheduled(&self, pathway: Pathway, paths: &[PathStatus]) -> bool {
+        let last = *self.last.lock().unwrap();
+        // 上次发送了流数据的路径之后的那条路径，上次的路径已不可用则从头开始
+        let next = match paths.iter().position(|status| Some(status.pathway) == last) {
+            Some(index) => paths[(index + 1) % paths.len()].pathway,
+            None => paths[0].pathway,
+        };
+        next == pathway
+    }
+
+    fn on_stream_sent(&self, pathway: Pathway) {
+        *self.last.lock().unwrap() = Some(pathway);
+    }
+}
+
+/// Schedule all the paths, and send the stream data on each of them.
+///
+/// The stream data arrives over the path with the lowest latency at the moment, at the cost of
+/// the bandwidth, which is suitable for the small latency-sensitive messages.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RedundantScheduler;
+
+impl PathScheduler for RedundantScheduler {
+    fn is_scheduled(&self, _pathway: Pathway, _paths: &[PathStatus]) -> bool {
+        true
+    }
+
+    fn is_redundant(&self) -> bool {
+        true
+    }
+}
+
+/// A handle to the [`Paths`], with which the sending task of a path asks whether the path may carry
+/// new stream data, read [`Paths::is_scheduled`].
+///
+/// The handle is given to the paths when they are created, and bound to the [`Paths`] once it is
+/// created. Before that, or after the [`Paths`] is dropped, every path is scheduled.
+#[derive(Clone, Default)]
+pub struct SchedulerHandle(Arc<OnceLock<Weak<Paths>>>);
+
+impl SchedulerHandle {
+    pub(super) fn bind(&self, paths: &Arc<Paths>) {
+        _ = self.0.set(Arc::downgrade(paths));
+    }
+
+    /// Returns whether the path on the `pathway` may carry new stream data now.
+    pub fn is_scheduled(&self, pathway: Pathway) -> bool {
+        match self.0.get().and_then(Weak::upgrade) {
+            Some(paths) => paths.is_scheduled(pathway),
+            None => true,
+        }
+    }
+
+    /// Returns whether the multipath extension is enabled, read [`Paths::enable_multipath`].
+    pub fn is_multipath_enabled(&self) -> bool {
+        self.0
+            .get()
+            .and_then(Weak::upgrade)
+            .is_some_and(|paths| paths.is_multipath_enabled())
+    }
+
+    /// Called when the path on the `pathway` has sent new stream data, read
+    /// [`Paths::on_stream_sent`].
+    pub fn on_stream_sent(&self, pathway: Pathway) {
+        if let Some(paths) = self.0.get().and_then(Weak::upgrade) {
+            paths.on_stream_sent(pathway);
+        }
+    }
+
+    /// Called when the stream `frame` carrying the `data` is sent on the path on the `pathway`,
+    /// read [`Paths::reinject`].
+    pub fn reinject(&self, pathway: Pathway, frame: &StreamFrame, data: &[u8]) {
+        if let Some(paths) = self.0.get().and_then(Weak::upgrade) {
+            paths.reinject(pathway, frame, data);
+        }
+    }
+}
+
+/// The stream data reinjection of a path for the redundant scheduler, read
+/// [`PathScheduler::is_redundant`].
+///
+/// The data space reader of the path reads the copies of the stream frames sent on the other paths
+/// from it, and reports the stream frames sent on the path to it.
+#[derive(Clone)]
+pub struct Reinjection {
+    pathway: Pathway,
+    sndbuf: ReinjectBuffer,
+    scheduler: SchedulerHandle,
+}
+
+impl Reinjection {
+    pub fn new(pathway: Pathway, sndbuf: ReinjectBuffer, scheduler: SchedulerHandle) -> Self {
+        Self {
+            pathway,
+            sndbuf,
+            scheduler,
+        }
+    }
+
+    /// Write a copy of the stream frame sent on the other paths into the `buf`, return the frame
+    /// and the size written.
+    pub fn try_read(&self, buf: &mut [u8]) -> Option<(StreamFrame, usize)> {
+        self.sndbuf.try_read(buf)
+    }
+
+    /// Called when the stream `frame` carrying the `data` is sent on the path.
+    pub fn on_stream_sent(&self, frame: &StreamFrame, data: &[u8]) {
+        self.scheduler.reinject(self.pathway, frame, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use qcongestion::MSS;
+
+    use super::*;
+
+    fn pathway(port: u16) -> Pathway {
+        Pathway::Direct {
+            local: SocketAddr::from(([127, 0, 0, 1], 4433)),
+            remote: SocketAddr::from(([127, 0, 0, 1], port)),
+        }
+    }
+
+    // 两条时延不同的路径，fast的RTT为10ms，slow的RTT为50ms
+    fn two_paths() -> [PathStatus; 2] {
+        [
+            PathStatus {
+                pathway: pathway(1),
+                smoothed_rtt: Duration::from_millis(10),
+                available_window: 10 * MSS,
+            },
+            PathStatus {
+                pathway: pathway(2),
+                smoothed_rtt: Duration::from_millis(50),
+                available_window: 10 * MSS,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_min_rtt() {
+        let [fast, slow] = two_paths();
+        let scheduler = MinRttScheduler;
+        assert!(scheduler.is_scheduled(fast.pathway, &[slow, fast]));
+        assert!(!scheduler.is_scheduled(slow.pathway, &[slow, fast]));
+
+        // 低时延路径的拥塞窗口满了，不再参与调度，溢出到高时延路径
+        assert!(scheduler.is_scheduled(slow.pathway, &[slow]));
+        assert!(!scheduler.is_redundant());
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let paths = two_paths();
+        let scheduler = RoundRobinScheduler::default();
+        let mut scheduled = vec![];
+        for _ in 0..4 {
+            let status = paths
+                .iter()
+                .find(|status| scheduler.is_scheduled(status.pathway, &paths))
+                .unwrap();
+            // 查询不改变调度器的状态，只有发送了流数据才轮到下一条路径
+            assert!(scheduler.is_scheduled(status.pathway, &paths));
+            scheduler.on_stream_sent(status.pathway);
+            scheduled.push(status.pathway);
+        }
+        // 不论时延，两条路径轮流
+        assert_eq!(scheduled, [pathway(1), pathway(2), pathway(1), pathway(2)]);
+
+        // 一条路径的拥塞窗口满了，只调度另一条路径
+        let [_, slow] = paths;
+        assert!(scheduler.is_scheduled(slow.pathway, &[slow]));
+        scheduler.on_stream_sent(slow.pathway);
+        assert!(scheduler.is_scheduled(slow.pathway, &[slow]));
+    }
+
+    #[test]
+    fn test_redundant() {
+        let paths = two_paths();
+        let scheduler = RedundantScheduler;
+        assert!(paths
+            .iter()
+            .all(|status| scheduler.is_scheduled(status.pathway, &paths)));
+        assert!(scheduler.is_redundant());
+    }
+}
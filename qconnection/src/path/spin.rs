@@ -0,0 +1,140 @@
+use std::sync::{Arc, Mutex};
+
+use qbase::{packet::SpinBit, sid::Role};
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// 至少每16条路径中有1条禁用spin bit
+const DISABLE_SPIN_ONE_IN: u8 = 16;
+
+fn random_byte() -> u8 {
+    let mut byte = [0u8; 1];
+    SystemRandom::new()
+        .fill(&mut byte)
+        .expect("failed to generate random byte");
+    byte[0]
+}
+
+#[derive(Debug)]
+struct Spin {
+    role: Role,
+    enabled: bool,
+    // 收到的最大包号，只有更大包号的包才会更新spin值，乱序到达的包不会
+    largest_pn: Option<u64>,
+    value: SpinBit,
+    // 禁用时使用的随机值，与对端的spin无关
+    disabled_value: SpinBit,
+}
+
+/// The latency spin bit of the 1-RTT packets sent on a path.
+///
+/// The server reflects the spin bit of the 1-RTT packet with the largest packet number it received,
+/// while the client sends the opposite of it, so that the value flips once per round trip, and an
+/// on-path observer can measure the RTT.
+///
+/// For privacy, the spin bit is disabled randomly on one in every 16 paths, a random value is sent
+/// instead. It can also be enabled or disabled explicitly by [`ArcSpinBit::set_enabled`].
+///
+/// See [section 17.4](https://www.rfc-editor.org/rfc/rfc9000.html#name-latency-spin-bit)
+/// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
+#[derive(Debug, Clone)]
+pub struct ArcSpinBit(Arc<Mutex<Spin>>);
+
+impl ArcSpinBit {
+    /// Create a new spin bit for the endpoint of the `role`.
+    pub fn new(role: Role) -> Self {
+        let random = random_byte();
+        Self(Arc::new(Mutex::new(Spin {
+            role,
+            enabled: random % DISABLE_SPIN_ONE_IN != 0,
+            largest_pn: None,
+            value: SpinBit::Zero,
+            disabled_value: SpinBit::from(random & 0x80 != 0),
+        })))
+    }
+
+    /// Enable or disable the spin bit.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.0.lock().unwrap().enabled = enabled;
+    }
+
+    /// Returns whether the spin bit is enabled.
+    pub fn is_enabled(&self) -> bool {
+        self.0.lock().unwrap().enabled
+    }
+
+    /// Called when a 1-RTT packet with the packet number `pn` and the spin bit `spin` is received.
+    pub fn on_rcvd(&self, pn: u64, spin: SpinBit) {
+        let mut guard = self.0.lock().unwrap();
+        if guard.largest_pn.is_some_and(|largest| pn <= largest) {
+            return;
+        }
+        guard.largest_pn = Some(pn);
+        guard.value = match guard.role {
+            Role::Server => spin,
+            Role::Client => !spin,
+        };
+    }
+
+    /// Returns the spin bit should be set in the next 1-RTT packet.
+    pub fn load(&self) -> SpinBit {
+        let guard = self.0.lock().unwrap();
+        if guard.enabled {
+            guard.value
+        } else {
+            guard.disabled_value
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reflect_and_flip() {
+        let client = ArcSpinBit::new(Role::Client);
+        let server = ArcSpinBit::new(Role::Server);
+        client.set_enabled(true);
+        server.set_enabled(true);
+
+        let mut client_pn = 0;
+        let mut server_pn = 0;
+        let mut client_spins = vec![];
+        for _ in 0..4 {
+            // 客户端发包，服务端收到后反射
+            let spin = client.load();
+            client_spins.push(spin);
+            server.on_rcvd(client_pn, spin);
+            client_pn += 1;
+            assert_eq!(server.load(), spin);
+            // 服务端回包，客户端收到后翻转
+            client.on_rcvd(server_pn, server.load());
+            server_pn += 1;
+            assert_eq!(client.load(), !spin);
+        }
+        // 每个RTT翻转一次
+        use SpinBit::*;
+        assert_eq!(client_spins, [Zero, One, Zero, One]);
+
+        // 乱序到达的包不影响spin
+        client.on_rcvd(0, Zero);
+        assert_eq!(client.load(), Zero);
+        server.on_rcvd(1, Zero);
+        assert_eq!(server.load(), One);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let client = ArcSpinBit::new(Role::Client);
+        client.set_enabled(false);
+        let value = client.load();
+        client.on_rcvd(0, !value);
+        assert_eq!(client.load(), value);
+        client.on_rcvd(1, value);
+        assert_eq!(client.load(), value);
+
+        client.set_enabled(true);
+        assert!(client.is_enabled());
+        assert_eq!(client.load(), !value);
+    }
+}
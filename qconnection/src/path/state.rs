@@ -9,6 +9,8 @@ use qbase::cid::ArcCidCell;
 use qrecovery::reliable::ArcReliableFrameDeque;
 use tokio::sync::Notify;
 
+use crate::clock::ArcClock;
+
 /// Represents the current state of the path.
 #[derive(Debug, Clone)]
 pub enum PathState {
@@ -23,7 +25,9 @@ pub enum PathState {
 
 #[derive(Debug, Clone, Deref)]
 pub struct ArcPathState {
+    #[deref]
     state: Arc<Mutex<PathState>>,
+    clock: ArcClock,
 }
 
 impl ArcPathState {
@@ -37,24 +41,27 @@ impl ArcPathState {
     /// receive time. If the difference exceeds the inactivity threshold, the path is transitioned
     /// to the [`InActive`] state and the task terminates.
     ///
+    /// The receive time is read from the `clock`, which also drives the background task.
+    ///
     /// [`InActive`]: PathState::InActive
-    pub fn new(cid: ArcCidCell<ArcReliableFrameDeque>) -> Self {
+    pub fn new(cid: ArcCidCell<ArcReliableFrameDeque>, clock: ArcClock) -> Self {
         let state = Self {
             state: Arc::new(
                 PathState::Active {
                     notifier: Default::default(),
                     cid_cell: cid,
-                    recv_time: time::Instant::now(),
+                    recv_time: clock.now(),
                 }
                 .into(),
             ),
+            clock,
         };
 
         tokio::spawn({
             let state = state.clone();
             async move {
                 loop {
-                    let now = time::Instant::now();
+                    let now = state.clock.now();
                     let recv_time = match state.lock().unwrap().deref() {
                         PathState::Active { recv_time, .. } => *recv_time,
                         PathState::InActive => break,
@@ -65,7 +72,7 @@ impl ArcPathState {
                         state.to_inactive();
                         break;
                     }
-                    tokio::time::sleep_until((recv_time + time).into()).await
+                    state.clock.sleep_until(recv_time + time).await
                 }
             }
         });
@@ -120,8 +127,17 @@ impl ArcPathState {
     pub fn update_recv_time(&self) {
         let mut state = self.state.lock().unwrap();
         match state.deref_mut() {
-            PathState::Active { recv_time, .. } => *recv_time = time::Instant::now(),
+            PathState::Active { recv_time, .. } => *recv_time = self.clock.now(),
             PathState::InActive => {}
         }
     }
+
+    /// Returns the time when the last packet was received on the path, [`None`] if the path is
+    /// inactive.
+    pub fn recv_time(&self) -> Option<time::Instant> {
+        match self.state.lock().unwrap().deref() {
+            PathState::Active { recv_time, .. } => Some(*recv_time),
+            PathState::InActive => None,
+        }
+    }
 }
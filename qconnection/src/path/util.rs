@@ -1,13 +1,17 @@
 use std::{
+    collections::VecDeque,
     ops::Deref,
     pin::Pin,
     sync::{Arc, Mutex},
     task::{Context, Poll},
 };
 
-use bytes::BufMut;
+use bytes::{BufMut, Bytes};
 use qbase::{
-    frame::{io::WriteFrame, BeFrame},
+    frame::{
+        io::{WriteDataFrame, WriteFrame},
+        BeFrame, StreamFrame,
+    },
     util::ArcAsyncDeque,
 };
 
@@ -18,6 +22,10 @@ impl<T> SendBuffer<T> {
     pub fn write(&self, frame: T) {
         *self.0.lock().unwrap() = Some(frame);
     }
+
+    pub fn take(&self) -> Option<T> {
+        self.0.lock().unwrap().take()
+    }
 }
 
 impl<T> SendBuffer<T>
@@ -39,6 +47,46 @@ where
     }
 }
 
+/// The maximum number of the stream frames reinjected from the other paths, read
+/// [`ReinjectBuffer`].
+const MAX_REINJECTED_FRAMES: usize = 64;
+
+/// The copies of the stream frames sent on the other paths, to be sent on this path by the
+/// redundant scheduler, read [`PathScheduler::is_redundant`].
+///
+/// The buffer is bounded, the oldest copies are dropped once it is full, as the original stream
+/// frames are still reliably delivered on the paths they were sent.
+///
+/// [`PathScheduler::is_redundant`]: super::PathScheduler::is_redundant
+#[derive(Default, Clone)]
+pub struct ReinjectBuffer(Arc<Mutex<VecDeque<(StreamFrame, Bytes)>>>);
+
+impl ReinjectBuffer {
+    pub fn write(&self, mut frame: StreamFrame, data: Bytes) {
+        // 副本之后可能还有其他帧，必须携带长度
+        frame.carry_length();
+        let mut guard = self.0.lock().unwrap();
+        if guard.len() >= MAX_REINJECTED_FRAMES {
+            guard.pop_front();
+        }
+        guard.push_back((frame, data));
+    }
+
+    /// Write the oldest copy into the `buf` if there is enough space, return the frame and the
+    /// size written.
+    pub fn try_read(&self, mut buf: &mut [u8]) -> Option<(StreamFrame, usize)> {
+        let mut guard = self.0.lock().unwrap();
+        let (frame, data) = guard.front()?;
+        let size = frame.encoding_size() + data.len();
+        if buf.remaining_mut() < size {
+            return None;
+        }
+        buf.put_data_frame(frame, data);
+        let (frame, _) = guard.pop_front()?;
+        Some((frame, size))
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct RecvBuffer<T>(ArcAsyncDeque<T>);
 
@@ -113,6 +161,11 @@ impl Constraints {
         self.credit_limit > 0
     }
 
+    /// 是否足以发送一个指定大小的数据包，如PMTU探测包
+    pub fn is_available_for(&self, size: usize) -> bool {
+        self.credit_limit >= size && self.send_quota >= size
+    }
+
     pub fn constrain<'b>(&self, buf: &'b mut [u8]) -> &'b mut [u8] {
         let min_len = buf
             .remaining_mut()
@@ -1,17 +1,121 @@
-use std::sync::LazyLock;
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, LazyLock, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
+use bytes::BytesMut;
 use dashmap::DashMap;
 use qbase::{
-    cid::{ConnectionId, GenUniqueCid},
+    cid::{ArcCidGenerator, ConnectionId, GenUniqueCid, MAX_CID_SIZE},
     error::Error,
     frame::{NewConnectionIdFrame, ReceiveFrame, RetireConnectionIdFrame, SendFrame},
-    packet::{header::GetDcid, long, DataHeader, DataPacket},
+    packet::{
+        header::GetDcid,
+        long,
+        r#type::long::{io::WriteLongType, v1::Type as LongV1Type, Type as LongType},
+        retry::retry_integrity_tag,
+        DataHeader, DataPacket, Ecn, Packet, PacketReader,
+    },
+    token::{ResetToken, MIN_STATELESS_RESET_SIZE, RESET_TOKEN_SIZE},
+};
+use ring::{
+    hmac,
+    rand::{SecureRandom, SystemRandom},
 };
 
-use crate::{conn::PacketEntry, path::Pathway, usc::ArcUsc};
+use crate::{
+    clock::{ArcClock, Clock, TokioClock},
+    conn::PacketEntry,
+    path::Pathway,
+    usc::ArcUsc,
+};
 
 /// Global Router for managing connections.
-static ROUTER: LazyLock<DashMap<ConnectionId, [PacketEntry; 4]>> = LazyLock::new(DashMap::new);
+static ROUTER: LazyLock<RouterTable> = LazyLock::new(RouterTable::default);
+
+/// The router entries of the local connection IDs, along with how many of them there are of each
+/// length.
+///
+/// A short header does not carry the length of its DCID, the length can only be known by the
+/// local connection IDs in the table, which may differ between the [`CidGenerator`]s.
+///
+/// [`CidGenerator`]: qbase::cid::CidGenerator
+struct RouterTable {
+    entries: DashMap<ConnectionId, [PacketEntry; 4]>,
+    cid_lens: [AtomicUsize; MAX_CID_SIZE + 1],
+}
+
+impl Default for RouterTable {
+    fn default() -> Self {
+        Self {
+            entries: DashMap::new(),
+            cid_lens: core::array::from_fn(|_| AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl RouterTable {
+    fn get(
+        &self,
+        cid: &ConnectionId,
+    ) -> Option<dashmap::mapref::one::Ref<'_, ConnectionId, [PacketEntry; 4]>> {
+        self.entries.get(cid)
+    }
+
+    fn contains_key(&self, cid: &ConnectionId) -> bool {
+        self.entries.contains_key(cid)
+    }
+
+    fn insert(&self, cid: ConnectionId, entries: [PacketEntry; 4]) {
+        if self.entries.insert(cid, entries).is_none() {
+            self.cid_lens[cid.len()].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Insert the `entries` only if the `cid` is not in use, return whether it is inserted.
+    fn insert_vacant(&self, cid: ConnectionId, entries: [PacketEntry; 4]) -> bool {
+        match self.entries.entry(cid) {
+            dashmap::Entry::Vacant(entry) => {
+                entry.insert(entries);
+                self.cid_lens[cid.len()].fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            dashmap::Entry::Occupied(_) => false,
+        }
+    }
+
+    fn remove(&self, cid: &ConnectionId) {
+        if self.entries.remove(cid).is_some() {
+            self.cid_lens[cid.len()].fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    /// The lengths of the local connection IDs in the table, from the shortest.
+    fn cid_lens(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..=MAX_CID_SIZE).filter(|&len| self.cid_lens[len].load(Ordering::Relaxed) > 0)
+    }
+}
+
+/// The stateless reset tokens issued by the peers, mapped to the 1-RTT packet entries of the
+/// corresponding connections.
+static RESET_TOKENS: LazyLock<DashMap<ResetToken, PacketEntry>> = LazyLock::new(DashMap::new);
+
+/// The size of [`RESET_TOKENS`] to remove the entries of the terminated connections at.
+static RESET_TOKENS_PRUNE_AT: AtomicUsize = AtomicUsize::new(64);
+
+/// The static key to derive the stateless reset tokens from the connection IDs.
+static RESET_KEY: LazyLock<hmac::Key> = LazyLock::new(|| {
+    hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new())
+        .expect("failed to generate stateless reset key")
+});
+
+/// The maximum size of a stateless reset packet sent by us.
+const MAX_STATELESS_RESET_SIZE: usize = 1200;
 
 /// A interface to control the global router, which used to route packets to the corresponding connection.
 pub struct Router;
@@ -19,12 +123,14 @@ pub struct Router;
 impl Router {
     /// Try to route the packet to the corresponding connection.
     ///
-    /// The argument `packet` is the packet to be routed, `pathway` and `usc` is where the packet
-    /// comes from,you can read the [`Pathway`] and [`ArcUsc`]'s documents for more information.
+    /// The argument `packet` is the packet to be routed, `ecn` is the ECN codepoint of the datagram
+    /// carried it, `pathway` and `usc` is where the packet comes from,you can read the [`Pathway`]
+    /// and [`ArcUsc`]'s documents for more information.
     ///
     /// If the connection does not exist, the packet will be returned with out any modification.
     pub fn try_to_route_packet_from(
         packet: DataPacket,
+        ecn: Ecn,
         pathway: Pathway,
         usc: &ArcUsc,
     ) -> Result<(), DataPacket> {
@@ -38,18 +144,114 @@ impl Router {
             DataHeader::Long(long::DataHeader::Handshake(_)) => 2,
             DataHeader::Short(_) => 3,
         };
-        _ = entries[index].unbounded_send((packet, pathway, usc.clone()));
+        _ = entries[index].unbounded_send((packet, ecn, pathway, usc.clone()));
         Ok(())
     }
 
+    /// Route all the packets coalesced in the `datagram` to the corresponding connections.
+    ///
+    /// Each coalesced packet is routed by its own DCID and packet type, so the packets of different
+    /// spaces in one datagram are delivered to the corresponding spaces of the same connection.
+    ///
+    /// The packets that cannot be routed, including the Version Negotiation packets, the Retry
+    /// packets, and the data packets of unknown connections, are passed to `unrouted` in order, so
+    /// that the caller can accept a new connection, or respond with a stateless reset.
+    ///
+    /// The senders must not coalesce packets with different connection IDs into one datagram, the
+    /// subsequent packets with a different DCID than the first packet are discarded.
+    ///
+    /// See [coalescing packets](https://www.rfc-editor.org/rfc/rfc9000.html#name-coalescing-packets)
+    /// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
+    pub fn route_datagram(
+        datagram: BytesMut,
+        ecn: Ecn,
+        pathway: Pathway,
+        usc: &ArcUsc,
+        mut unrouted: impl FnMut(Packet),
+    ) {
+        let mut first_dcid = None;
+        let dcid_len = Self::dcid_len(&datagram);
+        for packet in PacketReader::new(datagram, dcid_len).flatten() {
+            let Packet::Data(packet) = packet else {
+                unrouted(packet);
+                continue;
+            };
+            let dcid = *packet.header.get_dcid();
+            if *first_dcid.get_or_insert(dcid) != dcid {
+                continue;
+            }
+            let Err(packet) = Self::try_to_route_packet_from(packet, ecn, pathway, usc) else {
+                continue;
+            };
+            if let Err(packet) = Self::try_to_route_stateless_reset(packet, ecn, pathway, usc) {
+                unrouted(Packet::Data(packet));
+            }
+        }
+    }
+
+    /// A stateless reset looks like a short header packet with an unknown DCID, it is delivered to
+    /// the connection whose peer issued the reset token at the end of the packet.
+    ///
+    /// The connection confirms the stateless reset only if the packet cannot be decrypted, read
+    /// [detecting a stateless reset](https://www.rfc-editor.org/rfc/rfc9000.html#name-detecting-a-stateless-reset)
+    /// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
+    fn try_to_route_stateless_reset(
+        packet: DataPacket,
+        ecn: Ecn,
+        pathway: Pathway,
+        usc: &ArcUsc,
+    ) -> Result<(), DataPacket> {
+        if !matches!(packet.header, DataHeader::Short(_)) {
+            return Err(packet);
+        }
+        let Some(reset_token) = ResetToken::from_datagram_tail(&packet.bytes) else {
+            return Err(packet);
+        };
+        let Some(entry) = RESET_TOKENS.get(&reset_token).map(|entry| entry.clone()) else {
+            return Err(packet);
+        };
+        if entry.is_closed() {
+            RESET_TOKENS.remove(&reset_token);
+            return Err(packet);
+        }
+        _ = entry.unbounded_send((packet, ecn, pathway, usc.clone()));
+        Ok(())
+    }
+
+    /// The length of the DCID of the packets in the `datagram`, which is needed to parse the short
+    /// header packets.
+    ///
+    /// The packets coalesced in a datagram share the same DCID, if the first packet has a long
+    /// header, the length is read from it. Otherwise, each length of the local connection IDs is
+    /// tried, until a connection is found by the DCID of that length.
+    fn dcid_len(datagram: &[u8]) -> usize {
+        // 长包头：first byte, version(4 bytes), dcid len
+        if datagram.first().is_some_and(|byte| byte & 0x80 != 0) {
+            return datagram.get(5).map_or(0, |&len| len as usize);
+        }
+        let mut lens = ROUTER.cid_lens();
+        let first = lens.next().unwrap_or(0);
+        std::iter::once(first)
+            .chain(lens)
+            .find(|&len| {
+                datagram
+                    .get(1..1 + len)
+                    .is_some_and(|dcid| ROUTER.contains_key(&ConnectionId::from_slice(dcid)))
+            })
+            // 找不到连接的短包，用最短的长度解析出DCID，以回应无状态重置
+            .unwrap_or(first)
+    }
+
     /// Register a new connection to the global router.
     ///
     /// Return a [`RouterRegistry`], a wrapper around the connection's local CIDs. it can be used to
-    /// generate a new unique CID and add a router entry to the global router.
+    /// generate a new unique CID and add a router entry to the global router. The new CIDs are
+    /// produced by the `cid_generator`.
     pub fn registry<ISSUED>(
         scid: ConnectionId,
         issued_cids: ISSUED,
         packet_entries: [PacketEntry; 4],
+        cid_generator: ArcCidGenerator,
     ) -> RouterRegistry<ISSUED>
     where
         ISSUED: SendFrame<NewConnectionIdFrame>,
@@ -58,9 +260,26 @@ impl Router {
         RouterRegistry {
             issued_cids,
             packet_entries,
+            cid_generator,
         }
     }
 
+    /// Route the packets whose DCID is `alias` to the same connection as the `cid`.
+    ///
+    /// For server, the client keeps sending Initial and 0-RTT packets with the DCID chosen by
+    /// itself until it receives the server's first Initial packet, these packets, whether coalesced
+    /// in one datagram or not, must be delivered to the connection already created for them,
+    /// rather than create another one.
+    ///
+    /// Return `false` if the `cid` has no router entry, or the `alias` is already in use. The alias
+    /// should be removed by [`Router::remove`] once it is no longer used by the peer.
+    pub fn alias(alias: ConnectionId, cid: &ConnectionId) -> bool {
+        let Some(entries) = ROUTER.get(cid).map(|entries| entries.clone()) else {
+            return false;
+        };
+        ROUTER.insert_vacant(alias, entries)
+    }
+
     /// Return a [`RevokeRouter`], a wrapper around the local CIDs of the connection.
     ///
     /// It can be used to remove the router entry from the global router when a CID is revoked, read
@@ -69,6 +288,56 @@ impl Router {
         RevokeRouter { local_cids }
     }
 
+    /// Derive the stateless reset token of a connection ID issued by us, using a keyed hash of the
+    /// connection ID, so that the token can be regenerated without any connection state.
+    ///
+    /// See [calculating a stateless reset token](https://www.rfc-editor.org/rfc/rfc9000.html#name-calculating-a-stateless-res)
+    /// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
+    pub fn reset_token(cid: &ConnectionId) -> ResetToken {
+        let tag = hmac::sign(&RESET_KEY, cid);
+        ResetToken::new(&tag.as_ref()[..RESET_TOKEN_SIZE])
+    }
+
+    /// Generate a stateless reset in response to a packet of `rcvd_size` bytes with the unknown
+    /// `dcid`, which looks like a short header packet and ends with the stateless reset token.
+    ///
+    /// The stateless reset is always smaller than the received packet, to avoid an infinite loop
+    /// of stateless resets between two endpoints. Return [`None`] if the received packet is too
+    /// small to respond with a stateless reset.
+    pub fn stateless_reset(dcid: &ConnectionId, rcvd_size: usize) -> Option<Vec<u8>> {
+        if rcvd_size <= MIN_STATELESS_RESET_SIZE {
+            return None;
+        }
+        let size = (rcvd_size - 1).min(MAX_STATELESS_RESET_SIZE);
+        let mut datagram = vec![0u8; size - RESET_TOKEN_SIZE];
+        SystemRandom::new().fill(&mut datagram).ok()?;
+        // fixed bit set, long header bit cleared, the rest are unpredictable
+        datagram[0] = 0x40 | (datagram[0] & 0x3F);
+        datagram.extend_from_slice(&Self::reset_token(dcid));
+        Some(datagram)
+    }
+
+    /// Deliver the stateless resets ending with the `reset_token` issued by the peer to the 1-RTT
+    /// packet `entry` of the connection, since they can not be routed by their DCIDs.
+    pub fn add_reset_token(reset_token: ResetToken, entry: PacketEntry) {
+        // 连接结束后其入口随之关闭，表增长一倍时清理一次
+        let prune_at = RESET_TOKENS_PRUNE_AT.load(Ordering::Relaxed);
+        if RESET_TOKENS.len() >= prune_at {
+            RESET_TOKENS.retain(|_, entry| !entry.is_closed());
+            let prune_at = (RESET_TOKENS.len() * 2).max(64);
+            RESET_TOKENS_PRUNE_AT.store(prune_at, Ordering::Relaxed);
+        }
+        RESET_TOKENS.insert(reset_token, entry);
+    }
+
+    /// Return a [`ResetTokenRouter`], a wrapper around the remote CIDs of the connection.
+    ///
+    /// It can be used to deliver the stateless resets to the connection by the reset tokens carried
+    /// in the NEW_CONNECTION_ID frames, read the [`ResetTokenRouter`] for more information.
+    pub fn reset_tokens<T>(remote_cids: T, entry: PacketEntry) -> ResetTokenRouter<T> {
+        ResetTokenRouter { remote_cids, entry }
+    }
+
     /// Remove the router entry from the global router directly.
     ///
     /// This is used when the connection is closed, all the remaining router entries of the
@@ -82,6 +351,7 @@ impl Router {
 pub struct RouterRegistry<ISSUED> {
     issued_cids: ISSUED,
     packet_entries: [PacketEntry; 4],
+    cid_generator: ArcCidGenerator,
 }
 
 impl<T> SendFrame<NewConnectionIdFrame> for RouterRegistry<T>
@@ -95,18 +365,249 @@ where
 
 impl<T> GenUniqueCid for RouterRegistry<T> {
     fn gen_unique_cid(&self) -> ConnectionId {
-        std::iter::from_fn(|| Some(ConnectionId::random_gen_with_mark(8, 0x80, 0x7F)))
-            .find(|cid| {
-                let entry = ROUTER.entry(*cid);
-                if matches!(entry, dashmap::Entry::Vacant(_)) {
-                    entry.or_insert(self.packet_entries.clone());
-                    true
-                } else {
-                    false
-                }
-            })
+        std::iter::repeat_with(|| self.cid_generator.generate_cid())
+            .find(|cid| ROUTER.insert_vacant(*cid, self.packet_entries.clone()))
             .unwrap()
     }
+
+    fn gen_reset_token(&self, cid: &ConnectionId) -> ResetToken {
+        Router::reset_token(cid)
+    }
+}
+
+/// The policy of the server to respond the Initial packets of new connections with Retry packets,
+/// to validate the client's address before creating any connection state, as a DoS mitigation.
+///
+/// The retry token carried by the Retry packet is opaque to the client, it is bound to the client's
+/// address and the original destination connection ID with a keyed hash, and is only valid for a
+/// period of time. The connection will be created only when the subsequent Initial packet carries
+/// a valid retry token.
+///
+/// The policy also mints the tokens sent in the NEW_TOKEN frames, a client can carry such a token
+/// in the Initial packet of a future connection to skip the Retry round trip. These tokens are
+/// bound to the client's IP address only, because the client is likely to use another port, and
+/// have a longer lifetime. To prevent them from being replayed, each of them is accepted only once
+/// within its lifetime. The tokens are self-describing, a retry token is never accepted as a
+/// NEW_TOKEN token and vice versa.
+///
+/// See [address validation using retry packets](https://www.rfc-editor.org/rfc/rfc9000.html#name-address-validation-using-re)
+/// and [address validation for future connections](https://www.rfc-editor.org/rfc/rfc9000.html#name-address-validation-for-futu)
+/// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
+pub struct RetryPolicy {
+    key: hmac::Key,
+    lifetime: Duration,
+    new_token_lifetime: Duration,
+    // 已经被使用过的NEW_TOKEN令牌的认证标签，及其过期的时刻
+    used_new_tokens: Mutex<HashMap<Vec<u8>, u64>>,
+    filter: Box<dyn Fn(SocketAddr) -> bool + Send + Sync>,
+    // 令牌中的时间戳为UNIX时间，由时钟上的起点与其对应的UNIX时间推算
+    clock: ArcClock,
+    epoch: (Instant, u64),
+}
+
+impl std::fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("lifetime", &self.lifetime)
+            .field("new_token_lifetime", &self.new_token_lifetime)
+            .finish()
+    }
+}
+
+/// A valid address validation token carried by an Initial packet, see [`RetryPolicy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidatedToken {
+    /// A retry token, with the original destination connection ID bound to it.
+    Retry(ConnectionId),
+    /// A token issued in a NEW_TOKEN frame on a previous connection.
+    NewToken,
+}
+
+impl RetryPolicy {
+    /// The size of the timestamp in the token.
+    const TIMESTAMP_SIZE: usize = 8;
+    /// The first byte of a retry token.
+    const RETRY_TOKEN: u8 = 0;
+    /// The first byte of a token sent in a NEW_TOKEN frame.
+    const NEW_TOKEN: u8 = 1;
+    /// The default lifetime of the tokens sent in the NEW_TOKEN frames.
+    pub const DEFAULT_NEW_TOKEN_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+    /// Create a policy that responds all new connections with Retry packets, the retry tokens
+    /// are valid for `lifetime`.
+    pub fn new(lifetime: Duration) -> Self {
+        Self::with_filter(lifetime, |_| true)
+    }
+
+    /// Create a policy that responds the new connections from the peers selected by the `filter`
+    /// with Retry packets, the retry tokens are valid for `lifetime`.
+    pub fn with_filter(
+        lifetime: Duration,
+        filter: impl Fn(SocketAddr) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            key: hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new())
+                .expect("failed to generate retry token key"),
+            lifetime,
+            new_token_lifetime: Self::DEFAULT_NEW_TOKEN_LIFETIME,
+            used_new_tokens: Mutex::default(),
+            filter: Box::new(filter),
+            clock: Arc::new(TokioClock),
+            epoch: (TokioClock.now(), Self::unix_now()),
+        }
+    }
+
+    /// Read the time from the `clock`, the [`TokioClock`] by default.
+    ///
+    /// The tokens are stamped with the wall-clock time, which is the system time when the clock is
+    /// set plus the time elapsed on the `clock` since then.
+    pub fn with_clock(mut self, clock: ArcClock) -> Self {
+        self.epoch = (clock.now(), Self::unix_now());
+        self.clock = clock;
+        self
+    }
+
+    /// Set the lifetime of the tokens sent in the NEW_TOKEN frames, the default is
+    /// [`RetryPolicy::DEFAULT_NEW_TOKEN_LIFETIME`].
+    pub fn with_new_token_lifetime(mut self, lifetime: Duration) -> Self {
+        self.new_token_lifetime = lifetime;
+        self
+    }
+
+    /// Whether the Initial packet from the `peer` without a valid token should be responded
+    /// with a Retry packet.
+    pub fn should_retry(&self, peer: SocketAddr) -> bool {
+        (self.filter)(peer)
+    }
+
+    /// The message authenticated by the token, the port of the `peer` is only bound to the retry
+    /// token.
+    fn message(kind: u8, peer: SocketAddr, timestamp: &[u8], odcid: &ConnectionId) -> Vec<u8> {
+        let mut message = Vec::with_capacity(1 + timestamp.len() + 1 + odcid.len() + 18);
+        message.push(kind);
+        message.extend_from_slice(timestamp);
+        message.push(odcid.len() as u8);
+        message.extend_from_slice(odcid);
+        match peer.ip() {
+            IpAddr::V4(ip) => message.extend_from_slice(&ip.octets()),
+            IpAddr::V6(ip) => message.extend_from_slice(&ip.octets()),
+        }
+        if kind == Self::RETRY_TOKEN {
+            message.extend_from_slice(&peer.port().to_be_bytes());
+        }
+        message
+    }
+
+    fn unix_now() -> u64 {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        now.as_millis() as u64
+    }
+
+    fn now(&self) -> u64 {
+        let (start, unix_start) = self.epoch;
+        let elapsed = self.clock.now().saturating_duration_since(start);
+        unix_start + elapsed.as_millis() as u64
+    }
+
+    fn mint(&self, kind: u8, peer: SocketAddr, odcid: &ConnectionId) -> Vec<u8> {
+        let timestamp = self.now().to_be_bytes();
+        let tag = hmac::sign(&self.key, &Self::message(kind, peer, &timestamp, odcid));
+
+        let mut token = Vec::with_capacity(1 + Self::TIMESTAMP_SIZE + 1 + odcid.len() + 32);
+        token.push(kind);
+        token.extend_from_slice(&timestamp);
+        token.push(odcid.len() as u8);
+        token.extend_from_slice(odcid);
+        token.extend_from_slice(tag.as_ref());
+        token
+    }
+
+    /// Mint a retry token for the `peer`, which is bound to the original destination connection
+    /// ID `odcid`.
+    pub fn mint_token(&self, peer: SocketAddr, odcid: &ConnectionId) -> Vec<u8> {
+        self.mint(Self::RETRY_TOKEN, peer, odcid)
+    }
+
+    /// Mint a token for the `peer` to be sent in a NEW_TOKEN frame, which can be used once in the
+    /// Initial packet of a future connection from the same IP address.
+    pub fn mint_new_token(&self, peer: SocketAddr) -> Vec<u8> {
+        // NEW_TOKEN令牌不绑定连接ID，用随机数代替，使同一时刻签发给同一地址的令牌也各不相同
+        self.mint(Self::NEW_TOKEN, peer, &ConnectionId::random_gen(8))
+    }
+
+    // 记录NEW_TOKEN令牌已被使用，已使用过的令牌返回false；顺便清理已过期的记录
+    fn use_new_token(&self, tag: &[u8], expire_at: u64) -> bool {
+        let now = self.now();
+        let mut used = self.used_new_tokens.lock().unwrap();
+        used.retain(|_, expire_at| *expire_at >= now);
+        used.insert(tag.to_vec(), expire_at).is_none()
+    }
+
+    /// Validate the token carried by the Initial packet from the `peer`.
+    ///
+    /// Return which kind of token it is, or [`None`] if the token is not minted by this policy
+    /// for the `peer`, or has expired. The lifetime of the token depends on its kind, and a
+    /// NEW_TOKEN token which has been accepted before is not accepted again.
+    pub fn validate_token(&self, peer: SocketAddr, token: &[u8]) -> Option<ValidatedToken> {
+        let (kind, remain) = token.split_first()?;
+        let lifetime = match *kind {
+            Self::RETRY_TOKEN => self.lifetime,
+            Self::NEW_TOKEN => self.new_token_lifetime,
+            _ => return None,
+        };
+        let (timestamp, remain) = remain.split_at_checked(Self::TIMESTAMP_SIZE)?;
+        let (odcid_len, remain) = remain.split_first()?;
+        if *odcid_len as usize > MAX_CID_SIZE {
+            return None;
+        }
+        let (odcid, tag) = remain.split_at_checked(*odcid_len as usize)?;
+        let odcid = ConnectionId::from_slice(odcid);
+        let message = Self::message(*kind, peer, timestamp, &odcid);
+        hmac::verify(&self.key, &message, tag).ok()?;
+
+        let issued_at = u64::from_be_bytes(timestamp.try_into().unwrap());
+        let elapsed = self.now().checked_sub(issued_at)?;
+        if elapsed > lifetime.as_millis() as u64 {
+            return None;
+        }
+        match *kind {
+            Self::RETRY_TOKEN => Some(ValidatedToken::Retry(odcid)),
+            _ => self
+                .use_new_token(tag, issued_at + lifetime.as_millis() as u64)
+                .then_some(ValidatedToken::NewToken),
+        }
+    }
+
+    /// Assemble a Retry packet in response to the Initial packet of the QUIC `version` from the
+    /// `peer`, whose DCID is `odcid` and SCID is `client_scid`.
+    ///
+    /// Return the new connection ID chosen by the server, which is the SCID of the Retry packet,
+    /// and the Retry packet.
+    pub fn retry(
+        &self,
+        version: u32,
+        peer: SocketAddr,
+        odcid: &ConnectionId,
+        client_scid: &ConnectionId,
+    ) -> (ConnectionId, Vec<u8>) {
+        let retry_scid = ConnectionId::random_gen_with_mark(8, 0, 0x7F);
+        let token = self.mint_token(peer, odcid);
+
+        let mut packet = Vec::new();
+        packet.put_long_type(&LongType::new(version, LongV1Type::Retry));
+        // Retry包首字节的低4位未使用，随机填充
+        let mut unused = [0u8; 1];
+        _ = SystemRandom::new().fill(&mut unused);
+        packet[0] |= unused[0] & 0x0f;
+        for cid in [client_scid, &retry_scid] {
+            packet.push(cid.len() as u8);
+            packet.extend_from_slice(cid);
+        }
+        packet.extend_from_slice(&token);
+        let integrity = retry_integrity_tag(version, odcid, &packet);
+        packet.extend_from_slice(&integrity);
+        (retry_scid, packet)
+    }
 }
 
 /// A wrapper around the local CIDs of the connection, used to remove the router entry from the
@@ -133,3 +634,314 @@ where
         Ok(())
     }
 }
+
+/// A wrapper around the remote CIDs of the connection, used to deliver the stateless resets to
+/// the connection.
+///
+/// The way this structure works is receiving the [`NewConnectionIdFrame`], and then passed it to
+/// the wrapped struct. The wrapped struct should return the stateless reset token of the new CID
+/// as [`Option`], the stateless resets ending with the token will be delivered to the `entry`.
+#[derive(Clone)]
+pub struct ResetTokenRouter<T> {
+    remote_cids: T,
+    entry: PacketEntry,
+}
+
+impl<T> ReceiveFrame<NewConnectionIdFrame> for ResetTokenRouter<T>
+where
+    T: ReceiveFrame<NewConnectionIdFrame, Output = Option<ResetToken>>,
+{
+    type Output = ();
+
+    fn recv_frame(&self, frame: &NewConnectionIdFrame) -> Result<Self::Output, Error> {
+        if let Some(reset_token) = self.remote_cids.recv_frame(frame)? {
+            Router::add_reset_token(reset_token, self.entry.clone());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::channel::mpsc;
+    use qbase::packet::r#type::long::{QUIC_VERSION_1, QUIC_VERSION_2};
+
+    use super::*;
+    use crate::{clock::MockClock, usc::UscRegistry};
+
+    /// A long header packet with a 1-byte packet number and a 20-byte payload, not protected.
+    fn long_packet(ty: u8, dcid: &ConnectionId, scid: &ConnectionId) -> Vec<u8> {
+        let mut packet = vec![0xc0 | ty];
+        packet.extend_from_slice(&1u32.to_be_bytes());
+        for cid in [dcid, scid] {
+            packet.push(cid.len() as u8);
+            packet.extend_from_slice(cid);
+        }
+        if ty == 0x00 {
+            // empty token of the Initial packet
+            packet.push(0);
+        }
+        packet.push(21);
+        packet.extend_from_slice(&[0u8; 21]);
+        packet
+    }
+
+    #[tokio::test]
+    async fn test_route_coalesced_datagram() {
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let pathway = Pathway::Direct {
+            local: "127.0.0.1:4433".parse().unwrap(),
+            remote: "127.0.0.1:4434".parse().unwrap(),
+        };
+
+        let (entries, mut rcvd): (Vec<_>, Vec<_>) = (0..4).map(|_| mpsc::unbounded()).unzip();
+        let dcid = ConnectionId::random_gen(8);
+        let scid = ConnectionId::random_gen(8);
+        ROUTER.insert(dcid, entries.try_into().unwrap());
+
+        let mut datagram = long_packet(0x00, &dcid, &scid);
+        datagram.extend(long_packet(0x20, &dcid, &scid));
+        // the packet with a different DCID is discarded
+        datagram.extend(long_packet(0x20, &ConnectionId::random_gen(8), &scid));
+        let mut unrouted = 0;
+        Router::route_datagram(datagram[..].into(), Ecn::Ect0, pathway, &usc, |_| {
+            unrouted += 1
+        });
+        assert_eq!(unrouted, 0);
+
+        let (initial, ecn, ..) = rcvd[0].try_next().unwrap().unwrap();
+        assert!(matches!(
+            initial.header,
+            DataHeader::Long(long::DataHeader::Initial(_))
+        ));
+        assert_eq!(ecn, Ecn::Ect0);
+        let (handshake, ..) = rcvd[2].try_next().unwrap().unwrap();
+        assert!(matches!(
+            handshake.header,
+            DataHeader::Long(long::DataHeader::Handshake(_))
+        ));
+        assert!(rcvd.iter_mut().all(|rcvd| rcvd.try_next().is_err()));
+
+        // the packets of unknown connections are not routed
+        Router::remove(&dcid);
+        let datagram = long_packet(0x00, &dcid, &scid);
+        Router::route_datagram(datagram[..].into(), Ecn::NotEct, pathway, &usc, |packet| {
+            assert!(matches!(packet, Packet::Data(_)));
+            unrouted += 1;
+        });
+        assert_eq!(unrouted, 1);
+    }
+
+    #[tokio::test]
+    async fn test_alias() {
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let pathway = Pathway::Direct {
+            local: "127.0.0.1:4433".parse().unwrap(),
+            remote: "127.0.0.1:4434".parse().unwrap(),
+        };
+
+        let (entries, mut rcvd): (Vec<_>, Vec<_>) = (0..4).map(|_| mpsc::unbounded()).unzip();
+        let scid = ConnectionId::random_gen(8);
+        let odcid = ConnectionId::random_gen(8);
+        let client_scid = ConnectionId::random_gen(8);
+        assert!(!Router::alias(odcid, &scid));
+        ROUTER.insert(scid, entries.try_into().unwrap());
+        assert!(Router::alias(odcid, &scid));
+        assert!(!Router::alias(odcid, &scid));
+
+        // 两个Initial包被合并在一个数据报中，都交付给同一个连接
+        let mut datagram = long_packet(0x00, &odcid, &client_scid);
+        datagram.extend(long_packet(0x00, &odcid, &client_scid));
+        Router::route_datagram(datagram[..].into(), Ecn::NotEct, pathway, &usc, |_| {
+            panic!("should be routed")
+        });
+        assert!(rcvd[0].try_next().unwrap().is_some());
+        assert!(rcvd[0].try_next().unwrap().is_some());
+
+        Router::remove(&odcid);
+        Router::remove(&scid);
+    }
+
+    #[tokio::test]
+    async fn test_route_short_header_by_cid_len() {
+        let usc =
+            UscRegistry::get_or_create_usc("127.0.0.1:0".parse().unwrap(), |_| async {}).unwrap();
+        let pathway = Pathway::Direct {
+            local: "127.0.0.1:4433".parse().unwrap(),
+            remote: "127.0.0.1:4434".parse().unwrap(),
+        };
+
+        let (entries, mut rcvd): (Vec<_>, Vec<_>) = (0..4).map(|_| mpsc::unbounded()).unzip();
+        let scid = ConnectionId::random_gen(8);
+        ROUTER.insert(scid, entries.try_into().unwrap());
+        // 例如由RoutableCidGenerator产生的12字节连接ID
+        let cid = ConnectionId::random_gen(12);
+        assert!(Router::alias(cid, &scid));
+
+        // 短包头不携带DCID的长度，由本地连接ID的长度解析
+        let mut datagram = vec![0x40];
+        datagram.extend_from_slice(&cid);
+        datagram.extend_from_slice(&[0u8; 32]);
+        Router::route_datagram(datagram[..].into(), Ecn::NotEct, pathway, &usc, |_| {
+            panic!("should be routed")
+        });
+        let (packet, ..) = rcvd[3].try_next().unwrap().unwrap();
+        assert_eq!(*packet.header.get_dcid(), cid);
+
+        // 连接ID移除后，不再以其长度解析短包头
+        Router::remove(&cid);
+        assert!(ROUTER.cid_lens().all(|len| len != 12));
+        Router::remove(&scid);
+    }
+
+    #[test]
+    fn test_stateless_reset() {
+        let cid = ConnectionId::random_gen(8);
+        let reset_token = Router::reset_token(&cid);
+        assert_eq!(reset_token, Router::reset_token(&cid));
+        assert_ne!(
+            reset_token,
+            Router::reset_token(&ConnectionId::random_gen(8))
+        );
+
+        assert!(Router::stateless_reset(&cid, MIN_STATELESS_RESET_SIZE).is_none());
+        for rcvd_size in [MIN_STATELESS_RESET_SIZE + 1, 100, 1500] {
+            let reset = Router::stateless_reset(&cid, rcvd_size).unwrap();
+            assert!(reset.len() < rcvd_size);
+            assert!(reset.len() <= MAX_STATELESS_RESET_SIZE);
+            assert_eq!(reset[0] & 0xC0, 0x40);
+            assert_eq!(ResetToken::from_datagram_tail(&reset), Some(reset_token));
+        }
+    }
+
+    #[test]
+    fn test_retry_token() {
+        let policy = RetryPolicy::new(Duration::from_secs(10));
+        let peer = "127.0.0.1:4433".parse().unwrap();
+        let odcid = ConnectionId::random_gen(8);
+
+        let token = policy.mint_token(peer, &odcid);
+        assert_eq!(
+            policy.validate_token(peer, &token),
+            Some(ValidatedToken::Retry(odcid))
+        );
+        // bound to the peer address
+        assert_eq!(
+            policy.validate_token("127.0.0.1:4434".parse().unwrap(), &token),
+            None
+        );
+        assert_eq!(
+            policy.validate_token("127.0.0.2:4433".parse().unwrap(), &token),
+            None
+        );
+        // minted by another policy
+        let another = RetryPolicy::new(Duration::from_secs(10));
+        assert_eq!(another.validate_token(peer, &token), None);
+        // tampered or truncated
+        let mut tampered = token.clone();
+        tampered[1 + RetryPolicy::TIMESTAMP_SIZE + 1] ^= 0x01;
+        assert_eq!(policy.validate_token(peer, &tampered), None);
+        assert_eq!(policy.validate_token(peer, &token[..token.len() - 1]), None);
+        assert_eq!(policy.validate_token(peer, &[]), None);
+
+        let clock = MockClock::new();
+        let expiring =
+            RetryPolicy::new(Duration::from_millis(10)).with_clock(Arc::new(clock.clone()));
+        let token = expiring.mint_token(peer, &odcid);
+        clock.advance(Duration::from_millis(10));
+        assert!(expiring.validate_token(peer, &token).is_some());
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(expiring.validate_token(peer, &token), None);
+    }
+
+    #[test]
+    fn test_retry_packet() {
+        use bytes::BytesMut;
+        use qbase::packet::{header::GetScid, Packet, PacketReader};
+
+        let policy = RetryPolicy::with_filter(Duration::from_secs(10), |peer| peer.is_ipv6());
+        let peer = "127.0.0.1:4433".parse().unwrap();
+        assert!(!policy.should_retry(peer));
+        assert!(policy.should_retry("[::1]:4433".parse().unwrap()));
+
+        let odcid = ConnectionId::random_gen(8);
+        let client_scid = ConnectionId::random_gen(8);
+        for version in [QUIC_VERSION_1, QUIC_VERSION_2] {
+            let (retry_scid, packet) = policy.retry(version, peer, &odcid, &client_scid);
+
+            let Some(Ok(Packet::Retry(retry))) =
+                PacketReader::new(BytesMut::from(&packet[..]), 8).next()
+            else {
+                panic!("not a retry packet");
+            };
+            assert_eq!(retry.version, version);
+            assert!(retry.verify_integrity(&odcid));
+            assert_eq!(*retry.get_dcid(), client_scid);
+            assert_eq!(*retry.get_scid(), retry_scid);
+            assert_eq!(
+                policy.validate_token(peer, &retry.token),
+                Some(ValidatedToken::Retry(odcid))
+            );
+        }
+    }
+
+    #[test]
+    fn test_new_token() {
+        let clock = MockClock::new();
+        let policy = RetryPolicy::new(Duration::from_millis(10))
+            .with_new_token_lifetime(Duration::from_secs(10))
+            .with_clock(Arc::new(clock.clone()));
+        let peer = "127.0.0.1:4433".parse().unwrap();
+
+        let token = policy.mint_new_token(peer);
+        assert_eq!(
+            policy.validate_token(peer, &token),
+            Some(ValidatedToken::NewToken)
+        );
+        // 令牌只能使用一次，不能被重放
+        assert_eq!(policy.validate_token(peer, &token), None);
+        // 同一时刻签发给同一地址的令牌各不相同，互不影响
+        let token = policy.mint_new_token(peer);
+        let another = policy.mint_new_token(peer);
+        assert_ne!(token, another);
+        assert_eq!(
+            policy.validate_token(peer, &another),
+            Some(ValidatedToken::NewToken)
+        );
+        // 客户端在新连接中可能换用其它端口，但不能换用其它地址
+        assert_eq!(
+            policy.validate_token("127.0.0.2:4433".parse().unwrap(), &token),
+            None
+        );
+        assert_eq!(
+            policy.validate_token("127.0.0.1:5533".parse().unwrap(), &token),
+            Some(ValidatedToken::NewToken)
+        );
+        // 令牌的类型无法被篡改
+        let mut disguised = token.clone();
+        disguised[0] = RetryPolicy::RETRY_TOKEN;
+        assert_eq!(policy.validate_token(peer, &disguised), None);
+        let mut unknown = token.clone();
+        unknown[0] = 0xff;
+        assert_eq!(policy.validate_token(peer, &unknown), None);
+
+        // 两类令牌的有效期不同
+        let token = policy.mint_new_token(peer);
+        let retry_token = policy.mint_token(peer, &ConnectionId::random_gen(8));
+        clock.advance(Duration::from_millis(20));
+        assert_eq!(policy.validate_token(peer, &retry_token), None);
+        assert_eq!(
+            policy.validate_token(peer, &token),
+            Some(ValidatedToken::NewToken)
+        );
+
+        let expiring = RetryPolicy::new(Duration::from_secs(10))
+            .with_new_token_lifetime(Duration::from_millis(10))
+            .with_clock(Arc::new(clock.clone()));
+        let token = expiring.mint_new_token(peer);
+        clock.advance(Duration::from_millis(11));
+        assert_eq!(expiring.validate_token(peer, &token), None);
+    }
+}
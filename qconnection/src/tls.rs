@@ -4,8 +4,11 @@ use core::{
 };
 use std::sync::{Arc, Mutex};
 
+use dashmap::DashMap;
+
 use qbase::{
     cid::ConnectionId,
+    entropy::EntropySource,
     error::{Error, ErrorKind},
     packet::keys::{ArcKeys, ArcOneRttKeys},
     param::{
@@ -16,14 +19,19 @@ use qbase::{
 use qrecovery::{crypto::CryptoStream, space::Epoch};
 use rustls::{
     crypto::CryptoProvider,
-    quic::{KeyChange, Keys},
+    pki_types::{CertificateDer, ServerName},
+    quic::{KeyChange, Keys, Version},
     Side,
 };
 use thiserror::Error;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
 use crate::{
-    conn::{parameters::RemoteParameters, Handshake},
+    conn::{
+        parameters::RemoteParameters,
+        state::{ArcConnectionState, ConnectionState},
+        Handshake,
+    },
     error::ConnError,
 };
 
@@ -31,8 +39,62 @@ use crate::{
 #[error("TLS session is aborted")]
 pub struct Aborted;
 
+/// The cache of the server's transport parameters for 0-RTT, keyed by the server name.
+///
+/// To send 0-RTT data, the client must remember the transport parameters of the server along
+/// with the TLS session ticket. The ticket is cached by the [`ClientSessionStore`] of the
+/// [`rustls::ClientConfig`] when the NewSessionTicket message is received, and the parameters
+/// are cached here once the handshake is completed.
+///
+/// See [values of transport parameters for 0-RTT](https://www.rfc-editor.org/rfc/rfc9000.html#name-values-of-transport-paramet)
+/// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
+///
+/// [`ClientSessionStore`]: rustls::client::ClientSessionStore
+pub trait SessionCache: Send + Sync {
+    /// Store the transport parameters confirmed by the server.
+    fn store(&self, server_name: &str, params: Arc<Parameters>);
+
+    /// Load the remembered transport parameters of the server, if any.
+    fn load(&self, server_name: &str) -> Option<Arc<Parameters>>;
+}
+
+/// A [`SessionCache`] that keeps the remembered transport parameters in memory.
+#[derive(Debug, Default)]
+pub struct MemorySessionCache(DashMap<String, Arc<Parameters>>);
+
+impl SessionCache for MemorySessionCache {
+    fn store(&self, server_name: &str, params: Arc<Parameters>) {
+        self.0.insert(server_name.to_owned(), params);
+    }
+
+    fn load(&self, server_name: &str) -> Option<Arc<Parameters>> {
+        self.0.get(server_name).map(|params| params.clone())
+    }
+}
+
 type TlsConnection = rustls::quic::Connection;
 
+/// The arguments to create the client TLS connection, kept to restart the handshake with another
+/// QUIC version after the version negotiation.
+#[derive(Debug)]
+struct ClientArgs {
+    server_name: ServerName<'static>,
+    tls_config: Arc<rustls::ClientConfig>,
+    params: Vec<u8>,
+}
+
+impl ClientArgs {
+    fn connect(&self, version: Version) -> TlsConnection {
+        let client_connection = rustls::quic::ClientConnection::new(
+            self.tls_config.clone(),
+            version,
+            self.server_name.clone(),
+            self.params.clone(),
+        );
+        TlsConnection::Client(client_connection.unwrap())
+    }
+}
+
 #[derive(Debug)]
 struct TlsSession {
     tls_conn: TlsConnection,
@@ -40,6 +102,7 @@ struct TlsSession {
     /// Optimize: avoid reading transport parameters repeatedly, because the rustls willnot consume
     /// the bytes of transport parameters after reading them.
     params_read: bool,
+    client_args: Option<ClientArgs>,
 }
 
 impl From<TlsConnection> for TlsSession {
@@ -48,6 +111,7 @@ impl From<TlsConnection> for TlsSession {
             tls_conn,
             read_waker: None,
             params_read: false,
+            client_args: None,
         }
     }
 }
@@ -59,8 +123,21 @@ impl TlsSession {
         }
     }
 
-    fn write(&mut self, buf: &[u8]) -> Result<(), rustls::Error> {
-        self.tls_conn.read_hs(buf)
+    /// Feed the TLS handshake data received from the peer.
+    ///
+    /// The TLS errors are translated into QUIC [`Error`]s, with the TLS alert code carried in the
+    /// [`ErrorKind::Crypto`], which will be sent to the peer in the CONNECTION_CLOSE frame.
+    ///
+    /// See [TLS errors](https://www.rfc-editor.org/rfc/rfc9001.html#name-tls-errors)
+    /// of [RFC 9001](https://www.rfc-editor.org/rfc/rfc9001.html) for more details.
+    fn write(&mut self, buf: &[u8]) -> Result<(), Error> {
+        self.tls_conn.read_hs(buf).map_err(|e| {
+            let error_kind = match self.tls_conn.alert() {
+                Some(alert) => ErrorKind::Crypto(alert.into()),
+                None => ErrorKind::ProtocolViolation,
+            };
+            Error::with_default_fty(error_kind, format!("TLS error: {e}"))
+        })
     }
 
     fn read(&mut self, buf: &mut Vec<u8>) -> Option<KeyChange> {
@@ -99,12 +176,35 @@ impl TlsSession {
         self.tls_conn.is_handshaking()
     }
 
+    fn is_0rtt_rejected(&self) -> bool {
+        match &self.tls_conn {
+            TlsConnection::Client(client_conn) => !client_conn.is_early_data_accepted(),
+            TlsConnection::Server(_) => false,
+        }
+    }
+
     fn server_name(&self) -> Option<&str> {
         match &self.tls_conn {
             TlsConnection::Server(server_conn) => server_conn.server_name(),
             TlsConnection::Client(_) => None,
         }
     }
+
+    fn peer_certificates(&self) -> Option<&[CertificateDer<'static>]> {
+        // 握手完成之前，证书链可能尚未经过验证
+        if self.tls_conn.is_handshaking() {
+            return None;
+        }
+        self.tls_conn.peer_certificates()
+    }
+
+    fn alpn_protocol(&self) -> Option<&[u8]> {
+        // 握手完成之前，协商的结果对另一端而言还未确定
+        if self.tls_conn.is_handshaking() {
+            return None;
+        }
+        self.tls_conn.alpn_protocol()
+    }
 }
 
 struct ReadTls<'r> {
@@ -149,41 +249,74 @@ impl futures::Future for ReadTls<'_> {
 pub struct ArcTlsSession(Arc<Mutex<Result<TlsSession, Aborted>>>);
 
 impl ArcTlsSession {
-    /// The QUIC version used by the TLS session.
-    const QUIC_VERSION: rustls::quic::Version = rustls::quic::Version::V1;
-
-    /// Create a new client-side TLS session.
+    /// Create a new client-side TLS session, whose packet protection keys are derived with the
+    /// QUIC `version`.
     pub fn new_client(
-        server_name: rustls::pki_types::ServerName<'static>,
+        server_name: ServerName<'static>,
         tls_config: Arc<rustls::ClientConfig>,
         parameters: &Parameters,
+        version: Version,
+        entropy: &dyn EntropySource,
     ) -> Self {
-        let mut params_bytes = Vec::new();
-        params_bytes.put_parameters(parameters);
+        let mut params = Vec::new();
+        params.put_parameters(parameters, entropy);
 
-        let client_connection = rustls::quic::ClientConnection::new(
-            tls_config,
-            Self::QUIC_VERSION,
+        let client_args = ClientArgs {
             server_name,
-            params_bytes,
-        );
-        let connection = rustls::quic::Connection::Client(client_connection.unwrap());
-        Self(Arc::new(Mutex::new(Ok(connection.into()))))
+            tls_config,
+            params,
+        };
+        let mut session = TlsSession::from(client_args.connect(version));
+        session.client_args = Some(client_args);
+        Self(Arc::new(Mutex::new(Ok(session))))
     }
 
-    /// Create a new server-side TLS session.
-    pub fn new_server(tls_config: Arc<rustls::ServerConfig>, parameters: &Parameters) -> Self {
+    /// Create a new server-side TLS session, whose packet protection keys are derived with the
+    /// QUIC `version` of the client's Initial packets.
+    pub fn new_server(
+        tls_config: Arc<rustls::ServerConfig>,
+        parameters: &Parameters,
+        version: Version,
+        entropy: &dyn EntropySource,
+    ) -> Self {
         let mut params = Vec::new();
-        params.put_parameters(parameters);
+        params.put_parameters(parameters, entropy);
 
         let server_connection =
-            rustls::quic::ServerConnection::new(tls_config, Self::QUIC_VERSION, params).unwrap();
+            rustls::quic::ServerConnection::new(tls_config, version, params).unwrap();
         let connection = rustls::quic::Connection::Server(server_connection);
         Self(Arc::new(Mutex::new(Ok(connection.into()))))
     }
 
-    /// Generate the keys for the initial packet protection.
-    pub fn initial_keys(crypto_provider: &CryptoProvider, side: Side, cid: ConnectionId) -> Keys {
+    /// For client, restart the handshake with the QUIC `version` selected by the version
+    /// negotiation.
+    ///
+    /// A new TLS connection is created, which writes a new ClientHello, because the Handshake
+    /// and 1-RTT keys of the current one are derived with the version offered before. The caller
+    /// should restart the Initial crypto stream before, so that the new ClientHello is sent from
+    /// the beginning of it.
+    ///
+    /// For server, nothing happens.
+    pub fn restart(&self, version: Version) {
+        let mut guard = self.0.lock().unwrap();
+        let Ok(session) = guard.deref_mut() else {
+            return;
+        };
+        let Some(client_args) = session.client_args.as_ref() else {
+            return;
+        };
+        session.tls_conn = client_args.connect(version);
+        session.params_read = false;
+        session.wake_read();
+    }
+
+    /// Generate the keys for the initial packet protection of the QUIC `version`.
+    pub fn initial_keys(
+        crypto_provider: &CryptoProvider,
+        side: Side,
+        cid: ConnectionId,
+        version: Version,
+    ) -> Keys {
         let suite = crypto_provider
             .cipher_suites
             .iter()
@@ -195,7 +328,7 @@ impl ArcTlsSession {
             })
             .flatten()
             .unwrap();
-        suite.keys(&cid, side, rustls::quic::Version::V1)
+        suite.keys(&cid, side, version)
     }
 
     /// Abort the TLS session, the handshaking will be stopped if it is not completed.
@@ -230,6 +363,10 @@ impl ArcTlsSession {
     /// The [`Handshake`] is used to notify the other components that the handshake is completed,
     /// for server, it should send the [`HandshakeDoneFrame`] to the client.
     ///
+    /// The [`ArcConnectionState`] moves to [`ConnectionState::Handshaking`] when the Handshake keys
+    /// are installed, and to [`ConnectionState::Connected`] when the handshake is completed and
+    /// the peer's transport parameters are accepted, read [`RemoteParameters::accepted`].
+    ///
     /// Return a [`RemoteParameters`] that can be used to asynchronously get the peer's transport
     /// parameters.
     ///
@@ -241,6 +378,7 @@ impl ArcTlsSession {
         one_rtt_keys: ArcOneRttKeys,
         conn_error: ConnError,
         handshake: Handshake,
+        state: ArcConnectionState,
     ) -> RemoteParameters {
         let remote_params = RemoteParameters::new();
 
@@ -258,13 +396,8 @@ impl ArcTlsSession {
                         Err(_aborted) => break,
                     };
 
-                    if let Err(e) = tls_connection.write(&read_buf[..read]) {
-                        let error_kind = match tls_connection.alert() {
-                            Some(alert) => ErrorKind::Crypto(alert.into()),
-                            None => ErrorKind::ProtocolViolation,
-                        };
-                        let reason = format!("TLS error: {e}");
-                        conn_error.on_error(Error::with_default_fty(error_kind, reason));
+                    if let Err(error) = tls_connection.write(&read_buf[..read]) {
+                        conn_error.on_error(error);
                         break;
                     }
 
@@ -285,7 +418,10 @@ impl ArcTlsSession {
                 let mut send_buf = Vec::with_capacity(1500);
                 let mut cur_epoch = Epoch::Initial;
                 loop {
-                    let read_params = !remote_params.is_ready();
+                    // 客户端尝试0Rtt时，rustls会提前给出记忆的传输参数，进入Handshake空间后读到的才是服务端确认的
+                    let read_params = !remote_params.is_ready()
+                        && (matches!(handshake, Handshake::Server(..))
+                            || !matches!(cur_epoch, Epoch::Initial));
                     let read_result = tls_session.read(&mut send_buf, read_params).await;
                     let (key_upgrade, params, is_handshaking) = match read_result {
                         Ok(results) => results,
@@ -317,6 +453,7 @@ impl ArcTlsSession {
                             rustls::quic::KeyChange::Handshake { keys } => {
                                 handshake_keys.set_keys(keys);
                                 cur_epoch = Epoch::Handshake;
+                                _ = state.transition(ConnectionState::Handshaking);
                             }
                             rustls::quic::KeyChange::OneRtt { keys, next } => {
                                 one_rtt_keys.set_keys(keys, next);
@@ -326,6 +463,11 @@ impl ArcTlsSession {
                     }
 
                     if !is_handshaking {
+                        // 对端的传输参数验证通过之后，才算真正建立了连接
+                        if !remote_params.accepted().await {
+                            break;
+                        }
+                        _ = state.transition(ConnectionState::Connected);
                         if let Handshake::Server(server_handshake) = &handshake {
                             server_handshake.done();
                         }
@@ -341,6 +483,41 @@ impl ArcTlsSession {
         remote_params
     }
 
+    /// For client, try to resume the session with 0-RTT.
+    ///
+    /// Return the 0-RTT keys and the remembered transport parameters of the server, or [`None`]
+    /// if there is no ticket allowing early data, or no parameters remembered in the
+    /// `session_cache`, in which case the connection falls back to 1-RTT.
+    ///
+    /// For server, always returns [`None`].
+    pub fn load_0rtt(
+        &self,
+        server_name: &str,
+        session_cache: &dyn SessionCache,
+    ) -> Option<(Keys, Arc<Parameters>)> {
+        let guard = self.0.lock().unwrap();
+        let TlsConnection::Client(client_conn) = &guard.as_ref().ok()?.tls_conn else {
+            return None;
+        };
+        // 每次调用都会派生出新的一份密钥，0Rtt的密钥客户端只用于发送
+        let local = client_conn.zero_rtt_keys()?;
+        let remote = client_conn.zero_rtt_keys()?;
+        let remembered = session_cache.load(server_name)?;
+        Some((Keys { local, remote }, remembered))
+    }
+
+    /// For client, returns whether the 0-RTT data is rejected by the server, or 0-RTT is not
+    /// attempted at all. It is only meaningful once the handshake is completed.
+    ///
+    /// For server, always returns `false`.
+    pub fn is_0rtt_rejected(&self) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_ok_and(TlsSession::is_0rtt_rejected)
+    }
+
     /// For server, retrieves the server name, if any, used to select the certificate and private key.
     ///
     /// For client, returns [`None`].
@@ -355,4 +532,545 @@ impl ArcTlsSession {
             .and_then(TlsSession::server_name)
             .map(ToString::to_string)
     }
+
+    /// Retrieves the certificate chain presented by the peer, the end-entity certificate comes
+    /// first.
+    ///
+    /// Returns [`None`] if the handshake has not been completed yet, or the peer does not
+    /// present any certificate, such as a client without client authentication.
+    ///
+    /// read [`rustls::CommonState::peer_certificates`] for more.
+    pub fn peer_certificates(&self) -> Option<Vec<CertificateDer<'static>>> {
+        self.0
+            .lock()
+            .unwrap()
+            .as_ref()
+            .ok()
+            .and_then(TlsSession::peer_certificates)
+            .map(<[_]>::to_vec)
+    }
+
+    /// Retrieves the protocol negotiated by ALPN.
+    ///
+    /// Returns [`None`] if the handshake has not been completed yet, or no protocol is
+    /// negotiated, such as the peer does not support ALPN.
+    ///
+    /// read [`rustls::CommonState::alpn_protocol`] for more.
+    pub fn alpn_protocol(&self) -> Option<Vec<u8>> {
+        self.0
+            .lock()
+            .unwrap()
+            .as_ref()
+            .ok()
+            .and_then(TlsSession::alpn_protocol)
+            .map(<[u8]>::to_vec)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use rustls::{
+        client::{
+            danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+            ResolvesClientCert,
+        },
+        pki_types::{PrivateKeyDer, ServerName, UnixTime},
+        server::{
+            danger::{ClientCertVerified, ClientCertVerifier},
+            ClientHello, ResolvesServerCert,
+        },
+        sign::CertifiedKey,
+        AlertDescription, CertificateError, ClientConfig, DigitallySignedStruct, DistinguishedName,
+        ServerConfig, SignatureScheme,
+    };
+
+    use qbase::entropy::OsEntropy;
+
+    use super::*;
+
+    /// A verifier that accepts or rejects all the server certificates.
+    #[derive(Debug)]
+    struct Verifier {
+        accept: bool,
+    }
+
+    impl ServerCertVerifier for Verifier {
+        fn verify_server_cert(
+            &self,
+            _end_entity: &CertificateDer<'_>,
+            _intermediates: &[CertificateDer<'_>],
+            _server_name: &ServerName<'_>,
+            _ocsp_response: &[u8],
+            _now: UnixTime,
+        ) -> Result<ServerCertVerified, rustls::Error> {
+            match self.accept {
+                true => Ok(ServerCertVerified::assertion()),
+                false => Err(rustls::Error::InvalidCertificate(
+                    CertificateError::UnknownIssuer,
+                )),
+            }
+        }
+
+        fn verify_tls12_signature(
+            &self,
+            _message: &[u8],
+            _cert: &CertificateDer<'_>,
+            _dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            unreachable!("TLS 1.2 is not used in QUIC")
+        }
+
+        fn verify_tls13_signature(
+            &self,
+            _message: &[u8],
+            _cert: &CertificateDer<'_>,
+            _dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            Ok(HandshakeSignatureValid::assertion())
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+            vec![SignatureScheme::ECDSA_NISTP256_SHA256]
+        }
+    }
+
+    /// Always serve the same certificate, the certificate is never verified by itself.
+    #[derive(Debug)]
+    struct SingleCert(Arc<CertifiedKey>);
+
+    impl ResolvesServerCert for SingleCert {
+        fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+            Some(self.0.clone())
+        }
+    }
+
+    /// Always present the same client certificate.
+    #[derive(Debug)]
+    struct ClientCert(Arc<CertifiedKey>);
+
+    impl ResolvesClientCert for ClientCert {
+        fn resolve(
+            &self,
+            _root_hint_subjects: &[&[u8]],
+            _sigschemes: &[SignatureScheme],
+        ) -> Option<Arc<CertifiedKey>> {
+            Some(self.0.clone())
+        }
+
+        fn has_certs(&self) -> bool {
+            true
+        }
+    }
+
+    /// A verifier that requests the client certificates, and accepts or rejects all of them.
+    ///
+    /// The client without certificate is rejected if the client authentication is `mandatory`.
+    #[derive(Debug)]
+    struct ClientVerifier {
+        accept: bool,
+        mandatory: bool,
+    }
+
+    impl ClientCertVerifier for ClientVerifier {
+        fn client_auth_mandatory(&self) -> bool {
+            self.mandatory
+        }
+
+        fn root_hint_subjects(&self) -> &[DistinguishedName] {
+            &[]
+        }
+
+        fn verify_client_cert(
+            &self,
+            _end_entity: &CertificateDer<'_>,
+            _intermediates: &[CertificateDer<'_>],
+            _now: UnixTime,
+        ) -> Result<ClientCertVerified, rustls::Error> {
+            match self.accept {
+                true => Ok(ClientCertVerified::assertion()),
+                false => Err(rustls::Error::InvalidCertificate(
+                    CertificateError::UnknownIssuer,
+                )),
+            }
+        }
+
+        fn verify_tls12_signature(
+            &self,
+            _message: &[u8],
+            _cert: &CertificateDer<'_>,
+            _dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            unreachable!("TLS 1.2 is not used in QUIC")
+        }
+
+        fn verify_tls13_signature(
+            &self,
+            _message: &[u8],
+            _cert: &CertificateDer<'_>,
+            _dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            Ok(HandshakeSignatureValid::assertion())
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+            vec![SignatureScheme::ECDSA_NISTP256_SHA256]
+        }
+    }
+
+    /// Generate a signing key, along with a fake certificate chain, which is never parsed.
+    fn certified_key(cert_chain: &[&[u8]]) -> Arc<CertifiedKey> {
+        let rng = ring::rand::SystemRandom::new();
+        let alg = &ring::signature::ECDSA_P256_SHA256_ASN1_SIGNING;
+        let pkcs8 = ring::signature::EcdsaKeyPair::generate_pkcs8(alg, &rng).unwrap();
+        let key = PrivateKeyDer::Pkcs8(pkcs8.as_ref().to_vec().into());
+        let signing_key = rustls::crypto::ring::sign::any_supported_type(&key).unwrap();
+        let cert_chain = cert_chain
+            .iter()
+            .map(|cert| CertificateDer::from(cert.to_vec()))
+            .collect();
+        Arc::new(CertifiedKey::new(cert_chain, signing_key))
+    }
+
+    pub(crate) fn tls_configs(accept_cert: bool) -> (Arc<ServerConfig>, Arc<ClientConfig>) {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let certified_key = certified_key(&[b"not a certificate"]);
+
+        let mut server_config = ServerConfig::builder_with_provider(provider.clone())
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(SingleCert(certified_key)));
+        // QUIC要求early data的大小为0或者0xffffffff
+        server_config.max_early_data_size = u32::MAX;
+        server_config.send_tls13_tickets = 2;
+        let mut client_config = ClientConfig::builder_with_provider(provider)
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(Verifier {
+                accept: accept_cert,
+            }))
+            .with_no_client_auth();
+        client_config.enable_early_data = true;
+        (Arc::new(server_config), Arc::new(client_config))
+    }
+
+    /// The configs of mutual TLS, the client presents the `client_cert` chain if any.
+    fn mtls_configs(
+        verifier: ClientVerifier,
+        client_cert: Option<&[&[u8]]>,
+    ) -> (Arc<ServerConfig>, Arc<ClientConfig>) {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let server_config = ServerConfig::builder_with_provider(provider.clone())
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_client_cert_verifier(Arc::new(verifier))
+            .with_cert_resolver(Arc::new(SingleCert(certified_key(&[b"server cert"]))));
+        let client_config = ClientConfig::builder_with_provider(provider)
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(Verifier { accept: true }));
+        let client_config = match client_cert {
+            Some(cert_chain) => client_config
+                .with_client_cert_resolver(Arc::new(ClientCert(certified_key(cert_chain)))),
+            None => client_config.with_no_client_auth(),
+        };
+        (Arc::new(server_config), Arc::new(client_config))
+    }
+
+    fn tls_sessions(
+        server_config: Arc<ServerConfig>,
+        client_config: Arc<ClientConfig>,
+    ) -> (TlsSession, TlsSession) {
+        let mut params = Vec::new();
+        params.put_parameters(&Parameters::default(), &OsEntropy);
+        let server_name = ServerName::try_from("localhost").unwrap();
+        let version = Version::V1;
+        let client_conn = rustls::quic::ClientConnection::new(
+            client_config,
+            version,
+            server_name,
+            params.clone(),
+        )
+        .unwrap();
+        let server_conn =
+            rustls::quic::ServerConnection::new(server_config, version, params).unwrap();
+        (
+            TlsSession::from(TlsConnection::Client(client_conn)),
+            TlsSession::from(TlsConnection::Server(server_conn)),
+        )
+    }
+
+    fn transfer(from: &mut TlsSession, to: &mut TlsSession) -> Result<(), Error> {
+        let mut buf = Vec::new();
+        while from.read(&mut buf).is_some() {}
+        if buf.is_empty() {
+            return Ok(());
+        }
+        to.write(&buf)
+    }
+
+    /// Complete a handshake in memory, and return the 1-RTT keys of the client, for the tests
+    /// which need to send 1-RTT packets without a peer.
+    pub(crate) fn client_one_rtt_keys() -> (Keys, rustls::quic::Secrets) {
+        one_rtt_keys().0
+    }
+
+    /// Complete a handshake in memory, and return the 1-RTT keys of the client and the server,
+    /// for the tests which need to act as the server and send 1-RTT packets to the client.
+    pub(crate) fn one_rtt_keys() -> ((Keys, rustls::quic::Secrets), (Keys, rustls::quic::Secrets)) {
+        let (server_config, client_config) = tls_configs(true);
+        let (mut client, mut server) = tls_sessions(server_config, client_config);
+        transfer(&mut client, &mut server).unwrap();
+
+        // 服务端在发出Finished时就得到了1rtt密钥
+        let read_one_rtt_keys = |session: &mut TlsSession, buf: &mut Vec<u8>| {
+            let mut one_rtt_keys = None;
+            while let Some(key_change) = session.read(buf) {
+                if let KeyChange::OneRtt { keys, next } = key_change {
+                    one_rtt_keys = Some((keys, next));
+                }
+            }
+            one_rtt_keys
+        };
+        let mut buf = Vec::new();
+        let server_keys = read_one_rtt_keys(&mut server, &mut buf);
+        client.write(&buf).unwrap();
+        let client_keys = read_one_rtt_keys(&mut client, &mut Vec::new());
+        match (client_keys, server_keys) {
+            (Some(client_keys), Some(server_keys)) => (client_keys, server_keys),
+            _ => unreachable!("the handshake has completed"),
+        }
+    }
+
+    #[test]
+    fn test_certificate_verification_failed() {
+        let (server_config, client_config) = tls_configs(false);
+        let (mut client, mut server) = tls_sessions(server_config, client_config);
+
+        transfer(&mut client, &mut server).unwrap();
+        let error = transfer(&mut server, &mut client).unwrap_err();
+        assert_eq!(
+            error.kind(),
+            ErrorKind::Crypto(AlertDescription::UnknownCA.into())
+        );
+    }
+
+    #[test]
+    fn test_alpn_protocol() {
+        let (server_config, client_config) = tls_configs(true);
+        let (mut client, mut server) = tls_sessions(server_config.clone(), client_config.clone());
+        for _ in 0..2 {
+            transfer(&mut client, &mut server).unwrap();
+            transfer(&mut server, &mut client).unwrap();
+        }
+        // 未配置ALPN，没有协商出任何协议
+        assert!(!client.is_handshaking() && !server.is_handshaking());
+        assert_eq!(client.alpn_protocol(), None);
+        assert_eq!(server.alpn_protocol(), None);
+
+        let mut server_config = Arc::unwrap_or_clone(server_config);
+        server_config.alpn_protocols = vec![b"hq-interop".to_vec(), b"h3".to_vec()];
+        let mut client_config = Arc::unwrap_or_clone(client_config);
+        client_config.alpn_protocols = vec![b"h3".to_vec()];
+        let (mut client, mut server) =
+            tls_sessions(Arc::new(server_config), Arc::new(client_config));
+        transfer(&mut client, &mut server).unwrap();
+        // 握手完成之前不返回协商结果
+        assert_eq!(server.alpn_protocol(), None);
+        for _ in 0..2 {
+            transfer(&mut server, &mut client).unwrap();
+            transfer(&mut client, &mut server).unwrap();
+        }
+        assert!(!client.is_handshaking() && !server.is_handshaking());
+        assert_eq!(client.alpn_protocol(), Some(&b"h3"[..]));
+        assert_eq!(server.alpn_protocol(), Some(&b"h3"[..]));
+    }
+
+    #[test]
+    fn test_peer_certificates() {
+        let verifier = ClientVerifier {
+            accept: true,
+            mandatory: true,
+        };
+        let client_cert: &[&[u8]] = &[b"client cert", b"client intermediate"];
+        let (server_config, client_config) = mtls_configs(verifier, Some(client_cert));
+        let (mut client, mut server) = tls_sessions(server_config, client_config);
+
+        transfer(&mut client, &mut server).unwrap();
+        // 握手完成之前不返回证书链，但服务端已经从ClientHello中得知SNI
+        assert_eq!(server.peer_certificates(), None);
+        assert_eq!(server.server_name(), Some("localhost"));
+        for _ in 0..2 {
+            transfer(&mut server, &mut client).unwrap();
+            transfer(&mut client, &mut server).unwrap();
+        }
+        assert!(!client.is_handshaking() && !server.is_handshaking());
+
+        let client_chain = [
+            CertificateDer::from(b"client cert".to_vec()),
+            CertificateDer::from(b"client intermediate".to_vec()),
+        ];
+        assert_eq!(server.peer_certificates(), Some(&client_chain[..]));
+        let server_chain = [CertificateDer::from(b"server cert".to_vec())];
+        assert_eq!(client.peer_certificates(), Some(&server_chain[..]));
+        assert_eq!(client.server_name(), None);
+    }
+
+    #[test]
+    fn test_client_auth() {
+        let handshake = |verifier, client_cert: Option<&[&[u8]]>| {
+            let (server_config, client_config) = mtls_configs(verifier, client_cert);
+            let (mut client, mut server) = tls_sessions(server_config, client_config);
+            transfer(&mut client, &mut server)?;
+            transfer(&mut server, &mut client)?;
+            // 客户端在这一轮发送证书，由服务端验证
+            transfer(&mut client, &mut server)?;
+            assert!(!client.is_handshaking() && !server.is_handshaking());
+            Ok::<_, Error>(server.peer_certificates().map(<[_]>::to_vec))
+        };
+        let client_cert: &[&[u8]] = &[b"client cert"];
+
+        // 客户端证书被拒绝，以TLS警报作为QUIC的CRYPTO_ERROR
+        let rejected = ClientVerifier {
+            accept: false,
+            mandatory: true,
+        };
+        let error = handshake(rejected, Some(client_cert)).unwrap_err();
+        assert_eq!(
+            error.kind(),
+            ErrorKind::Crypto(AlertDescription::UnknownCA.into())
+        );
+
+        // 要求客户端认证，但客户端没有证书
+        let required = ClientVerifier {
+            accept: true,
+            mandatory: true,
+        };
+        let error = handshake(required, None).unwrap_err();
+        assert_eq!(
+            error.kind(),
+            ErrorKind::Crypto(AlertDescription::CertificateRequired.into())
+        );
+
+        // 可选的客户端认证，有无证书都能完成握手
+        let optional = || ClientVerifier {
+            accept: true,
+            mandatory: false,
+        };
+        assert_eq!(handshake(optional(), None), Ok(None));
+        assert_eq!(
+            handshake(optional(), Some(client_cert)),
+            Ok(Some(vec![CertificateDer::from(b"client cert".to_vec())]))
+        );
+    }
+
+    #[test]
+    fn test_load_0rtt() {
+        let (server_config, client_config) = tls_configs(true);
+        let session_cache = MemorySessionCache::default();
+
+        // 首次连接，没有会话票据
+        let tls_session = ArcTlsSession::new_client(
+            ServerName::try_from("localhost").unwrap(),
+            client_config.clone(),
+            &Parameters::default(),
+            Version::V1,
+            &OsEntropy,
+        );
+        assert!(tls_session.load_0rtt("localhost", &session_cache).is_none());
+
+        let (mut client, mut server) = tls_sessions(server_config, client_config.clone());
+        for _ in 0..3 {
+            transfer(&mut client, &mut server).unwrap();
+            // 握手完成后，服务端发送NewSessionTicket
+            transfer(&mut server, &mut client).unwrap();
+        }
+        assert!(!client.is_handshaking() && !server.is_handshaking());
+
+        // 有会话票据，但没有记忆的传输参数，退回到1Rtt
+        let tls_session = ArcTlsSession::new_client(
+            ServerName::try_from("localhost").unwrap(),
+            client_config.clone(),
+            &Parameters::default(),
+            Version::V1,
+            &OsEntropy,
+        );
+        assert!(tls_session.load_0rtt("localhost", &session_cache).is_none());
+
+        let mut remembered = Parameters::default();
+        remembered.set_initial_max_streams_bidi(8u32.into());
+        session_cache.store("localhost", Arc::new(remembered));
+        let tls_session = ArcTlsSession::new_client(
+            ServerName::try_from("localhost").unwrap(),
+            client_config,
+            &Parameters::default(),
+            Version::V1,
+            &OsEntropy,
+        );
+        let (_keys, params) = tls_session
+            .load_0rtt("localhost", &session_cache)
+            .expect("0-RTT should be attempted");
+        assert_eq!(params.initial_max_streams_bidi(), 8u32.into());
+    }
+
+    #[test]
+    fn test_restart() {
+        let (server_config, client_config) = tls_configs(true);
+        let tls_session = ArcTlsSession::new_client(
+            ServerName::try_from("localhost").unwrap(),
+            client_config,
+            &Parameters::default(),
+            Version::V2,
+            &OsEntropy,
+        );
+        let mut guard = tls_session.0.lock().unwrap();
+        let client = guard.as_mut().unwrap();
+        let mut client_hello = Vec::new();
+        while client.read(&mut client_hello).is_some() {}
+        assert!(!client_hello.is_empty());
+        drop(guard);
+
+        // 版本协商后以v1重新开始握手，发送新的ClientHello
+        tls_session.restart(Version::V1);
+        let mut guard = tls_session.0.lock().unwrap();
+        let client = guard.as_mut().unwrap();
+        let mut params = Vec::new();
+        params.put_parameters(&Parameters::default(), &OsEntropy);
+        let server_conn =
+            rustls::quic::ServerConnection::new(server_config, Version::V1, params).unwrap();
+        let mut server = TlsSession::from(TlsConnection::Server(server_conn));
+
+        let mut buf = Vec::new();
+        while client.read(&mut buf).is_some() {}
+        assert!(!buf.is_empty());
+        server.write(&buf).unwrap();
+
+        let read_handshake_keys = |session: &mut TlsSession, buf: &mut Vec<u8>| {
+            buf.clear();
+            let mut handshake_keys = None;
+            while let Some(key_change) = session.read(buf) {
+                if let KeyChange::Handshake { keys } = key_change {
+                    handshake_keys.get_or_insert(keys);
+                }
+            }
+            handshake_keys.unwrap()
+        };
+        let server_keys = read_handshake_keys(&mut server, &mut buf);
+        client.write(&buf).unwrap();
+        let client_keys = read_handshake_keys(client, &mut buf);
+
+        // Handshake密钥以相同的版本派生，双方才能互相解密
+        let tag = |key: &dyn rustls::quic::PacketKey| {
+            key.encrypt_in_place(0, &[], &mut [])
+                .unwrap()
+                .as_ref()
+                .to_vec()
+        };
+        assert_eq!(
+            tag(client_keys.local.packet.as_ref()),
+            tag(server_keys.remote.packet.as_ref())
+        );
+    }
 }
@@ -9,10 +9,12 @@ use std::{
 
 use dashmap::DashMap;
 use deref_derive::Deref;
+use qbase::packet::Ecn;
 use qcongestion::MSS;
+pub use qudp::UscConfig;
 use tokio::task::JoinHandle;
 
-use crate::path::Pathway;
+use crate::{path::Pathway, Sendmsg};
 
 /// 全局的usc注册管理，用于查找已有的usc，key是绑定的本地地址，包括v4和v6的地址
 static USC_REGISTRY: LazyLock<DashMap<SocketAddr, (ArcUsc, JoinHandle<()>)>> =
@@ -37,6 +39,23 @@ impl UscRegistry {
     /// For server, the address will not be freed until the address is unbined by server, or the server
     /// is closed.
     pub fn get_or_create_usc<Task, F>(addr: SocketAddr, recv_task: F) -> io::Result<ArcUsc>
+    where
+        Task: Future<Output = ()> + Send + 'static,
+        F: FnOnce(ArcUsc) -> Task,
+    {
+        Self::get_or_create_usc_with_config(addr, &UscConfig::default(), recv_task)
+    }
+
+    /// Get the exist [`ArcUsc`] which bound the given [`SocketAddr`], or create one with the
+    /// socket options in the `config`, such as the sizes of the socket buffers.
+    ///
+    /// The `config` only applies to the newly created udp socket, the options of an existing
+    /// [`ArcUsc`] are not changed. Read [`UscRegistry::get_or_create_usc`] for more details.
+    pub fn get_or_create_usc_with_config<Task, F>(
+        addr: SocketAddr,
+        config: &UscConfig,
+        recv_task: F,
+    ) -> io::Result<ArcUsc>
     where
         Task: Future<Output = ()> + Send + 'static,
         F: FnOnce(ArcUsc) -> Task,
@@ -45,7 +64,7 @@ impl UscRegistry {
             return Ok(usc.clone());
         }
 
-        let usc = Arc::new(qudp::UdpSocketController::new(addr)?);
+        let usc = Arc::new(qudp::UdpSocketController::with_config(addr, config)?);
         let addr = usc.local_addr()?;
 
         let usc = ArcUsc { usc, addr };
@@ -76,20 +95,24 @@ impl ArcUsc {
         cx: &mut Context,
         iovecs: &[IoSlice],
         pathway: Pathway,
+        ecn: Option<Ecn>,
     ) -> Poll<io::Result<usize>> {
         // todo: append relay hdr
+        // 除最后一个外，各数据报大小都是路径当前的MTU，以第一个数据报的大小作为GSO的分段大小
+        let seg_size = iovecs.first().map_or(MSS, |iovec| iovec.len());
         let hdr = qudp::PacketHeader {
             src: pathway.local_addr(),
             dst: pathway.dst_addr(),
             ttl: 64,
-            ecn: None,
-            seg_size: MSS as u16,
+            ecn: ecn.map(u8::from),
+            seg_size: seg_size as u16,
             gso: true,
+            gro_seg_size: 0,
         };
         self.usc.poll_send(iovecs, &hdr, cx)
     }
 
-    /// Send all the datagrams via the given pathway.
+    /// Send all the datagrams via the given pathway, marked with the `ecn` codepoint if any.
     ///
     /// The returned future completes when all the datagrams are sent, or an error occurs(or occured)
     /// on udp socket.
@@ -97,12 +120,95 @@ impl ArcUsc {
         &'s self,
         iovecs: &'s [IoSlice<'s>],
         pathway: Pathway,
+        ecn: Option<Ecn>,
     ) -> SendAllViaPathWay<'s> {
         SendAllViaPathWay {
             usc: self,
             iovecs,
             pathway,
+            ecn,
+        }
+    }
+}
+
+impl Sendmsg for ArcUsc {
+    fn sendmsg(&mut self, msg: &[u8], dest: SocketAddr) -> io::Result<usize> {
+        let hdr = qudp::PacketHeader {
+            src: self.addr,
+            dst: dest,
+            seg_size: msg.len() as u16,
+            gso: false,
+            ..Default::default()
+        };
+        self.usc.try_send(&[IoSlice::new(msg)], &hdr)?;
+        Ok(msg.len())
+    }
+
+    fn sendmsg_with_ttl(&mut self, msg: &[u8], dest: SocketAddr, ttl: u8) -> io::Result<usize> {
+        let hdr = qudp::PacketHeader {
+            src: self.addr,
+            dst: dest,
+            ttl,
+            seg_size: msg.len() as u16,
+            gso: false,
+            ..Default::default()
+        };
+        self.usc.try_send(&[IoSlice::new(msg)], &hdr)?;
+        Ok(msg.len())
+    }
+
+    fn sendmsg_gso(
+        &mut self,
+        buf: &[u8],
+        segment_size: u16,
+        dest: SocketAddr,
+    ) -> io::Result<usize> {
+        if segment_size == 0 {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+        let segments = buf
+            .chunks(segment_size as usize)
+            .map(IoSlice::new)
+            .collect::<Vec<_>>();
+        // the gso support is detected once when the usc created, and qudp will fall back to
+        // send the segments one by one if gso is not supported
+        let hdr = qudp::PacketHeader {
+            src: self.addr,
+            dst: dest,
+            seg_size: segment_size,
+            gso: self.usc.gso_segments() > 1,
+            ..Default::default()
+        };
+        let n = self.usc.try_send(&segments, &hdr)?;
+        Ok(segments[..n].iter().map(|segment| segment.len()).sum())
+    }
+
+    fn sendmmsg(&mut self, msgs: &[(&[u8], SocketAddr)]) -> io::Result<usize> {
+        let mut sent = 0;
+        // the consecutive datagrams to the same destination are sent in one syscall by qudp
+        for batch in msgs.chunk_by(|(_, a), (_, b)| a == b) {
+            let iovecs = batch
+                .iter()
+                .map(|(msg, _)| IoSlice::new(msg))
+                .collect::<Vec<_>>();
+            let hdr = qudp::PacketHeader {
+                src: self.addr,
+                dst: batch[0].1,
+                gso: false,
+                ..Default::default()
+            };
+            match self.usc.try_send(&iovecs, &hdr) {
+                Ok(n) => {
+                    sent += n;
+                    if n < batch.len() {
+                        break;
+                    }
+                }
+                Err(_) if sent > 0 => break,
+                Err(e) => return Err(e),
+            }
         }
+        Ok(sent)
     }
 }
 
@@ -127,6 +233,7 @@ pub struct SendAllViaPathWay<'s> {
     usc: &'s ArcUsc,
     iovecs: &'s [IoSlice<'s>],
     pathway: Pathway,
+    ecn: Option<Ecn>,
 }
 
 impl Unpin for SendAllViaPathWay<'_> {}
@@ -138,7 +245,7 @@ impl Future for SendAllViaPathWay<'_> {
         let this = self.get_mut();
         let iovecs = &mut this.iovecs;
         while !iovecs.is_empty() {
-            let send_once = this.usc.poll_send_via(cx, iovecs, this.pathway);
+            let send_once = this.usc.poll_send_via(cx, iovecs, this.pathway, this.ecn);
             let n = ready!(send_once)?;
             *iovecs = &iovecs[n..];
         }
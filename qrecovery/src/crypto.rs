@@ -53,6 +53,15 @@ mod send {
         fn may_loss_data(&mut self, crypto_frame: &CryptoFrame) {
             self.sndbuf.may_loss_data(&crypto_frame.range())
         }
+
+        fn restart(&mut self) {
+            // 丢弃所有已写入的数据，新的数据从偏移0开始
+            let capacity = self.sndbuf.len() + self.sndbuf.remaining_mut();
+            self.sndbuf = SendBuf::with_capacity(capacity);
+            if let Some(waker) = self.writable_waker.take() {
+                waker.wake();
+            }
+        }
     }
 
     impl Sender {
@@ -145,6 +154,15 @@ mod send {
         pub fn may_loss_data(&self, crypto_frame: &CryptoFrame) {
             self.0.lock().unwrap().may_loss_data(crypto_frame)
         }
+
+        /// Discard all the crypto data written, the data written later will be sent from offset 0.
+        ///
+        /// Used by the client to send a new ClientHello after the version negotiation. The frames
+        /// carrying the old data should be discarded by the caller, and never be acknowledged or
+        /// lost here.
+        pub fn restart(&self) {
+            self.0.lock().unwrap().restart()
+        }
     }
 
     pub(super) fn create(capacity: usize) -> ArcSender {
@@ -166,8 +184,8 @@ mod recv {
 
     use bytes::{BufMut, Bytes};
     use qbase::{
-        error::Error,
-        frame::{CryptoFrame, ReceiveFrame},
+        error::{Error, ErrorKind},
+        frame::{BeFrame, CryptoFrame, ReceiveFrame},
         varint::VARINT_MAX,
     };
     use tokio::io::{AsyncRead, ReadBuf};
@@ -177,18 +195,37 @@ mod recv {
     #[derive(Debug)]
     pub(super) struct Recver {
         rcvbuf: RecvBuf,
+        // 超出连续接收的数据这么多字节之外的乱序数据，不予缓存
+        max_buffered: u64,
         read_waker: Option<Waker>,
     }
 
     impl Recver {
-        fn recv(&mut self, offset: u64, data: Bytes) {
-            assert!(offset + data.len() as u64 <= VARINT_MAX);
-            self.rcvbuf.recv(offset, data);
+        fn recv(&mut self, frame: &CryptoFrame, data: Bytes) -> Result<(), Error> {
+            let offset = frame.offset.into_inner();
+            let data_end = offset + data.len() as u64;
+            assert!(data_end <= VARINT_MAX);
+            let limit = self.rcvbuf.available() + self.max_buffered;
+            if data_end > limit {
+                return Err(Error::new(
+                    ErrorKind::CryptoBufferExceeded,
+                    frame.frame_type(),
+                    format!("crypto data up to {data_end} exceeds the buffer limit {limit}"),
+                ));
+            }
+            self.rcvbuf.recv(offset, data).map_err(|conflict| {
+                Error::new(
+                    ErrorKind::ProtocolViolation,
+                    frame.frame_type(),
+                    format!("crypto {conflict}"),
+                )
+            })?;
             if self.rcvbuf.is_readable() {
                 if let Some(waker) = self.read_waker.take() {
                     waker.wake()
                 }
             }
+            Ok(())
         }
 
         fn poll_read<T: BufMut>(
@@ -229,18 +266,21 @@ mod recv {
     impl ReceiveFrame<(CryptoFrame, Bytes)> for CryptoStreamIncoming {
         type Output = ();
 
+        /// Receive the crypto data from the peer.
+        ///
+        /// The crypto data beyond the buffer limit results in a connection error of type
+        /// CRYPTO_BUFFER_EXCEEDED, see [`CryptoStream::new`].
+        ///
+        /// [`CryptoStream::new`]: super::CryptoStream::new
         fn recv_frame(&self, (frame, data): &(CryptoFrame, Bytes)) -> Result<Self::Output, Error> {
-            self.0
-                .lock()
-                .unwrap()
-                .recv(frame.offset.into(), data.clone());
-            Ok(())
+            self.0.lock().unwrap().recv(frame, data.clone())
         }
     }
 
-    pub(super) fn create() -> ArcRecver {
+    pub(super) fn create(max_buffered: usize) -> ArcRecver {
         Arc::new(Mutex::new(Recver {
             rcvbuf: RecvBuf::default(),
+            max_buffered: max_buffered as u64,
             read_waker: None,
         }))
     }
@@ -258,10 +298,16 @@ pub struct CryptoStream {
 
 impl CryptoStream {
     /// Create a new instance of [`CryptoStream`] with the given buffer size.
-    pub fn new(sndbuf_size: usize, _rcvbuf_size: usize) -> Self {
+    ///
+    /// The crypto data received out of order is buffered until the gap is filled, at most
+    /// `rcvbuf_size` bytes beyond the contiguous received data. If the peer sends crypto data
+    /// beyond that, the connection should be closed with a CRYPTO_BUFFER_EXCEEDED error, see
+    /// [section 7.5](https://www.rfc-editor.org/rfc/rfc9000.html#section-7.5) of RFC 9000, which
+    /// requires at least 4096 bytes to be buffered.
+    pub fn new(sndbuf_size: usize, rcvbuf_size: usize) -> Self {
         Self {
             sender: send::create(sndbuf_size),
-            recver: recv::create(),
+            recver: recv::create(rcvbuf_size),
         }
     }
 
@@ -288,7 +334,9 @@ impl CryptoStream {
 
 #[cfg(test)]
 mod tests {
+    use bytes::Bytes;
     use qbase::{
+        error::ErrorKind,
         frame::{CryptoFrame, ReceiveFrame},
         varint::VarInt,
     };
@@ -298,7 +346,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_read() {
-        let crypto_stream: CryptoStream = CryptoStream::new(1000_0000, 0);
+        let crypto_stream: CryptoStream = CryptoStream::new(1000_0000, 4096);
         crypto_stream
             .writer()
             .write_all(b"hello world")
@@ -319,4 +367,73 @@ mod tests {
         crypto_stream.reader().read_exact(&mut buf).await.unwrap();
         assert_eq!(&buf[..], b"hello world");
     }
+
+    fn crypto_frame(offset: u32, data: &'static [u8]) -> (CryptoFrame, Bytes) {
+        let frame = CryptoFrame {
+            offset: VarInt::from_u32(offset),
+            length: VarInt::from_u32(data.len() as u32),
+        };
+        (frame, Bytes::from_static(data))
+    }
+
+    #[test]
+    fn test_crypto_buffer_exceeded() {
+        let crypto_stream = CryptoStream::new(4096, 4096);
+        let incoming = crypto_stream.incoming();
+        // 乱序的数据在缓存限制之内，可以被接收
+        incoming.recv_frame(&crypto_frame(4090, b"hello!")).unwrap();
+        let error = incoming
+            .recv_frame(&crypto_frame(4096, b"world"))
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::CryptoBufferExceeded);
+        // 连续接收的数据推进了缓存的上限
+        incoming.recv_frame(&crypto_frame(0, b"hello")).unwrap();
+        incoming.recv_frame(&crypto_frame(4096, b"world")).unwrap();
+        let error = incoming
+            .recv_frame(&crypto_frame(1 << 20, b"far future"))
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::CryptoBufferExceeded);
+    }
+
+    #[test]
+    fn test_retransmit_lost_data() {
+        let crypto_stream = CryptoStream::new(4096, 4096);
+        let outgoing = crypto_stream.outgoing();
+        let mut writer = crypto_stream.writer();
+        let write = writer.write_all(b"client hello");
+        assert!(futures::FutureExt::now_or_never(write).unwrap().is_ok());
+
+        let mut buffer = [0u8; 64];
+        let (frame, _) = outgoing.try_read_data(&mut buffer).unwrap();
+        assert_eq!(frame.offset.into_inner(), 0);
+        assert_eq!(frame.length.into_inner(), 12);
+        assert!(outgoing.try_read_data(&mut buffer).is_none());
+
+        // 丢失的数据会被重新发送
+        outgoing.may_loss_data(&frame);
+        let (retransmitted, _) = outgoing.try_read_data(&mut buffer).unwrap();
+        assert_eq!(retransmitted, frame);
+        outgoing.on_data_acked(&retransmitted);
+        assert!(outgoing.try_read_data(&mut buffer).is_none());
+    }
+
+    #[test]
+    fn test_restart() {
+        let crypto_stream = CryptoStream::new(4096, 4096);
+        let outgoing = crypto_stream.outgoing();
+        let mut writer = crypto_stream.writer();
+        let write = writer.write_all(b"client hello");
+        assert!(futures::FutureExt::now_or_never(write).unwrap().is_ok());
+        let mut buffer = [0u8; 64];
+        assert!(outgoing.try_read_data(&mut buffer).is_some());
+
+        // 重新开始后，旧的数据不再发送，新的数据从偏移0开始
+        outgoing.restart();
+        assert!(outgoing.try_read_data(&mut buffer).is_none());
+        let write = writer.write_all(b"new hello");
+        assert!(futures::FutureExt::now_or_never(write).unwrap().is_ok());
+        let (frame, _) = outgoing.try_read_data(&mut buffer).unwrap();
+        assert_eq!(frame.offset.into_inner(), 0);
+        assert_eq!(frame.length.into_inner(), 9);
+    }
 }
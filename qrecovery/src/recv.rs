@@ -5,6 +5,6 @@ mod reader;
 mod recver;
 
 pub use incoming::Incoming;
-pub use rcvbuf::RecvBuf;
+pub use rcvbuf::{DataConflict, RecvBuf};
 pub use reader::Reader;
 pub use recver::ArcRecver;
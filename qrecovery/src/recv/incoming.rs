@@ -24,8 +24,15 @@ where
     /// by different stream frames may not continuous. The data will be assembled by [`RecvBuf`] into
     /// continuous data for the application layer to read through [`Reader`].
     ///
+    /// Returns whether all data of the stream has been received, and the increase of the largest
+    /// offset received on the stream, which counts toward the connection-level flow control.
+    ///
+    /// The final size of the stream, once known by the stream frame with the FIN flag, must not
+    /// change, and any data beyond it will cause a [`FINAL_SIZE_ERROR`].
+    ///
     /// [`RecvBuf`]: crate::recv::RecvBuf
     /// [`Reader`]: crate::recv::Reader
+    /// [`FINAL_SIZE_ERROR`]: https://www.rfc-editor.org/rfc/rfc9000.html#name-stream-final-size
     pub fn recv_data(
         &self,
         stream_frame: &StreamFrame,
@@ -34,14 +41,13 @@ where
         let mut recver = self.0.recver();
         let inner = recver.deref_mut();
         let mut is_into_rcvd = false;
-        let mut fresh_data = 0;
+        let mut new_data_size = 0;
         if let Ok(receiving_state) = inner {
             match receiving_state {
                 Recver::Recv(r) => {
                     if stream_frame.is_fin() {
-                        let final_size = stream_frame.offset() + stream_frame.len() as u64;
-                        let mut size_known = r.determin_size(final_size);
-                        fresh_data = size_known.recv(stream_frame, body)?;
+                        let mut size_known = r.determin_size(stream_frame)?;
+                        new_data_size = size_known.recv(stream_frame, body)?;
                         if size_known.is_all_rcvd() {
                             is_into_rcvd = true;
                             *receiving_state = Recver::DataRcvd(size_known.into());
@@ -49,11 +55,11 @@ where
                             *receiving_state = Recver::SizeKnown(size_known);
                         }
                     } else {
-                        fresh_data = r.recv(stream_frame, body)?;
+                        new_data_size = r.recv(stream_frame, body)?;
                     }
                 }
                 Recver::SizeKnown(r) => {
-                    fresh_data = r.recv(stream_frame, body)?;
+                    new_data_size = r.recv(stream_frame, body)?;
                     if r.is_all_rcvd() {
                         is_into_rcvd = true;
                         *receiving_state = Recver::DataRcvd(r.into());
@@ -64,34 +70,60 @@ where
                 }
             }
         }
-        Ok((is_into_rcvd, fresh_data))
+        Ok((is_into_rcvd, new_data_size))
     }
 
     /// Receive a stream reset frame from peer.
     ///
     /// If all data sent by the peer has not been received, receiving a stream reset frame will cause
     /// any read calls to return an error, received data will be discarded.
-    pub fn recv_reset(&self, reset_frame: &ResetStreamFrame) -> Result<(), QuicError> {
+    ///
+    /// The final size in the reset frame must be consistent with the final size known before, and
+    /// must not be smaller than the data received.
+    ///
+    /// Returns the increase from the largest offset received to the final size, which also counts
+    /// toward the connection-level flow control, and the size of the data up to the final size
+    /// that will never be read. The latter should be returned by [`Incoming::on_reset_discarded`]
+    /// only after the former is accepted by the connection-level flow control.
+    pub fn recv_reset(&self, reset_frame: &ResetStreamFrame) -> Result<(u64, u64), QuicError> {
         // TODO: ResetStream中还有错误信息，比如http3的错误码，看是否能用到
         let mut recver = self.0.recver();
         let inner = recver.deref_mut();
+        let mut new_data_size = 0;
+        let mut unread = 0;
         if let Ok(receiving_state) = inner {
-            match receiving_state {
-                Recver::Recv(r) => {
-                    let _final_size = r.recv_reset(reset_frame)?;
-                    *receiving_state = Recver::ResetRcvd(reset_frame.into());
-                }
-                Recver::SizeKnown(r) => {
-                    let _final_size = r.recv_reset(reset_frame)?;
-                    *receiving_state = Recver::ResetRcvd(reset_frame.into());
-                }
+            (new_data_size, unread) = match receiving_state {
+                Recver::Recv(r) => r.recv_reset(reset_frame)?,
+                Recver::SizeKnown(r) => r.recv_reset(reset_frame)?,
                 _ => {
                     log::error!("there is sth wrong, ignored recv_reset");
                     unreachable!();
                 }
-            }
+            };
+            *receiving_state = Recver::ResetRcvd(reset_frame.into());
+        }
+        Ok((new_data_size, unread))
+    }
+
+    /// Release the connection-level receive window occupied by the `unread` data of the reset
+    /// stream, returned by [`Incoming::recv_reset`].
+    ///
+    /// The data up to the final size will never be read, it is regarded as consumed.
+    pub fn on_reset_discarded(&self, unread: u64) {
+        self.0.on_consumed(unread as usize);
+    }
+
+    /// Receive a stream data blocked frame from peer.
+    ///
+    /// The peer is blocked by the stream-level flow control, a [`MAX_STREAM_DATA frame`] will be
+    /// sent to extend the credit if the application has read some data since the last extension.
+    ///
+    /// [`MAX_STREAM_DATA frame`]: https://www.rfc-editor.org/rfc/rfc9000.html#name-max_stream_data-frames
+    pub fn recv_data_blocked(&self) {
+        let mut recver = self.0.recver();
+        if let Ok(Recver::Recv(r)) = recver.deref_mut() {
+            r.on_data_blocked();
         }
-        Ok(())
     }
 }
 
@@ -1,47 +1,17 @@
 //！ An implementation of the receiving buffer for stream data.
 
-use std::{collections::VecDeque, fmt};
+use std::{collections::BTreeMap, fmt};
 
 use bytes::{BufMut, Bytes};
+use thiserror::Error;
 
-/// 一段连续的数据片段，每个片段都是Bytes
-#[derive(Debug, Default)]
-struct Segment {
-    offset: u64,
-    length: u64,
-    fragments: VecDeque<Bytes>,
-}
-
-impl fmt::Display for Segment {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "[{}..{}]", self.offset, self.offset + self.length)
-    }
-}
-
-impl Segment {
-    fn new_with_data(offset: u64, data: Bytes) -> Self {
-        Segment {
-            offset,
-            length: data.len() as u64,
-            fragments: vec![data].into(),
-        }
-    }
-
-    fn prepend(&mut self, data: Bytes) {
-        self.offset -= data.len() as u64;
-        self.length += data.len() as u64;
-        self.fragments.push_front(data);
-    }
-
-    fn append(&mut self, data: Bytes) {
-        self.length += data.len() as u64;
-        self.fragments.push_back(data);
-    }
-
-    fn take(&mut self) -> Self {
-        std::mem::take(self)
-    }
-}
+/// The data received conflicts with the data previously received at the same offset.
+///
+/// The retransmitted data must be identical to the data sent before, the peer sending different
+/// data at the same offset is a protocol violation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("data at offset {0} differs from that received before")]
+pub struct DataConflict(pub u64);
 
 /// Received data of a stream is stored in [`RecvBuf`].
 ///
@@ -49,17 +19,24 @@ impl Segment {
 /// that may not be continuous. It sequentially stores the received data
 /// fragments and then reassembles them into a continuous data stream for
 /// future reading by the application layer.
+///
+/// The byte ranges received are tracked in an ordered map, the adjacent or overlapping ranges
+/// are merged, so the gaps between them are what is still missing. The data retransmitted is
+/// received idempotently, only the part falling into the gaps is stored.
 #[derive(Default, Debug)]
 pub struct RecvBuf {
     nread: u64,
-    segments: VecDeque<Segment>,
+    // 尚未读取的数据片段，以起始偏移为键，片段之间互不重叠
+    fragments: BTreeMap<u64, Bytes>,
+    // 尚未读取的已接收区间，相邻或重叠的区间合并，以起始偏移为键，值为结束偏移
+    ranges: BTreeMap<u64, u64>,
 }
 
 impl fmt::Display for RecvBuf {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "RecvBuf(offset={}, segments=[", self.nread)?;
-        for segment in &self.segments {
-            write!(f, "{}", segment)?;
+        for (start, end) in &self.ranges {
+            write!(f, "[{start}..{end}]")?;
         }
         write!(f, "])")
     }
@@ -68,7 +45,7 @@ impl fmt::Display for RecvBuf {
 impl RecvBuf {
     /// Returns whether the receiving buffer is empty.
     pub fn is_empty(&self) -> bool {
-        self.segments.is_empty()
+        self.fragments.is_empty()
     }
 
     /// Returns how many continuous data have been read.
@@ -81,7 +58,7 @@ impl RecvBuf {
     /// let mut recvbuf = RecvBuf::default();
     /// assert_eq!(recvbuf.nread(), 0);
     ///
-    /// recvbuf.recv(0, Bytes::from("hello"));
+    /// recvbuf.recv(0, Bytes::from("hello")).unwrap();
     /// assert_eq!(recvbuf.nread(), 0);
     /// // recvbuf:  hello
     /// // offset=0  ^
@@ -97,6 +74,9 @@ impl RecvBuf {
 
     /// Receive a fragment of data, return the new data's size.
     ///
+    /// The part of the data which has been received before is ignored, but it must be identical to
+    /// the data received before, otherwise a [`DataConflict`] is returned, and nothing is received.
+    ///
     /// # Example
     ///
     /// The following example demonstrates how [`RecvBuf`] works.
@@ -104,197 +84,113 @@ impl RecvBuf {
     /// The data "hello, world!" is splitted into four fragments.
     /// ``` rust
     /// # use bytes::{Bytes, BytesMut};
-    /// # use qrecovery::recv::RecvBuf;
+    /// # use qrecovery::recv::{DataConflict, RecvBuf};
     /// let mut recvbuf = RecvBuf::default();
     /// // data:    "hello, world!"
-    /// assert_eq!(recvbuf.recv(0, Bytes::from("hell")), 4);
+    /// assert_eq!(recvbuf.recv(0, Bytes::from("hell")), Ok(4));
     /// // recvbuf: "hell"
     /// // new:     "hell"
-    /// assert_eq!(recvbuf.recv(7, Bytes::from("world")), 5);
+    /// assert_eq!(recvbuf.recv(7, Bytes::from("world")), Ok(5));
     /// // recvbuf: "hell" "world"
     /// // new:            "world"
-    /// assert_eq!(recvbuf.recv(3, Bytes::from("lo, ")), 3);
+    /// assert_eq!(recvbuf.recv(3, Bytes::from("lo, ")), Ok(3));
     /// // recvbuf: "hello, world"
     /// // new:         "o, "
-    /// assert_eq!(recvbuf.recv(7, Bytes::from("world!")), 1);
+    /// assert_eq!(recvbuf.recv(7, Bytes::from("world!")), Ok(1));
     /// // recvbuf: "hello, world!"
     /// // new:                 "!"
+    /// assert_eq!(recvbuf.recv(7, Bytes::from("w0rld")), Err(DataConflict(8)));
     ///
     /// let mut received = BytesMut::new();
     /// recvbuf.try_read(&mut received);
     /// assert_eq!(received.as_ref(), b"hello, world!");
     /// ```
     ///
-    pub fn recv(&mut self, mut offset: u64, mut data: Bytes) -> usize {
+    pub fn recv(&mut self, mut offset: u64, mut data: Bytes) -> Result<usize, DataConflict> {
         if data.is_empty() {
-            return 0;
+            return Ok(0);
         }
-
-        // 1. All data has been read.
-        // 2. Without a limit, `self.nread - offset` will exceed the `data.len()`, causing a panic.
-        if offset + data.len() as u64 <= self.nread {
-            return 0;
+        // 已读取的部分无从比较，直接忽略
+        if offset + (data.len() as u64) <= self.nread {
+            return Ok(0);
         }
-
         if offset < self.nread {
             data = data.slice((self.nread - offset) as usize..);
             offset = self.nread;
         }
-
-        match self.segments.binary_search_by(|s| s.offset.cmp(&offset)) {
-            // 恰好落在一个片段上
-            Ok(seg_idx) => {
-                let new_data_size = self.overlap_seg(seg_idx, data);
-                self.try_merge(seg_idx);
-                new_data_size
-            }
-            // 并没有落在一个片段上，offset比任何一个片段都小
-            Err(0) => {
-                // 优先尝试添加到后一个片段之前
-                if let Some(new_data_size) = self.try_prepend(0, offset, &data) {
-                    return new_data_size;
-                }
-                self.insert(0, Segment::new_with_data(offset, data))
-            }
-            // segments[recommend].offset < offset
-            Err(recommend) => {
-                // 优先尝试添加到前一个片段之后
-                if let Some(new_data_size) = self.try_append(recommend - 1, offset, &data) {
-                    return new_data_size;
-                }
-                // 再尝试添加到后一个片段之前
-                if let Some(new_data_size) = self.try_prepend(recommend, offset, &data) {
-                    return new_data_size;
-                }
-                self.insert(recommend, Segment::new_with_data(offset, data))
+        let end = offset + data.len() as u64;
+
+        // 与已接收片段重叠的部分必须一致，先全部检查，有冲突则什么都不接收
+        let mut gaps = Vec::new();
+        let mut cursor = offset;
+        for (&frag_start, frag) in self.overlapped_fragments(offset, end) {
+            let frag_end = frag_start + frag.len() as u64;
+            let (lo, hi) = (frag_start.max(offset), frag_end.min(end));
+            let received = &frag[(lo - frag_start) as usize..(hi - frag_start) as usize];
+            let incoming = &data[(lo - offset) as usize..(hi - offset) as usize];
+            if let Some(pos) = received.iter().zip(incoming).position(|(a, b)| a != b) {
+                return Err(DataConflict(lo + pos as u64));
             }
-        }
-    }
-
-    // 返回片段之间是否有连接
-    // 如果有连接，返回 Some(new_data_size)，否则返回 None
-    fn try_prepend(&mut self, seg_idx: usize, offset: u64, data: &Bytes) -> Option<usize> {
-        let data_end = offset + data.len() as u64;
-        let mut new_data_size = 0;
-        match self.segments.get(seg_idx) {
-            Some(next_seg) if data_end >= next_seg.offset => {
-                let end = (next_seg.offset - offset) as usize;
-                let next_end = next_seg.offset + next_seg.length;
-                self.segments[seg_idx].prepend(data.slice(..end));
-                new_data_size += end;
-                // 有可能被追加在前的片段覆盖了整个段
-                if data_end > next_end {
-                    let remain = data.slice((next_end - offset) as usize..);
-                    if let Some(new_data) = self.try_append(seg_idx, next_end, &remain) {
-                        new_data_size += new_data;
-                    }
-                }
-                Some(new_data_size)
+            if cursor < lo {
+                gaps.push(cursor..lo);
             }
-            _ => None,
-        }
-    }
-
-    // 需要 offset >= segs[seg_idx].offset
-    // 返回片段之间是否有连接
-    // 如果有连接，返回 Some(new_data_size)，否则返回 None
-    fn try_append(&mut self, seg_idx: usize, offset: u64, data: &Bytes) -> Option<usize> {
-        let pre_seg = &self.segments[seg_idx];
-        let data_end = offset + data.len() as u64;
-        let pre_seg_end = pre_seg.offset + pre_seg.length;
-
-        // 已经完全被前一个片段包含，不需要处理
-        if data_end <= pre_seg_end {
-            return Some(0);
-        }
-
-        // 完全和前一个片段没有连接
-        if offset > pre_seg_end {
-            return None;
+            cursor = cursor.max(hi);
         }
-
-        let start = if offset < pre_seg_end {
-            (pre_seg_end - offset) as usize
-        } else {
-            0
-        };
-        // 可能这段数据已经覆盖了下一段数据
-        let next_offset = self.segments.get(seg_idx + 1).map(|seg| seg.offset);
-        let end = match next_offset {
-            Some(next_offset) => (next_offset - offset) as usize,
-            None => data.len(),
+        if cursor < end {
+            gaps.push(cursor..end);
         }
-        .min(data.len());
 
         let mut new_data_size = 0;
-        new_data_size += end - start;
-        self.segments[seg_idx].append(data.slice(start..end));
-
-        // 有可能片段和下一个段有连接
-        if data.len() > end {
-            new_data_size += self.overlap_seg(seg_idx + 1, data.slice(end..));
+        for gap in gaps {
+            let slice = data.slice((gap.start - offset) as usize..(gap.end - offset) as usize);
+            new_data_size += slice.len();
+            self.fragments.insert(gap.start, slice);
         }
-        self.try_merge(seg_idx);
-        Some(new_data_size)
+        self.insert_range(offset, end);
+        Ok(new_data_size)
     }
 
-    fn insert(&mut self, at: usize, seg: Segment) -> usize {
-        let new_data_size = seg.length as usize;
-        self.segments.insert(at, seg);
-        new_data_size
+    // 与[start, end)重叠的片段，按偏移排序
+    fn overlapped_fragments(&self, start: u64, end: u64) -> impl Iterator<Item = (&u64, &Bytes)> {
+        // 起始于start之前的片段，最多只有一个可能与之重叠
+        let before = self
+            .fragments
+            .range(..start)
+            .next_back()
+            .filter(|(&frag_start, frag)| frag_start + frag.len() as u64 > start);
+        before.into_iter().chain(self.fragments.range(start..end))
     }
 
-    // 不同于 append 和 prepend，这是对于和段从头开始重合的情况
-    // 是append的特化情况
-    // 返回新增的数据字节数
-    fn overlap_seg(&mut self, mut seg_idx: usize, mut data: Bytes) -> usize {
-        let mut new_data_size = 0;
-        loop {
-            let cur_seg = &self.segments[seg_idx];
-            let offset = cur_seg.offset;
-
-            if data.len() as u64 <= cur_seg.length {
-                break new_data_size;
-            }
-
-            let start = cur_seg.length as usize;
-
-            let end = match self.segments.get(seg_idx + 1).map(|next| next.offset) {
-                Some(next_offset) => (next_offset - offset) as usize,
-                None => data.len(),
-            }
-            .min(data.len());
-
-            self.segments[seg_idx].append(data.slice(start..end));
-
-            new_data_size += end - start;
-            if data.len() > end {
-                data = data.slice(end..);
-                seg_idx += 1;
-            } else {
-                break new_data_size;
+    // 合并新接收的区间，相邻或重叠的区间合为一个
+    fn insert_range(&mut self, mut start: u64, mut end: u64) {
+        if let Some((&prev_start, &prev_end)) = self.ranges.range(..=start).next_back() {
+            if prev_end >= start {
+                start = prev_start;
+                end = end.max(prev_end);
             }
-
-            // 不在这里merge，而是在外部，是因为append可能多创造一个连接段
         }
+        let merged = self
+            .ranges
+            .range(start..=end)
+            .map(|(&s, &e)| (s, e))
+            .collect::<Vec<_>>();
+        for (s, e) in merged {
+            self.ranges.remove(&s);
+            end = end.max(e);
+        }
+        self.ranges.insert(start, end);
     }
 
-    fn try_merge(&mut self, mut idx: usize) {
-        let start_idx = idx;
-        let mut cur_seg = self.segments[idx].take();
-        while idx + 1 < self.segments.len() {
-            let next_seg = &mut self.segments[idx + 1];
-            if next_seg.offset != cur_seg.offset + cur_seg.length {
-                break;
+    // 读取了n字节，推进读取位置，已读取的区间不再保留
+    fn advance(&mut self, n: usize) {
+        self.nread += n as u64;
+        if let Some(entry) = self.ranges.first_entry() {
+            let end = *entry.get();
+            entry.remove();
+            if end > self.nread {
+                self.ranges.insert(self.nread, end);
             }
-
-            cur_seg.fragments.append(&mut next_seg.fragments);
-            cur_seg.length += next_seg.length;
-            idx += 1;
         }
-
-        self.segments[idx] = cur_seg;
-        self.segments.drain(start_idx..idx);
     }
 
     /// Try to read continuous data from [`RecvBuf`] into the buffer passed in.
@@ -309,9 +205,9 @@ impl RecvBuf {
     /// # use bytes::{BytesMut, Bytes};
     /// # use qrecovery::recv::RecvBuf;
     /// let mut recvbuf = RecvBuf::default();
-    /// recvbuf.recv(0, Bytes::from("012"));
-    /// recvbuf.recv(3, Bytes::from("345"));
-    /// recvbuf.recv(7, Bytes::from("789"));
+    /// recvbuf.recv(0, Bytes::from("012")).unwrap();
+    /// recvbuf.recv(3, Bytes::from("345")).unwrap();
+    /// recvbuf.recv(7, Bytes::from("789")).unwrap();
     /// // recvbuf:  012345 789
     /// // readable: ^^^^^^
     ///
@@ -320,7 +216,7 @@ impl RecvBuf {
     /// assert_eq!(dst1.as_ref(), b"012345");
     ///
     /// let mut dst2 = BytesMut::new();
-    /// recvbuf.recv(6, Bytes::from("6"));
+    /// recvbuf.recv(6, Bytes::from("6")).unwrap();
     /// // recvbuf:  0123456789
     /// // readable:       ^^^^
     ///
@@ -329,26 +225,85 @@ impl RecvBuf {
     ///
     /// ```
     pub fn try_read(&mut self, buf: &mut impl BufMut) -> Option<usize> {
-        let mut seg = self.segments.pop_front()?;
-        if seg.offset != self.nread {
-            self.segments.push_front(seg);
+        if !self.is_readable() {
             return None;
         }
         let origin = buf.remaining_mut();
-        while let Some(frag) = seg.fragments.pop_front() {
-            let n = buf.remaining_mut().min(frag.len());
-            buf.put_slice(&frag[..n]);
-            seg.offset += n as u64;
-            self.nread = seg.offset;
-            if n < frag.len() {
-                seg.fragments.push_front(frag.slice(n..));
-                self.segments.push_front(seg);
+        while buf.has_remaining_mut() {
+            let Some(data) = self.try_read_bytes(buf.remaining_mut()) else {
                 break;
-            }
+            };
+            buf.put_slice(&data);
         }
         Some(origin - buf.remaining_mut())
     }
 
+    /// Try to read continuous data from [`RecvBuf`] without copying, up to `max` bytes.
+    ///
+    /// The data returned is a view into the memory it was received in, such as the datagram the
+    /// stream frame was carried in. As the data received may be fragmented, only the first
+    /// fragment is returned, call it repeatedly to read more.
+    ///
+    /// Returns [`None`] if the following data is not continuous or there is no data.
+    ///
+    /// # Example
+    ///
+    /// ``` rust
+    /// # use bytes::Bytes;
+    /// # use qrecovery::recv::RecvBuf;
+    /// let mut recvbuf = RecvBuf::default();
+    /// recvbuf.recv(0, Bytes::from("hello")).unwrap();
+    /// recvbuf.recv(5, Bytes::from(" world")).unwrap();
+    ///
+    /// assert_eq!(recvbuf.try_read_bytes(3), Some(Bytes::from("hel")));
+    /// assert_eq!(recvbuf.try_read_bytes(usize::MAX), Some(Bytes::from("lo")));
+    /// assert_eq!(recvbuf.try_read_bytes(usize::MAX), Some(Bytes::from(" world")));
+    /// assert_eq!(recvbuf.try_read_bytes(usize::MAX), None);
+    /// ```
+    pub fn try_read_bytes(&mut self, max: usize) -> Option<Bytes> {
+        if !self.is_readable() || max == 0 {
+            return None;
+        }
+        let (_, mut frag) = self.fragments.pop_first()?;
+        let data = frag.split_to(max.min(frag.len()));
+        if !frag.is_empty() {
+            self.fragments.insert(self.nread + data.len() as u64, frag);
+        }
+        self.advance(data.len());
+        Some(data)
+    }
+
+    /// Try to read continuous data from [`RecvBuf`] into the `chunks` without copying.
+    ///
+    /// Each chunk is filled with a fragment returned by [`RecvBuf::try_read_bytes`], until there
+    /// is no more continuous data or all the chunks are filled. Returns the number of the chunks
+    /// filled.
+    ///
+    /// # Example
+    ///
+    /// ``` rust
+    /// # use bytes::Bytes;
+    /// # use qrecovery::recv::RecvBuf;
+    /// let mut recvbuf = RecvBuf::default();
+    /// recvbuf.recv(0, Bytes::from("hello")).unwrap();
+    /// recvbuf.recv(5, Bytes::from(" world")).unwrap();
+    ///
+    /// let mut chunks = [Bytes::new(), Bytes::new(), Bytes::new()];
+    /// assert_eq!(recvbuf.try_read_chunks(&mut chunks), 2);
+    /// assert_eq!(chunks[..2], [Bytes::from("hello"), Bytes::from(" world")]);
+    /// ```
+    pub fn try_read_chunks(&mut self, chunks: &mut [Bytes]) -> usize {
+        let mut filled = 0;
+        while filled < chunks.len() {
+            let Some(data) = self.try_read_bytes(usize::MAX) else {
+                break;
+            };
+            chunks[filled] = data;
+            filled += 1;
+        }
+        filled
+    }
+
     /// The length of continuous data received, which can be compared with the final sizeknown as `SizeKnown`.
     ///
     /// If they match, it indicates that all the data has been received.
@@ -359,11 +314,11 @@ impl RecvBuf {
     /// # use bytes::{Bytes, BytesMut};
     /// # use qrecovery::recv::RecvBuf;
     /// let mut recvbuf = RecvBuf::default();
-    /// assert_eq!(recvbuf.recv(0, Bytes::from("hello")), 5);
+    /// assert_eq!(recvbuf.recv(0, Bytes::from("hello")), Ok(5));
     /// // recvbuf:  "hello"
     /// // available: ^^^^^
     /// assert_eq!(recvbuf.available(), 5);
-    /// assert_eq!(recvbuf.recv(6, Bytes::from("world")), 5);
+    /// assert_eq!(recvbuf.recv(6, Bytes::from("world")), Ok(5));
     /// // recvbuf:  "hello" "world"
     /// // available: ^^^^^
     /// assert_eq!(recvbuf.available(), 5);
@@ -373,29 +328,24 @@ impl RecvBuf {
     /// assert_eq!(dst.as_ref(), b"hello");
     /// assert_eq!(recvbuf.available(), 5);
     ///
-    /// recvbuf.recv(5, Bytes::from(" "));
+    /// recvbuf.recv(5, Bytes::from(" ")).unwrap();
     /// // recvbuf:  "hello world"
     /// // available: ^^^^^^^^^^^
     /// assert_eq!(recvbuf.available(), 11);
     /// ```
     pub fn available(&self) -> u64 {
-        if self
-            .segments
-            .front()
-            .is_some_and(|seg| seg.offset == self.nread)
-        {
-            self.nread + self.segments[0].length
-        } else {
-            self.nread
+        match self.ranges.first_key_value() {
+            Some((&start, &end)) if start == self.nread => end,
+            _ => self.nread,
         }
     }
 
     /// Once the received data becomes continuous, it becomes readable. If necessary (if the application
     /// layer is blocked on reading), it is necessary to notify the application layer to read.
     pub fn is_readable(&self) -> bool {
-        !self.segments.is_empty()
-            && self.segments[0].offset == self.nread
-            && self.segments[0].length > 0
+        self.fragments
+            .first_key_value()
+            .is_some_and(|(&start, _)| start == self.nread)
     }
 }
 
@@ -403,151 +353,158 @@ impl RecvBuf {
 mod tests {
     use super::*;
 
+    fn ranges(buf: &RecvBuf) -> Vec<(u64, u64)> {
+        buf.ranges.iter().map(|(&s, &e)| (s, e)).collect()
+    }
+
+    fn fragments(buf: &RecvBuf) -> Vec<(u64, Bytes)> {
+        buf.fragments.iter().map(|(&o, d)| (o, d.clone())).collect()
+    }
+
     #[test]
     fn test_recvbuf_recv() {
         let mut buf = RecvBuf::default();
-        assert_eq!(buf.recv(0, Bytes::from("hello")), 5);
-        assert_eq!(buf.recv(6, Bytes::from("world")), 5);
-
-        assert_eq!(buf.segments.len(), 2);
-        assert_eq!(buf.segments[0].offset, 0);
-        assert_eq!(buf.segments[0].length, 5);
-        assert_eq!(buf.segments[0].fragments.len(), 1);
-        assert_eq!(buf.segments[0].fragments[0], Bytes::from("hello"));
-        assert_eq!(buf.segments[1].offset, 6);
-        assert_eq!(buf.segments[1].length, 5);
-        assert_eq!(buf.segments[1].fragments.len(), 1);
-        assert_eq!(buf.segments[1].fragments[0], Bytes::from("world"));
-
-        assert_eq!(buf.recv(5, Bytes::from(" ")), 1);
-        assert_eq!(buf.segments.len(), 1);
-        assert_eq!(buf.segments[0].offset, 0);
-        assert_eq!(buf.segments[0].length, 11);
-        assert_eq!(buf.segments[0].fragments.len(), 3);
-        assert_eq!(buf.segments[0].fragments[0], Bytes::from("hello"));
-        assert_eq!(buf.segments[0].fragments[1], Bytes::from(" "));
-        assert_eq!(buf.segments[0].fragments[2], Bytes::from("world"));
-
-        assert_eq!(buf.recv(12, Bytes::from("hello")), 5);
-        assert_eq!(buf.recv(6, Bytes::from("world.hell")), 1);
-        assert_eq!(buf.segments[0].fragments[2], Bytes::from("world"));
-        assert_eq!(buf.segments[0].fragments[3], Bytes::from("."));
-        assert_eq!(buf.segments[0].fragments[4], Bytes::from("hello"));
+        assert_eq!(buf.recv(0, Bytes::from("hello")), Ok(5));
+        assert_eq!(buf.recv(6, Bytes::from("world")), Ok(5));
+
+        assert_eq!(ranges(&buf), [(0, 5), (6, 11)]);
+        assert_eq!(
+            fragments(&buf),
+            [(0, Bytes::from("hello")), (6, Bytes::from("world"))]
+        );
+
+        assert_eq!(buf.recv(5, Bytes::from(" ")), Ok(1));
+        assert_eq!(ranges(&buf), [(0, 11)]);
+        assert_eq!(
+            fragments(&buf),
+            [
+                (0, Bytes::from("hello")),
+                (5, Bytes::from(" ")),
+                (6, Bytes::from("world"))
+            ]
+        );
+
+        assert_eq!(buf.recv(12, Bytes::from("hello")), Ok(5));
+        assert_eq!(buf.recv(6, Bytes::from("world.hell")), Ok(1));
+        assert_eq!(ranges(&buf), [(0, 17)]);
+        assert_eq!(
+            fragments(&buf)[2..],
+            [
+                (6, Bytes::from("world")),
+                (11, Bytes::from(".")),
+                (12, Bytes::from("hello"))
+            ]
+        );
     }
 
     #[test]
     fn test_rcvbuf_recv_extend() {
         let mut buf = RecvBuf::default();
-        assert_eq!(buf.recv(0, Bytes::from("hello")), 5);
-        assert_eq!(buf.recv(6, Bytes::from("world")), 5);
-        assert_eq!(buf.recv(5, Bytes::from(" wor")), 1);
-
-        assert_eq!(buf.segments.len(), 1);
-        assert_eq!(buf.segments[0].offset, 0);
-        assert_eq!(buf.segments[0].length, 11);
-        assert_eq!(buf.segments[0].fragments.len(), 3);
-        assert_eq!(buf.segments[0].fragments[0], Bytes::from("hello"));
-        assert_eq!(buf.segments[0].fragments[1], Bytes::from(" "));
-        assert_eq!(buf.segments[0].fragments[2], Bytes::from("world"));
+        assert_eq!(buf.recv(0, Bytes::from("hello")), Ok(5));
+        assert_eq!(buf.recv(6, Bytes::from("world")), Ok(5));
+        assert_eq!(buf.recv(5, Bytes::from(" wor")), Ok(1));
+
+        assert_eq!(ranges(&buf), [(0, 11)]);
+        assert_eq!(
+            fragments(&buf),
+            [
+                (0, Bytes::from("hello")),
+                (5, Bytes::from(" ")),
+                (6, Bytes::from("world"))
+            ]
+        );
     }
 
     #[test]
     fn test_rcvbuf_recv_extend_more() {
         let mut buf = RecvBuf::default();
-        assert_eq!(buf.recv(0, Bytes::from("hello")), 5);
-        assert_eq!(buf.recv(6, Bytes::from("world")), 5);
-        assert_eq!(buf.recv(5, Bytes::from(" world!")), 2);
-
-        assert_eq!(buf.segments.len(), 1);
-        assert_eq!(buf.segments[0].offset, 0);
-        assert_eq!(buf.segments[0].length, 12);
-        assert_eq!(buf.segments[0].fragments.len(), 4);
-        assert_eq!(buf.segments[0].fragments[0], Bytes::from("hello"));
-        assert_eq!(buf.segments[0].fragments[1], Bytes::from(" "));
-        assert_eq!(buf.segments[0].fragments[2], Bytes::from("world"));
-        assert_eq!(buf.segments[0].fragments[3], Bytes::from("!"));
+        assert_eq!(buf.recv(0, Bytes::from("hello")), Ok(5));
+        assert_eq!(buf.recv(6, Bytes::from("world")), Ok(5));
+        assert_eq!(buf.recv(5, Bytes::from(" world!")), Ok(2));
+
+        assert_eq!(ranges(&buf), [(0, 12)]);
+        assert_eq!(
+            fragments(&buf),
+            [
+                (0, Bytes::from("hello")),
+                (5, Bytes::from(" ")),
+                (6, Bytes::from("world")),
+                (11, Bytes::from("!"))
+            ]
+        );
     }
 
     #[test]
     fn test_overlap() {
         let mut buf = RecvBuf::default();
-        assert_eq!(buf.recv(2, Bytes::from("4514")), 4);
-        assert_eq!(buf.recv(0, Bytes::from("1199")), 2);
-
-        assert_eq!(buf.segments.len(), 1);
-        assert_eq!(buf.segments[0].offset, 0);
-        assert_eq!(buf.segments[0].length, 6);
-        assert_eq!(buf.segments[0].fragments.len(), 2);
-        assert_eq!(buf.segments[0].fragments[0], Bytes::from("11"));
-        assert_eq!(buf.segments[0].fragments[1], Bytes::from("4514"));
+        assert_eq!(buf.recv(2, Bytes::from("4514")), Ok(4));
+        // 重叠部分与之前收到的不一致
+        assert_eq!(buf.recv(0, Bytes::from("1199")), Err(DataConflict(2)));
+        assert_eq!(ranges(&buf), [(2, 6)]);
+
+        assert_eq!(buf.recv(0, Bytes::from("1145")), Ok(2));
+        assert_eq!(ranges(&buf), [(0, 6)]);
+        assert_eq!(
+            fragments(&buf),
+            [(0, Bytes::from("11")), (2, Bytes::from("4514"))]
+        );
     }
 
     #[test]
     fn test_rcvbuf_recv_extend_and_replace() {
         let mut buf = RecvBuf::default();
-        assert_eq!(buf.recv(0, Bytes::from("hello")), 5);
-        assert_eq!(buf.recv(7, Bytes::from("world")), 5);
-        assert_eq!(buf.recv(6, Bytes::from(" world!")), 2);
-
-        assert_eq!(buf.segments.len(), 2);
-        assert_eq!(buf.segments[0].offset, 0);
-        assert_eq!(buf.segments[0].length, 5);
-        assert_eq!(buf.segments[0].fragments.len(), 1);
-        assert_eq!(buf.segments[0].fragments[0], Bytes::from("hello"));
-        assert_eq!(buf.segments[1].offset, 6);
-        assert_eq!(buf.segments[1].length, 7);
-        assert_eq!(buf.segments[1].fragments.len(), 3);
-        assert_eq!(buf.segments[1].fragments[0], Bytes::from(" "));
-        assert_eq!(buf.segments[1].fragments[1], Bytes::from("world"));
-        assert_eq!(buf.segments[1].fragments[2], Bytes::from("!"));
+        assert_eq!(buf.recv(0, Bytes::from("hello")), Ok(5));
+        assert_eq!(buf.recv(7, Bytes::from("world")), Ok(5));
+        assert_eq!(buf.recv(6, Bytes::from(" world!")), Ok(2));
+
+        assert_eq!(ranges(&buf), [(0, 5), (6, 13)]);
+        assert_eq!(
+            fragments(&buf),
+            [
+                (0, Bytes::from("hello")),
+                (6, Bytes::from(" ")),
+                (7, Bytes::from("world")),
+                (12, Bytes::from("!"))
+            ]
+        );
     }
 
     #[test]
     fn test_recvbuf_recv_and_insert() {
         let mut buf = RecvBuf::default();
-        assert_eq!(buf.recv(0, Bytes::from("how")), 3);
-        assert_eq!(buf.recv(9, Bytes::from("you")), 3);
-        assert_eq!(buf.recv(5, Bytes::from("are")), 3);
-
-        assert_eq!(buf.segments.len(), 3);
-        assert_eq!(buf.segments[0].offset, 0);
-        assert_eq!(buf.segments[0].length, 3);
-        assert_eq!(buf.segments[0].fragments.len(), 1);
-        assert_eq!(buf.segments[0].fragments[0], Bytes::from("how"));
-        assert_eq!(buf.segments[1].offset, 5);
-        assert_eq!(buf.segments[1].length, 3);
-        assert_eq!(buf.segments[1].fragments.len(), 1);
-        assert_eq!(buf.segments[1].fragments[0], Bytes::from("are"));
-        assert_eq!(buf.segments[2].offset, 9);
-        assert_eq!(buf.segments[2].length, 3);
-        assert_eq!(buf.segments[2].fragments.len(), 1);
-        assert_eq!(buf.segments[2].fragments[0], Bytes::from("you"));
-
-        assert_eq!(buf.recv(3, Bytes::from("w are you")), 3);
-
-        assert_eq!(buf.segments.len(), 1);
-        assert_eq!(buf.segments[0].offset, 0);
-        assert_eq!(buf.segments[0].length, 12);
-        assert_eq!(buf.segments[0].fragments.len(), 5);
-        assert_eq!(buf.segments[0].fragments[0], Bytes::from("how"));
-        assert_eq!(buf.segments[0].fragments[1], Bytes::from("w "));
-        assert_eq!(buf.segments[0].fragments[2], Bytes::from("are"));
-        assert_eq!(buf.segments[0].fragments[3], Bytes::from(" "));
-        assert_eq!(buf.segments[0].fragments[4], Bytes::from("you"));
+        assert_eq!(buf.recv(0, Bytes::from("how")), Ok(3));
+        assert_eq!(buf.recv(9, Bytes::from("you")), Ok(3));
+        assert_eq!(buf.recv(5, Bytes::from("are")), Ok(3));
+
+        assert_eq!(ranges(&buf), [(0, 3), (5, 8), (9, 12)]);
+
+        assert_eq!(buf.recv(3, Bytes::from("w are you")), Ok(3));
+
+        assert_eq!(ranges(&buf), [(0, 12)]);
+        assert_eq!(
+            fragments(&buf),
+            [
+                (0, Bytes::from("how")),
+                (3, Bytes::from("w ")),
+                (5, Bytes::from("are")),
+                (8, Bytes::from(" ")),
+                (9, Bytes::from("you"))
+            ]
+        );
     }
 
     #[test]
     fn test_recvbuf_read() {
         let mut rcvbuf = RecvBuf::default();
-        assert_eq!(rcvbuf.recv(0, Bytes::from("hello")), 5);
-        assert_eq!(rcvbuf.recv(6, Bytes::from("world")), 5);
+        assert_eq!(rcvbuf.recv(0, Bytes::from("hello")), Ok(5));
+        assert_eq!(rcvbuf.recv(6, Bytes::from("world")), Ok(5));
 
         let mut dst = [0u8; 20];
         let mut buf = &mut dst[..];
         rcvbuf.try_read(&mut buf);
         assert_eq!(buf.remaining_mut(), 15);
 
-        assert_eq!(rcvbuf.recv(5, Bytes::from(" ")), 1);
+        assert_eq!(rcvbuf.recv(5, Bytes::from(" ")), Ok(1));
         rcvbuf.try_read(&mut buf);
 
         assert_eq!(buf.remaining_mut(), 9);
@@ -557,14 +514,87 @@ mod tests {
     #[test]
     fn test_rcvbuf_recv_overlap_seg() {
         let mut buf = RecvBuf::default();
-        assert_eq!(buf.recv(0, Bytes::from("he")), 2);
-        assert_eq!(buf.recv(6, Bytes::from("world")), 5);
-        assert_eq!(buf.recv(0, Bytes::from("hello")), 3);
+        assert_eq!(buf.recv(0, Bytes::from("he")), Ok(2));
+        assert_eq!(buf.recv(6, Bytes::from("world")), Ok(5));
+        assert_eq!(buf.recv(0, Bytes::from("hello")), Ok(3));
+
+        let mut buf = RecvBuf::default();
+        assert_eq!(buf.recv(0, Bytes::from("he")), Ok(2));
+        assert_eq!(buf.recv(6, Bytes::from("wo")), Ok(2));
+        assert_eq!(buf.recv(12, Bytes::from("00")), Ok(2));
+        assert_eq!(buf.recv(0, Bytes::from("hello world")), Ok(7));
+    }
+
+    #[test]
+    fn test_recvbuf_reverse_order() {
+        let data = Bytes::from_iter((0..100).map(|i| i as u8));
+        let mut buf = RecvBuf::default();
+        for start in (0..100).step_by(10).rev() {
+            assert_eq!(
+                buf.recv(start, data.slice(start as usize..start as usize + 10)),
+                Ok(10)
+            );
+            // 读取位置上的空缺未填上之前，都不可读
+            assert_eq!(buf.is_readable(), start == 0);
+            assert_eq!(ranges(&buf), [(start, 100)]);
+        }
+        assert_eq!(buf.available(), 100);
+
+        let mut dst = Vec::new();
+        assert_eq!(buf.try_read(&mut dst), Some(100));
+        assert_eq!(dst, data);
+        assert!(buf.is_empty());
+        assert!(ranges(&buf).is_empty());
+    }
+
+    #[test]
+    fn test_recvbuf_retransmission() {
+        let data = Bytes::from_iter((0..100).map(|i| i as u8));
+        let mut buf = RecvBuf::default();
+        assert_eq!(buf.recv(20, data.slice(20..40)), Ok(20));
+        assert_eq!(buf.recv(60, data.slice(60..80)), Ok(20));
+        // 重传的数据与之前收到的重叠，只接收空缺的部分
+        assert_eq!(buf.recv(10, data.slice(10..70)), Ok(30));
+        assert_eq!(buf.recv(10, data.slice(10..70)), Ok(0));
+        assert_eq!(ranges(&buf), [(10, 80)]);
+
+        assert_eq!(buf.recv(0, data.slice(0..30)), Ok(10));
+        let mut dst = Vec::new();
+        assert_eq!(buf.try_read(&mut dst), Some(80));
+        assert_eq!(dst, data[..80]);
+        // 已读取的数据再次收到，直接忽略
+        assert_eq!(buf.recv(0, data.slice(0..50)), Ok(0));
+        assert_eq!(buf.recv(70, data.slice(70..100)), Ok(20));
+        assert_eq!(ranges(&buf), [(80, 100)]);
+        // 只要有一个字节冲突，整段数据都不接收
+        let mut conflict = data.slice(85..100).to_vec();
+        conflict[14] = 0;
+        assert_eq!(buf.recv(85, Bytes::from(conflict)), Err(DataConflict(99)));
+        assert_eq!(ranges(&buf), [(80, 100)]);
+    }
 
+    #[test]
+    fn test_recvbuf_read_without_copy() {
+        // 两个数据报，各携带一段流数据
+        let datagram1 = Bytes::from_iter((0..100).map(|i| i as u8));
+        let datagram2 = Bytes::from_iter((100..200).map(|i| i as u8));
         let mut buf = RecvBuf::default();
-        assert_eq!(buf.recv(0, Bytes::from("he")), 2);
-        assert_eq!(buf.recv(6, Bytes::from("wo")), 2);
-        assert_eq!(buf.recv(12, Bytes::from("00")), 2);
-        assert_eq!(buf.recv(0, Bytes::from("hello world")), 7);
+        assert_eq!(buf.recv(50, datagram2.slice(40..90)), Ok(50));
+        assert_eq!(buf.recv(0, datagram1.slice(20..70)), Ok(50));
+
+        // 重组后的流数据仍是原数据报上的视图
+        let data = buf.try_read_bytes(30).unwrap();
+        assert_eq!(data.as_ptr(), datagram1[20..].as_ptr());
+        assert_eq!(data.len(), 30);
+        let data = buf.try_read_bytes(usize::MAX).unwrap();
+        assert_eq!(data.as_ptr(), datagram1[50..].as_ptr());
+        assert_eq!(data.len(), 20);
+        let data = buf.try_read_bytes(usize::MAX).unwrap();
+        assert_eq!(data.as_ptr(), datagram2[40..].as_ptr());
+        assert_eq!(data.len(), 50);
+
+        assert_eq!(buf.nread(), 100);
+        assert!(buf.try_read_bytes(usize::MAX).is_none());
+        assert!(buf.is_empty());
     }
 }
@@ -5,6 +5,7 @@ use std::{
     task::{Context, Poll},
 };
 
+use bytes::Bytes;
 use qbase::{
     frame::{MaxStreamDataFrame, SendFrame, StopSendingFrame},
     varint::VARINT_MAX,
@@ -95,6 +96,77 @@ where
     }
 }
 
+impl<TX> Reader<TX>
+where
+    TX: SendFrame<MaxStreamDataFrame>,
+{
+    /// Poll to read the data from the peer into the `chunks` without copying.
+    ///
+    /// Unlike [`poll_read`], the data is not copied into the caller's buffer, each chunk is a
+    /// continuous piece of the stream data, which is a view into the memory it was received in.
+    /// The chunks are filled in order, the flow control credit is released as they are read.
+    ///
+    /// Returns the number of the chunks filled, or [`None`] if all the data has been read and the
+    /// stream has been closed.
+    ///
+    /// [`poll_read`]: AsyncRead::poll_read
+    pub fn poll_read_chunks(
+        &mut self,
+        cx: &mut Context<'_>,
+        chunks: &mut [Bytes],
+    ) -> Poll<io::Result<Option<usize>>> {
+        if chunks.is_empty() {
+            return Poll::Ready(Ok(Some(0)));
+        }
+        let mut recver = self.0.recver();
+        let receiving_state = recver.as_mut().map_err(|e| e.clone())?;
+        let poll = match receiving_state {
+            Recver::Recv(r) => r.poll_read_chunks(cx, chunks).map_ok(Some),
+            Recver::SizeKnown(r) => r.poll_read_chunks(cx, chunks).map_ok(Some),
+            Recver::DataRcvd(r) => {
+                let filled = r.read_chunks(chunks);
+                if r.is_all_read() {
+                    *receiving_state = Recver::DataRead;
+                }
+                Poll::Ready(Ok((filled > 0).then_some(filled)))
+            }
+            Recver::DataRead => Poll::Ready(Ok(None)),
+            Recver::ResetRcvd(reset) => {
+                let reset = *reset;
+                *receiving_state = Recver::ResetRead(reset);
+                Poll::Ready(Err(io::Error::new(io::ErrorKind::BrokenPipe, reset)))
+            }
+            Recver::ResetRead(reset) => {
+                Poll::Ready(Err(io::Error::new(io::ErrorKind::BrokenPipe, *reset)))
+            }
+        };
+        if let Poll::Ready(Ok(Some(filled))) = poll {
+            let consumed = chunks[..filled].iter().map(Bytes::len).sum::<usize>();
+            if consumed > 0 {
+                self.0.on_consumed(consumed);
+            }
+        }
+        poll
+    }
+
+    /// Read the data from the peer into the `chunks` without copying, read
+    /// [`poll_read_chunks`] for more details.
+    ///
+    /// [`poll_read_chunks`]: Reader::poll_read_chunks
+    pub async fn read_chunks(&mut self, chunks: &mut [Bytes]) -> io::Result<Option<usize>> {
+        core::future::poll_fn(|cx| self.poll_read_chunks(cx, chunks)).await
+    }
+
+    /// Read the next continuous chunk of the data from the peer without copying.
+    ///
+    /// Returns [`None`] if all the data has been read and the stream has been closed.
+    pub async fn read_chunk(&mut self) -> io::Result<Option<Bytes>> {
+        let mut chunk = [Bytes::new()];
+        let filled = self.read_chunks(&mut chunk).await?;
+        Ok(filled.map(|_| std::mem::take(&mut chunk[0])))
+    }
+}
+
 impl<TX> AsyncRead for Reader<TX>
 where
     TX: SendFrame<MaxStreamDataFrame>,
@@ -106,8 +178,9 @@ where
     ) -> Poll<io::Result<()>> {
         let mut recver = self.0.recver();
         let receiving_state = recver.as_mut().map_err(|e| e.clone())?;
+        let filled = buf.filled().len();
         // 能相当清楚地看到应用层读取数据驱动的接收状态演变
-        match receiving_state {
+        let poll = match receiving_state {
             Recver::Recv(r) => r.poll_read(cx, buf),
             Recver::SizeKnown(r) => r.poll_read(cx, buf),
             Recver::DataRcvd(r) => {
@@ -126,7 +199,13 @@ where
             Recver::ResetRead(reset) => {
                 Poll::Ready(Err(io::Error::new(io::ErrorKind::BrokenPipe, *reset)))
             }
+        };
+        // 应用层读走的数据释放了连接级别的接收窗口
+        let consumed = buf.filled().len() - filled;
+        if consumed > 0 {
+            self.0.on_consumed(consumed);
         }
+        poll
     }
 }
 
@@ -7,6 +7,7 @@ use std::{
 use bytes::{BufMut, Bytes};
 use qbase::{
     error::{Error, ErrorKind},
+    flow::ArcRecvController,
     frame::{
         BeFrame, MaxStreamDataFrame, ResetStreamError, ResetStreamFrame, SendFrame,
         StopSendingFrame, StreamFrame,
@@ -24,8 +25,11 @@ pub(super) struct Recv<TX> {
     read_waker: Option<Waker>,
     stop_state: Option<u64>,
     frames_tx: TX,
+    // 暂定的最大偏移，即已收到的数据的最大结束位置，最终大小不得小于它
     largest: u64,
     max_stream_data: u64,
+    // 流级别的接收窗口，即初始的流数据限制
+    window: u64,
 }
 
 impl<TX> Recv<TX>
@@ -39,25 +43,51 @@ where
     ) -> Poll<io::Result<()>> {
         if self.rcvbuf.is_readable() {
             self.rcvbuf.try_read(buf);
-
-            let threshold = 1_000_000;
-            if self.rcvbuf.nread() + threshold > self.max_stream_data {
-                let max_stream_data = (self.rcvbuf.nread() + threshold * 2).min(VARINT_MAX);
-                if max_stream_data > self.max_stream_data {
-                    self.max_stream_data = max_stream_data;
-                    self.frames_tx.send_frame([MaxStreamDataFrame::new(
-                        self.stream_id,
-                        VarInt::from_u64(max_stream_data).unwrap(),
-                    )]);
-                }
+            // 应用层读取超过窗口的一半后，才扩展额度，避免频繁发送MaxStreamData
+            if self.rcvbuf.nread() + self.window / 2 > self.max_stream_data {
+                self.extend_credit();
             }
-
             Poll::Ready(Ok(()))
         } else {
             self.read_waker = Some(cx.waker().clone());
             Poll::Pending
         }
     }
+
+    pub(super) fn poll_read_chunks(
+        &mut self,
+        cx: &mut Context<'_>,
+        chunks: &mut [Bytes],
+    ) -> Poll<io::Result<usize>> {
+        if self.rcvbuf.is_readable() {
+            let filled = self.rcvbuf.try_read_chunks(chunks);
+            if self.rcvbuf.nread() + self.window / 2 > self.max_stream_data {
+                self.extend_credit();
+            }
+            Poll::Ready(Ok(filled))
+        } else {
+            self.read_waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    /// Called when the STREAM_DATA_BLOCKED frame is received, the credit is extended immediately
+    /// if the application has read some data since the last extension.
+    pub(super) fn on_data_blocked(&mut self) {
+        self.extend_credit();
+    }
+
+    // 以应用层已读取的位置为起点，扩展出一个窗口的额度
+    fn extend_credit(&mut self) {
+        let max_stream_data = (self.rcvbuf.nread() + self.window).min(VARINT_MAX);
+        if max_stream_data > self.max_stream_data {
+            self.max_stream_data = max_stream_data;
+            self.frames_tx.send_frame([MaxStreamDataFrame::new(
+                self.stream_id,
+                VarInt::from_u64(max_stream_data).unwrap(),
+            )]);
+        }
+    }
 }
 
 impl<TX> Recv<TX>
@@ -76,18 +106,46 @@ where
 }
 
 impl<TX: Clone> Recv<TX> {
-    pub(super) fn determin_size(&mut self, total_size: u64) -> SizeKnown<TX> {
+    /// Called when the stream frame with the FIN flag is received, the final size of the stream
+    /// is determined, which must not exceed the flow control limit, and must not be smaller than
+    /// the data already received.
+    pub(super) fn determin_size(
+        &mut self,
+        stream_frame: &StreamFrame,
+    ) -> Result<SizeKnown<TX>, Error> {
+        let final_size = stream_frame.offset() + stream_frame.len() as u64;
+        if final_size > self.max_stream_data {
+            return Err(Error::new(
+                ErrorKind::FlowControl,
+                stream_frame.frame_type(),
+                format!(
+                    "{} final size {final_size} exceeds the stream data limit {}",
+                    stream_frame.id, self.max_stream_data
+                ),
+            ));
+        }
+        if final_size < self.largest {
+            return Err(Error::new(
+                ErrorKind::FinalSize,
+                stream_frame.frame_type(),
+                format!(
+                    "{} final size {final_size} is smaller than the largest rcvd data offset {}",
+                    stream_frame.id, self.largest
+                ),
+            ));
+        }
         if let Some(waker) = self.read_waker.take() {
             waker.wake();
         }
-        SizeKnown {
-            final_size: total_size,
+        Ok(SizeKnown {
+            final_size,
+            largest: self.largest,
             stream_id: self.stream_id,
             rcvbuf: std::mem::take(&mut self.rcvbuf),
             stop_state: self.stop_state.take(),
             stop_tx: self.frames_tx.clone(),
             read_waker: self.read_waker.take(),
-        }
+        })
     }
 }
 
@@ -101,6 +159,7 @@ impl<TX> Recv<TX> {
             frames_tx,
             largest: 0,
             max_stream_data: buf_size,
+            window: buf_size,
         }
     }
 
@@ -118,18 +177,41 @@ impl<TX> Recv<TX> {
                 ),
             ));
         }
+        // 连接级别的流量控制按最大偏移的增量计算，而非新收到的字节数
+        let new_data_size = data_end.saturating_sub(self.largest);
         self.largest = std::cmp::max(self.largest, data_end);
-        let fresh_data = self.rcvbuf.recv(data_start, body);
+        self.rcvbuf.recv(data_start, body).map_err(|conflict| {
+            Error::new(
+                ErrorKind::ProtocolViolation,
+                stream_frame.frame_type(),
+                format!("{} {conflict}", stream_frame.id),
+            )
+        })?;
         if self.rcvbuf.is_readable() {
             if let Some(waker) = self.read_waker.take() {
                 waker.wake()
             }
         }
-        Ok(fresh_data)
+        Ok(new_data_size as usize)
     }
 
-    pub(super) fn recv_reset(&mut self, reset_frame: &ResetStreamFrame) -> Result<u64, Error> {
+    /// Returns the increase of the data counted by the connection-level flow control up to the
+    /// final size, and the size of the data discarded without being read.
+    pub(super) fn recv_reset(
+        &mut self,
+        reset_frame: &ResetStreamFrame,
+    ) -> Result<(u64, u64), Error> {
         let final_size = reset_frame.final_size.into_inner();
+        if final_size > self.max_stream_data {
+            return Err(Error::new(
+                ErrorKind::FlowControl,
+                reset_frame.frame_type(),
+                format!(
+                    "{} reset with final size {final_size} which exceeds the stream data limit {}",
+                    reset_frame.stream_id, self.max_stream_data
+                ),
+            ));
+        }
         if final_size < self.largest {
             return Err(Error::new(
                 ErrorKind::FinalSize,
@@ -141,7 +223,7 @@ impl<TX> Recv<TX> {
             ));
         }
         self.wake_reader();
-        Ok(final_size)
+        Ok((final_size - self.largest, final_size - self.rcvbuf.nread()))
     }
 
     pub(super) fn is_stopped(&self) -> bool {
@@ -164,6 +246,7 @@ pub struct SizeKnown<TX> {
     read_waker: Option<Waker>,
     stop_state: Option<u64>,
     stop_tx: TX,
+    largest: u64,
     final_size: u64,
 }
 
@@ -209,13 +292,21 @@ impl<TX> SizeKnown<TX> {
                 ),
             ));
         }
-        let fresh_data = self.rcvbuf.recv(data_start, buf);
+        let new_data_size = data_end.saturating_sub(self.largest);
+        self.largest = std::cmp::max(self.largest, data_end);
+        self.rcvbuf.recv(data_start, buf).map_err(|conflict| {
+            Error::new(
+                ErrorKind::ProtocolViolation,
+                stream_frame.frame_type(),
+                format!("{} {conflict}", stream_frame.id),
+            )
+        })?;
         if self.rcvbuf.is_readable() {
             if let Some(waker) = self.read_waker.take() {
                 waker.wake()
             }
         }
-        Ok(fresh_data)
+        Ok(new_data_size as usize)
     }
 
     pub(super) fn is_all_rcvd(&self) -> bool {
@@ -247,7 +338,25 @@ impl<TX> SizeKnown<TX> {
         }
     }
 
-    pub(super) fn recv_reset(&mut self, reset_frame: &ResetStreamFrame) -> Result<u64, Error> {
+    pub(super) fn poll_read_chunks(
+        &mut self,
+        cx: &mut Context<'_>,
+        chunks: &mut [Bytes],
+    ) -> Poll<io::Result<usize>> {
+        if self.rcvbuf.is_readable() {
+            Poll::Ready(Ok(self.rcvbuf.try_read_chunks(chunks)))
+        } else {
+            self.read_waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    /// Returns the increase of the data counted by the connection-level flow control up to the
+    /// final size, and the size of the data discarded without being read.
+    pub(super) fn recv_reset(
+        &mut self,
+        reset_frame: &ResetStreamFrame,
+    ) -> Result<(u64, u64), Error> {
         let final_size = reset_frame.final_size.into_inner();
         if final_size != self.final_size {
             return Err(Error::new(
@@ -260,7 +369,7 @@ impl<TX> SizeKnown<TX> {
             ));
         }
         self.wake_reader();
-        Ok(final_size)
+        Ok((final_size - self.largest, final_size - self.rcvbuf.nread()))
     }
 
     pub(super) fn is_stopped(&self) -> bool {
@@ -325,6 +434,10 @@ impl DataRcvd {
         self.rcvbuf.try_read(buf);
     }
 
+    pub(super) fn read_chunks(&mut self, chunks: &mut [Bytes]) -> usize {
+        self.rcvbuf.try_read_chunks(chunks)
+    }
+
     pub(super) fn is_all_read(&self) -> bool {
         self.rcvbuf.is_empty()
     }
@@ -362,22 +475,36 @@ impl<TX> Recver<TX> {
 /// [`Incoming`]: super::Incoming
 /// [`Reader`]: super::Reader
 #[derive(Debug, Clone)]
-pub struct ArcRecver<TX>(Arc<Mutex<Result<Recver<TX>, Error>>>);
+pub struct ArcRecver<TX> {
+    recver: Arc<Mutex<Result<Recver<TX>, Error>>>,
+    // 连接级别的接收流量控制，应用层读取数据后，更新连接级别的接收窗口
+    flow_ctrl: ArcRecvController,
+}
 
 impl<TX> ArcRecver<TX>
 where
     TX: SendFrame<StopSendingFrame> + SendFrame<MaxStreamDataFrame> + Clone + Send + 'static,
 {
     #[doc(hidden)]
-    pub(crate) fn new(stream_id: StreamId, buf_size: u64, frames_tx: TX) -> Self {
-        ArcRecver(Arc::new(Mutex::new(Ok(Recver::new(
-            stream_id, buf_size, frames_tx,
-        )))))
+    pub(crate) fn new(
+        stream_id: StreamId,
+        buf_size: u64,
+        frames_tx: TX,
+        flow_ctrl: ArcRecvController,
+    ) -> Self {
+        ArcRecver {
+            recver: Arc::new(Mutex::new(Ok(Recver::new(stream_id, buf_size, frames_tx)))),
+            flow_ctrl,
+        }
     }
 }
 
 impl<TX> ArcRecver<TX> {
     pub(super) fn recver(&self) -> MutexGuard<Result<Recver<TX>, Error>> {
-        self.0.lock().unwrap()
+        self.recver.lock().unwrap()
+    }
+
+    pub(super) fn on_consumed(&self, amount: usize) {
+        self.flow_ctrl.on_consumed(amount);
     }
 }
@@ -1,11 +1,18 @@
 //! The reliable transmission for frames.
 use std::{
-    collections::VecDeque,
+    collections::{HashMap, VecDeque},
     sync::{Arc, Mutex, MutexGuard},
 };
 
 use enum_dispatch::enum_dispatch;
-use qbase::frame::{io::WriteFrame, BeFrame, CryptoFrame, ReliableFrame, SendFrame, StreamFrame};
+use qbase::{
+    frame::{
+        io::WriteFrame, BeFrame, CryptoFrame, MaxStreamsFrame, ReliableFrame, SendFrame,
+        StreamCtlFrame, StreamFrame, StreamsBlockedFrame,
+    },
+    sid::{Dir, StreamId},
+    varint::VarInt,
+};
 
 mod rcvdpkt;
 pub use rcvdpkt::*;
@@ -29,6 +36,16 @@ pub enum GuaranteedFrame {
 /// frames write frames to this queue by calling [`SendFrame::send_frame`]. The transport layer can
 /// read the frames in the queue and encode them into the send buffer by calling [`try_read`].
 ///
+/// The frames of the lost packets are queued again too, so the queue coalesces the frames that only
+/// the latest one matters: a queued MAX_DATA, MAX_STREAM_DATA, MAX_STREAMS or their BLOCKED
+/// counterpart of the same stream(direction) is updated to the larger limit instead of queuing
+/// another one, a queued ACK_FREQUENCY frame is replaced by the one with a larger sequence number,
+/// and a frame that is exactly the same as a queued one is dropped. The other frames are kept in
+/// the order they are sent.
+///
+/// The queued frames are indexed by their kind and the stream(direction) or sequence they belong
+/// to, so a frame is coalesced without scanning the whole queue.
+///
 /// # Example
 /// ```rust
 /// use qbase::frame::{HandshakeDoneFrame, SendFrame};
@@ -41,15 +58,110 @@ pub enum GuaranteedFrame {
 /// [`try_read`]: ArcReliableFrameDeque::try_read
 /// [`DataStreams`]: crate::streams::DataStreams
 #[derive(Debug, Default, Clone)]
-pub struct ArcReliableFrameDeque(Arc<Mutex<VecDeque<ReliableFrame>>>);
+pub struct ArcReliableFrameDeque(Arc<Mutex<ReliableFrames>>);
+
+// 可能被合并的帧的索引键，同一个键下至多有一个帧需要被合并
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum FrameKey {
+    Ping,
+    NewToken(Vec<u8>),
+    MaxData,
+    DataBlocked,
+    NewConnectionId(VarInt),
+    RetireConnectionId(VarInt),
+    HandshakeDone,
+    AckFrequency,
+    ResetStream(StreamId),
+    StopSending(StreamId),
+    MaxStreamData(StreamId),
+    MaxStreams(Dir),
+    StreamDataBlocked(StreamId),
+    StreamsBlocked(Dir),
+}
+
+impl From<&ReliableFrame> for FrameKey {
+    fn from(frame: &ReliableFrame) -> Self {
+        use StreamCtlFrame::*;
+        match frame {
+            ReliableFrame::Ping(_) => FrameKey::Ping,
+            ReliableFrame::NewToken(f) => FrameKey::NewToken(f.token.clone()),
+            ReliableFrame::MaxData(_) => FrameKey::MaxData,
+            ReliableFrame::DataBlocked(_) => FrameKey::DataBlocked,
+            ReliableFrame::NewConnectionId(f) => FrameKey::NewConnectionId(f.sequence),
+            ReliableFrame::RetireConnectionId(f) => FrameKey::RetireConnectionId(f.sequence),
+            ReliableFrame::HandshakeDone(_) => FrameKey::HandshakeDone,
+            ReliableFrame::AckFrequency(_) => FrameKey::AckFrequency,
+            ReliableFrame::Stream(ResetStream(f)) => FrameKey::ResetStream(f.stream_id),
+            ReliableFrame::Stream(StopSending(f)) => FrameKey::StopSending(f.stream_id),
+            ReliableFrame::Stream(MaxStreamData(f)) => FrameKey::MaxStreamData(f.stream_id),
+            ReliableFrame::Stream(MaxStreams(MaxStreamsFrame::Bi(_))) => {
+                FrameKey::MaxStreams(Dir::Bi)
+            }
+            ReliableFrame::Stream(MaxStreams(MaxStreamsFrame::Uni(_))) => {
+                FrameKey::MaxStreams(Dir::Uni)
+            }
+            ReliableFrame::Stream(StreamDataBlocked(f)) => FrameKey::StreamDataBlocked(f.stream_id),
+            ReliableFrame::Stream(StreamsBlocked(StreamsBlockedFrame::Bi(_))) => {
+                FrameKey::StreamsBlocked(Dir::Bi)
+            }
+            ReliableFrame::Stream(StreamsBlocked(StreamsBlockedFrame::Uni(_))) => {
+                FrameKey::StreamsBlocked(Dir::Uni)
+            }
+        }
+    }
+}
+
+/// The frames queued in the [`ArcReliableFrameDeque`], indexed by their [`FrameKey`].
+#[derive(Debug, Default)]
+struct ReliableFrames {
+    deque: VecDeque<ReliableFrame>,
+    // 队首帧的绝对位置，索引中记录的是帧的绝对位置，出队时无需更新其他帧的索引
+    offset: u64,
+    index: HashMap<FrameKey, u64>,
+}
+
+impl ReliableFrames {
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            deque: VecDeque::with_capacity(capacity),
+            ..Default::default()
+        }
+    }
+
+    fn push_back(&mut self, frame: ReliableFrame) {
+        let key = FrameKey::from(&frame);
+        if let Some(&pos) = self.index.get(&key) {
+            let queued = &mut self.deque[(pos - self.offset) as usize];
+            if coalesce(queued, &frame) {
+                return;
+            }
+        }
+        self.index
+            .insert(key, self.offset + self.deque.len() as u64);
+        self.deque.push_back(frame);
+    }
+
+    fn pop_front(&mut self) -> Option<ReliableFrame> {
+        let frame = self.deque.pop_front()?;
+        let key = FrameKey::from(&frame);
+        // 同一个键下可能有更新的帧，只移除指向出队帧的索引
+        if self.index.get(&key) == Some(&self.offset) {
+            self.index.remove(&key);
+        }
+        self.offset += 1;
+        Some(frame)
+    }
+}
 
 impl ArcReliableFrameDeque {
     /// Create a new empty deque with at least the specified capacity.
     pub fn with_capacity(capacity: usize) -> Self {
-        Self(Arc::new(Mutex::new(VecDeque::with_capacity(capacity))))
+        Self(Arc::new(Mutex::new(ReliableFrames::with_capacity(
+            capacity,
+        ))))
     }
 
-    fn lock_guard(&self) -> MutexGuard<'_, VecDeque<ReliableFrame>> {
+    fn lock_guard(&self) -> MutexGuard<'_, ReliableFrames> {
         self.0.lock().unwrap()
     }
 
@@ -60,12 +172,12 @@ impl ArcReliableFrameDeque {
     ///
     /// If the read success, the frame and the number of bytes written will be return.
     pub fn try_read(&self, mut buf: &mut [u8]) -> Option<(ReliableFrame, usize)> {
-        let mut deque = self.0.lock().unwrap();
-        let frame = deque.front()?;
+        let mut frames = self.lock_guard();
+        let frame = frames.deque.front()?;
         if frame.max_encoding_size() <= buf.len() || frame.encoding_size() <= buf.len() {
             let buf_len = buf.len();
             buf.put_frame(frame);
-            Some((deque.pop_front().unwrap(), buf_len - buf.len()))
+            Some((frames.pop_front().unwrap(), buf_len - buf.len()))
         } else {
             None
         }
@@ -77,6 +189,216 @@ where
     T: Into<ReliableFrame>,
 {
     fn send_frame<I: IntoIterator<Item = T>>(&self, iter: I) {
-        self.lock_guard().extend(iter.into_iter().map(Into::into));
+        let mut frames = self.lock_guard();
+        for frame in iter.into_iter().map(Into::into) {
+            frames.push_back(frame);
+        }
+    }
+}
+
+// 尝试将frame合并到已在队列中的queued帧，返回是否合并成功，各类帧的策略：
+// - 限制类的控制帧是幂等的状态更新，同类(同一条流/同一方向)的只保留其中较大的值；
+// - ACK_FREQUENCY帧只有序号最大的一个有效，较旧的被替换；
+// - 其它帧不会被合并或重排，只丢弃完全相同的重复帧
+fn coalesce(queued: &mut ReliableFrame, frame: &ReliableFrame) -> bool {
+    use StreamCtlFrame::*;
+    match (queued, frame) {
+        (ReliableFrame::AckFrequency(q), ReliableFrame::AckFrequency(f)) => {
+            if f.sequence_number > q.sequence_number {
+                *q = *f;
+            }
+        }
+        (ReliableFrame::MaxData(q), ReliableFrame::MaxData(f)) => {
+            q.max_data = q.max_data.max(f.max_data);
+        }
+        (ReliableFrame::DataBlocked(q), ReliableFrame::DataBlocked(f)) => {
+            q.limit = q.limit.max(f.limit);
+        }
+        (ReliableFrame::Stream(MaxStreamData(q)), ReliableFrame::Stream(MaxStreamData(f)))
+            if q.stream_id == f.stream_id =>
+        {
+            q.max_stream_data = q.max_stream_data.max(f.max_stream_data);
+        }
+        (
+            ReliableFrame::Stream(StreamDataBlocked(q)),
+            ReliableFrame::Stream(StreamDataBlocked(f)),
+        ) if q.stream_id == f.stream_id => {
+            q.maximum_stream_data = q.maximum_stream_data.max(f.maximum_stream_data);
+        }
+        (ReliableFrame::Stream(MaxStreams(q)), ReliableFrame::Stream(MaxStreams(f))) => {
+            match (q, f) {
+                (MaxStreamsFrame::Bi(q), MaxStreamsFrame::Bi(f))
+                | (MaxStreamsFrame::Uni(q), MaxStreamsFrame::Uni(f)) => *q = (*q).max(*f),
+                _ => return false,
+            }
+        }
+        (ReliableFrame::Stream(StreamsBlocked(q)), ReliableFrame::Stream(StreamsBlocked(f))) => {
+            match (q, f) {
+                (StreamsBlockedFrame::Bi(q), StreamsBlockedFrame::Bi(f))
+                | (StreamsBlockedFrame::Uni(q), StreamsBlockedFrame::Uni(f)) => *q = (*q).max(*f),
+                _ => return false,
+            }
+        }
+        (queued, frame) => return queued == frame,
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use qbase::{
+        frame::{
+            AckFrequencyFrame, DataBlockedFrame, HandshakeDoneFrame, MaxDataFrame,
+            MaxStreamDataFrame, PingFrame,
+        },
+        sid::StreamId,
+        varint::VarInt,
+    };
+
+    use super::*;
+
+    fn drain(deque: &ArcReliableFrameDeque) -> Vec<ReliableFrame> {
+        let mut frames = deque.lock_guard();
+        core::iter::from_fn(|| frames.pop_front()).collect()
+    }
+
+    #[test]
+    fn test_only_highest_max_data_transmitted() {
+        let deque = ArcReliableFrameDeque::with_capacity(8);
+        for n in [100, 300, 200] {
+            deque.send_frame([MaxDataFrame {
+                max_data: VarInt::from_u32(n),
+            }]);
+        }
+        deque.send_frame([HandshakeDoneFrame]);
+        deque.send_frame([MaxDataFrame {
+            max_data: VarInt::from_u32(250),
+        }]);
+
+        let mut buf = [0u8; 64];
+        let mut sent = vec![];
+        while let Some((frame, _)) = deque.try_read(&mut buf) {
+            sent.push(frame);
+        }
+        assert_eq!(
+            sent,
+            vec![
+                ReliableFrame::MaxData(MaxDataFrame {
+                    max_data: VarInt::from_u32(300),
+                }),
+                ReliableFrame::HandshakeDone(HandshakeDoneFrame),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_coalesce_ack_frequency() {
+        let deque = ArcReliableFrameDeque::with_capacity(8);
+        let ack_frequency = |seq: u32, threshold: u32| AckFrequencyFrame {
+            sequence_number: VarInt::from_u32(seq),
+            ack_eliciting_threshold: VarInt::from_u32(threshold),
+            request_max_ack_delay: VarInt::from_u32(25_000),
+            reordering_threshold: VarInt::from_u32(1),
+        };
+        deque.send_frame([ack_frequency(1, 2)]);
+        deque.send_frame([PingFrame]);
+        // 较新的帧替换较旧的，丢失的旧帧不会覆盖新帧
+        deque.send_frame([ack_frequency(2, 10), ack_frequency(1, 2)]);
+        assert_eq!(
+            drain(&deque),
+            vec![
+                ReliableFrame::AckFrequency(ack_frequency(2, 10)),
+                ReliableFrame::Ping(PingFrame),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_coalesce_frames() {
+        let deque = ArcReliableFrameDeque::with_capacity(8);
+        let max_data = |n: u32| MaxDataFrame {
+            max_data: VarInt::from_u32(n),
+        };
+        deque.send_frame([max_data(100)]);
+        deque.send_frame([HandshakeDoneFrame]);
+        // 丢失包中较旧的MAX_DATA不会覆盖较新的值
+        deque.send_frame([max_data(200), max_data(150)]);
+        deque.send_frame([HandshakeDoneFrame]);
+        deque.send_frame([DataBlockedFrame {
+            limit: VarInt::from_u32(10),
+        }]);
+        assert_eq!(
+            drain(&deque),
+            vec![
+                ReliableFrame::MaxData(max_data(200)),
+                ReliableFrame::HandshakeDone(HandshakeDoneFrame),
+                ReliableFrame::DataBlocked(DataBlockedFrame {
+                    limit: VarInt::from_u32(10),
+                }),
+            ]
+        );
+
+        let sid = |id: u32| StreamId::from(VarInt::from_u32(id << 2));
+        let max_stream_data = |id: u32, n: u32| {
+            StreamCtlFrame::MaxStreamData(MaxStreamDataFrame {
+                stream_id: sid(id),
+                max_stream_data: VarInt::from_u32(n),
+            })
+        };
+        deque.send_frame([max_stream_data(0, 100), max_stream_data(1, 100)]);
+        deque.send_frame([max_stream_data(0, 300)]);
+        deque.send_frame([
+            StreamCtlFrame::MaxStreams(MaxStreamsFrame::Bi(VarInt::from_u32(10))),
+            StreamCtlFrame::MaxStreams(MaxStreamsFrame::Uni(VarInt::from_u32(5))),
+            StreamCtlFrame::MaxStreams(MaxStreamsFrame::Bi(VarInt::from_u32(20))),
+        ]);
+        assert_eq!(
+            drain(&deque),
+            vec![
+                ReliableFrame::Stream(max_stream_data(0, 300)),
+                ReliableFrame::Stream(max_stream_data(1, 100)),
+                ReliableFrame::Stream(StreamCtlFrame::MaxStreams(MaxStreamsFrame::Bi(
+                    VarInt::from_u32(20)
+                ))),
+                ReliableFrame::Stream(StreamCtlFrame::MaxStreams(MaxStreamsFrame::Uni(
+                    VarInt::from_u32(5)
+                ))),
+            ]
+        );
+
+        // 重复的PING也只发送一个
+        deque.send_frame([PingFrame, PingFrame]);
+        assert_eq!(drain(&deque), vec![ReliableFrame::Ping(PingFrame)]);
+    }
+
+    #[test]
+    fn test_coalesce_after_read() {
+        let deque = ArcReliableFrameDeque::with_capacity(8);
+        let max_stream_data = |n: u32| {
+            StreamCtlFrame::MaxStreamData(MaxStreamDataFrame {
+                stream_id: StreamId::from(VarInt::from_u32(0)),
+                max_stream_data: VarInt::from_u32(n),
+            })
+        };
+        deque.send_frame([max_stream_data(100)]);
+        deque.send_frame([PingFrame]);
+
+        // 已读出发送的帧不再参与合并，之后的帧重新入队
+        let mut buf = [0u8; 64];
+        let (frame, _) = deque.try_read(&mut buf).unwrap();
+        assert_eq!(frame, ReliableFrame::Stream(max_stream_data(100)));
+        deque.send_frame([max_stream_data(200)]);
+        deque.send_frame([max_stream_data(150), max_stream_data(100)]);
+        deque.send_frame([PingFrame]);
+        assert_eq!(
+            drain(&deque),
+            vec![
+                ReliableFrame::Ping(PingFrame),
+                ReliableFrame::Stream(max_stream_data(200)),
+            ]
+        );
+
+        // 队列清空后，索引也被清空
+        assert!(deque.lock_guard().index.is_empty());
     }
 }
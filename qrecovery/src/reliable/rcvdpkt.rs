@@ -4,8 +4,8 @@ use std::{
 };
 
 use qbase::{
-    frame::{io::WriteFrame, AckFrame},
-    packet::PacketNumber,
+    frame::{io::WriteFrame, AckFrame, EcnCounts, PathAckFrame},
+    packet::{Ecn, PacketNumber},
     util::IndexDeque,
     varint::{VarInt, VARINT_MAX},
 };
@@ -41,6 +41,9 @@ impl State {
     }
 }
 
+/// The default value of the ack_delay_exponent transport parameter.
+const DEFAULT_ACK_DELAY_EXPONENT: u8 = 3;
+
 #[derive(Debug, Error, PartialEq, Eq)]
 pub enum InvalidPacketNumber {
     #[error("packet number too old")]
@@ -55,21 +58,38 @@ pub enum InvalidPacketNumber {
 /// - 记录包有无收到
 /// - 根据某个largest pktno，生成ack frame（ack frame不能超过buf大小）
 /// - 确定记录不再需要，可以被丢弃，滑走
-#[derive(Debug, Default)]
+/// - 统计收到的各ECN标记的数据包数量，在ack frame中反馈
+#[derive(Debug)]
 struct RcvdPktRecords {
     queue: IndexDeque<State, VARINT_MAX>,
+    // 收到第一个带ECN标记的包之前，ack frame不携带ECN计数
+    ecn: Option<EcnCounts>,
+    // 本端通告的ack_delay_exponent，ack frame中的Ack Delay以2^exponent微秒为单位
+    ack_delay_exponent: u8,
+}
+
+impl Default for RcvdPktRecords {
+    fn default() -> Self {
+        Self::with_capacity(0)
+    }
 }
 
 impl RcvdPktRecords {
     fn with_capacity(capacity: usize) -> Self {
         Self {
             queue: IndexDeque::with_capacity(capacity),
+            ecn: None,
+            ack_delay_exponent: DEFAULT_ACK_DELAY_EXPONENT,
         }
     }
 
     fn decode_pn(&mut self, pkt_number: PacketNumber) -> Result<u64, InvalidPacketNumber> {
         let expected_pn = self.queue.largest();
         let pn = pkt_number.decode(expected_pn);
+        // 包号不能超过2^62-1，否则无法再被记录和确认
+        if pn > VARINT_MAX {
+            return Err(InvalidPacketNumber::TooLarge);
+        }
         if pn < self.queue.offset() {
             return Err(InvalidPacketNumber::TooOld);
         }
@@ -93,6 +113,24 @@ impl RcvdPktRecords {
         }
     }
 
+    fn on_rcvd_ecn(&mut self, ecn: Ecn) {
+        let zero = VarInt::from_u32(0);
+        let counts = match ecn {
+            Ecn::NotEct => return,
+            _ => self.ecn.get_or_insert(EcnCounts {
+                ect0: zero,
+                ect1: zero,
+                ce: zero,
+            }),
+        };
+        let count = match ecn {
+            Ecn::Ect0 => &mut counts.ect0,
+            Ecn::Ect1 => &mut counts.ect1,
+            _ => &mut counts.ce,
+        };
+        *count = VarInt::from_u64(count.into_inner() + 1).expect("ecn count never exceed limit");
+    }
+
     fn gen_ack_frame_util(
         &self,
         (largest, recv_time): (u64, Instant),
@@ -102,27 +140,35 @@ impl RcvdPktRecords {
             .queue
             .iter_with_idx()
             .rev()
-            .skip_while(|(pktno, _)| *pktno > largest);
+            .skip_while(|(pktno, _)| *pktno > largest)
+            .peekable();
 
         // 注意assert中的next消耗掉一个单位，若要去掉assert，first_range还需减1
         assert!(
-            iter.by_ref()
-                .next()
+            iter.next()
                 .expect("largest in recv pkt records must be record")
                 .1
                 .is_received
         );
 
         let largest = VarInt::from_u64(largest).unwrap();
-        let delay = VarInt::from_u64(recv_time.elapsed().as_micros() as u64).unwrap();
+        let delay = recv_time.elapsed().as_micros() as u64 >> self.ack_delay_exponent;
+        let delay = VarInt::from_u64(delay).unwrap();
+        let ecn_len = self.ecn.map_or(0, |ecn| {
+            ecn.ect0.encoding_size() + ecn.ect1.encoding_size() + ecn.ce.encoding_size()
+        });
         // Minimum length with at least ACK frame type, largest, delay, range count, first_range (at least 1 byte for 0)
-        let min_len = 1 + largest.encoding_size() + delay.encoding_size() + 1 + 1;
+        let min_len = 1 + largest.encoding_size() + delay.encoding_size() + 1 + 1 + ecn_len;
         if capacity < min_len {
             return None;
         }
         capacity -= min_len;
 
-        let first_range = iter.by_ref().take_while(|(_, s)| s.is_received).count();
+        // 用next_if逐个消耗，不能用take_while，它会多消耗掉第一个不满足条件的记录
+        let mut count_while = |is_received: bool| {
+            core::iter::from_fn(|| iter.next_if(|(_, s)| s.is_received == is_received)).count()
+        };
+        let first_range = count_while(true);
         let mut ack_range_count = 0u64;
         let mut ranges = Vec::with_capacity(16);
         loop {
@@ -140,18 +186,17 @@ impl RcvdPktRecords {
             }
             capacity -= additional_count_encoding;
 
-            if iter.next().is_none() {
-                break;
-            }
-            let gap = iter.by_ref().take_while(|(_, s)| !s.is_received).count();
-
-            if iter.next().is_none() {
+            // 未收到的包数，至少为1，否则说明已经遍历完了
+            let gap = count_while(false);
+            // 紧接着的收到的包数，为0说明剩下的都是未收到的
+            let acked = count_while(true);
+            if gap == 0 || acked == 0 {
                 break;
             }
-            let acked = iter.by_ref().take_while(|(_, s)| s.is_received).count();
 
-            let gap = VarInt::try_from(gap).unwrap();
-            let acked = VarInt::try_from(acked).unwrap();
+            // Gap和ACK Range Length字段的编码值，都比实际的包数少1
+            let gap = VarInt::try_from(gap - 1).unwrap();
+            let acked = VarInt::try_from(acked - 1).unwrap();
             if capacity < gap.encoding_size() + acked.encoding_size() {
                 break;
             }
@@ -166,7 +211,7 @@ impl RcvdPktRecords {
             delay,
             first_range: unsafe { VarInt::from_u64_unchecked(first_range as u64) },
             ranges,
-            ecn: None,
+            ecn: self.ecn,
         })
     }
 
@@ -183,6 +228,21 @@ impl RcvdPktRecords {
         Some(buf_len - buf.len())
     }
 
+    fn read_path_ack_frame_util(
+        &self,
+        mut buf: &mut [u8],
+        path_id: VarInt,
+        largest: u64,
+        recv_time: Instant,
+    ) -> Option<usize> {
+        let buf_len = buf.len();
+        // PATH_ACK帧与ACK帧的类型同为1字节，多出的只有Path Identifier
+        let capacity = buf_len.checked_sub(path_id.encoding_size())?;
+        let ack = self.gen_ack_frame_util((largest, recv_time), capacity)?;
+        buf.put_frame(&PathAckFrame { path_id, ack });
+        Some(buf_len - buf.len())
+    }
+
     fn retire(&mut self, pn: u64) {
         if let Some(record) = self.queue.get_mut(pn) {
             record.inactivate();
@@ -243,11 +303,30 @@ impl ArcRcvdPktRecords {
         self.inner.write().unwrap().on_rcvd_pn(pn);
     }
 
+    /// Count the ECN codepoint of the datagram that carried a registered packet.
+    ///
+    /// The counts are fed back to the peer in the ack frames, once a packet marked with ECT(0),
+    /// ECT(1) or CE is received. See [ECN counts](https://www.rfc-editor.org/rfc/rfc9000.html#name-ecn-counts)
+    /// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
+    pub fn register_ecn(&self, ecn: Ecn) {
+        self.inner.write().unwrap().on_rcvd_ecn(ecn);
+    }
+
+    /// Set the ack_delay_exponent transport parameter advertised by us, the `Ack Delay` field of
+    /// the ack frames generated later is scaled by it. The default value is 3.
+    ///
+    /// See [ack_delay_exponent](https://www.rfc-editor.org/rfc/rfc9000.html#name-transport-parameter-definit)
+    /// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html) for more details.
+    pub fn set_ack_delay_exponent(&self, exponent: u8) {
+        self.inner.write().unwrap().ack_delay_exponent = exponent;
+    }
+
     /// Generate an ack frame which ack the received frames until `largest`.
     ///
     /// This method will write an ack frame into the `buf`. The `Ack Delay` field of the frame is
-    /// the argument `recv_time` as microsec, the `Largest Acknowledged` field of the frame is the
-    /// `largest` frame, the ranges in ack frame will not exceed `largest`.
+    /// the time elapsed since `recv_time`, in units of 2^ack_delay_exponent microseconds, the
+    /// `Largest Acknowledged` field of the frame is the `largest` frame, the ranges in ack frame
+    /// will not exceed `largest`.
     pub fn read_ack_frame_util(
         &self,
         buf: &mut [u8],
@@ -260,6 +339,23 @@ impl ArcRcvdPktRecords {
             .read_ack_frame_util(buf, largest, recv_time)
     }
 
+    /// The same as [`ArcRcvdPktRecords::read_ack_frame_util`], but writes a [`PathAckFrame`]
+    /// acknowledging the packets of the packet number space of the path `path_id`.
+    ///
+    /// It is used by the multipath extension, where each path has its own packet number space.
+    pub fn read_path_ack_frame_util(
+        &self,
+        buf: &mut [u8],
+        path_id: VarInt,
+        largest: u64,
+        recv_time: Instant,
+    ) -> Option<usize> {
+        self.inner
+            .read()
+            .unwrap()
+            .read_path_ack_frame_util(buf, path_id, largest, recv_time)
+    }
+
     pub fn write(&self) -> ArcRcvdPktRecordsWriter<'_> {
         ArcRcvdPktRecordsWriter {
             guard: self.inner.write().unwrap(),
@@ -294,7 +390,7 @@ mod tests {
     #[test]
     fn test_rcvd_pkt_records() {
         let records = ArcRcvdPktRecords::default();
-        assert_eq!(records.decode_pn(PacketNumber::encode(1, 0)), Ok(1));
+        assert_eq!(records.decode_pn(PacketNumber::encode(1, None)), Ok(1));
         assert_eq!(records.inner.read().unwrap().queue.len(), 0);
 
         records.register_pn(1);
@@ -315,7 +411,7 @@ mod tests {
             }
         );
 
-        assert_eq!(records.decode_pn(PacketNumber::encode(30, 0)), Ok(30));
+        assert_eq!(records.decode_pn(PacketNumber::encode(30, None)), Ok(30));
         records.register_pn(30);
         {
             let mut writer = records.write();
@@ -334,8 +430,192 @@ mod tests {
         assert_eq!(records.inner.read().unwrap().queue.len(), 21);
 
         assert_eq!(
-            records.decode_pn(PacketNumber::encode(9, 0)),
+            records.decode_pn(PacketNumber::encode(9, None)),
             Err(InvalidPacketNumber::TooOld)
         );
     }
+
+    #[test]
+    fn test_duplicate_packet() {
+        let records = ArcRcvdPktRecords::default();
+        let mut processed = vec![];
+        // 模拟收包流程：解码包号，解析帧，最后登记收到；重复的包在解析帧之前就被丢弃
+        for pn in [0, 1, 1, 2, 0, 2, 3] {
+            let Ok(pn) = records.decode_pn(PacketNumber::encode(pn, None)) else {
+                continue;
+            };
+            processed.push(pn);
+            records.register_pn(pn);
+        }
+        assert_eq!(processed, [0, 1, 2, 3]);
+        assert_eq!(
+            records.decode_pn(PacketNumber::encode(1, None)),
+            Err(InvalidPacketNumber::HasRcvd)
+        );
+    }
+
+    #[test]
+    fn test_packet_number_too_large() {
+        let records = ArcRcvdPktRecords::default();
+        records
+            .inner
+            .write()
+            .unwrap()
+            .queue
+            .reset_offset(VARINT_MAX - 1);
+        records.register_pn(VARINT_MAX);
+        // 期望的下一个包号已是2^62，只能解出超过上限的包号
+        assert_eq!(
+            records.decode_pn(PacketNumber::U8(0x00)),
+            Err(InvalidPacketNumber::TooLarge)
+        );
+        assert_eq!(
+            records.decode_pn(PacketNumber::U8(0xff)),
+            Err(InvalidPacketNumber::HasRcvd)
+        );
+        assert_eq!(
+            records.decode_pn(PacketNumber::U8(0xfe)),
+            Ok(VARINT_MAX - 1)
+        );
+    }
+
+    #[test]
+    fn test_ack_frame_ranges() {
+        let records = ArcRcvdPktRecords::default();
+        let now = Instant::now();
+        // 收到 0,1,2, 5,6, 9, 12,13,14
+        for pn in [0, 1, 2, 5, 6, 9, 12, 13, 14] {
+            records.register_pn(pn);
+        }
+        let ack_frame = records
+            .inner
+            .read()
+            .unwrap()
+            .gen_ack_frame_util((14, now), 100)
+            .unwrap();
+        assert_eq!(ack_frame.largest, VarInt::from_u32(14));
+        // first_range不含largest，即14之前连续收到的2个
+        assert_eq!(ack_frame.first_range, VarInt::from_u32(2));
+        // (gap, ack_range)都要减1编码：缺10,11 收9；缺7,8 收5,6；缺3,4 收0,1,2
+        let ranges = [(1, 0), (1, 1), (1, 2)]
+            .map(|(gap, acked)| (VarInt::from_u32(gap), VarInt::from_u32(acked)));
+        assert_eq!(ack_frame.ranges, ranges);
+
+        // ack的largest之后收到的包不在ack frame中
+        let ack_frame = records
+            .inner
+            .read()
+            .unwrap()
+            .gen_ack_frame_util((6, now), 100)
+            .unwrap();
+        assert_eq!(ack_frame.largest, VarInt::from_u32(6));
+        assert_eq!(ack_frame.first_range, VarInt::from_u32(1));
+        assert_eq!(ack_frame.ranges.len(), 1);
+
+        // 空间不足时，舍弃较旧的ack range
+        let ack_frame = records
+            .inner
+            .read()
+            .unwrap()
+            .gen_ack_frame_util((14, now), 8)
+            .unwrap();
+        assert_eq!(ack_frame.ranges.len(), 1);
+    }
+
+    #[test]
+    fn test_read_path_ack_frame() {
+        use bytes::Bytes;
+        use qbase::{
+            frame::{io::be_frame, Frame},
+            packet::{r#type::short::OneRtt, SpinBit, Type},
+        };
+
+        let records = ArcRcvdPktRecords::default();
+        let now = Instant::now();
+        for pn in [0, 1, 3] {
+            records.register_pn(pn);
+        }
+        let mut buf = [0u8; 32];
+        let n = records
+            .read_path_ack_frame_util(&mut buf, VarInt::from_u32(2), 3, now)
+            .unwrap();
+        let one_rtt = Type::Short(OneRtt(SpinBit::Zero));
+        let raw = Bytes::copy_from_slice(&buf[..n]);
+        let Ok((len, Frame::PathAck(frame), false)) = be_frame(&raw, one_rtt) else {
+            panic!("not a PATH_ACK frame");
+        };
+        assert_eq!(len, n);
+        assert_eq!(frame.path_id, VarInt::from_u32(2));
+        assert_eq!(frame.ack.largest, VarInt::from_u32(3));
+        // 收到0,1,3：first_range为0，缺2，再确认0,1
+        assert_eq!(frame.ack.first_range, VarInt::from_u32(0));
+        assert_eq!(
+            frame.ack.ranges,
+            vec![(VarInt::from_u32(0), VarInt::from_u32(1))]
+        );
+
+        // 空间不足以容纳Path Identifier时不写入
+        let mut buf = [0u8; 4];
+        assert_eq!(
+            records.read_path_ack_frame_util(&mut buf, VarInt::from_u32(2), 3, now),
+            None
+        );
+    }
+
+    #[test]
+    fn test_ack_delay_exponent() {
+        let records = ArcRcvdPktRecords::default();
+        records.register_pn(0);
+        let recv_time = Instant::now() - std::time::Duration::from_millis(8);
+        let ack_frame = records
+            .inner
+            .read()
+            .unwrap()
+            .gen_ack_frame_util((0, recv_time), 100)
+            .unwrap();
+        // 默认的exponent为3，即以8微秒为单位
+        let delay = ack_frame.delay.into_inner();
+        assert!((1000..1100).contains(&delay));
+
+        records.set_ack_delay_exponent(0);
+        let ack_frame = records
+            .inner
+            .read()
+            .unwrap()
+            .gen_ack_frame_util((0, recv_time), 100)
+            .unwrap();
+        assert!((8000..8800).contains(&ack_frame.delay.into_inner()));
+    }
+
+    #[test]
+    fn test_ecn_counts_in_ack_frame() {
+        let records = ArcRcvdPktRecords::default();
+        let now = Instant::now();
+        records.register_pn(0);
+        records.register_ecn(Ecn::NotEct);
+        let ack_frame = records
+            .inner
+            .read()
+            .unwrap()
+            .gen_ack_frame_util((0, now), 100);
+        assert_eq!(ack_frame.unwrap().ecn, None);
+
+        for (pn, ecn) in [(1, Ecn::Ect0), (2, Ecn::Ect0), (3, Ecn::Ce)] {
+            records.register_pn(pn);
+            records.register_ecn(ecn);
+        }
+        let ack_frame = records
+            .inner
+            .read()
+            .unwrap()
+            .gen_ack_frame_util((3, now), 100);
+        assert_eq!(
+            ack_frame.unwrap().ecn,
+            Some(EcnCounts {
+                ect0: VarInt::from_u32(2),
+                ect1: VarInt::from_u32(0),
+                ce: VarInt::from_u32(1),
+            })
+        );
+    }
 }
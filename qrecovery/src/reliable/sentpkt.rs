@@ -63,7 +63,8 @@ struct SentPktRecords<T> {
     queue: VecDeque<T>,
     // 记录着每个包的内容，其实是一个数字，该数字对应着queue中的record数量
     records: IndexDeque<SentPktState, VARINT_MAX>,
-    largest_acked_pktno: u64,
+    // 对端确认过的最大包号，尚未有包被确认时为None
+    largest_acked_pktno: Option<u64>,
 }
 
 impl<T: Clone> SentPktRecords<T> {
@@ -105,7 +106,7 @@ impl<T> SentPktRecords<T> {
         Self {
             queue: VecDeque::with_capacity(capacity * 4),
             records: IndexDeque::with_capacity(capacity),
-            largest_acked_pktno: 0,
+            largest_acked_pktno: None,
         }
     }
 
@@ -186,8 +187,8 @@ impl<T: Clone> RecvGuard<'_, T> {
     ///
     /// [`Largest Acknowleged`]: https://www.rfc-editor.org/rfc/rfc9000.html#name-ack-frames
     pub fn update_largest(&mut self, largest: u64) {
-        if largest > self.inner.largest_acked_pktno {
-            self.inner.largest_acked_pktno = largest;
+        if self.inner.largest_acked_pktno < Some(largest) {
+            self.inner.largest_acked_pktno = Some(largest);
         }
     }
 
@@ -1,10 +1,13 @@
 //! Types for sending data on a Stream.
 mod outgoing;
+mod priority;
 mod sender;
 mod sndbuf;
 mod writer;
 
 pub use outgoing::Outgoing;
-pub use sender::ArcSender;
+pub(crate) use priority::ArcReprioritized;
+pub use priority::Priority;
+pub use sender::{ArcSender, DEFAULT_SEND_BUFFER_SIZE};
 pub use sndbuf::SendBuf;
 pub use writer::Writer;
@@ -9,7 +9,10 @@ use qbase::{
     varint::{VarInt, VARINT_MAX},
 };
 
-use super::sender::{ArcSender, DataSentSender, Sender, SendingSender};
+use super::{
+    sender::{ArcSender, DataSentSender, Sender, SendingSender},
+    Priority,
+};
 
 /// An struct for protocol layer to manage the sending part of a stream.
 #[derive(Debug, Clone)]
@@ -92,6 +95,13 @@ impl<TX> Outgoing<TX> {
         Self(sender)
     }
 
+    /// Returns the priority of the stream, which is set by [`Writer::set_priority`].
+    ///
+    /// [`Writer::set_priority`]: super::Writer::set_priority
+    pub fn priority(&self) -> Priority {
+        self.0.priority().load()
+    }
+
     /// Update the sending window to `max_data_size`
     ///
     /// Callded when the  [`MAX_STREAM_DATA frame`] belonging to the stream is received.
@@ -167,40 +177,40 @@ impl<TX> Outgoing<TX> {
 
     /// Called when the [`STOP_SENDING frame`] sent by the peer is received.
     ///
-    /// If the stream has not been closed, the stream will be reset and then a [`RESET_STREAM frame`] will
-    /// be sent to the peer to reset the peer. in this case, the method will return `true`.
+    /// If the stream has not been closed, the stream will be reset with the peer's error code and
+    /// the final size of the stream, which are returned to build the [`RESET_STREAM frame`] to be
+    /// sent to the peer.
     ///
-    /// If the stream has closed, `false` will be returned, and the method will do nothing.
+    /// If the stream has closed, [`None`] will be returned, and the method will do nothing.
     ///
     /// [`STOP_SENDING frame`]: https://www.rfc-editor.org/rfc/rfc9000.html#name-stop_sending-frames
-    /// [`STREAM_RESET frame`]: https://www.rfc-editor.org/rfc/rfc9000.html#name-reset_stream-frames
-    pub fn on_stopped(&self, error_code: u64) -> bool {
+    /// [`RESET_STREAM frame`]: https://www.rfc-editor.org/rfc/rfc9000.html#name-reset_stream-frames
+    pub fn on_stopped(&self, error_code: u64) -> Option<ResetStreamError> {
         let mut sender = self.0.sender();
-        let inner = sender.deref_mut();
-        match inner {
-            Ok(sending_state) => match sending_state {
-                Sender::Ready(_) => {
-                    unreachable!("never send data before recv data");
-                }
-                Sender::Sending(s) => {
-                    let final_size = s.stop();
-                    *sending_state = Sender::ResetSent(ResetStreamError::new(
-                        VarInt::from_u64(error_code).expect("app error code must not exceed 2^62"),
-                        VarInt::from_u64(final_size).expect("final size must not exceed 2^62"),
-                    ));
-                    true
-                }
-                Sender::DataSent(s) => {
-                    let final_size = s.stop();
-                    *sending_state = Sender::ResetSent(ResetStreamError::new(
-                        VarInt::from_u64(error_code).expect("app error code must not exceed 2^62"),
-                        VarInt::from_u64(final_size).expect("final size must not exceed 2^62"),
-                    ));
-                    true
-                }
-                _ => false,
-            },
-            Err(_) => false,
+        let sending_state = sender.as_mut().ok()?;
+        // 对方创建的双向流，我方可能还未发送过数据，也会收到STOP_SENDING
+        let final_size = match sending_state {
+            Sender::Ready(s) => s.stop(),
+            Sender::Sending(s) => s.stop(),
+            Sender::DataSent(s) => s.stop(),
+            _ => return None,
+        };
+        let reset_stream_err = ResetStreamError::new(
+            VarInt::from_u64(error_code).expect("app error code must not exceed 2^62"),
+            VarInt::from_u64(final_size).expect("final size must not exceed 2^62"),
+        );
+        *sending_state = Sender::ResetSent(reset_stream_err);
+        Some(reset_stream_err)
+    }
+
+    /// Returns whether all the data written to the stream has been acknowledged by the peer,
+    /// or the stream has been reset, there is nothing left to be sent.
+    pub fn is_flushed(&self) -> bool {
+        match self.0.sender().as_ref() {
+            Ok(Sender::Ready(s)) => s.is_all_rcvd(),
+            Ok(Sender::Sending(s)) => s.is_all_rcvd(),
+            Ok(Sender::DataSent(s)) => s.is_all_rcvd(),
+            _ => true,
         }
     }
 
@@ -0,0 +1,144 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicU8, Ordering},
+    Arc,
+};
+
+/// The priority of a stream, which decides the order in which the data of the streams is sent.
+///
+/// It follows the [Extensible Prioritization Scheme for HTTP](https://www.rfc-editor.org/rfc/rfc9218.html):
+///
+/// - `urgency` ranges from 0 to 7, a lower value means a higher priority. The data of the streams
+///   with a lower urgency are always sent first.
+/// - `incremental` indicates whether the receiver can process the data incrementally. Among the
+///   streams with the same urgency, the non-incremental ones are sent one by one in the order of
+///   their stream IDs, and then the incremental ones share the bandwidth in a round-robin manner.
+///
+/// The default priority is urgency 3 and non-incremental.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Priority {
+    urgency: u8,
+    incremental: bool,
+}
+
+impl Priority {
+    /// The maximum value of the urgency.
+    pub const MAX_URGENCY: u8 = 7;
+
+    /// Create a new priority.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `urgency` is greater than [`Priority::MAX_URGENCY`].
+    pub fn new(urgency: u8, incremental: bool) -> Self {
+        assert!(
+            urgency <= Self::MAX_URGENCY,
+            "urgency must be in range 0..=7"
+        );
+        Self {
+            urgency,
+            incremental,
+        }
+    }
+
+    /// Returns the urgency, a lower value means a higher priority.
+    pub fn urgency(&self) -> u8 {
+        self.urgency
+    }
+
+    /// Returns whether the data of the stream can be processed incrementally.
+    pub fn is_incremental(&self) -> bool {
+        self.incremental
+    }
+
+    fn to_bits(self) -> u8 {
+        self.urgency << 1 | self.incremental as u8
+    }
+
+    fn from_bits(bits: u8) -> Self {
+        Self {
+            urgency: bits >> 1,
+            incremental: bits & 1 == 1,
+        }
+    }
+}
+
+impl Default for Priority {
+    fn default() -> Self {
+        Self::new(3, false)
+    }
+}
+
+/// The priority of a stream, shared by the [`Writer`] and the [`Outgoing`].
+///
+/// [`Writer`]: super::Writer
+/// [`Outgoing`]: super::Outgoing
+#[derive(Debug, Clone)]
+pub(super) struct ArcPriority {
+    bits: Arc<AtomicU8>,
+    // 同一连接的各流共享，优先级变更时通知发送调度重新排序
+    reprioritized: ArcReprioritized,
+}
+
+impl ArcPriority {
+    pub(super) fn new(reprioritized: ArcReprioritized) -> Self {
+        Self {
+            bits: Arc::new(AtomicU8::new(Priority::default().to_bits())),
+            reprioritized,
+        }
+    }
+
+    pub(super) fn load(&self) -> Priority {
+        Priority::from_bits(self.bits.load(Ordering::Relaxed))
+    }
+
+    pub(super) fn store(&self, priority: Priority) {
+        // 先更新优先级再通知，调度者取走通知后读到的一定是新的优先级
+        self.bits.store(priority.to_bits(), Ordering::Release);
+        self.reprioritized.mark();
+    }
+}
+
+/// 连接内任一流的优先级发生变更的标记，发送调度只在变更后才重新排序各流
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ArcReprioritized(Arc<AtomicBool>);
+
+impl ArcReprioritized {
+    fn mark(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    /// 取走标记，返回此前是否有流的优先级发生了变更
+    pub(crate) fn take(&self) -> bool {
+        self.0.swap(false, Ordering::AcqRel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_priority() {
+        let reprioritized = ArcReprioritized::default();
+        let priority = ArcPriority::new(reprioritized.clone());
+        assert_eq!(priority.load(), Priority::default());
+        assert!(!reprioritized.take());
+        assert_eq!(priority.load().urgency(), 3);
+        assert!(!priority.load().is_incremental());
+
+        for urgency in 0..=Priority::MAX_URGENCY {
+            for incremental in [false, true] {
+                priority.store(Priority::new(urgency, incremental));
+                assert_eq!(priority.load(), Priority::new(urgency, incremental));
+                assert!(reprioritized.take());
+                assert!(!reprioritized.take());
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_invalid_urgency() {
+        Priority::new(8, false);
+    }
+}
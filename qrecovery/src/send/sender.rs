@@ -13,7 +13,37 @@ use qbase::{
     varint::VarInt,
 };
 
-use super::sndbuf::SendBuf;
+use super::{
+    priority::{ArcPriority, ArcReprioritized},
+    sndbuf::SendBuf,
+};
+
+/// 可写入的数据量，同时受流量控制窗口与发送缓冲区上限的限制
+fn writable_size(sndbuf: &SendBuf, max_stream_data: u64, max_buffered: usize) -> usize {
+    let window = max_stream_data.saturating_sub(sndbuf.written());
+    let space = max_buffered.saturating_sub(sndbuf.len());
+    window.min(space as u64) as usize
+}
+
+/// 流量控制窗口增大、缓冲区上限放宽或数据被确认后，若又可写入了，唤醒阻塞的写入
+fn wake_if_writable(
+    sndbuf: &SendBuf,
+    max_stream_data: u64,
+    max_buffered: usize,
+    writable_waker: &mut Option<Waker>,
+) {
+    if writable_size(sndbuf, max_stream_data, max_buffered) > 0 {
+        if let Some(waker) = writable_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// The default maximum number of bytes buffered in a send stream, which have been written but
+/// not yet acknowledged by the peer, see [`Writer::set_send_buffer_size`].
+///
+/// [`Writer::set_send_buffer_size`]: crate::send::Writer::set_send_buffer_size
+pub const DEFAULT_SEND_BUFFER_SIZE: usize = 1 << 20;
 
 /// The "Ready" state represents a newly created stream that is able to accept data from the application.
 /// Stream data might be buffered in this state in preparation for sending.
@@ -28,6 +58,8 @@ pub struct ReadySender<TX> {
     reset_frame_tx: TX,
     writable_waker: Option<Waker>,
     max_stream_data: u64,
+    // 发送缓冲区中未被确认的数据量上限
+    max_buffered: usize,
 }
 
 impl<TX> ReadySender<TX>
@@ -36,7 +68,7 @@ where
 {
     /// 应用层使用，取消发送流
     pub(super) fn cancel(&mut self, err_code: u64) -> ResetStreamError {
-        let final_size = self.sndbuf.written();
+        let final_size = self.sndbuf.sent();
         let reset_stream_err = ResetStreamError::new(
             VarInt::from_u64(err_code).expect("app error code must not exceed 2^62"),
             VarInt::from_u64(final_size).expect("final size must not exceed 2^62"),
@@ -57,6 +89,7 @@ impl<TX> ReadySender<TX> {
             reset_frame_tx,
             writable_waker: None,
             max_stream_data: buf_size,
+            max_buffered: DEFAULT_SEND_BUFFER_SIZE,
         }
     }
 
@@ -79,22 +112,34 @@ impl<TX> ReadySender<TX> {
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<io::Result<usize>> {
-        let stream_data = self.sndbuf.written();
-        if stream_data < self.max_stream_data {
-            let n = std::cmp::min((self.max_stream_data - stream_data) as usize, buf.len());
-            Poll::Ready(Ok(self.sndbuf.write(&buf[..n])))
+        let n = writable_size(&self.sndbuf, self.max_stream_data, self.max_buffered);
+        if n > 0 {
+            Poll::Ready(Ok(self.sndbuf.write(&buf[..n.min(buf.len())])))
         } else {
             self.writable_waker = Some(cx.waker().clone());
             Poll::Pending
         }
     }
 
+    pub(super) fn set_max_buffered(&mut self, max_buffered: usize) {
+        self.max_buffered = max_buffered;
+        wake_if_writable(
+            &self.sndbuf,
+            self.max_stream_data,
+            self.max_buffered,
+            &mut self.writable_waker,
+        );
+    }
+
     pub(super) fn update_window(&mut self, max_stream_data: u64) {
         if max_stream_data > self.max_stream_data {
             self.max_stream_data = max_stream_data;
-            if let Some(waker) = self.writable_waker.take() {
-                waker.wake();
-            }
+            wake_if_writable(
+                &self.sndbuf,
+                self.max_stream_data,
+                self.max_buffered,
+                &mut self.writable_waker,
+            );
         }
     }
 
@@ -112,6 +157,10 @@ impl<TX> ReadySender<TX> {
         self.shutdown_waker.is_some()
     }
 
+    pub(super) fn is_all_rcvd(&self) -> bool {
+        self.sndbuf.is_all_rcvd()
+    }
+
     pub(super) fn wake_all(&mut self) {
         if let Some(waker) = self.writable_waker.take() {
             waker.wake();
@@ -123,6 +172,12 @@ impl<TX> ReadySender<TX> {
             waker.wake();
         }
     }
+
+    /// 传输层使用，返回最终大小，即发送过的数据的最大偏移
+    pub(super) fn stop(&mut self) -> u64 {
+        self.wake_all();
+        self.sndbuf.sent()
+    }
 }
 
 /// 状态转换，ReaderSender => SendingSender
@@ -136,6 +191,7 @@ impl<TX: Clone> From<&mut ReadySender<TX>> for SendingSender<TX> {
             reset_frame_tx: value.reset_frame_tx.clone(),
             writable_waker: value.writable_waker.take(),
             max_stream_data: value.max_stream_data,
+            max_buffered: value.max_buffered,
         }
     }
 }
@@ -154,6 +210,20 @@ impl<TX: Clone> From<&mut ReadySender<TX>> for DataSentSender<TX> {
     }
 }
 
+/// 状态转换，ReaderSender => DataSentSender，Writer被丢弃时使用
+impl<TX> From<ReadySender<TX>> for DataSentSender<TX> {
+    fn from(value: ReadySender<TX>) -> Self {
+        DataSentSender {
+            stream_id: value.stream_id,
+            sndbuf: value.sndbuf,
+            flush_waker: value.flush_waker,
+            shutdown_waker: value.shutdown_waker,
+            reset_frame_tx: value.reset_frame_tx,
+            fin_state: FinState::None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct SendingSender<TX> {
     stream_id: StreamId,
@@ -163,6 +233,8 @@ pub struct SendingSender<TX> {
     reset_frame_tx: TX,
     writable_waker: Option<Waker>,
     max_stream_data: u64,
+    // 发送缓冲区中未被确认的数据量上限
+    max_buffered: usize,
 }
 
 type StreamData<'s> = (u64, bool, (&'s [u8], &'s [u8]), bool);
@@ -172,7 +244,7 @@ where
     TX: SendFrame<ResetStreamFrame>,
 {
     pub(super) fn cancel(&mut self, err_code: u64) -> ResetStreamError {
-        let final_size = self.sndbuf.written();
+        let final_size = self.sndbuf.sent();
         let reset_stream_err = ResetStreamError::new(
             VarInt::from_u64(err_code).expect("app error code must not exceed 2^62"),
             VarInt::from_u64(final_size).expect("final size must not exceed 2^62"),
@@ -189,23 +261,35 @@ impl<TX> SendingSender<TX> {
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<io::Result<usize>> {
-        let written = self.sndbuf.written();
-        if written < self.max_stream_data {
-            let n = std::cmp::min((self.max_stream_data - written) as usize, buf.len());
-            Poll::Ready(Ok(self.sndbuf.write(&buf[..n])))
+        let n = writable_size(&self.sndbuf, self.max_stream_data, self.max_buffered);
+        if n > 0 {
+            Poll::Ready(Ok(self.sndbuf.write(&buf[..n.min(buf.len())])))
         } else {
             self.writable_waker = Some(cx.waker().clone());
             Poll::Pending
         }
     }
 
+    pub(super) fn set_max_buffered(&mut self, max_buffered: usize) {
+        self.max_buffered = max_buffered;
+        wake_if_writable(
+            &self.sndbuf,
+            self.max_stream_data,
+            self.max_buffered,
+            &mut self.writable_waker,
+        );
+    }
+
     /// 传输层使用
     pub(super) fn update_window(&mut self, max_stream_data: u64) {
         if max_stream_data > self.max_stream_data {
             self.max_stream_data = max_stream_data;
-            if let Some(waker) = self.writable_waker.take() {
-                waker.wake();
-            }
+            wake_if_writable(
+                &self.sndbuf,
+                self.max_stream_data,
+                self.max_buffered,
+                &mut self.writable_waker,
+            );
         }
     }
 
@@ -220,6 +304,13 @@ impl<TX> SendingSender<TX> {
 
     pub(super) fn on_data_acked(&mut self, range: &Range<u64>) {
         self.sndbuf.on_data_acked(range);
+        // 被确认的数据移出了发送缓冲区，若因缓冲区满而阻塞的写入可以继续，唤醒之
+        wake_if_writable(
+            &self.sndbuf,
+            self.max_stream_data,
+            self.max_buffered,
+            &mut self.writable_waker,
+        );
         if self.sndbuf.is_all_rcvd() {
             if let Some(waker) = self.flush_waker.take() {
                 waker.wake();
@@ -234,6 +325,10 @@ impl<TX> SendingSender<TX> {
         self.sndbuf.may_loss_data(range)
     }
 
+    pub(super) fn is_all_rcvd(&self) -> bool {
+        self.sndbuf.is_all_rcvd()
+    }
+
     pub(super) fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
         if self.sndbuf.is_all_rcvd() {
             Poll::Ready(Ok(()))
@@ -260,11 +355,11 @@ impl<TX> SendingSender<TX> {
         }
     }
 
-    /// 传输层使用
+    /// 传输层使用，返回最终大小，即发送过的数据的最大偏移
     pub(super) fn stop(&mut self) -> u64 {
         self.wake_all();
         // Actually, these remaining data is not acked and will not be acked
-        self.sndbuf.written()
+        self.sndbuf.sent()
     }
 }
 
@@ -282,6 +377,20 @@ impl<TX: Clone> From<&mut SendingSender<TX>> for DataSentSender<TX> {
     }
 }
 
+/// 状态转换，SendingSender => DataSentSender，Writer被丢弃时使用
+impl<TX> From<SendingSender<TX>> for DataSentSender<TX> {
+    fn from(value: SendingSender<TX>) -> Self {
+        DataSentSender {
+            stream_id: value.stream_id,
+            sndbuf: value.sndbuf,
+            flush_waker: value.flush_waker,
+            shutdown_waker: value.shutdown_waker,
+            reset_frame_tx: value.reset_frame_tx,
+            fin_state: FinState::None,
+        }
+    }
+}
+
 /// 表示发送fin标志位的状态。当所有数据都发完但没发过fin的Stream帧时，也应发一个携带fin标志位的空Stream帧
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 enum FinState {
@@ -306,7 +415,7 @@ where
     TX: SendFrame<ResetStreamFrame>,
 {
     pub(super) fn cancel(&mut self, err_code: u64) -> ResetStreamError {
-        let final_size = self.sndbuf.written();
+        let final_size = self.sndbuf.sent();
         let reset_stream_err = ResetStreamError::new(
             VarInt::from_u64(err_code).expect("app error code must not exceed 2^62"),
             VarInt::from_u64(final_size).expect("final size must not exceed 2^62"),
@@ -396,7 +505,7 @@ impl<TX> DataSentSender<TX> {
     pub(super) fn stop(&mut self) -> u64 {
         self.wake_all();
         // Actually, these remaining data is not acked and will not be acked
-        self.sndbuf.written()
+        self.sndbuf.sent()
     }
 }
 
@@ -414,6 +523,15 @@ impl<TX> Sender<TX> {
     pub fn new(stream_id: StreamId, buf_size: u64, reset_frame_tx: TX) -> Self {
         Sender::Ready(ReadySender::new(stream_id, buf_size, reset_frame_tx))
     }
+
+    /// 应用层不再写入新数据，进入DataSent状态，已写入的数据发完后发送fin
+    pub(super) fn finish(&mut self) {
+        *self = match std::mem::replace(self, Sender::DataRcvd) {
+            Sender::Ready(s) => Sender::DataSent(s.into()),
+            Sender::Sending(s) => Sender::DataSent(s.into()),
+            other => other,
+        };
+    }
 }
 
 /// The internal state representations of [`Outgoing`] and [`Writer`].
@@ -427,16 +545,28 @@ impl<TX> Sender<TX> {
 /// [`Outgoing`]: super::Outgoing
 /// [`Writer`]: super::Writer
 #[derive(Debug, Clone)]
-pub struct ArcSender<TX>(Arc<Mutex<Result<Sender<TX>, Error>>>);
+pub struct ArcSender<TX> {
+    sender: Arc<Mutex<Result<Sender<TX>, Error>>>,
+    // 优先级独立于发送状态，调度时无需锁定发送状态
+    priority: ArcPriority,
+}
 
 impl<TX> ArcSender<TX> {
     #[doc(hidden)]
-    pub(crate) fn new(stream_id: StreamId, buf_size: u64, reset_frame_tx: TX) -> Self {
-        ArcSender(Arc::new(Mutex::new(Ok(Sender::new(
-            stream_id,
-            buf_size,
-            reset_frame_tx,
-        )))))
+    pub(crate) fn new(
+        stream_id: StreamId,
+        buf_size: u64,
+        reset_frame_tx: TX,
+        reprioritized: ArcReprioritized,
+    ) -> Self {
+        ArcSender {
+            sender: Arc::new(Mutex::new(Ok(Sender::new(
+                stream_id,
+                buf_size,
+                reset_frame_tx,
+            )))),
+            priority: ArcPriority::new(reprioritized),
+        }
     }
 }
 
@@ -449,6 +579,10 @@ impl<TX> ArcSender<TX> {
     }
 
     pub(super) fn sender(&self) -> MutexGuard<Result<Sender<TX>, Error>> {
-        self.0.lock().unwrap()
+        self.sender.lock().unwrap()
+    }
+
+    pub(super) fn priority(&self) -> &ArcPriority {
+        &self.priority
     }
 }
@@ -524,6 +524,8 @@ pub struct SendBuf {
     // 写入数据的环形队列，与接收队列不同的是，它是连续的
     data: VecDeque<u8>,
     state: BufMap,
+    // 发送过的数据的最大偏移，即对方可能已经收到的数据量
+    sent: u64,
 }
 
 impl SendBuf {
@@ -533,6 +535,7 @@ impl SendBuf {
             offset: 0,
             data: VecDeque::with_capacity(n),
             state: BufMap::default(),
+            sent: 0,
         }
     }
 
@@ -562,11 +565,25 @@ impl SendBuf {
         self.data.is_empty()
     }
 
+    /// Return the number of bytes in the [`SendBuf`], which have been written but not yet
+    /// acknowledged by the peer.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
     /// Return the total length of data that has been cumulatively written to the send buffer in the past.
     pub fn written(&self) -> u64 {
         self.state.1
     }
 
+    /// Return the largest offset of the data that has ever been picked up to be sent.
+    ///
+    /// Only the data sent consumes the flow control credit of the peer, the final size of a reset
+    /// stream is this rather than [`SendBuf::written`].
+    pub fn sent(&self) -> u64 {
+        self.sent
+    }
+
     /// Return the number of bytes can be written without reallocation.
     pub fn remaining_mut(&self) -> usize {
         self.data.capacity() - self.data.len()
@@ -600,17 +617,15 @@ impl SendBuf {
     where
         P: Fn(u64) -> Option<usize>,
     {
-        self.state
-            .pick(predicate, flow_limit)
-            .map(|(range, is_fresh)| {
-                let start = (range.start - self.offset) as usize;
-                let end = (range.end - self.offset) as usize;
-
-                let (l, r) = self.data.as_slices();
-                let s1 = &l[start.min(l.len())..l.len().min(end)];
-                let s2 = &r[start.saturating_sub(l.len())..end.saturating_sub(l.len())];
-                (range.start, is_fresh, (s1, s2))
-            })
+        let (range, is_fresh) = self.state.pick(predicate, flow_limit)?;
+        self.sent = self.sent.max(range.end);
+        let start = (range.start - self.offset) as usize;
+        let end = (range.end - self.offset) as usize;
+
+        let (l, r) = self.data.as_slices();
+        let s1 = &l[start.min(l.len())..l.len().min(end)];
+        let s2 = &r[start.saturating_sub(l.len())..end.saturating_sub(l.len())];
+        Some((range.start, is_fresh, (s1, s2)))
     }
 
     /// Called when the `range` of data sent is acknowledged by the peer.
@@ -8,7 +8,10 @@ use std::{
 use qbase::frame::{ResetStreamFrame, SendFrame};
 use tokio::io::AsyncWrite;
 
-use super::sender::{ArcSender, Sender};
+use super::{
+    sender::{ArcSender, Sender},
+    Priority,
+};
 use crate::send::sender::DataSentSender;
 
 /// The writer part of a QUIC stream.
@@ -20,12 +23,21 @@ use crate::send::sender::DataSentSender;
 /// The amount of data that can be sent is limited by flow control. The [`write`] call will be blocked
 /// if the amount of data written reaches the flow control limit.
 ///
+/// The data written is buffered until it is acknowledged by the peer, the size of the buffer can be
+/// limited by [`set_send_buffer_size`], the [`write`] call will also be blocked once the buffer is
+/// full, until the peer acknowledges some of the data.
+///
 /// The [`flush`] and [`shutdown`] calls will be blocked until all data written to [`Writer`] has
 /// been sent and acknowledged by the peer.
 ///
 /// # Note
 ///
-/// The stream must be cancelled or shutdowned before the [`Writer`] dropped.
+/// The [`Writer`] is independent of the [`Reader`] of the same bidirectional stream, they can be
+/// moved to different tasks, and closing one of them does not affect the other.
+///
+/// Dropping the [`Writer`] without [`shutdown`] or [`cancel`] finishes the stream gracefully: the
+/// data written is still sent, followed by the FIN, but the acknowledgment is not waited for. Call
+/// [`shutdown`] to know whether all the data has been received by the peer.
 ///
 /// Call [`shutdown`] means that there are no more new data will been written to the stream. If all
 /// of the data written to the stream has been sent and acknowledged by the peer, the stream will be
@@ -59,14 +71,50 @@ use crate::send::sender::DataSentSender;
 /// # }
 /// ```
 ///
+/// [`Reader`]: crate::recv::Reader
 /// [`write`]: tokio::io::AsyncWriteExt::write
 /// [`flush`]: tokio::io::AsyncWriteExt::flush
 /// [`shutdown`]: tokio::io::AsyncWriteExt::shutdown
 /// [`cancel`]: Writer::cancel
+/// [`set_send_buffer_size`]: Writer::set_send_buffer_size
 /// [`STOP_SENDING frame`]: https://www.rfc-editor.org/rfc/rfc9000.html#name-stop_sending-frames
 #[derive(Debug)]
 pub struct Writer<TX>(pub(crate) ArcSender<TX>);
 
+impl<TX> Writer<TX> {
+    /// Set the priority of the stream, which takes effect on the data sent afterwards.
+    ///
+    /// The data of the streams with a higher priority are sent first, read [`Priority`] for more
+    /// details. The connection-level frames, such as ACK frames and control frames, are always sent
+    /// before the stream data, regardless of the priority.
+    pub fn set_priority(&self, priority: Priority) {
+        self.0.priority().store(priority);
+    }
+
+    /// Returns the priority of the stream.
+    pub fn priority(&self) -> Priority {
+        self.0.priority().load()
+    }
+
+    /// Set the maximum number of bytes buffered in the stream, which have been written but not
+    /// yet acknowledged by the peer.
+    ///
+    /// Once the buffer is full, the [`write`] call will be blocked until the peer acknowledges
+    /// some of the data, even if the flow control allows more, so the memory does not grow
+    /// unboundedly when the peer or the network is slow. By default, the buffer holds at most
+    /// [`DEFAULT_SEND_BUFFER_SIZE`] bytes.
+    ///
+    /// [`write`]: tokio::io::AsyncWriteExt::write
+    /// [`DEFAULT_SEND_BUFFER_SIZE`]: crate::send::DEFAULT_SEND_BUFFER_SIZE
+    pub fn set_send_buffer_size(&self, size: usize) {
+        match self.0.sender().deref_mut() {
+            Ok(Sender::Ready(s)) => s.set_max_buffered(size),
+            Ok(Sender::Sending(s)) => s.set_max_buffered(size),
+            _ => {}
+        }
+    }
+}
+
 impl<TX> Writer<TX>
 where
     TX: SendFrame<ResetStreamFrame>,
@@ -205,15 +253,105 @@ impl<TX: Clone> AsyncWrite for Writer<TX> {
 impl<TX> Drop for Writer<TX> {
     fn drop(&mut self) {
         let mut sender = self.0.sender();
-        let inner = sender.deref_mut();
-        if let Ok(sending_state) = inner {
-            debug_assert!(
-                matches!(
-                    sending_state,
-                    Sender::DataRcvd | Sender::ResetSent(_) | Sender::ResetRcvd(_)
-                ),
-                "SendingStream must be shutdowned before dropped!"
-            );
+        // 未结束的流，发送完已写入的数据后发送fin，不影响双向流的接收方向
+        if let Ok(sending_state) = sender.deref_mut() {
+            sending_state.finish();
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    use futures::task::{waker, ArcWake};
+    use qbase::{sid::StreamId, varint::VarInt};
+
+    use super::*;
+    use crate::send::{Outgoing, DEFAULT_SEND_BUFFER_SIZE};
+
+    #[derive(Default)]
+    struct Woken(AtomicBool);
+
+    impl ArcWake for Woken {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.0.store(true, Ordering::Release);
+        }
+    }
+
+    #[test]
+    fn test_send_buffer_backpressure() {
+        let sid = StreamId::from(VarInt::from_u32(2));
+        let sender = ArcSender::new(sid, 1000, (), Default::default());
+        let mut writer = Writer(sender.clone());
+        let outgoing = Outgoing::new(sender);
+        writer.set_send_buffer_size(100);
+
+        let woken = Arc::new(Woken::default());
+        let waker = waker(woken.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        // 流量控制允许1000字节，但发送缓冲区只能容纳100字节
+        let data = [0u8; 300];
+        assert!(matches!(
+            Pin::new(&mut writer).poll_write(&mut cx, &data),
+            Poll::Ready(Ok(100))
+        ));
+        assert!(Pin::new(&mut writer)
+            .poll_write(&mut cx, &data)
+            .is_pending());
+
+        // 数据发出但未被确认，仍占用缓冲区
+        let mut buf = [0u8; 1200];
+        let (frame, len, ..) = outgoing.try_read(sid, &mut buf, usize::MAX, 1000).unwrap();
+        assert_eq!(len, 100);
+        assert!(Pin::new(&mut writer)
+            .poll_write(&mut cx, &data)
+            .is_pending());
+        assert!(!woken.0.load(Ordering::Acquire));
+
+        // 对端确认了部分数据，阻塞的写入被唤醒，可以继续写入
+        outgoing.on_data_acked(&(frame.offset()..frame.offset() + 60), false);
+        assert!(woken.0.load(Ordering::Acquire));
+        assert!(matches!(
+            Pin::new(&mut writer).poll_write(&mut cx, &data),
+            Poll::Ready(Ok(60))
+        ));
+
+        // 放宽缓冲区上限，写入受流量控制限制
+        writer.set_send_buffer_size(usize::MAX);
+        assert!(matches!(
+            Pin::new(&mut writer).poll_write(&mut cx, &[0u8; 1000]),
+            Poll::Ready(Ok(840))
+        ));
+        assert!(Pin::new(&mut writer)
+            .poll_write(&mut cx, &data)
+            .is_pending());
+
+        _ = outgoing.on_stopped(0);
+    }
+
+    #[test]
+    fn test_default_send_buffer_size() {
+        let sid = StreamId::from(VarInt::from_u32(2));
+        // 流量控制窗口足够大时，发送缓冲区默认也是有上限的
+        let sender = ArcSender::new(sid, 4 << 20, (), Default::default());
+        let mut writer = Writer(sender);
+
+        let woken = Arc::new(Woken::default());
+        let waker = waker(woken.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        let data = vec![0u8; DEFAULT_SEND_BUFFER_SIZE + 1];
+        assert!(matches!(
+            Pin::new(&mut writer).poll_write(&mut cx, &data),
+            Poll::Ready(Ok(n)) if n == DEFAULT_SEND_BUFFER_SIZE
+        ));
+        assert!(Pin::new(&mut writer)
+            .poll_write(&mut cx, &data)
+            .is_pending());
+    }
+}
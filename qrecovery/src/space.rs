@@ -108,7 +108,7 @@ mod tests {
         let space = InitialSpace::with_capacity(10);
         // assert_eq!(AsRef::<ArcSentPktRecords<_>>::as_ref(&space).lock_guard().len(), 0);
         assert_eq!(
-            AsRef::<ArcRcvdPktRecords>::as_ref(&space).decode_pn(PacketNumber::encode(0, 0)),
+            AsRef::<ArcRcvdPktRecords>::as_ref(&space).decode_pn(PacketNumber::encode(0, None)),
             Ok(0)
         );
     }
@@ -30,6 +30,7 @@ use deref_derive::Deref;
 pub use listener::{AcceptBiStream, AcceptUniStream};
 use qbase::{
     error::Error,
+    flow::ArcRecvController,
     frame::{ReceiveFrame, SendFrame, StreamCtlFrame, StreamFrame},
     param::Parameters,
     sid::{ControlConcurrency, Role, StreamId},
@@ -80,13 +81,17 @@ where
     /// Creates a new instance of [`DataStreams`].
     ///
     /// The `ctrl_frames` is the frame sender, read [`raw::DataStreams`] for more details.
+    ///
+    /// The `flow_ctrl` is the connection-level flow controller in the receiving direction, it is
+    /// updated when the application reads data from the streams.
     pub fn new(
         role: Role,
         local_params: &Parameters,
         strategy: Box<dyn ControlConcurrency>,
         ctrl_frames: TX,
+        flow_ctrl: ArcRecvController,
     ) -> Self {
-        let raw = raw::DataStreams::new(role, local_params, strategy, ctrl_frames);
+        let raw = raw::DataStreams::new(role, local_params, strategy, ctrl_frames, flow_ctrl);
 
         Self(Arc::new(raw))
     }
@@ -120,6 +125,13 @@ where
     pub fn accept_uni(&self) -> AcceptUniStream<Ext<TX>> {
         self.0.accept_uni()
     }
+
+    /// Wait for all the data written to the streams to be acknowledged by the peer, read
+    /// [`raw::DataStreams::poll_flushed`] for more details.
+    #[inline]
+    pub fn flushed(&self) -> Flushed<TX> {
+        Flushed { inner: self }
+    }
 }
 
 impl<TX> ReceiveFrame<StreamCtlFrame> for DataStreams<TX>
@@ -198,3 +210,25 @@ where
         self.inner.poll_open_uni_stream(cx, self.snd_wnd_size)
     }
 }
+
+/// Future to wait for all the data written to the streams to be acknowledged by the peer.
+///
+/// It is used to close the connection gracefully, if a connection error occurred, the future will
+/// complete immediately.
+pub struct Flushed<'d, TX>
+where
+    TX: SendFrame<StreamCtlFrame> + Clone + Send + 'static,
+{
+    inner: &'d raw::DataStreams<TX>,
+}
+
+impl<TX> Future for Flushed<'_, TX>
+where
+    TX: SendFrame<StreamCtlFrame> + Clone + Send + 'static,
+{
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.inner.poll_flushed(cx)
+    }
+}
@@ -9,7 +9,10 @@ use std::{
 use deref_derive::{Deref, DerefMut};
 use qbase::{error::Error as QuicError, sid::StreamId};
 
-use crate::{recv::Incoming, send::Outgoing};
+use crate::{
+    recv::Incoming,
+    send::{Outgoing, Priority},
+};
 
 #[derive(Debug, Clone)]
 pub(super) struct IOState(Arc<AtomicU8>);
@@ -48,6 +51,12 @@ pub(super) struct Output<TX> {
     #[deref]
     pub(super) outgoings: BTreeMap<StreamId, (Outgoing<TX>, IOState)>,
     pub(super) cursor: Option<(StreamId, usize)>,
+    // 发送调度的顺序，按(urgency, incremental, sid)升序，在流加入、移除以及优先级变更时维护
+    pub(super) schedule: Vec<(Priority, StreamId)>,
+}
+
+fn schedule_key((priority, sid): &(Priority, StreamId)) -> (u8, bool, StreamId) {
+    (priority.urgency(), priority.is_incremental(), *sid)
 }
 
 impl<TX> Output<TX> {
@@ -55,7 +64,30 @@ impl<TX> Output<TX> {
         Self {
             outgoings: BTreeMap::default(),
             cursor: None,
+            schedule: Vec::new(),
+        }
+    }
+
+    fn insert(&mut self, sid: StreamId, outgoing: Outgoing<TX>, io_state: IOState) {
+        let entry = (outgoing.priority(), sid);
+        let key = schedule_key(&entry);
+        let index = self.schedule.partition_point(|e| schedule_key(e) < key);
+        self.schedule.insert(index, entry);
+        self.outgoings.insert(sid, (outgoing, io_state));
+    }
+
+    pub(super) fn remove(&mut self, sid: &StreamId) -> Option<(Outgoing<TX>, IOState)> {
+        let removed = self.outgoings.remove(sid)?;
+        self.schedule.retain(|(_, s)| s != sid);
+        Some(removed)
+    }
+
+    /// 有流的优先级变更后，按新的优先级重新排序
+    pub(super) fn reschedule(&mut self) {
+        for (priority, sid) in self.schedule.iter_mut() {
+            *priority = self.outgoings[sid].0.priority();
         }
+        self.schedule.sort_by_key(schedule_key);
     }
 }
 
@@ -89,7 +121,7 @@ pub(super) struct ArcOutputGuard<'a, TX>(MutexGuard<'a, Result<Output<TX>, QuicE
 impl<TX> ArcOutputGuard<'_, TX> {
     pub(super) fn insert(&mut self, sid: StreamId, outgoing: Outgoing<TX>, io_state: IOState) {
         match self.0.as_mut() {
-            Ok(set) => set.insert(sid, (outgoing, io_state)),
+            Ok(set) => set.insert(sid, outgoing, io_state),
             Err(e) => unreachable!("output is invalid: {e}"),
         };
     }
@@ -1,7 +1,13 @@
-use std::task::{ready, Context, Poll};
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    task::{ready, Context, Poll},
+};
+
+use futures::task::AtomicWaker;
 
 use qbase::{
     error::{Error as QuicError, ErrorKind},
+    flow::ArcRecvController,
     frame::{
         BeFrame, FrameType, ReceiveFrame, ResetStreamFrame, SendFrame, StreamCtlFrame, StreamFrame,
         STREAM_FRAME_MAX_ENCODING_SIZE,
@@ -11,17 +17,16 @@ use qbase::{
         remote_sid::{AcceptSid, ExceedLimitError},
         ControlConcurrency, Dir, Role, StreamId, StreamIds,
     },
-    varint::VarInt,
 };
 
 use super::{
-    io::{ArcInput, ArcOutput, IOState},
+    io::{ArcInput, ArcOutput, IOState, Output},
     listener::{AcceptBiStream, AcceptUniStream, ArcListener},
     Ext,
 };
 use crate::{
     recv::{ArcRecver, Incoming, Reader},
-    send::{ArcSender, Outgoing, Writer},
+    send::{ArcReprioritized, ArcSender, Outgoing, Writer},
 };
 
 /// Manage all streams in the connection, send and receive frames, handle frame loss, and acknowledge.
@@ -58,7 +63,7 @@ use crate::{
 /// | [`recv_stream_control`] ([`RESET_STREAM frame`])         | [`Incoming::recv_reset`]                           |
 /// | [`recv_stream_control`] ([`STOP_SENDING frame`])         | [`Outgoing::on_stopped`]                           |
 /// | [`recv_stream_control`] ([`MAX_STREAM_DATA frame`])      | [`Outgoing::update_window`]                        |
-/// | [`recv_stream_control`] ([`STREAM_DATA_BLOCKED frame`])  | none(the frame will be ignored)                    |
+/// | [`recv_stream_control`] ([`STREAM_DATA_BLOCKED frame`])  | [`Incoming::recv_data_blocked`]                    |
 /// | [`recv_stream_control`] ([`MAX_STREAMS frame`])          | [`ArcLocalStreamIds::recv_max_streams_frame`]      |
 /// | [`recv_stream_control`] ([`STREAMS_BLOCKED frame`])      | [`ArcRemoteStreamIds::recv_streams_blocked_frame`] |
 /// | [`on_data_acked`]                                        | [`Outgoing::on_data_acked`]                        |
@@ -116,10 +121,18 @@ where
     remote_bi_stream_rcvbuf_size: u64,
     // 所有流的待写端，要发送数据，就得向这些流索取
     output: ArcOutput<Ext<TX>>,
+    // 各流的优先级变更时置位，发送时才需要重新排序
+    reprioritized: ArcReprioritized,
     // 所有流的待读端，收到了数据，交付给这些流
     input: ArcInput<Ext<TX>>,
     // 对方主动创建的流
     listener: ArcListener<Ext<TX>>,
+    // 连接级别的接收流量控制，应用层从各流读取数据后更新
+    flow_ctrl: ArcRecvController,
+    // 连接正在优雅关闭，不再创建新的流
+    is_closing: AtomicBool,
+    // 等待所有流的数据被确认，用于优雅关闭
+    flush_waker: AtomicWaker,
 }
 
 fn wrapper_error(fty: FrameType) -> impl FnOnce(ExceedLimitError) -> QuicError {
@@ -132,12 +145,14 @@ where
 {
     /// Try to read data from streams into stream frames and write the stream frame into the `buf`.
     ///
-    /// # Fairness
+    /// # Scheduling
     ///
-    /// It's fair between streams. We have implemented a token bucket algorithm, and the [`try_read_data`]
-    /// method will read the data of each stream sequentially. Starting from the first stream, when
-    /// a stream exhausts its tokens (default is 4096, depending on the priority of the stream), or
-    /// there is no data to send, the method will move to the next stream, and so on.
+    /// The streams are scheduled by their [`Priority`]. The data of the streams with a lower urgency
+    /// are always read first. Among the streams with the same urgency, the non-incremental ones are
+    /// read one by one in the order of their stream IDs, a stream is read until it has no data to
+    /// send. Then the incremental ones are read fairly, we have implemented a token bucket algorithm:
+    /// starting from the stream after the last read one, when a stream exhausts its tokens (default
+    /// is 4096), or there is no data to send, the method will move to the next stream, and so on.
     ///
     /// # Flow control
     ///
@@ -163,56 +178,64 @@ where
     ///
     /// [`try_read_data`]: DataStreams::try_read_data
     /// [`write`]: tokio::io::AsyncWriteExt::write
+    /// [`Priority`]: crate::send::Priority
     pub fn try_read_data(
         &self,
         buf: &mut [u8],
         flow_limit: usize,
     ) -> Option<(StreamFrame, usize, usize)> {
-        // todo: use core::range instead in rust 2024
-        use core::ops::Bound::*;
-
         if buf.len() < STREAM_FRAME_MAX_ENCODING_SIZE + 1 {
             return None;
         }
         let mut guard = self.output.streams();
         let output = guard.as_mut().ok()?;
 
-        // 该tokens是令牌桶算法的token，为了多条Stream的公平性，给每个流定期地发放tokens，不累积
-        // 各流轮流按令牌桶算法发放的tokens来整理数据去发送
+        // 该tokens是令牌桶算法的token，为了同优先级的incremental流的公平性，给每个流定期地发放tokens，
+        // 不累积，各流轮流按令牌桶算法发放的tokens来整理数据去发送
         const DEFAULT_TOKENS: usize = 4096;
-        let streams: &mut dyn Iterator<Item = _> = match &output.cursor {
-            // [sid+1..] + [..=sid]
-            Some((sid, tokens)) if *tokens == 0 => &mut output
-                .outgoings
-                .range((Excluded(sid), Unbounded))
-                .chain(output.outgoings.range(..=sid))
-                .map(|(sid, outgoing)| (*sid, outgoing, DEFAULT_TOKENS)),
-            // [sid] + [sid+1..] + [..sid]
-            Some((sid, tokens)) => &mut Option::into_iter(
-                output
-                    .outgoings
-                    .get(sid)
-                    .map(|outgoing| (*sid, outgoing, *tokens)),
-            )
-            .chain(
-                output
-                    .outgoings
-                    .range((Excluded(sid), Unbounded))
-                    .chain(output.outgoings.range(..sid))
-                    .map(|(sid, outgoing)| (*sid, outgoing, DEFAULT_TOKENS)),
-            ),
-            // [..]
-            None => &mut output
-                .outgoings
-                .range(..)
-                .map(|(sid, outgoing)| (*sid, outgoing, DEFAULT_TOKENS)),
-        };
-        for (sid, (outgoing, _s), tokens) in streams.into_iter() {
-            if let Some((frame, data_len, is_fresh, written)) =
-                outgoing.try_read(sid, buf, tokens, flow_limit)
-            {
-                output.cursor = Some((sid, tokens - data_len));
-                return Some((frame, written, if is_fresh { data_len } else { 0 }));
+        if self.reprioritized.take() {
+            output.reschedule();
+        }
+        // 按urgency升序，同urgency的non-incremental流在前，各组内按流ID排序
+        let Output {
+            outgoings,
+            cursor,
+            schedule,
+        } = output;
+
+        for group in schedule.chunk_by(|(a, _), (b, _)| a == b) {
+            if !group[0].0.is_incremental() {
+                // non-incremental流依次发送，直到前面的流没有数据可发
+                for (_, sid) in group {
+                    let (outgoing, _s) = &outgoings[sid];
+                    if let Some((frame, data_len, is_fresh, written)) =
+                        outgoing.try_read(*sid, buf, usize::MAX, flow_limit)
+                    {
+                        return Some((frame, written, if is_fresh { data_len } else { 0 }));
+                    }
+                }
+                continue;
+            }
+
+            // incremental流轮流发送，从上次发送的流开始(tokens未耗尽)，或者其后的流开始
+            let start = match *cursor {
+                Some((sid, tokens)) if tokens > 0 => group.partition_point(|(_, s)| *s < sid),
+                Some((sid, _)) => group.partition_point(|(_, s)| *s <= sid),
+                None => 0,
+            };
+            let (passed, rest) = group.split_at(start);
+            for (_, sid) in rest.iter().chain(passed) {
+                let tokens = match *cursor {
+                    Some((cursor, tokens)) if cursor == *sid && tokens > 0 => tokens,
+                    _ => DEFAULT_TOKENS,
+                };
+                let (outgoing, _s) = &outgoings[sid];
+                if let Some((frame, data_len, is_fresh, written)) =
+                    outgoing.try_read(*sid, buf, tokens, flow_limit)
+                {
+                    *cursor = Some((*sid, tokens - data_len));
+                    return Some((frame, written, if is_fresh { data_len } else { 0 }));
+                }
             }
         }
         None
@@ -238,6 +261,7 @@ where
                 set.remove(&frame.id);
             }
         }
+        self.flush_waker.wake();
     }
 
     /// Called when the stream frame may lost.
@@ -271,13 +295,16 @@ where
             }
             // 如果流是双向的，接收部分的流独立地管理结束。其实是上层应用决定接收的部分是否同时结束
         }
+        self.flush_waker.wake();
     }
 
     /// Called when a stream frame which from peer is received by local.
     ///
     /// If the correspoding stream is not exist, `accept` the stream.
     ///
-    /// Actually calls the [`Incoming::recv_data`] method of the corresponding stream.
+    /// Actually calls the [`Incoming::recv_data`] method of the corresponding stream, returns the
+    /// increase of the largest offset received on the stream, which counts toward the
+    /// connection-level flow control.
     pub fn recv_data(
         &self,
         (stream_frame, body): &(StreamFrame, bytes::Bytes),
@@ -301,7 +328,8 @@ where
 
         if let Ok(set) = self.input.streams().as_mut() {
             if let Some((incoming, s)) = set.get(&sid) {
-                let (is_into_rcvd, fresh_data) = incoming.recv_data(stream_frame, body.clone())?;
+                let (is_into_rcvd, new_data_size) =
+                    incoming.recv_data(stream_frame, body.clone())?;
                 if is_into_rcvd {
                     // 数据被接收完的，忽略后续的ResetStreamFrame
                     s.shutdown_receive();
@@ -310,7 +338,7 @@ where
                     }
                     set.remove(&sid);
                 }
-                return Ok(fresh_data);
+                return Ok(new_data_size);
             }
         }
         Ok(0)
@@ -341,7 +369,19 @@ where
                 }
                 if let Ok(set) = self.input.streams().as_mut() {
                     if let Some((incoming, s)) = set.remove(&sid) {
-                        incoming.recv_reset(reset)?;
+                        let (new_data_size, unread) = incoming.recv_reset(reset)?;
+                        // 被重置的流，直到最终大小的数据也计入连接级别的流量控制
+                        self.flow_ctrl
+                            .on_new_rcvd(new_data_size as usize)
+                            .map_err(|overflow| {
+                                QuicError::new(
+                                    ErrorKind::FlowControl,
+                                    reset.frame_type(),
+                                    format!("{sid} flow control overflow: {overflow}"),
+                                )
+                            })?;
+                        // 未超出限制，这些数据都不会再被读取了，视为已被消费，归还连接级别的接收窗口
+                        incoming.on_reset_discarded(unread);
                         s.shutdown_receive();
                         if s.is_terminated() {
                             self.stream_ids.remote.on_end_of_stream(reset.stream_id);
@@ -364,21 +404,20 @@ where
                     self.try_accept_sid(sid)
                         .map_err(wrapper_error(stop_sending.frame_type()))?;
                 }
-                if self
+                // 以对方给的错误码重置发送端，最终大小即已发送的数据的最大偏移
+                if let Some(reset_stream_err) = self
                     .output
                     .streams()
                     .as_mut()
                     .ok()
                     .and_then(|set| set.get(&sid))
-                    .map(|(outgoing, _s)| outgoing.on_stopped(stop_sending.app_err_code.into()))
-                    .unwrap_or(false)
+                    .and_then(|(outgoing, _s)| {
+                        outgoing.on_stopped(stop_sending.app_err_code.into())
+                    })
                 {
                     self.ctrl_frames
-                        .send_frame([StreamCtlFrame::ResetStream(ResetStreamFrame {
-                            stream_id: sid,
-                            app_error_code: VarInt::from_u32(0),
-                            final_size: VarInt::from_u32(0),
-                        })]);
+                        .send_frame([StreamCtlFrame::ResetStream(reset_stream_err.combine(sid))]);
+                    self.flush_waker.wake();
                 }
             }
             StreamCtlFrame::MaxStreamData(max_stream_data) => {
@@ -422,7 +461,16 @@ where
                         ));
                     }
                 }
-                // 仅仅起到通知作用?主动更新窗口的，此帧没多大用，或许要进一步放大缓冲区大小；被动更新窗口的，此帧有用
+                // 对方被流级别的流量控制阻塞，若应用层已读取了部分数据，立即扩展额度
+                if let Some((incoming, _s)) = self
+                    .input
+                    .streams()
+                    .as_ref()
+                    .ok()
+                    .and_then(|set| set.get(&sid))
+                {
+                    incoming.recv_data_blocked();
+                }
             }
             StreamCtlFrame::MaxStreams(max_streams) => {
                 // 主要更新我方能创建的单双向流
@@ -457,6 +505,48 @@ where
         output.on_conn_error(err);
         input.on_conn_error(err);
         listener.on_conn_error(err);
+        self.flush_waker.wake();
+    }
+
+    /// Stop opening new streams, called when the connection is going to be closed gracefully.
+    ///
+    /// After that, [`OpenBiStream`] and [`OpenUniStream`] will return [`None`], the streams that
+    /// have been opened can still be written and read.
+    ///
+    /// [`OpenBiStream`]: crate::streams::OpenBiStream
+    /// [`OpenUniStream`]: crate::streams::OpenUniStream
+    pub fn stop_opening(&self) {
+        self.is_closing.store(true, Ordering::Release);
+    }
+
+    /// Returns the number of the streams that are still sending or receiving.
+    ///
+    /// A bidirectional stream is counted once, it is removed after both of its directions are
+    /// terminated. Return 0 if a connection error occurred.
+    pub fn active_streams(&self) -> usize {
+        let output = self.output.streams();
+        let input = self.input.streams();
+        match (output.as_ref(), input.as_ref()) {
+            (Ok(output), Ok(input)) => {
+                // 双向流同时出现在output和input中，只计一次
+                let only_input = input.keys().filter(|sid| !output.contains_key(sid));
+                output.len() + only_input.count()
+            }
+            _ => 0,
+        }
+    }
+
+    /// Poll until all the data written to the streams has been acknowledged by the peer, or the
+    /// streams have been reset, or a connection error occurred.
+    ///
+    /// It is used to flush the pending data before closing the connection gracefully.
+    pub fn poll_flushed(&self, cx: &mut Context<'_>) -> Poll<()> {
+        // 先注册，避免错过数据被确认时的唤醒
+        self.flush_waker.register(cx.waker());
+        match self.output.streams().as_ref() {
+            Ok(output) if !output.values().all(|(o, _s)| o.is_flushed()) => Poll::Pending,
+            _ => Poll::Ready(()),
+        }
     }
 }
 
@@ -469,6 +559,7 @@ where
         local_params: &Parameters,
         strategy: Box<dyn ControlConcurrency>,
         ctrl_frames: TX,
+        flow_ctrl: ArcRecvController,
     ) -> Self {
         let max_bi_streams = local_params.initial_max_streams_bidi().into();
         let max_uni_streams = local_params.initial_max_streams_uni().into();
@@ -485,9 +576,13 @@ where
             local_bi_stream_rcvbuf_size: local_params.initial_max_stream_data_bidi_local().into(),
             remote_bi_stream_rcvbuf_size: local_params.initial_max_stream_data_bidi_remote().into(),
             output: ArcOutput::new(),
+            reprioritized: ArcReprioritized::default(),
             input: ArcInput::default(),
             listener: ArcListener::new(),
             ctrl_frames,
+            flow_ctrl,
+            is_closing: AtomicBool::new(false),
+            flush_waker: AtomicWaker::new(),
         }
     }
 
@@ -505,6 +600,9 @@ where
             Ok(input) => input,
             Err(e) => return Poll::Ready(Err(e)),
         };
+        if self.is_closing.load(Ordering::Acquire) {
+            return Poll::Ready(Ok(None));
+        }
         if let Some(sid) = ready!(self.stream_ids.local.poll_alloc_sid(cx, Dir::Bi)) {
             let arc_sender = self.create_sender(sid, snd_buf_size);
             let arc_recver = self.create_recver(sid, self.local_bi_stream_rcvbuf_size);
@@ -527,6 +625,9 @@ where
             Ok(out) => out,
             Err(e) => return Poll::Ready(Err(e)),
         };
+        if self.is_closing.load(Ordering::Acquire) {
+            return Poll::Ready(Ok(None));
+        }
         if let Some(sid) = ready!(self.stream_ids.local.poll_alloc_sid(cx, Dir::Uni)) {
             let arc_sender = self.create_sender(sid, snd_buf_size);
             let io_state = IOState::send_only();
@@ -611,10 +712,737 @@ where
     }
 
     fn create_sender(&self, sid: StreamId, buf_size: u64) -> ArcSender<Ext<TX>> {
-        ArcSender::new(sid, buf_size, Ext(self.ctrl_frames.clone()))
+        ArcSender::new(
+            sid,
+            buf_size,
+            Ext(self.ctrl_frames.clone()),
+            self.reprioritized.clone(),
+        )
     }
 
     fn create_recver(&self, sid: StreamId, buf_size: u64) -> ArcRecver<Ext<TX>> {
-        ArcRecver::new(sid, buf_size, Ext(self.ctrl_frames.clone()))
+        ArcRecver::new(
+            sid,
+            buf_size,
+            Ext(self.ctrl_frames.clone()),
+            self.flow_ctrl.clone(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::Future,
+        pin::Pin,
+        sync::{Arc, Mutex},
+    };
+
+    use bytes::Bytes;
+    use qbase::{
+        frame::{MaxStreamDataFrame, MaxStreamsFrame, StopSendingFrame, StreamDataBlockedFrame},
+        sid::handy::DemandConcurrency,
+        varint::VarInt,
+    };
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+    use super::*;
+    use crate::send::Priority;
+
+    #[derive(Debug, Default, Clone)]
+    struct CtrlFrames(Arc<Mutex<Vec<StreamCtlFrame>>>);
+
+    impl SendFrame<StreamCtlFrame> for CtrlFrames {
+        fn send_frame<I: IntoIterator<Item = StreamCtlFrame>>(&self, iter: I) {
+            self.0.lock().unwrap().extend(iter);
+        }
+    }
+
+    impl CtrlFrames {
+        fn take_max_stream_data(&self) -> Vec<u64> {
+            self.0
+                .lock()
+                .unwrap()
+                .drain(..)
+                .filter_map(|frame| match frame {
+                    StreamCtlFrame::MaxStreamData(frame) => Some(frame.max_stream_data.into()),
+                    _ => None,
+                })
+                .collect()
+        }
+
+        fn take_reset_stream(&self) -> Vec<ResetStreamFrame> {
+            self.0
+                .lock()
+                .unwrap()
+                .drain(..)
+                .filter_map(|frame| match frame {
+                    StreamCtlFrame::ResetStream(frame) => Some(frame),
+                    _ => None,
+                })
+                .collect()
+        }
+    }
+
+    fn streams(role: Role) -> DataStreams<CtrlFrames> {
+        DataStreams::new(
+            role,
+            &Parameters::default(),
+            Box::new(DemandConcurrency),
+            CtrlFrames::default(),
+            ArcRecvController::default(),
+        )
+    }
+
+    #[test]
+    fn test_sender_blocked_by_max_stream_data() {
+        let streams = streams(Role::Client);
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        let Poll::Ready(Ok(Some((sid, (mut reader, mut writer))))) =
+            streams.poll_open_bi_stream(&mut cx, 16)
+        else {
+            panic!("failed to open a bidirectional stream");
+        };
+
+        let data = [0u8; 32];
+        let write = Pin::new(&mut writer).poll_write(&mut cx, &data);
+        assert!(matches!(write, Poll::Ready(Ok(16))));
+        // 达到对方给的流数据限制，写入被阻塞
+        assert!(Pin::new(&mut writer)
+            .poll_write(&mut cx, &data)
+            .is_pending());
+
+        let max_stream_data = MaxStreamDataFrame::new(sid, VarInt::from_u32(40));
+        streams
+            .recv_stream_control(&StreamCtlFrame::MaxStreamData(max_stream_data))
+            .unwrap();
+        let write = Pin::new(&mut writer).poll_write(&mut cx, &data);
+        assert!(matches!(write, Poll::Ready(Ok(24))));
+        assert!(Pin::new(&mut writer)
+            .poll_write(&mut cx, &data)
+            .is_pending());
+
+        reader.stop(0);
+        writer.cancel(0);
+    }
+
+    #[test]
+    fn test_receiver_extends_max_stream_data() {
+        let streams = DataStreams {
+            remote_bi_stream_rcvbuf_size: 16,
+            ..streams(Role::Server)
+        };
+        let frames = &streams.ctrl_frames;
+        // 对方创建的第一个双向流
+        let sid = StreamId::from(VarInt::from_u32(0));
+        let stream_frame = StreamFrame::new(sid, 0, 16);
+        streams
+            .recv_data(&(stream_frame, Bytes::from_static(&[0u8; 16])))
+            .unwrap();
+        // 超过流数据限制
+        let stream_frame = StreamFrame::new(sid, 16, 1);
+        assert!(streams
+            .recv_data(&(stream_frame, Bytes::from_static(&[0u8; 1])))
+            .is_err());
+
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        let Poll::Ready(Ok((_, (mut reader, mut writer)))) =
+            streams.listener.poll_accept_bi_stream(&mut cx, 16)
+        else {
+            panic!("failed to accept the bidirectional stream");
+        };
+        let data_blocked = StreamCtlFrame::StreamDataBlocked(StreamDataBlockedFrame {
+            stream_id: sid,
+            maximum_stream_data: VarInt::from_u32(16),
+        });
+        // 应用层还没有读取数据，无法扩展额度
+        streams.recv_stream_control(&data_blocked).unwrap();
+        assert!(frames.take_max_stream_data().is_empty());
+
+        let mut buf = [0u8; 4];
+        let mut read_buf = ReadBuf::new(&mut buf);
+        assert!(Pin::new(&mut reader)
+            .poll_read(&mut cx, &mut read_buf)
+            .is_ready());
+        // 读取的数据不足窗口的一半，不主动扩展额度
+        assert!(frames.take_max_stream_data().is_empty());
+        // 对方被阻塞，立即扩展额度
+        streams.recv_stream_control(&data_blocked).unwrap();
+        assert_eq!(frames.take_max_stream_data(), vec![20]);
+
+        let mut buf = [0u8; 9];
+        let mut read_buf = ReadBuf::new(&mut buf);
+        assert!(Pin::new(&mut reader)
+            .poll_read(&mut cx, &mut read_buf)
+            .is_ready());
+        // 读取超过窗口的一半，扩展额度
+        assert_eq!(frames.take_max_stream_data(), vec![29]);
+
+        reader.stop(0);
+        writer.cancel(0);
+    }
+
+    // 将一端待发送的流数据交给另一端
+    fn deliver(from: &DataStreams<CtrlFrames>, to: &DataStreams<CtrlFrames>) {
+        let mut buf = [0u8; 1200];
+        while let Some((frame, written, _)) = from.try_read_data(&mut buf, usize::MAX) {
+            let body = Bytes::copy_from_slice(&buf[written - frame.len()..written]);
+            to.recv_data(&(frame, body)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_loopback_bi_stream() {
+        let client = streams(Role::Client);
+        let server = streams(Role::Server);
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+
+        let Poll::Ready(Ok(Some((sid, (mut client_reader, mut client_writer))))) =
+            client.poll_open_bi_stream(&mut cx, 1024)
+        else {
+            panic!("failed to open a bidirectional stream");
+        };
+        assert_eq!(sid.role(), Role::Client);
+        assert_eq!(sid.dir(), Dir::Bi);
+        // 双向流只计一次
+        assert_eq!(client.active_streams(), 1);
+        assert_eq!(server.active_streams(), 0);
+        // 对方在收到第一个流帧之前，感知不到该流
+        assert!(server
+            .listener
+            .poll_accept_bi_stream(&mut cx, 1024)
+            .is_pending());
+
+        let write = Pin::new(&mut client_writer).poll_write(&mut cx, b"hello");
+        assert!(matches!(write, Poll::Ready(Ok(5))));
+        deliver(&client, &server);
+
+        let Poll::Ready(Ok((accepted, (mut server_reader, mut server_writer)))) =
+            server.listener.poll_accept_bi_stream(&mut cx, 1024)
+        else {
+            panic!("failed to accept the bidirectional stream");
+        };
+        assert_eq!(accepted, sid);
+        assert_eq!(server.active_streams(), 1);
+        let mut buf = [0u8; 16];
+        let mut read_buf = ReadBuf::new(&mut buf);
+        assert!(Pin::new(&mut server_reader)
+            .poll_read(&mut cx, &mut read_buf)
+            .is_ready());
+        assert_eq!(read_buf.filled(), b"hello");
+
+        let write = Pin::new(&mut server_writer).poll_write(&mut cx, b"world");
+        assert!(matches!(write, Poll::Ready(Ok(5))));
+        deliver(&server, &client);
+        let mut read_buf = ReadBuf::new(&mut buf);
+        assert!(Pin::new(&mut client_reader)
+            .poll_read(&mut cx, &mut read_buf)
+            .is_ready());
+        assert_eq!(read_buf.filled(), b"world");
+
+        client_reader.stop(0);
+        client_writer.cancel(0);
+        server_reader.stop(0);
+        server_writer.cancel(0);
+    }
+
+    #[test]
+    fn test_drop_writer_keeps_reader() {
+        let client = streams(Role::Client);
+        let server = streams(Role::Server);
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        fn read(reader: &mut Reader<Ext<CtrlFrames>>, cx: &mut Context) -> Vec<u8> {
+            let mut buf = [0u8; 16];
+            let mut read_buf = ReadBuf::new(&mut buf);
+            match Pin::new(reader).poll_read(cx, &mut read_buf) {
+                Poll::Ready(Ok(())) => read_buf.filled().to_vec(),
+                _ => panic!("failed to read from the stream"),
+            }
+        }
+
+        // 写完请求后直接丢弃写端，发送fin
+        let Poll::Ready(Ok(Some((_, (mut client_reader, mut client_writer))))) =
+            client.poll_open_bi_stream(&mut cx, 1024)
+        else {
+            panic!("failed to open a bidirectional stream");
+        };
+        let write = Pin::new(&mut client_writer).poll_write(&mut cx, b"request");
+        assert!(matches!(write, Poll::Ready(Ok(7))));
+        drop(client_writer);
+        deliver(&client, &server);
+
+        let Poll::Ready(Ok((_, (mut server_reader, mut server_writer)))) =
+            server.listener.poll_accept_bi_stream(&mut cx, 1024)
+        else {
+            panic!("failed to accept the bidirectional stream");
+        };
+        assert_eq!(read(&mut server_reader, &mut cx), b"request");
+        assert!(read(&mut server_reader, &mut cx).is_empty());
+
+        // 客户端的读端不受写端关闭的影响，仍能读取响应
+        let write = Pin::new(&mut server_writer).poll_write(&mut cx, b"response");
+        assert!(matches!(write, Poll::Ready(Ok(8))));
+        drop(server_writer);
+        deliver(&server, &client);
+        assert_eq!(read(&mut client_reader, &mut cx), b"response");
+        assert!(read(&mut client_reader, &mut cx).is_empty());
+    }
+
+    #[test]
+    fn test_read_chunks() {
+        let client = streams(Role::Client);
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        let Poll::Ready(Ok(Some((_, (mut reader, mut writer))))) =
+            client.poll_open_bi_stream(&mut cx, 1024)
+        else {
+            panic!("failed to open a bidirectional stream");
+        };
+        let data = (0..900).map(|i| i as u8).collect::<Vec<_>>();
+        let write = Pin::new(&mut writer).poll_write(&mut cx, &data);
+        assert!(matches!(write, Poll::Ready(Ok(900))));
+        drop(writer);
+        // 以较小的流帧发出，数据在接收端是分片的
+        let mut buf = [0u8; 200];
+        let mut stream_frames = Vec::new();
+        while let Some((frame, written, _)) = client.try_read_data(&mut buf, usize::MAX) {
+            let body = Bytes::copy_from_slice(&buf[written - frame.len()..written]);
+            stream_frames.push((frame, body));
+        }
+        assert!(stream_frames.len() > 4);
+
+        // 相同的流帧交给两个接收端，分别逐字节读取和分片读取
+        let new_server = || {
+            let flow_ctrl = ArcRecvController::with_initial(1000);
+            let server = DataStreams {
+                flow_ctrl: flow_ctrl.clone(),
+                ..streams(Role::Server)
+            };
+            for stream_frame in &stream_frames {
+                server.recv_data(stream_frame).unwrap();
+            }
+            (server, flow_ctrl)
+        };
+        let (byte_server, byte_flow_ctrl) = new_server();
+        let (chunk_server, chunk_flow_ctrl) = new_server();
+        let Poll::Ready(Ok((_, (mut byte_reader, byte_writer)))) =
+            byte_server.listener.poll_accept_bi_stream(&mut cx, 1024)
+        else {
+            panic!("failed to accept the bidirectional stream");
+        };
+        let Poll::Ready(Ok((_, (mut chunk_reader, chunk_writer)))) =
+            chunk_server.listener.poll_accept_bi_stream(&mut cx, 1024)
+        else {
+            panic!("failed to accept the bidirectional stream");
+        };
+
+        let mut bytewise = Vec::new();
+        loop {
+            let mut byte = [0u8; 1];
+            let mut read_buf = ReadBuf::new(&mut byte);
+            assert!(Pin::new(&mut byte_reader)
+                .poll_read(&mut cx, &mut read_buf)
+                .is_ready());
+            if read_buf.filled().is_empty() {
+                break;
+            }
+            bytewise.extend_from_slice(read_buf.filled());
+        }
+
+        let mut chunked = Vec::new();
+        let mut chunks = [Bytes::new(), Bytes::new()];
+        loop {
+            let Poll::Ready(Ok(filled)) = chunk_reader.poll_read_chunks(&mut cx, &mut chunks)
+            else {
+                panic!("failed to read chunks from the stream");
+            };
+            let Some(filled) = filled else {
+                break;
+            };
+            assert!(filled > 0);
+            chunks[..filled]
+                .iter()
+                .for_each(|chunk| chunked.extend_from_slice(chunk));
+        }
+        assert_eq!(bytewise, data);
+        assert_eq!(chunked, data);
+
+        // 分片读取同样释放了连接级别的接收窗口
+        for flow_ctrl in [byte_flow_ctrl, chunk_flow_ctrl] {
+            let incr_limit = Pin::new(&mut flow_ctrl.incr_limit()).poll(&mut cx);
+            assert!(matches!(
+                incr_limit,
+                Poll::Ready(Some(frame)) if frame.max_data.into_inner() == 1900
+            ));
+        }
+
+        drop((byte_writer, chunk_writer));
+        reader.stop(0);
+    }
+
+    #[test]
+    fn test_stop_sending_triggers_reset() {
+        let streams = streams(Role::Client);
+        let frames = &streams.ctrl_frames;
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        let Poll::Ready(Ok(Some((sid, (mut reader, mut writer))))) =
+            streams.poll_open_bi_stream(&mut cx, 1024)
+        else {
+            panic!("failed to open a bidirectional stream");
+        };
+        let write = Pin::new(&mut writer).poll_write(&mut cx, &[0u8; 10]);
+        assert!(matches!(write, Poll::Ready(Ok(10))));
+        let mut buf = [0u8; 1200];
+        while streams.try_read_data(&mut buf, usize::MAX).is_some() {}
+        // 写入但尚未发送的数据
+        let write = Pin::new(&mut writer).poll_write(&mut cx, &[0u8; 5]);
+        assert!(matches!(write, Poll::Ready(Ok(5))));
+
+        let stop_sending = StopSendingFrame::new(sid, VarInt::from_u32(7));
+        streams
+            .recv_stream_control(&StreamCtlFrame::StopSending(stop_sending))
+            .unwrap();
+        // 以对方的错误码重置，最终大小为已发送的数据量，未发送的数据不计入
+        assert_eq!(
+            frames.take_reset_stream(),
+            vec![ResetStreamFrame {
+                stream_id: sid,
+                app_error_code: VarInt::from_u32(7),
+                final_size: VarInt::from_u32(10),
+            }]
+        );
+        assert!(matches!(
+            Pin::new(&mut writer).poll_write(&mut cx, &[0u8; 10]),
+            Poll::Ready(Err(e)) if e.kind() == std::io::ErrorKind::BrokenPipe
+        ));
+        // 重复的STOP_SENDING不会再次重置
+        streams
+            .recv_stream_control(&StreamCtlFrame::StopSending(stop_sending))
+            .unwrap();
+        assert!(frames.take_reset_stream().is_empty());
+
+        reader.stop(0);
+    }
+
+    #[test]
+    fn test_reset_before_data() {
+        let streams = streams(Role::Server);
+        // 对方创建的第一个单向流，还没有收到任何数据就被重置
+        let sid = StreamId::from(VarInt::from_u32(2));
+        let reset = ResetStreamFrame {
+            stream_id: sid,
+            app_error_code: VarInt::from_u32(3),
+            final_size: VarInt::from_u32(0),
+        };
+        streams
+            .recv_stream_control(&StreamCtlFrame::ResetStream(reset))
+            .unwrap();
+
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        let Poll::Ready(Ok((accepted, mut reader))) =
+            streams.listener.poll_accept_uni_stream(&mut cx)
+        else {
+            panic!("failed to accept the unidirectional stream");
+        };
+        assert_eq!(accepted, sid);
+        let mut buf = [0u8; 4];
+        let mut read_buf = ReadBuf::new(&mut buf);
+        assert!(matches!(
+            Pin::new(&mut reader).poll_read(&mut cx, &mut read_buf),
+            Poll::Ready(Err(e)) if e.kind() == std::io::ErrorKind::BrokenPipe
+        ));
+        // 重置之后的数据被忽略
+        let stream_frame = StreamFrame::new(sid, 0, 4);
+        assert_eq!(
+            streams
+                .recv_data(&(stream_frame, Bytes::from_static(&[0u8; 4])))
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_out_of_order_reassembly() {
+        let streams = streams(Role::Server);
+        let sid = StreamId::from(VarInt::from_u32(2));
+        let data = Bytes::from_static(b"0123456789abcdef");
+        let recv = |offset: usize, len: usize, fin: bool| {
+            let mut stream_frame = StreamFrame::new(sid, offset as u64, len);
+            stream_frame.set_eos_flag(fin);
+            streams.recv_data(&(stream_frame, data.slice(offset..offset + len)))
+        };
+
+        // 倒序到达，携带FIN的最后一段最先到达，流量控制按最大偏移计入全部16字节
+        assert_eq!(recv(12, 4, true).unwrap(), 16);
+        assert_eq!(recv(8, 4, false).unwrap(), 0);
+        assert_eq!(recv(4, 4, false).unwrap(), 0);
+
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        let Poll::Ready(Ok((_, mut reader))) = streams.listener.poll_accept_uni_stream(&mut cx)
+        else {
+            panic!("failed to accept the unidirectional stream");
+        };
+        // 读取位置上的空缺未填上，无数据可读
+        let mut buf = [0u8; 32];
+        let mut read_buf = ReadBuf::new(&mut buf);
+        assert!(Pin::new(&mut reader)
+            .poll_read(&mut cx, &mut read_buf)
+            .is_pending());
+
+        // 重传的数据与已收到的重叠，不超过最大偏移，不再计入流量控制
+        assert_eq!(recv(2, 8, false).unwrap(), 0);
+        assert_eq!(recv(2, 8, false).unwrap(), 0);
+        // 重叠部分与已收到的不一致
+        let stream_frame = StreamFrame::new(sid, 6, 4);
+        let error = streams
+            .recv_data(&(stream_frame, Bytes::from_static(b"6780")))
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ProtocolViolation);
+
+        // 填上空缺，全部数据连续可读
+        assert_eq!(recv(0, 4, false).unwrap(), 0);
+        assert!(Pin::new(&mut reader)
+            .poll_read(&mut cx, &mut read_buf)
+            .is_ready());
+        assert_eq!(read_buf.filled(), &data[..]);
+        let mut read_buf = ReadBuf::new(&mut buf);
+        assert!(Pin::new(&mut reader)
+            .poll_read(&mut cx, &mut read_buf)
+            .is_ready());
+        assert!(read_buf.filled().is_empty());
+    }
+
+    #[test]
+    fn test_reset_final_size_mismatch() {
+        let streams = streams(Role::Server);
+        let sid = StreamId::from(VarInt::from_u32(2));
+        // 只收到了部分数据，流的最终大小已确定为8
+        let mut stream_frame = StreamFrame::new(sid, 4, 4);
+        stream_frame.set_eos_flag(true);
+        streams
+            .recv_data(&(stream_frame, Bytes::from_static(&[0u8; 4])))
+            .unwrap();
+
+        let reset = ResetStreamFrame {
+            stream_id: sid,
+            app_error_code: VarInt::from_u32(0),
+            final_size: VarInt::from_u32(4),
+        };
+        let error = streams
+            .recv_stream_control(&StreamCtlFrame::ResetStream(reset))
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::FinalSize);
+    }
+
+    #[test]
+    fn test_final_size_consistency() {
+        let streams = streams(Role::Server);
+        let recv = |sid: u32, offset: u64, len: usize, fin: bool| {
+            let mut stream_frame =
+                StreamFrame::new(StreamId::from(VarInt::from_u32(sid)), offset, len);
+            stream_frame.set_eos_flag(fin);
+            streams.recv_data(&(stream_frame, Bytes::from(vec![0u8; len])))
+        };
+
+        // 最终大小确定为8之后，超出最终大小的数据
+        assert_eq!(recv(2, 4, 4, true).unwrap(), 8);
+        let error = recv(2, 6, 4, false).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::FinalSize);
+
+        // 已收到的数据偏移到了16，最终大小却只有8
+        assert_eq!(recv(6, 12, 4, false).unwrap(), 16);
+        let error = recv(6, 4, 4, true).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::FinalSize);
+
+        // FIN确定的最终大小为8，重置帧给出了更大的最终大小
+        assert_eq!(recv(10, 4, 4, true).unwrap(), 8);
+        let reset = ResetStreamFrame {
+            stream_id: StreamId::from(VarInt::from_u32(10)),
+            app_error_code: VarInt::from_u32(0),
+            final_size: VarInt::from_u32(12),
+        };
+        let error = streams
+            .recv_stream_control(&StreamCtlFrame::ResetStream(reset))
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::FinalSize);
+    }
+
+    #[test]
+    fn test_reset_final_size_flow_control() {
+        let flow_ctrl = ArcRecvController::with_initial(50);
+        let streams = DataStreams {
+            flow_ctrl: flow_ctrl.clone(),
+            ..streams(Role::Server)
+        };
+        let sid = StreamId::from(VarInt::from_u32(2));
+        let stream_frame = StreamFrame::new(sid, 0, 20);
+        assert_eq!(
+            streams
+                .recv_data(&(stream_frame, Bytes::from_static(&[0u8; 20])))
+                .unwrap(),
+            20
+        );
+
+        // 重置帧的最终大小未超出流级别的限制，但超出了连接级别的限制
+        let reset = ResetStreamFrame {
+            stream_id: sid,
+            app_error_code: VarInt::from_u32(0),
+            final_size: VarInt::from_u32(100),
+        };
+        let error = streams
+            .recv_stream_control(&StreamCtlFrame::ResetStream(reset))
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::FlowControl);
+        // 超限的数据没有被当作已消费，接收窗口不会因此扩大
+        assert_eq!(flow_ctrl.max_data(), 50);
+    }
+
+    #[test]
+    fn test_flush_before_closing() {
+        let streams = streams(Role::Client);
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        let Poll::Ready(Ok(Some((_, mut writer)))) = streams.poll_open_uni_stream(&mut cx, 1024)
+        else {
+            panic!("failed to open a unidirectional stream");
+        };
+        let write = Pin::new(&mut writer).poll_write(&mut cx, b"hello");
+        assert!(matches!(write, Poll::Ready(Ok(5))));
+
+        // 不再创建新的流，已创建的流仍可发送数据
+        streams.stop_opening();
+        assert!(matches!(
+            streams.poll_open_uni_stream(&mut cx, 1024),
+            Poll::Ready(Ok(None))
+        ));
+        assert!(streams.poll_flushed(&mut cx).is_pending());
+
+        let mut buf = [0u8; 1200];
+        let (frame, _, _) = streams.try_read_data(&mut buf, usize::MAX).unwrap();
+        assert!(streams.poll_flushed(&mut cx).is_pending());
+        streams.on_data_acked(frame);
+        assert!(streams.poll_flushed(&mut cx).is_ready());
+
+        writer.cancel(0);
+    }
+
+    fn open_uni_streams(
+        streams: &DataStreams<CtrlFrames>,
+        n: usize,
+        data: &[u8],
+    ) -> Vec<(StreamId, Writer<Ext<CtrlFrames>>)> {
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        let max_streams = MaxStreamsFrame::Uni(VarInt::from_u32(n as u32));
+        streams
+            .recv_stream_control(&StreamCtlFrame::MaxStreams(max_streams))
+            .unwrap();
+        (0..n)
+            .map(|_| {
+                let Poll::Ready(Ok(Some((sid, mut writer)))) =
+                    streams.poll_open_uni_stream(&mut cx, 1 << 16)
+                else {
+                    panic!("failed to open a unidirectional stream");
+                };
+                let write = Pin::new(&mut writer).poll_write(&mut cx, data);
+                assert!(matches!(write, Poll::Ready(Ok(n)) if n == data.len()));
+                (sid, writer)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_priority_scheduling() {
+        let streams = streams(Role::Client);
+        let mut writers = open_uni_streams(&streams, 2, &[0u8; 3000]);
+        let (low, high) = (writers[0].0, writers[1].0);
+        // 后创建的流优先级更高
+        writers[1].1.set_priority(Priority::new(0, false));
+        assert_eq!(writers[0].1.priority(), Priority::default());
+
+        let mut buf = [0u8; 1200];
+        let mut order = vec![];
+        while let Some((frame, _, _)) = streams.try_read_data(&mut buf, usize::MAX) {
+            order.push((frame.id, frame.len()));
+        }
+        let sent = |sid| {
+            order
+                .iter()
+                .filter(|(id, _)| *id == sid)
+                .map(|(_, len)| len)
+                .sum::<usize>()
+        };
+        assert_eq!(sent(high), 3000);
+        assert_eq!(sent(low), 3000);
+        // 高优先级流的数据全部先发出
+        let first_low = order.iter().position(|(id, _)| *id == low).unwrap();
+        assert!(order[..first_low].iter().all(|(id, _)| *id == high));
+        assert_eq!(
+            sent(high),
+            order[..first_low].iter().map(|(_, len)| len).sum()
+        );
+
+        for (_, writer) in writers.iter_mut() {
+            writer.cancel(0);
+        }
+    }
+
+    #[test]
+    fn test_schedule_kept_in_order() {
+        let streams = streams(Role::Client);
+        let mut writers = open_uni_streams(&streams, 3, &[0u8; 10]);
+        let sids = writers.iter().map(|(sid, _)| *sid).collect::<Vec<_>>();
+        let schedule = || {
+            let mut guard = streams.output.streams();
+            let output = guard.as_mut().unwrap();
+            if streams.reprioritized.take() {
+                output.reschedule();
+            }
+            output
+                .schedule
+                .iter()
+                .map(|(_, sid)| *sid)
+                .collect::<Vec<_>>()
+        };
+        // 新建的流按流ID排在同优先级的流之后
+        assert_eq!(schedule(), sids);
+
+        // 调整优先级之后重新排序
+        writers[2].1.set_priority(Priority::new(0, false));
+        writers[0].1.set_priority(Priority::new(3, true));
+        assert_eq!(schedule(), [sids[2], sids[1], sids[0]]);
+
+        // 已结束且数据全部被确认的流移出调度
+        drop(writers.remove(1));
+        let mut buf = [0u8; 1200];
+        while let Some((frame, _, _)) = streams.try_read_data(&mut buf, usize::MAX) {
+            streams.on_data_acked(frame);
+        }
+        assert_eq!(schedule(), [sids[2], sids[0]]);
+
+        for (_, writer) in writers.iter_mut() {
+            writer.cancel(0);
+        }
+    }
+
+    #[test]
+    fn test_incremental_round_robin() {
+        let streams = streams(Role::Client);
+        let mut writers = open_uni_streams(&streams, 3, &[0u8; 8192]);
+        // 两个同urgency的incremental流轮流发送，排在non-incremental流之后
+        writers[0].1.set_priority(Priority::new(3, true));
+        writers[1].1.set_priority(Priority::new(3, true));
+        let (first, second, last) = (writers[0].0, writers[1].0, writers[2].0);
+
+        let mut buf = [0u8; 1200];
+        let mut order = vec![];
+        while let Some((frame, _, _)) = streams.try_read_data(&mut buf, usize::MAX) {
+            if order.last() != Some(&frame.id) {
+                order.push(frame.id);
+            }
+        }
+        // 每个流每轮最多发送4096字节，8192字节需要两轮
+        assert_eq!(order, [last, first, second, first, second]);
+
+        for (_, writer) in writers.iter_mut() {
+            writer.cancel(0);
+        }
     }
 }
@@ -22,12 +22,17 @@ async fn main() {
     loop {
         match receiver.recv().await {
             Ok(n) => {
-                log::info!(
-                    "received {} packets, dst {}, src {}",
-                    n,
-                    receiver.headers[0].dst,
-                    receiver.headers[0].src
-                );
+                log::info!("received {} messages", n);
+                // 一个消息可能是GRO合并的多个数据报，按gro_seg_size切分
+                for (datagram, hdr) in receiver.datagrams(n) {
+                    log::info!(
+                        "received {} bytes, dst {}, src {}, gro segment size {}",
+                        datagram.len(),
+                        hdr.dst,
+                        hdr.src,
+                        hdr.gro_seg_size
+                    );
+                }
             }
             Err(e) => {
                 log::error!("receive failed: {}", e);
@@ -40,6 +40,7 @@ async fn main() {
         ecn: Some(1),
         seg_size: args.msg_size as u16,
         gso: args.gso,
+        gro_seg_size: 0,
     };
 
     let payload = vec![8u8; args.msg_size];
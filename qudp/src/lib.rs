@@ -7,6 +7,7 @@ use std::{
     task::{ready, Context, Poll},
 };
 
+use bytes::BytesMut;
 use socket2::{Domain, Socket, Type};
 use tokio::io::Interest;
 
@@ -32,6 +33,10 @@ cfg_if::cfg_if! {
 
 mod cmsghdr;
 
+/// The minimum size of the slab the messages are received into, the received datagrams are split
+/// off it, so that the memory is allocated and zero-filled once for many receivings.
+const SLAB_SIZE: usize = 1 << 20;
+
 #[derive(Clone, Copy, Debug)]
 pub struct PacketHeader {
     pub src: SocketAddr,
@@ -43,6 +48,8 @@ pub struct PacketHeader {
     pub seg_size: u16,
     // use gso
     pub gso: bool,
+    // the size of each datagram coalesced by gro into the received buffer, 0 if not coalesced
+    pub gro_seg_size: u16,
 }
 
 impl Default for PacketHeader {
@@ -54,6 +61,62 @@ impl Default for PacketHeader {
             ecn: None,
             gso: false,
             seg_size: 0,
+            gro_seg_size: 0,
+        }
+    }
+}
+
+/// The options of the udp socket, applied when the [`UdpSocketController`] is created.
+#[derive(Clone, Copy, Debug)]
+pub struct UscConfig {
+    /// The size of the receive buffer(SO_RCVBUF), the system default if `None`.
+    ///
+    /// High-throughput servers need a large receive buffer to avoid dropping datagrams in bursts.
+    /// The size is capped by the OS, such as `net.core.rmem_max` on Linux, read the actually
+    /// applied size by [`UdpSocketController::recv_buffer_size`].
+    pub recv_buffer_size: Option<usize>,
+    /// The size of the send buffer(SO_SNDBUF), the system default if `None`.
+    ///
+    /// The size is capped by the OS too, read [`UdpSocketController::send_buffer_size`].
+    pub send_buffer_size: Option<usize>,
+    /// Whether to use gso if the platform supports it, `true` by default.
+    pub gso: bool,
+    /// Whether to use gro if the platform supports it, `true` by default.
+    pub gro: bool,
+    /// The type of service(IP_TOS, or IPV6_TCLASS for IPv6) of the sent packets, the system
+    /// default if `None`.
+    ///
+    /// Only the DSCP in the upper 6 bits takes effect, the ECN codepoint in the lower 2 bits is
+    /// still set for each packet, read [`PacketHeader::ecn`].
+    pub tos: Option<u8>,
+    /// Whether to allow binding the address in the TIME_WAIT state(SO_REUSEADDR), `false` by
+    /// default.
+    pub reuse_address: bool,
+    /// Whether to allow multiple sockets to bind the same address(SO_REUSEPORT), so that the
+    /// datagrams are distributed among them by the kernel, `false` by default. It is ignored on
+    /// the platforms not supporting it.
+    pub reuse_port: bool,
+    /// Whether a socket bound to an IPv6 address only serves the IPv6 peers(IPV6_V6ONLY),
+    /// `false` by default.
+    ///
+    /// By default, a socket bound to `[::]` is dual-stack, it sends to and receives from both
+    /// the IPv4 and IPv6 peers. The kernel sees the IPv4 peers as IPv4-mapped IPv6 addresses,
+    /// but the [`PacketHeader`]s always carry the plain IPv4 addresses, read
+    /// [`UdpSocketController::is_dual_stack`].
+    pub ipv6_only: bool,
+}
+
+impl Default for UscConfig {
+    fn default() -> Self {
+        Self {
+            recv_buffer_size: None,
+            send_buffer_size: None,
+            gso: true,
+            gro: true,
+            tos: None,
+            reuse_address: false,
+            reuse_port: false,
+            ipv6_only: false,
         }
     }
 }
@@ -62,13 +125,31 @@ impl Default for PacketHeader {
 #[allow(dead_code)]
 pub struct UdpSocketController {
     io: tokio::net::UdpSocket,
-    // TOOD: unread?
+    // the max segments of gso, detected once at startup, 1 means gso is not supported
     gso_size: AtomicU16,
     gro_size: AtomicU16,
+    // 配置的DSCP，逐包设置ECN时需要保留
+    dscp: u8,
 }
 
 impl UdpSocketController {
     pub fn new(addr: SocketAddr) -> io::Result<Self> {
+        Self::with_config(addr, &UscConfig::default())
+    }
+
+    /// Create a udp socket bound to the `addr`, with the options in the `config`.
+    ///
+    /// Return an [`io::ErrorKind::InvalidInput`] error if a buffer size of the `config` is zero.
+    /// A buffer size larger than the OS allows is capped rather than rejected, a warning is
+    /// logged in that case.
+    pub fn with_config(addr: SocketAddr, config: &UscConfig) -> io::Result<Self> {
+        if config.recv_buffer_size == Some(0) || config.send_buffer_size == Some(0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the buffer size of the udp socket must be positive",
+            ));
+        }
+
         let domain = if addr.is_ipv4() {
             Domain::IPV4
         } else {
@@ -76,6 +157,25 @@ impl UdpSocketController {
         };
 
         let socket = Socket::new(domain, Type::DGRAM, None)?;
+        // 绑定相关的选项须在绑定之前设置，包括是否双栈
+        if addr.is_ipv6() {
+            socket.set_only_v6(config.ipv6_only)?;
+        }
+        if config.reuse_address {
+            socket.set_reuse_address(true)?;
+        }
+        #[cfg(unix)]
+        if config.reuse_port {
+            uinx::setsockopt(&socket, libc::SOL_SOCKET, libc::SO_REUSEPORT, 1)?;
+        }
+        if let Some(size) = config.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+            warn_if_capped("receive", size, socket.recv_buffer_size()?);
+        }
+        if let Some(size) = config.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+            warn_if_capped("send", size, socket.send_buffer_size()?);
+        }
         if let Err(e) = socket.bind(&addr.into()) {
             log::error!("Failed to bind socket: {}", e);
             return Err(io::Error::new(io::ErrorKind::AddrInUse, e));
@@ -90,8 +190,9 @@ impl UdpSocketController {
             io,
             gso_size: AtomicU16::new(1),
             gro_size: AtomicU16::new(1),
+            dscp: config.tos.map_or(0, |tos| tos & !0b11),
         };
-        socket.config()?;
+        socket.config(config)?;
         Ok(socket)
     }
 
@@ -99,6 +200,48 @@ impl UdpSocketController {
         self.io.local_addr()
     }
 
+    /// Returns whether the socket serves both the IPv4 and IPv6 peers, read
+    /// [`UscConfig::ipv6_only`].
+    ///
+    /// The datagrams to the IPv4 destinations are sent to their IPv4-mapped IPv6 addresses,
+    /// with the ancillary messages of IPv4, such as IP_TOS rather than IPV6_TCLASS.
+    pub fn is_dual_stack(&self) -> io::Result<bool> {
+        Ok(self.local_addr()?.is_ipv6() && !socket2::SockRef::from(&self.io).only_v6()?)
+    }
+
+    /// The size of the receive buffer actually applied by the OS.
+    ///
+    /// Linux doubles the requested size to leave room for its bookkeeping overhead, and reports
+    /// the doubled size.
+    pub fn recv_buffer_size(&self) -> io::Result<usize> {
+        socket2::SockRef::from(&self.io).recv_buffer_size()
+    }
+
+    /// The size of the send buffer actually applied by the OS, read
+    /// [`UdpSocketController::recv_buffer_size`].
+    pub fn send_buffer_size(&self) -> io::Result<usize> {
+        socket2::SockRef::from(&self.io).send_buffer_size()
+    }
+
+    /// The max number of segments that can be sent in one gso send, 1 if gso is not supported.
+    pub fn gso_segments(&self) -> u16 {
+        self.gso_size.load(std::sync::atomic::Ordering::Acquire)
+    }
+
+    /// The max number of segments that can be received in one gro buffer, 1 if gro is not supported.
+    pub fn gro_segments(&self) -> u16 {
+        self.gro_size.load(std::sync::atomic::Ordering::Acquire)
+    }
+
+    /// Try to send the datagrams without waiting for the socket to be writable.
+    ///
+    /// Return the number of `bufs` that have been sent, or an [`io::ErrorKind::WouldBlock`]
+    /// error if the socket is not ready to be written.
+    pub fn try_send(&self, bufs: &[IoSlice<'_>], hdr: &PacketHeader) -> io::Result<usize> {
+        self.io
+            .try_io(Interest::WRITABLE, || self.sendmsg(bufs, hdr))
+    }
+
     pub fn poll_send(
         &self,
         bufs: &[IoSlice<'_>],
@@ -127,8 +270,41 @@ impl UdpSocketController {
     }
 }
 
+/// Convert the IPv4-mapped IPv6 address seen by a dual-stack socket to the plain IPv4 address.
+fn to_canonical(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+            Some(ip) => SocketAddr::new(ip.into(), v6.port()),
+            None => addr,
+        },
+        SocketAddr::V4(_) => addr,
+    }
+}
+
+/// Convert the IPv4 destination to the IPv4-mapped IPv6 address for a dual-stack socket.
+fn to_mapped(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V4(v4) => SocketAddr::new(v4.ip().to_ipv6_mapped().into(), v4.port()),
+        SocketAddr::V6(_) => addr,
+    }
+}
+
+// Linux上读回的缓冲区大小是设置值的两倍
+fn warn_if_capped(kind: &str, requested: usize, applied: usize) {
+    let effective = if cfg!(any(target_os = "linux", target_os = "android")) {
+        applied / 2
+    } else {
+        applied
+    };
+    if effective < requested {
+        log::warn!(
+            "the {kind} buffer size {requested} is capped by the OS, {applied} is applied instead"
+        );
+    }
+}
+
 trait Io {
-    fn config(&self) -> io::Result<()>;
+    fn config(&self, config: &UscConfig) -> io::Result<()>;
 
     fn sendmsg(&self, bufs: &[IoSlice<'_>], hdr: &PacketHeader) -> io::Result<usize>;
 
@@ -147,11 +323,16 @@ impl UdpSocketController {
     }
 
     pub fn receiver(&self) -> Receiver {
+        // with gro, multiple datagrams are coalesced into one buffer, up to 64KB
+        let buf_size = if self.gro_segments() > 1 {
+            u16::MAX as usize
+        } else {
+            1500
+        };
         Receiver {
             usc: self,
-            iovecs: (0..BATCH_SIZE)
-                .map(|_| [0u8; 1500].to_vec())
-                .collect::<Vec<_>>(),
+            buf_size,
+            slab: BytesMut::new(),
             headers: (0..BATCH_SIZE)
                 .map(|_| PacketHeader::default())
                 .collect::<Vec<_>>(),
@@ -176,17 +357,26 @@ impl Future for Send<'_> {
 
 pub struct Receiver<'u> {
     pub usc: &'u UdpSocketController,
-    pub iovecs: Vec<Vec<u8>>,
+    // 每个消息最多占用的接收空间
+    buf_size: usize,
+    // 一批消息依次接收到slab的开头，相邻消息间隔buf_size，取走的数据报从slab上切分出去
+    slab: BytesMut,
     pub headers: Vec<PacketHeader>,
 }
 
 impl Receiver<'_> {
     #[inline]
     pub fn poll_recv(&mut self, cx: &mut Context) -> Poll<io::Result<usize>> {
-        let mut bufs = self
-            .iovecs
-            .iter_mut()
-            .map(|b| IoSliceMut::new(b))
+        let batch_size = self.buf_size * BATCH_SIZE;
+        if self.slab.len() < batch_size {
+            // slab剩余的空间不足一批消息，若从中取走的数据报都已释放，reserve复用原内存，否则重新分配
+            self.slab.clear();
+            self.slab.reserve(SLAB_SIZE.max(batch_size));
+            self.slab.resize(self.slab.capacity(), 0);
+        }
+        let mut bufs = self.slab[..batch_size]
+            .chunks_mut(self.buf_size)
+            .map(IoSliceMut::new)
             .collect::<Vec<_>>();
 
         self.usc.poll_recv(&mut bufs, &mut self.headers, cx)
@@ -196,4 +386,307 @@ impl Receiver<'_> {
     pub async fn recv(&mut self) -> io::Result<usize> {
         core::future::poll_fn(|cx| self.poll_recv(cx)).await
     }
+
+    /// Iterate over the datagrams of the `msg_count` messages received by the last
+    /// [`Receiver::recv`], with the header of the message each datagram belongs to.
+    ///
+    /// With gro, the datagrams from the same source are coalesced into one buffer, they are
+    /// split by the segment size reported by the kernel, the last one may be smaller.
+    pub fn datagrams(&self, msg_count: usize) -> impl Iterator<Item = (&[u8], &PacketHeader)> {
+        core::iter::zip(self.slab.chunks(self.buf_size), &self.headers)
+            .take(msg_count)
+            .flat_map(|(buf, hdr)| {
+                split_segments(&buf[..hdr.seg_size as usize], hdr.gro_seg_size)
+                    .map(move |segment| (segment, hdr))
+            })
+    }
+
+    /// Take the datagrams of the `msg_count` messages received by the last [`Receiver::recv`] out
+    /// of the receive buffers, with the header of the message each datagram belongs to.
+    ///
+    /// Unlike [`Receiver::datagrams`], the datagrams can be owned and passed on. All the messages
+    /// are received into a large slab shared by many receivings, the datagrams are split off it
+    /// without copying, the data parsed from them can be views into the same memory. The next
+    /// receiving goes on with the rest of the slab, a new slab is reserved only when it runs out,
+    /// and the memory is reused once all the datagrams taken from it are dropped.
+    pub fn take_datagrams(
+        &mut self,
+        msg_count: usize,
+    ) -> impl Iterator<Item = (BytesMut, PacketHeader)> + '_ {
+        let (slab, buf_size) = (&mut self.slab, self.buf_size);
+        self.headers[..msg_count]
+            .iter()
+            .enumerate()
+            .flat_map(move |(i, hdr)| {
+                let size = hdr.seg_size as usize;
+                // 最后一个消息之后的空间都留给下次接收，其余消息占满各自的接收空间
+                let mut msg = slab.split_to(if i + 1 == msg_count { size } else { buf_size });
+                msg.truncate(size);
+                split_segments_mut(msg, hdr.gro_seg_size).map(move |segment| (segment, *hdr))
+            })
+    }
+}
+
+/// Split a received buffer into the datagrams coalesced by gro.
+fn split_segments(buf: &[u8], gro_seg_size: u16) -> std::slice::Chunks<'_, u8> {
+    let segment_size = match gro_seg_size {
+        0 => buf.len().max(1),
+        size => size as usize,
+    };
+    buf.chunks(segment_size)
+}
+
+fn split_segments_mut(mut buf: BytesMut, gro_seg_size: u16) -> impl Iterator<Item = BytesMut> {
+    let segment_size = match gro_seg_size {
+        0 => buf.len().max(1),
+        size => size as usize,
+    };
+    core::iter::from_fn(move || {
+        (!buf.is_empty()).then(|| buf.split_to(segment_size.min(buf.len())))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_segments() {
+        let buf = (0..3000).map(|i| i as u8).collect::<Vec<_>>();
+        let segments = split_segments(&buf, 1200).collect::<Vec<_>>();
+        assert_eq!(segments.len(), 3);
+        assert_eq!(segments[0], &buf[..1200]);
+        assert_eq!(segments[1], &buf[1200..2400]);
+        assert_eq!(segments[2], &buf[2400..]);
+
+        // not coalesced
+        assert_eq!(split_segments(&buf, 0).collect::<Vec<_>>(), [&buf[..]]);
+        assert_eq!(split_segments(&[], 0).count(), 0);
+    }
+
+    #[test]
+    fn test_split_segments_mut() {
+        let buf = BytesMut::from_iter((0..3000).map(|i| i as u8));
+        let segments = split_segments_mut(buf.clone(), 1200).collect::<Vec<_>>();
+        assert_eq!(segments.len(), 3);
+        assert_eq!(segments[0], buf[..1200]);
+        assert_eq!(segments[1], buf[1200..2400]);
+        assert_eq!(segments[2], buf[2400..]);
+
+        // 切分出的数据报与接收缓冲区共享内存
+        let buf = BytesMut::from_iter((0..3000).map(|i| i as u8));
+        let ptr = buf.as_ptr();
+        let segments = split_segments_mut(buf, 1200).collect::<Vec<_>>();
+        for (i, segment) in segments.iter().enumerate() {
+            assert_eq!(segment.as_ptr(), ptr.wrapping_add(i * 1200));
+        }
+
+        assert_eq!(split_segments_mut(BytesMut::new(), 0).count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_usc_config() {
+        let config = UscConfig {
+            recv_buffer_size: Some(64 * 1024),
+            send_buffer_size: Some(64 * 1024),
+            gso: false,
+            gro: false,
+            ..Default::default()
+        };
+        let usc =
+            UdpSocketController::with_config("127.0.0.1:0".parse().unwrap(), &config).unwrap();
+        // 64KB在各平台的默认上限之内，读回的大小不小于设置值
+        assert!(usc.recv_buffer_size().unwrap() >= 64 * 1024);
+        assert!(usc.send_buffer_size().unwrap() >= 64 * 1024);
+        assert_eq!(usc.gso_segments(), 1);
+        assert_eq!(usc.gro_segments(), 1);
+
+        let config = UscConfig {
+            recv_buffer_size: Some(0),
+            ..Default::default()
+        };
+        let error =
+            UdpSocketController::with_config("127.0.0.1:0".parse().unwrap(), &config).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_canonical_addr() {
+        let v4: SocketAddr = "1.2.3.4:443".parse().unwrap();
+        let mapped: SocketAddr = "[::ffff:1.2.3.4]:443".parse().unwrap();
+        let v6: SocketAddr = "[2001:db8::1]:443".parse().unwrap();
+        assert_eq!(to_mapped(v4), mapped);
+        assert_eq!(to_canonical(mapped), v4);
+        assert_eq!(to_mapped(v6), v6);
+        assert_eq!(to_canonical(v6), v6);
+        assert_eq!(to_canonical(v4), v4);
+    }
+
+    #[tokio::test]
+    async fn test_dual_stack() {
+        // 不支持IPv6的环境跳过
+        let Ok(v6_peer) = tokio::net::UdpSocket::bind("[::1]:0").await else {
+            return;
+        };
+        let v4_peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let usc = UdpSocketController::new("[::]:0".parse().unwrap()).unwrap();
+        assert!(usc.is_dual_stack().unwrap());
+        let port = usc.local_addr().unwrap().port();
+
+        let mut receiver = usc.receiver();
+        for (peer, local) in [(&v4_peer, "127.0.0.1"), (&v6_peer, "::1")] {
+            // 同一个套接字，分别发往IPv4和IPv6的目的地址
+            let dst = peer.local_addr().unwrap();
+            let hdr = PacketHeader {
+                src: usc.local_addr().unwrap(),
+                dst,
+                ecn: Some(0b10),
+                seg_size: 5,
+                ..Default::default()
+            };
+            let sent = usc.send(&[IoSlice::new(b"hello")], hdr).await.unwrap();
+            assert_eq!(sent, 1);
+            let mut buf = [0u8; 16];
+            let (n, from) = peer.recv_from(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], b"hello");
+            assert_eq!(from.port(), port);
+
+            // 收到的IPv4数据报，地址是普通的IPv4地址而非映射地址
+            let local: std::net::IpAddr = local.parse().unwrap();
+            peer.send_to(b"world", (local, port)).await.unwrap();
+            let msg_count = receiver.recv().await.unwrap();
+            let (datagram, hdr) = receiver.datagrams(msg_count).next().unwrap();
+            assert_eq!(datagram, b"world");
+            assert_eq!(hdr.src, dst);
+            assert_eq!(hdr.dst.ip(), local);
+        }
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[tokio::test]
+    async fn test_ttl_to_mapped_destination() {
+        // 不支持IPv6的环境跳过
+        if tokio::net::UdpSocket::bind("[::1]:0").await.is_err() {
+            return;
+        }
+        let usc = UdpSocketController::new("[::]:0".parse().unwrap()).unwrap();
+        let peer = UdpSocketController::new("127.0.0.1:0".parse().unwrap()).unwrap();
+        // 目的地址是IPv4映射的IPv6地址，须以IP_TTL而非IPV6_HOPLIMIT设置TTL
+        let port = peer.local_addr().unwrap().port();
+        let hdr = PacketHeader {
+            src: usc.local_addr().unwrap(),
+            dst: SocketAddr::new("::ffff:127.0.0.1".parse().unwrap(), port),
+            ttl: 32,
+            seg_size: 5,
+            ..Default::default()
+        };
+        let sent = usc.send(&[IoSlice::new(b"hello")], hdr).await.unwrap();
+        assert_eq!(sent, 1);
+
+        let mut receiver = peer.receiver();
+        let msg_count = receiver.recv().await.unwrap();
+        let (datagram, hdr) = receiver.datagrams(msg_count).next().unwrap();
+        assert_eq!(datagram, b"hello");
+        assert_eq!(hdr.ttl, 32);
+    }
+
+    #[tokio::test]
+    async fn test_take_small_datagrams() {
+        let sender = UdpSocketController::new("127.0.0.1:0".parse().unwrap()).unwrap();
+        let receiver = UdpSocketController::new("127.0.0.1:0".parse().unwrap()).unwrap();
+        let hdr = PacketHeader {
+            src: sender.local_addr().unwrap(),
+            dst: receiver.local_addr().unwrap(),
+            seg_size: 100,
+            ..Default::default()
+        };
+        let mut receiver = receiver.receiver();
+        let mut datagrams = vec![];
+        for i in 0..2u8 {
+            let payload = [i; 100];
+            sender.send(&[IoSlice::new(&payload)], hdr).await.unwrap();
+            let recv = tokio::time::timeout(std::time::Duration::from_secs(1), receiver.recv());
+            let msg_count = recv.await.unwrap().unwrap();
+            assert_eq!(msg_count, 1);
+            let slab_len = receiver.slab.len();
+            datagrams.extend(
+                receiver
+                    .take_datagrams(msg_count)
+                    .map(|(datagram, _)| datagram),
+            );
+            assert_eq!(datagrams[i as usize], payload[..]);
+            // 小数据报只从slab上切走其自身的大小
+            assert_eq!(receiver.slab.len(), slab_len - payload.len());
+        }
+        // 两次接收的数据报在同一个slab上首尾相接，没有重新分配或拷贝
+        assert_eq!(
+            datagrams[1].as_ptr(),
+            datagrams[0].as_ptr().wrapping_add(100)
+        );
+        assert_eq!(
+            receiver.slab.as_ptr(),
+            datagrams[1].as_ptr().wrapping_add(100)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_recv_gro() {
+        const SEGMENT_SIZE: usize = 1200;
+        const LAST_SEGMENT_SIZE: usize = 500;
+        const SEGMENTS: usize = 16;
+
+        let sender = UdpSocketController::new("127.0.0.1:0".parse().unwrap()).unwrap();
+        let receiver = UdpSocketController::new("127.0.0.1:0".parse().unwrap()).unwrap();
+        let sender_addr = sender.local_addr().unwrap();
+        let hdr = PacketHeader {
+            src: sender_addr,
+            dst: receiver.local_addr().unwrap(),
+            seg_size: SEGMENT_SIZE as u16,
+            gso: sender.gso_segments() > 1,
+            ..Default::default()
+        };
+
+        // 每个数据报以其序号填充，以验证切分的结果；最后一个数据报较小
+        let sizes = (0..SEGMENTS)
+            .map(|i| match i {
+                i if i == SEGMENTS - 1 => LAST_SEGMENT_SIZE,
+                _ => SEGMENT_SIZE,
+            })
+            .collect::<Vec<_>>();
+        let payloads = sizes
+            .iter()
+            .enumerate()
+            .map(|(i, &size)| vec![i as u8; size])
+            .collect::<Vec<_>>();
+        let iovecs = payloads.iter().map(|p| IoSlice::new(p)).collect::<Vec<_>>();
+        let mut sent = 0;
+        while sent < iovecs.len() {
+            sent += sender.send(&iovecs[sent..], hdr).await.unwrap();
+        }
+
+        let mut receiver = receiver.receiver();
+        let mut received = vec![];
+        let mut messages = 0;
+        let mut coalesced = false;
+        while received.len() < SEGMENTS {
+            let recv = tokio::time::timeout(std::time::Duration::from_secs(1), receiver.recv());
+            let msg_count = recv.await.expect("datagrams lost").unwrap();
+            messages += msg_count;
+            for (datagram, hdr) in receiver.datagrams(msg_count) {
+                assert_eq!(hdr.src, sender_addr);
+                coalesced |= hdr.gro_seg_size == SEGMENT_SIZE as u16;
+                received.push(datagram.to_vec());
+            }
+        }
+
+        // 切分出的数据报个数、大小、内容与发送的一致，GRO合并的最后一个数据报可以较小
+        assert_eq!(received.len(), SEGMENTS);
+        assert_eq!(received.iter().map(Vec::len).collect::<Vec<_>>(), sizes);
+        assert_eq!(received, payloads);
+        // 发送端GSO、接收端GRO都可用时，多个数据报合并在更少的消息中收到
+        if sender.gso_segments() > 1 && receiver.usc.gro_segments() > 1 {
+            assert!(coalesced);
+            assert!(messages < SEGMENTS, "{messages} messages");
+        }
+    }
 }
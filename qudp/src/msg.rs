@@ -12,7 +12,7 @@ use crate::{
     PacketHeader, UdpSocketController, BATCH_SIZE,
 };
 
-pub(crate) const CMSG_LEN: usize = 88;
+pub(crate) const CMSG_LEN: usize = 128;
 
 #[cfg(target_os = "freebsd")]
 type IpTosTy = libc::c_uchar;
@@ -78,7 +78,10 @@ impl Message {
             let mut cmsghdr = unsafe { CmsgHdr::new(hdr) };
             let ecn = pkt_hdr.ecn.unwrap_or(0) as libc::c_int;
 
-            if pkt_hdr.dst.is_ipv4() {
+            // 双栈套接字发往IPv4地址时，须使用IPv4的控制消息，IPV6_TCLASS/IPV6_HOPLIMIT会被忽略
+            let is_ipv4 = pkt_hdr.dst.is_ipv4()
+                || matches!(pkt_hdr.dst.ip(), IpAddr::V6(ip) if ip.to_ipv4_mapped().is_some());
+            if is_ipv4 {
                 cmsghdr.append(libc::IPPROTO_IP, libc::IP_TOS, ecn as IpTosTy);
             } else {
                 cmsghdr.append(libc::IPPROTO_IPV6, libc::IPV6_TCLASS, ecn);
@@ -87,6 +90,17 @@ impl Message {
             if gso_size > 1 {
                 UdpSocketController::set_segment_size(&mut cmsghdr, pkt_hdr.seg_size);
             }
+
+            // Set the ttl/hop limit for this send only, the socket's default will not be changed.
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            if pkt_hdr.ttl != crate::DEFAULT_TTL as u8 {
+                let ttl = pkt_hdr.ttl as libc::c_int;
+                if is_ipv4 {
+                    cmsghdr.append(libc::IPPROTO_IP, libc::IP_TTL, ttl);
+                } else {
+                    cmsghdr.append(libc::IPPROTO_IPV6, libc::IPV6_HOPLIMIT, ttl);
+                }
+            }
             cmsghdr.finish();
         }
     }
@@ -126,6 +140,7 @@ impl Message {
             let cmsg_iter = unsafe { Iter::new(hdr) };
 
             let recv_hdr = &mut recv_hdrs[i];
+            recv_hdr.gro_seg_size = 0;
             for cmsg in cmsg_iter {
                 match (cmsg.cmsg_level, cmsg.cmsg_type) {
                     (libc::IPPROTO_IP, libc::IP_TOS) | (libc::IPPROTO_IP, libc::IP_RECVTOS) => unsafe {
@@ -169,6 +184,10 @@ impl Message {
                     (libc::IPPROTO_IP, libc::IP_RECVTTL) => unsafe {
                         recv_hdr.ttl = decode::<u32, libc::cmsghdr>(cmsg) as u8;
                     },
+                    #[cfg(target_os = "linux")]
+                    (libc::SOL_UDP, libc::UDP_GRO) => unsafe {
+                        recv_hdr.gro_seg_size = decode::<libc::c_int, libc::cmsghdr>(cmsg) as u16;
+                    },
                     _ => {
                         log::warn!(
                             "read unkown level {} cmsg {}",
@@ -1,14 +1,13 @@
-use std::{cmp, io::IoSlice, mem, net::SocketAddr, os::fd::AsRawFd};
+use std::{cmp, io::IoSlice, mem, os::fd::AsRawFd};
 
 use socket2::SockAddr;
 
 use crate::{
-    cmsghdr::CmsgHdr, io, msg::Message, Io, PacketHeader, UdpSocketController, BATCH_SIZE,
-    DEFAULT_TTL,
+    cmsghdr::CmsgHdr, io, msg::Message, to_canonical, to_mapped, Io, PacketHeader,
+    UdpSocketController, UscConfig, BATCH_SIZE, DEFAULT_TTL,
 };
 
 const OPTION_ON: libc::c_int = 1;
-const OPTION_OFF: libc::c_int = 0;
 
 pub trait Gso: Io {
     fn max_gso_segments(&self) -> u16;
@@ -40,7 +39,7 @@ macro_rules! handle_io_error {
 }
 
 impl Io for UdpSocketController {
-    fn config(&self) -> io::Result<()> {
+    fn config(&self, config: &UscConfig) -> io::Result<()> {
         let io = socket2::SockRef::from(&self.io);
         io.set_nonblocking(true)?;
 
@@ -71,10 +70,6 @@ impl Io for UdpSocketController {
         }
         // Options standardized in RFC 3542
         else {
-            //  If this flag is set to false (zero), then the socket can
-            //  be used to send and receive packets to and from an IPv6
-            //  address or an IPv4-mapped IPv6 address.
-            self.setsockopt(libc::IPPROTO_IPV6, libc::IPV6_V6ONLY, OPTION_OFF);
             // Set delivery of the IPV6_PKTINFO control message on incoming datagrams.
             self.setsockopt(libc::IPPROTO_IPV6, libc::IPV6_RECVPKTINFO, OPTION_ON);
             self.setsockopt(libc::IPPROTO_IPV6, libc::IPV6_RECVTCLASS, OPTION_ON);
@@ -87,14 +82,35 @@ impl Io for UdpSocketController {
             self.setsockopt(libc::IPPROTO_IPV6, libc::IPV6_UNICAST_HOPS, DEFAULT_TTL);
         }
 
-        self.gso_size.store(
-            self.max_gso_segments(),
-            std::sync::atomic::Ordering::Release,
-        );
-        self.gro_size.store(
-            self.max_gro_segments(),
-            std::sync::atomic::Ordering::Release,
-        );
+        if let Some(tos) = config.tos {
+            if is_ipv4 {
+                self.setsockopt(libc::IPPROTO_IP, libc::IP_TOS, tos as libc::c_int);
+            } else {
+                self.setsockopt(libc::IPPROTO_IPV6, libc::IPV6_TCLASS, tos as libc::c_int);
+            }
+        }
+
+        // gso/gro被关闭时，如同平台不支持
+        let gso_size = if config.gso {
+            self.max_gso_segments()
+        } else {
+            1
+        };
+        let gro_size = if config.gro {
+            self.max_gro_segments()
+        } else {
+            1
+        };
+        self.gso_size
+            .store(gso_size, std::sync::atomic::Ordering::Release);
+        self.gro_size
+            .store(gro_size, std::sync::atomic::Ordering::Release);
+        // Enable gro, the datagrams from the same source are coalesced into one buffer,
+        // the segment size is reported by the UDP_GRO ancillary message.
+        #[cfg(target_os = "linux")]
+        if gro_size > 1 {
+            self.setsockopt(libc::SOL_UDP, libc::UDP_GRO, OPTION_ON);
+        }
 
         Ok(())
     }
@@ -106,8 +122,16 @@ impl Io for UdpSocketController {
     fn sendmsg(&self, bufs: &[IoSlice<'_>], send_hdr: &PacketHeader) -> io::Result<usize> {
         let io = socket2::SockRef::from(&self.io);
 
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        if send_hdr.ttl != DEFAULT_TTL as u8 {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "setting ttl for each send is not supported on this platform",
+            ));
+        }
+
         let gso_size = if send_hdr.gso {
-            let max_gso = self.max_gso_segments();
+            let max_gso = self.gso_segments();
             let max_payloads = u16::MAX / send_hdr.seg_size;
             cmp::min(max_gso, max_payloads)
         } else {
@@ -115,18 +139,20 @@ impl Io for UdpSocketController {
         };
 
         let dst: SockAddr = if self.local_addr()?.is_ipv6() && !io.only_v6()? {
-            match send_hdr.dst.ip() {
-                std::net::IpAddr::V4(ip) => SocketAddr::new(
-                    std::net::IpAddr::V6(ip.to_ipv6_mapped()),
-                    send_hdr.dst.port(),
-                )
-                .into(),
-                std::net::IpAddr::V6(_) => send_hdr.dst.into(),
-            }
+            to_mapped(send_hdr.dst).into()
         } else {
             send_hdr.dst.into()
         };
 
+        // 逐包设置的TOS会覆盖套接字的TOS，需带上配置的DSCP
+        let send_hdr = &PacketHeader {
+            ecn: match self.dscp {
+                0 => send_hdr.ecn,
+                dscp => Some(dscp | (send_hdr.ecn.unwrap_or(0) & 0b11)),
+            },
+            ..*send_hdr
+        };
+
         #[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "openbsd",)))]
         return sendmmsg(&self.io, bufs, send_hdr, &dst, gso_size);
 
@@ -173,6 +199,10 @@ impl Io for UdpSocketController {
         };
 
         msg.decode_recv(recv_hdrs, msg_count, self.local_addr()?.port());
+        for hdr in &mut recv_hdrs[..msg_count] {
+            hdr.src = to_canonical(hdr.src);
+            hdr.dst = to_canonical(hdr.dst);
+        }
         Ok(msg_count)
     }
 }
@@ -206,9 +236,10 @@ pub(super) fn sendmmsg(
             hdr.msg_iovlen = iovec.len() as _;
         }
 
-        let mut msgvec = message.hdrs.as_mut_ptr();
-        let mut vlen = mmsg_batch_size as u32;
-        loop {
+        let mut sent_msgs = 0;
+        while sent_msgs < mmsg_batch_size {
+            let msgvec = message.hdrs[sent_msgs..].as_mut_ptr();
+            let vlen = (mmsg_batch_size - sent_msgs) as u32;
             let ret =
                 to_result(unsafe { libc::sendmmsg(io.as_raw_fd(), msgvec, vlen, 0) } as isize);
 
@@ -217,19 +248,27 @@ pub(super) fn sendmmsg(
                 // msgvec; if this is less than vlen, the caller can retry with a
                 // further sendmmsg() call to send the remaining messages.
                 Ok(n) => {
-                    sent_packets += n;
                     if n != vlen as usize {
                         log::warn!("sendmmsg : only {} messages sent out of {}", n, vlen);
-                        vlen = n as u32 - vlen;
-                        msgvec = message.hdrs[n..].as_mut_ptr();
-                        continue;
                     }
-                    break;
+                    // each message carries several segments with gso
+                    sent_packets += iovecs[sent_msgs..sent_msgs + n]
+                        .iter()
+                        .map(Vec::len)
+                        .sum::<usize>();
+                    sent_msgs += n;
                 }
                 Err(e) => match e.raw_os_error() {
                     Some(libc::EINTR) => continue,
                     Some(libc::EWOULDBLOCK) if sent_packets > 0 => return Ok(sent_packets),
                     Some(libc::EWOULDBLOCK) if sent_packets == 0 => return Err(e),
+                    // EIO: the gso is not supported by the device,
+                    // EMSGSIZE: the segment size or the number of segments is too large,
+                    // caller should back off the segment size
+                    Some(libc::EIO) | Some(libc::EMSGSIZE) if sent_packets > 0 => {
+                        return Ok(sent_packets)
+                    }
+                    Some(libc::EIO) | Some(libc::EMSGSIZE) => return Err(e),
                     Some(libc::EBADE) | Some(libc::EPIPE) | Some(libc::ENOTCONN) => return Err(e),
                     _ => break,
                 },
@@ -263,13 +302,17 @@ pub(super) fn sendmsg(
             let ret = to_result(unsafe { libc::sendmsg(io.as_raw_fd(), hdr, 0) });
             match ret {
                 Ok(_n) => {
-                    sent_packets += 1;
+                    sent_packets += batch.len();
                     break;
                 }
                 Err(e) => match e.raw_os_error() {
                     Some(libc::EINTR) => continue,
                     Some(libc::EWOULDBLOCK) if sent_packets > 0 => return Ok(sent_packets),
                     Some(libc::EWOULDBLOCK) if sent_packets == 0 => return Err(e),
+                    Some(libc::EIO) | Some(libc::EMSGSIZE) if sent_packets > 0 => {
+                        return Ok(sent_packets)
+                    }
+                    Some(libc::EIO) | Some(libc::EMSGSIZE) => return Err(e),
                     Some(libc::EBADF) | Some(libc::EPIPE) | Some(libc::ENOTCONN) => return Err(e),
                     _ => break,
                 },
@@ -389,7 +432,7 @@ impl Gro for UdpSocketController {
     }
 }
 
-fn setsockopt(
+pub(super) fn setsockopt(
     socket: &impl AsRawFd,
     level: libc::c_int,
     name: libc::c_int,
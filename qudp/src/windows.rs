@@ -11,7 +11,7 @@ use windows_sys::Win32::Networking::WinSock;
 
 use crate::{
     cmsghdr::{self, Cmsg, MsgHdr},
-    Io, PacketHeader, UdpSocketController, DEFAULT_TTL,
+    to_canonical, to_mapped, Io, PacketHeader, UdpSocketController, UscConfig, DEFAULT_TTL,
 };
 
 const OPTION_ON: libc::c_int = 1;
@@ -81,7 +81,7 @@ const CMSG_LEN: usize = 128;
 pub(crate) struct Aligned<T>(pub(crate) T);
 
 impl Io for UdpSocketController {
-    fn config(&self) -> std::io::Result<()> {
+    fn config(&self, config: &UscConfig) -> std::io::Result<()> {
         let io = socket2::SockRef::from(&self.io);
         io.set_nonblocking(true)?;
 
@@ -102,6 +102,14 @@ impl Io for UdpSocketController {
             self.setsockopt(WinSock::IPPROTO_IPV6, WinSock::IPV6_RECVTCLASS, OPTION_ON);
             self.setsockopt(WinSock::IPPROTO_IPV6, WinSock::IPV6_PKTINFO, OPTION_ON);
         }
+
+        if let Some(tos) = config.tos {
+            if is_ipv4 {
+                self.setsockopt(WinSock::IPPROTO_IP, WinSock::IP_TOS, tos as c_int);
+            } else {
+                self.setsockopt(WinSock::IPPROTO_IPV6, WinSock::IPV6_TCLASS, tos as c_int);
+            }
+        }
         Ok(())
     }
 
@@ -110,10 +118,20 @@ impl Io for UdpSocketController {
         bufs: &[std::io::IoSlice<'_>],
         hdr: &crate::PacketHeader,
     ) -> std::io::Result<usize> {
+        if hdr.ttl != DEFAULT_TTL as u8 {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "setting ttl for each send is not supported on this platform",
+            ));
+        }
+
         let mut ctrl_buf = Aligned([0; CMSG_LEN]);
 
-        // FIXME: Ipv6 mapped address should be supported
-        let dst = socket2::SockAddr::from(hdr.dst);
+        let dst = if self.is_dual_stack()? {
+            socket2::SockAddr::from(to_mapped(hdr.dst))
+        } else {
+            socket2::SockAddr::from(hdr.dst)
+        };
         let mut count = 0;
 
         for buf in bufs {
@@ -282,12 +300,13 @@ impl Io for UdpSocketController {
             self.local_addr()?
         };
         hdr[0] = PacketHeader {
-            src: addr.unwrap(),
-            dst,
+            src: to_canonical(addr.unwrap()),
+            dst: to_canonical(dst),
             ttl: DEFAULT_TTL as u8,
             ecn: Some(ecn_bits as u8),
             seg_size: len as u16,
             gso: false,
+            gro_seg_size: 0,
         };
 
         Ok(1)
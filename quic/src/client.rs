@@ -11,7 +11,8 @@ use qbase::{
     sid::{handy::ConsistentConcurrency, ControlConcurrency},
     token::{ArcTokenRegistry, TokenSink},
 };
-use qconnection::{conn::ArcConnection, path::Pathway};
+use qcongestion::CongestionAlgorithm;
+use qconnection::{conn::builder::ConnectionBuilder, path::Pathway, tls::SessionCache};
 use rustls::{
     client::WantsClientCert,
     pki_types::{CertificateDer, PrivateKeyDer},
@@ -27,11 +28,13 @@ pub struct QuicClient {
     addresses: Vec<SocketAddr>,
     _reuse_connection: bool,
     _enable_happy_eyepballs: bool,
-    _prefered_versions: Vec<u32>,
+    preferred_versions: Vec<u32>,
     parameters: Parameters,
     tls_config: Arc<TlsClientConfig>,
     streams_controller: Box<dyn Fn(u64, u64) -> Box<dyn ControlConcurrency> + Send + Sync>,
+    congestion_algorithm: CongestionAlgorithm,
     token_sink: Option<Arc<dyn TokenSink>>,
+    session_cache: Option<Arc<dyn SessionCache>>,
 }
 
 impl QuicClient {
@@ -78,7 +81,9 @@ impl QuicClient {
             parameters: Parameters::default(),
             tls_config: TlsClientConfig::builder_with_protocol_versions(&[&rustls::version::TLS13]),
             streams_controller: Box::new(|bi, uni| Box::new(ConsistentConcurrency::new(bi, uni))),
+            congestion_algorithm: CongestionAlgorithm::default(),
             token_sink: None,
+            session_cache: None,
         }
     }
 
@@ -94,7 +99,9 @@ impl QuicClient {
             parameters: Parameters::default(),
             tls_config,
             streams_controller: Box::new(|bi, uni| Box::new(ConsistentConcurrency::new(bi, uni))),
+            congestion_algorithm: CongestionAlgorithm::default(),
             token_sink: None,
+            session_cache: None,
         }
     }
 
@@ -161,14 +168,17 @@ impl QuicClient {
             None => ArcTokenRegistry::default_sink(server_name.clone()),
         };
 
-        let inner = ArcConnection::new_client(
-            initial_scid,
-            server_name,
-            self.parameters,
-            streams_ctrl,
-            self.tls_config.clone(),
-            token_registry,
-        );
+        let mut builder =
+            ConnectionBuilder::client(initial_scid, server_name, self.tls_config.clone())
+                .with_parameters(self.parameters)
+                .with_versions(self.preferred_versions.clone())
+                .with_streams_controller(streams_ctrl)
+                .with_congestion_control(self.congestion_algorithm)
+                .with_token_registry(token_registry);
+        if let Some(session_cache) = self.session_cache.clone() {
+            builder = builder.with_session_cache(session_cache);
+        }
+        let inner = builder.build();
         let conn = QuicConnection {
             _key: ConnKey::Client(initial_scid),
             inner: inner.clone(),
@@ -188,7 +198,9 @@ pub struct QuicClientBuilder<T> {
     parameters: Parameters,
     tls_config: T,
     streams_controller: Box<dyn Fn(u64, u64) -> Box<dyn ControlConcurrency> + Send + Sync>,
+    congestion_algorithm: CongestionAlgorithm,
     token_sink: Option<Arc<dyn TokenSink>>,
+    session_cache: Option<Arc<dyn SessionCache>>,
 }
 
 impl<T> QuicClientBuilder<T> {
@@ -229,6 +241,12 @@ impl<T> QuicClientBuilder<T> {
         self
     }
 
+    /// 设置新连接的各路径所使用的拥塞控制算法，默认为NewReno
+    pub fn with_congestion_control(mut self, algorithm: CongestionAlgorithm) -> Self {
+        self.congestion_algorithm = algorithm;
+        self
+    }
+
     /// 设置客户端的证书，用于传输给服务端验证客户端身份
     /// 一般情况下，客户端都无需设置证书，只有特别的安全需求，才需要客户端提交证书
     /// 设置TokenRegisty的方法，当收到服务端的NewToken，客户端自行决定如何保存。
@@ -239,6 +257,14 @@ impl<T> QuicClientBuilder<T> {
         self.token_sink = Some(sink);
         self
     }
+
+    /// 设置SessionCache，用于保存服务端的传输参数，以便未来连接同一服务端时尝试0-RTT。
+    /// 会话票据由rustls的ClientSessionStore保存，设置之后会开启TLS的early data。
+    /// 如不设置，则不会尝试0-RTT
+    pub fn with_session_cache(mut self, cache: Arc<dyn SessionCache>) -> Self {
+        self.session_cache = Some(cache);
+        self
+    }
 }
 
 impl QuicClientBuilder<TlsClientConfigBuilder<WantsVerifier>> {
@@ -255,7 +281,9 @@ impl QuicClientBuilder<TlsClientConfigBuilder<WantsVerifier>> {
             parameters: self.parameters,
             tls_config: self.tls_config.with_root_certificates(root_store),
             streams_controller: self.streams_controller,
+            congestion_algorithm: self.congestion_algorithm,
             token_sink: self.token_sink,
+            session_cache: self.session_cache,
         }
     }
     pub fn with_webpki_verifier(
@@ -270,7 +298,9 @@ impl QuicClientBuilder<TlsClientConfigBuilder<WantsVerifier>> {
             parameters: self.parameters,
             tls_config: self.tls_config.with_webpki_verifier(verifier),
             streams_controller: self.streams_controller,
+            congestion_algorithm: self.congestion_algorithm,
             token_sink: self.token_sink,
+            session_cache: self.session_cache,
         }
     }
 }
@@ -287,6 +317,16 @@ impl QuicClientBuilder<TlsClientConfigBuilder<WantsClientCert>> {
         let key = std::fs::read(key_file).unwrap();
         let key_der = PrivateKeyDer::try_from(key).unwrap();
 
+        self.with_cert_chain(cert_chain, key_der)
+    }
+
+    /// 向要求客户端认证的服务端出示证书链，证书链以终端实体证书开头，其后是中间证书，
+    /// 均为DER编码。用于双向TLS。
+    pub fn with_cert_chain(
+        self,
+        cert_chain: Vec<CertificateDer<'static>>,
+        key_der: PrivateKeyDer<'static>,
+    ) -> QuicClientBuilder<TlsClientConfig> {
         QuicClientBuilder {
             addresses: self.addresses,
             reuse_connection: self.reuse_connection,
@@ -298,7 +338,9 @@ impl QuicClientBuilder<TlsClientConfigBuilder<WantsClientCert>> {
                 .with_client_auth_cert(cert_chain, key_der)
                 .expect("The private key was wrong encoded or failed validation"),
             streams_controller: self.streams_controller,
+            congestion_algorithm: self.congestion_algorithm,
             token_sink: self.token_sink,
+            session_cache: self.session_cache,
         }
     }
 
@@ -311,7 +353,9 @@ impl QuicClientBuilder<TlsClientConfigBuilder<WantsClientCert>> {
             parameters: self.parameters,
             tls_config: self.tls_config.with_no_client_auth(),
             streams_controller: self.streams_controller,
+            congestion_algorithm: self.congestion_algorithm,
             token_sink: self.token_sink,
+            session_cache: self.session_cache,
         }
     }
 
@@ -327,7 +371,9 @@ impl QuicClientBuilder<TlsClientConfigBuilder<WantsClientCert>> {
             parameters: self.parameters,
             tls_config: self.tls_config.with_client_cert_resolver(cert_resolver),
             streams_controller: self.streams_controller,
+            congestion_algorithm: self.congestion_algorithm,
             token_sink: self.token_sink,
+            session_cache: self.session_cache,
         }
     }
 }
@@ -347,16 +393,21 @@ impl QuicClientBuilder<TlsClientConfig> {
         self
     }
 
-    pub fn build(self) -> QuicClient {
+    pub fn build(mut self) -> QuicClient {
+        if self.session_cache.is_some() {
+            self.tls_config.enable_early_data = true;
+        }
         QuicClient {
             addresses: self.addresses,
             _reuse_connection: self.reuse_connection,
             _enable_happy_eyepballs: self.enable_happy_eyepballs,
-            _prefered_versions: self.preferred_versions,
+            preferred_versions: self.preferred_versions,
             parameters: self.parameters,
             tls_config: Arc::new(self.tls_config),
             streams_controller: self.streams_controller,
+            congestion_algorithm: self.congestion_algorithm,
             token_sink: self.token_sink,
+            session_cache: self.session_cache,
         }
     }
 }
@@ -1,17 +1,20 @@
-use std::{io, net::SocketAddr, sync::LazyLock};
+use std::{
+    io::{self, IoSlice},
+    net::SocketAddr,
+    sync::LazyLock,
+};
 
-use bytes::BytesMut;
 use dashmap::DashMap;
 use deref_derive::Deref;
 use qbase::{
     cid::ConnectionId,
-    packet::{header::GetDcid, Packet, PacketReader, RetryHeader, VersionNegotiationHeader},
+    packet::{header::GetDcid, DataHeader, Ecn, Packet, RetryPacket, VersionNegotiationHeader},
 };
 use qconnection::{
     conn::ArcConnection,
     path::Pathway,
     router::Router,
-    usc::{ArcUsc, UscRegistry},
+    usc::{ArcUsc, UscConfig, UscRegistry},
 };
 
 pub mod client;
@@ -43,11 +46,11 @@ pub struct QuicConnection {
 }
 
 impl QuicConnection {
-    pub fn recv_version_negotiation(&self, _vn: &VersionNegotiationHeader) {
-        // self.inner.recv_version_negotiation(vn);
+    pub fn recv_version_negotiation(&self, vn: &VersionNegotiationHeader) {
+        self.inner.recv_version_negotiation(vn);
     }
 
-    pub fn recv_retry_packet(&self, retry: &RetryHeader) {
+    pub fn recv_retry_packet(&self, retry: RetryPacket) {
         self.inner.recv_retry_packet(retry);
     }
 
@@ -65,37 +68,58 @@ impl Drop for QuicConnection {
 }
 
 pub fn get_or_create_usc(bind_addr: &SocketAddr) -> io::Result<ArcUsc> {
+    get_or_create_usc_with_config(bind_addr, &UscConfig::default())
+}
+
+/// Get or create the [`ArcUsc`] bound to the `bind_addr`, a newly created udp socket is
+/// configured by the `config`, read [`UscRegistry::get_or_create_usc_with_config`].
+pub fn get_or_create_usc_with_config(
+    bind_addr: &SocketAddr,
+    config: &UscConfig,
+) -> io::Result<ArcUsc> {
     let recv_task = |usc: ArcUsc| async move {
         let mut receiver = usc.receiver();
         while let Ok(msg_count) = receiver.recv().await {
-            for (hdr, buf) in core::iter::zip(&receiver.headers, &receiver.iovecs).take(msg_count) {
-                let data: BytesMut = buf[0..hdr.seg_size as usize].into();
+            // 数据报从接收缓冲区中取出而非拷贝，之后解析出的流数据都是其上的视图
+            for (data, hdr) in receiver.take_datagrams(msg_count) {
                 let pathway = Pathway::Direct {
                     local: hdr.dst,
                     remote: hdr.src,
                 };
 
-                let reader = PacketReader::new(data, 8);
-                for pkt in reader.flatten() {
-                    accpet_packet(pkt, pathway, &usc);
-                }
+                let ecn = hdr.ecn.map(Ecn::from).unwrap_or_default();
+
+                Router::route_datagram(data, ecn, pathway, &usc, |packet| {
+                    accpet_packet(packet, ecn, pathway, &usc);
+                });
             }
         }
     };
 
-    let usc = UscRegistry::get_or_create_usc(*bind_addr, recv_task)?;
+    let usc = UscRegistry::get_or_create_usc_with_config(*bind_addr, config, recv_task)?;
     Ok(usc)
 }
 
-fn accpet_packet(packet: Packet, pathway: Pathway, usc: &ArcUsc) {
+fn accpet_packet(packet: Packet, ecn: Ecn, pathway: Pathway, usc: &ArcUsc) {
     match packet {
         Packet::Data(packet) => {
-            if let Err(packet) = Router::try_to_route_packet_from(packet, pathway, usc) {
-                ArcQuicServer::try_to_accept_conn_from(packet, pathway, usc);
+            if let DataHeader::Short(hdr) = &packet.header {
+                // 无法识别的1-RTT包，回应一个无状态重置
+                if let Some(reset) = Router::stateless_reset(hdr.get_dcid(), packet.bytes.len()) {
+                    let usc = usc.clone();
+                    tokio::spawn(async move {
+                        _ = usc
+                            .send_all_via_pathway(&[IoSlice::new(&reset)], pathway, None)
+                            .await;
+                    });
+                }
+                return;
             }
+            ArcQuicServer::try_to_accept_conn_from(packet, ecn, pathway, usc);
         }
         Packet::VN(vn) => {
-            let key = ConnKey::Server(*vn.get_dcid());
+            // 版本协商包只会发给客户端，其dcid即客户端的initial scid
+            let key = ConnKey::Client(*vn.get_dcid());
             if let Some(conn) = CONNECTIONS.get(&key) {
                 conn.recv_version_negotiation(&vn);
                 conn.update_path_recv_time(pathway);
@@ -104,9 +128,10 @@ fn accpet_packet(packet: Packet, pathway: Pathway, usc: &ArcUsc) {
             }
         }
         Packet::Retry(retry) => {
-            let key = ConnKey::Server(*retry.get_dcid());
+            // Retry包只会发给客户端，其dcid即客户端的initial scid
+            let key = ConnKey::Client(*retry.get_dcid());
             if let Some(conn) = CONNECTIONS.get(&key) {
-                conn.recv_retry_packet(&retry);
+                conn.recv_retry_packet(retry);
                 conn.update_path_recv_time(pathway);
             } else {
                 log::error!("No connection found for Retry packet");
@@ -1,6 +1,6 @@
 use std::{
     collections::HashMap,
-    io::{self},
+    io::{self, IoSlice},
     iter::FusedIterator,
     net::SocketAddr,
     path::Path,
@@ -8,25 +8,35 @@ use std::{
 };
 
 use dashmap::DashMap;
+use futures::Stream;
 use qbase::{
-    cid::ConnectionId,
+    cid::{ArcCidGenerator, ConnectionId},
     packet::{
         header::{GetDcid, GetScid},
-        long, DataHeader, DataPacket, InitialHeader, RetryHeader,
+        long, DataHeader, DataPacket, Ecn, InitialHeader, RetryHeader,
     },
     param::{Parameters, ServerParameters},
     sid::{handy::ConsistentConcurrency, ControlConcurrency},
     token::{ArcTokenRegistry, TokenProvider},
     util::ArcAsyncDeque,
 };
-use qconnection::{conn::ArcConnection, path::Pathway, router::Router, usc::ArcUsc};
+use qcongestion::CongestionAlgorithm;
+use qconnection::{
+    conn::{builder::ConnectionBuilder, version::initial_keys_version},
+    path::Pathway,
+    router::{RetryPolicy, Router, ValidatedToken},
+    usc::{ArcUsc, UscConfig},
+};
 use rustls::{
     pki_types::{CertificateDer, PrivateKeyDer},
-    server::{danger::ClientCertVerifier, NoClientAuth, ResolvesServerCert, WantsServerCert},
-    ConfigBuilder, ServerConfig as TlsServerConfig, WantsVerifier,
+    server::{
+        danger::ClientCertVerifier, NoClientAuth, ResolvesServerCert, VerifierBuilderError,
+        WantsServerCert, WebPkiClientVerifier,
+    },
+    ConfigBuilder, RootCertStore, ServerConfig as TlsServerConfig, WantsVerifier,
 };
 
-use crate::{get_or_create_usc, ConnKey, QuicConnection, CONNECTIONS};
+use crate::{get_or_create_usc_with_config, ConnKey, QuicConnection, CONNECTIONS};
 
 type TlsServerConfigBuilder<T> = ConfigBuilder<TlsServerConfig, T>;
 type QuicListner = ArcAsyncDeque<(QuicConnection, SocketAddr)>;
@@ -64,7 +74,10 @@ pub struct QuicServer {
     tls_config: Arc<TlsServerConfig>,
     streams_controller:
         Box<dyn Fn(u64, u64) -> Box<dyn ControlConcurrency> + Send + Sync + 'static>,
+    congestion_algorithm: CongestionAlgorithm,
     token_provider: Option<Arc<dyn TokenProvider>>,
+    retry_policy: Option<Arc<RetryPolicy>>,
+    cid_generator: Option<ArcCidGenerator>,
 }
 
 #[derive(Clone)]
@@ -91,7 +104,11 @@ impl ArcQuicServer {
             .with_protocol_versions(&[&rustls::version::TLS13])
             .unwrap(),
             streams_controller: Box::new(|bi, uni| Box::new(ConsistentConcurrency::new(bi, uni))),
+            congestion_algorithm: CongestionAlgorithm::default(),
             token_provider: None,
+            retry_policy: None,
+            cid_generator: None,
+            usc_config: UscConfig::default(),
         }
     }
 
@@ -108,7 +125,11 @@ impl ArcQuicServer {
             parameters: Parameters::default(),
             tls_config,
             streams_controller: Box::new(|bi, uni| Box::new(ConsistentConcurrency::new(bi, uni))),
+            congestion_algorithm: CongestionAlgorithm::default(),
             token_provider: None,
+            retry_policy: None,
+            cid_generator: None,
+            usc_config: UscConfig::default(),
         }
     }
 
@@ -124,15 +145,73 @@ impl ArcQuicServer {
         self.0.listener.pop().await.ok_or_else(listening_stopped)
     }
 
-    pub(crate) fn try_to_accept_conn_from(mut packet: DataPacket, pathway: Pathway, usc: &ArcUsc) {
+    /// 以流的形式监听新连接，类似于TcpListener的incoming
+    /// 与[`accept`]一样，当监听停止时，流结束
+    ///
+    /// [`accept`]: ArcQuicServer::accept
+    pub fn listen(&self) -> impl Stream<Item = (QuicConnection, SocketAddr)> {
+        futures::stream::unfold(self.clone(), |server| async move {
+            let incoming = server.accept().await.ok()?;
+            Some((incoming, server))
+        })
+    }
+
+    pub(crate) fn try_to_accept_conn_from(
+        mut packet: DataPacket,
+        ecn: Ecn,
+        pathway: Pathway,
+        usc: &ArcUsc,
+    ) {
         let server = SERVER.read().unwrap();
         let Some(server) = server.upgrade() else {
             return;
         };
-        let initial_scid =
-            std::iter::repeat_with(|| ConnectionId::random_gen_with_mark(8, 0, 0x7F))
-                .find(|cid| !CONNECTIONS.contains_key(&ConnKey::Server(*cid)))
-                .unwrap();
+
+        let version = match &packet.header {
+            DataHeader::Long(long::DataHeader::Initial(hdr)) => hdr.version,
+            DataHeader::Long(long::DataHeader::ZeroRtt(hdr)) => hdr.version,
+            _ => return,
+        };
+        let mut parameters = server.parameters;
+        let mut address_validated = false;
+        if let Some(policy) = &server.retry_policy {
+            let peer = pathway.remote_addr();
+            match &packet.header {
+                DataHeader::Long(long::DataHeader::Initial(hdr)) => {
+                    match policy.validate_token(peer, &hdr.token) {
+                        Some(ValidatedToken::Retry(odcid)) => {
+                            parameters.set_original_destination_connection_id(Some(odcid));
+                            parameters.set_retry_source_connection_id(Some(*hdr.get_dcid()));
+                            address_validated = true;
+                        }
+                        // 之前的连接中下发的令牌，无需Retry，地址已经验证
+                        Some(ValidatedToken::NewToken) => address_validated = true,
+                        None if policy.should_retry(peer) => {
+                            let (_, retry) =
+                                policy.retry(version, peer, hdr.get_dcid(), hdr.get_scid());
+                            let usc = usc.clone();
+                            tokio::spawn(async move {
+                                _ = usc
+                                    .send_all_via_pathway(&[IoSlice::new(&retry)], pathway, None)
+                                    .await;
+                            });
+                            return;
+                        }
+                        None => {}
+                    }
+                }
+                // 0-RTT包无法被Retry，在地址验证之前丢弃
+                _ if policy.should_retry(peer) => return,
+                _ => {}
+            }
+        }
+
+        let initial_scid = std::iter::repeat_with(|| match &server.cid_generator {
+            Some(generator) => generator.generate_cid(),
+            None => ConnectionId::random_gen_with_mark(8, 0, 0x7F),
+        })
+        .find(|cid| !CONNECTIONS.contains_key(&ConnKey::Server(*cid)))
+        .unwrap();
         let (initial_dcid, client_initial_dcid) = match &mut packet.header {
             DataHeader::Long(long::DataHeader::Initial(hdr)) => {
                 let client_dcid = *hdr.get_dcid();
@@ -147,6 +226,10 @@ impl ArcQuicServer {
             _ => return,
         };
 
+        if parameters.original_destination_connection_id().is_none() {
+            parameters.set_original_destination_connection_id(Some(client_initial_dcid));
+        }
+
         let streams_ctrl = (server.streams_controller)(
             server.parameters.initial_max_streams_bidi().into_inner(),
             server.parameters.initial_max_streams_uni().into_inner(),
@@ -157,17 +240,26 @@ impl ArcQuicServer {
             None => ArcTokenRegistry::default_provider(),
         };
 
-        let initial_keys = server.initial_server_keys(client_initial_dcid);
-        let inner = ArcConnection::new_server(
+        let initial_keys = server.initial_server_keys(client_initial_dcid, version);
+        let mut builder = ConnectionBuilder::server(
             initial_scid,
             initial_dcid,
             initial_keys,
-            server.parameters,
-            streams_ctrl,
             server.tls_config.clone(),
-            token_provider,
-        );
+        )
+        .with_version(version)
+        .with_parameters(parameters)
+        .with_streams_controller(streams_ctrl)
+        .with_congestion_control(server.congestion_algorithm)
+        .with_token_registry(token_provider);
+        if let Some(generator) = server.cid_generator.clone() {
+            builder = builder.with_cid_generator(generator);
+        }
+        let inner = builder.build();
         inner.add_initial_path(pathway, usc.clone());
+        if address_validated {
+            inner.on_address_validated(pathway);
+        }
         let conn = QuicConnection {
             _key: ConnKey::Server(initial_scid),
             inner,
@@ -176,14 +268,31 @@ impl ArcQuicServer {
         server
             .listener
             .push_back((conn.clone(), pathway.remote_addr()));
-        CONNECTIONS.insert(ConnKey::Server(initial_scid), conn);
-        _ = Router::try_to_route_packet_from(packet, pathway, usc);
+        CONNECTIONS.insert(ConnKey::Server(initial_scid), conn.clone());
+        // 握手完成后下发NEW_TOKEN，供客户端之后的连接跳过Retry
+        if let Some(policy) = server.retry_policy.clone() {
+            let conn = conn.clone();
+            tokio::spawn(async move {
+                if conn.handshake_completed().await.is_ok() {
+                    conn.send_new_token(policy.mint_new_token(pathway.remote_addr()));
+                }
+            });
+        }
+        // 客户端在收到服务端的Initial包之前，后续的Initial包和0-RTT包仍以client_initial_dcid为dcid，
+        // 即便合并在同一个数据报中，也要交付给这个连接，而不是再创建新连接
+        if Router::alias(client_initial_dcid, &initial_scid) {
+            tokio::spawn(async move {
+                _ = conn.handshake_completed().await;
+                Router::remove(&client_initial_dcid);
+            });
+        }
+        _ = Router::try_to_route_packet_from(packet, ecn, pathway, usc);
     }
 }
 
 impl QuicServer {
     /// 获取所有监听的地址，因为客户端创建的每一个usc都可以成为监听端口
-    pub fn initial_server_keys(&self, dcid: ConnectionId) -> rustls::quic::Keys {
+    pub fn initial_server_keys(&self, dcid: ConnectionId, version: u32) -> rustls::quic::Keys {
         let suite = self
             .tls_config
             .crypto_provider()
@@ -194,7 +303,9 @@ impl QuicServer {
                 _ => None,
             })
             .unwrap();
-        suite.keys(&dcid, rustls::Side::Server, rustls::quic::Version::V1)
+        // 未知的版本退回到QUIC v1，与连接使用的版本一致
+        let version = initial_keys_version(version).unwrap_or(rustls::quic::Version::V1);
+        suite.keys(&dcid, rustls::Side::Server, version)
     }
 }
 
@@ -213,7 +324,11 @@ pub struct QuicServerBuilder<T> {
     tls_config: T,
     streams_controller:
         Box<dyn Fn(u64, u64) -> Box<dyn ControlConcurrency> + Send + Sync + 'static>,
+    congestion_algorithm: CongestionAlgorithm,
     token_provider: Option<Arc<dyn TokenProvider>>,
+    retry_policy: Option<Arc<RetryPolicy>>,
+    cid_generator: Option<ArcCidGenerator>,
+    usc_config: UscConfig,
 }
 
 pub struct QuicServerSniBuilder<T> {
@@ -226,7 +341,11 @@ pub struct QuicServerSniBuilder<T> {
     tls_config: T,
     streams_controller:
         Box<dyn Fn(u64, u64) -> Box<dyn ControlConcurrency> + Send + Sync + 'static>,
+    congestion_algorithm: CongestionAlgorithm,
     token_provider: Option<Arc<dyn TokenProvider>>,
+    retry_policy: Option<Arc<RetryPolicy>>,
+    cid_generator: Option<ArcCidGenerator>,
+    usc_config: UscConfig,
 }
 
 impl<T> QuicServerBuilder<T> {
@@ -254,6 +373,37 @@ impl<T> QuicServerBuilder<T> {
         self
     }
 
+    /// 设置Retry策略，对于需要验证地址的客户端，收到其新连接的Initial包时，先回复一个携带Token的Retry包，
+    /// 直到客户端在后续的Initial包中携带了有效的Token，才创建连接，以缓解DoS攻击
+    pub fn with_retry_policy(mut self, retry_policy: Arc<RetryPolicy>) -> Self {
+        self.retry_policy = Some(retry_policy);
+        self
+    }
+
+    /// 设置连接ID生成器，新连接的初始连接ID以及之后通过NEW_CONNECTION_ID帧颁发的连接ID都由其生成。
+    /// 部署在负载均衡器之后时，可以使用[`RoutableCidGenerator`]，在连接ID中编码本服务器的server ID，
+    /// 使负载均衡器能将数据包路由到本服务器。默认生成随机的连接ID。
+    ///
+    /// [`RoutableCidGenerator`]: qbase::cid::RoutableCidGenerator
+    pub fn with_cid_generator(mut self, cid_generator: ArcCidGenerator) -> Self {
+        self.cid_generator = Some(cid_generator);
+        self
+    }
+
+    /// 设置监听地址上UDP套接字的选项，如收发缓冲区大小、是否使用GSO/GRO、IP_TOS等，
+    /// 在创建套接字时生效。高吞吐的服务器需要较大的接收缓冲区，以免突发流量时丢包。
+    /// 缓冲区大小受操作系统上限约束，实际生效的大小可通过[`ArcUsc`]查询。
+    pub fn with_usc_config(mut self, config: UscConfig) -> Self {
+        self.usc_config = config;
+        self
+    }
+
+    /// 设置新连接的各路径所使用的拥塞控制算法，默认为NewReno
+    pub fn with_congestion_control(mut self, algorithm: CongestionAlgorithm) -> Self {
+        self.congestion_algorithm = algorithm;
+        self
+    }
+
     /// 设值服务端连接参数。若不设置，则会使用一组默认参数。
     /// 后续接受新的连接，会直接使用这些参数。不过在sni模式下，各个host可以有不同的参数，该函数将失去意义。
     /// 因此，它最好配合[`with_single_cert`]或者[`with_single_cert_with_ocsp`]一起使用
@@ -283,10 +433,38 @@ impl QuicServerBuilder<TlsServerConfigBuilder<WantsVerifier>> {
                 .tls_config
                 .with_client_cert_verifier(client_cert_verifier),
             streams_controller: self.streams_controller,
+            congestion_algorithm: self.congestion_algorithm,
             token_provider: self.token_provider,
+            retry_policy: self.retry_policy,
+            cid_generator: self.cid_generator,
+            usc_config: self.usc_config,
         }
     }
 
+    /// 启用双向TLS，用`roots`中的根证书验证客户端证书。
+    ///
+    /// `mandatory`为true时，客户端必须提供有效的证书，否则握手失败；为false时只请求客户端证书，
+    /// 未提供证书的客户端也能完成握手，可通过连接的`peer_certificates`区分它们。
+    /// 客户端证书验证失败时，连接以携带TLS警报的CRYPTO_ERROR关闭。
+    ///
+    /// 若`roots`中没有任何根证书，返回[`VerifierBuilderError::NoRootAnchors`]
+    pub fn with_client_auth(
+        self,
+        roots: impl Into<Arc<RootCertStore>>,
+        mandatory: bool,
+    ) -> Result<QuicServerBuilder<TlsServerConfigBuilder<WantsServerCert>>, VerifierBuilderError>
+    {
+        let builder = WebPkiClientVerifier::builder_with_provider(
+            roots.into(),
+            rustls::crypto::ring::default_provider().into(),
+        );
+        let builder = match mandatory {
+            true => builder,
+            false => builder.allow_unauthenticated(),
+        };
+        Ok(self.with_cert_verifier(builder.build()?))
+    }
+
     /// Disable client authentication.
     pub fn without_cert_verifier(
         self,
@@ -301,7 +479,11 @@ impl QuicServerBuilder<TlsServerConfigBuilder<WantsVerifier>> {
                 .tls_config
                 .with_client_cert_verifier(Arc::new(NoClientAuth)),
             streams_controller: self.streams_controller,
+            congestion_algorithm: self.congestion_algorithm,
             token_provider: self.token_provider,
+            retry_policy: self.retry_policy,
+            cid_generator: self.cid_generator,
+            usc_config: self.usc_config,
         }
     }
 }
@@ -330,7 +512,11 @@ impl QuicServerBuilder<TlsServerConfigBuilder<WantsServerCert>> {
                 .with_single_cert(cert_chain, key_der)
                 .expect("The private key was wrong encoded or failed validation"),
             streams_controller: self.streams_controller,
+            congestion_algorithm: self.congestion_algorithm,
             token_provider: self.token_provider,
+            retry_policy: self.retry_policy,
+            cid_generator: self.cid_generator,
+            usc_config: self.usc_config,
         }
     }
 
@@ -357,7 +543,11 @@ impl QuicServerBuilder<TlsServerConfigBuilder<WantsServerCert>> {
                 .with_single_cert_with_ocsp(cert_chain, key_der, ocsp)
                 .expect("The private key was wrong encoded or failed validation"),
             streams_controller: self.streams_controller,
+            congestion_algorithm: self.congestion_algorithm,
             token_provider: self.token_provider,
+            retry_policy: self.retry_policy,
+            cid_generator: self.cid_generator,
+            usc_config: self.usc_config,
         }
     }
 
@@ -375,7 +565,11 @@ impl QuicServerBuilder<TlsServerConfigBuilder<WantsServerCert>> {
                 .with_cert_resolver(Arc::new(VirtualHosts(hosts.clone()))),
             hosts,
             streams_controller: self.streams_controller,
+            congestion_algorithm: self.congestion_algorithm,
             token_provider: self.token_provider,
+            retry_policy: self.retry_policy,
+            cid_generator: self.cid_generator,
+            usc_config: self.usc_config,
         }
     }
 }
@@ -426,7 +620,8 @@ impl QuicServerBuilder<TlsServerConfig> {
             .addresses
             .into_iter()
             .filter_map(|address| {
-                let arc_usc = get_or_create_usc(&address).map_err(|e| log::error!("{e}"));
+                let arc_usc = get_or_create_usc_with_config(&address, &self.usc_config)
+                    .map_err(|e| log::error!("{e}"));
                 Some((address, arc_usc.ok()?))
             })
             .collect::<HashMap<_, _>>();
@@ -445,7 +640,10 @@ impl QuicServerBuilder<TlsServerConfig> {
             parameters: self.parameters,
             tls_config: Arc::new(self.tls_config),
             streams_controller: self.streams_controller,
+            congestion_algorithm: self.congestion_algorithm,
             token_provider: self.token_provider,
+            retry_policy: self.retry_policy,
+            cid_generator: self.cid_generator,
         }));
         *SERVER.write().unwrap() = Arc::downgrade(&quic_server.0);
         Ok(quic_server)
@@ -463,7 +661,8 @@ impl QuicServerSniBuilder<TlsServerConfig> {
             .addresses
             .into_iter()
             .filter_map(|address| {
-                let arc_usc = get_or_create_usc(&address).map_err(|e| log::error!("{e}"));
+                let arc_usc = get_or_create_usc_with_config(&address, &self.usc_config)
+                    .map_err(|e| log::error!("{e}"));
                 Some((address, arc_usc.ok()?))
             })
             .collect::<HashMap<_, _>>();
@@ -481,9 +680,144 @@ impl QuicServerSniBuilder<TlsServerConfig> {
             parameters: self.parameters,
             tls_config: Arc::new(self.tls_config),
             streams_controller: self.streams_controller,
+            congestion_algorithm: self.congestion_algorithm,
             token_provider: self.token_provider,
+            retry_policy: self.retry_policy,
+            cid_generator: self.cid_generator,
         }));
         *SERVER.write().unwrap() = Arc::downgrade(&quic_server.0);
         Ok(quic_server)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use rustls::{
+        pki_types::ServerName,
+        quic::{ClientConnection, ServerConnection, Version},
+        ClientConfig,
+    };
+
+    use super::*;
+
+    /// A certificate authority, which issues the certificates of both the server and the clients.
+    struct Ca {
+        cert: rcgen::Certificate,
+        key: KeyPair,
+    }
+
+    impl Ca {
+        fn new() -> Self {
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(vec![]).unwrap();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let cert = params.self_signed(&key).unwrap();
+            Self { cert, key }
+        }
+
+        fn roots(&self) -> RootCertStore {
+            let mut roots = RootCertStore::empty();
+            roots.add(self.cert.der().clone()).unwrap();
+            roots
+        }
+
+        fn issue(&self, name: &str) -> (Vec<CertificateDer<'static>>, PrivateKeyDer<'static>) {
+            let key = KeyPair::generate().unwrap();
+            let params = CertificateParams::new(vec![name.to_owned()]).unwrap();
+            let cert = params.signed_by(&key, &self.cert, &self.key).unwrap();
+            let key = PrivateKeyDer::Pkcs8(key.serialize_der().into());
+            (vec![cert.der().clone()], key)
+        }
+    }
+
+    fn server_config(ca: &Ca, mandatory: bool) -> Arc<TlsServerConfig> {
+        let (cert_chain, key) = ca.issue("localhost");
+        let builder = ArcQuicServer::bind([], false)
+            .with_client_auth(ca.roots(), mandatory)
+            .unwrap();
+        Arc::new(
+            builder
+                .tls_config
+                .with_single_cert(cert_chain, key)
+                .unwrap(),
+        )
+    }
+
+    fn client_config(ca: &Ca, client_cert: Option<&Ca>) -> Arc<ClientConfig> {
+        let builder =
+            ClientConfig::builder_with_provider(rustls::crypto::ring::default_provider().into())
+                .with_protocol_versions(&[&rustls::version::TLS13])
+                .unwrap()
+                .with_root_certificates(ca.roots());
+        let config = match client_cert {
+            Some(issuer) => {
+                let (cert_chain, key) = issuer.issue("client");
+                builder.with_client_auth_cert(cert_chain, key).unwrap()
+            }
+            None => builder.with_no_client_auth(),
+        };
+        Arc::new(config)
+    }
+
+    /// 在内存中完成握手，返回服务端的连接，握手失败时返回服务端遇到的错误
+    fn handshake(
+        server_config: Arc<TlsServerConfig>,
+        client_config: Arc<ClientConfig>,
+    ) -> Result<ServerConnection, rustls::Error> {
+        let server_name = ServerName::try_from("localhost").unwrap();
+        let mut client =
+            ClientConnection::new(client_config, Version::V1, server_name, vec![]).unwrap();
+        let mut server = ServerConnection::new(server_config, Version::V1, vec![]).unwrap();
+
+        let mut buf = Vec::new();
+        while client.is_handshaking() || server.is_handshaking() {
+            buf.clear();
+            while client.write_hs(&mut buf).is_some() {}
+            if !buf.is_empty() {
+                server.read_hs(&buf)?;
+            }
+            buf.clear();
+            while server.write_hs(&mut buf).is_some() {}
+            if !buf.is_empty() {
+                client.read_hs(&buf).unwrap();
+            }
+        }
+        Ok(server)
+    }
+
+    #[test]
+    fn test_client_auth_without_roots() {
+        let result = ArcQuicServer::bind([], false).with_client_auth(RootCertStore::empty(), true);
+        assert!(matches!(result, Err(VerifierBuilderError::NoRootAnchors)));
+    }
+
+    #[test]
+    fn test_client_auth() {
+        let ca = Ca::new();
+        let other_ca = Ca::new();
+
+        // 由受信任的根证书签发的客户端证书通过验证
+        let server = handshake(server_config(&ca, true), client_config(&ca, Some(&ca)));
+        assert_eq!(server.unwrap().peer_certificates().map(<[_]>::len), Some(1));
+
+        // 由其他根证书签发的客户端证书被拒绝
+        let error = handshake(
+            server_config(&ca, true),
+            client_config(&ca, Some(&other_ca)),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            error,
+            rustls::Error::InvalidCertificate(rustls::CertificateError::UnknownIssuer)
+        ));
+
+        // 强制验证时，未提供证书的客户端被拒绝
+        let error = handshake(server_config(&ca, true), client_config(&ca, None)).unwrap_err();
+        assert!(matches!(error, rustls::Error::NoCertificatesPresented));
+
+        // 可选验证时，未提供证书的客户端也能完成握手
+        let server = handshake(server_config(&ca, false), client_config(&ca, None));
+        assert!(server.unwrap().peer_certificates().is_none());
+    }
+}
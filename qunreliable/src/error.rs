@@ -0,0 +1,32 @@
+use std::io;
+
+use thiserror::Error;
+
+/// The errors that may occur when sending a datagram to the peer.
+#[derive(Debug, Error)]
+pub enum DatagramError {
+    /// The peer did not advertise the [`max_datagram_frame_size`] transport parameter,
+    /// or advertised it as 0, which means that the peer does not support DATAGRAM frames.
+    ///
+    /// [`max_datagram_frame_size`]: https://www.rfc-editor.org/rfc/rfc9221.html#name-transport-parameter
+    #[error("the peer does not support DATAGRAM frames")]
+    Unsupported,
+    /// The datagram cannot be carried by a single DATAGRAM frame within the peer's limit.
+    ///
+    /// DATAGRAM frames cannot be fragmented, so the application has to split the payload itself.
+    #[error("datagram of {size} bytes exceeds the limit of {max} bytes")]
+    TooLarge { size: usize, max: usize },
+    /// The connection is closing or already closed.
+    #[error(transparent)]
+    Closed(#[from] io::Error),
+}
+
+impl From<DatagramError> for io::Error {
+    fn from(error: DatagramError) -> Self {
+        match error {
+            DatagramError::Unsupported => io::Error::new(io::ErrorKind::Unsupported, error),
+            DatagramError::TooLarge { .. } => io::Error::new(io::ErrorKind::InvalidInput, error),
+            DatagramError::Closed(error) => error,
+        }
+    }
+}
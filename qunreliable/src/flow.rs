@@ -10,7 +10,7 @@ use qbase::{
 
 use super::{
     reader::{DatagramReader, RawDatagramReader},
-    writer::{DatagramWriter, RawDatagramWriter},
+    writer::{DatagramSendPolicy, DatagramWriter, RawDatagramWriter},
 };
 use crate::{DatagramIncoming, DatagramOutgoing};
 
@@ -47,6 +47,36 @@ impl DatagramFlow {
         self.outgoing.try_read_datagram(buf)
     }
 
+    /// See [`DatagramOutgoing::set_send_policy`] for more details.
+    #[inline]
+    pub fn set_send_policy(&self, policy: DatagramSendPolicy) {
+        self.outgoing.set_send_policy(policy)
+    }
+
+    /// See [`DatagramOutgoing::on_congestion_limited`] for more details.
+    #[inline]
+    pub fn on_congestion_limited(&self) -> usize {
+        self.outgoing.on_congestion_limited()
+    }
+
+    /// See [`DatagramOutgoing::on_window_available`] for more details.
+    #[inline]
+    pub fn on_window_available(&self) {
+        self.outgoing.on_window_available()
+    }
+
+    /// See [`DatagramOutgoing::drop_oversized`] for more details.
+    #[inline]
+    pub fn drop_oversized(&self, max_size: usize) -> usize {
+        self.outgoing.drop_oversized(max_size)
+    }
+
+    /// See [`DatagramOutgoing::dropped_datagrams`] for more details.
+    #[inline]
+    pub fn dropped_datagrams(&self) -> u64 {
+        self.outgoing.dropped_datagrams()
+    }
+
     /// Create a new **unique** instance of [`DatagramReader`].
     ///
     /// Return an error if the connection is closing or already closed, or there is already a reader exist.
@@ -4,3 +4,5 @@ mod writer;
 pub use writer::*;
 mod flow;
 pub use flow::*;
+mod error;
+pub use error::*;
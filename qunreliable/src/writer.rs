@@ -12,6 +12,25 @@ use qbase::{
     varint::VarInt,
 };
 
+use crate::DatagramError;
+
+/// What to do with the queued datagrams when the congestion window is exhausted.
+///
+/// Datagrams compete with the stream data for the congestion window, they can not be sent until
+/// the window opens again. For real-time media, a late datagram is often useless, so dropping it
+/// is preferable to delaying the following ones.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DatagramSendPolicy {
+    /// Keep the datagrams in the queue, until the congestion window is available.
+    Block,
+    /// Drop all the queued datagrams once the congestion window is exhausted, which is the default.
+    ///
+    /// The datagrams sent while the window stays exhausted are dropped at once instead of being
+    /// queued. The dropped datagrams are counted, see [`DatagramOutgoing::dropped_datagrams`].
+    #[default]
+    Drop,
+}
+
 /// The [`RawDatagramWriter`] struct represents a queue for sending [`DatagramFrame`].
 ///
 /// The protocol layer will read the datagram from the queue and send it to the peer, or set the internal queue to an error state
@@ -25,12 +44,20 @@ use qbase::{
 pub struct RawDatagramWriter {
     /// The queue for storing the datagram frame to send.
     queue: VecDeque<Bytes>,
+    policy: DatagramSendPolicy,
+    /// Whether the congestion window is exhausted, until the protocol layer can send again.
+    congestion_limited: bool,
+    /// The number of the datagrams dropped due to congestion, or being too large for the path.
+    dropped: u64,
 }
 
 impl RawDatagramWriter {
     pub(crate) fn new() -> Self {
         Self {
             queue: Default::default(),
+            policy: Default::default(),
+            congestion_limited: false,
+            dropped: 0,
         }
     }
 }
@@ -123,6 +150,75 @@ impl DatagramOutgoing {
         }
     }
 
+    /// Set what to do with the queued datagrams when the congestion window is exhausted,
+    /// [`DatagramSendPolicy::Drop`] by default.
+    pub fn set_send_policy(&self, policy: DatagramSendPolicy) {
+        if let Ok(writer) = self.0.lock().unwrap().as_mut() {
+            writer.policy = policy;
+        }
+    }
+
+    /// Called by the protocol layer when the congestion window of the path is exhausted.
+    ///
+    /// Under the [`DatagramSendPolicy::Drop`], all the queued datagrams are dropped rather than
+    /// waiting for the window, returns the number of the datagrams dropped. The datagrams sent
+    /// after this are dropped immediately, until [`DatagramOutgoing::on_window_available`] is
+    /// called.
+    pub fn on_congestion_limited(&self) -> usize {
+        let mut guard = self.0.lock().unwrap();
+        let Ok(writer) = guard.as_mut() else {
+            return 0;
+        };
+        writer.congestion_limited = true;
+        if writer.policy == DatagramSendPolicy::Block {
+            return 0;
+        }
+        let dropped = writer.queue.len();
+        writer.queue.clear();
+        writer.dropped += dropped as u64;
+        dropped
+    }
+
+    /// Called by the protocol layer when the congestion window allows sending again.
+    ///
+    /// The datagrams sent after this are queued again, whatever the policy is.
+    pub fn on_window_available(&self) {
+        if let Ok(writer) = self.0.lock().unwrap().as_mut() {
+            writer.congestion_limited = false;
+        }
+    }
+
+    /// Called by the protocol layer with the largest datagram that a packet on the path can carry.
+    ///
+    /// The datagrams at the head of the queue which are larger than `max_size` can never be sent
+    /// until the path MTU grows, they are dropped and counted, rather than blocking all the
+    /// datagrams behind them. Returns the number of the datagrams dropped.
+    pub fn drop_oversized(&self, max_size: usize) -> usize {
+        let mut guard = self.0.lock().unwrap();
+        let Ok(writer) = guard.as_mut() else {
+            return 0;
+        };
+        let mut dropped = 0;
+        while writer
+            .queue
+            .front()
+            .is_some_and(|data| data.len() > max_size)
+        {
+            writer.queue.pop_front();
+            dropped += 1;
+        }
+        writer.dropped += dropped as u64;
+        dropped
+    }
+
+    /// Returns the number of the datagrams dropped due to congestion or being oversized so far.
+    pub fn dropped_datagrams(&self) -> u64 {
+        match self.0.lock().unwrap().as_ref() {
+            Ok(writer) => writer.dropped,
+            Err(_) => 0,
+        }
+    }
+
     /// When a connection error occurs, set the internal writer to an error state.
     ///
     /// Any subsequent calls to [`DatagramWriter::send`] or [`DatagramWriter::send_bytes`] will return an error.
@@ -158,19 +254,42 @@ impl DatagramWriter {
     /// Returns [`Ok`] when the data is successfully pushed into the internal queue.
     /// Returns [`Err`] when the connection is closing or already closed.
     pub fn send_bytes(&self, data: Bytes) -> io::Result<()> {
+        Ok(self.try_send(data)?)
+    }
+
+    /// Try to send a datagram to the peer.
+    ///
+    /// The datagram is pushed into the internal queue as a whole, it will be carried by a single
+    /// [`DatagramFrame`], and will never be retransmitted even if the packet carrying it is lost.
+    ///
+    /// Under the [`DatagramSendPolicy::Drop`], the datagram is dropped and counted rather than
+    /// queued if the congestion window is exhausted, [`Ok`] is still returned.
+    ///
+    /// Returns [`DatagramError::Unsupported`] if the peer does not support DATAGRAM frames,
+    /// [`DatagramError::TooLarge`] if the datagram cannot fit into a frame within the peer's limit,
+    /// or [`DatagramError::Closed`] when the connection is closing or already closed.
+    pub fn try_send(&self, data: Bytes) -> Result<(), DatagramError> {
         match self.writer.lock().unwrap().deref_mut() {
             Ok(writer) => {
+                if self.max_datagram_frame_size == 0 {
+                    return Err(DatagramError::Unsupported);
+                }
                 // Only consider the smallest encoding method: 1 byte
                 if (1 + data.len()) > self.max_datagram_frame_size {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidInput,
-                        "datagram frame size exceeds the limit",
-                    ));
+                    return Err(DatagramError::TooLarge {
+                        size: data.len(),
+                        max: self.max_datagram_frame_size - 1,
+                    });
+                }
+                // 拥塞窗口耗尽时，排队的数据报只会迟到，按照策略直接丢弃
+                if writer.congestion_limited && writer.policy == DatagramSendPolicy::Drop {
+                    writer.dropped += 1;
+                    return Ok(());
                 }
-                writer.queue.push_back(data.clone());
+                writer.queue.push_back(data);
                 Ok(())
             }
-            Err(e) => Err(io::Error::from(e.clone())),
+            Err(e) => Err(io::Error::from(e.clone()).into()),
         }
     }
 
@@ -304,6 +423,85 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_datagram_writer_unsupported() {
+        let writer = Arc::new(Mutex::new(Ok(RawDatagramWriter::new())));
+        let outgoing = DatagramOutgoing(writer);
+
+        let writer = outgoing.new_writer(0).unwrap();
+        let result = writer.try_send(Bytes::from_static(b"hello world"));
+        assert!(matches!(result, Err(DatagramError::Unsupported)));
+
+        let writer = outgoing.new_writer(8).unwrap();
+        let result = writer.try_send(Bytes::from_static(b"hello world"));
+        assert!(matches!(
+            result,
+            Err(DatagramError::TooLarge { size: 11, max: 7 })
+        ));
+        assert!(writer.try_send(Bytes::from_static(b"hello")).is_ok());
+    }
+
+    #[test]
+    fn test_datagram_send_policy() {
+        let writer = Arc::new(Mutex::new(Ok(RawDatagramWriter::new())));
+        let outgoing = DatagramOutgoing(writer);
+        let writer = outgoing.new_writer(1024).unwrap();
+
+        // 默认丢弃：拥塞窗口耗尽时，队列中的数据报被丢弃而非继续排队
+        writer.send(b"hello").unwrap();
+        writer.send(b"world").unwrap();
+        assert_eq!(outgoing.on_congestion_limited(), 2);
+        assert_eq!(outgoing.dropped_datagrams(), 2);
+        let mut buffer = [0; 1024];
+        assert!(outgoing.try_read_datagram(&mut buffer).is_none());
+
+        // 拥塞窗口仍然耗尽，新的数据报直接被丢弃，不进入队列
+        writer.send(b"again").unwrap();
+        assert_eq!(outgoing.dropped_datagrams(), 3);
+        assert!(outgoing.try_read_datagram(&mut buffer).is_none());
+
+        // 拥塞窗口恢复，数据报重新排队发送
+        outgoing.on_window_available();
+        writer.send(b"hello").unwrap();
+        assert_eq!(outgoing.dropped_datagrams(), 3);
+        assert!(outgoing.try_read_datagram(&mut buffer).is_some());
+
+        // 阻塞：数据报留在队列中，等待拥塞窗口
+        outgoing.set_send_policy(DatagramSendPolicy::Block);
+        writer.send(b"hello").unwrap();
+        assert_eq!(outgoing.on_congestion_limited(), 0);
+        writer.send(b"world").unwrap();
+        assert_eq!(outgoing.dropped_datagrams(), 3);
+        assert!(outgoing.try_read_datagram(&mut buffer).is_some());
+        assert!(outgoing.try_read_datagram(&mut buffer).is_some());
+    }
+
+    #[test]
+    fn test_datagram_drop_oversized() {
+        let writer = Arc::new(Mutex::new(Ok(RawDatagramWriter::new())));
+        let outgoing = DatagramOutgoing(writer);
+        let writer = outgoing.new_writer(1024).unwrap();
+
+        writer.send(&[b'a'; 100]).unwrap();
+        writer.send(&[b'b'; 200]).unwrap();
+        writer.send(b"hello").unwrap();
+        writer.send(&[b'c'; 100]).unwrap();
+
+        // 队首的数据报放不进任何数据包，被丢弃，不再堵住后面的数据报
+        assert_eq!(outgoing.drop_oversized(50), 2);
+        assert_eq!(outgoing.dropped_datagrams(), 2);
+        let mut buffer = [0; 64];
+        assert_eq!(
+            outgoing.try_read_datagram(&mut buffer),
+            Some((DatagramFrame::new(Some(VarInt::from_u32(5))), 1 + 1 + 5))
+        );
+        // 轮到队首时，同样被丢弃
+        assert!(outgoing.try_read_datagram(&mut buffer).is_none());
+        assert_eq!(outgoing.drop_oversized(50), 1);
+        assert_eq!(outgoing.dropped_datagrams(), 3);
+        assert_eq!(outgoing.drop_oversized(50), 0);
+    }
+
     #[test]
     fn test_datagram_writer_on_conn_error() {
         let writer = Arc::new(Mutex::new(Ok(RawDatagramWriter::new())));